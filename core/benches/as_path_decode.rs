@@ -0,0 +1,86 @@
+//! Decodes long, prepend heavy AS_PATHs and large COMMUNITIES attributes, as full tables carry
+//! for some routes.
+//!
+//! Run with `cargo bench --bench as_path_decode`.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+
+use bgp_core::message::{
+    AsPath, AsPathSegment, AsPathSegmentType, AttributeType, Community, IpAddrPrefix,
+    PathAttribute, UpdateMessageBuilder,
+};
+
+const ATTRIBUTES: usize = 100_000;
+/// ASNs in each path: an origin prepending itself many times behind a few transits
+const PATH_LEN: u32 = 60;
+const COMMUNITIES: u16 = 60;
+const RUNS: u32 = 5;
+
+fn attribute(i: u32) -> (Bytes, Bytes) {
+    let origin = 64512 + i % 1000;
+    let asns = [3356, 1299, 6939]
+        .into_iter()
+        .chain((3..PATH_LEN).map(|_| origin))
+        .collect();
+    let builder = (0..COMMUNITIES).fold(UpdateMessageBuilder::new(), |builder, value| {
+        builder.community(Community {
+            asn: 65001,
+            value: value + i as u16,
+        })
+    });
+    // Without a prefix there would be no attributes
+    let update = builder
+        .announce(IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8).unwrap())
+        .as_path(AsPath {
+            segments: [AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns,
+            }]
+            .into(),
+        })
+        .build();
+    let encoded = |type_code| {
+        let attribute = update
+            .path_attributes
+            .iter()
+            .find(|attribute| attribute.type_code == type_code)
+            .unwrap();
+        let mut buf = BytesMut::new();
+        attribute.encode(&mut buf);
+        buf.freeze()
+    };
+    (
+        encoded(AttributeType::AsPath),
+        encoded(AttributeType::Communities),
+    )
+}
+
+/// The best of [`RUNS`] timings of decoding every attribute
+fn best(attributes: &[Bytes]) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            for attribute in attributes {
+                PathAttribute::try_decode(&mut attribute.clone()).unwrap();
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let (as_paths, communities): (Vec<_>, Vec<_>) = (0..ATTRIBUTES as u32).map(attribute).unzip();
+    let as_path = best(&as_paths);
+    let community = best(&communities);
+
+    println!(
+        "{ATTRIBUTES} AS_PATHs of {PATH_LEN} ASNs decoded in {as_path:?} ({:?} each), \
+         COMMUNITIES of {COMMUNITIES} in {community:?} ({:?} each), best of {RUNS}",
+        as_path / ATTRIBUTES as u32,
+        community / ATTRIBUTES as u32
+    );
+}
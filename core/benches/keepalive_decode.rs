@@ -0,0 +1,71 @@
+//! Reads a stream of keepalives with `MessageReader`, reporting the time and the allocations
+//! per message.
+//!
+//! Run with `cargo bench --bench keepalive_decode --features tokio`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use bgp_core::message::BgpMessage;
+use bgp_core::session::MessageReader;
+
+const MESSAGES: usize = 1_000_000;
+const RUNS: u32 = 5;
+
+struct Counting;
+
+/// Allocations made
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let stream = BgpMessage::Keepalive.to_bytes().repeat(MESSAGES);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut best = Duration::MAX;
+    let mut allocations = 0;
+    for _ in 0..RUNS {
+        let mut reader = MessageReader::new(&stream[..]);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        let read = runtime.block_on(async {
+            let mut read = 0;
+            while let Some(message) = reader.next().await.unwrap() {
+                assert_eq!(message, BgpMessage::Keepalive);
+                read += 1;
+            }
+            read
+        });
+        best = best.min(start.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        assert_eq!(read, MESSAGES);
+    }
+
+    println!(
+        "{MESSAGES} keepalives read in {best:?} ({:.1} ns each), {:.2} allocations each, best \
+         of {RUNS}",
+        best.as_nanos() as f64 / MESSAGES as f64,
+        allocations as f64 / MESSAGES as f64
+    );
+}
@@ -0,0 +1,92 @@
+//! Decodes a synthetic TABLE_DUMP_V2 dump of two peers' full tables, sequentially and with
+//! `MrtReader::par_records`.
+//!
+//! Run with `cargo bench --bench mrt_parse --features rayon`.
+
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant, SystemTime};
+
+use bgp_core::message::{
+    AsPath, AsPathSegment, AsPathSegmentType, IpAddrPrefix, UpdateMessageBuilder,
+};
+use bgp_core::mrt::{MrtReader, MrtWriter};
+use bgp_core::rib::{RibIn, RibPeer};
+
+const PREFIXES: u32 = 500_000;
+/// Prefixes sharing the attributes of one UPDATE
+const PER_UPDATE: u32 = 20;
+const RUNS: u32 = 5;
+
+fn peer_rib(asn: u32) -> RibIn {
+    let mut rib = RibIn::new();
+    for update in 0..PREFIXES / PER_UPDATE {
+        let builder = (0..PER_UPDATE).fold(UpdateMessageBuilder::new(), |builder, i| {
+            let network = (update * PER_UPDATE + i) << 8;
+            let addr = IpAddr::V4(Ipv4Addr::from_bits(0x0100_0000 + network));
+            builder.announce(IpAddrPrefix::new(addr, 24).unwrap())
+        });
+        let update = builder
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [asn, 64500 + update % 1000].into(),
+                }]
+                .into(),
+            })
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .med(update)
+            .build();
+        rib.apply(&update);
+    }
+    rib
+}
+
+/// The best of [`RUNS`] timings of decoding every record
+fn best(decode: impl Fn() -> usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            assert_eq!(decode(), 1 + PREFIXES as usize);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let peers = [65001, 65002].map(|asn| {
+        let peer = RibPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, (asn % 256) as u8)),
+            asn,
+            router_id: Ipv4Addr::new(192, 0, 2, (asn % 256) as u8),
+            external: true,
+        };
+        (peer, peer_rib(asn))
+    });
+    let mut writer = MrtWriter::new(vec![]);
+    let ribs: Vec<_> = peers.iter().map(|(peer, rib)| (*peer, rib)).collect();
+    writer
+        .dump_rib(SystemTime::now(), Ipv4Addr::new(192, 0, 2, 254), &ribs)
+        .unwrap();
+    let dump = writer.into_inner();
+
+    let records = |parallel: bool| {
+        let reader = MrtReader::new(Cursor::new(dump.clone()));
+        match parallel {
+            true => reader.par_records().map(Result::unwrap).count(),
+            false => reader.map(Result::unwrap).count(),
+        }
+    };
+    let sequential = best(|| records(false));
+    let parallel = best(|| records(true));
+
+    println!(
+        "{} records of {} octets: sequential {sequential:?}, parallel {parallel:?} on {} \
+         threads ({:.1}x), best of {RUNS}",
+        1 + PREFIXES,
+        dump.len(),
+        rayon::current_num_threads(),
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
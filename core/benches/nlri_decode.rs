@@ -0,0 +1,42 @@
+//! Decodes an NLRI field of 500k prefixes, of every length from /8 to /32.
+//!
+//! Run with `cargo bench --bench nlri_decode`.
+
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use bgp_core::message::UpdateMessage;
+
+const PREFIXES: u32 = 500_000;
+const RUNS: u32 = 10;
+
+fn main() {
+    // An UPDATE body without withdrawn routes or attributes, only NLRI
+    let mut body = BytesMut::new();
+    body.put_u16(0);
+    body.put_u16(0);
+    for i in 0..PREFIXES {
+        let length = 8 + (i % 25) as u8;
+        let addr = (0x0100_0000 + (i << 8)).to_be_bytes();
+        let byte_len = (length as usize).div_ceil(8);
+        body.put_u8(length);
+        body.put_slice(&addr[..byte_len]);
+    }
+    let body = body.freeze();
+
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let update = UpdateMessage::try_decode(&mut Bytes::clone(&body)).unwrap();
+        let decoded = start.elapsed();
+        assert_eq!(update.nlri.len(), PREFIXES as usize);
+        best = best.min(decoded);
+    }
+
+    println!(
+        "{PREFIXES} prefixes of {} octets decoded in {best:?}, {:?} per prefix, best of {RUNS}",
+        body.len(),
+        best / PREFIXES
+    );
+}
@@ -0,0 +1,112 @@
+//! Loads a synthetic TABLE_DUMP_V2 dump of two peers' full tables and reports the memory the
+//! RIBs take.
+//!
+//! Run with `cargo bench --bench rib_memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime};
+
+use bgp_core::message::{
+    AsPath, AsPathSegment, AsPathSegmentType, IpAddrPrefix, UpdateMessageBuilder,
+};
+use bgp_core::mrt::{MrtReader, MrtWriter, RibLoader};
+use bgp_core::rib::{RibIn, RibPeer};
+
+const PREFIXES: u32 = 500_000;
+/// Prefixes sharing the attributes of one UPDATE, each UPDATE's being distinct
+const PER_UPDATE: u32 = 20;
+
+struct Counting;
+
+/// Octets allocated and not yet freed
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE.fetch_add(new_size, Ordering::Relaxed);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn peer_rib(asn: u32) -> RibIn {
+    let mut rib = RibIn::new();
+    for update in 0..PREFIXES / PER_UPDATE {
+        let builder = (0..PER_UPDATE).fold(UpdateMessageBuilder::new(), |builder, i| {
+            let network = (update * PER_UPDATE + i) << 8;
+            let addr = IpAddr::V4(Ipv4Addr::from_bits(0x0100_0000 + network));
+            builder.announce(IpAddrPrefix::new(addr, 24).unwrap())
+        });
+        let update = builder
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [asn, 64500 + update % 1000].into(),
+                }]
+                .into(),
+            })
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .med(update)
+            .build();
+        rib.apply(&update);
+    }
+    rib
+}
+
+fn main() {
+    let peers = [65001, 65002].map(|asn| {
+        let peer = RibPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, (asn % 256) as u8)),
+            asn,
+            router_id: Ipv4Addr::new(192, 0, 2, (asn % 256) as u8),
+            external: true,
+        };
+        (peer, peer_rib(asn))
+    });
+    let mut writer = MrtWriter::new(vec![]);
+    let ribs: Vec<_> = peers.iter().map(|(peer, rib)| (*peer, rib)).collect();
+    writer
+        .dump_rib(SystemTime::now(), Ipv4Addr::new(192, 0, 2, 254), &ribs)
+        .unwrap();
+    let dump = writer.into_inner();
+    drop(ribs);
+    drop(peers);
+
+    let before = LIVE.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut loader = RibLoader::new();
+    for record in MrtReader::new(Cursor::new(&dump)) {
+        loader.load(&record.unwrap());
+    }
+    let loaded = start.elapsed();
+    let stats = loader.interner_stats();
+    let ribs = loader.into_ribs();
+    let routes: usize = ribs.iter().map(|(_, rib)| rib.len()).sum();
+    let resident = LIVE.load(Ordering::Relaxed) - before;
+    assert_eq!(routes, 2 * PREFIXES as usize);
+
+    println!(
+        "{routes} routes from {} octets loaded in {loaded:?}: {resident} octets resident \
+         ({:.1} per route), {} attribute sets, {:.1}% shared",
+        dump.len(),
+        resident as f64 / routes as f64,
+        stats.resident,
+        stats.hit_rate() * 100.0
+    );
+}
@@ -0,0 +1,59 @@
+//! Round trips a synthetic full table through a RIB snapshot.
+//!
+//! Run with `cargo bench --bench rib_snapshot`.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Instant;
+
+use bgp_core::message::{
+    AsPath, AsPathSegment, AsPathSegmentType, IpAddrPrefix, UpdateMessageBuilder,
+};
+use bgp_core::rib::{RibIn, RibSnapshot};
+
+const PREFIXES: u32 = 1_000_000;
+/// Prefixes sharing the attributes of one UPDATE
+const PER_UPDATE: u32 = 100;
+
+fn main() {
+    let mut rib = RibIn::new();
+    for update in 0..PREFIXES / PER_UPDATE {
+        let builder = (0..PER_UPDATE).fold(UpdateMessageBuilder::new(), |builder, i| {
+            let network = (update * PER_UPDATE + i) << 8;
+            let addr = IpAddr::V4(Ipv4Addr::from_bits(0x0100_0000 + network));
+            builder.announce(IpAddrPrefix::new(addr, 24).unwrap())
+        });
+        let update = builder
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 64500 + update % 1000].into(),
+                }]
+                .into(),
+            })
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .med(update)
+            .build();
+        rib.apply(&update);
+    }
+    assert_eq!(rib.len(), PREFIXES as usize);
+
+    let start = Instant::now();
+    let snapshot = rib.snapshot();
+    let encoded = start.elapsed();
+    let bytes = snapshot.into_bytes();
+
+    let start = Instant::now();
+    let snapshot = RibSnapshot::from_bytes(bytes.clone()).unwrap();
+    let restored = RibIn::restore(&snapshot).unwrap();
+    let decoded = start.elapsed();
+    assert_eq!(restored.len(), rib.len());
+
+    println!(
+        "{} routes: snapshot {:?}, restore {:?}, {} bytes ({:.1} per route)",
+        rib.len(),
+        encoded,
+        decoded,
+        bytes.len(),
+        bytes.len() as f64 / rib.len() as f64
+    );
+}
@@ -0,0 +1,85 @@
+//! Decodes the UPDATEs of a synthetic full table, counting the allocations made.
+//!
+//! Run with `cargo bench --bench update_decode`, adding `--no-default-features` to compare
+//! without `smallvec`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use bgp_core::message::{
+    AsPath, AsPathSegment, AsPathSegmentType, Community, IpAddrPrefix, OriginType, UpdateMessage,
+    UpdateMessageBuilder,
+};
+
+const PREFIXES: u32 = 1_000_000;
+/// Prefixes sharing the attributes of one UPDATE
+const PER_UPDATE: u32 = 100;
+
+/// The system allocator, counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let updates: Vec<_> = (0..PREFIXES / PER_UPDATE)
+        .map(|update| {
+            let builder = (0..PER_UPDATE).fold(UpdateMessageBuilder::new(), |builder, i| {
+                let network = (update * PER_UPDATE + i) << 8;
+                let addr = IpAddr::V4(Ipv4Addr::from_bits(0x0100_0000 + network));
+                builder.announce(IpAddrPrefix::new(addr, 24).unwrap())
+            });
+            builder
+                .origin(OriginType::Igp)
+                .as_path(AsPath {
+                    segments: [AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSequence,
+                        asns: [65001, 3356, 64500 + update % 1000].into(),
+                    }]
+                    .into(),
+                })
+                .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+                .med(update)
+                .community(Community {
+                    asn: 65001,
+                    value: (update % 100) as u16,
+                })
+                .build()
+                .to_bytes()
+        })
+        .collect();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut prefixes = 0;
+    for update in &updates {
+        let update = UpdateMessage::try_decode(&mut update.clone()).unwrap();
+        prefixes += update.nlri.len();
+    }
+    let decoded = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert_eq!(prefixes, PREFIXES as usize);
+
+    println!(
+        "{} UPDATEs of {prefixes} prefixes decoded in {decoded:?}, {:?} and {:.1} allocations \
+         per UPDATE",
+        updates.len(),
+        decoded / updates.len() as u32,
+        allocations as f64 / updates.len() as f64
+    );
+}
@@ -0,0 +1,63 @@
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Afi {
+    Ipv4,
+    Ipv6,
+    Unknown(u16),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Safi {
+    Unicast,
+    Multicast,
+    Unknown(u8),
+}
+
+impl From<u16> for Afi {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Afi::Ipv4,
+            2 => Afi::Ipv6,
+            _ => Afi::Unknown(value),
+        }
+    }
+}
+
+impl From<Afi> for u16 {
+    fn from(afi: Afi) -> Self {
+        match afi {
+            Afi::Ipv4 => 1,
+            Afi::Ipv6 => 2,
+            Afi::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<u8> for Safi {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Safi::Unicast,
+            2 => Safi::Multicast,
+            _ => Safi::Unknown(value),
+        }
+    }
+}
+
+impl From<Safi> for u8 {
+    fn from(safi: Safi) -> Self {
+        match safi {
+            Safi::Unicast => 1,
+            Safi::Multicast => 2,
+            Safi::Unknown(value) => value,
+        }
+    }
+}
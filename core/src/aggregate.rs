@@ -0,0 +1,327 @@
+//! Summarizing prefixes into the fewest covering them, and splitting prefixes up again
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::address_family::Afi;
+use crate::update_message::IpAddrPrefix;
+
+/// How far [`aggregate`] summarizes; the default covers exactly the input address space
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AggregateOptions {
+    /// Replaces IPv4 prefixes longer than this by the prefix of this length covering them,
+    /// which may cover address space no input prefix does
+    pub max_ipv4_length: Option<u8>,
+    pub max_ipv6_length: Option<u8>,
+}
+
+/// A prefix of the minimal covering set, with the input prefixes within it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub prefix: IpAddrPrefix,
+    /// Sorted and without duplicates, path identifiers kept
+    pub covers: Vec<IpAddrPrefix>,
+}
+
+impl Aggregate {
+    /// Whether the aggregate stands for anything but a single input prefix equal to it
+    pub fn is_summary(&self) -> bool {
+        !matches!(&self.covers[..], [only] if only.length() == self.prefix.length())
+    }
+}
+
+/// The fewest prefixes covering `prefixes`, sorted, both families at once.
+///
+/// Prefixes within others are folded into them and sibling halves into their parent, repeatedly.
+/// Path identifiers are ignored for the aggregates.
+pub fn aggregate(
+    prefixes: impl IntoIterator<Item = IpAddrPrefix>,
+    options: AggregateOptions,
+) -> Vec<Aggregate> {
+    let mut inputs: Vec<_> = prefixes.into_iter().collect();
+    inputs.sort();
+    inputs.dedup();
+
+    let mut summarized: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let max_length = match input.afi() {
+                Afi::Ipv4 => options.max_ipv4_length,
+                _ => options.max_ipv6_length,
+            };
+            let length = max_length.map_or(input.length(), |max| input.length().min(max));
+            (truncate(&input, length), input)
+        })
+        .collect();
+    // Covering prefixes sort before what they cover
+    summarized.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut aggregates: Vec<Aggregate> = vec![];
+    for (prefix, input) in summarized {
+        if let Some(last) = aggregates.last_mut()
+            && covers(&last.prefix, &prefix)
+        {
+            last.covers.push(input);
+            continue;
+        }
+        aggregates.push(Aggregate {
+            prefix,
+            covers: vec![input],
+        });
+
+        // The aggregates are disjoint and sorted, so only the last two can be siblings
+        while let [.., low, high] = &aggregates[..]
+            && let Some(parent) = parent_of_siblings(&low.prefix, &high.prefix)
+        {
+            let high = aggregates.pop().unwrap();
+            let low = aggregates.last_mut().unwrap();
+            low.prefix = parent;
+            low.covers.extend(high.covers);
+        }
+    }
+    for aggregate in &mut aggregates {
+        aggregate.covers.sort();
+    }
+    aggregates
+}
+
+/// The prefixes of `length` bits within `prefix`, in order, or `prefix` itself when it's at
+/// least that long. `length` is capped at the address width.
+pub fn deaggregate(prefix: &IpAddrPrefix, length: u8) -> impl Iterator<Item = IpAddrPrefix> {
+    let width = prefix.max_length();
+    let length = length.clamp(prefix.length(), width);
+    let base = match prefix.addr() {
+        IpAddr::V4(addr) => addr.to_bits() as u128,
+        IpAddr::V6(addr) => addr.to_bits(),
+    };
+    let last = u128::MAX
+        .checked_shr(128 - (length - prefix.length()) as u32)
+        .unwrap_or(0);
+    let afi = prefix.afi();
+    (0..=last).map(move |i| {
+        let bits = base | i.checked_shl((width - length) as u32).unwrap_or(0);
+        let addr = match afi {
+            Afi::Ipv4 => IpAddr::V4(Ipv4Addr::from_bits(bits as u32)),
+            _ => IpAddr::V6(Ipv6Addr::from_bits(bits)),
+        };
+        IpAddrPrefix::new(addr, length).unwrap()
+    })
+}
+
+fn truncate(prefix: &IpAddrPrefix, length: u8) -> IpAddrPrefix {
+    IpAddrPrefix::new(prefix.addr(), length).unwrap()
+}
+
+fn covers(outer: &IpAddrPrefix, inner: &IpAddrPrefix) -> bool {
+    outer.afi() == inner.afi()
+        && outer.length() <= inner.length()
+        && truncate(inner, outer.length()) == *outer
+}
+
+/// The parent of `low` and `high` when they're its two halves
+fn parent_of_siblings(low: &IpAddrPrefix, high: &IpAddrPrefix) -> Option<IpAddrPrefix> {
+    if low.afi() != high.afi() || low.length() != high.length() || low.length() == 0 {
+        return None;
+    }
+    let parent = truncate(low, low.length() - 1);
+    (low != high && truncate(high, low.length() - 1) == parent).then_some(parent)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn prefixes(prefixes: &[&str]) -> Vec<IpAddrPrefix> {
+        prefixes
+            .iter()
+            .map(|prefix| prefix.parse().unwrap())
+            .collect()
+    }
+
+    fn aggregated(input: &[&str], options: AggregateOptions) -> Vec<(String, Vec<String>)> {
+        aggregate(prefixes(input), options)
+            .into_iter()
+            .map(|aggregate| {
+                let covers = aggregate.covers.iter().map(|p| p.to_string()).collect();
+                (aggregate.prefix.to_string(), covers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let options = AggregateOptions::default();
+        // Siblings merge repeatedly, more specifics fold into what covers them
+        assert_eq!(
+            aggregated(
+                &[
+                    "10.0.3.0/24",
+                    "10.0.0.0/24",
+                    "10.0.1.0/24",
+                    "10.0.2.128/25",
+                    "10.0.2.0/23",
+                    "10.0.1.0/24",
+                ],
+                options
+            ),
+            [(
+                "10.0.0.0/22".to_string(),
+                [
+                    "10.0.0.0/24",
+                    "10.0.1.0/24",
+                    "10.0.2.0/23",
+                    "10.0.2.128/25",
+                    "10.0.3.0/24"
+                ]
+                .map(String::from)
+                .to_vec()
+            )]
+        );
+        // Adjacent prefixes that aren't siblings stay apart
+        assert_eq!(
+            aggregated(&["10.0.1.0/24", "10.0.2.0/24"], options)
+                .into_iter()
+                .map(|(prefix, _)| prefix)
+                .collect::<Vec<_>>(),
+            ["10.0.1.0/24", "10.0.2.0/24"]
+        );
+        // Both halves of the address space make the /0 root, families stay apart
+        assert_eq!(
+            aggregated(
+                &[
+                    "0.0.0.0/1",
+                    "128.0.0.0/1",
+                    "2001:db8::/33",
+                    "2001:db8:8000::/33"
+                ],
+                options
+            )
+            .into_iter()
+            .map(|(prefix, _)| prefix)
+            .collect::<Vec<_>>(),
+            ["0.0.0.0/0", "2001:db8::/32"]
+        );
+        assert!(aggregate(vec![], options).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_max_length() {
+        let options = AggregateOptions {
+            max_ipv4_length: Some(24),
+            max_ipv6_length: Some(48),
+        };
+        let aggregates = aggregate(
+            prefixes(&[
+                "192.0.2.64/26",
+                "192.0.2.192/27",
+                "2001:db8:1:2::/64",
+                "10.0.0.0/8",
+            ]),
+            options,
+        );
+        assert_eq!(
+            aggregates
+                .iter()
+                .map(|aggregate| (aggregate.prefix.to_string(), aggregate.covers.len()))
+                .collect::<Vec<_>>(),
+            [
+                ("10.0.0.0/8".to_string(), 1),
+                ("192.0.2.0/24".to_string(), 2),
+                ("2001:db8:1::/48".to_string(), 1),
+            ]
+        );
+        assert!(!aggregates[0].is_summary());
+        assert!(aggregates[1].is_summary() && aggregates[2].is_summary());
+    }
+
+    #[test]
+    fn test_deaggregate() {
+        let prefix: IpAddrPrefix = "10.0.0.0/22".parse().unwrap();
+        assert_eq!(
+            deaggregate(&prefix, 24)
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>(),
+            ["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24", "10.0.3.0/24"]
+        );
+        assert_eq!(deaggregate(&prefix, 40).count(), 1 << 10);
+        assert_eq!(deaggregate(&prefix, 16).collect::<Vec<_>>(), [prefix]);
+
+        let root = "::/0".parse().unwrap();
+        let halves = deaggregate(&root, 1)
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(halves, ["::/1", "8000::/1"]);
+        assert_eq!(
+            deaggregate(&root, 128).nth(5).unwrap().to_string(),
+            "::5/128"
+        );
+        assert_eq!(
+            deaggregate(&"0.0.0.0/0".parse().unwrap(), 0).collect::<Vec<_>>(),
+            prefixes(&["0.0.0.0/0"])
+        );
+    }
+
+    /// The /24s (or /48s) within `prefixes`, which are no longer than that
+    fn address_space(prefixes: impl IntoIterator<Item = IpAddrPrefix>) -> BTreeSet<IpAddrPrefix> {
+        prefixes
+            .into_iter()
+            .flat_map(|prefix| {
+                let length = match prefix.afi() {
+                    Afi::Ipv4 => 24,
+                    _ => 48,
+                };
+                deaggregate(&prefix, length).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregate_covers_input_address_space() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..500 {
+            let input: Vec<_> = (0..next() % 40)
+                .map(|_| {
+                    let bits = next();
+                    // Within 10.0.0.0/20 and 2001:db8::/44, down to /24 and /48
+                    let (addr, length) = match bits % 2 {
+                        0 => (IpAddr::from([10, 0, (bits >> 8) as u8 & 0xf, 0]), 20),
+                        _ => (
+                            IpAddr::from([0x2001, 0xdb8, (bits >> 8) as u16 & 0xf, 0, 0, 0, 0, 0]),
+                            44,
+                        ),
+                    };
+                    let length = length + (bits >> 16) % 5;
+                    IpAddrPrefix::new(addr, length as u8).unwrap()
+                })
+                .collect();
+            let aggregates = aggregate(input.clone(), AggregateOptions::default());
+
+            let outputs = aggregates.iter().map(|aggregate| aggregate.prefix.clone());
+            assert_eq!(
+                address_space(outputs),
+                address_space(input.clone()),
+                "{input:?}"
+            );
+            for pair in aggregates.windows(2) {
+                assert!(!covers(&pair[0].prefix, &pair[1].prefix));
+                assert_eq!(parent_of_siblings(&pair[0].prefix, &pair[1].prefix), None);
+            }
+            let mut covered: Vec<_> = aggregates
+                .into_iter()
+                .flat_map(|aggregate| aggregate.covers)
+                .collect();
+            covered.sort();
+            let mut expected = input;
+            expected.sort();
+            expected.dedup();
+            assert_eq!(covered, expected);
+        }
+    }
+}
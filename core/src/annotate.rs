@@ -0,0 +1,260 @@
+//! Annotated hex dumps of BGP messages, for making sense of what a peer sent.
+//!
+//! The dump is laid out from the fields the message decoders report as they read them, so it
+//! shows the message as this crate understands it. Where decoding fails the rest of the input
+//! is dumped as raw hex below a line saying why.
+
+use std::fmt::Write;
+
+use bytes::Bytes;
+
+use crate::bgp_message::{BgpMessage, MessageDecodeError};
+use crate::spans::{self, Span};
+
+/// Octets per line of hex
+const WIDTH: usize = 16;
+
+/// Dumps `data`, one or more messages each starting with the header, one labelled field per
+/// line and nested fields indented:
+///
+/// ```text
+/// 0000                                                   message
+/// 0000  ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff    marker
+/// 0010  00 17                                              length: 23
+/// 0012  02                                                 type: Update
+/// ```
+pub fn annotate(data: &Bytes) -> String {
+    dump(data, true)
+}
+
+/// [`annotate`] for messages from a speaker without the 4 octet AS capability, whose AS_PATHs
+/// carry 2 octet ASNs
+pub fn annotate_two_octet_as(data: &Bytes) -> String {
+    dump(data, false)
+}
+
+fn dump(data: &Bytes, four_octet_as: bool) -> String {
+    let mut rest = data.clone();
+    let (result, spans) = spans::record(data, || {
+        while !rest.is_empty() {
+            BgpMessage::decode_framed(&mut rest, four_octet_as, false)?;
+        }
+        Ok::<_, MessageDecodeError>(())
+    });
+    // Decoding stopped after the last field it read
+    let mut error = result.err().map(|err| {
+        let offset = spans
+            .iter()
+            .filter(|span| !span.group)
+            .map(|span| span.end)
+            .max()
+            .unwrap_or(0);
+        (offset, err)
+    });
+
+    let mut out = String::new();
+    let mut dumped = 0;
+    let mut depth = 0;
+    for span in &spans {
+        // Read again, as AS4_PATH is when merged into AS_PATH
+        if span.start < dumped {
+            continue;
+        }
+        if span.start > dumped {
+            gap(&mut out, data, dumped, span.start, span.depth, &mut error);
+            dumped = span.start;
+        }
+        depth = span.depth;
+        match span.group {
+            true => line(&mut out, span.start, &[], span.depth, &span.label),
+            false => {
+                field(&mut out, data, span);
+                dumped = dumped.max(span.end);
+            }
+        }
+    }
+    if dumped < data.len() {
+        gap(&mut out, data, dumped, data.len(), depth, &mut error);
+    }
+    if let Some((offset, err)) = error {
+        line(
+            &mut out,
+            offset,
+            &[],
+            0,
+            &format!("!! gave up: {}", reason(&err)),
+        );
+    }
+    out
+}
+
+/// Octets no field covers, the remainder after a failure or what a decoder skipped
+fn gap(
+    out: &mut String,
+    data: &Bytes,
+    start: usize,
+    end: usize,
+    depth: usize,
+    error: &mut Option<(usize, MessageDecodeError)>,
+) {
+    let label = match error.take_if(|(offset, _)| *offset == start) {
+        Some((_, err)) => {
+            line(
+                out,
+                start,
+                &[],
+                depth,
+                &format!("!! gave up: {}", reason(&err)),
+            );
+            "unparsed"
+        }
+        None => "not decoded",
+    };
+    let span = Span {
+        start,
+        end,
+        depth,
+        label: label.into(),
+        group: false,
+    };
+    field(out, data, &span);
+}
+
+/// UPDATE errors without the octets they carry, the dump shows them
+fn reason(err: &MessageDecodeError) -> String {
+    match err {
+        MessageDecodeError::Update(err) => format!("Malformed UPDATE message: {:?}", err.kind),
+        err => err.to_string(),
+    }
+}
+
+fn field(out: &mut String, data: &Bytes, span: &Span) {
+    let octets = &data[span.start..span.end];
+    if octets.is_empty() {
+        line(out, span.start, &[], span.depth, &span.label);
+    }
+    for (i, chunk) in octets.chunks(WIDTH).enumerate() {
+        let label = match i {
+            0 => span.label.as_str(),
+            _ => "",
+        };
+        line(out, span.start + i * WIDTH, chunk, span.depth, label);
+    }
+}
+
+fn line(out: &mut String, offset: usize, octets: &[u8], depth: usize, label: &str) {
+    let hex: Vec<String> = octets.iter().map(|octet| format!("{octet:02x}")).collect();
+    let text = format!(
+        "{offset:04x}  {:<width$}  {:indent$}{label}",
+        hex.join(" "),
+        "",
+        width = WIDTH * 3 - 1,
+        indent = depth * 2,
+    );
+    let _ = writeln!(out, "{}", text.trim_end());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::path::Path;
+
+    use crate::address_family::{Afi, Safi};
+    use crate::capability::Capability;
+    use crate::message::{OpenMessage, RouteRefreshMessage};
+    use crate::notification_message::{CeaseSubErr, NotificationErrorCode, NotificationMessage};
+
+    /// Offset, length and whether ASNs are 4 octets, of each BGP message in the BGP4MP fixture
+    const FIXTURE_MESSAGES: [(usize, usize, bool); 6] = [
+        (68, 62, true),
+        (158, 59, false),
+        (249, 19, true),
+        (324, 70, true),
+        (426, 27, true),
+        (489, 19, true),
+    ];
+
+    fn data(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    /// Every vector, named, with its dump
+    fn dumps() -> String {
+        let fixture = std::fs::read(data("bgp4mp_updates.mrt")).unwrap();
+        let mut vectors = vec![];
+        for (offset, len, four_octet_as) in FIXTURE_MESSAGES {
+            let message = Bytes::copy_from_slice(&fixture[offset..offset + len]);
+            let dump = match four_octet_as {
+                true => annotate(&message),
+                false => annotate_two_octet_as(&message),
+            };
+            vectors.push((format!("bgp4mp_updates.mrt at {offset}"), dump));
+        }
+
+        let open = BgpMessage::Open(OpenMessage::new(
+            65001,
+            90,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv6,
+                    safi: Safi::Unicast,
+                },
+                Capability::RouteRefresh,
+                Capability::FourOctetAs { asn: 4_200_000_000 },
+            ],
+        ));
+        vectors.push(("OPEN".into(), annotate(&open.to_bytes())));
+
+        let mut stream = BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::Cease(CeaseSubErr::AdministrativeShutdown),
+            b"\x0bmaintenance".to_vec(),
+        ))
+        .to_bytes()
+        .to_vec();
+        let route_refresh = RouteRefreshMessage::request(Afi::Ipv4, Safi::Unicast);
+        stream.extend_from_slice(&BgpMessage::RouteRefresh(route_refresh).to_bytes());
+        vectors.push((
+            "NOTIFICATION and ROUTE-REFRESH".into(),
+            annotate(&stream.into()),
+        ));
+
+        let mut invalid_origin = fixture[68..68 + 62].to_vec();
+        invalid_origin[0x1a] = 5;
+        vectors.push((
+            "invalid ORIGIN".into(),
+            annotate(&invalid_origin.clone().into()),
+        ));
+        vectors.push((
+            "truncated".into(),
+            annotate(&Bytes::copy_from_slice(&invalid_origin[..40])),
+        ));
+
+        vectors
+            .iter()
+            .map(|(name, dump)| format!("== {name}\n{dump}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn matches_snapshot() {
+        let expected = std::fs::read_to_string(data("annotate.txt")).unwrap();
+        assert_eq!(dumps(), expected);
+    }
+
+    #[test]
+    fn dumps_nothing_for_nothing() {
+        assert_eq!(annotate(&Bytes::new()), "");
+    }
+
+    #[test]
+    fn leaves_decoding_unrecorded() {
+        let open = BgpMessage::Open(OpenMessage::new(65001, 90, Ipv4Addr::LOCALHOST, &[]));
+        annotate(&open.to_bytes());
+        assert!(!spans::active());
+    }
+}
@@ -0,0 +1,271 @@
+//! The subset of CBOR (RFC 8949) the archive uses: unsigned and negative integers, byte and
+//! text strings, arrays, tags, booleans and null, all with definite lengths.
+
+use bytes::{BufMut, Bytes};
+
+/// Self-described CBOR (RFC 8949 section 3.4.6), lets tools recognise the stream
+pub const SELF_DESCRIBE_TAG: u64 = 55799;
+
+/// Deepest nesting accepted, records are only a few levels deep
+const MAX_DEPTH: usize = 16;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+const NULL: u8 = 22;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Unsigned(u64),
+    /// `-1 - n`
+    Negative(u64),
+    Bytes(Bytes),
+    Text(String),
+    Array(Vec<Item>),
+    Tag(u64, Box<Item>),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The item continues past the end of the data
+    Incomplete,
+    Malformed(&'static str),
+}
+
+pub fn unsigned(buf: &mut Vec<u8>, value: u64) {
+    head(buf, UNSIGNED, value);
+}
+
+pub fn signed(buf: &mut Vec<u8>, value: i64) {
+    match u64::try_from(value) {
+        Ok(value) => head(buf, UNSIGNED, value),
+        Err(_) => head(buf, NEGATIVE, !(value as u64)),
+    }
+}
+
+pub fn bytes(buf: &mut Vec<u8>, value: &[u8]) {
+    head(buf, BYTES, value.len() as u64);
+    buf.put_slice(value);
+}
+
+pub fn text(buf: &mut Vec<u8>, value: &str) {
+    head(buf, TEXT, value.len() as u64);
+    buf.put_slice(value.as_bytes());
+}
+
+/// Starts an array, its `len` items follow
+pub fn array(buf: &mut Vec<u8>, len: usize) {
+    head(buf, ARRAY, len as u64);
+}
+
+/// Starts a tag, the tagged item follows
+pub fn tag(buf: &mut Vec<u8>, value: u64) {
+    head(buf, TAG, value);
+}
+
+/// Only peer down records carry one
+pub fn bool(buf: &mut Vec<u8>, value: bool) {
+    buf.put_u8(SIMPLE << 5 | if value { TRUE } else { FALSE });
+}
+
+pub fn null(buf: &mut Vec<u8>) {
+    buf.put_u8(SIMPLE << 5 | NULL);
+}
+
+/// The initial byte and argument, in the shortest form
+fn head(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..24 => buf.put_u8(major | value as u8),
+        24..0x100 => {
+            buf.put_u8(major | 24);
+            buf.put_u8(value as u8);
+        }
+        0x100..0x1_0000 => {
+            buf.put_u8(major | 25);
+            buf.put_u16(value as u16);
+        }
+        0x1_0000..0x1_0000_0000 => {
+            buf.put_u8(major | 26);
+            buf.put_u32(value as u32);
+        }
+        _ => {
+            buf.put_u8(major | 27);
+            buf.put_u64(value);
+        }
+    }
+}
+
+/// Decodes the item at the start of `data`, returning it and its encoded length
+pub fn decode(data: &Bytes) -> Result<(Item, usize), DecodeError> {
+    let mut offset = 0;
+    let item = decode_item(data, &mut offset, 0)?;
+    Ok((item, offset))
+}
+
+fn decode_item(data: &Bytes, offset: &mut usize, depth: usize) -> Result<Item, DecodeError> {
+    if depth > MAX_DEPTH {
+        return Err(DecodeError::Malformed("nested too deeply"));
+    }
+    let initial = *data.get(*offset).ok_or(DecodeError::Incomplete)?;
+    *offset += 1;
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+    if major == SIMPLE {
+        return match info {
+            FALSE => Ok(Item::Bool(false)),
+            TRUE => Ok(Item::Bool(true)),
+            NULL => Ok(Item::Null),
+            _ => Err(DecodeError::Malformed("unsupported simple value or float")),
+        };
+    }
+
+    let argument = match info {
+        0..24 => info as u64,
+        24..28 => {
+            let width = 1 << (info - 24);
+            let end = *offset + width;
+            let octets = data.get(*offset..end).ok_or(DecodeError::Incomplete)?;
+            *offset = end;
+            octets
+                .iter()
+                .fold(0, |value, octet| value << 8 | *octet as u64)
+        }
+        31 => return Err(DecodeError::Malformed("indefinite length")),
+        _ => return Err(DecodeError::Malformed("reserved additional information")),
+    };
+
+    match major {
+        UNSIGNED => Ok(Item::Unsigned(argument)),
+        NEGATIVE => Ok(Item::Negative(argument)),
+        BYTES | TEXT => {
+            let len = usize::try_from(argument).map_err(|_| DecodeError::Incomplete)?;
+            let end = offset.checked_add(len).ok_or(DecodeError::Incomplete)?;
+            if end > data.len() {
+                return Err(DecodeError::Incomplete);
+            }
+            let content = data.slice(*offset..end);
+            *offset = end;
+            match major {
+                BYTES => Ok(Item::Bytes(content)),
+                _ => String::from_utf8(content.to_vec())
+                    .map(Item::Text)
+                    .map_err(|_| DecodeError::Malformed("text is not UTF-8")),
+            }
+        }
+        ARRAY => {
+            // Every item takes at least an octet, which bounds the allocation by the data
+            let len = usize::try_from(argument).unwrap_or(usize::MAX);
+            let mut items = Vec::with_capacity(len.min(data.len() - *offset));
+            for _ in 0..len {
+                items.push(decode_item(data, offset, depth + 1)?);
+            }
+            Ok(Item::Array(items))
+        }
+        TAG => Ok(Item::Tag(
+            argument,
+            Box::new(decode_item(data, offset, depth + 1)?),
+        )),
+        _ => Err(DecodeError::Malformed("maps are not used")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoded(write: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut buf = vec![];
+        write(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn encodes_rfc_8949_examples() {
+        assert_eq!(encoded(|buf| unsigned(buf, 0)), [0x00]);
+        assert_eq!(encoded(|buf| unsigned(buf, 23)), [0x17]);
+        assert_eq!(encoded(|buf| unsigned(buf, 24)), [0x18, 0x18]);
+        assert_eq!(encoded(|buf| unsigned(buf, 1000)), [0x19, 0x03, 0xe8]);
+        assert_eq!(
+            encoded(|buf| unsigned(buf, 1_000_000)),
+            [0x1a, 0x00, 0x0f, 0x42, 0x40]
+        );
+        assert_eq!(
+            encoded(|buf| unsigned(buf, 1_000_000_000_000)),
+            [0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00]
+        );
+        assert_eq!(encoded(|buf| signed(buf, -1)), [0x20]);
+        assert_eq!(encoded(|buf| signed(buf, -1000)), [0x39, 0x03, 0xe7]);
+        assert_eq!(encoded(|buf| bytes(buf, &[1, 2, 3, 4])), [0x44, 1, 2, 3, 4]);
+        assert_eq!(encoded(|buf| text(buf, "IETF")), b"\x64IETF");
+        assert_eq!(encoded(|buf| bool(buf, true)), [0xf5]);
+        assert_eq!(encoded(null), [0xf6]);
+        assert_eq!(
+            encoded(|buf| tag(buf, SELF_DESCRIBE_TAG)),
+            [0xd9, 0xd9, 0xf7]
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = encoded(|buf| {
+            array(buf, 5);
+            signed(buf, -500);
+            text(buf, "bgp");
+            tag(buf, 1);
+            bytes(buf, &[0xff]);
+            null(buf);
+            array(buf, 1);
+            bool(buf, false);
+        });
+        let (item, len) = decode(&Bytes::from(data.clone())).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(
+            item,
+            Item::Array(vec![
+                Item::Negative(499),
+                Item::Text("bgp".into()),
+                Item::Tag(1, Box::new(Item::Bytes(Bytes::from_static(&[0xff])))),
+                Item::Null,
+                Item::Array(vec![Item::Bool(false)]),
+            ])
+        );
+
+        for end in 0..data.len() {
+            assert_eq!(
+                decode(&Bytes::copy_from_slice(&data[..end])),
+                Err(DecodeError::Incomplete)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_what_the_archive_never_writes() {
+        for data in [
+            &[0x5f][..],         // indefinite length byte string
+            &[0xa0],             // map
+            &[0xf9, 0x3c, 0],    // half float
+            &[0x1c],             // reserved
+            &[0x62, 0xff, 0xfe], // invalid UTF-8
+        ] {
+            assert!(matches!(
+                decode(&Bytes::copy_from_slice(data)),
+                Err(DecodeError::Malformed(_))
+            ));
+        }
+        let nested = Bytes::from(vec![0x81; MAX_DEPTH + 2]);
+        assert_eq!(
+            decode(&nested),
+            Err(DecodeError::Malformed("nested too deeply"))
+        );
+    }
+}
@@ -0,0 +1,1148 @@
+//! Compact binary archive of route and session events, in CBOR (RFC 8949).
+//!
+//! A stream starts with the self-describe tag and `["bgp_core events", version]`, followed by
+//! one array per record:
+//!
+//! ```text
+//! [0, set id, path attributes as encoded in an UPDATE]     defines an attribute set
+//! [1]                                                       forgets every attribute set
+//! [2, Δt, peer, AFI, prefix as encoded in NLRI, SAFI, path id | null, RPKI | null, change]
+//!     change: 0, set id | 1 | 2, old set id, new set id    announced, withdrawn, reannounced
+//! [3, Δt, peer, local OPEN, remote OPEN]                    peer up
+//! [4, Δt, peer, graceful restart, reason, ...]              peer down
+//! ```
+//!
+//! `Δt` is the signed difference in microseconds to the time of the previous record, the
+//! first relative to the Unix epoch. An attribute set is defined once, before the first event
+//! using it, and referred to by id after, so events for known paths cost little more than the
+//! prefix. Peers of session events are `[address, port, ASN, router id, router | null]`,
+//! OPENs and NOTIFICATIONs are their encoded bodies.
+//!
+//! Readers reject archives of a later version than [`ARCHIVE_VERSION`] rather than guess at
+//! their records.
+
+mod cbor;
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::PathAttribute;
+use crate::notification_message::NotificationMessage;
+use crate::open_message::OpenMessage;
+use crate::peer::{Negotiated, PeerDown, PeerDownReason, PeerInfo, PeerUp, ShutdownReason};
+use crate::rib::{AttributeSet, RouteEvent, RouteEventKind, decode_prefix};
+use crate::rpki::RpkiStatus;
+#[cfg(feature = "tokio")]
+use crate::{session::BusEvent, timestamped::Timestamped};
+
+use cbor::{DecodeError, Item};
+
+/// Bumped whenever the layout of a record changes
+pub const ARCHIVE_VERSION: u64 = 1;
+const MAGIC: &str = "bgp_core events";
+/// Defined attribute sets after which the writer starts over, bounding both sides' memory
+const MAX_ATTRIBUTE_SETS: usize = 1 << 16;
+const READ_SIZE: usize = 8 * 1024;
+
+const DEFINE_SET: u64 = 0;
+const RESET_SETS: u64 = 1;
+const ROUTE: u64 = 2;
+const PEER_UP: u64 = 3;
+const PEER_DOWN: u64 = 4;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("event archive I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("not an event archive")]
+    NotArchive,
+    #[error("unsupported event archive version {0}")]
+    UnsupportedVersion(u64),
+    #[error("event archive ends in a truncated record")]
+    Truncated,
+    #[error("malformed event archive record {record}: {reason}")]
+    Malformed { record: u64, reason: &'static str },
+}
+
+/// An event as stored in an archive
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchivedEvent {
+    Route(RouteEvent),
+    PeerUp {
+        timestamp: SystemTime,
+        up: PeerUp,
+    },
+    PeerDown {
+        timestamp: SystemTime,
+        down: PeerDown,
+    },
+}
+
+impl ArchivedEvent {
+    pub fn timestamp(&self) -> SystemTime {
+        match self {
+            ArchivedEvent::Route(event) => event.timestamp,
+            ArchivedEvent::PeerUp { timestamp, .. } | ArchivedEvent::PeerDown { timestamp, .. } => {
+                *timestamp
+            }
+        }
+    }
+}
+
+impl From<RouteEvent> for ArchivedEvent {
+    fn from(event: RouteEvent) -> Self {
+        ArchivedEvent::Route(event)
+    }
+}
+
+/// Writes events to an archive, see the [module](self) for the format
+pub struct EventArchiveWriter<W: Write> {
+    writer: W,
+    /// Ids of the defined attribute sets, by their encoding
+    sets: HashMap<Bytes, u64>,
+    max_sets: usize,
+    micros: i64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EventArchiveWriter<W> {
+    /// Writes the header
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut buf = vec![];
+        cbor::tag(&mut buf, cbor::SELF_DESCRIBE_TAG);
+        cbor::array(&mut buf, 2);
+        cbor::text(&mut buf, MAGIC);
+        cbor::unsigned(&mut buf, ARCHIVE_VERSION);
+        writer.write_all(&buf)?;
+        buf.clear();
+        Ok(EventArchiveWriter {
+            writer,
+            sets: HashMap::new(),
+            max_sets: MAX_ATTRIBUTE_SETS,
+            micros: 0,
+            buf,
+        })
+    }
+
+    pub fn write(&mut self, event: &ArchivedEvent) -> io::Result<()> {
+        match event {
+            ArchivedEvent::Route(event) => self.write_route(event),
+            ArchivedEvent::PeerUp { timestamp, up } => self.write_peer_up(*timestamp, up),
+            ArchivedEvent::PeerDown { timestamp, down } => self.write_peer_down(*timestamp, down),
+        }
+    }
+
+    pub fn write_route(&mut self, event: &RouteEvent) -> io::Result<()> {
+        let (change, sets) = match &event.kind {
+            RouteEventKind::Announced { attrs } => (0, vec![attrs]),
+            RouteEventKind::Withdrawn => (1, vec![]),
+            RouteEventKind::Reannounced {
+                old_attrs,
+                new_attrs,
+            } => (2, vec![old_attrs, new_attrs]),
+        };
+        // All sets of the event must be defined in the same generation
+        if self.sets.len() + sets.len() > self.max_sets {
+            self.reset();
+        }
+        let ids: Vec<u64> = sets.into_iter().map(|set| self.set_id(set)).collect();
+
+        cbor::array(&mut self.buf, 9 + ids.len());
+        cbor::unsigned(&mut self.buf, ROUTE);
+        self.delta(event.timestamp);
+        cbor::bytes(&mut self.buf, &address(event.peer));
+        cbor::unsigned(&mut self.buf, u16::from(event.prefix.afi()).into());
+        let mut prefix = BytesMut::new();
+        event.prefix.encode(&mut prefix);
+        cbor::bytes(&mut self.buf, &prefix);
+        cbor::unsigned(&mut self.buf, u8::from(event.safi).into());
+        match event.path_id {
+            Some(path_id) => cbor::unsigned(&mut self.buf, path_id.into()),
+            None => cbor::null(&mut self.buf),
+        }
+        match event.rpki {
+            Some(status) => cbor::unsigned(&mut self.buf, rpki_code(status)),
+            None => cbor::null(&mut self.buf),
+        }
+        cbor::unsigned(&mut self.buf, change);
+        for id in ids {
+            cbor::unsigned(&mut self.buf, id);
+        }
+        self.flush_record()
+    }
+
+    pub fn write_peer_up(&mut self, timestamp: SystemTime, up: &PeerUp) -> io::Result<()> {
+        cbor::array(&mut self.buf, 5);
+        cbor::unsigned(&mut self.buf, PEER_UP);
+        self.delta(timestamp);
+        peer_info(&mut self.buf, &up.peer);
+        cbor::bytes(&mut self.buf, &up.local_open.to_bytes());
+        cbor::bytes(&mut self.buf, &up.remote_open.to_bytes());
+        self.flush_record()
+    }
+
+    pub fn write_peer_down(&mut self, timestamp: SystemTime, down: &PeerDown) -> io::Result<()> {
+        let mut reason = vec![];
+        let fields = peer_down_reason(&mut reason, &down.reason);
+        cbor::array(&mut self.buf, 4 + fields);
+        cbor::unsigned(&mut self.buf, PEER_DOWN);
+        self.delta(timestamp);
+        peer_info(&mut self.buf, &down.peer);
+        cbor::bool(&mut self.buf, down.graceful_restart);
+        self.buf.extend_from_slice(&reason);
+        self.flush_record()
+    }
+
+    /// Writes the route and peer events of the bus, `false` for the session events it skips
+    #[cfg(feature = "tokio")]
+    pub fn write_bus(&mut self, event: &Timestamped<BusEvent>) -> io::Result<bool> {
+        match &event.value {
+            BusEvent::Session(_) => return Ok(false),
+            BusEvent::Route(route) => self.write_route(route)?,
+            BusEvent::PeerUp(up) => self.write_peer_up(event.received, up)?,
+            BusEvent::PeerDown(down) => self.write_peer_down(event.received, down)?,
+        }
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// The id of a set, defining it first if it's new
+    fn set_id(&mut self, attributes: &AttributeSet) -> u64 {
+        let mut encoded = BytesMut::new();
+        for attribute in attributes.iter() {
+            attribute.encode(&mut encoded);
+        }
+        let encoded = encoded.freeze();
+        if let Some(id) = self.sets.get(&encoded) {
+            return *id;
+        }
+        let id = self.sets.len() as u64;
+        cbor::array(&mut self.buf, 3);
+        cbor::unsigned(&mut self.buf, DEFINE_SET);
+        cbor::unsigned(&mut self.buf, id);
+        cbor::bytes(&mut self.buf, &encoded);
+        self.sets.insert(encoded, id);
+        id
+    }
+
+    fn reset(&mut self) {
+        cbor::array(&mut self.buf, 1);
+        cbor::unsigned(&mut self.buf, RESET_SETS);
+        self.sets.clear();
+    }
+
+    fn delta(&mut self, timestamp: SystemTime) {
+        let micros = micros(timestamp);
+        cbor::signed(&mut self.buf, micros.wrapping_sub(self.micros));
+        self.micros = micros;
+    }
+
+    fn flush_record(&mut self) -> io::Result<()> {
+        let result = self.writer.write_all(&self.buf);
+        self.buf.clear();
+        result
+    }
+}
+
+/// Reads the events of an archive.
+///
+/// A record that doesn't decode ends the iteration, since the sets and times of the records
+/// after it may depend on it.
+pub struct EventArchiveReader<R> {
+    reader: R,
+    /// Read but not yet decoded
+    pending: Bytes,
+    version: u64,
+    sets: Vec<AttributeSet>,
+    micros: i64,
+    records: u64,
+    done: bool,
+}
+
+impl<R: Read> EventArchiveReader<R> {
+    /// Reads and checks the header
+    pub fn new(reader: R) -> Result<Self, ArchiveError> {
+        let mut archive = EventArchiveReader {
+            reader,
+            pending: Bytes::new(),
+            version: 0,
+            sets: vec![],
+            micros: 0,
+            records: 0,
+            done: false,
+        };
+        let header = match archive.next_item() {
+            Ok(Some(Item::Tag(cbor::SELF_DESCRIBE_TAG, header))) => *header,
+            Ok(_) | Err(ArchiveError::Malformed { .. } | ArchiveError::Truncated) => {
+                return Err(ArchiveError::NotArchive);
+            }
+            Err(err) => return Err(err),
+        };
+        let version = match header {
+            Item::Array(fields) => match fields.as_slice() {
+                [Item::Text(magic), Item::Unsigned(version), ..] if magic == MAGIC => *version,
+                _ => return Err(ArchiveError::NotArchive),
+            },
+            _ => return Err(ArchiveError::NotArchive),
+        };
+        if !(1..=ARCHIVE_VERSION).contains(&version) {
+            return Err(ArchiveError::UnsupportedVersion(version));
+        }
+        archive.version = version;
+        Ok(archive)
+    }
+
+    /// Of the archive being read, at most [`ARCHIVE_VERSION`]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The next CBOR item, `None` at the end of the stream
+    fn next_item(&mut self) -> Result<Option<Item>, ArchiveError> {
+        loop {
+            if !self.pending.is_empty() {
+                match cbor::decode(&self.pending) {
+                    Ok((item, len)) => {
+                        self.pending.advance(len);
+                        return Ok(Some(item));
+                    }
+                    Err(DecodeError::Incomplete) => {}
+                    Err(DecodeError::Malformed(reason)) => return Err(self.malformed(reason)),
+                }
+            }
+
+            let mut chunk = [0; READ_SIZE];
+            let read = match self.reader.read(&mut chunk) {
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if read == 0 {
+                return match self.pending.is_empty() {
+                    true => Ok(None),
+                    false => Err(ArchiveError::Truncated),
+                };
+            }
+            let mut joined = BytesMut::with_capacity(self.pending.len() + read);
+            joined.put_slice(&self.pending);
+            joined.put_slice(&chunk[..read]);
+            self.pending = joined.freeze();
+        }
+    }
+
+    fn malformed(&self, reason: &'static str) -> ArchiveError {
+        ArchiveError::Malformed {
+            record: self.records,
+            reason,
+        }
+    }
+
+    /// The next event, reading through set definitions
+    fn next_event(&mut self) -> Result<Option<ArchivedEvent>, ArchiveError> {
+        loop {
+            let Some(item) = self.next_item()? else {
+                return Ok(None);
+            };
+            let event = self.decode(item).map_err(|reason| self.malformed(reason))?;
+            self.records += 1;
+            if event.is_some() {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Decodes a record, `None` for set definitions
+    fn decode(&mut self, item: Item) -> Result<Option<ArchivedEvent>, &'static str> {
+        let Item::Array(fields) = item else {
+            return Err("not an array");
+        };
+        let mut fields = Fields(fields.into_iter());
+        let event = match fields.unsigned("record type")? {
+            DEFINE_SET => {
+                if fields.unsigned("set id")? != self.sets.len() as u64 {
+                    return Err("attribute set ids out of order");
+                }
+                let mut encoded = fields.bytes("attribute set")?;
+                let mut attributes = vec![];
+                while !encoded.is_empty() {
+                    let attribute = PathAttribute::try_decode(&mut encoded)
+                        .map_err(|_| "malformed attribute set")?;
+                    attributes.push(attribute);
+                }
+                self.sets.push(attributes.into());
+                None
+            }
+            RESET_SETS => {
+                self.sets.clear();
+                None
+            }
+            ROUTE => Some(ArchivedEvent::Route(self.route(&mut fields)?)),
+            PEER_UP => {
+                let timestamp = self.timestamp(&mut fields)?;
+                let peer = peer_from(&mut fields)?;
+                let local_open = open_from(&mut fields, "local OPEN")?;
+                let remote_open = open_from(&mut fields, "remote OPEN")?;
+                Some(ArchivedEvent::PeerUp {
+                    timestamp,
+                    up: PeerUp {
+                        peer,
+                        negotiated: Negotiated::new(&local_open, &remote_open),
+                        local_open,
+                        remote_open,
+                    },
+                })
+            }
+            PEER_DOWN => {
+                let timestamp = self.timestamp(&mut fields)?;
+                let peer = peer_from(&mut fields)?;
+                let graceful_restart = fields.bool("graceful restart")?;
+                let reason = peer_down_reason_from(&mut fields)?;
+                Some(ArchivedEvent::PeerDown {
+                    timestamp,
+                    down: PeerDown {
+                        peer,
+                        reason,
+                        graceful_restart,
+                    },
+                })
+            }
+            _ => return Err("unknown record type"),
+        };
+        Ok(event)
+    }
+
+    fn route(&mut self, fields: &mut Fields) -> Result<RouteEvent, &'static str> {
+        let timestamp = self.timestamp(fields)?;
+        let peer = ip_addr(&fields.bytes("peer")?).ok_or("peer")?;
+        let afi = Afi::from(u16::try_from(fields.unsigned("AFI")?).map_err(|_| "AFI")?);
+        let mut encoded = fields.bytes("prefix")?;
+        let prefix = decode_prefix(&mut encoded, afi).ok_or("prefix")?;
+        if !encoded.is_empty() {
+            return Err("prefix");
+        }
+        let safi = Safi::from(u8::try_from(fields.unsigned("SAFI")?).map_err(|_| "SAFI")?);
+        let path_id = match fields.optional_unsigned("path id")? {
+            Some(path_id) => Some(u32::try_from(path_id).map_err(|_| "path id")?),
+            None => None,
+        };
+        let rpki = match fields.optional_unsigned("RPKI status")? {
+            Some(code) => Some(rpki_status(code).ok_or("RPKI status")?),
+            None => None,
+        };
+        let kind = match fields.unsigned("change")? {
+            0 => RouteEventKind::Announced {
+                attrs: self.set(fields)?,
+            },
+            1 => RouteEventKind::Withdrawn,
+            2 => RouteEventKind::Reannounced {
+                old_attrs: self.set(fields)?,
+                new_attrs: self.set(fields)?,
+            },
+            _ => return Err("change"),
+        };
+        Ok(RouteEvent {
+            peer,
+            timestamp,
+            prefix,
+            safi,
+            path_id,
+            kind,
+            rpki,
+        })
+    }
+
+    fn set(&self, fields: &mut Fields) -> Result<AttributeSet, &'static str> {
+        let id = fields.unsigned("set id")?;
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| self.sets.get(id))
+            .cloned()
+            .ok_or("undefined attribute set")
+    }
+
+    fn timestamp(&mut self, fields: &mut Fields) -> Result<SystemTime, &'static str> {
+        self.micros = self.micros.wrapping_add(fields.signed("time")?);
+        Ok(from_micros(self.micros))
+    }
+}
+
+impl<R: Read> Iterator for EventArchiveReader<R> {
+    type Item = Result<ArchivedEvent, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// The remaining fields of a record, each error naming the field
+struct Fields(std::vec::IntoIter<Item>);
+
+impl Fields {
+    fn next(&mut self, field: &'static str) -> Result<Item, &'static str> {
+        self.0.next().ok_or(field)
+    }
+
+    fn unsigned(&mut self, field: &'static str) -> Result<u64, &'static str> {
+        match self.next(field)? {
+            Item::Unsigned(value) => Ok(value),
+            _ => Err(field),
+        }
+    }
+
+    fn optional_unsigned(&mut self, field: &'static str) -> Result<Option<u64>, &'static str> {
+        match self.next(field)? {
+            Item::Unsigned(value) => Ok(Some(value)),
+            Item::Null => Ok(None),
+            _ => Err(field),
+        }
+    }
+
+    fn signed(&mut self, field: &'static str) -> Result<i64, &'static str> {
+        match self.next(field)? {
+            Item::Unsigned(value) => i64::try_from(value).map_err(|_| field),
+            Item::Negative(value) => i64::try_from(value)
+                .map(|value| -1 - value)
+                .map_err(|_| field),
+            _ => Err(field),
+        }
+    }
+
+    fn bytes(&mut self, field: &'static str) -> Result<Bytes, &'static str> {
+        match self.next(field)? {
+            Item::Bytes(value) => Ok(value),
+            _ => Err(field),
+        }
+    }
+
+    fn bool(&mut self, field: &'static str) -> Result<bool, &'static str> {
+        match self.next(field)? {
+            Item::Bool(value) => Ok(value),
+            _ => Err(field),
+        }
+    }
+}
+
+fn address(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+fn ip_addr(octets: &[u8]) -> Option<IpAddr> {
+    match octets.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(octets).unwrap())),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(octets).unwrap())),
+        _ => None,
+    }
+}
+
+fn micros(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }
+}
+
+fn from_micros(micros: i64) -> SystemTime {
+    match u64::try_from(micros) {
+        Ok(since) => UNIX_EPOCH + Duration::from_micros(since),
+        Err(_) => UNIX_EPOCH - Duration::from_micros(micros.unsigned_abs()),
+    }
+}
+
+fn rpki_code(status: RpkiStatus) -> u64 {
+    match status {
+        RpkiStatus::Valid => 0,
+        RpkiStatus::Invalid => 1,
+        RpkiStatus::NotFound => 2,
+    }
+}
+
+fn rpki_status(code: u64) -> Option<RpkiStatus> {
+    match code {
+        0 => Some(RpkiStatus::Valid),
+        1 => Some(RpkiStatus::Invalid),
+        2 => Some(RpkiStatus::NotFound),
+        _ => None,
+    }
+}
+
+fn peer_info(buf: &mut Vec<u8>, peer: &PeerInfo) {
+    cbor::array(buf, 5);
+    cbor::bytes(buf, &address(peer.peer_addr.ip()));
+    cbor::unsigned(buf, peer.peer_addr.port().into());
+    cbor::unsigned(buf, peer.asn.into());
+    cbor::bytes(buf, &peer.router_id.octets());
+    match peer.router {
+        Some(router) => cbor::bytes(buf, &address(router)),
+        None => cbor::null(buf),
+    }
+}
+
+fn peer_from(fields: &mut Fields) -> Result<PeerInfo, &'static str> {
+    let Item::Array(peer) = fields.next("peer")? else {
+        return Err("peer");
+    };
+    let mut peer = Fields(peer.into_iter());
+    let addr = ip_addr(&peer.bytes("peer address")?).ok_or("peer address")?;
+    let port = u16::try_from(peer.unsigned("peer port")?).map_err(|_| "peer port")?;
+    let asn = u32::try_from(peer.unsigned("peer ASN")?).map_err(|_| "peer ASN")?;
+    let router_id = <[u8; 4]>::try_from(&peer.bytes("router id")?[..]).map_err(|_| "router id")?;
+    let router = match peer.next("router")? {
+        Item::Bytes(router) => Some(ip_addr(&router).ok_or("router")?),
+        Item::Null => None,
+        _ => return Err("router"),
+    };
+    Ok(PeerInfo {
+        peer_addr: (addr, port).into(),
+        asn,
+        router_id: router_id.into(),
+        router,
+    })
+}
+
+fn open_from(fields: &mut Fields, field: &'static str) -> Result<OpenMessage, &'static str> {
+    let mut encoded = fields.bytes(field)?;
+    OpenMessage::try_from(&mut encoded).map_err(|_| field)
+}
+
+fn notification_from(fields: &mut Fields) -> Result<NotificationMessage, &'static str> {
+    let mut encoded = fields.bytes("NOTIFICATION")?;
+    NotificationMessage::try_decode(&mut encoded).map_err(|_| "NOTIFICATION")
+}
+
+/// Writes the reason and its details, returning how many fields they take
+fn peer_down_reason(buf: &mut Vec<u8>, reason: &PeerDownReason) -> usize {
+    let message = |buf: &mut Vec<u8>, message: &Option<String>| match message {
+        Some(message) => cbor::text(buf, message),
+        None => cbor::null(buf),
+    };
+    match reason {
+        PeerDownReason::LocalShutdown(shutdown) => {
+            cbor::unsigned(buf, 0);
+            match shutdown {
+                ShutdownReason::AdministrativeShutdown(text) => {
+                    cbor::unsigned(buf, 0);
+                    message(buf, text);
+                    3
+                }
+                ShutdownReason::PeerDeconfigured => {
+                    cbor::unsigned(buf, 1);
+                    2
+                }
+                ShutdownReason::AdministrativeReset(text) => {
+                    cbor::unsigned(buf, 2);
+                    message(buf, text);
+                    3
+                }
+                ShutdownReason::OtherConfigurationChange => {
+                    cbor::unsigned(buf, 3);
+                    2
+                }
+                ShutdownReason::OutOfResources => {
+                    cbor::unsigned(buf, 4);
+                    2
+                }
+                ShutdownReason::HardReset => {
+                    cbor::unsigned(buf, 5);
+                    2
+                }
+                ShutdownReason::MaximumPrefixes { afi, safi, limit } => {
+                    cbor::unsigned(buf, 6);
+                    cbor::unsigned(buf, u16::from(*afi).into());
+                    cbor::unsigned(buf, u8::from(*safi).into());
+                    cbor::unsigned(buf, (*limit).into());
+                    5
+                }
+            }
+        }
+        PeerDownReason::HoldTimerExpired => {
+            cbor::unsigned(buf, 1);
+            1
+        }
+        PeerDownReason::LocalError(notification) => {
+            cbor::unsigned(buf, 2);
+            cbor::bytes(buf, &notification.to_bytes());
+            2
+        }
+        PeerDownReason::LocalClose => {
+            cbor::unsigned(buf, 3);
+            1
+        }
+        PeerDownReason::RemoteNotification(notification) => {
+            cbor::unsigned(buf, 4);
+            cbor::bytes(buf, &notification.to_bytes());
+            2
+        }
+        PeerDownReason::CollisionResolution => {
+            cbor::unsigned(buf, 5);
+            1
+        }
+        PeerDownReason::ConnectionLost => {
+            cbor::unsigned(buf, 6);
+            1
+        }
+    }
+}
+
+fn peer_down_reason_from(fields: &mut Fields) -> Result<PeerDownReason, &'static str> {
+    let message = |fields: &mut Fields| match fields.next("shutdown communication")? {
+        Item::Text(message) => Ok(Some(message)),
+        Item::Null => Ok(None),
+        _ => Err("shutdown communication"),
+    };
+    let reason = match fields.unsigned("peer down reason")? {
+        0 => PeerDownReason::LocalShutdown(match fields.unsigned("shutdown reason")? {
+            0 => ShutdownReason::AdministrativeShutdown(message(fields)?),
+            1 => ShutdownReason::PeerDeconfigured,
+            2 => ShutdownReason::AdministrativeReset(message(fields)?),
+            3 => ShutdownReason::OtherConfigurationChange,
+            4 => ShutdownReason::OutOfResources,
+            5 => ShutdownReason::HardReset,
+            6 => ShutdownReason::MaximumPrefixes {
+                afi: Afi::from(u16::try_from(fields.unsigned("AFI")?).map_err(|_| "AFI")?),
+                safi: Safi::from(u8::try_from(fields.unsigned("SAFI")?).map_err(|_| "SAFI")?),
+                limit: u32::try_from(fields.unsigned("limit")?).map_err(|_| "limit")?,
+            },
+            _ => return Err("shutdown reason"),
+        }),
+        1 => PeerDownReason::HoldTimerExpired,
+        2 => PeerDownReason::LocalError(notification_from(fields)?),
+        3 => PeerDownReason::LocalClose,
+        4 => PeerDownReason::RemoteNotification(notification_from(fields)?),
+        5 => PeerDownReason::CollisionResolution,
+        6 => PeerDownReason::ConnectionLost,
+        _ => return Err("peer down reason"),
+    };
+    Ok(reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::json::Json;
+    use crate::rib::RouteEventSource;
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    /// A table load followed by churn from four peers, as a collector would see it: UPDATEs
+    /// announcing 256 prefixes each with one of a few dozen paths, then a MED change or
+    /// withdrawal every so often
+    fn fixture_events() -> Vec<RouteEvent> {
+        let mut events = vec![];
+        let mut time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for peer in 1..=4u8 {
+            let peer_addr = IpAddr::from([192, 0, 2, peer]);
+            let mut source = RouteEventSource::with_rib(peer_addr);
+            let path = |block: u32, med: u32| {
+                UpdateMessageBuilder::new()
+                    .as_path(AsPath {
+                        segments: [AsPathSegment {
+                            segment_type: AsPathSegmentType::AsSequence,
+                            asns: [64500 + peer as u32, 3356, 65000 + block % 40].into(),
+                        }]
+                        .into(),
+                    })
+                    .next_hop(peer_addr)
+                    .med(med)
+            };
+            for block in 0..16u8 {
+                let update = (0..=255)
+                    .fold(path(block.into(), 0), |update, third| {
+                        update
+                            .announce(IpAddrPrefix::new([10, block, third, 0].into(), 24).unwrap())
+                    })
+                    .build();
+                time += Duration::from_micros(1234);
+                events.extend(source.events(&update, time));
+            }
+            for step in 0..200u32 {
+                let prefix =
+                    IpAddrPrefix::new([10, (step % 16) as u8, (step * 7) as u8, 0].into(), 24)
+                        .unwrap();
+                let update = match step % 3 {
+                    0 => UpdateMessageBuilder::new().withdraw(prefix),
+                    _ => path(step % 16, step).announce(prefix),
+                };
+                time += Duration::from_millis(250);
+                events.extend(source.events(&update.build(), time));
+            }
+        }
+        events
+    }
+
+    fn write(events: &[ArchivedEvent]) -> Vec<u8> {
+        let mut writer = EventArchiveWriter::new(vec![]).unwrap();
+        for event in events {
+            writer.write(event).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn read(archive: &[u8]) -> Vec<ArchivedEvent> {
+        EventArchiveReader::new(archive)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn announced(prefix: &str, med: u32, micros: u64) -> RouteEvent {
+        let update = UpdateMessageBuilder::new()
+            .next_hop("192.0.2.1".parse().unwrap())
+            .med(med)
+            .announce(prefix.parse().unwrap())
+            .build();
+        let mut events = RouteEventSource::stateless("192.0.2.1".parse().unwrap())
+            .events(&update, UNIX_EPOCH + Duration::from_micros(micros));
+        events.pop().unwrap()
+    }
+
+    /// What a JSON line per event would take, attribute sets as the hex of their encoding
+    fn json(event: &RouteEvent) -> Json {
+        let hex = |attributes: &AttributeSet| {
+            let mut encoded = BytesMut::new();
+            attributes
+                .iter()
+                .for_each(|attribute| attribute.encode(&mut encoded));
+            Json::from(
+                encoded
+                    .iter()
+                    .map(|octet| format!("{octet:02x}"))
+                    .collect::<String>(),
+            )
+        };
+        let timestamp = event.timestamp.duration_since(UNIX_EPOCH).unwrap();
+        let kind = match &event.kind {
+            RouteEventKind::Announced { attrs } => {
+                Json::object([("announced", Json::object([("attrs", hex(attrs))]))])
+            }
+            RouteEventKind::Withdrawn => Json::from("withdrawn"),
+            RouteEventKind::Reannounced {
+                old_attrs,
+                new_attrs,
+            } => Json::object([(
+                "reannounced",
+                Json::object([("old_attrs", hex(old_attrs)), ("new_attrs", hex(new_attrs))]),
+            )]),
+        };
+        Json::object([
+            ("peer", Json::from(event.peer.to_string())),
+            ("timestamp", Json::Number(timestamp.as_secs_f64())),
+            ("prefix", Json::from(event.prefix.to_string())),
+            ("safi", Json::from("unicast")),
+            ("path_id", event.path_id.map_or(Json::Null, Json::from)),
+            ("kind", kind),
+            ("rpki", Json::Null),
+        ])
+    }
+
+    #[test]
+    fn round_trips_the_fixture_in_a_fraction_of_json() {
+        let events = fixture_events();
+        assert!(events.len() > 16_000);
+        let archived: Vec<ArchivedEvent> = events.iter().cloned().map(Into::into).collect();
+        let archive = write(&archived);
+        assert_eq!(read(&archive), archived);
+
+        // About a tenth, mostly from writing each path once rather than with every prefix
+        let json: usize = events
+            .iter()
+            .map(|event| json(event).to_string().len() + 1)
+            .sum();
+        assert!(
+            archive.len() * 8 < json,
+            "archive {} bytes, JSON {json} bytes",
+            archive.len()
+        );
+    }
+
+    #[test]
+    fn defines_attribute_sets_once() {
+        let events: Vec<ArchivedEvent> = ["10.0.0.0/8", "10.1.0.0/16", "10.2.0.0/16"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, prefix)| announced(prefix, 10, 1_700_000_000_000_000 + i as u64).into())
+            .collect();
+        let archive = write(&events);
+        let single = write(&events[..1]);
+        // A later event with the same attributes costs the record alone, about 20 bytes
+        assert!(archive.len() - single.len() < 2 * 20);
+
+        let read = read(&archive);
+        assert_eq!(read, events);
+        let sets: Vec<&AttributeSet> = read
+            .iter()
+            .map(|event| match event {
+                ArchivedEvent::Route(RouteEvent {
+                    kind: RouteEventKind::Announced { attrs },
+                    ..
+                }) => attrs,
+                _ => panic!("{event:?}"),
+            })
+            .collect();
+        assert!(Arc::ptr_eq(sets[0], sets[1]) && Arc::ptr_eq(sets[1], sets[2]));
+    }
+
+    #[test]
+    fn starts_over_when_the_sets_fill_up() {
+        let mut events: Vec<ArchivedEvent> = (0..5)
+            .map(|med| announced("10.0.0.0/8", med, 1_000).into())
+            .collect();
+        let RouteEventKind::Announced { attrs: old_attrs } = announced("10.0.0.0/8", 1, 0).kind
+        else {
+            unreachable!()
+        };
+        let RouteEventKind::Announced { attrs: new_attrs } = announced("10.0.0.0/8", 9, 0).kind
+        else {
+            unreachable!()
+        };
+        let mut reannounced = announced("10.0.0.0/8", 0, 500);
+        reannounced.kind = RouteEventKind::Reannounced {
+            old_attrs,
+            new_attrs,
+        };
+        reannounced.path_id = Some(7);
+        reannounced.rpki = Some(RpkiStatus::Invalid);
+        events.push(reannounced.into());
+
+        let mut writer = EventArchiveWriter::new(vec![]).unwrap();
+        writer.max_sets = 2;
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        assert_eq!(read(&writer.into_inner()), events);
+    }
+
+    #[test]
+    fn keeps_times_to_the_microsecond_in_any_order() {
+        let events: Vec<ArchivedEvent> = [1_700_000_000_123_456, 5, 1_700_000_000_000_001]
+            .into_iter()
+            .map(|micros| announced("10.0.0.0/8", 0, micros).into())
+            .collect();
+        let mut before_epoch = announced("10.0.0.0/8", 0, 0);
+        before_epoch.timestamp = UNIX_EPOCH - Duration::from_micros(42);
+        let mut events = events;
+        events.push(before_epoch.into());
+        assert_eq!(read(&write(&events)), events);
+    }
+
+    /// Hands out a byte per read, as a slow socket might
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn reads_records_split_across_reads() {
+        let events: Vec<ArchivedEvent> = fixture_events()
+            .into_iter()
+            .take(200)
+            .map(Into::into)
+            .collect();
+        let archive = write(&events);
+        let read: Vec<_> = EventArchiveReader::new(Trickle(&archive))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+    }
+
+    fn header(version: u64) -> Vec<u8> {
+        let mut buf = vec![];
+        cbor::tag(&mut buf, cbor::SELF_DESCRIBE_TAG);
+        cbor::array(&mut buf, 2);
+        cbor::text(&mut buf, MAGIC);
+        cbor::unsigned(&mut buf, version);
+        buf
+    }
+
+    #[test]
+    fn rejects_later_versions_and_other_data() {
+        assert_eq!(
+            EventArchiveReader::new(&header(ARCHIVE_VERSION)[..])
+                .unwrap()
+                .version(),
+            ARCHIVE_VERSION
+        );
+        for version in [0, ARCHIVE_VERSION + 1, u64::MAX] {
+            assert!(matches!(
+                EventArchiveReader::new(&header(version)[..]),
+                Err(ArchiveError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+        for data in [
+            &b""[..],
+            b"BGPRIB\x00\x02",
+            &header(1)[..10],
+            b"\xd9\xd9\xf7\x01",
+        ] {
+            assert!(
+                matches!(EventArchiveReader::new(data), Err(ArchiveError::NotArchive)),
+                "{data:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_truncated_and_malformed_records() {
+        let events: Vec<ArchivedEvent> = (0..2)
+            .map(|med| announced("10.0.0.0/8", med, 0).into())
+            .collect();
+        let archive = write(&events);
+        let mut reader = EventArchiveReader::new(&archive[..archive.len() - 1]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), events[0]);
+        assert!(matches!(reader.next(), Some(Err(ArchiveError::Truncated))));
+        assert!(reader.next().is_none());
+
+        let mut undefined = header(1);
+        cbor::array(&mut undefined, 10);
+        for field in [ROUTE, 0] {
+            cbor::unsigned(&mut undefined, field);
+        }
+        cbor::bytes(&mut undefined, &[10, 0, 0, 1]);
+        cbor::unsigned(&mut undefined, 1);
+        cbor::bytes(&mut undefined, &[8, 10]);
+        cbor::unsigned(&mut undefined, 1);
+        cbor::null(&mut undefined);
+        cbor::null(&mut undefined);
+        cbor::unsigned(&mut undefined, 0);
+        cbor::unsigned(&mut undefined, 3);
+        let mut reader = EventArchiveReader::new(&undefined[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(ArchiveError::Malformed {
+                record: 0,
+                reason: "undefined attribute set"
+            }))
+        ));
+    }
+
+    #[test]
+    fn round_trips_peer_events() {
+        use crate::capability::Capability;
+        use crate::notification_message::{CeaseSubErr, NotificationErrorCode};
+        use std::net::Ipv4Addr;
+
+        let peer = PeerInfo {
+            peer_addr: "[2001:db8::1]:179".parse().unwrap(),
+            asn: 4_200_000_000,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: Some("192.0.2.254".parse().unwrap()),
+        };
+        let local_open = OpenMessage::new(
+            65000,
+            90,
+            Ipv4Addr::new(192, 0, 2, 2),
+            &[Capability::FourOctetAs { asn: 65000 }],
+        );
+        let remote_open = OpenMessage::new(
+            4_200_000_000,
+            30,
+            peer.router_id,
+            &[Capability::FourOctetAs { asn: 4_200_000_000 }],
+        );
+        let at = |seconds| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds);
+        let mut events = vec![ArchivedEvent::PeerUp {
+            timestamp: at(0),
+            up: PeerUp {
+                peer,
+                negotiated: Negotiated::new(&local_open, &remote_open),
+                local_open,
+                remote_open,
+            },
+        }];
+        let reasons = [
+            PeerDownReason::LocalShutdown(ShutdownReason::AdministrativeShutdown(Some(
+                "maintenance".into(),
+            ))),
+            PeerDownReason::LocalShutdown(ShutdownReason::AdministrativeReset(None)),
+            PeerDownReason::LocalShutdown(ShutdownReason::HardReset),
+            PeerDownReason::LocalShutdown(ShutdownReason::MaximumPrefixes {
+                afi: Afi::Ipv6,
+                safi: Safi::Unicast,
+                limit: 1000,
+            }),
+            PeerDownReason::HoldTimerExpired,
+            PeerDownReason::LocalError(NotificationMessage::new(
+                NotificationErrorCode::Cease(CeaseSubErr::OutOfResources),
+                vec![],
+            )),
+            PeerDownReason::LocalClose,
+            PeerDownReason::RemoteNotification(NotificationMessage::new(
+                NotificationErrorCode::Unknown(6, 42),
+                vec![1, 2, 3],
+            )),
+            PeerDownReason::CollisionResolution,
+            PeerDownReason::ConnectionLost,
+        ];
+        for (i, reason) in reasons.into_iter().enumerate() {
+            events.push(ArchivedEvent::PeerDown {
+                timestamp: at(i as u64 + 1),
+                down: PeerDown {
+                    peer,
+                    reason,
+                    graceful_restart: i % 2 == 0,
+                },
+            });
+        }
+        events.push(announced("10.0.0.0/8", 0, 0).into());
+        assert_eq!(read(&write(&events)), events);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn writes_route_and_peer_events_of_the_bus() {
+        use crate::session::SessionEvent;
+        use std::net::Ipv4Addr;
+
+        let event = announced("10.0.0.0/8", 0, 0);
+        let mut writer = EventArchiveWriter::new(vec![]).unwrap();
+        let bus = Timestamped::now(BusEvent::Route(event.clone()));
+        assert!(writer.write_bus(&bus).unwrap());
+        let keepalive = Timestamped::now(BusEvent::Session(SessionEvent::Keepalive(PeerInfo {
+            peer_addr: "192.0.2.1:179".parse().unwrap(),
+            asn: 65001,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: None,
+        })));
+        assert!(!writer.write_bus(&keepalive).unwrap());
+        assert_eq!(read(&writer.into_inner()), [ArchivedEvent::Route(event)]);
+    }
+}
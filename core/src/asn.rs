@@ -0,0 +1,179 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An autonomous system number, printed in asplain, or in asdot with `{:#}` (RFC 5396)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct Asn(pub u32);
+
+impl Asn {
+    /// Stands in for 4 octet ASNs towards speakers without 4 octet AS support (RFC 6793)
+    pub const AS_TRANS: Asn = Asn(23456);
+
+    /// `high.low` for ASNs beyond 16 bits, asplain below, e.g. `64086.59904` for 4200000000
+    pub fn format_asdot(self) -> String {
+        format!("{self:#}")
+    }
+
+    /// Set aside for private use (RFC 6996), 64512 to 65534 and 4200000000 to 4294967294
+    pub fn is_private(self) -> bool {
+        matches!(self.0, 64512..=65534 | 4_200_000_000..=4_294_967_294)
+    }
+
+    /// Reserved by IANA: 0 (RFC 7607), 65535 and 4294967295 (RFC 7300), and 65552 to 131071
+    pub fn is_reserved(self) -> bool {
+        matches!(self.0, 0 | 65535 | 65552..=131071 | 4_294_967_295)
+    }
+
+    pub fn is_as_trans(self) -> bool {
+        self == Asn::AS_TRANS
+    }
+
+    /// For use in documentation (RFC 5398), 64496 to 64511 and 65536 to 65551
+    pub fn is_documentation(self) -> bool {
+        matches!(self.0, 64496..=64511 | 65536..=65551)
+    }
+
+    /// Private, reserved, documentation or AS_TRANS: no ASN for a path in the global routing
+    /// table
+    pub fn is_special(self) -> bool {
+        self.is_private() || self.is_reserved() || self.is_as_trans() || self.is_documentation()
+    }
+}
+
+impl From<u32> for Asn {
+    fn from(asn: u32) -> Self {
+        Asn(asn)
+    }
+}
+
+impl From<Asn> for u32 {
+    fn from(asn: Asn) -> Self {
+        asn.0
+    }
+}
+
+impl fmt::Display for Asn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            asn if f.alternate() && asn > 0xffff => write!(f, "{}.{}", asn >> 16, asn & 0xffff),
+            asn => write!(f, "{asn}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid ASN {0:?}")]
+pub struct ParseAsnError(String);
+
+/// Parses asplain, or asdot where both halves are 16 bit numbers (`1.0` being 65536)
+impl FromStr for Asn {
+    type Err = ParseAsnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseAsnError(s.to_owned());
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|byte| byte.is_ascii_digit());
+        match s.split_once('.') {
+            Some((high, low)) if digits(high) && digits(low) => {
+                let high: u16 = high.parse().map_err(|_| error())?;
+                let low: u16 = low.parse().map_err(|_| error())?;
+                Ok(Asn(u32::from(high) << 16 | u32::from(low)))
+            }
+            None if digits(s) => s.parse().map(Asn).map_err(|_| error()),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_asdot() {
+        for (asn, asdot) in [
+            (0, "0"),
+            (65535, "65535"),
+            (65536, "1.0"),
+            (4_200_000_000, "64086.59904"),
+            (u32::MAX, "65535.65535"),
+        ] {
+            assert_eq!(Asn(asn).format_asdot(), asdot);
+            assert_eq!(asdot.parse(), Ok(Asn(asn)));
+            assert_eq!(Asn(asn).to_string().parse(), Ok(Asn(asn)));
+        }
+        assert_eq!(Asn(4_200_000_000).to_string(), "4200000000");
+        // asdot+ spelling of a 2 octet ASN
+        assert_eq!("0.64500".parse(), Ok(Asn(64500)));
+
+        for invalid in [
+            "",
+            "AS65001",
+            "4294967296",
+            "1.65536",
+            "65536.0",
+            "1.",
+            ".1",
+            "1.2.3",
+            "-1",
+            "+1",
+        ] {
+            assert_eq!(
+                invalid.parse::<Asn>(),
+                Err(ParseAsnError(invalid.into())),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_special_ranges() {
+        let private = [
+            64511,
+            64512,
+            65534,
+            65535,
+            4_199_999_999,
+            4_200_000_000,
+            4_294_967_294,
+        ];
+        assert_eq!(
+            private.map(|asn| Asn(asn).is_private()),
+            [false, true, true, false, false, true, true]
+        );
+        let reserved = [
+            0,
+            1,
+            65534,
+            65535,
+            65551,
+            65552,
+            131071,
+            131072,
+            4_294_967_295,
+        ];
+        assert_eq!(
+            reserved.map(|asn| Asn(asn).is_reserved()),
+            [true, false, false, true, false, true, true, false, true]
+        );
+        let documentation = [64495, 64496, 64511, 64512, 65535, 65536, 65551, 65552];
+        assert_eq!(
+            documentation.map(|asn| Asn(asn).is_documentation()),
+            [false, true, true, false, false, true, true, false]
+        );
+        assert!(Asn(23456).is_as_trans() && !Asn(23455).is_as_trans());
+
+        assert!(
+            [0, 23456, 64496, 64512, 65552, 4_294_967_295]
+                .map(Asn)
+                .iter()
+                .all(|asn| asn.is_special())
+        );
+        assert!(
+            ![1, 3356, 64495, 131072, 4_199_999_999]
+                .map(Asn)
+                .iter()
+                .any(|asn| asn.is_special())
+        );
+    }
+}
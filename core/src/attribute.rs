@@ -1,16 +1,31 @@
 use super::error::{Error as BgpError, ErrorKind};
-use std::{env::VarError, net::Ipv4Addr};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-#[derive(Debug, PartialEq)]
+use crate::address_family::{Afi, Safi};
+use crate::asn::Asn;
+use crate::filter::CommunityMatcher;
+use crate::list::SmallList;
+use crate::notification_message::UpdateMessageSubErr;
+use crate::open_message::OpenMessage;
+use crate::spans;
+use crate::update_message::{IpAddrPrefix, ParserConfig};
+use crate::validate::Validate;
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PathAttribute {
     pub flags: PathAttributeFlags,
     pub type_code: AttributeType,
     pub value: AttributeValue,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PathAttributeFlags {
     pub optional: bool,
     pub transitive: bool,
@@ -18,7 +33,12 @@ pub struct PathAttributeFlags {
     pub extended_length: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 #[repr(u8)]
 pub enum AttributeType {
     Origin = 1,
@@ -29,25 +49,44 @@ pub enum AttributeType {
     AtomicAggregate = 6,
     Aggregator = 7,
     Communities = 8,
+    MpReachNlri = 14,
+    MpUnreachNlri = 15,
     Unknown(u8),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum AttributeValue {
     Origin(Origin),
-    AsPath(AsPath),
+    AsPath(Box<AsPath>),
     NextHop(NextHop),
     MultiExitDisc(MultiExitDisc),
     LocalPref(LocalPref),
     AtomicAggregate, // This attribute has no value
     Aggregator(Aggregator),
     Communities(Communities),
+    MpReachNlri(Box<MpReachNlri>),
+    MpUnreachNlri(Box<MpUnreachNlri>),
     Unknown(Bytes),
 }
 
+// Every attribute in every table pays for the largest variant, so those that would outgrow
+// the raw bytes of unknown attributes are boxed
+const _: () = assert!(size_of::<AttributeValue>() <= 40);
+
 // --- Attribute Value Structs ---
 
-#[derive(Debug, PartialEq)]
+/// Ordered by preference in route selection, the most preferred first (RFC 4271 9.1.2.2)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 #[repr(u8)]
 pub enum OriginType {
     Igp = 0,
@@ -55,59 +94,137 @@ pub enum OriginType {
     Incomplete = 2,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Origin {
     pub origin_type: OriginType,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 #[repr(u8)]
 pub enum AsPathSegmentType {
     AsSet = 1,
     AsSequence = 2,
+    /// Confederation segments (RFC 5065)
+    AsConfedSequence = 3,
+    AsConfedSet = 4,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AsPathSegment {
     pub segment_type: AsPathSegmentType,
-    pub asns: Vec<u32>,
+    pub asns: SmallList<u32, 8>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AsPath {
-    pub segments: Vec<AsPathSegment>,
+    pub segments: SmallList<AsPathSegment, 1>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct NextHop {
     pub ip: Ipv4Addr,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct MultiExitDisc {
     pub med: u32,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct LocalPref {
     pub pref: u32,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Aggregator {
     pub asn: u32,
     pub ip: Ipv4Addr,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Community {
     pub asn: u16,
     pub value: u16,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Communities {
-    pub communities: Vec<Community>,
+    pub communities: SmallList<Community, 4>,
+}
+
+/// Multiprotocol reachable NLRI (RFC 4760), decoded for IPv4 and IPv6 unicast and multicast
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MpReachNlri {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub next_hop: IpAddr,
+    /// Link-local address following an IPv6 global next hop (RFC 2545)
+    pub link_local: Option<Ipv6Addr>,
+    pub nlri: Vec<IpAddrPrefix>,
+}
+
+/// Multiprotocol unreachable NLRI (RFC 4760); without prefixes it marks End-of-RIB
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MpUnreachNlri {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub withdrawn_routes: Vec<IpAddrPrefix>,
+}
+
+impl TryFrom<u8> for OriginType {
+    type Error = ErrorKind;
+
+    fn try_from(value: u8) -> Result<Self, ErrorKind> {
+        match value {
+            0 => Ok(OriginType::Igp),
+            1 => Ok(OriginType::Egp),
+            2 => Ok(OriginType::Incomplete),
+            _ => Err(ErrorKind::InvalidOrigin),
+        }
+    }
+}
+
+impl From<OriginType> for u8 {
+    fn from(origin_type: OriginType) -> Self {
+        origin_type as u8
+    }
+}
+
+impl AttributeType {
+    /// The flags RFC 4271, RFC 1997 and RFC 4760 give the type, partial and extended length
+    /// unset. Unknown types are taken as optional transitive, as attributes passed on
+    /// without being understood are.
+    pub fn default_flags(&self) -> PathAttributeFlags {
+        match self {
+            AttributeType::Origin
+            | AttributeType::AsPath
+            | AttributeType::NextHop
+            | AttributeType::LocalPref
+            | AttributeType::AtomicAggregate => PathAttributeFlags::well_known(),
+            AttributeType::MultiExitDisc
+            | AttributeType::MpReachNlri
+            | AttributeType::MpUnreachNlri => PathAttributeFlags::optional_non_transitive(),
+            AttributeType::Aggregator | AttributeType::Communities | AttributeType::Unknown(_) => {
+                PathAttributeFlags::optional_transitive()
+            }
+        }
+    }
 }
 
 impl From<u8> for AttributeType {
@@ -121,47 +238,367 @@ impl From<u8> for AttributeType {
             6 => AttributeType::AtomicAggregate,
             7 => AttributeType::Aggregator,
             8 => AttributeType::Communities,
+            14 => AttributeType::MpReachNlri,
+            15 => AttributeType::MpUnreachNlri,
             _ => AttributeType::Unknown(value),
         }
     }
 }
 
-impl PathAttribute {
-    pub fn try_decode(data: &mut Bytes) -> Result<Self, BgpError> {
-        let c_data = data.clone().to_owned();
+impl From<&AttributeType> for u8 {
+    fn from(type_code: &AttributeType) -> Self {
+        match type_code {
+            AttributeType::Origin => Origin::TYPE_CODE,
+            AttributeType::AsPath => AsPath::TYPE_CODE,
+            AttributeType::NextHop => NextHop::TYPE_CODE,
+            AttributeType::MultiExitDisc => MultiExitDisc::TYPE_CODE,
+            AttributeType::LocalPref => LocalPref::TYPE_CODE,
+            AttributeType::AtomicAggregate => 6,
+            AttributeType::Aggregator => Aggregator::TYPE_CODE,
+            AttributeType::Communities => Communities::TYPE_CODE,
+            AttributeType::MpReachNlri => MpReachNlri::TYPE_CODE,
+            AttributeType::MpUnreachNlri => MpUnreachNlri::TYPE_CODE,
+            AttributeType::Unknown(value) => *value,
+        }
+    }
+}
 
-        let flags_byte = data.get_u8();
-        // Parse flag bits
-        let flags = PathAttributeFlags {
-            optional: (flags_byte & 0x80) != 0, // bit at pos -> boolean
-            transitive: (flags_byte & 0x40) != 0,
-            partial: (flags_byte & 0x20) != 0,
-            extended_length: (flags_byte & 0x10) != 0,
+/// Typed lookups into the path attributes of a route or UPDATE.
+///
+/// Each lookup scans the attributes, of which there are only a handful, rather than indexing
+/// them. When an attribute appears more than once, the first one counts.
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes<'a>(&'a [PathAttribute]);
+
+impl<'a> Attributes<'a> {
+    pub fn new(attributes: &'a [PathAttribute]) -> Self {
+        Attributes(attributes)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'a, PathAttribute> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, type_code: AttributeType) -> Option<&'a PathAttribute> {
+        self.0
+            .iter()
+            .find(|attribute| attribute.type_code == type_code)
+    }
+
+    pub fn has(&self, type_code: AttributeType) -> bool {
+        self.get(type_code).is_some()
+    }
+
+    pub fn origin(&self) -> Option<&'a Origin> {
+        match &self.get(AttributeType::Origin)?.value {
+            AttributeValue::Origin(origin) => Some(origin),
+            _ => None,
+        }
+    }
+
+    pub fn as_path(&self) -> Option<&'a AsPath> {
+        match &self.get(AttributeType::AsPath)?.value {
+            AttributeValue::AsPath(as_path) => Some(as_path),
+            _ => None,
+        }
+    }
+
+    /// The NEXT_HOP attribute, which IPv6 routes do without, carrying theirs in MP_REACH_NLRI
+    pub fn next_hop(&self) -> Option<&'a NextHop> {
+        match &self.get(AttributeType::NextHop)?.value {
+            AttributeValue::NextHop(next_hop) => Some(next_hop),
+            _ => None,
+        }
+    }
+
+    pub fn med(&self) -> Option<&'a MultiExitDisc> {
+        match &self.get(AttributeType::MultiExitDisc)?.value {
+            AttributeValue::MultiExitDisc(med) => Some(med),
+            _ => None,
+        }
+    }
+
+    pub fn local_pref(&self) -> Option<&'a LocalPref> {
+        match &self.get(AttributeType::LocalPref)?.value {
+            AttributeValue::LocalPref(local_pref) => Some(local_pref),
+            _ => None,
+        }
+    }
+
+    pub fn aggregator(&self) -> Option<&'a Aggregator> {
+        match &self.get(AttributeType::Aggregator)?.value {
+            AttributeValue::Aggregator(aggregator) => Some(aggregator),
+            _ => None,
+        }
+    }
+
+    pub fn communities(&self) -> Option<&'a Communities> {
+        match &self.get(AttributeType::Communities)?.value {
+            AttributeValue::Communities(communities) => Some(communities),
+            _ => None,
+        }
+    }
+
+    pub fn mp_reach(&self) -> Option<&'a MpReachNlri> {
+        match &self.get(AttributeType::MpReachNlri)?.value {
+            AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach),
+            _ => None,
+        }
+    }
+
+    pub fn mp_unreach(&self) -> Option<&'a MpUnreachNlri> {
+        match &self.get(AttributeType::MpUnreachNlri)?.value {
+            AttributeValue::MpUnreachNlri(mp_unreach) => Some(mp_unreach),
+            _ => None,
+        }
+    }
+}
+
+/// Whether two attribute lists mean the same, whatever order the attributes come in.
+///
+/// COMMUNITIES, CLUSTER_LIST, EXTENDED_COMMUNITIES and LARGE_COMMUNITIES compare as sets, the
+/// extended length flag is ignored, and everything else, AS_PATH included, must be equal.
+pub fn semantic_eq(a: &[PathAttribute], b: &[PathAttribute]) -> bool {
+    let sorted = |attributes| {
+        let mut sorted: Vec<&PathAttribute> = Vec::from_iter(attributes);
+        sorted.sort_by_key(|attribute| u8::from(&attribute.type_code));
+        sorted
+    };
+    a.len() == b.len()
+        && sorted(a).into_iter().zip(sorted(b)).all(|(a, b)| {
+            a.type_code == b.type_code
+                && a.flags.optional == b.flags.optional
+                && a.flags.transitive == b.flags.transitive
+                && a.flags.partial == b.flags.partial
+                && values_eq(&a.type_code, &a.value, &b.value)
+        })
+}
+
+fn values_eq(type_code: &AttributeType, a: &AttributeValue, b: &AttributeValue) -> bool {
+    match (a, b) {
+        (AttributeValue::Communities(a), AttributeValue::Communities(b)) => {
+            BTreeSet::from_iter(&a.communities) == BTreeSet::from_iter(&b.communities)
+        }
+        (AttributeValue::Unknown(a), AttributeValue::Unknown(b)) => {
+            match set_element_len(type_code.into()) {
+                Some(len) if a.len().is_multiple_of(len) && b.len().is_multiple_of(len) => {
+                    BTreeSet::from_iter(a.chunks(len)) == BTreeSet::from_iter(b.chunks(len))
+                }
+                _ => a == b,
+            }
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// The length of the elements of attributes whose value is a set, where order and repetition
+/// don't matter
+pub(crate) fn set_element_len(type_code: u8) -> Option<usize> {
+    match type_code {
+        // COMMUNITIES (RFC 1997) and CLUSTER_LIST (RFC 4456)
+        8 | 10 => Some(4),
+        // EXTENDED_COMMUNITIES (RFC 4360)
+        16 => Some(8),
+        // LARGE_COMMUNITIES (RFC 8092)
+        32 => Some(12),
+        _ => None,
+    }
+}
+
+/// An attribute list comparing with [`semantic_eq`], e.g. `SemanticAttributes(&route.attributes)`
+#[derive(Debug, Clone, Copy)]
+pub struct SemanticAttributes<T>(pub T);
+
+impl<T: AsRef<[PathAttribute]>, U: AsRef<[PathAttribute]>> PartialEq<SemanticAttributes<U>>
+    for SemanticAttributes<T>
+{
+    fn eq(&self, other: &SemanticAttributes<U>) -> bool {
+        semantic_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+impl<T: AsRef<[PathAttribute]>> Eq for SemanticAttributes<T> {}
+
+/// Edits to the path attributes of an UPDATE, as policies make them. They work on the first
+/// COMMUNITIES attribute, the one [`Attributes::communities`] returns.
+#[derive(Debug)]
+pub struct AttributesMut<'a>(&'a mut Vec<PathAttribute>);
+
+impl<'a> AttributesMut<'a> {
+    pub fn new(attributes: &'a mut Vec<PathAttribute>) -> Self {
+        AttributesMut(attributes)
+    }
+
+    /// Adds the community unless the route carries it already, creating the COMMUNITIES
+    /// attribute if need be
+    pub fn add_community(&mut self, community: Community) {
+        match self.communities_mut() {
+            Some(communities) if communities.contains(&community) => {}
+            Some(communities) => communities.push(community),
+            None => self.set_communities(vec![community]),
+        }
+    }
+
+    /// Removes every community the matcher holds for on its own, and the COMMUNITIES attribute
+    /// with the last one
+    pub fn remove_communities_matching(&mut self, matcher: &CommunityMatcher) {
+        let Some(communities) = self.communities_mut() else {
+            return;
         };
+        communities.retain(|community| !matcher.matches(&[*community]));
+        if communities.is_empty() {
+            self.set_communities(vec![]);
+        }
+    }
 
-        let type_code_byte = data.get_u8();
-        let attr_type = AttributeType::from(type_code_byte);
+    /// Replaces the communities, removing the COMMUNITIES attribute when there are none
+    pub fn set_communities(&mut self, communities: Vec<Community>) {
+        let position = self
+            .0
+            .iter()
+            .position(|attribute| attribute.type_code == AttributeType::Communities);
+        if communities.is_empty() {
+            if let Some(i) = position {
+                self.0.remove(i);
+            }
+            return;
+        }
 
-        let length = if flags.extended_length {
-            if data.len() < 2 {
-                return Err(ErrorKind::AttributeLengthErr.with_bytes(c_data));
+        let attribute = PathAttribute {
+            flags: AttributeType::Communities.default_flags(),
+            type_code: AttributeType::Communities,
+            value: AttributeValue::Communities(Communities {
+                communities: communities.into(),
+            }),
+        };
+        match position {
+            Some(i) => self.0[i] = attribute,
+            // Attributes are sent in type code order
+            None => {
+                let i = self
+                    .0
+                    .iter()
+                    .position(|attribute| u8::from(&attribute.type_code) > Communities::TYPE_CODE)
+                    .unwrap_or(self.0.len());
+                self.0.insert(i, attribute);
             }
-            data.get_u16() as usize
-        } else {
-            if data.len() < 1 {
-                return Err(ErrorKind::AttributeLengthErr.with_bytes(c_data));
+        }
+    }
+
+    /// Points NEXT_HOP and the MP_REACH_NLRI next hop at `next_hop`, dropping a link-local
+    /// address left over from the previous one. Nothing changes when an attribute can't carry it
+    pub fn set_next_hop(&mut self, next_hop: IpAddr) -> Result<(), NextHopError> {
+        self.rewrite_next_hop(next_hop, false)
+    }
+
+    /// Sets our own address as the next hop, as an IPv4-mapped address in IPv6 MP_REACH_NLRI
+    /// when `local_addr` is IPv4
+    pub fn next_hop_self(&mut self, local_addr: IpAddr) -> Result<(), NextHopError> {
+        self.rewrite_next_hop(local_addr, true)
+    }
+
+    fn rewrite_next_hop(&mut self, next_hop: IpAddr, map_v4: bool) -> Result<(), NextHopError> {
+        let mp_next_hop = |afi: Afi| match next_hop {
+            IpAddr::V4(ip) if afi == Afi::Ipv6 && map_v4 => Ok(IpAddr::V6(ip.to_ipv6_mapped())),
+            IpAddr::V4(_) if afi == Afi::Ipv6 => {
+                Err(NextHopError::FamilyMismatch { afi, next_hop })
             }
-            data.get_u8() as usize
+            _ => Ok(next_hop),
         };
 
-        if data.len() < length {
-            return Err(ErrorKind::AttributeLengthErr.with_bytes(c_data));
+        // Check every attribute first so that a failure leaves them untouched
+        for attribute in self.0.iter() {
+            match (&attribute.value, next_hop) {
+                (AttributeValue::NextHop(_), IpAddr::V6(ip)) => {
+                    return Err(NextHopError::ExtendedNextHopNeeded(ip));
+                }
+                (AttributeValue::MpReachNlri(mp_reach), _) => {
+                    mp_next_hop(mp_reach.afi)?;
+                }
+                _ => {}
+            }
+        }
+
+        for attribute in self.0.iter_mut() {
+            match (&mut attribute.value, next_hop) {
+                (AttributeValue::NextHop(classic), IpAddr::V4(ip)) => classic.ip = ip,
+                (AttributeValue::MpReachNlri(mp_reach), _) => {
+                    mp_reach.next_hop = mp_next_hop(mp_reach.afi)?;
+                    mp_reach.link_local = None;
+                }
+                _ => {}
+            }
         }
+        Ok(())
+    }
 
-        let mut value_data = data.copy_to_bytes(length);
+    fn communities_mut(&mut self) -> Option<&mut SmallList<Community, 4>> {
+        self.0
+            .iter_mut()
+            .find(|attribute| attribute.type_code == AttributeType::Communities)
+            .and_then(|attribute| match &mut attribute.value {
+                AttributeValue::Communities(communities) => Some(&mut communities.communities),
+                _ => None,
+            })
+    }
+}
 
-        let value = AttributeValue::try_decode(&attr_type, &mut value_data)
-            .map_err(|err: ErrorKind| err.with_bytes(c_data))?;
+impl PathAttribute {
+    pub fn try_decode(data: &mut Bytes) -> Result<Self, BgpError> {
+        Self::decode(data, &ParserConfig::default())
+    }
+
+    /// Decodes an attribute with the AS_PATH ASN width of `config`, and MP_(UN)REACH_NLRI
+    /// prefixes with path identifiers for its ADD-PATH families
+    pub(crate) fn decode(data: &mut Bytes, config: &ParserConfig) -> Result<Self, BgpError> {
+        // Errors locate the attribute, or as much of it as there is, and nothing after it
+        let Some(&[flags_byte, type_code_byte]) = data.get(..2) else {
+            return Err(ErrorKind::AttributeLengthErr.with_span(0..data.len()));
+        };
+        let start = spans::mark(data);
+        spans::enter(start, || "path attribute".into());
+        let flags = PathAttributeFlags::from_byte(flags_byte);
+        spans::sized(start, 1, || {
+            format!("flags: {flags_byte:#04x}{}", flags.names())
+        });
+        let attr_type = AttributeType::from(type_code_byte);
+        spans::sized(start + 1, 1, || match attr_type {
+            AttributeType::Unknown(_) => format!("type: {type_code_byte}"),
+            _ => format!("type: {attr_type:?} ({type_code_byte})"),
+        });
+
+        let (header_len, length) = match (flags.extended_length, &data[2..]) {
+            (true, [high, low, ..]) => (4, u16::from_be_bytes([*high, *low]) as usize),
+            (false, [length, ..]) => (3, *length as usize),
+            _ => return Err(ErrorKind::AttributeLengthErr.with_span(0..data.len())),
+        };
+        spans::sized(start + 2, header_len - 2, || format!("length: {length}"));
+        let attribute_len = header_len + length;
+        if data.len() < attribute_len {
+            return Err(ErrorKind::AttributeLengthErr.with_span(0..data.len()));
+        }
+
+        let mut value_data = data.slice(header_len..attribute_len);
+        let at = spans::mark(&value_data);
+        let value = match AttributeValue::decode(&attr_type, &mut value_data, config) {
+            Ok(value) => value,
+            Err(err) => {
+                data.advance(attribute_len);
+                return Err(err.with_span(0..attribute_len));
+            }
+        };
+        data.advance(attribute_len);
+        // Structured values report their own fields
+        if let Some(description) = spans::active().then(|| value.describe()).flatten() {
+            spans::sized(at, length, || description);
+        }
+        spans::leave(data);
+        event!(
+            Debug,
+            "attribute decoded",
+            "attr.type" = type_code_byte,
+            "attr.len" = length,
+        );
 
         Ok(PathAttribute {
             flags,
@@ -169,6 +606,61 @@ impl PathAttribute {
             value,
         })
     }
+
+    /// Octets [`PathAttribute::encode`] writes, the length field growing to two octets for
+    /// values beyond 255 octets
+    pub fn encoded_len(&self) -> usize {
+        let value_len = self.value.encoded_len();
+        let length_len = if self.extended_length(value_len) {
+            2
+        } else {
+            1
+        };
+        2 + length_len + value_len
+    }
+
+    fn extended_length(&self, value_len: usize) -> bool {
+        self.flags.extended_length || value_len > u8::MAX as usize
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let mut value = BytesMut::new();
+        self.value.encode(&mut value);
+
+        let extended_length = self.extended_length(value.len());
+        let flags = PathAttributeFlags {
+            extended_length,
+            ..self.flags
+        };
+
+        buf.put_u8(flags.to_byte());
+        buf.put_u8((&self.type_code).into());
+        if extended_length {
+            buf.put_u16(value.len() as u16);
+        } else {
+            buf.put_u8(value.len() as u8);
+        }
+        buf.put_slice(&value);
+    }
+}
+
+/// Checks the flags against the type's [`AttributeType::default_flags`], unknown types aside
+impl Validate<UpdateMessageSubErr> for PathAttribute {
+    fn validate(&self) -> Result<(), UpdateMessageSubErr> {
+        if let AttributeType::Unknown(_) = self.type_code {
+            return Ok(());
+        }
+        let expected = self.type_code.default_flags();
+        // Only optional transitive attributes may be marked partial (RFC 4271 section 4.3)
+        let partial_allowed = expected == PathAttributeFlags::optional_transitive();
+        if self.flags.optional != expected.optional
+            || self.flags.transitive != expected.transitive
+            || (self.flags.partial && !partial_allowed)
+        {
+            return Err(UpdateMessageSubErr::AttributeFlagsError);
+        }
+        Ok(())
+    }
 }
 
 impl AttributeValue {
@@ -176,79 +668,457 @@ impl AttributeValue {
         type_code: &AttributeType,
         value_data: &mut Bytes,
     ) -> Result<Self, ErrorKind> {
-        match type_code {
-            &AttributeType::Origin => Ok(AttributeValue::Origin(Origin::try_decode(value_data)?)),
-            &AttributeType::AsPath => Ok(AttributeValue::AsPath(AsPath::try_decode(value_data)?)),
-            &AttributeType::NextHop => {
-                Ok(AttributeValue::NextHop(NextHop::try_decode(value_data)?))
-            }
-            &AttributeType::MultiExitDisc => Ok(AttributeValue::MultiExitDisc(
+        Self::decode(type_code, value_data, &ParserConfig::default())
+    }
+
+    pub(crate) fn decode(
+        type_code: &AttributeType,
+        value_data: &mut Bytes,
+        config: &ParserConfig,
+    ) -> Result<Self, ErrorKind> {
+        match *type_code {
+            AttributeType::Origin => Ok(AttributeValue::Origin(Origin::try_decode(value_data)?)),
+            AttributeType::AsPath => Ok(AttributeValue::AsPath(Box::new(AsPath::try_decode(
+                value_data,
+                config.four_octet_as,
+                config,
+            )?))),
+            AttributeType::NextHop => Ok(AttributeValue::NextHop(NextHop::try_decode(value_data)?)),
+            AttributeType::MultiExitDisc => Ok(AttributeValue::MultiExitDisc(
                 MultiExitDisc::try_decode(value_data)?,
             )),
-            &AttributeType::LocalPref => Ok(AttributeValue::LocalPref(LocalPref::try_decode(
+            AttributeType::LocalPref => Ok(AttributeValue::LocalPref(LocalPref::try_decode(
                 value_data,
             )?)),
-            &AttributeType::AtomicAggregate => {
-                if value_data.len() > 0 {
+            AttributeType::AtomicAggregate => {
+                if !value_data.is_empty() {
                     return Err(ErrorKind::AttributeLengthErr);
                 }
                 Ok(AttributeValue::AtomicAggregate)
             }
-            &AttributeType::Aggregator => Ok(AttributeValue::Aggregator(Aggregator::try_decode(
+            AttributeType::Aggregator => Ok(AttributeValue::Aggregator(Aggregator::try_decode(
                 value_data,
             )?)),
-            &AttributeType::Communities => Ok(AttributeValue::Communities(
-                Communities::try_decode(value_data)?,
-            )),
+            AttributeType::Communities => Ok(AttributeValue::Communities(Communities::try_decode(
+                value_data, config,
+            )?)),
+            // Families with other NLRI encodings are passed through untouched
+            AttributeType::MpReachNlri if decodable_family(value_data) => Ok(
+                AttributeValue::MpReachNlri(Box::new(MpReachNlri::try_decode(value_data, config)?)),
+            ),
+            AttributeType::MpUnreachNlri if decodable_family(value_data) => {
+                Ok(AttributeValue::MpUnreachNlri(Box::new(
+                    MpUnreachNlri::try_decode(value_data, config)?,
+                )))
+            }
             _ => Ok(AttributeValue::Unknown(value_data.clone())),
         }
     }
+
+    /// Octets [`AttributeValue::encode`] writes
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            AttributeValue::Origin(_) => 1,
+            AttributeValue::AsPath(as_path) => as_path.encoded_len(true),
+            AttributeValue::NextHop(_)
+            | AttributeValue::MultiExitDisc(_)
+            | AttributeValue::LocalPref(_) => 4,
+            AttributeValue::AtomicAggregate => 0,
+            AttributeValue::Aggregator(_) => 8,
+            AttributeValue::Communities(communities) => 4 * communities.communities.len(),
+            AttributeValue::MpReachNlri(mp_reach) => mp_reach.encoded_len(),
+            AttributeValue::MpUnreachNlri(mp_unreach) => mp_unreach.encoded_len(),
+            AttributeValue::Unknown(value) => value.len(),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        match self {
+            AttributeValue::Origin(origin) => buf.put_u8(origin.origin_type.into()),
+            AttributeValue::AsPath(as_path) => as_path.encode(buf, true),
+            AttributeValue::NextHop(next_hop) => buf.put_u32(next_hop.ip.to_bits()),
+            AttributeValue::MultiExitDisc(med) => buf.put_u32(med.med),
+            AttributeValue::LocalPref(local_pref) => buf.put_u32(local_pref.pref),
+            AttributeValue::AtomicAggregate => {}
+            AttributeValue::Aggregator(aggregator) => {
+                buf.put_u32(aggregator.asn);
+                buf.put_u32(aggregator.ip.to_bits());
+            }
+            AttributeValue::Communities(communities) => {
+                for community in &communities.communities {
+                    buf.put_u16(community.asn);
+                    buf.put_u16(community.value);
+                }
+            }
+            AttributeValue::MpReachNlri(mp_reach) => mp_reach.encode(buf),
+            AttributeValue::MpUnreachNlri(mp_unreach) => mp_unreach.encode(buf),
+            AttributeValue::Unknown(value) => buf.put_slice(value),
+        }
+    }
+}
+
+impl PathAttributeFlags {
+    const OPTIONAL: u8 = 0x80;
+    const TRANSITIVE: u8 = 0x40;
+    const PARTIAL: u8 = 0x20;
+    const EXTENDED_LENGTH: u8 = 0x10;
+
+    /// Well-known attributes are transitive and never optional
+    pub const fn well_known() -> Self {
+        PathAttributeFlags::new(false, true)
+    }
+
+    pub const fn optional_transitive() -> Self {
+        PathAttributeFlags::new(true, true)
+    }
+
+    pub const fn optional_non_transitive() -> Self {
+        PathAttributeFlags::new(true, false)
+    }
+
+    const fn new(optional: bool, transitive: bool) -> Self {
+        PathAttributeFlags {
+            optional,
+            transitive,
+            partial: false,
+            extended_length: false,
+        }
+    }
+
+    /// The flags octet, the low four bits unused
+    pub fn to_byte(&self) -> u8 {
+        (self.optional as u8 * Self::OPTIONAL)
+            | (self.transitive as u8 * Self::TRANSITIVE)
+            | (self.partial as u8 * Self::PARTIAL)
+            | (self.extended_length as u8 * Self::EXTENDED_LENGTH)
+    }
+
+    /// Reads the flags octet, ignoring the unused low four bits
+    pub fn from_byte(byte: u8) -> Self {
+        PathAttributeFlags {
+            optional: byte & Self::OPTIONAL != 0,
+            transitive: byte & Self::TRANSITIVE != 0,
+            partial: byte & Self::PARTIAL != 0,
+            extended_length: byte & Self::EXTENDED_LENGTH != 0,
+        }
+    }
+
+    /// The set flags, each preceded by a space
+    fn names(&self) -> String {
+        [
+            (self.optional, " optional"),
+            (self.transitive, " transitive"),
+            (self.partial, " partial"),
+            (self.extended_length, " extended-length"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+impl AttributeValue {
+    /// A one line summary for annotated dumps, `None` for values that report their fields
+    fn describe(&self) -> Option<String> {
+        Some(match self {
+            AttributeValue::AsPath(_)
+            | AttributeValue::MpReachNlri(_)
+            | AttributeValue::MpUnreachNlri(_) => return None,
+            AttributeValue::Origin(origin) => format!("origin: {:?}", origin.origin_type),
+            AttributeValue::NextHop(next_hop) => format!("next hop: {}", next_hop.ip),
+            AttributeValue::MultiExitDisc(med) => format!("MED: {}", med.med),
+            AttributeValue::LocalPref(local_pref) => {
+                format!("local preference: {}", local_pref.pref)
+            }
+            AttributeValue::AtomicAggregate => "atomic aggregate".into(),
+            AttributeValue::Aggregator(aggregator) => {
+                format!("aggregator: AS{} {}", aggregator.asn, aggregator.ip)
+            }
+            AttributeValue::Communities(communities) => communities
+                .communities
+                .iter()
+                .fold("communities:".to_string(), |text, community| {
+                    format!("{text} {}:{}", community.asn, community.value)
+                }),
+            AttributeValue::Unknown(_) => "value".into(),
+        })
+    }
+}
+
+/// Whether an MP_REACH_NLRI or MP_UNREACH_NLRI value carries plain IP prefixes
+fn decodable_family(value_data: &Bytes) -> bool {
+    if value_data.len() < 3 {
+        return false;
+    }
+    let afi = Afi::from(u16::from_be_bytes([value_data[0], value_data[1]]));
+    let safi = Safi::from(value_data[2]);
+    matches!(afi, Afi::Ipv4 | Afi::Ipv6) && matches!(safi, Safi::Unicast | Safi::Multicast)
+}
+
+fn address_len(afi: Afi) -> u8 {
+    match afi {
+        Afi::Ipv4 => 4,
+        _ => 16,
+    }
 }
 
 impl Origin {
     const TYPE_CODE: u8 = 1;
 
     fn try_decode(data: &mut Bytes) -> Result<Self, ErrorKind> {
-        let origin_val = data.get_u8();
-        let origin_type = match origin_val {
-            0 => OriginType::Igp,
-            1 => OriginType::Egp,
-            2 => OriginType::Incomplete,
-            _ => return Err(ErrorKind::InvalidOrigin),
-        };
-
+        if data.len() != 1 {
+            return Err(ErrorKind::AttributeLengthErr);
+        }
+        let origin_type = OriginType::try_from(data.get_u8())?;
         Ok(Origin { origin_type })
     }
 }
 
 impl AsPath {
     const TYPE_CODE: u8 = 2;
-    const MIN_LEN: u8 = 4;
 
-    fn try_decode(data: &mut Bytes) -> Result<Self, ErrorKind> {
-        let mut segments = Vec::new();
+    /// Whether the path holds no ASN, as for routes originated within the AS
+    pub fn is_empty(&self) -> bool {
+        self.iter_asns().next().is_none()
+    }
+
+    /// Every ASN of every segment, in order, confederation segments included
+    pub fn iter_asns(&self) -> impl Iterator<Item = u32> + '_ {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.asns.iter().copied())
+    }
+
+    /// The length compared in route selection (RFC 4271 9.1.2.2): an AS_SET counts as one,
+    /// confederation segments (RFC 5065) count as zero
+    pub fn path_len(&self) -> u32 {
+        self.segments
+            .iter()
+            .map(|segment| match segment.segment_type {
+                AsPathSegmentType::AsSequence => segment.asns.len() as u32,
+                AsPathSegmentType::AsSet => 1,
+                AsPathSegmentType::AsConfedSequence | AsPathSegmentType::AsConfedSet => 0,
+            })
+            .sum()
+    }
+
+    /// The ASN that originated the route, the last of a path ending in an AS_SEQUENCE.
+    /// `None` for empty paths and for aggregates ending in an AS_SET, whose candidate origins
+    /// [`AsPath::origin_set`] returns.
+    pub fn origin_as(&self) -> Option<u32> {
+        let last = self.segments.last()?;
+        match last.segment_type {
+            AsPathSegmentType::AsSequence => last.asns.last().copied(),
+            _ => None,
+        }
+    }
+
+    /// The ASNs of the AS_SET a path ends in, any of which may have originated the route
+    pub fn origin_set(&self) -> Option<&[u32]> {
+        let last = self.segments.last()?;
+        match last.segment_type {
+            AsPathSegmentType::AsSet => Some(&last.asns),
+            _ => None,
+        }
+    }
+
+    /// The ASN of the neighbor that sent the route, `None` for empty paths as learned over
+    /// iBGP and for paths starting with an AS_SET. Leading confederation segments are skipped.
+    pub fn first_hop(&self) -> Option<u32> {
+        let first = self.segments.iter().find(|segment| {
+            !matches!(
+                segment.segment_type,
+                AsPathSegmentType::AsConfedSequence | AsPathSegmentType::AsConfedSet
+            )
+        })?;
+        match first.segment_type {
+            AsPathSegmentType::AsSequence => first.asns.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// How often the origin AS was prepended, so 0 when it appears once. Paths ending in an
+    /// AS_SET have no origin to prepend.
+    pub fn prepend_count(&self) -> u32 {
+        let mut trailing = self
+            .segments
+            .iter()
+            .rev()
+            .take_while(|segment| segment.segment_type == AsPathSegmentType::AsSequence)
+            .flat_map(|segment| segment.asns.iter().rev());
+        let Some(origin) = trailing.next() else {
+            return 0;
+        };
+        trailing.take_while(|&asn| asn == origin).count() as u32
+    }
+
+    /// Every ASN on the path once, in the order they first appear
+    pub fn unique_asns(&self) -> Vec<u32> {
+        let mut unique = vec![];
+        for asn in self.iter_asns() {
+            if !unique.contains(&asn) {
+                unique.push(asn);
+            }
+        }
+        unique
+    }
+
+    /// Puts `count` copies of `asn` in front, as a speaker does with its own ASN
+    pub fn prepend(&mut self, asn: u32, count: usize) {
+        self.segments.insert(
+            0,
+            AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns: std::iter::repeat_n(asn, count).collect(),
+            },
+        );
+        self.normalize();
+    }
+
+    /// Appends the ASNs as an AS_SEQUENCE
+    pub fn push_sequence(&mut self, asns: &[u32]) {
+        self.segments.push(AsPathSegment {
+            segment_type: AsPathSegmentType::AsSequence,
+            asns: asns.into(),
+        });
+        self.normalize();
+    }
+
+    /// Drops private ASNs from every segment, or substitutes `replace_with` for them, as
+    /// routers do before announcing a path to a transit provider. See [`Asn::is_private`].
+    pub fn remove_private(&mut self, replace_with: Option<u32>) {
+        for segment in &mut self.segments {
+            match replace_with {
+                Some(replacement) => segment
+                    .asns
+                    .iter_mut()
+                    .filter(|asn| Asn(**asn).is_private())
+                    .for_each(|asn| *asn = replacement),
+                None => segment.asns.retain(|&asn| !Asn(asn).is_private()),
+            }
+        }
+        self.normalize();
+    }
+
+    /// Shortens the path to a [`path_len`](AsPath::path_len) of at most `len`, keeping its
+    /// start. AS_SETs are kept whole or dropped.
+    pub fn truncate_to(&mut self, len: u32) {
+        let mut left = len;
+        let mut kept = 0;
+        for segment in &mut self.segments {
+            if left == 0 {
+                break;
+            }
+            match segment.segment_type {
+                AsPathSegmentType::AsSequence => {
+                    segment.asns.truncate(left as usize);
+                    left -= segment.asns.len() as u32;
+                }
+                AsPathSegmentType::AsSet => left -= 1,
+                AsPathSegmentType::AsConfedSequence | AsPathSegmentType::AsConfedSet => {}
+            }
+            kept += 1;
+        }
+        self.segments.truncate(kept);
+        self.normalize();
+    }
+
+    /// Appends the segments, merging a leading AS_SEQUENCE into a trailing one
+    pub(crate) fn push_segments(&mut self, segments: impl IntoIterator<Item = AsPathSegment>) {
+        self.segments.extend(segments);
+        self.normalize();
+    }
+
+    /// Merges adjacent sequences, drops empty segments and splits sequences longer than a
+    /// segment holds
+    fn normalize(&mut self) {
+        let mut segments: SmallList<AsPathSegment, 1> = SmallList::new();
+        for segment in self.segments.drain(..) {
+            let sequence = matches!(
+                segment.segment_type,
+                AsPathSegmentType::AsSequence | AsPathSegmentType::AsConfedSequence
+            );
+            match segments.last_mut() {
+                _ if segment.asns.is_empty() => {}
+                Some(last) if sequence && last.segment_type == segment.segment_type => {
+                    last.asns.extend(segment.asns)
+                }
+                _ => segments.push(segment),
+            }
+        }
+
+        for segment in segments {
+            let sequence = matches!(
+                segment.segment_type,
+                AsPathSegmentType::AsSequence | AsPathSegmentType::AsConfedSequence
+            );
+            if !sequence || segment.asns.len() <= MAX_SEGMENT_ASNS {
+                self.segments.push(segment);
+                continue;
+            }
+            for asns in segment.asns.chunks(MAX_SEGMENT_ASNS) {
+                self.segments.push(AsPathSegment {
+                    segment_type: segment.segment_type,
+                    asns: asns.into(),
+                });
+            }
+        }
+    }
+
+    pub(crate) fn try_decode(
+        data: &mut Bytes,
+        four_octet_as: bool,
+        config: &ParserConfig,
+    ) -> Result<Self, ErrorKind> {
+        let asn_len = if four_octet_as { 4 } else { 2 };
+        let mut segments = SmallList::new();
 
         while !data.is_empty() {
+            if data.len() < 2 {
+                return Err(ErrorKind::MalformedAsPath);
+            }
+            let at = spans::mark(data);
             let seg_type_val = data.get_u8();
             let seg_type = match seg_type_val {
                 1 => AsPathSegmentType::AsSet,
                 2 => AsPathSegmentType::AsSequence,
+                3 => AsPathSegmentType::AsConfedSequence,
+                4 => AsPathSegmentType::AsConfedSet,
                 _ => return Err(ErrorKind::MalformedAsPath),
             };
 
-            // TODO: add neogtiation for ASN size
-            // count is quantity of 4 octet ASNs
             let count = data.get_u8() as usize;
-            let asn_byte_len = count * 4;
-            if data.len() < asn_byte_len {
+            if data.len() < count * asn_len {
                 return Err(ErrorKind::MalformedAsPath);
             }
-
-            let mut asns = Vec::with_capacity(count);
-            for _ in 0..count {
-                asns.push(data.get_u32());
+            config.check_push(segments.len())?;
+            if count > config.max_collection_len {
+                return Err(ErrorKind::CollectionTooLong);
             }
 
+            // The length is checked, so the ASNs are read off a slice rather than one by one
+            let values = &data[..count * asn_len];
+            let asns: SmallList<u32, _> = match four_octet_as {
+                true => values
+                    .as_chunks()
+                    .0
+                    .iter()
+                    .map(|&asn| u32::from_be_bytes(asn))
+                    .collect(),
+                false => values
+                    .as_chunks()
+                    .0
+                    .iter()
+                    .map(|&asn| u16::from_be_bytes(asn).into())
+                    .collect(),
+            };
+            data.advance(count * asn_len);
+
+            spans::since(at, data, || {
+                asns.iter()
+                    .fold(format!("{seg_type:?}"), |text, asn| format!("{text} {asn}"))
+            });
             segments.push(AsPathSegment {
                 segment_type: seg_type,
                 asns,
@@ -257,6 +1127,174 @@ impl AsPath {
 
         Ok(AsPath { segments })
     }
+
+    /// Octets the path takes on the wire, with 2 octet ASNs unless `four_octet_as`
+    pub fn encoded_len(&self, four_octet_as: bool) -> usize {
+        let asn_len = if four_octet_as { 4 } else { 2 };
+        self.wire_segments()
+            .map(|(_, asns)| 2 + asns.len() * asn_len)
+            .sum()
+    }
+
+    /// Writes the path with 2 octet ASNs unless `four_octet_as`, those beyond 16 bits replaced
+    /// by AS_TRANS
+    pub(crate) fn encode(&self, buf: &mut BytesMut, four_octet_as: bool) {
+        for (segment_type, asns) in self.wire_segments() {
+            buf.put_u8(segment_type as u8);
+            buf.put_u8(asns.len() as u8);
+            for &asn in asns {
+                match four_octet_as {
+                    true => buf.put_u32(asn),
+                    false => buf.put_u16(u16::try_from(asn).unwrap_or(OpenMessage::AS_TRANS)),
+                }
+            }
+        }
+    }
+
+    /// The segments as written, those holding more ASNs than a segment can split up
+    fn wire_segments(&self) -> impl Iterator<Item = (AsPathSegmentType, &[u32])> {
+        self.segments.iter().flat_map(|segment| {
+            let empty = segment.asns.is_empty().then_some(&[][..]);
+            empty
+                .into_iter()
+                .chain(segment.asns.chunks(MAX_SEGMENT_ASNS))
+                .map(|asns| (segment.segment_type, asns))
+        })
+    }
+}
+
+/// Prints the path as operators read it: AS_SEQUENCEs as space separated ASNs, AS_SETs in
+/// braces, `65001 65002 {65010,65011}`, confederation sequences in parentheses and
+/// confederation sets in brackets, `(64512 64513) [64514,64515] 65001`. Empty sets print as
+/// `{}`, an empty path as nothing. Adjacent AS_SEQUENCEs read as one, and an empty AS_SEQUENCE
+/// doesn't show at all. ASNs are in asplain, or in asdot with `{:#}`.
+impl fmt::Display for AsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for segment in &self.segments {
+            let (open, separator, close) = match segment.segment_type {
+                AsPathSegmentType::AsSequence if segment.asns.is_empty() => continue,
+                AsPathSegmentType::AsSequence => ("", " ", ""),
+                AsPathSegmentType::AsSet => ("{", ",", "}"),
+                AsPathSegmentType::AsConfedSequence => ("(", " ", ")"),
+                AsPathSegmentType::AsConfedSet => ("[", ",", "]"),
+            };
+            if !first {
+                f.write_str(" ")?;
+            }
+            first = false;
+            f.write_str(open)?;
+            for (i, asn) in segment.asns.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(separator)?;
+                }
+                fmt::Display::fmt(&Asn(*asn), f)?;
+            }
+            f.write_str(close)?;
+        }
+        Ok(())
+    }
+}
+
+/// ASNs a segment holds at most, its count being a single octet
+const MAX_SEGMENT_ASNS: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid AS path {path:?} at offset {offset}: {reason}")]
+pub struct ParseAsPathError {
+    path: String,
+    offset: usize,
+    reason: &'static str,
+}
+
+/// Parses what [`AsPath`]'s `Display` prints, with any whitespace between tokens. Inside
+/// brackets ASNs may be separated by commas, whitespace or both. ASNs outside brackets are
+/// split into AS_SEQUENCEs of at most 255, as they would be on the wire.
+impl FromStr for AsPath {
+    type Err = ParseAsPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        let error = |offset, reason| ParseAsPathError {
+            path: s.to_owned(),
+            offset,
+            reason,
+        };
+        let skip_whitespace = |pos: &mut usize| {
+            while bytes.get(*pos).is_some_and(u8::is_ascii_whitespace) {
+                *pos += 1;
+            }
+        };
+        let asn = |pos: &mut usize| {
+            let start = *pos;
+            while bytes
+                .get(*pos)
+                .is_some_and(|byte| byte.is_ascii_digit() || *byte == b'.')
+            {
+                *pos += 1;
+            }
+            match s[start..*pos].parse::<Asn>() {
+                Ok(asn) => Ok(asn.0),
+                Err(_) if start == *pos => Err(error(start, "expected an ASN")),
+                Err(_) if s[start..*pos].contains('.') => Err(error(start, "invalid asdot ASN")),
+                Err(_) => Err(error(start, "ASN out of range")),
+            }
+        };
+
+        let mut segments: SmallList<AsPathSegment, 1> = SmallList::new();
+        let mut pos = 0;
+        loop {
+            skip_whitespace(&mut pos);
+            let Some(&byte) = bytes.get(pos) else {
+                break;
+            };
+            let (segment_type, close) = match byte {
+                b'{' => (AsPathSegmentType::AsSet, b'}'),
+                b'(' => (AsPathSegmentType::AsConfedSequence, b')'),
+                b'[' => (AsPathSegmentType::AsConfedSet, b']'),
+                b'0'..=b'9' => {
+                    let asn = asn(&mut pos)?;
+                    match segments.last_mut() {
+                        Some(last)
+                            if last.segment_type == AsPathSegmentType::AsSequence
+                                && last.asns.len() < MAX_SEGMENT_ASNS =>
+                        {
+                            last.asns.push(asn)
+                        }
+                        _ => segments.push(AsPathSegment {
+                            segment_type: AsPathSegmentType::AsSequence,
+                            asns: [asn].into(),
+                        }),
+                    }
+                    continue;
+                }
+                _ => return Err(error(pos, "expected an ASN or a segment")),
+            };
+
+            let start = pos;
+            pos += 1;
+            let mut asns = SmallList::new();
+            loop {
+                skip_whitespace(&mut pos);
+                match bytes.get(pos) {
+                    None => return Err(error(start, "unclosed segment")),
+                    Some(&byte) if byte == close => break,
+                    Some(b',') if !asns.is_empty() => {
+                        pos += 1;
+                        skip_whitespace(&mut pos);
+                    }
+                    _ => {}
+                }
+                asns.push(asn(&mut pos)?);
+            }
+            pos += 1;
+            if asns.len() > MAX_SEGMENT_ASNS {
+                return Err(error(start, "segment of more than 255 ASNs"));
+            }
+            segments.push(AsPathSegment { segment_type, asns });
+        }
+        Ok(AsPath { segments })
+    }
 }
 
 impl NextHop {
@@ -320,26 +1358,272 @@ impl Aggregator {
     }
 }
 
+impl Community {
+    /// Not to be advertised outside the AS or confederation (RFC 1997)
+    pub const NO_EXPORT: Community = Community::well_known(0xff01);
+    /// Not to be advertised to any peer (RFC 1997)
+    pub const NO_ADVERTISE: Community = Community::well_known(0xff02);
+    /// Not to be advertised to external peers, confederation members included (RFC 1997)
+    pub const NO_EXPORT_SUBCONFED: Community = Community::well_known(0xff03);
+    /// Traffic to the prefix is to be dropped (RFC 7999)
+    pub const BLACKHOLE: Community = Community::well_known(666);
+    /// The route is about to go away, so should be least preferred (RFC 8326)
+    pub const GRACEFUL_SHUTDOWN: Community = Community::well_known(0);
+
+    /// Well-known communities by the names routers print them with
+    const NAMED: [(&'static str, Community); 5] = [
+        ("no-export", Community::NO_EXPORT),
+        ("no-advertise", Community::NO_ADVERTISE),
+        ("no-export-subconfed", Community::NO_EXPORT_SUBCONFED),
+        ("blackhole", Community::BLACKHOLE),
+        ("graceful-shutdown", Community::GRACEFUL_SHUTDOWN),
+    ];
+
+    const fn well_known(value: u16) -> Community {
+        Community { asn: 0xffff, value }
+    }
+
+    /// Whether the community lies in the range RFC 1997 reserves for well-known ones, 65535:*
+    pub fn is_well_known(&self) -> bool {
+        self.asn == 0xffff
+    }
+
+    pub fn is_blackhole(&self) -> bool {
+        *self == Community::BLACKHOLE
+    }
+
+    /// The name of a well-known community, such as `no-export`
+    pub fn name(&self) -> Option<&'static str> {
+        Community::NAMED
+            .iter()
+            .find(|(_, community)| community == self)
+            .map(|(name, _)| *name)
+    }
+}
+
+/// `asn:value`, or the name of a well-known community such as `no-export`
+impl fmt::Display for Community {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}:{}", self.asn, self.value),
+        }
+    }
+}
+
+/// Why a next hop couldn't be set on a route's attributes
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NextHopError {
+    /// NEXT_HOP only carries IPv4; IPv6 next hops for IPv4 NLRI need RFC 8950 extended next hop
+    #[error("IPv6 next hop {0} needs extended next hop encoding for classic NLRI")]
+    ExtendedNextHopNeeded(Ipv6Addr),
+    #[error("next hop {next_hop} can't be used for {afi:?} routes")]
+    FamilyMismatch { afi: Afi, next_hop: IpAddr },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid community {0:?}")]
+pub struct ParseCommunityError(String);
+
+/// Parses `asn:value` or the name of a well-known community, as printed by `Display`
+impl FromStr for Community {
+    type Err = ParseCommunityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, community)) = Community::NAMED.iter().find(|(name, _)| *name == s) {
+            return Ok(*community);
+        }
+        s.split_once(':')
+            .and_then(|(asn, value)| {
+                Some(Community {
+                    asn: asn.parse().ok()?,
+                    value: value.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| ParseCommunityError(s.to_owned()))
+    }
+}
+
 impl Communities {
     const TYPE_CODE: u8 = 8;
 
-    fn try_decode(data: &mut Bytes) -> Result<Self, ErrorKind> {
-        if data.len() % 4 != 0 {
+    pub fn contains(&self, community: Community) -> bool {
+        self.communities.contains(&community)
+    }
+
+    fn try_decode(data: &mut Bytes, config: &ParserConfig) -> Result<Self, ErrorKind> {
+        if !data.len().is_multiple_of(4) {
             return Err(ErrorKind::OptionalAttributeError);
         }
-
-        let mut communities = Vec::with_capacity(data.len() / 4);
-        while !data.is_empty() {
-            communities.push(Community {
-                asn: data.get_u16(),
-                value: data.get_u16(),
-            });
+        if data.len() / 4 > config.max_collection_len {
+            return Err(ErrorKind::CollectionTooLong);
         }
 
+        let communities = data
+            .as_chunks()
+            .0
+            .iter()
+            .map(|&[asn_high, asn_low, high, low]| Community {
+                asn: u16::from_be_bytes([asn_high, asn_low]),
+                value: u16::from_be_bytes([high, low]),
+            })
+            .collect();
+        data.advance(data.len());
+
         Ok(Communities { communities })
     }
 }
 
+/// The communities in ascending order, space separated, so that equal sets print the same
+impl fmt::Display for Communities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sorted = self.communities.clone();
+        sorted.sort_unstable();
+        for (i, community) in sorted.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{community}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Invalid prefixes make MP_(UN)REACH_NLRI an optional attribute error, too many of them
+/// are reported as such
+fn prefix_error(err: BgpError) -> ErrorKind {
+    match err.kind {
+        ErrorKind::CollectionTooLong => ErrorKind::CollectionTooLong,
+        _ => ErrorKind::OptionalAttributeError,
+    }
+}
+
+impl MpReachNlri {
+    const TYPE_CODE: u8 = 14;
+
+    fn try_decode(data: &mut Bytes, config: &ParserConfig) -> Result<Self, ErrorKind> {
+        let afi = Afi::from(data.get_u16());
+        let safi = Safi::from(data.get_u8());
+        spans::consumed(data, 3, || format!("AFI {afi:?}, SAFI {safi:?}"));
+        let add_path = config.add_path.contains(afi, safi);
+        if data.is_empty() {
+            return Err(ErrorKind::OptionalAttributeError);
+        }
+        let next_hop_len = data.get_u8() as usize;
+        spans::consumed(data, 1, || format!("next hop length: {next_hop_len}"));
+        // The next hop is followed by a reserved octet
+        if data.len() < next_hop_len + 1 {
+            return Err(ErrorKind::OptionalAttributeError);
+        }
+
+        let (next_hop, link_local) = match next_hop_len {
+            4 => (IpAddr::V4(Ipv4Addr::from_bits(data.get_u32())), None),
+            16 => (IpAddr::V6(Ipv6Addr::from_bits(data.get_u128())), None),
+            32 => (
+                IpAddr::V6(Ipv6Addr::from_bits(data.get_u128())),
+                Some(Ipv6Addr::from_bits(data.get_u128())),
+            ),
+            _ => return Err(ErrorKind::OptionalAttributeError),
+        };
+        spans::consumed(data, next_hop_len, || match link_local {
+            Some(link_local) => format!("next hop: {next_hop}, link-local {link_local}"),
+            None => format!("next hop: {next_hop}"),
+        });
+        data.advance(1);
+        spans::consumed(data, 1, || "reserved".into());
+
+        spans::enter(spans::mark(data), || "NLRI".into());
+        let nlri = IpAddrPrefix::decode_stream(data, address_len(afi), add_path, config)
+            .map_err(prefix_error)?;
+        spans::leave(data);
+        Ok(MpReachNlri {
+            afi,
+            safi,
+            next_hop,
+            link_local,
+            nlri,
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        let next_hop_len = match (self.next_hop, self.link_local) {
+            (IpAddr::V4(_), _) => 4,
+            (IpAddr::V6(_), None) => 16,
+            (IpAddr::V6(_), Some(_)) => 32,
+        };
+        3 + 1
+            + next_hop_len
+            + 1
+            + self
+                .nlri
+                .iter()
+                .map(IpAddrPrefix::encoded_len)
+                .sum::<usize>()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u16(self.afi.into());
+        buf.put_u8(self.safi.into());
+        match (self.next_hop, self.link_local) {
+            (IpAddr::V4(next_hop), _) => {
+                buf.put_u8(4);
+                buf.put_u32(next_hop.to_bits());
+            }
+            (IpAddr::V6(next_hop), None) => {
+                buf.put_u8(16);
+                buf.put_u128(next_hop.to_bits());
+            }
+            (IpAddr::V6(next_hop), Some(link_local)) => {
+                buf.put_u8(32);
+                buf.put_u128(next_hop.to_bits());
+                buf.put_u128(link_local.to_bits());
+            }
+        }
+        buf.put_u8(0);
+        for prefix in &self.nlri {
+            prefix.encode(buf);
+        }
+    }
+}
+
+impl MpUnreachNlri {
+    const TYPE_CODE: u8 = 15;
+
+    fn try_decode(data: &mut Bytes, config: &ParserConfig) -> Result<Self, ErrorKind> {
+        let afi = Afi::from(data.get_u16());
+        let safi = Safi::from(data.get_u8());
+        spans::consumed(data, 3, || format!("AFI {afi:?}, SAFI {safi:?}"));
+        let add_path = config.add_path.contains(afi, safi);
+        spans::enter(spans::mark(data), || "withdrawn routes".into());
+        let withdrawn_routes =
+            IpAddrPrefix::decode_stream(data, address_len(afi), add_path, config)
+                .map_err(prefix_error)?;
+        spans::leave(data);
+        Ok(MpUnreachNlri {
+            afi,
+            safi,
+            withdrawn_routes,
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        let withdrawn_len: usize = self
+            .withdrawn_routes
+            .iter()
+            .map(IpAddrPrefix::encoded_len)
+            .sum();
+        3 + withdrawn_len
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u16(self.afi.into());
+        buf.put_u8(self.safi.into());
+        for prefix in &self.withdrawn_routes {
+            prefix.encode(buf);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -349,8 +1633,8 @@ mod test {
     fn test_decode_origin() {
         let mut data = Bytes::from_static(&[0x40, 0x01, 0x01, 0x00]); // Flags, Type, Length, Value (IGP)
         let attr = PathAttribute::try_decode(&mut data).unwrap();
-        assert_eq!(attr.flags.transitive, true);
-        assert_eq!(attr.flags.optional, false);
+        assert!(attr.flags.transitive);
+        assert!(!attr.flags.optional);
         assert_eq!(attr.type_code, AttributeType::Origin);
         assert_eq!(
             attr.value,
@@ -384,6 +1668,376 @@ mod test {
         }
     }
 
+    fn path(segments: &[(AsPathSegmentType, &[u32])]) -> AsPath {
+        AsPath {
+            segments: segments
+                .iter()
+                .map(|(segment_type, asns)| AsPathSegment {
+                    segment_type: *segment_type,
+                    asns: asns.to_vec().into(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_as_path_analysis() {
+        use AsPathSegmentType::*;
+
+        let prepended = path(&[(AsSequence, &[65001, 65002, 65003, 65003, 65003])]);
+        assert_eq!(prepended.first_hop(), Some(65001));
+        assert_eq!(prepended.prepend_count(), 2);
+        assert_eq!(prepended.unique_asns(), vec![65001, 65002, 65003]);
+
+        // Prepends may span segments
+        let split = path(&[(AsSequence, &[65001, 65003]), (AsSequence, &[65003])]);
+        assert_eq!(split.prepend_count(), 1);
+
+        let aggregated = path(&[(AsSequence, &[65001, 65002]), (AsSet, &[65003, 65002])]);
+        assert_eq!(aggregated.first_hop(), Some(65001));
+        assert_eq!(aggregated.prepend_count(), 0);
+        assert_eq!(aggregated.unique_asns(), vec![65001, 65002, 65003]);
+
+        let confederation = path(&[(AsConfedSequence, &[64512]), (AsSequence, &[65001])]);
+        assert_eq!(confederation.first_hop(), Some(65001));
+    }
+
+    #[test]
+    fn test_as_path_analysis_degenerate() {
+        let set_only = path(&[(AsPathSegmentType::AsSet, &[65001, 65002])]);
+        assert_eq!(set_only.first_hop(), None);
+        assert_eq!(set_only.prepend_count(), 0);
+        assert_eq!(set_only.unique_asns(), vec![65001, 65002]);
+
+        // As received over iBGP for locally originated routes
+        let empty = path(&[]);
+        assert_eq!(empty.first_hop(), None);
+        assert_eq!(empty.prepend_count(), 0);
+        assert!(empty.unique_asns().is_empty());
+    }
+
+    #[test]
+    fn test_origin_type_order() {
+        use OriginType::*;
+
+        // Route selection prefers the lower origin, whatever the discriminants
+        assert!(Igp < Egp && Egp < Incomplete);
+        let mut origins = vec![Incomplete, Igp, Egp, Igp];
+        origins.sort();
+        assert_eq!(origins, [Igp, Igp, Egp, Incomplete]);
+        assert_eq!(origins.iter().min(), Some(&Igp));
+
+        for origin in [Igp, Egp, Incomplete] {
+            assert_eq!(OriginType::try_from(u8::from(origin)), Ok(origin));
+        }
+        assert_eq!(OriginType::try_from(3), Err(ErrorKind::InvalidOrigin));
+    }
+
+    #[test]
+    fn test_path_len() {
+        use AsPathSegmentType::*;
+
+        assert_eq!(path(&[(AsSequence, &[65001, 65002, 65002])]).path_len(), 3);
+        // However many ASNs an AS_SET holds it counts as one
+        let aggregated = path(&[
+            (AsSequence, &[65001, 65002]),
+            (AsSet, &[65003, 65004, 65005]),
+        ]);
+        assert_eq!(aggregated.path_len(), 3);
+        assert_eq!(path(&[(AsSet, &[65003, 65004])]).path_len(), 1);
+        // Confederation segments count as nothing
+        let confederation = path(&[
+            (AsConfedSequence, &[64512, 64513]),
+            (AsConfedSet, &[64514, 64515]),
+            (AsSequence, &[65001]),
+        ]);
+        assert_eq!(confederation.path_len(), 1);
+        assert_eq!(path(&[]).path_len(), 0);
+    }
+
+    #[test]
+    fn test_origin_as() {
+        use AsPathSegmentType::*;
+
+        let split = path(&[(AsSequence, &[65001]), (AsSequence, &[65002, 65003])]);
+        assert_eq!(split.origin_as(), Some(65003));
+        assert_eq!(split.origin_set(), None);
+
+        let aggregated = path(&[(AsSequence, &[65001]), (AsSet, &[65003, 65002])]);
+        assert_eq!(aggregated.origin_as(), None);
+        assert_eq!(aggregated.origin_set(), Some(&[65003, 65002][..]));
+
+        let set_before_sequence = path(&[(AsSet, &[65003, 65002]), (AsSequence, &[65001])]);
+        assert_eq!(set_before_sequence.origin_as(), Some(65001));
+
+        let empty = path(&[]);
+        assert_eq!(empty.origin_as(), None);
+        assert_eq!(empty.origin_set(), None);
+    }
+
+    #[test]
+    fn test_iter_asns() {
+        use AsPathSegmentType::*;
+
+        let confederation = path(&[
+            (AsConfedSequence, &[64512]),
+            (AsSequence, &[65001, 65001]),
+            (AsSet, &[65003, 65002]),
+        ]);
+        assert_eq!(
+            confederation.iter_asns().collect::<Vec<_>>(),
+            [64512, 65001, 65001, 65003, 65002]
+        );
+        assert!(!confederation.is_empty());
+
+        assert!(path(&[]).is_empty());
+        // A segment may be empty on the wire
+        assert!(path(&[(AsSequence, &[])]).is_empty());
+        assert_eq!(path(&[(AsSequence, &[])]).origin_as(), None);
+    }
+
+    #[test]
+    fn test_display_as_path() {
+        use AsPathSegmentType::*;
+
+        let aggregate = path(&[(AsSequence, &[65001, 65002]), (AsSet, &[65010, 65011])]);
+        assert_eq!(aggregate.to_string(), "65001 65002 {65010,65011}");
+        let confederation = path(&[
+            (AsConfedSequence, &[64512, 64513]),
+            (AsConfedSet, &[64514, 64515]),
+            (AsSequence, &[65001]),
+        ]);
+        assert_eq!(
+            confederation.to_string(),
+            "(64512 64513) [64514,64515] 65001"
+        );
+
+        assert_eq!(path(&[]).to_string(), "");
+        assert_eq!(path(&[(AsSet, &[])]).to_string(), "{}");
+        assert_eq!(path(&[(AsSequence, &[]), (AsSet, &[1])]).to_string(), "{1}");
+    }
+
+    #[test]
+    fn test_parse_as_path() {
+        use AsPathSegmentType::*;
+
+        assert_eq!(
+            " 65001  65002\t{ 65010 , 65011 }".parse(),
+            Ok(path(&[
+                (AsSequence, &[65001, 65002]),
+                (AsSet, &[65010, 65011])
+            ]))
+        );
+        assert_eq!(
+            "(64512,64513)[64514 64515]65001{}".parse(),
+            Ok(path(&[
+                (AsConfedSequence, &[64512, 64513]),
+                (AsConfedSet, &[64514, 64515]),
+                (AsSequence, &[65001]),
+                (AsSet, &[]),
+            ]))
+        );
+        assert_eq!("".parse(), Ok(path(&[])));
+        assert_eq!("  ".parse(), Ok(path(&[])));
+        assert_eq!("{}".parse(), Ok(path(&[(AsSet, &[])])));
+
+        let long: AsPath = vec!["65001"; 300].join(" ").parse().unwrap();
+        assert_eq!(long.segments.len(), 2);
+        assert_eq!(long.segments[0].asns.len(), 255);
+        assert_eq!(long.path_len(), 300);
+
+        let error = |path: &str, offset, reason| {
+            Err::<AsPath, _>(ParseAsPathError {
+                path: path.to_owned(),
+                offset,
+                reason,
+            })
+        };
+        assert_eq!(
+            "65001 x".parse(),
+            error("65001 x", 6, "expected an ASN or a segment")
+        );
+        assert_eq!("{1,}".parse(), error("{1,}", 3, "expected an ASN"));
+        assert_eq!("{,1}".parse(), error("{,1}", 1, "expected an ASN"));
+        assert_eq!("1 (2 3".parse(), error("1 (2 3", 2, "unclosed segment"));
+        assert_eq!("{1)".parse(), error("{1)", 2, "expected an ASN"));
+        assert_eq!(
+            "4294967296".parse(),
+            error("4294967296", 0, "ASN out of range")
+        );
+        assert_eq!("1 2.3.4".parse(), error("1 2.3.4", 2, "invalid asdot ASN"));
+        let set = format!("{{{}}}", vec!["1"; 256].join(","));
+        assert_eq!(set.parse(), error(&set, 0, "segment of more than 255 ASNs"));
+    }
+
+    #[test]
+    fn test_as_path_asdot() {
+        let as_path: AsPath = "3356 64086.59904 {1.0,0.1} (65001)".parse().unwrap();
+        assert_eq!(as_path.to_string(), "3356 4200000000 {65536,1} (65001)");
+        assert_eq!(format!("{as_path:#}"), "3356 64086.59904 {1.0,1} (65001)");
+        assert_eq!(format!("{as_path:#}").parse(), Ok(as_path));
+    }
+
+    /// Paths as they could arrive on the wire, without the ambiguities `Display` resolves:
+    /// no empty or adjacent AS_SEQUENCEs, none longer than 255
+    fn generated_paths(count: usize) -> Vec<AsPath> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut paths = vec![];
+        for _ in 0..count {
+            let mut segments: Vec<AsPathSegment> = vec![];
+            for _ in 0..next() % 6 {
+                let segment_type = match next() % 4 {
+                    0 => AsPathSegmentType::AsSet,
+                    1 => AsPathSegmentType::AsConfedSequence,
+                    2 => AsPathSegmentType::AsConfedSet,
+                    _ => AsPathSegmentType::AsSequence,
+                };
+                let sequence = segment_type == AsPathSegmentType::AsSequence;
+                if sequence
+                    && segments
+                        .last()
+                        .is_some_and(|last| last.segment_type == AsPathSegmentType::AsSequence)
+                {
+                    continue;
+                }
+                let len = match next() % 20 {
+                    0 => 255,
+                    n => (n % 5) as usize + sequence as usize,
+                };
+                let asns = (0..len)
+                    .map(|_| match next() % 3 {
+                        0 => next() as u32,
+                        _ => 64512 + (next() % 1024) as u32,
+                    })
+                    .collect();
+                segments.push(AsPathSegment { segment_type, asns });
+            }
+            paths.push(AsPath {
+                segments: segments.into(),
+            });
+        }
+        paths
+    }
+
+    #[test]
+    fn test_as_path_round_trip() {
+        for as_path in generated_paths(2000) {
+            let display = as_path.to_string();
+            assert_eq!(display.parse(), Ok(as_path.clone()), "{display}");
+
+            let spaced = display.replace(' ', "  ").replace(',', " , ");
+            assert_eq!(spaced.parse(), Ok(as_path), "{spaced}");
+        }
+    }
+
+    #[test]
+    fn test_prepend() {
+        use AsPathSegmentType::*;
+
+        let mut as_path: AsPath = "65002 {65010,65011}".parse().unwrap();
+        as_path.prepend(65001, 3);
+        assert_eq!(as_path.to_string(), "65001 65001 65001 65002 {65010,65011}");
+        assert_eq!(as_path.segments.len(), 2);
+
+        // Crossing the 255 ASN boundary starts another segment
+        let mut as_path = path(&[(AsSequence, &[65002; 250])]);
+        as_path.prepend(65001, 10);
+        assert_eq!(
+            as_path
+                .segments
+                .iter()
+                .map(|segment| segment.asns.len())
+                .collect::<Vec<_>>(),
+            [255, 5]
+        );
+        assert_eq!(as_path.segments[0].asns[..10], [65001; 10]);
+        assert_eq!(as_path.path_len(), 260);
+        assert_eq!(as_path.first_hop(), Some(65001));
+
+        // In front of a confederation segment, and not at all
+        let mut as_path: AsPath = "(64512) 65002".parse().unwrap();
+        as_path.prepend(65001, 1);
+        assert_eq!(as_path.to_string(), "65001 (64512) 65002");
+        as_path.prepend(65001, 0);
+        assert_eq!(as_path.to_string(), "65001 (64512) 65002");
+
+        let mut as_path = path(&[]);
+        as_path.push_sequence(&[65001, 65002]);
+        as_path.push_sequence(&[65003]);
+        as_path.push_sequence(&[]);
+        assert_eq!(as_path, path(&[(AsSequence, &[65001, 65002, 65003])]));
+    }
+
+    #[test]
+    fn test_remove_private() {
+        let original: AsPath = "3356 64512 64513 {64514,1299} 4200000001".parse().unwrap();
+        let mut removed = original.clone();
+        removed.remove_private(None);
+        assert_eq!(removed.to_string(), "3356 {1299}");
+        let mut replaced = original.clone();
+        replaced.remove_private(Some(3356));
+        assert_eq!(replaced.to_string(), "3356 3356 3356 {3356,1299} 3356");
+
+        // A path of private ASNs only becomes empty, without empty segments left over
+        let mut private: AsPath = "64512 {64513} (64514) 4200000000".parse().unwrap();
+        private.remove_private(None);
+        assert!(private.is_empty());
+        assert!(private.segments.is_empty());
+        assert_eq!(private.to_string(), "");
+    }
+
+    #[test]
+    fn test_truncate_to() {
+        let original: AsPath = "(64512) 65001 65002 {65010,65011} 65003".parse().unwrap();
+        let truncated = |len| {
+            let mut as_path = original.clone();
+            as_path.truncate_to(len);
+            as_path.to_string()
+        };
+        assert_eq!(truncated(5), "(64512) 65001 65002 {65010,65011} 65003");
+        assert_eq!(truncated(4), "(64512) 65001 65002 {65010,65011} 65003");
+        assert_eq!(truncated(3), "(64512) 65001 65002 {65010,65011}");
+        assert_eq!(truncated(1), "(64512) 65001");
+        assert_eq!(truncated(0), "");
+    }
+
+    #[test]
+    fn test_manipulated_path_with_two_octet_asns() {
+        use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+        let mut as_path: AsPath = "4200000001 3356".parse().unwrap();
+        as_path.prepend(396982, 300);
+        let encode = |as_path: &AsPath| {
+            let update = UpdateMessageBuilder::new()
+                .as_path(as_path.clone())
+                .next_hop("192.0.2.1".parse().unwrap())
+                .announce("198.51.100.0/24".parse().unwrap())
+                .four_octet_as(false)
+                .build();
+            let has_as4_path = update.attributes().has(AttributeType::Unknown(17));
+            let mut data = update.to_bytes();
+            let decoded = UpdateMessage::try_decode_two_octet_as(&mut data).unwrap();
+            (
+                decoded.attributes().as_path().unwrap().clone(),
+                has_as4_path,
+            )
+        };
+
+        // AS_TRANS stands in for the 4 octet ASNs, which AS4_PATH restores
+        assert_eq!(encode(&as_path), (as_path.clone(), true));
+
+        // Once the 4 octet ASNs are gone the path needs no AS4_PATH
+        let mut short: AsPath = "4200000001 3356".parse().unwrap();
+        short.remove_private(None);
+        assert_eq!(encode(&short), (short.clone(), false));
+    }
+
     #[test]
     fn test_decode_next_hop() {
         let mut data = Bytes::from_static(&[0x40, 0x03, 0x04, 192, 168, 1, 1]);
@@ -401,7 +2055,7 @@ mod test {
     fn test_decode_med() {
         let mut data = Bytes::from_static(&[0x80, 0x04, 0x04, 0x00, 0x00, 0x00, 0x64]); // MED 100
         let attr = PathAttribute::try_decode(&mut data).unwrap();
-        assert_eq!(attr.flags.optional, true);
+        assert!(attr.flags.optional);
         assert_eq!(attr.type_code, AttributeType::MultiExitDisc);
         assert_eq!(
             attr.value,
@@ -409,6 +2063,191 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_attributes_first_wins() {
+        // LOCAL_PREF 100, MED 5, LOCAL_PREF 200
+        let mut data = Bytes::from_static(&[
+            0x40, 0x05, 0x04, 0x00, 0x00, 0x00, 0x64, // LOCAL_PREF
+            0x80, 0x04, 0x04, 0x00, 0x00, 0x00, 0x05, // MULTI_EXIT_DISC
+            0x40, 0x05, 0x04, 0x00, 0x00, 0x00, 0xc8, // LOCAL_PREF
+        ]);
+        let mut decoded = vec![];
+        while !data.is_empty() {
+            decoded.push(PathAttribute::try_decode(&mut data).unwrap());
+        }
+
+        let attributes = Attributes::new(&decoded);
+        assert_eq!(attributes.local_pref(), Some(&LocalPref { pref: 100 }));
+        assert_eq!(attributes.get(AttributeType::LocalPref), Some(&decoded[0]));
+        assert_eq!(attributes.med(), Some(&MultiExitDisc { med: 5 }));
+        assert_eq!(attributes.origin(), None);
+        assert!(!attributes.has(AttributeType::Origin));
+        assert!(Attributes::new(&[]).as_path().is_none());
+    }
+
+    #[test]
+    fn test_edit_communities() {
+        use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+        let mut update = UpdateMessageBuilder::new()
+            .as_path("3356".parse().unwrap())
+            .next_hop("192.0.2.1".parse().unwrap())
+            .local_pref(100)
+            .announce("198.51.100.0/24".parse().unwrap())
+            .build();
+        let type_codes = |update: &UpdateMessage| -> Vec<u8> {
+            update
+                .path_attributes
+                .iter()
+                .map(|attribute| u8::from(&attribute.type_code))
+                .collect()
+        };
+        let communities = |update: &UpdateMessage| {
+            let mut data = update.to_bytes();
+            let decoded = UpdateMessage::try_decode(&mut data).unwrap();
+            decoded
+                .attributes()
+                .communities()
+                .map(|communities| communities.to_string())
+        };
+        assert_eq!(type_codes(&update), [1, 2, 3, 5]);
+
+        // Adding creates the attribute, in type code order, with the optional transitive flags
+        update.attributes_mut().add_community(Community::NO_EXPORT);
+        assert_eq!(type_codes(&update), [1, 2, 3, 5, 8]);
+        let attribute = update.attributes().get(AttributeType::Communities).unwrap();
+        assert!(attribute.flags.optional && attribute.flags.transitive);
+        update
+            .attributes_mut()
+            .add_community("3356:100".parse().unwrap());
+        update.attributes_mut().add_community(Community::NO_EXPORT);
+        assert_eq!(communities(&update).unwrap(), "3356:100 no-export");
+
+        let matcher = "3356:*".parse().unwrap();
+        update
+            .attributes_mut()
+            .remove_communities_matching(&matcher);
+        assert_eq!(communities(&update).unwrap(), "no-export");
+
+        // Removing the last community removes the attribute
+        let matcher = "no-export | blackhole".parse().unwrap();
+        update
+            .attributes_mut()
+            .remove_communities_matching(&matcher);
+        assert_eq!(type_codes(&update), [1, 2, 3, 5]);
+        assert_eq!(communities(&update), None);
+        update
+            .attributes_mut()
+            .remove_communities_matching(&matcher);
+
+        update
+            .attributes_mut()
+            .set_communities(vec![Community::BLACKHOLE, "64500:1".parse().unwrap()]);
+        assert_eq!(communities(&update).unwrap(), "64500:1 blackhole");
+        update
+            .attributes_mut()
+            .set_communities(vec![Community::GRACEFUL_SHUTDOWN]);
+        assert_eq!(communities(&update).unwrap(), "graceful-shutdown");
+        update.attributes_mut().set_communities(vec![]);
+        assert_eq!(type_codes(&update), [1, 2, 3, 5]);
+
+        // Before attributes with a higher type code
+        let mut attributes = vec![];
+        AttributesMut::new(&mut attributes).add_community(Community::NO_ADVERTISE);
+        let mut mp_reach = UpdateMessageBuilder::new()
+            .next_hop("2001:db8::1".parse().unwrap())
+            .announce("2001:db8::/32".parse().unwrap())
+            .build();
+        mp_reach
+            .attributes_mut()
+            .add_community(Community::NO_ADVERTISE);
+        assert_eq!(type_codes(&mp_reach), [1, 2, 8, 14]);
+        assert_eq!(attributes.len(), 1);
+    }
+
+    #[test]
+    fn test_set_next_hop() {
+        use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+        let reencoded = |update: &UpdateMessage| {
+            let mut data = update.to_bytes();
+            UpdateMessage::try_decode(&mut data).unwrap()
+        };
+
+        // IPv4 route: NEXT_HOP is rewritten
+        let mut update = UpdateMessageBuilder::new()
+            .as_path("3356".parse().unwrap())
+            .next_hop("192.0.2.1".parse().unwrap())
+            .announce("198.51.100.0/24".parse().unwrap())
+            .build();
+        update
+            .attributes_mut()
+            .set_next_hop("203.0.113.9".parse().unwrap())
+            .unwrap();
+        let decoded = reencoded(&update);
+        assert_eq!(
+            decoded.attributes().next_hop().unwrap().ip,
+            Ipv4Addr::new(203, 0, 113, 9)
+        );
+
+        // Classic NLRI can't carry an IPv6 next hop, and a failure changes nothing
+        let v6: Ipv6Addr = "2001:db8::9".parse().unwrap();
+        assert_eq!(
+            update.attributes_mut().set_next_hop(IpAddr::V6(v6)),
+            Err(NextHopError::ExtendedNextHopNeeded(v6))
+        );
+        assert_eq!(reencoded(&update), decoded);
+
+        // IPv6 route with global and link-local next hops: the 32 octet form shrinks to 16
+        let mut update = UpdateMessageBuilder::new()
+            .as_path("3356".parse().unwrap())
+            .mp_next_hop(
+                "2001:db8::1".parse().unwrap(),
+                Some("fe80::1".parse().unwrap()),
+            )
+            .announce("2001:db8:100::/48".parse().unwrap())
+            .build();
+        let length = |update: &UpdateMessage| {
+            let mut data = BytesMut::new();
+            update
+                .attributes()
+                .get(AttributeType::MpReachNlri)
+                .unwrap()
+                .encode(&mut data);
+            data.len()
+        };
+        let before = length(&update);
+        update
+            .attributes_mut()
+            .set_next_hop(IpAddr::V6(v6))
+            .unwrap();
+        assert_eq!(length(&update), before - 16);
+        let decoded = reencoded(&update);
+        let mp_reach = decoded.attributes().mp_reach().unwrap();
+        assert_eq!(mp_reach.next_hop, IpAddr::V6(v6));
+        assert_eq!(mp_reach.link_local, None);
+        assert_eq!(mp_reach.nlri, ["2001:db8:100::/48".parse().unwrap()]);
+
+        // IPv6 routes need an IPv6 next hop, unless it's our own IPv4 address mapped into IPv6
+        let local = Ipv4Addr::new(192, 0, 2, 7);
+        assert_eq!(
+            update.attributes_mut().set_next_hop(IpAddr::V4(local)),
+            Err(NextHopError::FamilyMismatch {
+                afi: Afi::Ipv6,
+                next_hop: IpAddr::V4(local)
+            })
+        );
+        update
+            .attributes_mut()
+            .next_hop_self(IpAddr::V4(local))
+            .unwrap();
+        let decoded = reencoded(&update);
+        assert_eq!(
+            decoded.attributes().mp_reach().unwrap().next_hop,
+            IpAddr::V6(local.to_ipv6_mapped())
+        );
+    }
+
     #[test]
     fn test_decode_atomic_aggregate() {
         // Note: Length is 0
@@ -446,13 +2285,13 @@ mod test {
             0xFF, 0xFF, 0xFF, 0x02, // NO_ADVERTISE (FFFF:FF02)
         ]);
         let attr = PathAttribute::try_decode(&mut data).unwrap();
-        assert_eq!(attr.flags.optional, true);
-        assert_eq!(attr.flags.transitive, true);
+        assert!(attr.flags.optional);
+        assert!(attr.flags.transitive);
         assert_eq!(attr.type_code, AttributeType::Communities);
         assert_eq!(
             attr.value,
             AttributeValue::Communities(Communities {
-                communities: vec![
+                communities: [
                     Community {
                         asn: 65535,
                         value: 65281
@@ -462,10 +2301,87 @@ mod test {
                         value: 65282
                     },
                 ]
+                .into()
             })
         );
     }
 
+    #[test]
+    fn test_community_names() {
+        let named = [
+            ("no-export", Community::NO_EXPORT, "65535:65281"),
+            ("no-advertise", Community::NO_ADVERTISE, "65535:65282"),
+            (
+                "no-export-subconfed",
+                Community::NO_EXPORT_SUBCONFED,
+                "65535:65283",
+            ),
+            ("blackhole", Community::BLACKHOLE, "65535:666"),
+            ("graceful-shutdown", Community::GRACEFUL_SHUTDOWN, "65535:0"),
+        ];
+        for (name, community, numeric) in named {
+            assert_eq!(community.to_string(), name);
+            assert_eq!(community.name(), Some(name));
+            assert_eq!(name.parse(), Ok(community));
+            assert_eq!(numeric.parse(), Ok(community));
+            assert!(community.is_well_known());
+        }
+        assert!(Community::BLACKHOLE.is_blackhole());
+        assert!(!Community::NO_EXPORT.is_blackhole());
+
+        let plain = Community {
+            asn: 65000,
+            value: 100,
+        };
+        assert_eq!(plain.to_string(), "65000:100");
+        assert_eq!("65000:100".parse(), Ok(plain));
+        assert_eq!(plain.name(), None);
+        assert!(!plain.is_well_known());
+        assert!("65535:1".parse::<Community>().unwrap().is_well_known());
+
+        for invalid in [
+            "",
+            "65000",
+            "65000:",
+            ":100",
+            "65536:1",
+            "1:-1",
+            "No-Export",
+            "1:2:3",
+        ] {
+            assert_eq!(
+                invalid.parse::<Community>(),
+                Err(ParseCommunityError(invalid.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_communities() {
+        let communities = Communities {
+            communities: [
+                "65000:200".parse().unwrap(),
+                Community::NO_EXPORT,
+                "64500:1".parse().unwrap(),
+                "65000:100".parse().unwrap(),
+            ]
+            .into(),
+        };
+        assert_eq!(
+            communities.to_string(),
+            "64500:1 65000:100 65000:200 no-export"
+        );
+        assert!(communities.contains(Community::NO_EXPORT));
+        assert!(!communities.contains(Community::BLACKHOLE));
+        assert_eq!(
+            Communities {
+                communities: [].into()
+            }
+            .to_string(),
+            ""
+        );
+    }
+
     #[test]
     fn test_decode_extended_length() {
         // Create a dummy attribute with a value > 255 bytes to test extended length
@@ -474,7 +2390,7 @@ mod test {
         let mut data = Bytes::from(raw_data);
 
         let attr = PathAttribute::try_decode(&mut data).unwrap();
-        assert_eq!(attr.flags.extended_length, true);
+        assert!(attr.flags.extended_length);
         assert_eq!(attr.type_code, AttributeType::Unknown(153));
         match attr.value {
             AttributeValue::Unknown(val) => assert_eq!(val.len(), 261),
@@ -489,4 +2405,241 @@ mod test {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind, ErrorKind::AttributeLengthErr);
     }
+
+    #[test]
+    fn test_error_data_is_the_attribute() {
+        // An invalid ORIGIN, then a MED that mustn't end up in the error
+        let input = [0x40, 0x01, 0x01, 0x05, 0x80, 0x04, 0x04, 0, 0, 0, 1];
+        let mut data = Bytes::copy_from_slice(&input);
+        let err = PathAttribute::try_decode(&mut data).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidOrigin);
+        assert_eq!(err.span, Some(0..4));
+        assert_eq!(err.data_in(&input), Some(&[0x40, 0x01, 0x01, 0x05][..]));
+        assert_eq!(err.data, None);
+        // Past the attribute in error
+        assert_eq!(data.len(), 7);
+
+        // An extended length AS_PATH with a segment running past its value
+        let input = [
+            0x50, 0x02, 0x00, 0x06, 0x02, 0x02, 0, 0, 0xfb, 0xf4, 0x40, 0x01, 0x01, 0x00,
+        ];
+        let mut err = PathAttribute::try_decode(&mut Bytes::copy_from_slice(&input)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MalformedAsPath);
+        err.materialize(&input);
+        assert_eq!(
+            err.data.as_deref(),
+            Some(&[0x50, 0x02, 0x00, 0x06, 0x02, 0x02, 0, 0, 0xfb, 0xf4][..])
+        );
+
+        // Truncated, all there is of it
+        for truncated in [&[0x40][..], &[0x50, 0x01, 0x00], &[0x40, 0x01, 0x02, 0x00]] {
+            let err =
+                PathAttribute::try_decode(&mut Bytes::copy_from_slice(truncated)).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::AttributeLengthErr);
+            assert_eq!(err.data_in(truncated), Some(truncated));
+        }
+    }
+
+    #[test]
+    fn test_mp_reach_nlri_round_trip() {
+        let mut data = Bytes::from_static(&[
+            0x80, 0x0E, 0x1A, // Flags (Optional), Type, Length
+            0x00, 0x02, 0x01, // AFI IPv6, SAFI unicast
+            0x10, // Next hop length
+            0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,    // 2001:db8::1
+            0x00, // Reserved
+            0x20, 0x20, 0x01, 0x0D, 0xB8, // 2001:db8::/32
+        ]);
+        let raw = data.clone();
+        let attr = PathAttribute::try_decode(&mut data).unwrap();
+        let AttributeValue::MpReachNlri(mp_reach) = &attr.value else {
+            panic!("Incorrect attribute value type");
+        };
+        assert_eq!(mp_reach.afi, Afi::Ipv6);
+        assert_eq!(mp_reach.next_hop, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(mp_reach.link_local, None);
+        assert_eq!(mp_reach.nlri[0].to_string(), "2001:db8::/32");
+
+        let mut encoded = BytesMut::new();
+        attr.encode(&mut encoded);
+        assert_eq!(encoded.freeze(), raw);
+    }
+
+    #[test]
+    fn test_mp_reach_other_family_kept_raw() {
+        // AFI IPv4, SAFI 128 (VPN) uses labeled NLRI which isn't decoded
+        let mut data = Bytes::from_static(&[0x80, 0x0E, 0x04, 0x00, 0x01, 0x80, 0x00]);
+        let attr = PathAttribute::try_decode(&mut data).unwrap();
+        assert_eq!(attr.type_code, AttributeType::MpReachNlri);
+        assert!(matches!(attr.value, AttributeValue::Unknown(_)));
+    }
+
+    #[test]
+    fn test_default_flags() {
+        // (type code, optional, transitive) per RFC 4271 section 5, RFC 1997 and RFC 4760
+        let table = [
+            (1, false, true),
+            (2, false, true),
+            (3, false, true),
+            (4, true, false),
+            (5, false, true),
+            (6, false, true),
+            (7, true, true),
+            (8, true, true),
+            (14, true, false),
+            (15, true, false),
+        ];
+        for (type_code, optional, transitive) in table {
+            let attribute_type = AttributeType::from(type_code);
+            assert!(!matches!(attribute_type, AttributeType::Unknown(_)));
+            assert_eq!(
+                attribute_type.default_flags(),
+                PathAttributeFlags {
+                    optional,
+                    transitive,
+                    partial: false,
+                    extended_length: false,
+                },
+                "type code {type_code}"
+            );
+        }
+        assert_eq!(
+            AttributeType::from(32).default_flags(),
+            PathAttributeFlags::optional_transitive()
+        );
+    }
+
+    #[test]
+    fn test_flags_byte() {
+        assert_eq!(PathAttributeFlags::well_known().to_byte(), 0x40);
+        assert_eq!(PathAttributeFlags::optional_transitive().to_byte(), 0xc0);
+        assert_eq!(
+            PathAttributeFlags::optional_non_transitive().to_byte(),
+            0x80
+        );
+        for byte in [0x00, 0x40, 0x80, 0xc0, 0xe0, 0xf0, 0x10] {
+            assert_eq!(PathAttributeFlags::from_byte(byte).to_byte(), byte);
+        }
+        // The unused low bits are dropped
+        assert_eq!(PathAttributeFlags::from_byte(0x4f).to_byte(), 0x40);
+    }
+
+    #[test]
+    fn test_validate_flags() {
+        let attribute = |type_code: u8, flags: u8| {
+            let type_code = AttributeType::from(type_code);
+            PathAttribute {
+                flags: PathAttributeFlags::from_byte(flags),
+                type_code,
+                value: AttributeValue::Unknown(Bytes::new()),
+            }
+        };
+        for (type_code, flags, valid) in [
+            (1, 0x40, true),
+            (1, 0x50, true),
+            (1, 0xc0, false),
+            (1, 0x00, false),
+            (1, 0x60, false),
+            (4, 0x80, true),
+            (4, 0xc0, false),
+            (4, 0xa0, false),
+            (8, 0xc0, true),
+            (8, 0xe0, true),
+            (8, 0x40, false),
+            (14, 0x90, true),
+            (99, 0x00, true),
+        ] {
+            let result = attribute(type_code, flags).validate();
+            let expected = if valid {
+                Ok(())
+            } else {
+                Err(UpdateMessageSubErr::AttributeFlagsError)
+            };
+            assert_eq!(
+                result, expected,
+                "type code {type_code}, flags {flags:#04x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_semantic_eq() {
+        let attribute = |type_code: u8, flags: u8, value: &'static [u8]| PathAttribute {
+            flags: PathAttributeFlags::from_byte(flags),
+            type_code: AttributeType::from(type_code),
+            value: AttributeValue::Unknown(Bytes::from_static(value)),
+        };
+        let origin = PathAttribute {
+            flags: PathAttributeFlags::well_known(),
+            type_code: AttributeType::Origin,
+            value: AttributeValue::Origin(Origin {
+                origin_type: OriginType::Igp,
+            }),
+        };
+        let as_path = |path: &str| PathAttribute {
+            flags: PathAttributeFlags::well_known(),
+            type_code: AttributeType::AsPath,
+            value: AttributeValue::AsPath(Box::new(path.parse().unwrap())),
+        };
+        let communities = |communities: &[(u16, u16)]| PathAttribute {
+            flags: PathAttributeFlags::optional_transitive(),
+            type_code: AttributeType::Communities,
+            value: AttributeValue::Communities(Communities {
+                communities: communities
+                    .iter()
+                    .map(|&(asn, value)| Community { asn, value })
+                    .collect(),
+            }),
+        };
+        let cluster_list = |value| attribute(10, 0x80, value);
+        let large = |value| attribute(32, 0xc0, value);
+
+        let a = [
+            origin.clone(),
+            as_path("65001 65002 {65010,65011}"),
+            communities(&[(65001, 1), (65001, 2)]),
+            cluster_list(&[192, 0, 2, 1, 192, 0, 2, 2]),
+            large(&[
+                0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+            ]),
+        ];
+        // Reordered attributes, communities, cluster list and large communities
+        let b = [
+            large(&[
+                0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3,
+            ]),
+            cluster_list(&[192, 0, 2, 2, 192, 0, 2, 1]),
+            communities(&[(65001, 2), (65001, 1), (65001, 2)]),
+            as_path("65001 65002 {65010,65011}"),
+            origin.clone(),
+        ];
+        assert!(semantic_eq(&a, &b));
+        assert!(SemanticAttributes(&a) == SemanticAttributes(b.to_vec()));
+        assert_ne!(a[..], b[..]);
+
+        // The extended length flag is only about encoding
+        let mut extended = a.clone();
+        extended[3].flags.extended_length = true;
+        assert!(semantic_eq(&a, &extended));
+
+        let unequal = [
+            // Reordered AS path
+            vec![as_path("65002 65001 {65010,65011}")],
+            // Another community
+            vec![communities(&[(65001, 1), (65001, 3)])],
+            // Partial
+            vec![attribute(10, 0xa0, &[192, 0, 2, 1, 192, 0, 2, 2])],
+        ];
+        for replacement in unequal {
+            let mut changed = a.clone();
+            let position = changed
+                .iter()
+                .position(|attribute| attribute.type_code == replacement[0].type_code)
+                .unwrap();
+            changed[position] = replacement[0].clone();
+            assert!(!semantic_eq(&a, &changed), "{replacement:?}");
+        }
+        assert!(!semantic_eq(&a, &a[1..]));
+        assert!(!semantic_eq(&a[..2], &[origin.clone(), origin]));
+    }
 }
@@ -0,0 +1,617 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+use crate::error::{Error as BgpError, ErrorKind};
+use crate::header::{BgpHeader, BgpMessageType, HeaderParseError, is_marker};
+use crate::notification_message::{
+    HeaderSubErr, NotificationErrorCode, NotificationMessage, NotificationParseError,
+    UpdateMessageSubErr,
+};
+use crate::open_message::{OpenMessage, OpenParseError};
+use crate::route_refresh_message::RouteRefreshMessage;
+use crate::spans;
+use crate::update_message::{AddPathFamilies, ParserConfig, UpdateMessage};
+use crate::validate::Validate;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BgpMessage {
+    Open(OpenMessage),
+    Update(UpdateMessage),
+    Notification(NotificationMessage),
+    Keepalive,
+    RouteRefresh(RouteRefreshMessage),
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum MessageDecodeError {
+    #[error(transparent)]
+    Header(#[from] HeaderParseError),
+    #[error("Malformed OPEN message: {0}")]
+    Open(#[from] OpenParseError),
+    #[error("Malformed UPDATE message: {0:?}")]
+    Update(BgpError),
+    #[error("Malformed NOTIFICATION message: {0}")]
+    Notification(#[from] NotificationParseError),
+    #[error("Malformed ROUTE-REFRESH message: {0}")]
+    RouteRefresh(String),
+    #[error("KEEPALIVE message carries {0} unexpected body bytes")]
+    KeepaliveLength(usize),
+    #[error("Unsupported message type {0}")]
+    UnknownType(u8),
+}
+
+impl BgpMessage {
+    pub fn message_type(&self) -> BgpMessageType {
+        match self {
+            BgpMessage::Open(_) => BgpMessageType::Open,
+            BgpMessage::Update(_) => BgpMessageType::Update,
+            BgpMessage::Notification(_) => BgpMessageType::Notification,
+            BgpMessage::Keepalive => BgpMessageType::Keepalive,
+            BgpMessage::RouteRefresh(_) => BgpMessageType::RouteRefresh,
+        }
+    }
+
+    /// Decodes a message body whose header has already been parsed
+    pub fn try_decode(header: &BgpHeader, body: &mut Bytes) -> Result<Self, MessageDecodeError> {
+        Self::decode_body(header, body, &ParserConfig::default())
+    }
+
+    /// Decodes a body in a span of its type, UPDATEs with the ASN width and ADD-PATH families
+    /// of `config` and within its limits
+    fn decode_body(
+        header: &BgpHeader,
+        body: &mut Bytes,
+        config: &ParserConfig,
+    ) -> Result<Self, MessageDecodeError> {
+        let _span = span!("msg.type" = header.message_type);
+        // A NOTIFICATION in error isn't answered with another one (RFC 4271 section 6.4), so
+        // one too short for its codes is a NOTIFICATION error rather than a header error
+        if header.message_type == BgpMessageType::Notification
+            && is_marker(&header.marker)
+            && body.len() < 2
+        {
+            return Err(NotificationParseError::MessageLength(body.len()).into());
+        }
+        header.validate()?;
+        match header.message_type {
+            BgpMessageType::Open => Ok(BgpMessage::Open(OpenMessage::try_from(body)?)),
+            BgpMessageType::Update => UpdateMessage::decode(body, config)
+                .map(BgpMessage::Update)
+                .map_err(MessageDecodeError::Update),
+            BgpMessageType::Notification => Ok(BgpMessage::Notification(
+                NotificationMessage::try_decode(body)?,
+            )),
+            BgpMessageType::Keepalive => {
+                if !body.is_empty() {
+                    return Err(MessageDecodeError::KeepaliveLength(body.len()));
+                }
+                Ok(BgpMessage::Keepalive)
+            }
+            BgpMessageType::RouteRefresh => RouteRefreshMessage::try_decode(body)
+                .map(BgpMessage::RouteRefresh)
+                .map_err(MessageDecodeError::RouteRefresh),
+            BgpMessageType::Unknown(value) => Err(MessageDecodeError::UnknownType(value)),
+        }
+    }
+
+    /// Decodes a message including its header, as embedded in MRT and BMP records, leaving
+    /// `data` after the length the header announces as [`parse_packet`] does. The AS_PATHs of
+    /// UPDATEs have 2 octet ASNs unless `four_octet_as`, and their prefixes path identifiers if
+    /// `add_path`.
+    pub(crate) fn decode_framed(
+        data: &mut Bytes,
+        four_octet_as: bool,
+        add_path: bool,
+    ) -> Result<Self, MessageDecodeError> {
+        let config = ParserConfig {
+            four_octet_as,
+            add_path: if add_path {
+                AddPathFamilies::ALL
+            } else {
+                AddPathFamilies::NONE
+            },
+            ..ParserConfig::default()
+        };
+        parse_packet(data, &config).map(|(_, message)| message)
+    }
+
+    /// Encodes the message including its header
+    pub fn to_bytes(&self) -> Bytes {
+        // The whole of a keepalive is its header
+        static KEEPALIVE: [u8; BgpHeader::MIN_LEN as usize] = {
+            let mut header = [0xFF; BgpHeader::MIN_LEN as usize];
+            (header[16], header[17], header[18]) = (0, BgpHeader::MIN_LEN as u8, 4);
+            header
+        };
+
+        let body = match self {
+            BgpMessage::Open(open) => open.to_bytes(),
+            BgpMessage::Update(update) => update.to_bytes(),
+            BgpMessage::Notification(notification) => notification.to_bytes(),
+            BgpMessage::Keepalive => return Bytes::from_static(&KEEPALIVE),
+            BgpMessage::RouteRefresh(route_refresh) => route_refresh.to_bytes(),
+        };
+
+        let header = BgpHeader {
+            marker: BgpHeader::MARKER_VALUE,
+            length: BgpHeader::MIN_LEN + body.len() as u16,
+            message_type: self.message_type(),
+        };
+
+        let mut buffer = BytesMut::with_capacity(header.length as usize);
+        buffer.put_slice(&header.to_bytes());
+        buffer.put_slice(&body);
+        buffer.freeze()
+    }
+}
+
+/// Parses the message at the start of `buf`, header and body, and leaves `buf` at the next
+/// one.
+///
+/// Once the header is read and the `length` it announces is there, `buf` is advanced by
+/// exactly that many octets whatever the body decodes to, so octets the body decoder leaves
+/// unread are skipped and a malformed body can be stepped over. A malformed header or a
+/// truncated message leave `buf` as it was. The octets of an UPDATE error are
+/// [materialized](MessageDecodeError::materialize).
+///
+/// UPDATEs are decoded with the ASN width and ADD-PATH families of `config`, as the session
+/// carrying them negotiated.
+pub fn parse_packet(
+    buf: &mut Bytes,
+    config: &ParserConfig,
+) -> Result<(BgpHeader, BgpMessage), MessageDecodeError> {
+    let mut message = buf.clone();
+    spans::enter(spans::mark(&message), || "message".into());
+    let header = BgpHeader::try_from_bytes(&mut message)?;
+    let length = header.length as usize;
+    if buf.len() < length {
+        return Err(HeaderParseError::InputLengthOutOfRange(length, buf.len()).into());
+    }
+    buf.advance(length);
+
+    let mut body = message.split_to(length - BgpHeader::MIN_LEN as usize);
+    let original = body.clone();
+    let decoded = BgpMessage::decode_body(&header, &mut body, config);
+    spans::leave(&message);
+    decoded.map(|message| (header, message)).map_err(|mut err| {
+        err.materialize(&original);
+        err
+    })
+}
+
+impl MessageDecodeError {
+    /// Copies the octets in error out of the message `body`, after the header, for the
+    /// NOTIFICATION to carry them
+    pub fn materialize(&mut self, body: &[u8]) {
+        if let MessageDecodeError::Update(err) = self {
+            err.materialize(body);
+        }
+    }
+
+    /// The NOTIFICATION a speaker should send in response to this error, carrying the octets
+    /// in error once [materialized](MessageDecodeError::materialize).
+    ///
+    /// `None` for a malformed NOTIFICATION, which is never answered with another one (RFC 4271
+    /// section 6.4): the connection is simply closed.
+    pub fn notification(&self) -> Option<NotificationMessage> {
+        let notification = match self {
+            MessageDecodeError::Header(HeaderParseError::MalformedMarkerField) => {
+                NotificationMessage::new(
+                    NotificationErrorCode::Header(HeaderSubErr::ConnectionNotSyncronized),
+                    vec![],
+                )
+            }
+            MessageDecodeError::Header(HeaderParseError::LengthFieldOutOfRange {
+                actual, ..
+            }) => NotificationMessage::new(
+                NotificationErrorCode::Header(HeaderSubErr::BadMessageLength),
+                (*actual as u16).to_be_bytes().to_vec(),
+            ),
+            MessageDecodeError::Header(HeaderParseError::InputLengthOutOfRange(..)) => {
+                NotificationMessage::new(
+                    NotificationErrorCode::Header(HeaderSubErr::BadMessageLength),
+                    vec![],
+                )
+            }
+            MessageDecodeError::KeepaliveLength(length) => NotificationMessage::new(
+                NotificationErrorCode::Header(HeaderSubErr::BadMessageLength),
+                ((BgpHeader::MIN_LEN as usize + length) as u16)
+                    .to_be_bytes()
+                    .to_vec(),
+            ),
+            MessageDecodeError::UnknownType(value) => NotificationMessage::new(
+                NotificationErrorCode::Header(HeaderSubErr::BadMessageType),
+                vec![*value],
+            ),
+            MessageDecodeError::Open(OpenParseError::MessageLength(length)) => {
+                NotificationMessage::new(
+                    NotificationErrorCode::Header(HeaderSubErr::BadMessageLength),
+                    ((BgpHeader::MIN_LEN as usize + length) as u16)
+                        .to_be_bytes()
+                        .to_vec(),
+                )
+            }
+            // No OPEN Message Error subcode is for malformed parameters, so none is given
+            MessageDecodeError::Open(err) => match err.sub_err() {
+                Some(sub_err) => {
+                    NotificationMessage::new(NotificationErrorCode::OpenMessage(sub_err), vec![])
+                }
+                None => NotificationMessage::new(NotificationErrorCode::Unknown(2, 0), vec![]),
+            },
+            MessageDecodeError::Update(err) => {
+                let sub_err = match err.kind {
+                    ErrorKind::BadMessageLength => {
+                        return Some(NotificationMessage::new(
+                            NotificationErrorCode::Header(HeaderSubErr::BadMessageLength),
+                            vec![],
+                        ));
+                    }
+                    ErrorKind::AttributeLengthErr => UpdateMessageSubErr::AttributeLengthError,
+                    ErrorKind::InvalidOrigin => UpdateMessageSubErr::InvalidOriginAttribute,
+                    ErrorKind::MalformedAsPath => UpdateMessageSubErr::MalformedAsPath,
+                    ErrorKind::OptionalAttributeError => {
+                        UpdateMessageSubErr::OptionalAttributeError
+                    }
+                    ErrorKind::InvalidNetworkField => UpdateMessageSubErr::InvalidNetworkField,
+                    ErrorKind::MalformedAttributeList
+                    | ErrorKind::CollectionTooLong
+                    | ErrorKind::Other => UpdateMessageSubErr::MalformedAttributeList,
+                };
+                NotificationMessage::new(
+                    NotificationErrorCode::UpdateMessage(sub_err),
+                    err.data
+                        .as_ref()
+                        .map(|data| data.to_vec())
+                        .unwrap_or_default(),
+                )
+            }
+            // ROUTE-REFRESH Message Error / Invalid Message Length (RFC 7313)
+            MessageDecodeError::RouteRefresh(_) => {
+                NotificationMessage::new(NotificationErrorCode::Unknown(7, 1), vec![])
+            }
+            MessageDecodeError::Notification(_) => return None,
+        };
+        Some(notification)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::address_family::{Afi, Safi};
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, AttributeValue};
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    fn round_trip(message: BgpMessage) {
+        let mut data = message.to_bytes();
+        let header = BgpHeader::try_from_bytes(&mut data).unwrap();
+        assert_eq!(header.message_type, message.message_type());
+        assert_eq!(BgpMessage::try_decode(&header, &mut data).unwrap(), message);
+    }
+
+    #[test]
+    fn test_keepalive_encoding() {
+        let data = BgpMessage::Keepalive.to_bytes();
+        assert_eq!(data.len(), 19);
+        assert_eq!(&data[..16], &BgpHeader::MARKER_VALUE);
+        assert_eq!(&data[16..], &[0, 19, 4]);
+        round_trip(BgpMessage::Keepalive);
+    }
+
+    #[test]
+    fn test_header_from_slice() {
+        let data = BgpMessage::Keepalive.to_bytes();
+        let header = BgpHeader::from_slice(&data).unwrap();
+        assert_eq!(
+            header,
+            BgpHeader::try_from_bytes(&mut data.clone()).unwrap()
+        );
+        assert_eq!(header.message_type, BgpMessageType::Keepalive);
+
+        assert!(matches!(
+            BgpHeader::from_slice(&data[..18]),
+            Err(HeaderParseError::InputLengthOutOfRange(19, 18))
+        ));
+        for (at, octet, malformed) in [(7, 0xFE, "marker"), (17, 18, "length")] {
+            let mut data = data.to_vec();
+            data[at] = octet;
+            let from_slice = format!("{:?}", BgpHeader::from_slice(&data).unwrap_err());
+            let from_bytes = BgpHeader::try_from_bytes(&mut Bytes::from(data)).unwrap_err();
+            assert_eq!(from_slice, format!("{from_bytes:?}"), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        round_trip(BgpMessage::Open(OpenMessage::new(
+            65001,
+            180,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[],
+        )));
+        round_trip(BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::HoldTimeExpired,
+            vec![],
+        )));
+        round_trip(BgpMessage::Update(UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        }));
+        round_trip(BgpMessage::RouteRefresh(RouteRefreshMessage::request(
+            Afi::Ipv4,
+            Safi::Unicast,
+        )));
+    }
+
+    #[test]
+    fn test_unknown_type_notification() {
+        let mut data = Bytes::from_static(&[]);
+        let header = BgpHeader::new(19, BgpMessageType::Unknown(9)).unwrap();
+        let err = BgpMessage::try_decode(&header, &mut data).unwrap_err();
+        assert_eq!(
+            err.notification().unwrap().to_bytes(),
+            Bytes::from_static(&[1, 3, 9])
+        );
+    }
+
+    #[test]
+    fn test_open_error_notification() {
+        // Shorter than its header says
+        let open = BgpHeader::new(29, BgpMessageType::Open).unwrap();
+        let err =
+            BgpMessage::try_decode(&open, &mut Bytes::from_static(&[4, 0, 1, 0])).unwrap_err();
+        assert!(matches!(
+            err,
+            MessageDecodeError::Open(OpenParseError::MessageLength(4))
+        ));
+        assert_eq!(
+            err.notification().unwrap().to_bytes(),
+            Bytes::from_static(&[1, 2, 0, 23])
+        );
+
+        // A parameter of 7 octets with none left
+        let mut data = Bytes::from_static(&[4, 0xfd, 0xe9, 0, 90, 10, 0, 0, 1, 2, 1, 7]);
+        let open = BgpHeader::new(19 + 12, BgpMessageType::Open).unwrap();
+        let err = BgpMessage::try_decode(&open, &mut data).unwrap_err();
+        assert!(matches!(
+            err,
+            MessageDecodeError::Open(OpenParseError::ParamLength {
+                param_type: 1,
+                length: 7,
+                remaining: 0
+            })
+        ));
+        assert_eq!(
+            err.notification().unwrap().to_bytes(),
+            Bytes::from_static(&[2, 0])
+        );
+
+        // Without room for its subcode
+        let notification = BgpHeader::new(19 + 1, BgpMessageType::Notification).unwrap();
+        let err = BgpMessage::try_decode(&notification, &mut Bytes::from_static(&[2])).unwrap_err();
+        assert!(matches!(
+            err,
+            MessageDecodeError::Notification(NotificationParseError::MessageLength(1))
+        ));
+        // A broken NOTIFICATION isn't answered with one
+        assert_eq!(err.notification(), None);
+    }
+
+    #[test]
+    fn test_header_length_per_type() {
+        for (message_type, length) in [
+            (BgpMessageType::Open, 28),
+            (BgpMessageType::Update, 22),
+            (BgpMessageType::Keepalive, 20),
+        ] {
+            let header = BgpHeader::new(length, message_type).unwrap();
+            let (min, max) = (message_type.min_len(), message_type.max_len());
+            assert!(matches!(
+                header.validate(),
+                Err(HeaderParseError::LengthFieldOutOfRange { actual, .. }) if actual == length as usize
+            ));
+
+            let mut body = Bytes::from(vec![0; length as usize - 19]);
+            let err = BgpMessage::try_decode(&header, &mut body).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    MessageDecodeError::Header(HeaderParseError::LengthFieldOutOfRange {
+                        min: found_min,
+                        max: found_max,
+                        ..
+                    }) if (found_min, found_max) == (min as usize, max as usize)
+                ),
+                "{message_type}"
+            );
+            // Bad Message Length, with the length
+            let mut expected = vec![1, 2];
+            expected.extend(length.to_be_bytes());
+            assert_eq!(
+                err.notification().unwrap().to_bytes(),
+                expected,
+                "{message_type}"
+            );
+
+            let fitting = BgpHeader::new(min, message_type).unwrap();
+            assert!(fitting.validate().is_ok());
+        }
+
+        let mut header = BgpHeader::new(19, BgpMessageType::Keepalive).unwrap();
+        header.marker[0] = 0;
+        assert!(matches!(
+            header.validate(),
+            Err(HeaderParseError::MalformedMarkerField)
+        ));
+    }
+
+    /// The message, then `padding` zeros its header's length takes in
+    fn padded(message: &BgpMessage, padding: usize) -> Vec<u8> {
+        let mut data = message.to_bytes().to_vec();
+        let length = data.len() + padding;
+        data[16..18].copy_from_slice(&(length as u16).to_be_bytes());
+        data.resize(length, 0);
+        data
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let open = BgpMessage::Open(OpenMessage::new(
+            65001,
+            180,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[],
+        ));
+        let keepalive = BgpMessage::Keepalive.to_bytes();
+        let config = ParserConfig::default();
+
+        // The OPEN decoder stops after its parameters, leaving the padding unread
+        let mut data = padded(&open, 3);
+        data.extend_from_slice(&keepalive);
+        let mut buf = Bytes::from(data);
+        let (header, message) = parse_packet(&mut buf, &config).unwrap();
+        assert_eq!(header.length as usize, open.to_bytes().len() + 3);
+        assert_eq!(message, open);
+        assert_eq!(buf, keepalive);
+        let (header, message) = parse_packet(&mut buf, &config).unwrap();
+        assert_eq!(
+            (header.message_type, message),
+            (BgpMessageType::Keepalive, BgpMessage::Keepalive)
+        );
+        assert!(buf.is_empty());
+
+        // A NOTIFICATION takes the padding as its data
+        let notification = BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::HoldTimeExpired,
+            vec![],
+        ));
+        let mut buf = Bytes::from(padded(&notification, 2));
+        let (_, message) = parse_packet(&mut buf, &config).unwrap();
+        let BgpMessage::Notification(notification) = message else {
+            panic!("not a NOTIFICATION");
+        };
+        assert_eq!(notification.data, [0, 0]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_parse_packet_errors() {
+        let keepalive = BgpMessage::Keepalive.to_bytes();
+        let config = ParserConfig::default();
+
+        // Truncated or out of sync, nothing is consumed
+        let open = BgpMessage::Open(OpenMessage::new(
+            65001,
+            180,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[],
+        ))
+        .to_bytes();
+        let mut buf = open.slice(..open.len() - 1);
+        assert!(matches!(
+            parse_packet(&mut buf, &config),
+            Err(MessageDecodeError::Header(
+                HeaderParseError::InputLengthOutOfRange(29, 28)
+            ))
+        ));
+        assert_eq!(buf.len(), 28);
+        let mut data = keepalive.to_vec();
+        data[0] = 0;
+        let mut buf = Bytes::from(data);
+        assert!(matches!(
+            parse_packet(&mut buf, &config),
+            Err(MessageDecodeError::Header(
+                HeaderParseError::MalformedMarkerField
+            ))
+        ));
+        assert_eq!(buf.len(), 19);
+
+        // A malformed body is stepped over
+        let mut data = keepalive.to_vec();
+        data[16..19].copy_from_slice(&[0, 23, 2]);
+        data.extend_from_slice(&[0, 9, 0, 0]);
+        data.extend_from_slice(&keepalive);
+        let mut buf = Bytes::from(data);
+        let Err(MessageDecodeError::Update(err)) = parse_packet(&mut buf, &config) else {
+            panic!("not an UPDATE error");
+        };
+        assert_eq!(err.kind, ErrorKind::MalformedAttributeList);
+        assert_eq!(buf, keepalive);
+
+        // Limits apply to the body
+        let update = UpdateMessageBuilder::new()
+            .announce("10.0.0.0/8".parse().unwrap())
+            .announce("10.1.0.0/16".parse().unwrap())
+            .announce("10.2.0.0/16".parse().unwrap())
+            .next_hop(Ipv4Addr::new(192, 0, 2, 1).into())
+            .build();
+        let mut buf = BgpMessage::Update(update).to_bytes();
+        let config = ParserConfig {
+            max_collection_len: 2,
+            ..ParserConfig::default()
+        };
+        let Err(MessageDecodeError::Update(err)) = parse_packet(&mut buf, &config) else {
+            panic!("not an UPDATE error");
+        };
+        assert_eq!(err.kind, ErrorKind::CollectionTooLong);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_parse_packet_negotiated() {
+        // A 2 octet speaker with ADD-PATH for IPv4 unicast but not for IPv6
+        let ipv4 = IpAddrPrefix::new(Ipv4Addr::new(198, 51, 100, 0).into(), 24).unwrap();
+        let ipv6 = IpAddrPrefix::new("2001:db8::".parse().unwrap(), 32).unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(ipv4.with_path_id(Some(1)))
+            .withdraw(ipv6)
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 65002].into(),
+                }]
+                .into(),
+            })
+            .next_hop(Ipv4Addr::new(192, 0, 2, 1).into())
+            .four_octet_as(false)
+            .build();
+        let encoded = BgpMessage::Update(update.clone()).to_bytes();
+        let config = ParserConfig {
+            four_octet_as: false,
+            add_path: [(Afi::Ipv4, Safi::Unicast)].into_iter().collect(),
+            ..ParserConfig::default()
+        };
+
+        let (_, BgpMessage::Update(decoded)) = parse_packet(&mut encoded.clone(), &config).unwrap()
+        else {
+            panic!("not an UPDATE");
+        };
+        assert_eq!(decoded.nlri, update.nlri);
+        assert_eq!(
+            decoded.path_attributes[1].value,
+            AttributeValue::AsPath(Box::new(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 65002].into(),
+                }]
+                .into(),
+            }))
+        );
+        assert_eq!(
+            decoded.path_attributes.last().unwrap().value,
+            update.path_attributes.last().unwrap().value
+        );
+
+        // Defaults read the ASNs as 4 octets and the path identifier as a prefix length
+        assert!(parse_packet(&mut encoded.clone(), &ParserConfig::default()).is_err());
+        let config = ParserConfig {
+            four_octet_as: false,
+            add_path: AddPathFamilies::ALL,
+            ..ParserConfig::default()
+        };
+        assert!(parse_packet(&mut encoded.clone(), &config).is_err());
+    }
+}
@@ -0,0 +1,983 @@
+//! The BGP Monitoring Protocol (RFC 7854), over which routers export the routes they learn
+//! from their peers to a monitoring station.
+//!
+//! Every message starts with a common header, all integers big endian:
+//!
+//! ```text
+//! version: u8 = 3 | length of the whole message: u32 | type: u8
+//! ```
+//!
+//! Messages about a peer then carry a [`PeerHeader`]. [`BmpMessage::decode_frame`] takes
+//! messages off a buffered stream, and [`BmpReader`] iterates over those of a [`Read`].
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, Bytes, BytesMut};
+use thiserror::Error;
+
+use crate::address_family::{Afi, Safi};
+use crate::bgp_message::{BgpMessage, MessageDecodeError};
+use crate::notification_message::NotificationMessage;
+use crate::open_message::OpenMessage;
+use crate::update_message::UpdateMessage;
+
+#[cfg(feature = "tokio")]
+mod station;
+
+#[cfg(feature = "tokio")]
+pub use station::{BmpStation, StationEvent};
+
+pub const BMP_VERSION: u8 = 3;
+/// Of the common header
+pub const HEADER_LEN: usize = 6;
+const PEER_HEADER_LEN: usize = 42;
+
+/// Message types
+const ROUTE_MONITORING: u8 = 0;
+const STATISTICS_REPORT: u8 = 1;
+const PEER_DOWN: u8 = 2;
+const PEER_UP: u8 = 3;
+const INITIATION: u8 = 4;
+const TERMINATION: u8 = 5;
+
+/// Peer header flags
+const FLAG_IPV6: u8 = 0x80;
+const FLAG_POST_POLICY: u8 = 0x40;
+const FLAG_LEGACY_AS_PATH: u8 = 0x20;
+
+#[derive(Debug, Error)]
+pub enum BmpError {
+    #[error("BMP I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("BMP stream ends in a truncated message")]
+    Truncated,
+    #[error("unsupported BMP version {0}")]
+    Version(u8),
+    #[error("BMP message length {0} is shorter than its header")]
+    Length(u32),
+    #[error("malformed BMP message: {0}")]
+    Malformed(&'static str),
+    #[error("BGP message in BMP message doesn't decode: {0}")]
+    Message(#[from] MessageDecodeError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BmpMessage {
+    RouteMonitoring {
+        peer: PeerHeader,
+        update: UpdateMessage,
+    },
+    StatisticsReport {
+        peer: PeerHeader,
+        stats: Vec<Stat>,
+    },
+    PeerDown {
+        peer: PeerHeader,
+        reason: PeerDownReason,
+    },
+    PeerUp {
+        peer: PeerHeader,
+        peer_up: PeerUp,
+    },
+    Initiation(Vec<InformationTlv>),
+    /// The router closes the session, the TCP connection follows
+    Termination(Vec<TerminationTlv>),
+    /// Route Mirroring and types after it, undecoded
+    Other {
+        message_type: u8,
+        body: Bytes,
+    },
+}
+
+/// The peer a message is about, as the router sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerHeader {
+    pub peer_type: PeerType,
+    pub flags: u8,
+    /// Route distinguisher of the VRF the peer belongs to for [`PeerType::RdInstance`]
+    pub distinguisher: u64,
+    pub addr: IpAddr,
+    pub asn: u32,
+    pub bgp_id: Ipv4Addr,
+    /// When the routes were received or the peer went up or down; the epoch when the router
+    /// doesn't say
+    pub timestamp: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerType {
+    Global,
+    RdInstance,
+    LocalInstance,
+    Unknown(u8),
+}
+
+/// A counter of a Statistics Report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    pub stat_type: StatType,
+    pub value: StatValue,
+}
+
+/// What a statistic counts, as numbered by RFC 7854 section 4.8 with the additions of RFC 7606
+/// (11 and 12) and RFC 8671 (14 to 17)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatType {
+    /// Prefixes rejected by the import policy
+    RejectedPrefixes,
+    DuplicatePrefixAdvertisements,
+    DuplicateWithdrawals,
+    ClusterListLoops,
+    AsPathLoops,
+    OriginatorIdLoops,
+    AsConfedLoops,
+    AdjRibInRoutes,
+    LocRibRoutes,
+    AdjRibInRoutesPerFamily,
+    LocRibRoutesPerFamily,
+    /// UPDATEs treated as withdrawals for their errors
+    TreatAsWithdrawUpdates,
+    /// Prefixes of UPDATEs treated as withdrawals
+    TreatAsWithdrawPrefixes,
+    DuplicateUpdates,
+    PrePolicyAdjRibOutRoutes,
+    PostPolicyAdjRibOutRoutes,
+    PrePolicyAdjRibOutRoutesPerFamily,
+    PostPolicyAdjRibOutRoutesPerFamily,
+    Unknown(u16),
+}
+
+/// Counters are 32 bits and gauges 64, the value of a statistic of another length or of an
+/// unknown type is kept as it came
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatValue {
+    Counter(u32),
+    Gauge(u64),
+    /// A gauge of one address family, such as the routes of the family in the Adj-RIB-In
+    FamilyGauge {
+        afi: Afi,
+        safi: Safi,
+        value: u64,
+    },
+    Unknown(Bytes),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerDownReason {
+    /// The router closed the session, sending the NOTIFICATION
+    LocalNotification(NotificationMessage),
+    /// The router closed the session without a NOTIFICATION after the FSM event
+    LocalNoNotification {
+        fsm_event: u16,
+    },
+    /// The peer closed the session, sending the NOTIFICATION
+    RemoteNotification(NotificationMessage),
+    RemoteNoNotification,
+    /// The peer was deconfigured, or is no longer monitored (RFC 9069)
+    Deconfigured,
+    /// The router closed a Loc-RIB instance, saying which in the TLVs (RFC 9069)
+    LocalClosed(Vec<InformationTlv>),
+    Unknown {
+        code: u8,
+        data: Bytes,
+    },
+}
+
+/// A session coming up: the OPENs exchanged and the addresses of the TCP connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerUp {
+    pub local_addr: IpAddr,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub sent_open: OpenMessage,
+    pub received_open: OpenMessage,
+    pub information: Vec<InformationTlv>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InformationTlv {
+    String(String),
+    SysDescr(String),
+    SysName(String),
+    Unknown { info_type: u16, value: Bytes },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationTlv {
+    String(String),
+    Reason(TerminationReason),
+    Unknown { info_type: u16, value: Bytes },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminationReason {
+    AdministrativelyClosed,
+    Unspecified,
+    OutOfResources,
+    RedundantConnection,
+    PermanentlyClosed,
+    Unknown(u16),
+}
+
+impl PeerHeader {
+    /// Whether the routes are those accepted by the import policy rather than as received
+    pub fn post_policy(&self) -> bool {
+        self.flags & FLAG_POST_POLICY != 0
+    }
+
+    /// Whether AS_PATHs have 2 octet ASNs, as from routers predating RFC 6793
+    pub fn legacy_as_path(&self) -> bool {
+        self.flags & FLAG_LEGACY_AS_PATH != 0
+    }
+
+    fn decode(data: &mut Bytes) -> Result<Self, BmpError> {
+        if data.len() < PEER_HEADER_LEN {
+            return Err(BmpError::Malformed("truncated per-peer header"));
+        }
+        let peer_type = match data.get_u8() {
+            0 => PeerType::Global,
+            1 => PeerType::RdInstance,
+            2 => PeerType::LocalInstance,
+            peer_type => PeerType::Unknown(peer_type),
+        };
+        let flags = data.get_u8();
+        let distinguisher = data.get_u64();
+        let addr = decode_addr(data, flags & FLAG_IPV6 != 0);
+        let asn = data.get_u32();
+        let bgp_id = Ipv4Addr::from(data.get_u32());
+        let seconds = data.get_u32();
+        let micros = data.get_u32();
+        Ok(PeerHeader {
+            peer_type,
+            flags,
+            distinguisher,
+            addr,
+            asn,
+            bgp_id,
+            timestamp: SystemTime::UNIX_EPOCH
+                + Duration::from_secs(seconds.into())
+                + Duration::from_micros(micros.into()),
+        })
+    }
+}
+
+impl BmpMessage {
+    /// The length of the message at the start of `data`, once its common header is there
+    pub fn frame_len(data: &[u8]) -> Result<Option<usize>, BmpError> {
+        if data.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        if data[0] != BMP_VERSION {
+            return Err(BmpError::Version(data[0]));
+        }
+        let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        if (length as usize) < HEADER_LEN {
+            return Err(BmpError::Length(length));
+        }
+        Ok(Some(length as usize))
+    }
+
+    /// Takes the message at the start of `buf` off it, `None` until all of it was buffered.
+    ///
+    /// A message that doesn't decode is still taken off, so the next call continues with the
+    /// next message. A bad version or length leaves no way to find the next message.
+    pub fn decode_frame(buf: &mut BytesMut) -> Result<Option<Self>, BmpError> {
+        match Self::frame_len(buf)? {
+            Some(length) if buf.len() >= length => {
+                let mut message = buf.split_to(length).freeze();
+                Self::try_decode(&mut message).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Decodes a whole message, common header included
+    pub fn try_decode(data: &mut Bytes) -> Result<Self, BmpError> {
+        let length = Self::frame_len(data)?.ok_or(BmpError::Truncated)?;
+        if data.len() < length {
+            return Err(BmpError::Truncated);
+        }
+        let message_type = data[5];
+        let mut body = data.split_to(length).split_off(HEADER_LEN);
+
+        match message_type {
+            ROUTE_MONITORING => {
+                let peer = PeerHeader::decode(&mut body)?;
+                match BgpMessage::decode_framed(&mut body, !peer.legacy_as_path(), false)? {
+                    BgpMessage::Update(update) => Ok(BmpMessage::RouteMonitoring { peer, update }),
+                    _ => Err(BmpError::Malformed("route monitoring without an UPDATE")),
+                }
+            }
+            STATISTICS_REPORT => {
+                let peer = PeerHeader::decode(&mut body)?;
+                Ok(BmpMessage::StatisticsReport {
+                    peer,
+                    stats: decode_stats(&mut body)?,
+                })
+            }
+            PEER_DOWN => {
+                let peer = PeerHeader::decode(&mut body)?;
+                Ok(BmpMessage::PeerDown {
+                    peer,
+                    reason: PeerDownReason::decode(&mut body)?,
+                })
+            }
+            PEER_UP => {
+                let peer = PeerHeader::decode(&mut body)?;
+                Ok(BmpMessage::PeerUp {
+                    peer,
+                    peer_up: PeerUp::decode(&mut body, peer.addr.is_ipv6())?,
+                })
+            }
+            INITIATION => Ok(BmpMessage::Initiation(decode_information(&mut body)?)),
+            TERMINATION => {
+                let tlvs = decode_tlvs(&mut body)?
+                    .into_iter()
+                    .map(|(info_type, value)| match info_type {
+                        0 => TerminationTlv::String(string(&value)),
+                        1 if value.len() == 2 => {
+                            TerminationTlv::Reason(match u16::from_be_bytes([value[0], value[1]]) {
+                                0 => TerminationReason::AdministrativelyClosed,
+                                1 => TerminationReason::Unspecified,
+                                2 => TerminationReason::OutOfResources,
+                                3 => TerminationReason::RedundantConnection,
+                                4 => TerminationReason::PermanentlyClosed,
+                                reason => TerminationReason::Unknown(reason),
+                            })
+                        }
+                        _ => TerminationTlv::Unknown { info_type, value },
+                    })
+                    .collect();
+                Ok(BmpMessage::Termination(tlvs))
+            }
+            message_type => Ok(BmpMessage::Other { message_type, body }),
+        }
+    }
+
+    /// The peer the message is about, `None` for messages about the router itself
+    pub fn peer(&self) -> Option<&PeerHeader> {
+        match self {
+            BmpMessage::RouteMonitoring { peer, .. }
+            | BmpMessage::StatisticsReport { peer, .. }
+            | BmpMessage::PeerDown { peer, .. }
+            | BmpMessage::PeerUp { peer, .. } => Some(peer),
+            _ => None,
+        }
+    }
+}
+
+impl StatType {
+    /// Whether the value is of a single address family
+    pub fn is_per_family(&self) -> bool {
+        matches!(
+            self,
+            StatType::AdjRibInRoutesPerFamily
+                | StatType::LocRibRoutesPerFamily
+                | StatType::PrePolicyAdjRibOutRoutesPerFamily
+                | StatType::PostPolicyAdjRibOutRoutesPerFamily
+        )
+    }
+}
+
+impl From<u16> for StatType {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => StatType::RejectedPrefixes,
+            1 => StatType::DuplicatePrefixAdvertisements,
+            2 => StatType::DuplicateWithdrawals,
+            3 => StatType::ClusterListLoops,
+            4 => StatType::AsPathLoops,
+            5 => StatType::OriginatorIdLoops,
+            6 => StatType::AsConfedLoops,
+            7 => StatType::AdjRibInRoutes,
+            8 => StatType::LocRibRoutes,
+            9 => StatType::AdjRibInRoutesPerFamily,
+            10 => StatType::LocRibRoutesPerFamily,
+            11 => StatType::TreatAsWithdrawUpdates,
+            12 => StatType::TreatAsWithdrawPrefixes,
+            13 => StatType::DuplicateUpdates,
+            14 => StatType::PrePolicyAdjRibOutRoutes,
+            15 => StatType::PostPolicyAdjRibOutRoutes,
+            16 => StatType::PrePolicyAdjRibOutRoutesPerFamily,
+            17 => StatType::PostPolicyAdjRibOutRoutesPerFamily,
+            value => StatType::Unknown(value),
+        }
+    }
+}
+
+impl From<StatType> for u16 {
+    fn from(stat_type: StatType) -> Self {
+        match stat_type {
+            StatType::RejectedPrefixes => 0,
+            StatType::DuplicatePrefixAdvertisements => 1,
+            StatType::DuplicateWithdrawals => 2,
+            StatType::ClusterListLoops => 3,
+            StatType::AsPathLoops => 4,
+            StatType::OriginatorIdLoops => 5,
+            StatType::AsConfedLoops => 6,
+            StatType::AdjRibInRoutes => 7,
+            StatType::LocRibRoutes => 8,
+            StatType::AdjRibInRoutesPerFamily => 9,
+            StatType::LocRibRoutesPerFamily => 10,
+            StatType::TreatAsWithdrawUpdates => 11,
+            StatType::TreatAsWithdrawPrefixes => 12,
+            StatType::DuplicateUpdates => 13,
+            StatType::PrePolicyAdjRibOutRoutes => 14,
+            StatType::PostPolicyAdjRibOutRoutes => 15,
+            StatType::PrePolicyAdjRibOutRoutesPerFamily => 16,
+            StatType::PostPolicyAdjRibOutRoutesPerFamily => 17,
+            StatType::Unknown(value) => value,
+        }
+    }
+}
+
+impl PeerDownReason {
+    fn decode(data: &mut Bytes) -> Result<Self, BmpError> {
+        if data.is_empty() {
+            return Err(BmpError::Malformed("peer down without a reason"));
+        }
+        let notification = |data: &mut Bytes| match BgpMessage::decode_framed(data, true, false)? {
+            BgpMessage::Notification(notification) => Ok(notification),
+            _ => Err(BmpError::Malformed("peer down without a NOTIFICATION")),
+        };
+        Ok(match data.get_u8() {
+            1 => PeerDownReason::LocalNotification(notification(data)?),
+            2 if data.len() >= 2 => PeerDownReason::LocalNoNotification {
+                fsm_event: data.get_u16(),
+            },
+            2 => return Err(BmpError::Malformed("peer down without an FSM event")),
+            3 => PeerDownReason::RemoteNotification(notification(data)?),
+            4 => PeerDownReason::RemoteNoNotification,
+            5 => PeerDownReason::Deconfigured,
+            6 => PeerDownReason::LocalClosed(decode_information(data)?),
+            code => PeerDownReason::Unknown {
+                code,
+                data: std::mem::take(data),
+            },
+        })
+    }
+}
+
+impl PeerUp {
+    fn decode(data: &mut Bytes, ipv6: bool) -> Result<Self, BmpError> {
+        if data.len() < 16 + 2 + 2 {
+            return Err(BmpError::Malformed("truncated peer up"));
+        }
+        let local_addr = decode_addr(data, ipv6);
+        let local_port = data.get_u16();
+        let remote_port = data.get_u16();
+        let open = |data: &mut Bytes| match BgpMessage::decode_framed(data, true, false)? {
+            BgpMessage::Open(open) => Ok(open),
+            _ => Err(BmpError::Malformed("peer up without OPENs")),
+        };
+        let sent_open = open(data)?;
+        let received_open = open(data)?;
+        Ok(PeerUp {
+            local_addr,
+            local_port,
+            remote_port,
+            sent_open,
+            received_open,
+            information: decode_information(data)?,
+        })
+    }
+}
+
+/// An address in 16 bytes, IPv4 in the last 4 unless `ipv6`
+fn decode_addr(data: &mut Bytes, ipv6: bool) -> IpAddr {
+    let addr = data.get_u128();
+    match ipv6 {
+        true => IpAddr::V6(Ipv6Addr::from(addr)),
+        false => IpAddr::V4(Ipv4Addr::from(addr as u32)),
+    }
+}
+
+fn decode_stats(data: &mut Bytes) -> Result<Vec<Stat>, BmpError> {
+    if data.len() < 4 {
+        return Err(BmpError::Malformed("truncated statistics report"));
+    }
+    let count = data.get_u32();
+    let mut stats = vec![];
+    for _ in 0..count {
+        let Some((stat_type, mut value)) = decode_tlv(data)? else {
+            return Err(BmpError::Malformed("truncated statistics report"));
+        };
+        let stat_type = StatType::from(stat_type);
+        let value = match (stat_type, value.len()) {
+            (StatType::Unknown(_), _) => StatValue::Unknown(value),
+            (stat_type, 4) if !stat_type.is_per_family() => StatValue::Counter(value.get_u32()),
+            (stat_type, 8) if !stat_type.is_per_family() => StatValue::Gauge(value.get_u64()),
+            (stat_type, 11) if stat_type.is_per_family() => StatValue::FamilyGauge {
+                afi: value.get_u16().into(),
+                safi: value.get_u8().into(),
+                value: value.get_u64(),
+            },
+            _ => StatValue::Unknown(value),
+        };
+        stats.push(Stat { stat_type, value });
+    }
+    Ok(stats)
+}
+
+fn decode_information(data: &mut Bytes) -> Result<Vec<InformationTlv>, BmpError> {
+    Ok(decode_tlvs(data)?
+        .into_iter()
+        .map(|(info_type, value)| match info_type {
+            0 => InformationTlv::String(string(&value)),
+            1 => InformationTlv::SysDescr(string(&value)),
+            2 => InformationTlv::SysName(string(&value)),
+            _ => InformationTlv::Unknown { info_type, value },
+        })
+        .collect())
+}
+
+/// The type and value of every TLV left in `data`
+fn decode_tlvs(data: &mut Bytes) -> Result<Vec<(u16, Bytes)>, BmpError> {
+    let mut tlvs = vec![];
+    while let Some(tlv) = decode_tlv(data)? {
+        tlvs.push(tlv);
+    }
+    Ok(tlvs)
+}
+
+fn decode_tlv(data: &mut Bytes) -> Result<Option<(u16, Bytes)>, BmpError> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+    if data.len() < 4 {
+        return Err(BmpError::Malformed("truncated TLV"));
+    }
+    let tlv_type = data.get_u16();
+    let length = data.get_u16() as usize;
+    if data.len() < length {
+        return Err(BmpError::Malformed("truncated TLV"));
+    }
+    Ok(Some((tlv_type, data.split_to(length))))
+}
+
+fn string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).into_owned()
+}
+
+/// Iterates over the messages of a BMP stream, as read from a router's TCP connection.
+///
+/// A message that doesn't decode is reported and skipped. Since a truncated message, a bad
+/// common header or a failing reader leaves no way to find the next one, those errors end the
+/// iteration.
+pub struct BmpReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> BmpReader<R> {
+    pub fn new(reader: R) -> Self {
+        BmpReader {
+            reader,
+            done: false,
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Bytes>, BmpError> {
+        let mut header = [0; HEADER_LEN];
+        let mut read = 0;
+        while read < HEADER_LEN {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(BmpError::Truncated),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let length = BmpMessage::frame_len(&header)?.unwrap_or(HEADER_LEN);
+        let mut frame = header.to_vec();
+        (&mut self.reader)
+            .take((length - HEADER_LEN) as u64)
+            .read_to_end(&mut frame)?;
+        if frame.len() < length {
+            return Err(BmpError::Truncated);
+        }
+        Ok(Some(frame.into()))
+    }
+}
+
+impl<R: Read> Iterator for BmpReader<R> {
+    type Item = Result<BmpMessage, BmpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(Some(mut frame)) => Some(BmpMessage::try_decode(&mut frame)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::path::Path;
+
+    use crate::attribute::{AsPathSegment, AsPathSegmentType, AttributeValue};
+    use crate::notification_message::{CeaseSubErr, NotificationErrorCode};
+
+    fn fixture() -> Vec<u8> {
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bmp_session.bin"))
+            .unwrap()
+    }
+
+    fn at(secs: u64, micros: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(1_700_000_000 + secs)
+            + Duration::from_micros(micros)
+    }
+
+    fn as_path(update: &UpdateMessage) -> Vec<u32> {
+        match &update.attributes().as_path().unwrap().segments[..] {
+            [
+                AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns,
+                },
+            ] => asns.to_vec(),
+            segments => panic!("unexpected AS_PATH {segments:?}"),
+        }
+    }
+
+    /// A session as an IOS XR router exports it: Initiation, a Peer Up, Route Monitoring from
+    /// an IPv4 peer, a post-policy IPv6 peer and a peer with 2 octet AS_PATHs, a Statistics
+    /// Report, Route Monitoring with a corrupt BGP marker, two Peer Downs and the Termination
+    #[test]
+    fn test_read_session() {
+        let file =
+            File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bmp_session.bin"))
+                .unwrap();
+        let messages: Vec<_> = BmpReader::new(file).collect();
+        assert_eq!(messages.len(), 10);
+        let message = |i: usize| messages[i].as_ref().unwrap();
+
+        assert_eq!(
+            message(0),
+            &BmpMessage::Initiation(vec![
+                InformationTlv::SysDescr("Cisco IOS XR Software, Version 7.5.2".into()),
+                InformationTlv::SysName("edge1.example.net".into()),
+            ])
+        );
+
+        let BmpMessage::PeerUp { peer, peer_up } = message(1) else {
+            panic!("{:?} isn't a Peer Up", message(1));
+        };
+        assert_eq!(
+            peer,
+            &PeerHeader {
+                peer_type: PeerType::Global,
+                flags: 0,
+                distinguisher: 0,
+                addr: "192.0.2.1".parse().unwrap(),
+                asn: 3356,
+                bgp_id: Ipv4Addr::new(4, 69, 0, 1),
+                timestamp: at(0, 0),
+            }
+        );
+        assert_eq!(peer_up.local_addr, "192.0.2.2".parse::<IpAddr>().unwrap());
+        assert_eq!((peer_up.local_port, peer_up.remote_port), (179, 51234));
+        assert_eq!(peer_up.sent_open.bgp_id, Ipv4Addr::new(198, 51, 100, 1));
+        assert_eq!(peer_up.received_open.my_autonomous_system, 3356);
+        assert_eq!(
+            peer_up.information,
+            vec![InformationTlv::String("uplink".into())]
+        );
+
+        let BmpMessage::RouteMonitoring { peer, update } = message(2) else {
+            panic!("{:?} isn't Route Monitoring", message(2));
+        };
+        assert_eq!(peer.timestamp, at(10, 250_000));
+        assert!(!peer.post_policy());
+        assert_eq!(as_path(update), vec![3356, 1299, 4_200_000_000]);
+        assert_eq!(update.nlri, vec!["203.0.113.0/24".parse().unwrap()]);
+
+        let BmpMessage::RouteMonitoring { peer, update } = message(3) else {
+            panic!("{:?} isn't Route Monitoring", message(3));
+        };
+        assert_eq!(peer.addr, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert!(peer.post_policy());
+        assert_eq!(as_path(update), vec![6939, 13335]);
+        assert!(update.path_attributes.iter().any(|attribute| matches!(
+            &attribute.value,
+            AttributeValue::MpReachNlri(mp_reach)
+                if mp_reach.nlri == vec!["2001:db8:100::/48".parse().unwrap()]
+        )));
+
+        let BmpMessage::RouteMonitoring { peer, update } = message(4) else {
+            panic!("{:?} isn't Route Monitoring", message(4));
+        };
+        assert!(peer.legacy_as_path());
+        assert_eq!(as_path(update), vec![7018, 701]);
+
+        let BmpMessage::StatisticsReport { stats, .. } = message(5) else {
+            panic!("{:?} isn't a Statistics Report", message(5));
+        };
+        assert_eq!(
+            stats,
+            &vec![
+                Stat {
+                    stat_type: StatType::RejectedPrefixes,
+                    value: StatValue::Counter(3)
+                },
+                Stat {
+                    stat_type: StatType::AdjRibInRoutes,
+                    value: StatValue::Gauge(912_345)
+                },
+                Stat {
+                    stat_type: StatType::AdjRibInRoutesPerFamily,
+                    value: StatValue::FamilyGauge {
+                        afi: Afi::Ipv4,
+                        safi: Safi::Unicast,
+                        value: 912_000
+                    }
+                },
+                Stat {
+                    stat_type: StatType::Unknown(99),
+                    value: StatValue::Unknown(Bytes::from_static(&[1, 2]))
+                },
+            ]
+        );
+
+        assert!(matches!(messages[6], Err(BmpError::Message(_))));
+
+        let BmpMessage::PeerDown { reason, .. } = message(7) else {
+            panic!("{:?} isn't a Peer Down", message(7));
+        };
+        let PeerDownReason::LocalNotification(notification) = reason else {
+            panic!("unexpected reason {reason:?}");
+        };
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::Cease(CeaseSubErr::AdministrativeShutdown)
+        );
+        assert!(matches!(
+            message(8),
+            BmpMessage::PeerDown {
+                reason: PeerDownReason::RemoteNoNotification,
+                ..
+            }
+        ));
+
+        assert_eq!(
+            message(9),
+            &BmpMessage::Termination(vec![
+                TerminationTlv::String("shutting down".into()),
+                TerminationTlv::Reason(TerminationReason::AdministrativelyClosed),
+            ])
+        );
+        assert_eq!(message(9).peer(), None);
+    }
+
+    /// A message about the IPv4 peer 192.0.2.1 of AS 64500
+    fn peer_message(message_type: u8, body: &[u8]) -> Bytes {
+        let mut message = vec![BMP_VERSION, 0, 0, 0, 0, message_type];
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&[0; 12]);
+        message.extend_from_slice(&[192, 0, 2, 1]);
+        message.extend_from_slice(&64500u32.to_be_bytes());
+        message.extend_from_slice(&[192, 0, 2, 1]);
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(body);
+        let length = message.len() as u32;
+        message[1..5].copy_from_slice(&length.to_be_bytes());
+        Bytes::from(message)
+    }
+
+    #[test]
+    fn test_peer_down_reasons() {
+        let reason = |body: &[u8]| match BmpMessage::try_decode(&mut peer_message(PEER_DOWN, body))
+            .unwrap()
+        {
+            BmpMessage::PeerDown { reason, .. } => reason,
+            message => panic!("{message:?} isn't a Peer Down"),
+        };
+        let notification = |code: u8, subcode: u8| {
+            let mut body = vec![0xff; 16];
+            body.extend_from_slice(&[0, 21, 3, code, subcode]);
+            body
+        };
+
+        let local = [&[1][..], &notification(6, 2)].concat();
+        let PeerDownReason::LocalNotification(sent) = reason(&local) else {
+            panic!("unexpected reason {:?}", reason(&local));
+        };
+        assert_eq!(
+            sent.error_codes,
+            NotificationErrorCode::Cease(CeaseSubErr::AdministrativeShutdown)
+        );
+        assert_eq!(
+            reason(&[2, 0, 10]),
+            PeerDownReason::LocalNoNotification { fsm_event: 10 }
+        );
+        let remote = [&[3][..], &notification(4, 0)].concat();
+        let PeerDownReason::RemoteNotification(received) = reason(&remote) else {
+            panic!("unexpected reason {:?}", reason(&remote));
+        };
+        assert_eq!(received.error_codes, NotificationErrorCode::HoldTimeExpired);
+        assert_eq!(reason(&[4]), PeerDownReason::RemoteNoNotification);
+        assert_eq!(reason(&[5]), PeerDownReason::Deconfigured);
+        assert_eq!(
+            reason(&[6, 0, 3, 0, 6, b'g', b'l', b'o', b'b', b'a', b'l']),
+            PeerDownReason::LocalClosed(vec![InformationTlv::Unknown {
+                info_type: 3,
+                value: Bytes::from_static(b"global"),
+            }])
+        );
+        assert_eq!(
+            reason(&[9, 1, 2]),
+            PeerDownReason::Unknown {
+                code: 9,
+                data: Bytes::from_static(&[1, 2]),
+            }
+        );
+
+        for malformed in [&[][..], &[2, 0], &[1, 0xff, 0xff]] {
+            assert!(BmpMessage::try_decode(&mut peer_message(PEER_DOWN, malformed)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_statistics_report() {
+        let mut body = vec![0, 0, 0, 18];
+        let mut stat = |stat_type: u16, value: &[u8]| {
+            body.extend_from_slice(&stat_type.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+        };
+        for stat_type in (0..=6).chain(11..=13) {
+            stat(stat_type, &u32::from(stat_type).to_be_bytes());
+        }
+        stat(7, &912_345u64.to_be_bytes());
+        stat(8, &900_000u64.to_be_bytes());
+        stat(9, &[&[0, 1, 1][..], &912_000u64.to_be_bytes()].concat());
+        stat(10, &[&[0, 2, 1][..], &200_000u64.to_be_bytes()].concat());
+        // An unknown type of a known length, and a counter of an unexpected length
+        stat(99, &[0, 0, 0, 1]);
+        stat(0, &[1, 2]);
+        stat(9, &[0; 8]);
+        stat(65535, &[]);
+
+        let message = peer_message(STATISTICS_REPORT, &body);
+        let BmpMessage::StatisticsReport { stats, .. } =
+            BmpMessage::try_decode(&mut message.clone()).unwrap()
+        else {
+            panic!("not a Statistics Report");
+        };
+        let types: Vec<u16> = stats.iter().map(|stat| stat.stat_type.into()).collect();
+        assert_eq!(
+            types,
+            [
+                0, 1, 2, 3, 4, 5, 6, 11, 12, 13, 7, 8, 9, 10, 99, 0, 9, 65535
+            ]
+        );
+        assert_eq!(
+            stats[1],
+            Stat {
+                stat_type: StatType::DuplicatePrefixAdvertisements,
+                value: StatValue::Counter(1),
+            }
+        );
+        assert_eq!(
+            stats[9],
+            Stat {
+                stat_type: StatType::DuplicateUpdates,
+                value: StatValue::Counter(13),
+            }
+        );
+        assert_eq!(
+            stats[11],
+            Stat {
+                stat_type: StatType::LocRibRoutes,
+                value: StatValue::Gauge(900_000),
+            }
+        );
+        assert_eq!(
+            stats[13],
+            Stat {
+                stat_type: StatType::LocRibRoutesPerFamily,
+                value: StatValue::FamilyGauge {
+                    afi: Afi::Ipv6,
+                    safi: Safi::Unicast,
+                    value: 200_000,
+                },
+            }
+        );
+        assert_eq!(
+            stats[14..],
+            [
+                Stat {
+                    stat_type: StatType::Unknown(99),
+                    value: StatValue::Unknown(Bytes::from_static(&[0, 0, 0, 1])),
+                },
+                Stat {
+                    stat_type: StatType::RejectedPrefixes,
+                    value: StatValue::Unknown(Bytes::from_static(&[1, 2])),
+                },
+                Stat {
+                    stat_type: StatType::AdjRibInRoutesPerFamily,
+                    value: StatValue::Unknown(Bytes::from_static(&[0; 8])),
+                },
+                Stat {
+                    stat_type: StatType::Unknown(65535),
+                    value: StatValue::Unknown(Bytes::new()),
+                },
+            ]
+        );
+
+        // One statistic fewer than counted
+        let truncated = peer_message(STATISTICS_REPORT, &body[..body.len() - 4]);
+        assert!(BmpMessage::try_decode(&mut truncated.clone()).is_err());
+    }
+
+    /// Messages come off the buffer as they complete, however the stream splits them
+    #[test]
+    fn test_decode_frame() {
+        let data = fixture();
+        let mut buf = BytesMut::new();
+        let mut messages = vec![];
+        for chunk in data.chunks(7) {
+            buf.extend_from_slice(chunk);
+            while let Some(message) = BmpMessage::decode_frame(&mut buf).transpose() {
+                messages.push(message);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(messages.len(), 10);
+        assert_eq!(
+            messages.iter().filter(|message| message.is_err()).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let data = fixture();
+        let messages: Vec<_> = BmpReader::new(&data[..data.len() - 3]).collect();
+        assert_eq!(messages.len(), 10);
+        assert!(matches!(messages[9], Err(BmpError::Truncated)));
+
+        let mut buf = BytesMut::from(&[4, 0, 0, 0, 6, 0][..]);
+        assert!(matches!(
+            BmpMessage::decode_frame(&mut buf),
+            Err(BmpError::Version(4))
+        ));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use tokio::io::AsyncReadExt;
+use tokio::net::{self, TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use super::{BmpError, BmpMessage};
+
+const EVENT_QUEUE: usize = 1024;
+
+type Connections = Arc<Mutex<HashMap<IpAddr, (u64, AbortHandle)>>>;
+
+/// Accepts BMP feeds from routers, see [`PeerManager::add_bmp_station`] to feed the monitored
+/// peers into the usual session pipeline.
+///
+/// Routers are told apart by address. A router connecting again replaces its previous
+/// connection, which is reported as disconnected first.
+///
+/// [`PeerManager::add_bmp_station`]: crate::session::PeerManager::add_bmp_station
+#[derive(Debug)]
+pub struct BmpStation {
+    local_addr: SocketAddr,
+    events: mpsc::Receiver<StationEvent>,
+    post_policy: bool,
+    task: JoinHandle<()>,
+}
+
+/// What happened on the feed of a router
+#[derive(Debug)]
+pub enum StationEvent {
+    Connected(IpAddr),
+    Message(IpAddr, BmpMessage),
+    /// A message that doesn't decode, the feed continues with the next one
+    Error(IpAddr, BmpError),
+    /// The connection closed or broke, or the router connected again
+    Disconnected(IpAddr),
+}
+
+impl BmpStation {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addr = net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+
+        let (events_tx, events) = mpsc::channel(EVENT_QUEUE);
+        let task = tokio::spawn(accept_loop(listener, events_tx));
+        Ok(BmpStation {
+            local_addr,
+            events,
+            post_policy: false,
+            task,
+        })
+    }
+
+    /// Feeds the peers' routes after the router's import policy into the [`PeerManager`]
+    /// rather than as received, for routers that monitor post-policy Adj-RIB-In
+    ///
+    /// [`PeerManager`]: crate::session::PeerManager
+    pub fn post_policy(mut self, post_policy: bool) -> Self {
+        self.post_policy = post_policy;
+        self
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub(crate) fn is_post_policy(&self) -> bool {
+        self.post_policy
+    }
+
+    /// Waits for the next event of any router
+    pub async fn next(&mut self) -> Option<StationEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for BmpStation {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, events: mpsc::Sender<StationEvent>) {
+    let connections: Connections = Arc::default();
+    let mut next_id = 0;
+    while let Ok((stream, remote)) = listener.accept().await {
+        let router = remote.ip().to_canonical();
+        let id = next_id;
+        next_id += 1;
+
+        // Reported before the new connection is, so the router's state is flushed in between
+        let previous = connections.lock().unwrap().remove(&router);
+        if let Some((_, previous)) = previous {
+            previous.abort();
+            if events
+                .send(StationEvent::Disconnected(router))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        // Held until registered, so the feed can't end before it is
+        let mut registered = connections.lock().unwrap();
+        let task = tokio::spawn(read_feed(
+            stream,
+            router,
+            id,
+            connections.clone(),
+            events.clone(),
+        ));
+        registered.insert(router, (id, task.abort_handle()));
+    }
+}
+
+async fn read_feed(
+    mut stream: TcpStream,
+    router: IpAddr,
+    id: u64,
+    connections: Connections,
+    events: mpsc::Sender<StationEvent>,
+) {
+    if events.send(StationEvent::Connected(router)).await.is_err() {
+        return;
+    }
+    let mut buf = BytesMut::with_capacity(64 * 1024);
+    'read: loop {
+        loop {
+            let event = match BmpMessage::decode_frame(&mut buf) {
+                Ok(Some(message)) => StationEvent::Message(router, message),
+                Ok(None) => break,
+                // Without a usable common header the stream can't be followed any further
+                Err(err @ (BmpError::Version(_) | BmpError::Length(_))) => {
+                    let _ = events.send(StationEvent::Error(router, err)).await;
+                    break 'read;
+                }
+                Err(err) => StationEvent::Error(router, err),
+            };
+            if events.send(event).await.is_err() {
+                return;
+            }
+        }
+        match stream.read_buf(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+
+    // A replaced connection was already reported by the accept loop
+    let current = {
+        let mut connections = connections.lock().unwrap();
+        let current = connections
+            .get(&router)
+            .is_some_and(|(current, _)| *current == id);
+        if current {
+            connections.remove(&router);
+        }
+        current
+    };
+    if current {
+        let _ = events.send(StationEvent::Disconnected(router)).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::path::Path;
+
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_reconnect_replaces_connection() {
+        let data =
+            std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bmp_session.bin"))
+                .unwrap();
+        let mut station = BmpStation::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let router = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let mut first = TcpStream::connect(station.local_addr()).await.unwrap();
+        first.write_all(&data[..100]).await.unwrap();
+        assert!(matches!(station.next().await, Some(StationEvent::Connected(r)) if r == router));
+        assert!(matches!(
+            station.next().await,
+            Some(StationEvent::Message(_, BmpMessage::Initiation(_)))
+        ));
+
+        // The rest of the Peer Up never arrives on the replaced connection
+        let mut second = TcpStream::connect(station.local_addr()).await.unwrap();
+        second.write_all(&data).await.unwrap();
+        drop(second);
+        assert!(matches!(station.next().await, Some(StationEvent::Disconnected(r)) if r == router));
+        assert!(matches!(
+            station.next().await,
+            Some(StationEvent::Connected(_))
+        ));
+        let mut messages = 0;
+        let mut errors = 0;
+        loop {
+            match station.next().await.unwrap() {
+                StationEvent::Message(..) => messages += 1,
+                StationEvent::Error(..) => errors += 1,
+                StationEvent::Disconnected(_) => break,
+                event => panic!("unexpected {event:?}"),
+            }
+        }
+        assert_eq!((messages, errors), (9, 1));
+        drop(first);
+    }
+}
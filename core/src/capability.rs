@@ -0,0 +1,285 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::address_family::{Afi, Safi};
+use crate::open_message::OpenParseError;
+use crate::spans;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Capability {
+    MultiProtocol { afi: Afi, safi: Safi },
+    RouteRefresh,
+    FourOctetAs { asn: u32 },
+    AddPath(Vec<AddPathFamily>),
+    EnhancedRouteRefresh,
+    GracefulRestart(GracefulRestart),
+    Unknown { code: u8, value: Bytes },
+}
+
+/// Graceful Restart capability (RFC 4724)
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct GracefulRestart {
+    /// The sender is restarting and its peer shouldn't wait for its End-of-RIB
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub restarting: bool,
+    /// Seconds the peer should keep our routes while the session is re-established, 12 bits
+    pub restart_time: u16,
+    /// Families whose routes are retained across a restart
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub families: Vec<GracefulRestartFamily>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct GracefulRestartFamily {
+    pub afi: Afi,
+    pub safi: Safi,
+    /// Forwarding state was kept across the restart
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub forwarding_preserved: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct AddPathFamily {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub direction: AddPathDirection,
+}
+
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AddPathDirection {
+    Receive = 1,
+    Send = 2,
+    Both = 3,
+}
+
+impl AddPathDirection {
+    pub fn can_send(&self) -> bool {
+        matches!(self, AddPathDirection::Send | AddPathDirection::Both)
+    }
+
+    pub fn can_receive(&self) -> bool {
+        matches!(self, AddPathDirection::Receive | AddPathDirection::Both)
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(AddPathDirection::Receive),
+            2 => Some(AddPathDirection::Send),
+            3 => Some(AddPathDirection::Both),
+            _ => None,
+        }
+    }
+}
+
+impl Capability {
+    pub const MULTI_PROTOCOL: u8 = 1;
+    pub const ROUTE_REFRESH: u8 = 2;
+    pub const GRACEFUL_RESTART: u8 = 64;
+    pub const FOUR_OCTET_AS: u8 = 65;
+    pub const ADD_PATH: u8 = 69;
+    pub const ENHANCED_ROUTE_REFRESH: u8 = 70;
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Capability::MultiProtocol { .. } => Self::MULTI_PROTOCOL,
+            Capability::RouteRefresh => Self::ROUTE_REFRESH,
+            Capability::FourOctetAs { .. } => Self::FOUR_OCTET_AS,
+            Capability::AddPath(_) => Self::ADD_PATH,
+            Capability::EnhancedRouteRefresh => Self::ENHANCED_ROUTE_REFRESH,
+            Capability::GracefulRestart(_) => Self::GRACEFUL_RESTART,
+            Capability::Unknown { code, .. } => *code,
+        }
+    }
+
+    /// Decodes every capability TLV contained in a Capabilities optional parameter value
+    pub fn decode_list(data: &mut Bytes) -> Result<Vec<Self>, OpenParseError> {
+        let mut capabilities = Vec::new();
+
+        while data.has_remaining() {
+            if data.len() < 2 {
+                return Err(OpenParseError::TruncatedCapability);
+            }
+            spans::enter(spans::mark(data), || "capability".into());
+            let code = data.get_u8();
+            spans::consumed(data, 1, || format!("code: {code}"));
+            let length = data.get_u8() as usize;
+            spans::consumed(data, 1, || format!("length: {length}"));
+            if data.len() < length {
+                return Err(OpenParseError::CapabilityLength {
+                    code,
+                    length: length as u8,
+                    remaining: data.len(),
+                });
+            }
+            let mut value = data.copy_to_bytes(length);
+
+            let capability = match code {
+                Self::MULTI_PROTOCOL if length == 4 => {
+                    let afi = Afi::from(value.get_u16());
+                    value.advance(1); // reserved
+                    Capability::MultiProtocol {
+                        afi,
+                        safi: Safi::from(value.get_u8()),
+                    }
+                }
+                Self::ROUTE_REFRESH if length == 0 => Capability::RouteRefresh,
+                Self::ENHANCED_ROUTE_REFRESH if length == 0 => Capability::EnhancedRouteRefresh,
+                Self::FOUR_OCTET_AS if length == 4 => Capability::FourOctetAs {
+                    asn: value.get_u32(),
+                },
+                Self::ADD_PATH if length.is_multiple_of(4) => {
+                    match decode_add_path(&mut value.clone()) {
+                        Some(families) => Capability::AddPath(families),
+                        None => return Err(OpenParseError::AddPath(value)),
+                    }
+                }
+                Self::GRACEFUL_RESTART if length >= 2 && (length - 2).is_multiple_of(4) => {
+                    Capability::GracefulRestart(decode_graceful_restart(&mut value))
+                }
+                _ => Capability::Unknown { code, value },
+            };
+            if length > 0 {
+                spans::consumed(data, length, || format!("{capability:?}"));
+            }
+            spans::leave(data);
+            capabilities.push(capability);
+        }
+
+        Ok(capabilities)
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.code());
+        match self {
+            Capability::MultiProtocol { afi, safi } => {
+                buf.put_u8(4);
+                buf.put_u16((*afi).into());
+                buf.put_u8(0);
+                buf.put_u8((*safi).into());
+            }
+            Capability::RouteRefresh | Capability::EnhancedRouteRefresh => buf.put_u8(0),
+            Capability::FourOctetAs { asn } => {
+                buf.put_u8(4);
+                buf.put_u32(*asn);
+            }
+            Capability::AddPath(families) => {
+                buf.put_u8((families.len() * 4) as u8);
+                for family in families {
+                    buf.put_u16(family.afi.into());
+                    buf.put_u8(family.safi.into());
+                    buf.put_u8(family.direction as u8);
+                }
+            }
+            Capability::GracefulRestart(graceful_restart) => {
+                buf.put_u8((2 + graceful_restart.families.len() * 4) as u8);
+                let restarting = if graceful_restart.restarting {
+                    0x8000
+                } else {
+                    0
+                };
+                buf.put_u16(restarting | graceful_restart.restart_time & 0x0fff);
+                for family in &graceful_restart.families {
+                    buf.put_u16(family.afi.into());
+                    buf.put_u8(family.safi.into());
+                    buf.put_u8(if family.forwarding_preserved { 0x80 } else { 0 });
+                }
+            }
+            Capability::Unknown { value, .. } => {
+                buf.put_u8(value.len() as u8);
+                buf.put_slice(value);
+            }
+        }
+    }
+}
+
+fn decode_add_path(value: &mut Bytes) -> Option<Vec<AddPathFamily>> {
+    let mut families = Vec::with_capacity(value.len() / 4);
+    while value.has_remaining() {
+        families.push(AddPathFamily {
+            afi: Afi::from(value.get_u16()),
+            safi: Safi::from(value.get_u8()),
+            direction: AddPathDirection::from_u8(value.get_u8())?,
+        });
+    }
+    Some(families)
+}
+
+fn decode_graceful_restart(value: &mut Bytes) -> GracefulRestart {
+    let flags_and_time = value.get_u16();
+    let mut families = Vec::with_capacity(value.len() / 4);
+    while value.has_remaining() {
+        families.push(GracefulRestartFamily {
+            afi: Afi::from(value.get_u16()),
+            safi: Safi::from(value.get_u8()),
+            forwarding_preserved: value.get_u8() & 0x80 != 0,
+        });
+    }
+    GracefulRestart {
+        restarting: flags_and_time & 0x8000 != 0,
+        restart_time: flags_and_time & 0x0fff,
+        families,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capability_round_trip() {
+        let capabilities = vec![
+            Capability::MultiProtocol {
+                afi: Afi::Ipv6,
+                safi: Safi::Unicast,
+            },
+            Capability::RouteRefresh,
+            Capability::EnhancedRouteRefresh,
+            Capability::FourOctetAs { asn: 4200000000 },
+            Capability::AddPath(vec![AddPathFamily {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                direction: AddPathDirection::Both,
+            }]),
+            Capability::GracefulRestart(GracefulRestart {
+                restarting: true,
+                restart_time: 120,
+                families: vec![GracefulRestartFamily {
+                    afi: Afi::Ipv4,
+                    safi: Safi::Unicast,
+                    forwarding_preserved: true,
+                }],
+            }),
+            Capability::Unknown {
+                code: 128,
+                value: Bytes::from_static(&[1, 2]),
+            },
+        ];
+
+        let mut buf = BytesMut::new();
+        for capability in &capabilities {
+            capability.encode(&mut buf);
+        }
+
+        let decoded = Capability::decode_list(&mut buf.freeze()).unwrap();
+        assert_eq!(decoded, capabilities);
+    }
+
+    #[test]
+    fn test_capability_truncated() {
+        let mut data = Bytes::from_static(&[65, 4, 0, 0]);
+        assert!(Capability::decode_list(&mut data).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! bzip2 streams: blocks of Huffman coded move-to-front output of a Burrows-Wheeler transform
+//! of run-length encoded input
+
+use std::io::{self, BufRead, Read};
+
+use super::{Huffman, invalid_data, next_byte};
+
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+/// Symbols coded with the same table before the next selector
+const GROUP_SIZE: usize = 50;
+const RUN_A: u16 = 0;
+const RUN_B: u16 = 1;
+
+/// Decompresses a bzip2 file of any number of streams as it's read
+pub struct Bzip2Decoder<R> {
+    bits: Bits<R>,
+    state: State,
+    /// Of the current stream, from its header
+    max_block_len: usize,
+    combined_crc: u32,
+    /// The inverted transform: each entry's low byte is a byte of the block, the rest the
+    /// index of the entry holding the byte after it
+    block: Vec<u32>,
+    next: usize,
+    /// Bytes of the block still to come out of the transform
+    left: usize,
+    expected_crc: u32,
+    crc: u32,
+    /// The last byte written and how many times in a row, the fourth making the next byte
+    /// out of the transform a count of further repeats
+    last: u8,
+    run: u8,
+    repeats: u8,
+}
+
+enum State {
+    Header,
+    Block,
+    Done,
+}
+
+impl<R: BufRead> Bzip2Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        Bzip2Decoder {
+            bits: Bits {
+                reader,
+                buffer: 0,
+                count: 0,
+            },
+            state: State::Header,
+            max_block_len: 0,
+            combined_crc: 0,
+            block: Vec::new(),
+            next: 0,
+            left: 0,
+            expected_crc: 0,
+            crc: 0,
+            last: 0,
+            run: 0,
+            repeats: 0,
+        }
+    }
+
+    /// Reads the header of the next stream, `false` if the input ended before one
+    fn header(&mut self) -> io::Result<bool> {
+        let Some(b) = self.bits.byte_or_end()? else {
+            return Ok(false);
+        };
+        let magic = [b, self.bits.byte()?, self.bits.byte()?];
+        let level = self.bits.byte()?;
+        if &magic != b"BZh" || !(b'1'..=b'9').contains(&level) {
+            return Err(invalid_data("not a bzip2 stream"));
+        }
+        self.max_block_len = (level - b'0') as usize * 100_000;
+        self.combined_crc = 0;
+        Ok(true)
+    }
+
+    /// Reads and inverts the next block, `false` after the end of the stream
+    fn block(&mut self) -> io::Result<bool> {
+        let magic = (self.bits.bits(24)? as u64) << 24 | self.bits.bits(24)? as u64;
+        match magic {
+            BLOCK_MAGIC => {}
+            END_MAGIC => {
+                if self.bits.bits(32)? != self.combined_crc {
+                    return Err(invalid_data("bzip2 stream fails its CRC"));
+                }
+                self.bits.align();
+                return Ok(false);
+            }
+            _ => return Err(invalid_data("invalid bzip2 block magic")),
+        }
+        self.expected_crc = self.bits.bits(32)?;
+        if self.bits.bits(1)? == 1 {
+            return Err(invalid_data("randomized bzip2 blocks aren't supported"));
+        }
+        let origin = self.bits.bits(24)? as usize;
+
+        let symbols = self.symbols_in_use()?;
+        // The move-to-front indexes 1 onwards, shifted up for the two run symbols, and the end
+        let alphabet = symbols.len() + 2;
+        let tables = self.bits.bits(3)? as usize;
+        if !(2..=6).contains(&tables) {
+            return Err(invalid_data("invalid number of bzip2 Huffman tables"));
+        }
+        let selectors = self.selectors(tables)?;
+        let codes = (0..tables)
+            .map(|_| self.code(alphabet))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let end = alphabet as u16 - 1;
+        let mut order: Vec<u8> = (0..symbols.len()).map(|index| index as u8).collect();
+        let mut counts = [0usize; 256];
+        self.block.clear();
+        let mut run = 0;
+        let mut run_weight = 1;
+        for index in 0.. {
+            let Some(&selector) = selectors.get(index / GROUP_SIZE) else {
+                return Err(invalid_data("bzip2 block runs out of selectors"));
+            };
+            let symbol = codes[selector as usize].decode(|| self.bits.bits(1))?;
+            if symbol == RUN_A || symbol == RUN_B {
+                run += run_weight << symbol;
+                run_weight <<= 1;
+                // Before the run is written out, as the runs of a block add up
+                if self.block.len() + run > self.max_block_len {
+                    return Err(too_long());
+                }
+                continue;
+            }
+            if run > 0 {
+                let byte = symbols[order[0] as usize];
+                counts[byte as usize] += run;
+                self.block.extend(std::iter::repeat_n(byte as u32, run));
+                run = 0;
+                run_weight = 1;
+            }
+            if symbol == end {
+                break;
+            }
+            let position = symbol as usize - 1;
+            if position >= order.len() {
+                return Err(invalid_data("bzip2 block refers past its symbols"));
+            }
+            let front = order[position];
+            order.copy_within(..position, 1);
+            order[0] = front;
+            if self.block.len() == self.max_block_len {
+                return Err(too_long());
+            }
+            let byte = symbols[front as usize];
+            counts[byte as usize] += 1;
+            self.block.push(byte as u32);
+        }
+        if origin >= self.block.len() {
+            return Err(invalid_data("bzip2 block starts past its end"));
+        }
+
+        let mut starts = [0usize; 256];
+        let mut total = 0;
+        for (start, count) in starts.iter_mut().zip(counts) {
+            *start = total;
+            total += count;
+        }
+        for index in 0..self.block.len() {
+            let byte = (self.block[index] & 0xff) as usize;
+            self.block[starts[byte]] |= (index as u32) << 8;
+            starts[byte] += 1;
+        }
+        self.next = (self.block[origin] >> 8) as usize;
+        self.left = self.block.len();
+        self.crc = !0;
+        self.run = 0;
+        Ok(true)
+    }
+
+    /// The bytes the block uses, in order
+    fn symbols_in_use(&mut self) -> io::Result<Vec<u8>> {
+        let ranges = self.bits.bits(16)?;
+        let mut symbols = Vec::new();
+        for range in 0..16 {
+            if ranges & (0x8000 >> range) == 0 {
+                continue;
+            }
+            let used = self.bits.bits(16)?;
+            for byte in 0..16 {
+                if used & (0x8000 >> byte) != 0 {
+                    symbols.push((range * 16 + byte) as u8);
+                }
+            }
+        }
+        if symbols.is_empty() {
+            return Err(invalid_data("bzip2 block uses no symbols"));
+        }
+        Ok(symbols)
+    }
+
+    /// Which table codes each group of symbols
+    fn selectors(&mut self, tables: usize) -> io::Result<Vec<u8>> {
+        let count = self.bits.bits(15)?;
+        if count == 0 {
+            return Err(invalid_data("bzip2 block has no selectors"));
+        }
+        let mut order: Vec<u8> = (0..tables as u8).collect();
+        (0..count)
+            .map(|_| {
+                let mut position = 0;
+                while self.bits.bits(1)? == 1 {
+                    position += 1;
+                    if position >= tables {
+                        return Err(invalid_data("invalid bzip2 selector"));
+                    }
+                }
+                let table = order[position];
+                order.copy_within(..position, 1);
+                order[0] = table;
+                Ok(table)
+            })
+            .collect()
+    }
+
+    /// A table's code lengths, each sent as a change from the one before
+    fn code(&mut self, alphabet: usize) -> io::Result<Huffman> {
+        let mut length = self.bits.bits(5)? as u8;
+        let mut lengths = Vec::with_capacity(alphabet);
+        for _ in 0..alphabet {
+            loop {
+                if !(1..=20).contains(&length) {
+                    return Err(invalid_data("invalid bzip2 code length"));
+                }
+                if self.bits.bits(1)? == 0 {
+                    break;
+                }
+                if self.bits.bits(1)? == 0 {
+                    length += 1;
+                } else {
+                    length -= 1;
+                }
+            }
+            lengths.push(length);
+        }
+        Huffman::new(&lengths)
+    }
+
+    /// Checks the CRC of the block just written out
+    fn end_of_block(&mut self) -> io::Result<()> {
+        let crc = !self.crc;
+        if crc != self.expected_crc {
+            return Err(invalid_data("bzip2 block fails its CRC"));
+        }
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ crc;
+        Ok(())
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.crc = (self.crc << 8) ^ CRC_TABLE[((self.crc >> 24) as u8 ^ byte) as usize];
+    }
+}
+
+impl<R: BufRead> Read for Bzip2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let byte = if self.repeats > 0 {
+                self.repeats -= 1;
+                self.last
+            } else if self.left > 0 {
+                let entry = self.block[self.next];
+                self.next = (entry >> 8) as usize;
+                self.left -= 1;
+                let byte = entry as u8;
+                if self.run == 4 {
+                    self.repeats = byte;
+                    self.run = 0;
+                    continue;
+                }
+                if self.run > 0 && byte == self.last {
+                    self.run += 1;
+                } else {
+                    self.last = byte;
+                    self.run = 1;
+                }
+                byte
+            } else {
+                match self.state {
+                    State::Header => {
+                        self.state = if self.header()? {
+                            State::Block
+                        } else {
+                            State::Done
+                        };
+                    }
+                    State::Block => {
+                        if !self.block.is_empty() {
+                            self.end_of_block()?;
+                            self.block.clear();
+                        }
+                        if !self.block()? {
+                            self.state = State::Header;
+                        }
+                    }
+                    State::Done => break,
+                }
+                continue;
+            };
+            self.write(byte);
+            buf[written] = byte;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+/// CRC-32 with the Ethernet polynomial, most significant bit first
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The bits of a bzip2 stream, most significant bit of each byte first
+struct Bits<R> {
+    reader: R,
+    buffer: u64,
+    count: u8,
+}
+
+impl<R: BufRead> Bits<R> {
+    fn bits(&mut self, count: u8) -> io::Result<u32> {
+        while self.count < count {
+            let byte = next_byte(&mut self.reader)?.ok_or_else(truncated)?;
+            self.buffer = (self.buffer << 8) | byte as u64;
+            self.count += 8;
+        }
+        self.count -= count;
+        Ok(((self.buffer >> self.count) & ((1 << count) - 1)) as u32)
+    }
+
+    /// Drops the bits left of the current byte, as streams end on a byte boundary
+    fn align(&mut self) {
+        self.count -= self.count % 8;
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.byte_or_end()?.ok_or_else(truncated)
+    }
+
+    /// A whole byte, once aligned
+    fn byte_or_end(&mut self) -> io::Result<Option<u8>> {
+        if self.count >= 8 {
+            return Ok(Some(self.bits(8)? as u8));
+        }
+        next_byte(&mut self.reader)
+    }
+}
+
+fn too_long() -> io::Error {
+    invalid_data("bzip2 block is longer than its stream allows")
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "bzip2 stream is truncated")
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+
+    fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Bzip2Decoder::new(Cursor::new(bytes)).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_streams() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/as_paths.txt");
+        let text = fs::read(path).unwrap();
+        // Two streams, the second with a run long enough to be counted rather than repeated
+        let compressed = fs::read(format!("{path}.bz2")).unwrap();
+        let mut expected = text.clone();
+        expected.extend(&text);
+        expected.extend([b'-'; 300]);
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+
+        let mut decoder = Bzip2Decoder::new(BufReader::with_capacity(1, Cursor::new(&compressed)));
+        let mut out = Vec::new();
+        let mut byte = [0];
+        while decoder.read(&mut byte).unwrap() == 1 {
+            out.push(byte[0]);
+        }
+        assert_eq!(out, expected);
+
+        // The combined CRC of the last stream
+        let mut damaged = compressed.clone();
+        let len = damaged.len();
+        damaged[len - 2] ^= 1;
+        assert_eq!(
+            decompress(&damaged).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let error = decompress(&compressed[..len - 8]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Writes bits most significant first, as [`Bits`] reads them
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        count: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, count: u8, value: u64) {
+            for bit in (0..count).rev() {
+                if self.count.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let last = self.bytes.last_mut().unwrap();
+                *last |= ((value >> bit) as u8 & 1) << (7 - self.count % 8);
+                self.count += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_runs_add_up_past_block_len() {
+        let mut stream = BitWriter::default();
+        stream.bytes.extend(b"BZh1");
+        stream.bits(48, BLOCK_MAGIC);
+        stream.bits(32, 0);
+        stream.bits(1, 0);
+        stream.bits(24, 0);
+        // Bytes 'a' and 'b'
+        stream.bits(16, 0x0200);
+        stream.bits(16, 0x6000);
+        // Two tables, every group coded with the first
+        stream.bits(3, 2);
+        stream.bits(15, 100);
+        for _ in 0..100 {
+            stream.bits(1, 0);
+        }
+        // RUN_A, RUN_B, the second symbol to front and the end, all 2 bits long
+        for _ in 0..2 {
+            stream.bits(5, 2);
+            stream.bits(4, 0);
+        }
+        // Runs of the whole block length, in bijective base 2, each swapping the front byte
+        for _ in 0..100 {
+            let mut run = 100_000;
+            while run > 0 {
+                let symbol = if run % 2 == 1 { RUN_A } else { RUN_B };
+                stream.bits(2, symbol as u64);
+                run = (run - 1 - symbol as usize) / 2;
+            }
+            stream.bits(2, 2);
+        }
+
+        let error = decompress(&stream.bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "bzip2 block is longer than its stream allows"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! gzip members (RFC 1952) holding DEFLATE streams (RFC 1951)
+
+use std::io::{self, BufRead, Read};
+
+use super::{Huffman, invalid_data, next_byte};
+use crate::journal::crc32_update;
+
+/// How far back a match may reach
+const WINDOW: usize = 32 * 1024;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Base lengths of the length symbols 257 onwards, and their extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order the lengths of the code length code are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a gzip file of any number of members as it's read
+pub struct GzipDecoder<R> {
+    bits: Bits<R>,
+    state: State,
+    /// Whether the block being read is the last of its member
+    last: bool,
+    /// The literal/length and distance codes of the current block
+    codes: Option<(Huffman, Huffman)>,
+    /// The last [`WINDOW`] bytes written, `position` being where the next goes
+    window: Box<[u8]>,
+    position: usize,
+    /// Bytes written by the current member, as far as matches can reach back
+    filled: usize,
+    /// The length and distance of the match being copied out
+    copy: (usize, usize),
+    /// Of the current member's output so far
+    crc: u32,
+    size: u32,
+}
+
+enum State {
+    Header,
+    Block,
+    /// The bytes of a stored block still to be copied
+    Stored(u16),
+    Codes,
+    Done,
+}
+
+enum Symbol {
+    Literal(u8),
+    /// Copies `length` bytes from `distance` back
+    Match(usize, usize),
+    End,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        GzipDecoder {
+            bits: Bits {
+                reader,
+                buffer: 0,
+                count: 0,
+            },
+            state: State::Header,
+            last: false,
+            codes: None,
+            window: vec![0; WINDOW].into_boxed_slice(),
+            position: 0,
+            filled: 0,
+            copy: (0, 0),
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Reads the header of the next member, `false` if the input ended before one
+    fn header(&mut self) -> io::Result<bool> {
+        let Some(id1) = self.bits.byte_or_end()? else {
+            return Ok(false);
+        };
+        let mut fixed = [0; 9];
+        for byte in &mut fixed {
+            *byte = self.bits.byte()?;
+        }
+        if [id1, fixed[0]] != [0x1f, 0x8b] {
+            return Err(invalid_data("not a gzip member"));
+        }
+        if fixed[1] != 8 {
+            return Err(invalid_data("gzip member isn't deflated"));
+        }
+
+        let flags = fixed[2];
+        if flags & FEXTRA != 0 {
+            let len = u16::from_le_bytes([self.bits.byte()?, self.bits.byte()?]);
+            for _ in 0..len {
+                self.bits.byte()?;
+            }
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while self.bits.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            self.bits.byte()?;
+            self.bits.byte()?;
+        }
+
+        self.last = false;
+        self.filled = 0;
+        self.crc = 0;
+        self.size = 0;
+        Ok(true)
+    }
+
+    /// Checks the CRC-32 and length that end a member against what it decompressed to
+    fn trailer(&mut self) -> io::Result<()> {
+        self.bits.align();
+        let mut trailer = [0; 8];
+        for byte in &mut trailer {
+            *byte = self.bits.byte()?;
+        }
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != self.crc || size != self.size {
+            return Err(invalid_data("gzip member fails its CRC-32 or length check"));
+        }
+        Ok(())
+    }
+
+    /// Reads the header of a block, and its codes unless it's stored
+    fn block(&mut self) -> io::Result<State> {
+        self.last = self.bits.bits(1)? == 1;
+        match self.bits.bits(2)? {
+            0 => {
+                self.bits.align();
+                let len = u16::from_le_bytes([self.bits.byte()?, self.bits.byte()?]);
+                let complement = u16::from_le_bytes([self.bits.byte()?, self.bits.byte()?]);
+                if len != !complement {
+                    return Err(invalid_data(
+                        "stored block length doesn't match its complement",
+                    ));
+                }
+                Ok(State::Stored(len))
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                self.codes = Some((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?));
+                Ok(State::Codes)
+            }
+            2 => {
+                self.codes = Some(self.dynamic_codes()?);
+                Ok(State::Codes)
+            }
+            _ => Err(invalid_data("invalid deflate block type")),
+        }
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let literals = self.bits.bits(5)? as usize + 257;
+        let distances = self.bits.bits(5)? as usize + 1;
+        let code_lengths = self.bits.bits(4)? as usize + 4;
+        if literals > 286 || distances > 30 {
+            return Err(invalid_data("too many deflate codes"));
+        }
+
+        let mut lengths = [0; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = self.bits.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = code_length_code.decode(|| self.bits.bits(1))?;
+            let (length, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    let Some(index) = index.checked_sub(1) else {
+                        return Err(invalid_data(
+                            "deflate code repeats a length before the first",
+                        ));
+                    };
+                    (lengths[index], 3 + self.bits.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits.bits(3)? as usize),
+                _ => (0, 11 + self.bits.bits(7)? as usize),
+            };
+            if index + repeat > lengths.len() {
+                return Err(invalid_data("deflate code lengths overrun their count"));
+            }
+            lengths[index..index + repeat].fill(length);
+            index += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid_data("deflate block has no end code"));
+        }
+        Ok((
+            Huffman::new(&lengths[..literals])?,
+            Huffman::new(&lengths[literals..])?,
+        ))
+    }
+
+    fn symbol(&mut self) -> io::Result<Symbol> {
+        let (literal, distance) = self.codes.as_ref().expect("codes are read with the block");
+        let symbol = literal.decode(|| self.bits.bits(1))? as usize;
+        if symbol < 256 {
+            return Ok(Symbol::Literal(symbol as u8));
+        }
+        if symbol == 256 {
+            return Ok(Symbol::End);
+        }
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(invalid_data("invalid deflate length symbol"));
+        }
+        let length = LENGTH_BASE[index] as usize + self.bits.bits(LENGTH_EXTRA[index])? as usize;
+        let index = distance.decode(|| self.bits.bits(1))? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(invalid_data("invalid deflate distance symbol"));
+        }
+        let distance =
+            DISTANCE_BASE[index] as usize + self.bits.bits(DISTANCE_EXTRA[index])? as usize;
+        if distance > self.filled {
+            return Err(invalid_data("deflate match reaches back before the start"));
+        }
+        Ok(Symbol::Match(length, distance))
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        // Where the CRC-32 of the current member has got to in `buf`
+        let mut checked = 0;
+        while written < buf.len() {
+            let byte = if self.copy.0 > 0 {
+                self.copy.0 -= 1;
+                self.window[(self.position + WINDOW - self.copy.1) % WINDOW]
+            } else {
+                match self.state {
+                    State::Header => {
+                        self.state = if self.header()? {
+                            State::Block
+                        } else {
+                            State::Done
+                        };
+                        continue;
+                    }
+                    State::Block => {
+                        self.state = self.block()?;
+                        continue;
+                    }
+                    State::Stored(0) => {
+                        self.state = self.end_of_block(&buf[checked..written])?;
+                        checked = written;
+                        continue;
+                    }
+                    State::Stored(ref mut left) => {
+                        *left -= 1;
+                        self.bits.byte()?
+                    }
+                    State::Codes => match self.symbol()? {
+                        Symbol::Literal(literal) => literal,
+                        Symbol::Match(length, distance) => {
+                            self.copy = (length, distance);
+                            continue;
+                        }
+                        Symbol::End => {
+                            self.codes = None;
+                            self.state = self.end_of_block(&buf[checked..written])?;
+                            checked = written;
+                            continue;
+                        }
+                    },
+                    State::Done => break,
+                }
+            };
+            buf[written] = byte;
+            written += 1;
+            self.window[self.position] = byte;
+            self.position = (self.position + 1) % WINDOW;
+            self.filled = (self.filled + 1).min(WINDOW);
+        }
+        self.crc = crc32_update(self.crc, &buf[checked..written]);
+        self.size = self.size.wrapping_add((written - checked) as u32);
+        Ok(written)
+    }
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    /// Moves on to the next block, or past the trailer after the last; `unchecked` is the
+    /// output not yet counted into the CRC-32
+    fn end_of_block(&mut self, unchecked: &[u8]) -> io::Result<State> {
+        self.crc = crc32_update(self.crc, unchecked);
+        self.size = self.size.wrapping_add(unchecked.len() as u32);
+        if !self.last {
+            return Ok(State::Block);
+        }
+        self.trailer()?;
+        Ok(State::Header)
+    }
+}
+
+/// The bits of a DEFLATE stream, least significant bit of each byte first
+struct Bits<R> {
+    reader: R,
+    buffer: u64,
+    count: u8,
+}
+
+impl<R: BufRead> Bits<R> {
+    fn bits(&mut self, count: u8) -> io::Result<u32> {
+        while self.count < count {
+            let byte = next_byte(&mut self.reader)?.ok_or_else(truncated)?;
+            self.buffer |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+        let bits = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(bits as u32)
+    }
+
+    /// Drops the bits left of the current byte
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.buffer >>= partial;
+        self.count -= partial;
+    }
+
+    /// A whole byte, once aligned
+    fn byte(&mut self) -> io::Result<u8> {
+        self.byte_or_end()?.ok_or_else(truncated)
+    }
+
+    fn byte_or_end(&mut self) -> io::Result<Option<u8>> {
+        if self.count >= 8 {
+            return Ok(Some(self.bits(8)? as u8));
+        }
+        next_byte(&mut self.reader)
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "gzip stream is truncated")
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+
+    fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzipDecoder::new(Cursor::new(bytes)).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_members() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/as_paths.txt");
+        let text = fs::read(path).unwrap();
+        // A stored member followed by a compressed one
+        let compressed = fs::read(format!("{path}.gz")).unwrap();
+        let mut expected = text.clone();
+        expected.extend(&text);
+        assert_eq!(decompress(compressed.clone()).unwrap(), expected);
+
+        // Read a byte at a time to cross every boundary mid-read
+        let mut decoder = GzipDecoder::new(BufReader::with_capacity(1, Cursor::new(&compressed)));
+        let mut out = Vec::new();
+        let mut byte = [0];
+        while decoder.read(&mut byte).unwrap() == 1 {
+            out.push(byte[0]);
+        }
+        assert_eq!(out, expected);
+
+        let mut damaged = compressed.clone();
+        let len = damaged.len();
+        damaged[len - 5] ^= 1;
+        assert_eq!(
+            decompress(damaged).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let error = decompress(compressed[..len - 3].to_vec()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,184 @@
+//! Reading gzip and bzip2 compressed files as if they weren't, as collectors archive their MRT
+//! dumps compressed.
+//!
+//! [`Decompress`] tells the format from the first bytes of its input. Without the
+//! `compression` feature it still recognizes both, but refuses them instead of handing out the
+//! compressed bytes. The decoders stream: a gzip member needs its 32 KiB window, a bzip2 block
+//! at most 900 000 entries of four bytes.
+
+#[cfg(feature = "compression")]
+mod bzip2;
+#[cfg(feature = "compression")]
+mod gzip;
+
+use std::io::{self, BufRead, Read};
+
+#[cfg(feature = "compression")]
+pub use bzip2::Bzip2Decoder;
+#[cfg(feature = "compression")]
+pub use gzip::GzipDecoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// The bytes of a plain, gzipped or bzipped input, decompressed
+pub enum Decompress<R> {
+    Plain(R),
+    #[cfg(feature = "compression")]
+    Gzip(GzipDecoder<R>),
+    #[cfg(feature = "compression")]
+    Bzip2(Bzip2Decoder<R>),
+}
+
+impl<R: BufRead> Decompress<R> {
+    /// Looks at the start of `reader` without consuming it to pick the decoder
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let start = reader.fill_buf()?;
+        let format = if start.starts_with(GZIP_MAGIC) {
+            "gzip"
+        } else if start.starts_with(BZIP2_MAGIC) {
+            "bzip2"
+        } else {
+            return Ok(Decompress::Plain(reader));
+        };
+
+        #[cfg(feature = "compression")]
+        {
+            Ok(match format {
+                "gzip" => Decompress::Gzip(GzipDecoder::new(reader)),
+                _ => Decompress::Bzip2(Bzip2Decoder::new(reader)),
+            })
+        }
+        #[cfg(not(feature = "compression"))]
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{format} compressed input needs the compression feature"),
+        ))
+    }
+}
+
+impl<R: BufRead> Read for Decompress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decompress::Plain(reader) => reader.read(buf),
+            #[cfg(feature = "compression")]
+            Decompress::Gzip(decoder) => decoder.read(buf),
+            #[cfg(feature = "compression")]
+            Decompress::Bzip2(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Decodes canonical Huffman codes a bit at a time, the first bit read being the most
+/// significant of the code, which is how both DEFLATE and bzip2 lay them out
+#[cfg(feature = "compression")]
+struct Huffman {
+    /// Codes of each length
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+#[cfg(feature = "compression")]
+const MAX_CODE_LEN: usize = 20;
+
+#[cfg(feature = "compression")]
+impl Huffman {
+    /// The code giving each symbol the length at its index, 0 for an unused symbol
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_CODE_LEN + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LEN + 2];
+        for length in 1..=MAX_CODE_LEN {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_LEN + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, mut bit: impl FnMut() -> io::Result<u32>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+#[cfg(feature = "compression")]
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The next byte of `reader`, `None` at its end
+#[cfg(feature = "compression")]
+fn next_byte(reader: &mut impl BufRead) -> io::Result<Option<u8>> {
+    let Some(&byte) = reader.fill_buf()?.first() else {
+        return Ok(None);
+    };
+    reader.consume(1);
+    Ok(Some(byte))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_plain_passes_through() {
+        let mut decompress = Decompress::new(Cursor::new(b"MRT bytes".to_vec())).unwrap();
+        let mut out = Vec::new();
+        decompress.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"MRT bytes");
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn test_refuses_compressed() {
+        for start in [&[0x1f, 0x8b, 8][..], b"BZh9"] {
+            let error = Decompress::new(Cursor::new(start.to_vec())).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_huffman() {
+        // A: 0, B: 10, C: 110, D: 111
+        let huffman = Huffman::new(&[1, 2, 3, 3]).unwrap();
+        let mut bits = [1, 1, 0, 1, 0, 0, 1, 1, 1].into_iter();
+        let mut decoded = Vec::new();
+        while bits.len() > 0 {
+            decoded.push(huffman.decode(|| Ok(bits.next().unwrap())).unwrap());
+        }
+        assert_eq!(decoded, [2, 1, 0, 3]);
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Names for ASNs and routing data for prefixes, so output reads `AS15169 (GOOGLE - Google
+//! LLC, US)` rather than a bare number.
+//!
+//! An [`Enricher`] answers from what it holds in memory, lookups never wait on the network.
+//! [`FileEnricher`] is loaded from the usual flat files: an `asn.txt` of an ASN and its name
+//! per line, as the RIPE NCC publishes it, and a CAIDA `pfx2as` file of the origins of routed
+//! prefixes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::asn::Asn;
+use crate::trie::PrefixTrie;
+use crate::update_message::IpAddrPrefix;
+
+/// Looks up what's known about ASNs and prefixes, without blocking
+pub trait Enricher: fmt::Debug + Send + Sync {
+    fn asn_name(&self, asn: u32) -> Option<String>;
+
+    /// The most specific routed prefix covering `prefix`, with its origins
+    fn prefix_info(&self, prefix: &IpAddrPrefix) -> Option<PrefixInfo>;
+
+    /// `AS15169 (GOOGLE)`, or `AS15169` without a name
+    fn describe_asn(&self, asn: u32) -> String {
+        match self.asn_name(asn) {
+            Some(name) => format!("AS{asn} ({name})"),
+            None => format!("AS{asn}"),
+        }
+    }
+}
+
+/// A routed prefix and the ASes originating it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixInfo {
+    pub prefix: IpAddrPrefix,
+    /// In the order listed, the members of AS sets included
+    pub origins: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {reason}")]
+pub struct EnrichmentFileError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// An [`Enricher`] of names and prefixes loaded from files
+#[derive(Debug, Clone, Default)]
+pub struct FileEnricher {
+    names: HashMap<u32, String>,
+    prefixes: PrefixTrie<Vec<u32>>,
+}
+
+impl FileEnricher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the names of an `asn.txt`, lines of an ASN, optionally `AS` prefixed, and its name
+    /// separated by spaces or a tab, and returns how many were added. Blank lines and `#`
+    /// comments are skipped, and nothing is added when a line fails to parse.
+    pub fn load_asn_names(&mut self, text: &str) -> Result<usize, EnrichmentFileError> {
+        let mut names = vec![];
+        for (line, asn, rest) in fields(text) {
+            let error = |reason| EnrichmentFileError { line, reason };
+            let asn = asn.strip_prefix("AS").unwrap_or(asn);
+            let Ok(Asn(asn)) = asn.parse() else {
+                return Err(error("invalid ASN"));
+            };
+            if rest.is_empty() {
+                return Err(error("missing name"));
+            }
+            names.push((asn, rest.to_owned()));
+        }
+        let added = names.len();
+        self.names.extend(names);
+        Ok(added)
+    }
+
+    /// Adds the prefixes of a CAIDA `pfx2as` file and returns how many lines were added.
+    ///
+    /// Lines hold an address, a prefix length and the origins, separated by tabs or spaces.
+    /// Prefixes originated by several ASes list them separated by `_`, and the members of an
+    /// AS set by `,`. Prefixes listed again gain the origins. Blank lines and `#` comments are
+    /// skipped, and nothing is added when a line fails to parse.
+    pub fn load_pfx2as(&mut self, text: &str) -> Result<usize, EnrichmentFileError> {
+        let mut prefixes = vec![];
+        for (line, addr, rest) in fields(text) {
+            let error = |reason| EnrichmentFileError { line, reason };
+            let (length, origins) = match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                [length, origins] => (length, origins),
+                _ => return Err(error("expected address, length and origins")),
+            };
+            let prefix = addr
+                .parse::<IpAddr>()
+                .ok()
+                .zip(length.parse().ok())
+                .and_then(|(addr, length)| IpAddrPrefix::new(addr, length))
+                .ok_or_else(|| error("invalid prefix"))?;
+            let origins = origins
+                .split(['_', ','])
+                .map(|origin| origin.parse().map_err(|_| error("invalid origin AS")))
+                .collect::<Result<Vec<u32>, _>>()?;
+            prefixes.push((prefix, origins));
+        }
+        let added = prefixes.len();
+        for (prefix, origins) in prefixes {
+            match self.prefixes.get_mut(&prefix) {
+                Some(known) => {
+                    for origin in origins {
+                        if !known.contains(&origin) {
+                            known.push(origin);
+                        }
+                    }
+                }
+                None => {
+                    self.prefixes.insert(&prefix, origins);
+                }
+            }
+        }
+        Ok(added)
+    }
+}
+
+impl Enricher for FileEnricher {
+    fn asn_name(&self, asn: u32) -> Option<String> {
+        self.names.get(&asn).cloned()
+    }
+
+    fn prefix_info(&self, prefix: &IpAddrPrefix) -> Option<PrefixInfo> {
+        let (prefix, origins) = self.prefixes.covering(prefix).last()?;
+        Some(PrefixInfo {
+            prefix,
+            origins: origins.clone(),
+        })
+    }
+}
+
+/// The line number, first field and the trimmed rest of each line that isn't blank or a
+/// comment, whatever the line endings
+fn fields(text: &str) -> impl Iterator<Item = (usize, &str, &str)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (first, rest) = line.split_once([' ', '\t']).unwrap_or((line, ""));
+        Some((i + 1, first, rest.trim()))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn enricher() -> FileEnricher {
+        let mut enricher = FileEnricher::new();
+        assert_eq!(enricher.load_asn_names(&fixture("asn.txt")), Ok(5));
+        assert_eq!(enricher.load_pfx2as(&fixture("pfx2as.txt")), Ok(6));
+        enricher
+    }
+
+    fn prefix(prefix: &str) -> IpAddrPrefix {
+        prefix.parse().unwrap()
+    }
+
+    #[test]
+    fn test_asn_names() {
+        let enricher = enricher();
+        assert_eq!(
+            enricher.asn_name(15169).as_deref(),
+            Some("GOOGLE - Google LLC, US")
+        );
+        // `AS` prefixed and tab separated
+        assert_eq!(
+            enricher.asn_name(3356).as_deref(),
+            Some("LEVEL3 - Level 3 Parent, LLC, US")
+        );
+        assert_eq!(enricher.asn_name(4_200_000_000).as_deref(), Some("PRIVATE"));
+        assert_eq!(enricher.asn_name(64501), None);
+        assert_eq!(
+            enricher.describe_asn(64500),
+            "AS64500 (EXAMPLE-NET, ZZ)".to_string()
+        );
+        assert_eq!(enricher.describe_asn(64501), "AS64501");
+    }
+
+    #[test]
+    fn test_prefix_info() {
+        let enricher = enricher();
+        let info = |p: &str| {
+            enricher
+                .prefix_info(&prefix(p))
+                .map(|info| (info.prefix.to_string(), info.origins))
+        };
+        assert_eq!(info("8.8.8.0/24"), Some(("8.8.8.0/24".into(), vec![15169])));
+        // The most specific covering prefix
+        assert_eq!(info("8.8.4.0/24"), Some(("8.0.0.0/9".into(), vec![3356])));
+        assert_eq!(info("8.0.0.0/8"), None);
+        // Windows line endings
+        assert_eq!(
+            info("1.0.0.128/25"),
+            Some(("1.0.0.0/24".into(), vec![13335]))
+        );
+        // Multi-origin, and an AS set among them on a space separated line
+        assert_eq!(
+            info("192.0.2.0/24"),
+            Some(("192.0.2.0/24".into(), vec![64500, 64501]))
+        );
+        assert_eq!(
+            info("198.51.100.0/24"),
+            Some(("198.51.100.0/24".into(), vec![64500, 64502, 64503]))
+        );
+        assert_eq!(
+            info("2001:4860:4860::/48"),
+            Some(("2001:4860::/32".into(), vec![15169]))
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut enricher = FileEnricher::new();
+        assert_eq!(enricher.load_pfx2as("192.0.2.0\t24\t64500\n"), Ok(1));
+        // Listed again with another origin
+        assert_eq!(enricher.load_pfx2as("192.0.2.0 24 64501_64500"), Ok(1));
+        assert_eq!(
+            enricher
+                .prefix_info(&prefix("192.0.2.0/24"))
+                .unwrap()
+                .origins,
+            [64500, 64501]
+        );
+
+        for (text, reason) in [
+            ("10.0.0.0\t8", "expected address, length and origins"),
+            ("10.0.0.0\t33\t64500", "invalid prefix"),
+            ("10.0.0.0\t8\t64500_", "invalid origin AS"),
+            ("x\t8\t64500", "invalid prefix"),
+        ] {
+            let text = format!("# header\n{text}\n203.0.113.0\t24\t64500");
+            assert_eq!(
+                enricher.load_pfx2as(&text),
+                Err(EnrichmentFileError { line: 2, reason })
+            );
+        }
+        assert!(enricher.prefix_info(&prefix("203.0.113.0/24")).is_none());
+
+        assert_eq!(
+            enricher.load_asn_names("64500 EXAMPLE\nAS64501"),
+            Err(EnrichmentFileError {
+                line: 2,
+                reason: "missing name"
+            })
+        );
+        assert_eq!(
+            enricher.load_asn_names("EXAMPLE 64500"),
+            Err(EnrichmentFileError {
+                line: 1,
+                reason: "invalid ASN"
+            })
+        );
+        assert_eq!(enricher.asn_name(64500), None);
+    }
+}
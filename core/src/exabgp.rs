@@ -0,0 +1,691 @@
+//! Conversion of UPDATEs to and from exabgp's JSON API, as of exabgp 4.
+//!
+//! The encoding differs between exabgp releases; this follows the 4.x one, where an update
+//! received from a neighbor reads
+//!
+//! ```text
+//! {"exabgp": "4.0.1", "type": "update",
+//!  "neighbor": {"address": {"peer": "192.0.2.1"}, "asn": {"peer": 64500},
+//!   "direction": "receive", "message": {"update": {
+//!     "attribute": {"origin": "igp", "as-path": [64500, [64501, 64502]],
+//!       "confederation-path": [], "med": 10, "local-preference": 100,
+//!       "community": [[64500, 1]]},
+//!     "announce": {"ipv4 unicast": {"192.0.2.1": [{"nlri": "203.0.113.0/24"}]}},
+//!     "withdraw": {"ipv6 unicast": [{"nlri": "2001:db8::/32"}]}}}}}
+//! ```
+//!
+//! AS_SETs are nested arrays in `as-path` and `confederation-path`, attributes without a
+//! name of their own are `"attribute-0x<type>-0x<flags>": "0x<value>"`, and an End-of-RIB
+//! marker is `"update": {"eor": {"afi": "ipv4", "safi": "unicast"}}`. The `time`, `host`,
+//! `pid`, `ppid` and `counter` members and the local address and ASN aren't known here and
+//! are left out; exabgp's own tooling doesn't need them.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use bytes::Bytes;
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{
+    Aggregator, AsPath, AsPathSegment, AsPathSegmentType, AttributeType, AttributeValue, Community,
+    OriginType, PathAttribute, PathAttributeFlags,
+};
+use crate::json::Json;
+use crate::peer::PeerInfo;
+use crate::update_message::{IpAddrPrefix, UpdateMessage, UpdateMessageBuilder};
+
+/// The exabgp release whose encoding is produced
+pub const EXABGP_VERSION: &str = "4.0.1";
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ExabgpError {
+    #[error("missing {0}")]
+    Missing(&'static str),
+    #[error("invalid {field}: {value}")]
+    Invalid { field: String, value: String },
+    #[error("unsupported family {0:?}")]
+    Family(String),
+    #[error("more than one next hop for {0}")]
+    NextHops(String),
+    #[error("unsupported attribute {0:?}")]
+    Attribute(String),
+}
+
+/// An UPDATE received from `peer` as exabgp would report it
+pub fn to_exabgp_json(peer: &PeerInfo, update: &UpdateMessage) -> Json {
+    let message = match update.end_of_rib_family() {
+        Some((afi, safi)) => vec![(
+            "eor".to_owned(),
+            Json::object([
+                ("afi", Json::from(afi_name(afi))),
+                ("safi", Json::from(safi_name(safi))),
+            ]),
+        )],
+        None => update_json(update),
+    };
+    Json::object([
+        ("exabgp", Json::from(EXABGP_VERSION)),
+        ("type", Json::from("update")),
+        (
+            "neighbor",
+            Json::object([
+                (
+                    "address",
+                    Json::object([("peer", Json::from(peer.peer_addr.ip().to_string()))]),
+                ),
+                ("asn", Json::object([("peer", Json::from(peer.asn))])),
+                ("direction", Json::from("receive")),
+                ("message", Json::object([("update", Json::Object(message))])),
+            ]),
+        ),
+    ])
+}
+
+/// The `attribute` member of an UPDATE with `attributes`: those other than the next hop
+/// and NLRI
+#[cfg(any(feature = "sqlite", feature = "ws"))]
+pub(crate) fn attributes_json(attributes: &[PathAttribute]) -> Json {
+    let update = UpdateMessage {
+        withdrawn_routes: vec![],
+        path_attributes: attributes.to_vec(),
+        nlri: vec![],
+    };
+    update_json(&update)
+        .into_iter()
+        .find_map(|(member, json)| (member == "attribute").then_some(json))
+        .unwrap_or(Json::Object(vec![]))
+}
+
+fn update_json(update: &UpdateMessage) -> Vec<(String, Json)> {
+    let mut attributes: Vec<_> = update.path_attributes.iter().collect();
+    attributes.sort_by_key(|attribute| u8::from(&attribute.type_code));
+
+    let mut attribute = vec![];
+    let mut next_hop = None;
+    let mut announce = vec![];
+    let mut withdraw = vec![];
+    for PathAttribute {
+        flags,
+        type_code,
+        value,
+    } in attributes
+    {
+        match value {
+            AttributeValue::Origin(origin) => attribute.push((
+                "origin".to_owned(),
+                Json::from(match origin.origin_type {
+                    OriginType::Igp => "igp",
+                    OriginType::Egp => "egp",
+                    OriginType::Incomplete => "incomplete",
+                }),
+            )),
+            AttributeValue::AsPath(as_path) => {
+                let (confederation, path): (Vec<_>, Vec<_>) =
+                    as_path.segments.iter().partition(|segment| {
+                        matches!(
+                            segment.segment_type,
+                            AsPathSegmentType::AsConfedSequence | AsPathSegmentType::AsConfedSet
+                        )
+                    });
+                attribute.push(("as-path".to_owned(), path_json(&path)));
+                attribute.push(("confederation-path".to_owned(), path_json(&confederation)));
+            }
+            AttributeValue::NextHop(hop) => next_hop = Some(IpAddr::V4(hop.ip)),
+            AttributeValue::MultiExitDisc(med) => {
+                attribute.push(("med".to_owned(), Json::from(med.med)))
+            }
+            AttributeValue::LocalPref(pref) => {
+                attribute.push(("local-preference".to_owned(), Json::from(pref.pref)))
+            }
+            AttributeValue::AtomicAggregate => {
+                attribute.push(("atomic-aggregate".to_owned(), Json::Bool(true)))
+            }
+            AttributeValue::Aggregator(aggregator) => attribute.push((
+                "aggregator".to_owned(),
+                Json::from(format!("{}:{}", aggregator.asn, aggregator.ip)),
+            )),
+            AttributeValue::Communities(communities) => attribute.push((
+                "community".to_owned(),
+                Json::Array(
+                    communities
+                        .communities
+                        .iter()
+                        .map(|c| {
+                            Json::from(vec![u32::from(c.asn).into(), u32::from(c.value).into()])
+                        })
+                        .collect(),
+                ),
+            )),
+            AttributeValue::MpReachNlri(mp_reach) if !mp_reach.nlri.is_empty() => announce.push((
+                family_name(mp_reach.afi, mp_reach.safi),
+                Json::object([(mp_reach.next_hop.to_string(), nlri_json(&mp_reach.nlri))]),
+            )),
+            AttributeValue::MpUnreachNlri(mp_unreach)
+                if !mp_unreach.withdrawn_routes.is_empty() =>
+            {
+                withdraw.push((
+                    family_name(mp_unreach.afi, mp_unreach.safi),
+                    nlri_json(&mp_unreach.withdrawn_routes),
+                ))
+            }
+            AttributeValue::MpReachNlri(_) | AttributeValue::MpUnreachNlri(_) => {}
+            AttributeValue::Unknown(value) => attribute.push((
+                format!(
+                    "attribute-0x{:02X}-0x{:02X}",
+                    u8::from(type_code),
+                    flags.to_byte()
+                ),
+                Json::from(format!("0x{}", hex(value))),
+            )),
+        }
+    }
+
+    if !update.nlri.is_empty() {
+        let next_hop = next_hop.map_or_else(|| "null".to_owned(), |hop| hop.to_string());
+        announce.insert(
+            0,
+            (
+                family_name(Afi::Ipv4, Safi::Unicast),
+                Json::object([(next_hop, nlri_json(&update.nlri))]),
+            ),
+        );
+    }
+    if !update.withdrawn_routes.is_empty() {
+        withdraw.insert(
+            0,
+            (
+                family_name(Afi::Ipv4, Safi::Unicast),
+                nlri_json(&update.withdrawn_routes),
+            ),
+        );
+    }
+
+    let mut message = vec![];
+    if !attribute.is_empty() {
+        message.push(("attribute".to_owned(), Json::Object(attribute)));
+    }
+    if !announce.is_empty() {
+        message.push(("announce".to_owned(), Json::Object(announce)));
+    }
+    if !withdraw.is_empty() {
+        message.push(("withdraw".to_owned(), Json::Object(withdraw)));
+    }
+    message
+}
+
+fn path_json(segments: &[&AsPathSegment]) -> Json {
+    let mut path = vec![];
+    for segment in segments {
+        let asns = segment.asns.iter().map(|&asn| Json::from(asn));
+        match segment.segment_type {
+            AsPathSegmentType::AsSequence | AsPathSegmentType::AsConfedSequence => {
+                path.extend(asns)
+            }
+            AsPathSegmentType::AsSet | AsPathSegmentType::AsConfedSet => {
+                path.push(Json::Array(asns.collect()))
+            }
+        }
+    }
+    Json::Array(path)
+}
+
+fn nlri_json(prefixes: &[IpAddrPrefix]) -> Json {
+    Json::Array(
+        prefixes
+            .iter()
+            .map(|prefix| Json::object([("nlri", Json::from(prefix.to_string()))]))
+            .collect(),
+    )
+}
+
+fn afi_name(afi: Afi) -> String {
+    match afi {
+        Afi::Ipv4 => "ipv4".to_owned(),
+        Afi::Ipv6 => "ipv6".to_owned(),
+        Afi::Unknown(afi) => format!("unknown-afi-{afi}"),
+    }
+}
+
+pub(crate) fn safi_name(safi: Safi) -> String {
+    match safi {
+        Safi::Unicast => "unicast".to_owned(),
+        Safi::Multicast => "multicast".to_owned(),
+        Safi::Unknown(safi) => format!("unknown-safi-{safi}"),
+    }
+}
+
+fn family_name(afi: Afi, safi: Safi) -> String {
+    format!("{} {}", afi_name(afi), safi_name(safi))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// The UPDATE described by an exabgp update, for announcing through a session what exabgp
+/// would.
+///
+/// Takes either a whole message as [`to_exabgp_json`] produces it or just its `update`
+/// member. IPv4 and IPv6 unicast are supported, with one next hop per family since an
+/// UPDATE carries only one.
+pub fn from_exabgp_json(json: &Json) -> Result<UpdateMessage, ExabgpError> {
+    let update = match json.get("neighbor") {
+        Some(neighbor) => neighbor
+            .get("message")
+            .and_then(|message| message.get("update"))
+            .ok_or(ExabgpError::Missing("neighbor.message.update"))?,
+        None => json,
+    };
+    if update.as_object().is_none() {
+        return Err(invalid("update", update));
+    }
+
+    if let Some(eor) = update.get("eor") {
+        let name = |key| eor.get(key).and_then(Json::as_str);
+        let (Some(afi), Some(safi)) = (name("afi"), name("safi")) else {
+            return Err(invalid("eor", eor));
+        };
+        let (afi, safi) = family(&format!("{afi} {safi}"))?;
+        return Ok(UpdateMessage::end_of_rib(afi, safi));
+    }
+
+    let mut builder = UpdateMessageBuilder::new();
+    let mut as_path = AsPath::default();
+    if let Some(attribute) = update.get("attribute") {
+        let attributes = attribute
+            .as_object()
+            .ok_or_else(|| invalid("attribute", attribute))?;
+        for (key, value) in attributes {
+            builder = match key.as_str() {
+                "origin" => builder.origin(match value.as_str() {
+                    Some("igp") => OriginType::Igp,
+                    Some("egp") => OriginType::Egp,
+                    Some("incomplete") => OriginType::Incomplete,
+                    _ => return Err(invalid(key, value)),
+                }),
+                "as-path" => {
+                    as_path.segments.extend(path_segments(key, value, false)?);
+                    builder
+                }
+                "confederation-path" => {
+                    // Confederation segments come first on the wire
+                    let confederation = path_segments(key, value, true)?;
+                    as_path.segments.insert_many(0, confederation);
+                    builder
+                }
+                "med" => builder.med(number(key, value)?),
+                "local-preference" => builder.local_pref(number(key, value)?),
+                "atomic-aggregate" => match value.as_bool() {
+                    Some(true) => builder.attribute(PathAttribute {
+                        flags: PathAttributeFlags::well_known(),
+                        type_code: AttributeType::AtomicAggregate,
+                        value: AttributeValue::AtomicAggregate,
+                    }),
+                    Some(false) => builder,
+                    None => return Err(invalid(key, value)),
+                },
+                "aggregator" => {
+                    let aggregator = value
+                        .as_str()
+                        .and_then(|s| s.split_once(':'))
+                        .and_then(|(asn, ip)| Some((asn.parse().ok()?, ip.parse().ok()?)))
+                        .map(|(asn, ip): (u32, Ipv4Addr)| Aggregator { asn, ip })
+                        .ok_or_else(|| invalid(key, value))?;
+                    builder.attribute(PathAttribute {
+                        flags: PathAttributeFlags::optional_transitive(),
+                        type_code: AttributeType::Aggregator,
+                        value: AttributeValue::Aggregator(aggregator),
+                    })
+                }
+                "community" => {
+                    let communities = value.as_array().ok_or_else(|| invalid(key, value))?;
+                    for element in communities {
+                        let community = match element.as_array() {
+                            Some([asn, value]) => {
+                                asn.as_u64().zip(value.as_u64()).and_then(|(asn, value)| {
+                                    Some(Community {
+                                        asn: asn.try_into().ok()?,
+                                        value: value.try_into().ok()?,
+                                    })
+                                })
+                            }
+                            _ => None,
+                        };
+                        builder =
+                            builder.community(community.ok_or_else(|| invalid(key, element))?);
+                    }
+                    builder
+                }
+                _ => builder.attribute(generic_attribute(key, value)?),
+            };
+        }
+    }
+    builder = builder.as_path(as_path);
+
+    let mut next_hops = vec![];
+    if let Some(announce) = update.get("announce") {
+        for (name, next_hop) in announce
+            .as_object()
+            .ok_or_else(|| invalid("announce", announce))?
+        {
+            let (afi, _) = family(name)?;
+            let [(hop, prefixes)] = next_hop
+                .as_object()
+                .ok_or_else(|| invalid(name, next_hop))?
+            else {
+                return Err(ExabgpError::NextHops(name.clone()));
+            };
+            let hop: IpAddr = hop
+                .parse()
+                .map_err(|_| invalid(name, &Json::from(hop.as_str())))?;
+            next_hops.push((afi, hop));
+            for prefix in nlri(name, prefixes, afi)? {
+                builder = builder.announce(prefix);
+            }
+        }
+    }
+    if let Some(withdraw) = update.get("withdraw") {
+        for (name, prefixes) in withdraw
+            .as_object()
+            .ok_or_else(|| invalid("withdraw", withdraw))?
+        {
+            let (afi, _) = family(name)?;
+            for prefix in nlri(name, prefixes, afi)? {
+                builder = builder.withdraw(prefix);
+            }
+        }
+    }
+    let hop = |family| {
+        next_hops
+            .iter()
+            .find(|(afi, _)| *afi == family)
+            .map(|(_, hop)| *hop)
+    };
+    if let Some(hop) = hop(Afi::Ipv4).or(hop(Afi::Ipv6)) {
+        builder = builder.next_hop(hop);
+    }
+    if let Some(hop) = hop(Afi::Ipv6) {
+        builder = builder.mp_next_hop(hop, None);
+    }
+    let update = builder.build();
+    Ok(update)
+}
+
+fn invalid(field: &str, value: &Json) -> ExabgpError {
+    ExabgpError::Invalid {
+        field: field.to_owned(),
+        value: value.to_string(),
+    }
+}
+
+fn number(field: &str, value: &Json) -> Result<u32, ExabgpError> {
+    value
+        .as_u64()
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or_else(|| invalid(field, value))
+}
+
+/// The families [`UpdateMessageBuilder`] can announce
+fn family(name: &str) -> Result<(Afi, Safi), ExabgpError> {
+    match name {
+        "ipv4 unicast" => Ok((Afi::Ipv4, Safi::Unicast)),
+        "ipv6 unicast" => Ok((Afi::Ipv6, Safi::Unicast)),
+        _ => Err(ExabgpError::Family(name.to_owned())),
+    }
+}
+
+fn path_segments(
+    field: &str,
+    value: &Json,
+    confederation: bool,
+) -> Result<Vec<AsPathSegment>, ExabgpError> {
+    let (sequence, set) = match confederation {
+        false => (AsPathSegmentType::AsSequence, AsPathSegmentType::AsSet),
+        true => (
+            AsPathSegmentType::AsConfedSequence,
+            AsPathSegmentType::AsConfedSet,
+        ),
+    };
+    let mut segments: Vec<AsPathSegment> = vec![];
+    for element in value.as_array().ok_or_else(|| invalid(field, value))? {
+        match element {
+            Json::Array(asns) => segments.push(AsPathSegment {
+                segment_type: set,
+                asns: asns
+                    .iter()
+                    .map(|asn| number(field, asn))
+                    .collect::<Result<_, _>>()?,
+            }),
+            asn => {
+                let asn = number(field, asn)?;
+                match segments.last_mut() {
+                    Some(last) if last.segment_type == sequence => last.asns.push(asn),
+                    _ => segments.push(AsPathSegment {
+                        segment_type: sequence,
+                        asns: [asn].into(),
+                    }),
+                }
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn nlri(family: &str, prefixes: &Json, afi: Afi) -> Result<Vec<IpAddrPrefix>, ExabgpError> {
+    prefixes
+        .as_array()
+        .ok_or_else(|| invalid(family, prefixes))?
+        .iter()
+        .map(|entry| {
+            entry
+                .get("nlri")
+                .and_then(Json::as_str)
+                .and_then(|prefix| prefix.parse::<IpAddrPrefix>().ok())
+                .filter(|prefix| prefix.afi() == afi)
+                .ok_or_else(|| invalid(family, entry))
+        })
+        .collect()
+}
+
+fn generic_attribute(key: &str, value: &Json) -> Result<PathAttribute, ExabgpError> {
+    let unsupported = || ExabgpError::Attribute(key.to_owned());
+    let (type_code, flags_byte) = key
+        .strip_prefix("attribute-0x")
+        .and_then(|rest| rest.split_once("-0x"))
+        .and_then(|(type_code, flags)| {
+            Some((
+                u8::from_str_radix(type_code, 16).ok()?,
+                u8::from_str_radix(flags, 16).ok()?,
+            ))
+        })
+        .ok_or_else(unsupported)?;
+    let bytes = value
+        .as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .filter(|hex| hex.len() % 2 == 0)
+        .and_then(|hex| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| invalid(key, value))?;
+    Ok(PathAttribute {
+        flags: PathAttributeFlags::from_byte(flags_byte),
+        type_code: AttributeType::from(type_code),
+        value: AttributeValue::Unknown(Bytes::from(bytes)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::net::{Ipv6Addr, SocketAddr};
+    use std::path::Path;
+
+    use crate::attribute::{AsPathSegment, AsPathSegmentType};
+
+    fn golden(name: &str) -> Json {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name);
+        let text = fs::read_to_string(path).unwrap();
+        let json: Json = text.parse().unwrap();
+        // The fixtures are the pretty form byte for byte, not just the same value
+        assert_eq!(format!("{json:#}\n"), text, "{name}");
+        json
+    }
+
+    fn peer() -> PeerInfo {
+        PeerInfo {
+            peer_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 179),
+            asn: 64500,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: None,
+        }
+    }
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_update() {
+        let update = UpdateMessageBuilder::new()
+            .origin(OriginType::Igp)
+            .as_path(AsPath {
+                segments: [
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsConfedSequence,
+                        asns: [65001].into(),
+                    },
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSequence,
+                        asns: [64500, 3356].into(),
+                    },
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSet,
+                        asns: [64510, 64511].into(),
+                    },
+                ]
+                .into(),
+            })
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .med(10)
+            .local_pref(100)
+            .community(Community {
+                asn: 64500,
+                value: 1,
+            })
+            .community(Community {
+                asn: 65535,
+                value: 65281,
+            })
+            .attribute(PathAttribute {
+                flags: PathAttributeFlags::optional_transitive(),
+                type_code: AttributeType::Unknown(32),
+                value: AttributeValue::Unknown(Bytes::from_static(&[0, 0, 0xfb, 0xf4, 0, 0, 0, 1])),
+            })
+            .announce(prefix("203.0.113.0/24"))
+            .announce(prefix("198.51.100.0/25"))
+            .withdraw(prefix("192.0.2.128/25"))
+            .build();
+
+        let json = to_exabgp_json(&peer(), &update);
+        assert_eq!(json, golden("exabgp_ipv4_update.json"));
+        assert_eq!(from_exabgp_json(&json).unwrap(), update);
+    }
+
+    #[test]
+    fn test_ipv6_and_eor() {
+        let update = UpdateMessageBuilder::new()
+            .origin(OriginType::Incomplete)
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [64500, 4200000000].into(),
+                }]
+                .into(),
+            })
+            .next_hop(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)))
+            .announce(prefix("2001:db8:100::/48"))
+            .withdraw(prefix("2001:db8:200::/48"))
+            .build();
+        let json = to_exabgp_json(&peer(), &update);
+        assert_eq!(json, golden("exabgp_ipv6_update.json"));
+        assert_eq!(from_exabgp_json(&json).unwrap(), update);
+
+        let eor = UpdateMessage::end_of_rib(Afi::Ipv6, Safi::Unicast);
+        let json = to_exabgp_json(&peer(), &eor);
+        assert_eq!(
+            json.to_string(),
+            r#"{"exabgp":"4.0.1","type":"update","neighbor":{"address":{"peer":"192.0.2.1"},"asn":{"peer":64500},"direction":"receive","message":{"update":{"eor":{"afi":"ipv6","safi":"unicast"}}}}}"#
+        );
+        assert_eq!(from_exabgp_json(&json).unwrap(), eor);
+    }
+
+    #[test]
+    fn test_announce_both_families() {
+        let json: Json = r#"{"attribute": {"origin": "igp", "as-path": [64500]},
+            "announce": {
+                "ipv4 unicast": {"192.0.2.1": [{"nlri": "203.0.113.0/24"}]},
+                "ipv6 unicast": {"2001:db8::1": [{"nlri": "2001:db8:100::/48"}]}}}"#
+            .parse()
+            .unwrap();
+        let update = from_exabgp_json(&json).unwrap();
+        assert_eq!(update.nlri, vec![prefix("203.0.113.0/24")]);
+        let mp_reach = update
+            .path_attributes
+            .iter()
+            .find_map(|attribute| match &attribute.value {
+                AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach),
+                _ => None,
+            });
+        assert_eq!(
+            mp_reach.unwrap().next_hop,
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+        let json = to_exabgp_json(&peer(), &update);
+        assert!(json.to_string().contains(
+            r#""announce":{"ipv4 unicast":{"192.0.2.1":[{"nlri":"203.0.113.0/24"}]},"ipv6 unicast":{"2001:db8::1":[{"nlri":"2001:db8:100::/48"}]}}"#
+        ));
+    }
+
+    #[test]
+    fn test_rejected_input() {
+        for (text, err) in [
+            (
+                r#"{"announce": {"ipv4 flow": {"192.0.2.1": []}}}"#,
+                ExabgpError::Family("ipv4 flow".into()),
+            ),
+            (
+                r#"{"announce": {"ipv4 unicast": {"192.0.2.1": [], "192.0.2.2": []}}}"#,
+                ExabgpError::NextHops("ipv4 unicast".into()),
+            ),
+            (
+                r#"{"attribute": {"large-community": [[64500, 1, 1]]}}"#,
+                ExabgpError::Attribute("large-community".into()),
+            ),
+            (
+                r#"{"attribute": {"med": -1}}"#,
+                ExabgpError::Invalid {
+                    field: "med".into(),
+                    value: "-1".into(),
+                },
+            ),
+            (
+                r#"{"announce": {"ipv4 unicast": {"192.0.2.1": [{"nlri": "2001:db8::/32"}]}}}"#,
+                ExabgpError::Invalid {
+                    field: "ipv4 unicast".into(),
+                    value: r#"{"nlri":"2001:db8::/32"}"#.into(),
+                },
+            ),
+            (
+                r#"{"neighbor": {}}"#,
+                ExabgpError::Missing("neighbor.message.update"),
+            ),
+        ] {
+            assert_eq!(from_exabgp_json(&text.parse().unwrap()), Err(err), "{text}");
+        }
+    }
+}
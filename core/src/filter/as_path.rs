@@ -0,0 +1,149 @@
+use std::str::FromStr;
+
+use crate::asn::Asn;
+use crate::attribute::{AsPathSegmentType, AttributeValue, PathAttribute};
+use crate::rib::RibKey;
+
+use super::RouteFilter;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid AS_PATH pattern {0:?}")]
+pub struct ParseAsPathPatternError(String);
+
+/// ASNs that must appear next to each other in the AS_PATH of a route.
+///
+/// Parsed from space separated ASNs in asplain or asdot, where a leading `^` ties the first to the neighbor end
+/// of the path and a trailing `$` the last to the origin: `3356 1299` matches paths through
+/// both in this order, `^64500` routes learned from AS 64500, `64511$` routes it
+/// originates and `^$` routes without ASNs. AS_SETs count as their members in order,
+/// confederation segments are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsPathPattern {
+    asns: Vec<u32>,
+    from_neighbor: bool,
+    at_origin: bool,
+}
+
+impl AsPathPattern {
+    /// Whether a path with these ASNs, neighbor first, matches
+    pub fn matches(&self, path: &[u32]) -> bool {
+        let len = self.asns.len();
+        match (self.from_neighbor, self.at_origin) {
+            (true, true) => path == self.asns,
+            (true, false) => path.starts_with(&self.asns),
+            (false, true) => path.ends_with(&self.asns),
+            (false, false) => len == 0 || path.windows(len).any(|window| window == self.asns),
+        }
+    }
+
+    /// Whether a route with `attributes` matches, one without AS_PATH as an empty path
+    pub fn matches_attributes(&self, attributes: &[PathAttribute]) -> bool {
+        let path: Vec<u32> = attributes
+            .iter()
+            .find_map(|attribute| match &attribute.value {
+                AttributeValue::AsPath(as_path) => Some(&as_path.segments),
+                _ => None,
+            })
+            .into_iter()
+            .flatten()
+            .filter(|segment| {
+                matches!(
+                    segment.segment_type,
+                    AsPathSegmentType::AsSequence | AsPathSegmentType::AsSet
+                )
+            })
+            .flat_map(|segment| segment.asns.iter().copied())
+            .collect();
+        self.matches(&path)
+    }
+}
+
+/// Permits the routes whose AS_PATH matches
+impl RouteFilter for AsPathPattern {
+    fn permits(&self, _key: &RibKey, attributes: &[PathAttribute]) -> bool {
+        self.matches_attributes(attributes)
+    }
+}
+
+impl FromStr for AsPathPattern {
+    type Err = ParseAsPathPatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = s.trim();
+        let (from_neighbor, pattern) = match pattern.strip_prefix('^') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let (at_origin, pattern) = match pattern.strip_suffix('$') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let asns = pattern
+            .split_whitespace()
+            .map(|asn| asn.parse::<Asn>().map(u32::from))
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| ParseAsPathPatternError(s.to_owned()))?;
+        if asns.is_empty() && from_neighbor != at_origin {
+            return Err(ParseAsPathPatternError(s.to_owned()));
+        }
+        Ok(AsPathPattern {
+            asns,
+            from_neighbor,
+            at_origin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::update_message::UpdateMessageBuilder;
+
+    #[test]
+    fn test_matches_paths() {
+        let path = [3356, 1299, 64500];
+        let matches = |pattern: &str| pattern.parse::<AsPathPattern>().unwrap().matches(&path);
+        assert!(matches("1299"));
+        assert!(matches("3356 1299"));
+        assert!(!matches("3356 64500"));
+        assert!(matches("^3356"));
+        assert!(!matches("^1299"));
+        assert!(matches("64500$"));
+        assert!(matches(" ^3356 1299 64500$ "));
+        assert!(!matches("^3356 1299$"));
+        assert!(!matches("^$"));
+        assert!("^$".parse::<AsPathPattern>().unwrap().matches(&[]));
+        assert!("".parse::<AsPathPattern>().unwrap().matches(&path));
+        let four_octet = [3356, 4_200_000_000];
+        let asdot = "3356 64086.59904$".parse::<AsPathPattern>().unwrap();
+        assert!(asdot.matches(&four_octet));
+
+        for invalid in ["as3356", "^", "$", "1299 -1", "4294967296", "1.2.3"] {
+            assert_eq!(
+                invalid.parse::<AsPathPattern>(),
+                Err(ParseAsPathPatternError(invalid.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_attributes() {
+        let update = UpdateMessageBuilder::new()
+            .as_path("(65001) 3356 {64500,64501}".parse().unwrap())
+            .next_hop("192.0.2.1".parse().unwrap())
+            .announce("203.0.113.0/24".parse().unwrap())
+            .build();
+        let matches = |pattern: &str| {
+            let pattern: AsPathPattern = pattern.parse().unwrap();
+            pattern.matches_attributes(&update.path_attributes)
+        };
+        assert!(matches("^3356 64500"));
+        assert!(matches("64501$"));
+        assert!(!matches("65001"));
+        assert!(
+            "^$".parse::<AsPathPattern>()
+                .unwrap()
+                .matches_attributes(&[])
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use crate::attribute::{AttributeValue, Community, PathAttribute};
+use crate::rib::RibKey;
+
+use super::RouteFilter;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid community expression {expression:?} at offset {offset}: {reason}")]
+pub struct ParseMatcherError {
+    expression: String,
+    offset: usize,
+    reason: &'static str,
+}
+
+/// A community where either half may be a wildcard, such as `65000:*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommunityPattern {
+    pub asn: Option<u16>,
+    pub value: Option<u16>,
+}
+
+/// A condition on the communities a route carries.
+///
+/// Parsed from expressions such as `65000:* & !no-export | blackhole`, where `!` binds tighter
+/// than `&`, which binds tighter than `|`, and parentheses group. Only the COMMUNITIES
+/// attribute is looked at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommunityMatcher {
+    /// The route carries a community matching the pattern
+    Community(CommunityPattern),
+    AllOf(Vec<CommunityMatcher>),
+    AnyOf(Vec<CommunityMatcher>),
+    NoneOf(Vec<CommunityMatcher>),
+}
+
+impl CommunityPattern {
+    pub fn exact(community: Community) -> Self {
+        CommunityPattern {
+            asn: Some(community.asn),
+            value: Some(community.value),
+        }
+    }
+
+    pub fn matches(&self, community: &Community) -> bool {
+        self.asn.is_none_or(|asn| asn == community.asn)
+            && self.value.is_none_or(|value| value == community.value)
+    }
+}
+
+impl CommunityMatcher {
+    /// Whether a route carrying `communities` satisfies the condition
+    pub fn matches(&self, communities: &[Community]) -> bool {
+        match self {
+            CommunityMatcher::Community(pattern) => communities
+                .iter()
+                .any(|community| pattern.matches(community)),
+            CommunityMatcher::AllOf(matchers) => {
+                matchers.iter().all(|matcher| matcher.matches(communities))
+            }
+            CommunityMatcher::AnyOf(matchers) => {
+                matchers.iter().any(|matcher| matcher.matches(communities))
+            }
+            CommunityMatcher::NoneOf(matchers) => {
+                !matchers.iter().any(|matcher| matcher.matches(communities))
+            }
+        }
+    }
+
+    /// Whether the condition holds for a route carrying just `community`
+    pub fn matches_community(&self, community: &Community) -> bool {
+        self.matches(std::slice::from_ref(community))
+    }
+
+    /// Whether a route with `attributes` satisfies the condition
+    pub fn matches_attributes(&self, attributes: &[PathAttribute]) -> bool {
+        let communities = attributes
+            .iter()
+            .find_map(|attribute| match &attribute.value {
+                AttributeValue::Communities(communities) => Some(&communities.communities[..]),
+                _ => None,
+            });
+        self.matches(communities.unwrap_or_default())
+    }
+}
+
+/// Permits the routes the condition holds for
+impl RouteFilter for CommunityMatcher {
+    fn permits(&self, _key: &RibKey, attributes: &[PathAttribute]) -> bool {
+        self.matches_attributes(attributes)
+    }
+}
+
+impl FromStr for CommunityMatcher {
+    type Err = ParseMatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            expression: s,
+            chars: s.char_indices().peekable(),
+        };
+        let matcher = parser.any_of()?;
+        match parser.peek() {
+            None => Ok(matcher),
+            Some(_) => Err(parser.error("unexpected character")),
+        }
+    }
+}
+
+/// Recursive descent over `any_of := all_of ('|' all_of)*`, `all_of := not ('&' not)*` and
+/// `not := '!' not | '(' any_of ')' | pattern`
+struct Parser<'a> {
+    expression: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn any_of(&mut self) -> Result<CommunityMatcher, ParseMatcherError> {
+        let mut matchers = vec![self.all_of()?];
+        while self.eat('|') {
+            matchers.push(self.all_of()?);
+        }
+        Ok(match matchers.len() {
+            1 => matchers.remove(0),
+            _ => CommunityMatcher::AnyOf(matchers),
+        })
+    }
+
+    fn all_of(&mut self) -> Result<CommunityMatcher, ParseMatcherError> {
+        let mut matchers = vec![self.not()?];
+        while self.eat('&') {
+            matchers.push(self.not()?);
+        }
+        Ok(match matchers.len() {
+            1 => matchers.remove(0),
+            _ => CommunityMatcher::AllOf(matchers),
+        })
+    }
+
+    fn not(&mut self) -> Result<CommunityMatcher, ParseMatcherError> {
+        if self.eat('!') {
+            return Ok(CommunityMatcher::NoneOf(vec![self.not()?]));
+        }
+        if self.eat('(') {
+            let matcher = self.any_of()?;
+            if !self.eat(')') {
+                return Err(self.error("expected ')'"));
+            }
+            return Ok(matcher);
+        }
+        self.pattern().map(CommunityMatcher::Community)
+    }
+
+    fn pattern(&mut self) -> Result<CommunityPattern, ParseMatcherError> {
+        self.peek();
+        let start = self.offset();
+        while self
+            .chars
+            .next_if(|(_, c)| c.is_ascii_alphanumeric() || matches!(c, ':' | '*' | '-'))
+            .is_some()
+        {}
+        let word = &self.expression[start..self.offset()];
+        if word.is_empty() {
+            return Err(self.error("expected a community"));
+        }
+
+        if let Ok(community) = word.parse() {
+            return Ok(CommunityPattern::exact(community));
+        }
+        let half = |half: &str| match half {
+            "*" => Some(None),
+            _ => half.parse().ok().map(Some),
+        };
+        let pattern = word
+            .split_once(':')
+            .and_then(|(asn, value)| Some((half(asn)?, half(value)?)));
+        match pattern {
+            Some((asn, value)) => Ok(CommunityPattern { asn, value }),
+            None => Err(ParseMatcherError {
+                expression: self.expression.to_string(),
+                offset: start,
+                reason: "not a community",
+            }),
+        }
+    }
+
+    /// Skips whitespace and returns the next character
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.chars.next();
+        }
+        found
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.expression.len(), |(offset, _)| *offset)
+    }
+
+    fn error(&mut self, reason: &'static str) -> ParseMatcherError {
+        ParseMatcherError {
+            expression: self.expression.to_string(),
+            offset: self.offset(),
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::filter::PrefixList;
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn community(asn: u16, value: u16) -> Community {
+        Community { asn, value }
+    }
+
+    fn matcher(s: &str) -> CommunityMatcher {
+        s.parse().unwrap()
+    }
+
+    fn pattern(asn: Option<u16>, value: Option<u16>) -> CommunityMatcher {
+        CommunityMatcher::Community(CommunityPattern { asn, value })
+    }
+
+    #[test]
+    fn test_patterns() {
+        let communities = [community(65000, 100), community(0xffff, 0xff01)];
+        assert!(matcher("65000:100").matches(&communities));
+        assert!(!matcher("65000:200").matches(&communities));
+        assert!(matcher("65000:*").matches(&communities));
+        assert!(matcher("*:100").matches(&communities));
+        assert!(!matcher("*:200").matches(&communities));
+        assert!(matcher("no-export").matches(&communities));
+        assert!(matcher("65535:65281").matches(&communities));
+        assert!(!matcher("blackhole").matches(&communities));
+        assert!(!matcher("*:*").matches(&[]));
+    }
+
+    #[test]
+    fn test_precedence() {
+        // `!` over `&` over `|`
+        assert_eq!(
+            matcher("65000:* & !no-export | blackhole"),
+            CommunityMatcher::AnyOf(vec![
+                CommunityMatcher::AllOf(vec![
+                    pattern(Some(65000), None),
+                    CommunityMatcher::NoneOf(vec![pattern(Some(0xffff), Some(0xff01))]),
+                ]),
+                pattern(Some(0xffff), Some(666)),
+            ])
+        );
+        assert_eq!(
+            matcher("1:1 | 2:2 & 3:3"),
+            CommunityMatcher::AnyOf(vec![
+                pattern(Some(1), Some(1)),
+                CommunityMatcher::AllOf(vec![pattern(Some(2), Some(2)), pattern(Some(3), Some(3))]),
+            ])
+        );
+        assert_eq!(
+            matcher("(1:1 | 2:2) & 3:3"),
+            CommunityMatcher::AllOf(vec![
+                CommunityMatcher::AnyOf(vec![pattern(Some(1), Some(1)), pattern(Some(2), Some(2))]),
+                pattern(Some(3), Some(3)),
+            ])
+        );
+        assert_eq!(
+            matcher("!!1:1"),
+            CommunityMatcher::NoneOf(vec![CommunityMatcher::NoneOf(vec![pattern(
+                Some(1),
+                Some(1)
+            )])])
+        );
+
+        let only_third = [community(3, 3)];
+        assert!(matcher("1:1 | 2:2 & 3:3").matches(&[community(1, 1)]));
+        assert!(!matcher("(1:1 | 2:2) & 3:3").matches(&[community(1, 1)]));
+        assert!(!matcher("1:1 | 2:2 & 3:3").matches(&only_third));
+        assert!(matcher("!1:1 & 3:3").matches(&only_third));
+        assert!(!matcher("!(1:1 | 3:3)").matches(&only_third));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (invalid, offset) in [
+            ("", 0),
+            ("65000", 0),
+            ("65536:1", 0),
+            ("no-exportt", 0),
+            ("1:1 &", 5),
+            ("(1:1 | 2:2", 10),
+            ("1:1 2:2", 4),
+            ("1:1 && 2:2", 5),
+        ] {
+            let error = invalid.parse::<CommunityMatcher>().unwrap_err();
+            assert_eq!(error.offset, offset, "{invalid}: {error}");
+        }
+    }
+
+    #[test]
+    fn test_matches_attributes() {
+        let update = UpdateMessageBuilder::new()
+            .announce("192.0.2.0/24".parse().unwrap())
+            .next_hop("192.0.2.1".parse().unwrap())
+            .community(community(65000, 100))
+            .build();
+        let key = RibKey::unicast("192.0.2.0/24".parse().unwrap());
+        assert!(matcher("65000:100").permits(&key, &update.path_attributes));
+        assert!(!matcher("65000:200").permits(&key, &update.path_attributes));
+        assert!(matcher("!no-export").permits(&key, &[]));
+        assert!(matcher("65000:* & !65000:200").matches_community(&community(65000, 100)));
+
+        let pipeline: Vec<Box<dyn RouteFilter>> = vec![
+            Box::new("permit 192.0.2.0/24".parse::<PrefixList>().unwrap()),
+            Box::new(matcher("65000:*")),
+        ];
+        assert!(pipeline.permits(&key, &update.path_attributes));
+        assert!(!pipeline.permits(&key, &[]));
+    }
+}
@@ -0,0 +1,50 @@
+mod as_path;
+mod community;
+mod prefix_list;
+
+use std::fmt;
+
+use crate::attribute::PathAttribute;
+use crate::rib::RibKey;
+
+pub use as_path::{AsPathPattern, ParseAsPathPatternError};
+pub use community::{CommunityMatcher, CommunityPattern, ParseMatcherError};
+pub use prefix_list::{PrefixList, PrefixListBuilder, PrefixListEntry, PrefixListError};
+
+/// Decides which announced routes enter a RIB or reach an observer.
+///
+/// Only announcements are filtered: a withdrawal can only remove a route that was admitted.
+pub trait RouteFilter: fmt::Debug + Send + Sync {
+    fn permits(&self, key: &RibKey, attributes: &[PathAttribute]) -> bool;
+}
+
+/// Permits what every filter permits, asking them in order
+impl<F: RouteFilter> RouteFilter for Vec<F> {
+    fn permits(&self, key: &RibKey, attributes: &[PathAttribute]) -> bool {
+        self.iter().all(|filter| filter.permits(key, attributes))
+    }
+}
+
+impl<F: RouteFilter + ?Sized> RouteFilter for std::sync::Arc<F> {
+    fn permits(&self, key: &RibKey, attributes: &[PathAttribute]) -> bool {
+        (**self).permits(key, attributes)
+    }
+}
+
+impl<F: RouteFilter + ?Sized> RouteFilter for Box<F> {
+    fn permits(&self, key: &RibKey, attributes: &[PathAttribute]) -> bool {
+        (**self).permits(key, attributes)
+    }
+}
+
+/// What a filter does with the routes an entry matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FilterAction {
+    Permit,
+    Deny,
+}
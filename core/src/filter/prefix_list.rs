@@ -0,0 +1,520 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::attribute::PathAttribute;
+use crate::rib::RibKey;
+use crate::trie::PrefixTrie;
+use crate::update_message::IpAddrPrefix;
+
+use super::{FilterAction, RouteFilter};
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PrefixListError {
+    #[error("malformed entry {0:?}")]
+    Malformed(String),
+    #[error("invalid length range for {prefix}: ge {ge:?}, le {le:?}")]
+    InvalidRange {
+        prefix: IpAddrPrefix,
+        ge: Option<u8>,
+        le: Option<u8>,
+    },
+    #[error("line {line}: {error}")]
+    Line {
+        line: usize,
+        error: Box<PrefixListError>,
+    },
+}
+
+/// One line of a prefix list, such as `permit 10.0.0.0/8 ge 16 le 24`.
+///
+/// Without `ge` or `le` only the prefix itself matches. Otherwise routes within the prefix
+/// match when their length lies in `ge..=le`, where `ge` defaults to the prefix length and `le`
+/// to the address width.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixListEntry {
+    action: FilterAction,
+    prefix: IpAddrPrefix,
+    ge: Option<u8>,
+    le: Option<u8>,
+}
+
+/// An ordered list of entries where the first one matching a route decides, as in router
+/// configurations.
+///
+/// Routes no entry matches get the default action, deny unless configured otherwise. Entries
+/// are indexed by prefix in a [`PrefixTrie`] so lookups cost one walk down the route's bits.
+#[derive(Debug, Clone)]
+pub struct PrefixList {
+    entries: Vec<PrefixListEntry>,
+    default_action: FilterAction,
+    /// Entry indexes by prefix, in list order
+    trie: PrefixTrie<Vec<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefixListBuilder {
+    entries: Vec<PrefixListEntry>,
+    default_action: FilterAction,
+}
+
+impl PrefixListEntry {
+    /// Fails unless `length < ge <= le <= width` and `length <= le`
+    pub fn new(
+        action: FilterAction,
+        prefix: IpAddrPrefix,
+        ge: Option<u8>,
+        le: Option<u8>,
+    ) -> Result<Self, PrefixListError> {
+        let entry = PrefixListEntry {
+            action,
+            prefix,
+            ge,
+            le,
+        };
+        let (min, max) = entry.lengths();
+        let valid = ge.is_none_or(|ge| ge > entry.prefix.length())
+            && min <= max
+            && max <= entry.prefix.max_length();
+        if !valid {
+            return Err(PrefixListError::InvalidRange {
+                prefix: entry.prefix,
+                ge,
+                le,
+            });
+        }
+        Ok(entry)
+    }
+
+    pub fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    pub fn prefix(&self) -> &IpAddrPrefix {
+        &self.prefix
+    }
+
+    /// The range of route lengths the entry matches
+    pub fn lengths(&self) -> (u8, u8) {
+        match (self.ge, self.le) {
+            (None, None) => (self.prefix.length(), self.prefix.length()),
+            (ge, le) => (
+                ge.unwrap_or(self.prefix.length()),
+                le.unwrap_or(self.prefix.max_length()),
+            ),
+        }
+    }
+
+    fn matches_length(&self, length: u8) -> bool {
+        let (min, max) = self.lengths();
+        (min..=max).contains(&length)
+    }
+}
+
+impl fmt::Display for PrefixListEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            FilterAction::Permit => "permit",
+            FilterAction::Deny => "deny",
+        };
+        write!(f, "{action} {}", self.prefix)?;
+        if let Some(ge) = self.ge {
+            write!(f, " ge {ge}")?;
+        }
+        if let Some(le) = self.le {
+            write!(f, " le {le}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `permit|deny prefix [ge n] [le n]`
+impl FromStr for PrefixListEntry {
+    type Err = PrefixListError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || PrefixListError::Malformed(s.to_string());
+        let mut words = s.split_whitespace();
+        let action = match words.next() {
+            Some("permit") => FilterAction::Permit,
+            Some("deny") => FilterAction::Deny,
+            _ => return Err(malformed()),
+        };
+        let prefix = words
+            .next()
+            .and_then(|prefix| prefix.parse().ok())
+            .ok_or_else(malformed)?;
+
+        let (mut ge, mut le) = (None, None);
+        while let Some(keyword) = words.next() {
+            let bound = match keyword {
+                "ge" if ge.is_none() && le.is_none() => &mut ge,
+                "le" if le.is_none() => &mut le,
+                _ => return Err(malformed()),
+            };
+            let length = words.next().and_then(|length| length.parse().ok());
+            *bound = Some(length.ok_or_else(malformed)?);
+        }
+        PrefixListEntry::new(action, prefix, ge, le)
+    }
+}
+
+impl PrefixList {
+    pub fn builder() -> PrefixListBuilder {
+        PrefixListBuilder {
+            entries: vec![],
+            default_action: FilterAction::Deny,
+        }
+    }
+
+    pub fn entries(&self) -> &[PrefixListEntry] {
+        &self.entries
+    }
+
+    pub fn default_action(&self) -> FilterAction {
+        self.default_action
+    }
+
+    /// The first entry matching `prefix`, if any
+    pub fn find(&self, prefix: &IpAddrPrefix) -> Option<&PrefixListEntry> {
+        // The first matching entry of each covering prefix, then the first of those
+        let index = self
+            .trie
+            .covering(prefix)
+            .filter_map(|(_, entries)| {
+                entries
+                    .iter()
+                    .copied()
+                    .find(|&i| self.entries[i as usize].matches_length(prefix.length()))
+            })
+            .min()?;
+        Some(&self.entries[index as usize])
+    }
+
+    pub fn action(&self, prefix: &IpAddrPrefix) -> FilterAction {
+        self.find(prefix)
+            .map_or(self.default_action, PrefixListEntry::action)
+    }
+
+    pub fn permits_prefix(&self, prefix: &IpAddrPrefix) -> bool {
+        self.action(prefix) == FilterAction::Permit
+    }
+}
+
+impl RouteFilter for PrefixList {
+    fn permits(&self, key: &RibKey, _attributes: &[PathAttribute]) -> bool {
+        self.permits_prefix(&key.prefix)
+    }
+}
+
+/// One entry per line, blank lines and lines starting with `#` are skipped.
+///
+/// A final `default permit` or `default deny` line replaces the implicit deny.
+impl FromStr for PrefixList {
+    type Err = PrefixListError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut builder = PrefixList::builder();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            builder = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["default", "permit"] => builder.default_action(FilterAction::Permit),
+                ["default", "deny"] => builder.default_action(FilterAction::Deny),
+                _ => builder.entry(line.parse().map_err(|error| PrefixListError::Line {
+                    line: i + 1,
+                    error: Box::new(error),
+                })?),
+            };
+        }
+        Ok(builder.build())
+    }
+}
+
+impl PrefixListBuilder {
+    pub fn entry(mut self, entry: PrefixListEntry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Permits exactly `prefix`
+    pub fn permit(self, prefix: IpAddrPrefix) -> Self {
+        self.entry(PrefixListEntry {
+            action: FilterAction::Permit,
+            prefix,
+            ge: None,
+            le: None,
+        })
+    }
+
+    /// Denies exactly `prefix`
+    pub fn deny(self, prefix: IpAddrPrefix) -> Self {
+        self.entry(PrefixListEntry {
+            action: FilterAction::Deny,
+            prefix,
+            ge: None,
+            le: None,
+        })
+    }
+
+    /// What happens to routes no entry matches
+    pub fn default_action(mut self, action: FilterAction) -> Self {
+        self.default_action = action;
+        self
+    }
+
+    pub fn build(self) -> PrefixList {
+        let mut trie: PrefixTrie<Vec<u32>> = PrefixTrie::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            match trie.get_mut(&entry.prefix) {
+                Some(entries) => entries.push(i as u32),
+                None => {
+                    trie.insert(&entry.prefix, vec![i as u32]);
+                }
+            }
+        }
+        PrefixList {
+            entries: self.entries,
+            default_action: self.default_action,
+            trie,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::rib::RibIn;
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    fn list(s: &str) -> PrefixList {
+        s.parse().unwrap()
+    }
+
+    /// Which of the routes the single entry `entry` permits
+    fn permitted<'a>(entry: &str, routes: &[&'a str]) -> Vec<&'a str> {
+        let list = list(entry);
+        routes
+            .iter()
+            .copied()
+            .filter(|route| list.permits_prefix(&prefix(route)))
+            .collect()
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let routes = ["10.0.0.0/8", "10.0.0.0/9", "10.0.0.0/7", "11.0.0.0/8"];
+        assert_eq!(permitted("permit 10.0.0.0/8", &routes), ["10.0.0.0/8"]);
+    }
+
+    #[test]
+    fn test_le() {
+        let routes = [
+            "10.0.0.0/7",
+            "10.0.0.0/8",
+            "10.20.0.0/16",
+            "10.20.30.0/24",
+            "10.20.30.0/25",
+            "11.0.0.0/16",
+        ];
+        assert_eq!(
+            permitted("permit 10.0.0.0/8 le 24", &routes),
+            ["10.0.0.0/8", "10.20.0.0/16", "10.20.30.0/24"]
+        );
+    }
+
+    #[test]
+    fn test_ge() {
+        let routes = [
+            "10.0.0.0/8",
+            "10.20.0.0/15",
+            "10.20.0.0/16",
+            "10.20.30.40/32",
+        ];
+        assert_eq!(
+            permitted("permit 10.0.0.0/8 ge 16", &routes),
+            ["10.20.0.0/16", "10.20.30.40/32"]
+        );
+    }
+
+    #[test]
+    fn test_ge_le() {
+        let routes = [
+            "192.168.0.0/16",
+            "192.168.1.0/23",
+            "192.168.1.0/24",
+            "192.168.1.0/26",
+            "192.168.1.0/27",
+        ];
+        assert_eq!(
+            permitted("permit 192.168.0.0/16 ge 24 le 26", &routes),
+            ["192.168.1.0/24", "192.168.1.0/26"]
+        );
+        // ge equal to le matches a single length
+        assert_eq!(
+            permitted("permit 192.168.0.0/16 ge 24 le 24", &routes),
+            ["192.168.1.0/24"]
+        );
+    }
+
+    #[test]
+    fn test_any() {
+        let routes = ["0.0.0.0/0", "10.0.0.0/8", "192.0.2.1/32", "2001:db8::/32"];
+        assert_eq!(
+            permitted("permit 0.0.0.0/0 le 32", &routes),
+            ["0.0.0.0/0", "10.0.0.0/8", "192.0.2.1/32"]
+        );
+        assert_eq!(permitted("permit 0.0.0.0/0", &routes), ["0.0.0.0/0"]);
+        assert_eq!(
+            permitted("permit ::/0 ge 1 le 48", &routes),
+            ["2001:db8::/32"]
+        );
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let list = list(
+            "# customer space, without the more specifics of the lab
+            deny 10.1.0.0/16 ge 17
+            permit 10.0.0.0/8 le 24
+            deny 10.1.2.0/24",
+        );
+        assert!(list.permits_prefix(&prefix("10.1.0.0/16")));
+        assert!(!list.permits_prefix(&prefix("10.1.128.0/17")));
+        // The later deny is shadowed by the permit
+        assert!(!list.permits_prefix(&prefix("10.1.2.0/24")));
+        assert!(list.permits_prefix(&prefix("10.2.2.0/24")));
+        assert_eq!(list.find(&prefix("10.2.2.0/24")), Some(&list.entries()[1]));
+        // Implicit deny
+        assert_eq!(list.find(&prefix("10.2.2.0/25")), None);
+        assert!(!list.permits_prefix(&prefix("10.2.2.0/25")));
+    }
+
+    #[test]
+    fn test_default_action() {
+        let list = list("deny 192.0.2.0/24 le 32\ndefault permit");
+        assert_eq!(list.default_action(), FilterAction::Permit);
+        assert!(!list.permits_prefix(&prefix("192.0.2.128/25")));
+        assert!(list.permits_prefix(&prefix("198.51.100.0/24")));
+
+        let built = PrefixList::builder()
+            .deny(prefix("192.0.2.0/24"))
+            .default_action(FilterAction::Permit)
+            .build();
+        assert!(built.permits_prefix(&prefix("192.0.2.0/25")));
+        assert!(!built.permits_prefix(&prefix("192.0.2.0/24")));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for malformed in [
+            "allow 10.0.0.0/8",
+            "permit",
+            "permit 10.0.0.0",
+            "permit 10.0.0.0/8 le",
+            "permit 10.0.0.0/8 le 24 ge 16",
+            "permit 10.0.0.0/8 ge 16 ge 20",
+            "permit 10.0.0.0/8 upto 24",
+        ] {
+            assert!(
+                matches!(
+                    malformed.parse::<PrefixListEntry>(),
+                    Err(PrefixListError::Malformed(_))
+                ),
+                "{malformed}"
+            );
+        }
+        for invalid in [
+            "permit 10.0.0.0/8 ge 8",
+            "permit 10.0.0.0/8 ge 4",
+            "permit 10.0.0.0/8 le 4",
+            "permit 10.0.0.0/8 ge 24 le 16",
+            "permit 10.0.0.0/8 le 33",
+            "permit 2001:db8::/32 ge 129",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<PrefixListEntry>(),
+                    Err(PrefixListError::InvalidRange { .. })
+                ),
+                "{invalid}"
+            );
+        }
+        let error = "permit 10.0.0.0/8\n\npermit 10.0.0.0/8 le 4"
+            .parse::<PrefixList>()
+            .unwrap_err();
+        assert!(matches!(error, PrefixListError::Line { line: 3, .. }));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for entry in ["permit 10.0.0.0/8 ge 16 le 24", "deny 2001:db8::/32 le 48"] {
+            assert_eq!(entry.parse::<PrefixListEntry>().unwrap().to_string(), entry);
+        }
+    }
+
+    #[test]
+    fn test_many_entries() {
+        let mut builder = PrefixList::builder();
+        for i in 0..20_000u32 {
+            let addr = std::net::Ipv4Addr::from((10 << 24) | (i << 8));
+            let entry = match i % 2 {
+                0 => PrefixListEntry::new(
+                    FilterAction::Permit,
+                    IpAddrPrefix::new(addr.into(), 24).unwrap(),
+                    None,
+                    Some(26),
+                ),
+                _ => PrefixListEntry::new(
+                    FilterAction::Deny,
+                    IpAddrPrefix::new(addr.into(), 24).unwrap(),
+                    None,
+                    None,
+                ),
+            };
+            builder = builder.entry(entry.unwrap());
+        }
+        let list = builder.build();
+        assert!(list.permits_prefix(&prefix("10.0.2.64/26")));
+        assert!(!list.permits_prefix(&prefix("10.0.3.0/24")));
+        assert!(!list.permits_prefix(&prefix("10.0.2.64/27")));
+        assert_eq!(
+            list.find(&prefix("10.78.30.0/25"))
+                .map(|entry| entry.prefix()),
+            Some(&prefix("10.78.30.0/24"))
+        );
+    }
+
+    #[test]
+    fn test_rib_counts_filtered_routes() {
+        let filter = Arc::new(list("permit 10.0.0.0/8 le 24"));
+        let mut rib = RibIn::new().with_filter(filter);
+        let announce = |prefixes: &[&str], med| {
+            prefixes
+                .iter()
+                .fold(UpdateMessageBuilder::new(), |builder, p| {
+                    builder.announce(prefix(p))
+                })
+                .next_hop("192.0.2.1".parse().unwrap())
+                .med(med)
+                .build()
+        };
+
+        let changes = rib.apply(&announce(
+            &["10.1.0.0/16", "10.1.1.0/25", "192.0.2.0/24"],
+            0,
+        ));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(rib.len(), 1);
+        assert_eq!(rib.filtered(), 2);
+        assert!(rib.lookup(&prefix("10.1.0.0/16")).is_some());
+        assert!(rib.lookup(&prefix("192.0.2.0/24")).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+//! Stable hashes of attribute sets, for telling whether a route's attributes changed
+//!
+//! Fingerprints are computed over a canonical form: attributes in type code order, the
+//! elements of set valued attributes such as COMMUNITIES sorted without duplicates, the
+//! extended length flag ignored, MP_REACH_NLRI without its prefixes and MP_UNREACH_NLRI left
+//! out. Sets differing only in wire order or encoding therefore share a fingerprint. The hash
+//! is FNV-1a, so fingerprints are the same across processes and platforms and may be stored.
+
+use std::borrow::Cow;
+
+use bytes::BytesMut;
+
+use crate::attribute::{AttributeType, Attributes, set_element_len};
+
+impl Attributes<'_> {
+    /// The fingerprint of the attribute set, see the [module](self) description
+    pub fn fingerprint(&self) -> u64 {
+        let encoded: Vec<_> = self
+            .iter()
+            .map(|attribute| {
+                let mut value = BytesMut::new();
+                attribute.value.encode(&mut value);
+                (
+                    u8::from(&attribute.type_code),
+                    attribute.flags.to_byte(),
+                    value,
+                )
+            })
+            .collect();
+        fingerprint(
+            encoded
+                .iter()
+                .map(|(type_code, flags, value)| (*type_code, *flags, &value[..])),
+        )
+    }
+}
+
+/// The fingerprint of the path attributes field of an UPDATE as received on a 4 octet AS
+/// session, equal to that of the decoded attributes, or `None` when an attribute is truncated
+pub fn raw_fingerprint(mut data: &[u8]) -> Option<u64> {
+    let mut attributes = vec![];
+    while let [flags, type_code, rest @ ..] = data {
+        let (len, rest) = match (flags & EXTENDED_LENGTH != 0, rest) {
+            (true, [high, low, rest @ ..]) => (u16::from_be_bytes([*high, *low]) as usize, rest),
+            (false, [len, rest @ ..]) => (*len as usize, rest),
+            _ => return None,
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        attributes.push((*type_code, *flags, value));
+        data = rest;
+    }
+    data.is_empty().then(|| fingerprint(attributes))
+}
+
+const EXTENDED_LENGTH: u8 = 0x10;
+
+/// Hashes `(type code, flags, value)` triples in their canonical form
+fn fingerprint<'a>(attributes: impl IntoIterator<Item = (u8, u8, &'a [u8])>) -> u64 {
+    let mut canonical: Vec<_> = attributes
+        .into_iter()
+        .filter_map(|(type_code, flags, value)| {
+            Some((
+                type_code,
+                flags & !EXTENDED_LENGTH,
+                canonical_value(type_code, value)?,
+            ))
+        })
+        .collect();
+    canonical.sort();
+
+    let mut hasher = Fnv1a::new();
+    for (type_code, flags, value) in &canonical {
+        hasher.write(&[*type_code, *flags]);
+        hasher.write(&(value.len() as u32).to_be_bytes());
+        hasher.write(value);
+    }
+    hasher.finish()
+}
+
+/// The value with the elements of sets sorted, `None` for attributes left out
+fn canonical_value(type_code: u8, value: &[u8]) -> Option<Cow<'_, [u8]>> {
+    if type_code == u8::from(&AttributeType::MpUnreachNlri) {
+        return None;
+    }
+    if type_code == u8::from(&AttributeType::MpReachNlri) {
+        // AFI, SAFI and the next hop, without the reserved octet and the prefixes
+        let len = match value.get(3) {
+            Some(next_hop_len) => (4 + *next_hop_len as usize).min(value.len()),
+            None => value.len(),
+        };
+        return Some(Cow::Borrowed(&value[..len]));
+    }
+    let Some(element_len) = set_element_len(type_code) else {
+        return Some(Cow::Borrowed(value));
+    };
+    if !value.len().is_multiple_of(element_len) {
+        return Some(Cow::Borrowed(value));
+    }
+    let mut elements: Vec<_> = value.chunks(element_len).collect();
+    elements.sort();
+    elements.dedup();
+    Some(Cow::Owned(elements.concat()))
+}
+
+/// 64 bit FNV-1a, the same on every platform and in every process
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// FNV-1a of `data` in one go
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use bytes::{BufMut, Bytes};
+
+    use super::*;
+    use crate::attribute::{AttributeValue, Community, PathAttribute, PathAttributeFlags};
+    use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+    const LARGE_COMMUNITIES: u8 = 32;
+
+    fn update(communities: &[Community], prefixes: &[&str]) -> UpdateMessage {
+        let mut builder = UpdateMessageBuilder::new()
+            .as_path("65001 65002".parse().unwrap())
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .mp_next_hop("2001:db8::1".parse().unwrap(), None)
+            .med(10)
+            .attribute(PathAttribute {
+                flags: PathAttributeFlags::optional_transitive(),
+                type_code: AttributeType::Unknown(LARGE_COMMUNITIES),
+                value: AttributeValue::Unknown(Bytes::from_static(&[
+                    0, 0, 0xfb, 0xf1, 0, 0, 0, 2, 0, 0, 0, 3, //
+                    0, 0, 0xfb, 0xf1, 0, 0, 0, 1, 0, 0, 0, 9,
+                ])),
+            });
+        for community in communities {
+            builder = builder.community(*community);
+        }
+        for prefix in prefixes {
+            builder = builder.announce(prefix.parse().unwrap());
+        }
+        builder.build()
+    }
+
+    fn raw(attributes: &[PathAttribute]) -> Vec<u8> {
+        let mut data = BytesMut::new();
+        for attribute in attributes {
+            attribute.encode(&mut data);
+        }
+        data.to_vec()
+    }
+
+    #[test]
+    fn test_order_independent() {
+        let a = update(
+            &[
+                Community::NO_EXPORT,
+                Community {
+                    asn: 65001,
+                    value: 1,
+                },
+            ],
+            &["198.51.100.0/24", "2001:db8:1::/48"],
+        );
+        let mut b = update(
+            &[
+                Community {
+                    asn: 65001,
+                    value: 1,
+                },
+                Community::NO_EXPORT,
+                Community {
+                    asn: 65001,
+                    value: 1,
+                },
+            ],
+            &["203.0.113.0/24", "2001:db8:2::/48", "2001:db8:3::/48"],
+        );
+        b.path_attributes.reverse();
+        // The same large communities in the other order
+        let large = b
+            .path_attributes
+            .iter_mut()
+            .find(|attribute| attribute.type_code == AttributeType::Unknown(LARGE_COMMUNITIES))
+            .unwrap();
+        if let AttributeValue::Unknown(value) = &mut large.value {
+            let (first, second) = value.split_at(12);
+            *value = Bytes::from([second, first].concat());
+        }
+        // Written with an extended length
+        large.flags.extended_length = true;
+
+        let fingerprint = a.attributes().fingerprint();
+        assert_eq!(b.attributes().fingerprint(), fingerprint);
+        assert_eq!(raw_fingerprint(&raw(&a.path_attributes)), Some(fingerprint));
+        assert_eq!(raw_fingerprint(&raw(&b.path_attributes)), Some(fingerprint));
+        assert_ne!(raw(&a.path_attributes), raw(&b.path_attributes));
+
+        // Withdrawals don't count either
+        let mut withdrawing = a.clone();
+        withdrawing.path_attributes.extend(
+            UpdateMessageBuilder::new()
+                .withdraw("2001:db8:9::/48".parse().unwrap())
+                .build()
+                .path_attributes,
+        );
+        assert_eq!(withdrawing.attributes().fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_changes() {
+        let base = update(&[Community::NO_EXPORT], &["198.51.100.0/24"]);
+        let fingerprint = base.attributes().fingerprint();
+
+        let changed = [
+            update(&[Community::NO_ADVERTISE], &["198.51.100.0/24"]),
+            update(&[], &["198.51.100.0/24"]),
+            update(&[Community::NO_EXPORT], &["2001:db8:1::/48"]),
+        ];
+        for update in changed {
+            assert_ne!(update.attributes().fingerprint(), fingerprint);
+        }
+
+        let mut other_med = base.clone();
+        for attribute in &mut other_med.path_attributes {
+            if let AttributeValue::MultiExitDisc(med) = &mut attribute.value {
+                med.med = 20;
+            }
+        }
+        assert_ne!(other_med.attributes().fingerprint(), fingerprint);
+
+        let mut partial = base.clone();
+        partial.path_attributes[0].flags.partial = true;
+        assert_ne!(partial.attributes().fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_raw_fingerprint() {
+        // Fixed across releases, fingerprints may be stored
+        let origin = [0x40, 1, 1, 0];
+        assert_eq!(raw_fingerprint(&origin), Some(0xd7d5_1145_e128_0935));
+        assert_eq!(raw_fingerprint(&[]), Some(fnv1a(&[])));
+
+        let mut extended = BytesMut::new();
+        extended.put_slice(&[0x50, 1, 0, 1, 0]);
+        assert_eq!(raw_fingerprint(&extended), raw_fingerprint(&origin));
+
+        for truncated in [&[0x40][..], &[0x40, 1], &[0x40, 1, 2, 0], &[0x50, 1, 0]] {
+            assert_eq!(raw_fingerprint(truncated), None, "{truncated:?}");
+        }
+    }
+}
@@ -1,10 +1,14 @@
+use std::fmt;
+
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-// Added Buf and BufMut
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+use crate::spans;
+use crate::validate::Validate;
+
+#[derive(Error, Debug, Clone)]
 pub enum HeaderParseError {
-    #[error("Input length {0} is shorter than {1}")]
+    #[error("Input length {1} is shorter than {0}")]
     InputLengthOutOfRange(usize, usize),
     #[error("BGP marker field is malformed, expected all ones")]
     MalformedMarkerField,
@@ -27,17 +31,24 @@ pub enum BgpHeaderError {
 }
 
 #[repr(u8)]
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum BgpMessageType {
     Open = 1,
     Update = 2,
     Notification = 3,
     Keepalive = 4,
+    RouteRefresh = 5,
     // Represents unknown or future message types
     Unknown(u8),
 }
 
 #[derive(PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BgpHeader {
     pub marker: [u8; 16],
     pub length: u16,
@@ -51,19 +62,59 @@ impl From<u8> for BgpMessageType {
             2 => BgpMessageType::Update,
             3 => BgpMessageType::Notification,
             4 => BgpMessageType::Keepalive,
+            5 => BgpMessageType::RouteRefresh,
             _ => BgpMessageType::Unknown(value),
         }
     }
 }
 
+impl BgpMessageType {
+    /// The shortest message of the type, header included, that the header accepts
+    pub fn min_len(&self) -> u16 {
+        match self {
+            BgpMessageType::Open => 29,
+            BgpMessageType::Update => 23,
+            BgpMessageType::Notification => 21,
+            BgpMessageType::Keepalive => BgpHeader::MIN_LEN,
+            // Of another length, it's a ROUTE-REFRESH Message Error rather than a header one
+            // (RFC 7313)
+            BgpMessageType::RouteRefresh => BgpHeader::MIN_LEN,
+            BgpMessageType::Unknown(_) => BgpHeader::MIN_LEN,
+        }
+    }
+
+    /// The longest message of the type, a KEEPALIVE being only its header
+    pub fn max_len(&self) -> u16 {
+        match self {
+            BgpMessageType::Keepalive => BgpHeader::MIN_LEN,
+            _ => BgpHeader::MAX_LEN,
+        }
+    }
+}
+
 impl From<&BgpMessageType> for u8 {
     fn from(msg_type: &BgpMessageType) -> Self {
-        match msg_type {
-            &BgpMessageType::Open => 1,
-            &BgpMessageType::Update => 2,
-            &BgpMessageType::Notification => 3,
-            &BgpMessageType::Keepalive => 4,
-            &BgpMessageType::Unknown(value) => value,
+        match *msg_type {
+            BgpMessageType::Open => 1,
+            BgpMessageType::Update => 2,
+            BgpMessageType::Notification => 3,
+            BgpMessageType::Keepalive => 4,
+            BgpMessageType::RouteRefresh => 5,
+            BgpMessageType::Unknown(value) => value,
+        }
+    }
+}
+
+/// Lower case, as metric labels and diagnostics name the types, or the type code
+impl fmt::Display for BgpMessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BgpMessageType::Open => f.write_str("open"),
+            BgpMessageType::Update => f.write_str("update"),
+            BgpMessageType::Notification => f.write_str("notification"),
+            BgpMessageType::Keepalive => f.write_str("keepalive"),
+            BgpMessageType::RouteRefresh => f.write_str("route_refresh"),
+            BgpMessageType::Unknown(code) => write!(f, "{code}"),
         }
     }
 }
@@ -74,7 +125,7 @@ impl BgpHeader {
     pub const MARKER_VALUE: [u8; 16] = [0xFF; 16];
 
     pub fn new(length: u16, message_type: BgpMessageType) -> Result<Self, BgpHeaderError> {
-        if length < Self::MIN_LEN || length > Self::MAX_LEN {
+        if !(Self::MIN_LEN..=Self::MAX_LEN).contains(&length) {
             return Err(BgpHeaderError::LengthFieldOutOfRange {
                 min: Self::MIN_LEN as usize,
                 max: Self::MAX_LEN as usize,
@@ -99,25 +150,20 @@ impl BgpHeader {
         }
 
         // Validate marker
-        let mut marker = [0u8; 16];
-        let bytes = input.copy_to_bytes(16);
-        marker.copy_from_slice(&bytes[..]);
-
-        if marker != Self::MARKER_VALUE {
+        let marker = *input.first_chunk::<16>().unwrap();
+        input.advance(16);
+        spans::consumed(input, 16, || "marker".into());
+        if !is_marker(&marker) {
             return Err(HeaderParseError::MalformedMarkerField);
         }
 
         // Get length of message (big endian ordering)
         let length = input.get_u16();
-        if length < Self::MIN_LEN || length > Self::MAX_LEN {
-            return Err(HeaderParseError::LengthFieldOutOfRange {
-                min: Self::MIN_LEN as usize,
-                max: Self::MAX_LEN as usize,
-                actual: length as usize,
-            });
-        }
+        spans::consumed(input, 2, || format!("length: {length}"));
+        check_length(length)?;
 
         let message_type: BgpMessageType = input.get_u8().into();
+        spans::consumed(input, 1, || format!("type: {message_type:?}"));
 
         Ok(BgpHeader {
             marker,
@@ -126,6 +172,28 @@ impl BgpHeader {
         })
     }
 
+    /// Parses the header at the start of `input`, without consuming it
+    pub fn from_slice(input: &[u8]) -> Result<Self, HeaderParseError> {
+        let Some(header) = input.first_chunk::<19>() else {
+            return Err(HeaderParseError::InputLengthOutOfRange(
+                Self::MIN_LEN as usize,
+                input.len(),
+            ));
+        };
+        let (marker, rest) = header.split_first_chunk::<16>().unwrap();
+        if !is_marker(marker) {
+            return Err(HeaderParseError::MalformedMarkerField);
+        }
+        let length = u16::from_be_bytes([rest[0], rest[1]]);
+        check_length(length)?;
+
+        Ok(BgpHeader {
+            marker: *marker,
+            length,
+            message_type: rest[2].into(),
+        })
+    }
+
     pub fn to_bytes(&self) -> Bytes {
         let mut buffer = BytesMut::with_capacity(Self::MIN_LEN as usize);
 
@@ -136,3 +204,37 @@ impl BgpHeader {
         buffer.freeze()
     }
 }
+
+/// The marker, and a length within what the message type allows (RFC 4271 6.1)
+impl Validate<HeaderParseError> for BgpHeader {
+    fn validate(&self) -> Result<(), HeaderParseError> {
+        if !is_marker(&self.marker) {
+            return Err(HeaderParseError::MalformedMarkerField);
+        }
+        let (min, max) = (self.message_type.min_len(), self.message_type.max_len());
+        if !(min..=max).contains(&self.length) {
+            return Err(HeaderParseError::LengthFieldOutOfRange {
+                min: min as usize,
+                max: max as usize,
+                actual: self.length as usize,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Compares the 16 octets at once
+pub(crate) fn is_marker(marker: &[u8; 16]) -> bool {
+    u128::from_ne_bytes(*marker) == u128::MAX
+}
+
+fn check_length(length: u16) -> Result<(), HeaderParseError> {
+    if !(BgpHeader::MIN_LEN..=BgpHeader::MAX_LEN).contains(&length) {
+        return Err(HeaderParseError::LengthFieldOutOfRange {
+            min: BgpHeader::MIN_LEN as usize,
+            max: BgpHeader::MAX_LEN as usize,
+            actual: length as usize,
+        });
+    }
+    Ok(())
+}
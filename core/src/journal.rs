@@ -0,0 +1,753 @@
+//! Append-only record of raw BGP messages, for reconstructing what a peer sent after the fact.
+//!
+//! A journal is a directory of numbered files, `00000001.bgpj` onwards, each starting with
+//! `"BGPJRNL\0" | version: u16`, followed by records laid out as, all integers big endian,
+//!
+//! ```text
+//! received seconds: u64 | nanoseconds: u32 | peer: 4 or 6, then 4 or 16 address bytes
+//! | direction: u8 | length: u32 | message as on the wire | CRC-32 of everything before: u32
+//! ```
+//!
+//! A process killed mid-write leaves a torn record at the end of the newest file, which
+//! [`Journal::open`] cuts off before appending again. Files compressed after the fact, named
+//! `00000001.bgpj.gz` or `.bz2`, are read by [`JournalReader`] and never appended to.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::bgp_message::{BgpMessage, MessageDecodeError, parse_packet};
+use crate::compression::Decompress;
+use crate::timestamped::Timestamped;
+use crate::update_message::ParserConfig;
+
+const MAGIC: &[u8; 8] = b"BGPJRNL\0";
+/// Bumped whenever the layout above changes
+pub const JOURNAL_VERSION: u16 = 1;
+const HEADER_LEN: u64 = 8 + 2;
+const EXTENSION: &str = "bgpj";
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("journal I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} is not a journal file")]
+    BadMagic(PathBuf),
+    #[error("{path} has unsupported journal version {version}")]
+    UnsupportedVersion { path: PathBuf, version: u16 },
+    #[error("{path} ends in a truncated record at offset {offset}")]
+    Truncated { path: PathBuf, offset: u64 },
+    #[error("{path} has a damaged record at offset {offset}")]
+    Checksum { path: PathBuf, offset: u64 },
+}
+
+/// Whether a message was received from or sent to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// When [`Journal`] starts a new file; a file always holds at least one record
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Largest file size in bytes
+    pub max_bytes: Option<u64>,
+    /// Longest time between the first and last record of a file, by their timestamps
+    pub max_age: Option<Duration>,
+}
+
+/// A single journaled message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalRecord {
+    pub received: SystemTime,
+    pub peer: IpAddr,
+    pub direction: Direction,
+    /// The whole message including its header
+    pub bytes: Bytes,
+}
+
+/// Appends records to the newest file of a journal directory
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    policy: RotationPolicy,
+    sequence: u32,
+    file: BufWriter<File>,
+    len: u64,
+    /// Timestamp of the first record in the current file
+    first: Option<SystemTime>,
+    recovered: u64,
+}
+
+/// Iterates the records of every file in a journal directory, oldest first
+#[derive(Debug)]
+pub struct JournalReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<(PathBuf, Bytes, u64)>,
+    failed: bool,
+}
+
+impl RotationPolicy {
+    pub fn size(max_bytes: u64) -> Self {
+        RotationPolicy {
+            max_bytes: Some(max_bytes),
+            max_age: None,
+        }
+    }
+
+    pub fn age(max_age: Duration) -> Self {
+        RotationPolicy {
+            max_bytes: None,
+            max_age: Some(max_age),
+        }
+    }
+}
+
+impl JournalRecord {
+    pub fn received(peer: IpAddr, message: Timestamped<Bytes>) -> Self {
+        JournalRecord {
+            received: message.received,
+            peer,
+            direction: Direction::Received,
+            bytes: message.value,
+        }
+    }
+
+    /// Decodes the journaled message again
+    pub fn message(&self) -> Result<Timestamped<BgpMessage>, MessageDecodeError> {
+        let (_, message) = parse_packet(&mut self.bytes.clone(), &ParserConfig::default())?;
+        Ok(Timestamped::at(self.received, message))
+    }
+
+    fn encoded_len(&self) -> u64 {
+        let peer = match self.peer {
+            IpAddr::V4(_) => 1 + 4,
+            IpAddr::V6(_) => 1 + 16,
+        };
+        (8 + 4 + peer + 1 + 4 + self.bytes.len() + 4) as u64
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        let start = buf.len();
+        let since_epoch = self
+            .received
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        buf.put_u64(since_epoch.as_secs());
+        buf.put_u32(since_epoch.subsec_nanos());
+        match self.peer {
+            IpAddr::V4(addr) => {
+                buf.put_u8(4);
+                buf.put_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                buf.put_u8(6);
+                buf.put_slice(&addr.octets());
+            }
+        }
+        buf.put_u8(match self.direction {
+            Direction::Received => 0,
+            Direction::Sent => 1,
+        });
+        buf.put_u32(self.bytes.len() as u32);
+        buf.put_slice(&self.bytes);
+        let crc = crc32(&buf[start..]);
+        buf.put_u32(crc);
+    }
+
+    /// Decodes the record at the start of `bytes`, `None` if it is incomplete
+    fn decode(bytes: &[u8]) -> Result<Option<(JournalRecord, usize)>, RecordError> {
+        let mut buf = bytes;
+        if buf.remaining() < 8 + 4 + 1 {
+            return Ok(None);
+        }
+        let secs = buf.get_u64();
+        let nanos = buf.get_u32();
+        let peer = match buf.get_u8() {
+            4 if buf.remaining() >= 4 => {
+                let mut octets = [0; 4];
+                buf.copy_to_slice(&mut octets);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 if buf.remaining() >= 16 => {
+                let mut octets = [0; 16];
+                buf.copy_to_slice(&mut octets);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            4 | 6 => return Ok(None),
+            _ => return Err(RecordError),
+        };
+        if buf.remaining() < 1 + 4 {
+            return Ok(None);
+        }
+        let direction = match buf.get_u8() {
+            0 => Direction::Received,
+            1 => Direction::Sent,
+            _ => return Err(RecordError),
+        };
+        let length = buf.get_u32() as usize;
+        if buf.remaining() < length + 4 {
+            return Ok(None);
+        }
+        let message = &buf[..length];
+        buf.advance(length);
+        let end = bytes.len() - buf.remaining();
+        if crc32(&bytes[..end]) != buf.get_u32() || nanos >= 1_000_000_000 {
+            return Err(RecordError);
+        }
+
+        let record = JournalRecord {
+            received: SystemTime::UNIX_EPOCH + Duration::new(secs, nanos),
+            peer,
+            direction,
+            bytes: Bytes::copy_from_slice(message),
+        };
+        Ok(Some((record, end + 4)))
+    }
+}
+
+/// A record failing its checksum or holding impossible values
+struct RecordError;
+
+impl Journal {
+    /// Opens the journal in `dir`, creating the directory if needed, and continues its newest
+    /// file after cutting off a torn record left by an interrupted write
+    pub fn open(dir: impl AsRef<Path>, policy: RotationPolicy) -> Result<Self, JournalError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let Some(path) = journal_files(&dir)?.pop() else {
+            return Self::create(dir, policy, 1, 0);
+        };
+        let sequence = sequence_of(&path).unwrap_or(1);
+        if path != file_path(&dir, sequence) {
+            // Compressed, so closed for good
+            return Self::create(dir, policy, sequence + 1, 0);
+        }
+
+        let bytes = fs::read(&path)?;
+        if (bytes.len() as u64) < HEADER_LEN {
+            // Torn while writing the header, nothing to keep
+            return Self::create(dir, policy, sequence, bytes.len() as u64);
+        }
+        check_header(&path, &bytes)?;
+
+        let mut valid = HEADER_LEN as usize;
+        let mut first = None;
+        while let Ok(Some((record, len))) = JournalRecord::decode(&bytes[valid..]) {
+            first.get_or_insert(record.received);
+            valid += len;
+        }
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(valid as u64)?;
+        let mut file = BufWriter::new(file);
+        io::Seek::seek(&mut file, io::SeekFrom::End(0))?;
+
+        Ok(Journal {
+            dir,
+            policy,
+            sequence,
+            file,
+            len: valid as u64,
+            first,
+            recovered: (bytes.len() - valid) as u64,
+        })
+    }
+
+    fn create(
+        dir: PathBuf,
+        policy: RotationPolicy,
+        sequence: u32,
+        recovered: u64,
+    ) -> Result<Self, JournalError> {
+        let file = File::create(file_path(&dir, sequence))?;
+        let mut journal = Journal {
+            dir,
+            policy,
+            sequence,
+            file: BufWriter::new(file),
+            len: 0,
+            first: None,
+            recovered,
+        };
+        journal.write_header()?;
+        Ok(journal)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.file.write_all(MAGIC)?;
+        self.file.write_all(&JOURNAL_VERSION.to_be_bytes())?;
+        self.len = HEADER_LEN;
+        self.first = None;
+        Ok(())
+    }
+
+    /// Bytes cut off the newest file by [`Journal::open`]
+    pub fn recovered(&self) -> u64 {
+        self.recovered
+    }
+
+    /// The file records are appended to
+    pub fn path(&self) -> PathBuf {
+        file_path(&self.dir, self.sequence)
+    }
+
+    /// Appends `record`, starting a new file first if the rotation policy calls for it.
+    ///
+    /// Records are buffered until [`Journal::flush`] or the next rotation.
+    pub fn append(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        let len = record.encoded_len();
+        if let Some(first) = self.first {
+            let too_large = self
+                .policy
+                .max_bytes
+                .is_some_and(|max_bytes| self.len + len > max_bytes);
+            let too_old = self.policy.max_age.is_some_and(|max_age| {
+                record
+                    .received
+                    .duration_since(first)
+                    .is_ok_and(|age| age >= max_age)
+            });
+            if too_large || too_old {
+                self.rotate()?;
+            }
+        }
+
+        let mut buf = BytesMut::with_capacity(len as usize);
+        record.encode(&mut buf);
+        self.file.write_all(&buf)?;
+        self.len += len;
+        self.first.get_or_insert(record.received);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), JournalError> {
+        Ok(self.file.flush()?)
+    }
+
+    fn rotate(&mut self) -> Result<(), JournalError> {
+        self.file.flush()?;
+        self.sequence += 1;
+        self.file = BufWriter::new(File::create(self.path())?);
+        Ok(self.write_header()?)
+    }
+}
+
+impl JournalReader {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, JournalError> {
+        Ok(JournalReader {
+            files: journal_files(dir.as_ref())?.into_iter(),
+            current: None,
+            failed: false,
+        })
+    }
+
+    fn next_record(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        loop {
+            let Some((path, bytes, offset)) = &mut self.current else {
+                let Some(path) = self.files.next() else {
+                    return Ok(None);
+                };
+                let mut bytes = Vec::new();
+                Decompress::new(BufReader::new(File::open(&path)?))?.read_to_end(&mut bytes)?;
+                let bytes = Bytes::from(bytes);
+                check_header(&path, &bytes)?;
+                self.current = Some((path, bytes, HEADER_LEN));
+                continue;
+            };
+
+            let rest = &bytes[*offset as usize..];
+            if rest.is_empty() {
+                self.current = None;
+                continue;
+            }
+            return match JournalRecord::decode(rest) {
+                Ok(Some((record, len))) => {
+                    *offset += len as u64;
+                    Ok(Some(record))
+                }
+                Ok(None) => Err(JournalError::Truncated {
+                    path: path.clone(),
+                    offset: *offset,
+                }),
+                Err(RecordError) => Err(JournalError::Checksum {
+                    path: path.clone(),
+                    offset: *offset,
+                }),
+            };
+        }
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = Result<JournalRecord, JournalError>;
+
+    /// Stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_record().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+fn check_header(path: &Path, bytes: &[u8]) -> Result<(), JournalError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(JournalError::BadMagic(path.to_path_buf()));
+    }
+    if (bytes.len() as u64) < HEADER_LEN {
+        return Err(JournalError::Truncated {
+            path: path.to_path_buf(),
+            offset: 0,
+        });
+    }
+    let version = u16::from_be_bytes([bytes[8], bytes[9]]);
+    if version != JOURNAL_VERSION {
+        return Err(JournalError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+        });
+    }
+    Ok(())
+}
+
+fn file_path(dir: &Path, sequence: u32) -> PathBuf {
+    dir.join(format!("{sequence:08}.{EXTENSION}"))
+}
+
+fn sequence_of(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    let name = [".gz", ".bz2"]
+        .into_iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    name.strip_suffix(EXTENSION)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()
+}
+
+/// The journal files in `dir`, oldest first
+fn journal_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<(u32, PathBuf)> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter_map(|path| match path {
+            Ok(path) => sequence_of(&path).map(|sequence| Ok((sequence, path))),
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<io::Result<_>>()?;
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// CRC-32 as used by Ethernet and gzip
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continues `crc`, the CRC-32 of the data so far, over `data`
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    let mut crc = !crc;
+    for &byte in data {
+        crc = TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(feature = "tokio")]
+pub use writer::JournalWriter;
+
+#[cfg(feature = "tokio")]
+mod writer {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+
+    use super::{Journal, JournalError, JournalRecord};
+
+    /// Queues records for a [`Journal`] written on a blocking thread, so disk latency never
+    /// holds up a session. Cheap to clone, one per session.
+    #[derive(Debug, Clone)]
+    pub struct JournalWriter {
+        sender: mpsc::Sender<JournalRecord>,
+        dropped: Arc<AtomicU64>,
+    }
+
+    impl Journal {
+        /// Moves the journal to a blocking task fed by a queue of `capacity` records.
+        ///
+        /// The task flushes whenever the queue runs empty and ends once every writer is
+        /// dropped, or at the first I/O error.
+        ///
+        /// # Panics
+        ///
+        /// When `capacity` is zero
+        pub fn spawn(
+            mut self,
+            capacity: usize,
+        ) -> (JournalWriter, JoinHandle<Result<(), JournalError>>) {
+            let (sender, mut receiver) = mpsc::channel::<JournalRecord>(capacity);
+            let task = tokio::task::spawn_blocking(move || {
+                while let Some(record) = receiver.blocking_recv() {
+                    self.append(&record)?;
+                    while let Ok(record) = receiver.try_recv() {
+                        self.append(&record)?;
+                    }
+                    self.flush()?;
+                }
+                self.flush()
+            });
+            let writer = JournalWriter {
+                sender,
+                dropped: Arc::default(),
+            };
+            (writer, task)
+        }
+    }
+
+    impl JournalWriter {
+        /// Queues `record` without waiting, dropping it if the queue is full or the journal
+        /// task has stopped
+        pub fn record(&self, record: JournalRecord) {
+            if self.sender.try_send(record).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        /// Records dropped by every clone of this writer
+        pub fn dropped(&self) -> u64 {
+            self.dropped.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Writers are equal when they feed the same journal
+    impl PartialEq for JournalWriter {
+        fn eq(&self, other: &Self) -> bool {
+            self.sender.same_channel(&other.sender)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::notification_message::{NotificationErrorCode, NotificationMessage};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bgp-journal-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn record(secs: u64, message: &BgpMessage) -> JournalRecord {
+        JournalRecord {
+            received: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            peer: "2001:db8::1".parse().unwrap(),
+            direction: Direction::Received,
+            bytes: message.to_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_write_rotate_read_back() {
+        let dir = temp_dir("rotate");
+        let notification = BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::HoldTimeExpired,
+            vec![],
+        ));
+        // A keepalive from an IPv6 peer takes 57 bytes, so two fit after the header
+        let mut journal = Journal::open(&dir, RotationPolicy::size(10 + 2 * 57 + 2)).unwrap();
+        let records: Vec<JournalRecord> = (0..5)
+            .map(|i| record(1_700_000_000 + i, &BgpMessage::Keepalive))
+            .chain([record(1_700_000_005, &notification)])
+            .collect();
+        for record in &records {
+            journal.append(record).unwrap();
+        }
+        journal.flush().unwrap();
+        assert_eq!(journal_files(&dir).unwrap().len(), 3);
+
+        let read: Vec<JournalRecord> = JournalReader::open(&dir)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, records);
+        let message = read[5].message().unwrap();
+        assert_eq!(message.value, notification);
+        assert_eq!(message.received, records[5].received);
+
+        // Rotation by time starts a new file once the first record is a minute old
+        let dir = temp_dir("rotate-age");
+        let mut journal =
+            Journal::open(&dir, RotationPolicy::age(Duration::from_secs(60))).unwrap();
+        for secs in [0, 30, 59, 60, 200] {
+            journal
+                .append(&record(secs, &BgpMessage::Keepalive))
+                .unwrap();
+        }
+        journal.flush().unwrap();
+        assert_eq!(journal_files(&dir).unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `data` as a gzip member of stored blocks
+    #[cfg(feature = "compression")]
+    fn gzip_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        let mut chunks = data.chunks(0xffff).peekable();
+        while let Some(chunk) = chunks.next() {
+            out.push(chunks.peek().is_none() as u8);
+            out.extend((chunk.len() as u16).to_le_bytes());
+            out.extend((!(chunk.len() as u16)).to_le_bytes());
+            out.extend(chunk);
+        }
+        out.extend(crc32(data).to_le_bytes());
+        out.extend((data.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_files() {
+        let dir = temp_dir("compressed");
+        let mut journal = Journal::open(&dir, RotationPolicy::size(10 + 2 * 57 + 2)).unwrap();
+        let records: Vec<JournalRecord> = (0..4)
+            .map(|i| record(1_700_000_000 + i, &BgpMessage::Keepalive))
+            .collect();
+        for record in &records {
+            journal.append(record).unwrap();
+        }
+        journal.flush().unwrap();
+        drop(journal);
+
+        // Both files compressed as a rotation script would
+        for sequence in [1, 2] {
+            let path = file_path(&dir, sequence);
+            let compressed = gzip_stored(&fs::read(&path).unwrap());
+            fs::write(path.with_extension("bgpj.gz"), compressed).unwrap();
+            fs::remove_file(path).unwrap();
+        }
+        let read: Vec<JournalRecord> = JournalReader::open(&dir)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, records);
+
+        // Appending goes to a new file rather than into the compressed one
+        let mut journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        journal
+            .append(&record(1_700_000_004, &BgpMessage::Keepalive))
+            .unwrap();
+        journal.flush().unwrap();
+        assert!(file_path(&dir, 3).exists());
+        assert_eq!(JournalReader::open(&dir).unwrap().count(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_tail_recovered_on_open() {
+        let dir = temp_dir("torn");
+        let mut journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        for secs in 0..3 {
+            journal
+                .append(&record(secs, &BgpMessage::Keepalive))
+                .unwrap();
+        }
+        journal.flush().unwrap();
+        let path = journal.path();
+        drop(journal);
+
+        // Tear the last record as if the process died mid-write
+        let intact = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(intact - 7).unwrap();
+        drop(file);
+
+        let mut reader = JournalReader::open(&dir).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(JournalError::Truncated { .. }))
+        ));
+        assert!(reader.next().is_none());
+
+        let mut journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        assert_eq!(journal.recovered(), 57 - 7);
+        journal.append(&record(3, &BgpMessage::Keepalive)).unwrap();
+        journal.flush().unwrap();
+        let read: Vec<u64> = JournalReader::open(&dir)
+            .unwrap()
+            .map(|record| {
+                let received = record.unwrap().received;
+                received
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            })
+            .collect();
+        assert_eq!(read, vec![0, 1, 3]);
+
+        // A flipped byte fails the checksum and is cut off along with everything after it
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN as usize + 57 + 20] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            JournalReader::open(&dir).unwrap().nth(1),
+            Some(Err(JournalError::Checksum { .. }))
+        ));
+        let journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        assert_eq!(journal.recovered(), 2 * 57);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_writer_task() {
+        let dir = temp_dir("writer");
+        let journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        let (writer, task) = journal.spawn(16);
+        for secs in 0..10 {
+            writer.record(record(secs, &BgpMessage::Keepalive));
+        }
+        assert_eq!(writer.dropped(), 0);
+        drop(writer);
+        task.await.unwrap().unwrap();
+        assert_eq!(JournalReader::open(&dir).unwrap().count(), 10);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,847 @@
+//! A small JSON value for the text formats other tools speak, such as exabgp's.
+//!
+//! Objects keep their keys in insertion order so output is stable and can be compared
+//! byte for byte. `{}` prints compact JSON, `{:#}` indents it by two spaces.
+
+use std::fmt::{self, Write};
+use std::ops::Index;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Input that isn't a single well-formed JSON value
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid JSON at offset {offset}: {reason}")]
+pub struct ParseJsonError {
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl Json {
+    /// An object from its members, in order
+    pub fn object<K: Into<String>>(members: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// The member of an object, the first one if the key repeats
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// A number without a fraction that fits in a `u64`
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n < u64::MAX as f64 => {
+                Some(*n as u64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: Option<usize>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            // JSON has no representation for these
+            Json::Number(n) if !n.is_finite() => f.write_str("null"),
+            Json::Number(n) => write!(f, "{n}"),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) if values.is_empty() => f.write_str("[]"),
+            Json::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, indent.map(|depth| depth + 1))?;
+                    value.write(f, indent.map(|depth| depth + 1))?;
+                }
+                newline(f, indent)?;
+                f.write_char(']')
+            }
+            Json::Object(members) if members.is_empty() => f.write_str("{}"),
+            Json::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, indent.map(|depth| depth + 1))?;
+                    write_string(f, key)?;
+                    f.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    value.write(f, indent.map(|depth| depth + 1))?;
+                }
+                newline(f, indent)?;
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn newline(f: &mut fmt::Formatter<'_>, indent: Option<usize>) -> fmt::Result {
+    if let Some(depth) = indent {
+        f.write_char('\n')?;
+        for _ in 0..depth {
+            f.write_str("  ")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, f.alternate().then_some(0))
+    }
+}
+
+/// The member of an object, `Null` when missing as in JavaScript
+impl Index<&str> for Json {
+    type Output = Json;
+
+    fn index(&self, key: &str) -> &Json {
+        self.get(key).unwrap_or(&Json::Null)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Self {
+        Json::Number(value.into())
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Self {
+        Json::Array(values)
+    }
+}
+
+impl FromStr for Json {
+    type Err = ParseJsonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let value = parser.value(0)?;
+        parser.whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+/// Deeper input is rejected rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &'static str) -> ParseJsonError {
+        ParseJsonError {
+            offset: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, reason: &'static str) -> Result<(), ParseJsonError> {
+        self.whitespace();
+        if self.peek() != Some(byte) {
+            return Err(self.error(reason));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, ParseJsonError> {
+        if !self.input[self.pos..].starts_with(literal) {
+            return Err(self.error("unexpected character"));
+        }
+        self.pos += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, ParseJsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = vec![];
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = vec![];
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':', "expected ':'")?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, ParseJsonError> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        let text = &self.input[start..self.pos];
+        // Rust accepts a few forms JSON doesn't, such as "1.", ".5" and "01"
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        let (int, rest) = digits.split_at(
+            digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len()),
+        );
+        let leading_zero = int.len() > 1 && int.starts_with('0');
+        let empty_fraction =
+            rest.starts_with('.') && !rest[1..].starts_with(|c: char| c.is_ascii_digit());
+        let valid = !int.is_empty() && !leading_zero && !empty_fraction;
+        match text.parse() {
+            Ok(n) if valid => Ok(Json::Number(n)),
+            _ => {
+                self.pos = start;
+                Err(self.error("invalid number"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseJsonError> {
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let Some(c) = self.input[self.pos..].chars().next() else {
+                return Err(self.error("unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let high = self.hex4()?;
+                            let code = if (0xd800..0xdc00).contains(&high) {
+                                if !self.input[self.pos..].starts_with("\\u") {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                            } else {
+                                high
+                            };
+                            value.push(
+                                char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?,
+                            );
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    value.push(escaped);
+                    self.pos += 1;
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                c => {
+                    value.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseJsonError> {
+        let code = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use ser::{ToJsonError, to_json};
+
+/// A [`serde::Serializer`] building [`Json`] the way serde_json lays values out: externally
+/// tagged enum variants, `None` and unit as `null`, and bytes as an array of numbers
+#[cfg(feature = "serde")]
+mod ser {
+    use serde::Serialize;
+    use serde::ser;
+
+    use super::Json;
+
+    /// The serde representation of `value`; integers beyond 2^53 lose precision
+    pub fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<Json, ToJsonError> {
+        value.serialize(Serializer)
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    #[error("{0}")]
+    pub struct ToJsonError(String);
+
+    impl ser::Error for ToJsonError {
+        fn custom<T: std::fmt::Display>(msg: T) -> Self {
+            ToJsonError(msg.to_string())
+        }
+    }
+
+    struct Serializer;
+
+    /// Wraps `value` in an object keyed by the variant when there is one
+    fn tagged(variant: Option<&'static str>, value: Json) -> Json {
+        match variant {
+            Some(variant) => Json::object([(variant, value)]),
+            None => value,
+        }
+    }
+
+    impl ser::Serializer for Serializer {
+        type Ok = Json;
+        type Error = ToJsonError;
+        type SerializeSeq = Seq;
+        type SerializeTuple = Seq;
+        type SerializeTupleStruct = Seq;
+        type SerializeTupleVariant = Seq;
+        type SerializeMap = Map;
+        type SerializeStruct = Map;
+        type SerializeStructVariant = Map;
+
+        fn serialize_bool(self, v: bool) -> Result<Json, ToJsonError> {
+            Ok(Json::Bool(v))
+        }
+
+        fn serialize_i8(self, v: i8) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_i16(self, v: i16) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_i32(self, v: i32) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_i64(self, v: i64) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v as f64))
+        }
+
+        fn serialize_u8(self, v: u8) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_u16(self, v: u16) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_u32(self, v: u32) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_u64(self, v: u64) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v as f64))
+        }
+
+        fn serialize_f32(self, v: f32) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v.into()))
+        }
+
+        fn serialize_f64(self, v: f64) -> Result<Json, ToJsonError> {
+            Ok(Json::Number(v))
+        }
+
+        fn serialize_char(self, v: char) -> Result<Json, ToJsonError> {
+            Ok(Json::String(v.into()))
+        }
+
+        fn serialize_str(self, v: &str) -> Result<Json, ToJsonError> {
+            Ok(Json::String(v.to_owned()))
+        }
+
+        fn serialize_bytes(self, v: &[u8]) -> Result<Json, ToJsonError> {
+            Ok(Json::Array(
+                v.iter().map(|&byte| Json::Number(byte.into())).collect(),
+            ))
+        }
+
+        fn serialize_none(self) -> Result<Json, ToJsonError> {
+            Ok(Json::Null)
+        }
+
+        fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Json, ToJsonError> {
+            value.serialize(self)
+        }
+
+        fn serialize_unit(self) -> Result<Json, ToJsonError> {
+            Ok(Json::Null)
+        }
+
+        fn serialize_unit_struct(self, _name: &'static str) -> Result<Json, ToJsonError> {
+            Ok(Json::Null)
+        }
+
+        fn serialize_unit_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            variant: &'static str,
+        ) -> Result<Json, ToJsonError> {
+            Ok(Json::from(variant))
+        }
+
+        fn serialize_newtype_struct<T: Serialize + ?Sized>(
+            self,
+            _name: &'static str,
+            value: &T,
+        ) -> Result<Json, ToJsonError> {
+            value.serialize(self)
+        }
+
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(
+            self,
+            _name: &'static str,
+            _index: u32,
+            variant: &'static str,
+            value: &T,
+        ) -> Result<Json, ToJsonError> {
+            Ok(tagged(Some(variant), value.serialize(self)?))
+        }
+
+        fn serialize_seq(self, len: Option<usize>) -> Result<Seq, ToJsonError> {
+            Ok(Seq::new(None, len.unwrap_or(0)))
+        }
+
+        fn serialize_tuple(self, len: usize) -> Result<Seq, ToJsonError> {
+            Ok(Seq::new(None, len))
+        }
+
+        fn serialize_tuple_struct(
+            self,
+            _name: &'static str,
+            len: usize,
+        ) -> Result<Seq, ToJsonError> {
+            Ok(Seq::new(None, len))
+        }
+
+        fn serialize_tuple_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            variant: &'static str,
+            len: usize,
+        ) -> Result<Seq, ToJsonError> {
+            Ok(Seq::new(Some(variant), len))
+        }
+
+        fn serialize_map(self, _len: Option<usize>) -> Result<Map, ToJsonError> {
+            Ok(Map::new(None))
+        }
+
+        fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Map, ToJsonError> {
+            Ok(Map::new(None))
+        }
+
+        fn serialize_struct_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            variant: &'static str,
+            _len: usize,
+        ) -> Result<Map, ToJsonError> {
+            Ok(Map::new(Some(variant)))
+        }
+    }
+
+    struct Seq {
+        variant: Option<&'static str>,
+        values: Vec<Json>,
+    }
+
+    impl Seq {
+        fn new(variant: Option<&'static str>, len: usize) -> Self {
+            Seq {
+                variant,
+                values: Vec::with_capacity(len),
+            }
+        }
+
+        fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ToJsonError> {
+            self.values.push(value.serialize(Serializer)?);
+            Ok(())
+        }
+
+        fn finish(self) -> Result<Json, ToJsonError> {
+            Ok(tagged(self.variant, Json::Array(self.values)))
+        }
+    }
+
+    impl ser::SerializeSeq for Seq {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_element<T: Serialize + ?Sized>(
+            &mut self,
+            value: &T,
+        ) -> Result<(), ToJsonError> {
+            self.push(value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    impl ser::SerializeTuple for Seq {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_element<T: Serialize + ?Sized>(
+            &mut self,
+            value: &T,
+        ) -> Result<(), ToJsonError> {
+            self.push(value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    impl ser::SerializeTupleStruct for Seq {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ToJsonError> {
+            self.push(value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    impl ser::SerializeTupleVariant for Seq {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ToJsonError> {
+            self.push(value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    struct Map {
+        variant: Option<&'static str>,
+        members: Vec<(String, Json)>,
+        key: Option<String>,
+    }
+
+    impl Map {
+        fn new(variant: Option<&'static str>) -> Self {
+            Map {
+                variant,
+                members: Vec::new(),
+                key: None,
+            }
+        }
+
+        fn insert<T: Serialize + ?Sized>(
+            &mut self,
+            key: String,
+            value: &T,
+        ) -> Result<(), ToJsonError> {
+            self.members.push((key, value.serialize(Serializer)?));
+            Ok(())
+        }
+
+        fn finish(self) -> Result<Json, ToJsonError> {
+            Ok(tagged(self.variant, Json::Object(self.members)))
+        }
+    }
+
+    impl ser::SerializeMap for Map {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ToJsonError> {
+            self.key = Some(match key.serialize(Serializer)? {
+                Json::String(key) => key,
+                key @ (Json::Number(_) | Json::Bool(_)) => key.to_string(),
+                _ => return Err(ToJsonError("map keys must be strings or numbers".into())),
+            });
+            Ok(())
+        }
+
+        fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ToJsonError> {
+            let key = self
+                .key
+                .take()
+                .expect("serde serializes a key before its value");
+            self.insert(key, value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    impl ser::SerializeStruct for Map {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), ToJsonError> {
+            self.insert(key.to_owned(), value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+
+    impl ser::SerializeStructVariant for Map {
+        type Ok = Json;
+        type Error = ToJsonError;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), ToJsonError> {
+            self.insert(key.to_owned(), value)
+        }
+
+        fn end(self) -> Result<Json, ToJsonError> {
+            self.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = r#"{"b":[1,-2.5,1e3,true,null],"a":{"s":"q\"\\\n\u0001é"},"e":[],"o":{}}"#;
+        let json: Json = text.parse().unwrap();
+        assert_eq!(
+            json.get("b").unwrap().as_array().unwrap()[2].as_u64(),
+            Some(1000)
+        );
+        assert_eq!(
+            json.get("a").unwrap().get("s").unwrap().as_str(),
+            Some("q\"\\\n\u{1}é")
+        );
+        assert_eq!(
+            json.to_string(),
+            r#"{"b":[1,-2.5,1000,true,null],"a":{"s":"q\"\\\n\u0001é"},"e":[],"o":{}}"#
+        );
+        assert_eq!(json.to_string().parse::<Json>().unwrap(), json);
+        assert_eq!(
+            format!(
+                "{:#}",
+                Json::object([("a", Json::from(vec![1.into(), 2.into()]))])
+            ),
+            "{\n  \"a\": [\n    1,\n    2\n  ]\n}"
+        );
+        assert_eq!("\"\\ud83d\\ude00\"".parse(), Ok(Json::from("😀")));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (text, offset) in [
+            ("", 0),
+            ("[1,]", 3),
+            ("{\"a\" 1}", 5),
+            ("01", 0),
+            ("1.", 0),
+            ("\"abc", 4),
+            ("[1] x", 4),
+            ("tru", 0),
+        ] {
+            assert_eq!(
+                text.parse::<Json>().map_err(|err| err.offset),
+                Err(offset),
+                "{text:?}"
+            );
+        }
+        assert!("[".repeat(1000).parse::<Json>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_json() {
+        use crate::message::{NotificationErrorCode, NotificationMessage, OriginType};
+
+        let notification =
+            NotificationMessage::new(NotificationErrorCode::HoldTimeExpired, vec![1, 2]);
+        assert_eq!(
+            to_json(&notification).unwrap().to_string(),
+            r#"{"error_codes":"hold_time_expired","data":[1,2]}"#
+        );
+        let cease = NotificationErrorCode::Unknown(7, 1);
+        assert_eq!(to_json(&cease).unwrap().to_string(), r#"{"unknown":[7,1]}"#);
+        assert_eq!(to_json(&Some(OriginType::Igp)).unwrap(), Json::from("igp"));
+        let map = std::collections::BTreeMap::from([(64500, "a")]);
+        assert_eq!(to_json(&map).unwrap().to_string(), r#"{"64500":"a"}"#);
+    }
+}
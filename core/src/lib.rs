@@ -1,16 +1,151 @@
+//! Decoding, encoding and tracking of BGP messages and routes.
+//!
+//! The wire parsers ([`message`]), the RIBs ([`rib`]), filters, monitors, the JSON, exabgp
+//! and journal formats build without any optional dependency. The rest is behind features:
+//!
+//! | feature | adds |
+//! |---|---|
+//! | `smallvec` (default) | inline storage for short [`message::SmallList`]s |
+//! | `mrt` (default) | `mrt`, reading and writing MRT dumps |
+//! | `bmp` (default) | `bmp`, decoding BMP messages, and `peer::PeerDownReason::from_bmp` |
+//! | `rayon` | `mrt::MrtReader::par_records`, decoding on the rayon thread pool, implies `mrt` |
+//! | `compression` | gzip and bzip2 decoders in [`compression`], for MRT dumps and journals |
+//! | `serde` | `Serialize` and `Deserialize` for messages, route events and configuration |
+//! | `cbor` | `archive`, the compact event archive |
+//! | `tokio` | `session`, the BMP station, `journal::JournalWriter`, `rib::ShardedLocRib` and `monitor::Watch` subscriptions; implies `mrt` and `bmp` |
+//! | `md5sig` | TCP MD5 signatures on sessions, implies `tokio` |
+//! | `metrics` | `metrics`, Prometheus exposition |
+//! | `pcap` | `pcap`, reading BGP out of packet captures |
+//! | `tracing` | `trace`, structured diagnostics from the decoders, sessions and RIBs |
+//! | `sqlite` | `sqlite`, a durable history of route events and peer state, implies `tokio` |
+//! | `ws` | `ws`, a live feed of route events over WebSocket, implies `tokio` |
+//!
+//! The events of peers going up and down live in [`peer`], so archives and RIBs use them
+//! without `tokio`; `session` re-exports them.
+
+/// Raises a [`trace`] event, `event!(Warn, "message", "field" = value, ...)`. Without the
+/// `tracing` feature the values are type checked but never evaluated.
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $message:literal $(, $name:literal = $value:expr)* $(,)?) => {
+        if $crate::trace::enabled($crate::trace::Level::$level) {
+            $crate::trace::event(
+                $crate::trace::Level::$level,
+                module_path!(),
+                $message,
+                &[$(($name, &$value as &dyn ::std::fmt::Display)),*],
+            );
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $message:literal $(, $name:literal = $value:expr)* $(,)?) => {{
+        let _ = || {
+            $(let _ = &$value;)*
+        };
+    }};
+}
+
+/// Enters a [`trace`] span with fields, `let _span = span!("field" = value, ...)`, exited when
+/// dropped. Without the `tracing` feature the values are type checked but never evaluated.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($name:literal = $value:expr),* $(,)?) => {
+        match $crate::trace::active() {
+            true => $crate::trace::Span::enter(&[$(($name, &$value as &dyn ::std::fmt::Display)),*]),
+            false => $crate::trace::Span::none(),
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($name:literal = $value:expr),* $(,)?) => {{
+        let _ = || {
+            $(let _ = &$value;)*
+        };
+        $crate::NoSpan
+    }};
+}
+
+/// What [`span!`] enters without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+struct NoSpan;
+
+mod address_family;
+mod asn;
 mod attribute;
+mod bgp_message;
+mod capability;
+mod fingerprint;
+mod header;
+mod list;
 mod notification_message;
 mod open_message;
+mod route;
+mod route_refresh_message;
+mod spans;
+mod timestamped;
 mod update_message;
+mod validate;
+
+pub mod aggregate;
+pub mod annotate;
+#[cfg(feature = "cbor")]
+pub mod archive;
+#[cfg(feature = "bmp")]
+pub mod bmp;
+pub mod compression;
+pub mod enrich;
+pub mod exabgp;
+pub mod filter;
+pub mod journal;
+pub mod json;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod monitor;
+#[cfg(feature = "mrt")]
+pub mod mrt;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod peer;
+pub mod rib;
+pub mod ris_live;
+pub mod rpki;
+#[cfg(feature = "tokio")]
+pub mod session;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod testing;
+#[cfg(feature = "tracing")]
+pub mod trace;
+pub mod trie;
+#[cfg(feature = "ws")]
+pub mod ws;
 
 pub mod message {
+    pub use crate::address_family::*;
+    pub use crate::asn::*;
     pub use crate::attribute::*;
+    pub use crate::bgp_message::*;
+    pub use crate::capability::*;
+    pub use crate::fingerprint::raw_fingerprint;
+    pub use crate::header::*;
+    pub use crate::list::SmallList;
     pub use crate::notification_message::*;
     pub use crate::open_message::*;
+    pub use crate::route::*;
+    pub use crate::route_refresh_message::*;
+    pub use crate::timestamped::*;
     pub use crate::update_message::*;
+    pub use crate::validate::*;
 }
 
 pub mod error {
+    use std::ops::Range;
+
     use bytes::Bytes;
 
     #[derive(Debug, PartialEq, Copy, Clone)]
@@ -22,36 +157,68 @@ pub mod error {
         MalformedAsPath,
         OptionalAttributeError,
         InvalidNetworkField,
+        /// A list held more elements than [`ParserConfig::max_collection_len`] allows
+        ///
+        /// [`ParserConfig::max_collection_len`]: crate::message::ParserConfig::max_collection_len
+        CollectionTooLong,
         Other,
     }
 
     #[derive(Debug, PartialEq, Clone)]
     pub struct Error {
         pub kind: ErrorKind,
+        /// Where the octets in error are in the message body, for the kinds whose
+        /// NOTIFICATION carries them
+        pub span: Option<Range<usize>>,
+        /// A copy of the octets of `span`, once [`Error::materialize`]d. Errors don't hold
+        /// the message otherwise, so keeping them keeps no buffer alive.
         pub data: Option<Bytes>,
     }
 
     impl ErrorKind {
-        pub fn with_bytes(&self, bytes: Bytes) -> Error {
-            let data = match self {
-                ErrorKind::AttributeLengthErr => Some(bytes),
-                ErrorKind::MalformedAsPath => Some(bytes),
-                ErrorKind::InvalidOrigin => Some(bytes),
-                ErrorKind::OptionalAttributeError => Some(bytes),
+        /// The error, locating the octets at `span` for the kinds whose NOTIFICATION carries
+        /// them
+        pub fn with_span(&self, span: Range<usize>) -> Error {
+            let span = match self {
+                ErrorKind::AttributeLengthErr => Some(span),
+                ErrorKind::MalformedAsPath => Some(span),
+                ErrorKind::InvalidOrigin => Some(span),
+                ErrorKind::OptionalAttributeError => Some(span),
                 _ => None,
             };
 
             Error {
-                kind: self.clone(),
-                data,
+                kind: *self,
+                span,
+                data: None,
             }
         }
 
         pub fn as_err(&self) -> Error {
             Error {
-                kind: self.clone(),
+                kind: *self,
+                span: None,
                 data: None,
             }
         }
     }
+
+    impl Error {
+        /// The octets in error, from the message body the error was found in
+        pub fn data_in<'a>(&self, body: &'a [u8]) -> Option<&'a [u8]> {
+            body.get(self.span.clone()?)
+        }
+
+        /// Copies the octets in error out of the message body, for the NOTIFICATION answering
+        /// the error to carry them
+        pub fn materialize(&mut self, body: &[u8]) {
+            self.data = self.data_in(body).map(Bytes::copy_from_slice);
+        }
+
+        /// The error of a decoder given the body from `offset` on
+        pub(crate) fn offset_by(mut self, offset: usize) -> Self {
+            self.span = self.span.map(|span| span.start + offset..span.end + offset);
+            self
+        }
+    }
 }
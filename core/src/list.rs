@@ -0,0 +1,262 @@
+//! A list keeping its first few elements inline, for the short lists of AS paths and
+//! communities.
+//!
+//! With the `smallvec` feature, on by default, a [`SmallList`] of up to `N` elements doesn't
+//! allocate; without it, it's a `Vec`. Either way it reads as a slice, so the container may
+//! change without changing the API.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut, RangeBounds};
+
+#[cfg(feature = "smallvec")]
+type Inner<T, const N: usize> = smallvec::SmallVec<[T; N]>;
+#[cfg(not(feature = "smallvec"))]
+type Inner<T, const N: usize> = Vec<T>;
+
+/// A growable list of `T`, the first `N` stored inline, see the [module](self) description
+#[derive(Clone)]
+pub struct SmallList<T, const N: usize>(Inner<T, N>);
+
+impl<T, const N: usize> SmallList<T, N> {
+    pub fn new() -> Self {
+        SmallList(Inner::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        SmallList(Inner::with_capacity(capacity))
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.0.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) {
+        self.0.insert(index, value);
+    }
+
+    /// Inserts `values` at `index`, in order
+    pub fn insert_many(&mut self, index: usize, values: impl IntoIterator<Item = T>) {
+        #[cfg(feature = "smallvec")]
+        self.0.insert_many(index, values);
+        #[cfg(not(feature = "smallvec"))]
+        self.0.splice(index..index, values);
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        self.0.remove(index)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        self.0.retain(|value| keep(value));
+    }
+
+    pub fn drain(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> impl DoubleEndedIterator<Item = T> + '_ {
+        self.0.drain(range)
+    }
+
+    /// Whether the elements are stored apart from the list, as they are once there are more
+    /// than `N`
+    pub fn spilled(&self) -> bool {
+        #[cfg(feature = "smallvec")]
+        return self.0.spilled();
+        #[cfg(not(feature = "smallvec"))]
+        return self.0.capacity() > 0;
+    }
+
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.0.to_vec()
+    }
+}
+
+impl<T: PartialEq, const N: usize> SmallList<T, N> {
+    pub fn dedup(&mut self) {
+        self.0.dedup();
+    }
+}
+
+impl<T, const N: usize> Default for SmallList<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Deref for SmallList<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T, const N: usize> DerefMut for SmallList<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
+impl<T, const N: usize> AsRef<[T]> for SmallList<T, N> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for SmallList<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, const N: usize, const M: usize> PartialEq<SmallList<T, M>> for SmallList<T, N> {
+    fn eq(&self, other: &SmallList<T, M>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for SmallList<T, N> {}
+
+impl<T: PartialEq, const N: usize> PartialEq<Vec<T>> for SmallList<T, N> {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq<[T]> for SmallList<T, N> {
+    fn eq(&self, other: &[T]) -> bool {
+        self[..] == *other
+    }
+}
+
+impl<T: PartialEq, const N: usize, const M: usize> PartialEq<[T; M]> for SmallList<T, N> {
+    fn eq(&self, other: &[T; M]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialOrd, const N: usize> PartialOrd for SmallList<T, N> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self[..].partial_cmp(&other[..])
+    }
+}
+
+impl<T: Ord, const N: usize> Ord for SmallList<T, N> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self[..].cmp(&other[..])
+    }
+}
+
+impl<T: Hash, const N: usize> Hash for SmallList<T, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self[..].hash(state);
+    }
+}
+
+impl<T, const N: usize> From<Vec<T>> for SmallList<T, N> {
+    fn from(values: Vec<T>) -> Self {
+        SmallList(Inner::from(values))
+    }
+}
+
+impl<T, const N: usize, const M: usize> From<[T; M]> for SmallList<T, N> {
+    fn from(values: [T; M]) -> Self {
+        values.into_iter().collect()
+    }
+}
+
+impl<T: Clone, const N: usize> From<&[T]> for SmallList<T, N> {
+    fn from(values: &[T]) -> Self {
+        values.iter().cloned().collect()
+    }
+}
+
+impl<T, const N: usize> From<SmallList<T, N>> for Vec<T> {
+    fn from(list: SmallList<T, N>) -> Self {
+        list.into_iter().collect()
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for SmallList<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        SmallList(values.into_iter().collect())
+    }
+}
+
+impl<T, const N: usize> Extend<T> for SmallList<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, values: I) {
+        self.0.extend(values);
+    }
+}
+
+impl<T, const N: usize> IntoIterator for SmallList<T, N> {
+    type Item = T;
+    type IntoIter = <Inner<T, N> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a SmallList<T, N> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut SmallList<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize, const N: usize> serde::Serialize for SmallList<T, N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inline_until_full() {
+        let mut list: SmallList<u32, 2> = [1, 2].into();
+        assert_eq!(list, [1, 2]);
+        #[cfg(feature = "smallvec")]
+        assert!(!list.spilled());
+        list.push(3);
+        assert!(list.spilled());
+        assert_eq!(list, vec![1, 2, 3]);
+        assert_eq!(format!("{list:?}"), "[1, 2, 3]");
+        assert_eq!(list.iter().rev().copied().collect::<Vec<_>>(), [3, 2, 1]);
+
+        let other: SmallList<u32, 8> = list.iter().copied().collect();
+        assert_eq!(list, other);
+        assert_eq!(Vec::from(other), [1, 2, 3]);
+    }
+}
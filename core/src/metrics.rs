@@ -0,0 +1,716 @@
+//! Prometheus metrics in the text exposition format.
+//!
+//! The series below are stable; labels are `peer` (the peer's address), `asn`, `afi` and
+//! `safi` (`ipv4`, `ipv6`, `unicast`, `multicast`, or the number of an unknown family),
+//! `type` (`open`, `update`, `notification`, `keepalive` or `route_refresh`), `window`
+//! (`10s`, `1m`, `5m` or `1h`), `rib` and `kind` (`keys`, `attributes` or `index`). Only `bgp_peer_up` carries the ASN, so join on `peer` to
+//! get it for the other series.
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `bgp_peer_up` | gauge | `peer`, `asn` |
+//! | `bgp_peer_uptime_seconds` | gauge | `peer` |
+//! | `bgp_peer_sessions_total` | counter | `peer` |
+//! | `bgp_messages_received_total` | counter | `peer`, `type` |
+//! | `bgp_messages_sent_total` | counter | `peer`, `type` |
+//! | `bgp_received_bytes_total` | counter | `peer` |
+//! | `bgp_sent_bytes_total` | counter | `peer` |
+//! | `bgp_parse_errors_total` | counter | `peer` |
+//! | `bgp_updates_per_second` | gauge | `peer`, `window` |
+//! | `bgp_withdrawals_per_second` | gauge | `peer`, `window` |
+//! | `bgp_prefix_changes_per_second` | gauge | `peer`, `window` |
+//! | `bgp_total_updates_per_second` | gauge | `window` |
+//! | `bgp_total_withdrawals_per_second` | gauge | `window` |
+//! | `bgp_total_prefix_changes_per_second` | gauge | `window` |
+//! | `bgp_prefixes` | gauge | `peer`, `afi`, `safi` |
+//! | `bgp_announcements_total` | counter | `peer` |
+//! | `bgp_withdrawals_total` | counter | `peer` |
+//! | `bgp_announcements` | gauge | `peer`, `window` |
+//! | `bgp_withdrawals` | gauge | `peer`, `window` |
+//! | `bgp_origin_asns` | gauge | `peer` |
+//! | `bgp_treat_as_withdraw_total` | counter | `peer` |
+//! | `bgp_rib_routes` | gauge | `rib` |
+//! | `bgp_rib_memory_bytes` | gauge | `rib`, `kind` |
+//! | `bgp_rib_attribute_sets` | gauge | `rib` |
+//! | `bgp_flapping_routes` | gauge | |
+//! | `bgp_suppressed_routes` | gauge | |
+//!
+//! The `_per_second` rates average UPDATEs, withdrawn prefixes and announced or withdrawn
+//! prefixes over the window, the `total` ones across all peers.
+//!
+//! Message, byte, parse error and route counters cover the current session and reset when
+//! the peer reconnects, which Prometheus treats like any other counter reset.
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use crate::address_family::{Afi, Safi};
+use crate::monitor::{FlapTracker, PeerMonitorSnapshot};
+use crate::rib::RibMemoryStats;
+
+#[cfg(feature = "tokio")]
+use std::net::IpAddr;
+
+#[cfg(feature = "tokio")]
+use crate::header::BgpMessageType;
+#[cfg(feature = "tokio")]
+use crate::session::{PeerManager, PeerSnapshot, PeerState, SessionStats, UpdateRates};
+
+/// Collects metric families and renders them in the Prometheus text format.
+///
+/// Samples added for a family that already exists are appended to it, so the sources can be
+/// added in any order.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRenderer {
+    families: Vec<Family>,
+}
+
+#[derive(Debug, Clone)]
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    labels: Vec<(&'static str, String)>,
+    value: f64,
+}
+
+#[cfg(feature = "tokio")]
+const MESSAGE_TYPES: [(BgpMessageType, &str); 5] = [
+    (BgpMessageType::Open, "open"),
+    (BgpMessageType::Update, "update"),
+    (BgpMessageType::Notification, "notification"),
+    (BgpMessageType::Keepalive, "keepalive"),
+    (BgpMessageType::RouteRefresh, "route_refresh"),
+];
+
+impl MetricsRenderer {
+    pub fn new() -> Self {
+        MetricsRenderer::default()
+    }
+
+    /// Adds the state, traffic and monitoring statistics of every peer of `manager`
+    #[cfg(feature = "tokio")]
+    pub fn manager(&mut self, manager: &PeerManager) -> &mut Self {
+        let peers = manager.snapshot();
+        self.peers(&peers);
+        for peer in &peers {
+            if let Some(stats) = manager.stats(peer.remote_addr) {
+                self.session(peer.remote_addr, &stats);
+            }
+        }
+        self.rates(&manager.rates());
+        self.monitoring(&manager.monitoring())
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn peers(&mut self, peers: &[PeerSnapshot]) -> &mut Self {
+        for snapshot in peers {
+            let peer = snapshot.remote_addr.to_string();
+            let asn = snapshot
+                .peer
+                .map(|info| info.asn.to_string())
+                .unwrap_or_default();
+            let up = snapshot.state == PeerState::Established;
+            self.gauge(
+                "bgp_peer_up",
+                "Whether the session with the peer is established",
+                vec![("peer", peer.clone()), ("asn", asn)],
+                if up { 1.0 } else { 0.0 },
+            );
+            if let Some(uptime) = snapshot.uptime.filter(|_| up) {
+                self.gauge(
+                    "bgp_peer_uptime_seconds",
+                    "Time since the session was established",
+                    vec![("peer", peer.clone())],
+                    uptime.as_secs_f64(),
+                );
+            }
+            self.counter(
+                "bgp_peer_sessions_total",
+                "Sessions established with the peer",
+                vec![("peer", peer)],
+                snapshot.counters.sessions,
+            );
+        }
+        self
+    }
+
+    /// Adds the traffic counters of a session with `peer`
+    #[cfg(feature = "tokio")]
+    pub fn session(&mut self, peer: IpAddr, stats: &SessionStats) -> &mut Self {
+        let peer = peer.to_string();
+        for (message_type, name) in MESSAGE_TYPES {
+            let labels = vec![("peer", peer.clone()), ("type", name.to_string())];
+            self.counter(
+                "bgp_messages_received_total",
+                "Messages received from the peer",
+                labels.clone(),
+                stats.messages_in(message_type),
+            );
+            self.counter(
+                "bgp_messages_sent_total",
+                "Messages sent to the peer",
+                labels,
+                stats.messages_out(message_type),
+            );
+        }
+        self.counter(
+            "bgp_received_bytes_total",
+            "Bytes of BGP messages received from the peer",
+            vec![("peer", peer.clone())],
+            stats.bytes_in(),
+        );
+        self.counter(
+            "bgp_sent_bytes_total",
+            "Bytes of BGP messages sent to the peer",
+            vec![("peer", peer.clone())],
+            stats.bytes_out(),
+        );
+        self.counter(
+            "bgp_parse_errors_total",
+            "Messages from the peer that failed to parse",
+            vec![("peer", peer.clone())],
+            stats.parse_errors(),
+        );
+        for rates in stats.rates() {
+            let labels = vec![
+                ("peer", peer.clone()),
+                ("window", window_label(rates.window)),
+            ];
+            self.gauge(
+                "bgp_updates_per_second",
+                "UPDATEs received from the peer per second within the window",
+                labels.clone(),
+                rates.updates,
+            );
+            self.gauge(
+                "bgp_withdrawals_per_second",
+                "Prefixes withdrawn by the peer per second within the window",
+                labels.clone(),
+                rates.withdrawals,
+            );
+            self.gauge(
+                "bgp_prefix_changes_per_second",
+                "Prefixes announced or withdrawn by the peer per second within the window",
+                labels,
+                rates.prefixes_changed,
+            );
+        }
+        self
+    }
+
+    /// Adds the UPDATE rates of all peers together, see [`PeerManager::rates`]
+    #[cfg(feature = "tokio")]
+    pub fn rates(&mut self, rates: &[UpdateRates]) -> &mut Self {
+        for rates in rates {
+            let labels = vec![("window", window_label(rates.window))];
+            self.gauge(
+                "bgp_total_updates_per_second",
+                "UPDATEs received from all peers per second within the window",
+                labels.clone(),
+                rates.updates,
+            );
+            self.gauge(
+                "bgp_total_withdrawals_per_second",
+                "Prefixes withdrawn by all peers per second within the window",
+                labels.clone(),
+                rates.withdrawals,
+            );
+            self.gauge(
+                "bgp_total_prefix_changes_per_second",
+                "Prefixes announced or withdrawn by all peers per second within the window",
+                labels,
+                rates.prefixes_changed,
+            );
+        }
+        self
+    }
+
+    pub fn monitoring(&mut self, peers: &[PeerMonitorSnapshot]) -> &mut Self {
+        for snapshot in peers {
+            let peer = snapshot.peer.to_string();
+            for family in &snapshot.prefixes {
+                self.gauge(
+                    "bgp_prefixes",
+                    "Prefixes currently received from the peer",
+                    vec![
+                        ("peer", peer.clone()),
+                        ("afi", afi_label(family.afi)),
+                        ("safi", safi_label(family.safi)),
+                    ],
+                    family.count as f64,
+                );
+            }
+            self.counter(
+                "bgp_announcements_total",
+                "Routes announced by the peer, including re-announcements",
+                vec![("peer", peer.clone())],
+                snapshot.announcements.total,
+            );
+            self.counter(
+                "bgp_withdrawals_total",
+                "Routes withdrawn by the peer",
+                vec![("peer", peer.clone())],
+                snapshot.withdrawals.total,
+            );
+            let windows = [
+                (
+                    "1m",
+                    snapshot.announcements.last_minute,
+                    snapshot.withdrawals.last_minute,
+                ),
+                (
+                    "5m",
+                    snapshot.announcements.last_5_minutes,
+                    snapshot.withdrawals.last_5_minutes,
+                ),
+                (
+                    "1h",
+                    snapshot.announcements.last_hour,
+                    snapshot.withdrawals.last_hour,
+                ),
+            ];
+            for (window, announcements, withdrawals) in windows {
+                let labels = vec![("peer", peer.clone()), ("window", window.to_string())];
+                self.gauge(
+                    "bgp_announcements",
+                    "Routes announced by the peer within the window",
+                    labels.clone(),
+                    announcements as f64,
+                );
+                self.gauge(
+                    "bgp_withdrawals",
+                    "Routes withdrawn by the peer within the window",
+                    labels,
+                    withdrawals as f64,
+                );
+            }
+            self.gauge(
+                "bgp_origin_asns",
+                "Distinct origin ASNs announced by the peer",
+                vec![("peer", peer.clone())],
+                snapshot.origin_asns as f64,
+            );
+            self.counter(
+                "bgp_treat_as_withdraw_total",
+                "UPDATEs from the peer handled as withdrawals",
+                vec![("peer", peer)],
+                snapshot.treat_as_withdraw,
+            );
+        }
+        self
+    }
+
+    /// Adds the size of a RIB, such as the `len()` of a [`LocRib`](crate::rib::LocRib)
+    pub fn rib(&mut self, rib: &str, routes: usize) -> &mut Self {
+        self.gauge(
+            "bgp_rib_routes",
+            "Routes held in the RIB",
+            vec![("rib", rib.to_string())],
+            routes as f64,
+        )
+    }
+
+    /// Adds the memory an Adj-RIB-In takes, see [`RibIn::memory_stats`]
+    ///
+    /// [`RibIn::memory_stats`]: crate::rib::RibIn::memory_stats
+    pub fn rib_memory(&mut self, rib: &str, stats: &RibMemoryStats) -> &mut Self {
+        for (kind, bytes) in [
+            ("keys", stats.key_bytes),
+            ("attributes", stats.attribute_bytes),
+            ("index", stats.index_bytes),
+        ] {
+            self.gauge(
+                "bgp_rib_memory_bytes",
+                "Estimated memory the RIB takes",
+                vec![("rib", rib.to_string()), ("kind", kind.to_string())],
+                bytes as f64,
+            );
+        }
+        self.gauge(
+            "bgp_rib_attribute_sets",
+            "Distinct attribute sets the routes of the RIB share",
+            vec![("rib", rib.to_string())],
+            stats.attribute_sets as f64,
+        )
+    }
+
+    /// Adds how many routes are flapping and how many of them are suppressed as of `now`
+    pub fn flaps(&mut self, tracker: &FlapTracker, now: SystemTime) -> &mut Self {
+        let suppressed = tracker
+            .top(tracker.len(), now)
+            .iter()
+            .filter(|status| status.suppressed)
+            .count();
+        self.gauge(
+            "bgp_flapping_routes",
+            "Routes with a dampening penalty",
+            vec![],
+            tracker.len() as f64,
+        );
+        self.gauge(
+            "bgp_suppressed_routes",
+            "Routes suppressed by dampening",
+            vec![],
+            suppressed as f64,
+        )
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in &self.families {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+            };
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+            for sample in &family.samples {
+                out.push_str(family.name);
+                if !sample.labels.is_empty() {
+                    out.push('{');
+                    for (i, (name, value)) in sample.labels.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        let _ = write!(out, "{}=\"{}\"", name, escape(value));
+                    }
+                    out.push('}');
+                }
+                let _ = writeln!(out, " {}", sample.value);
+            }
+        }
+        out
+    }
+
+    fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: Vec<(&'static str, String)>,
+        value: u64,
+    ) -> &mut Self {
+        self.sample(name, help, Kind::Counter, labels, value as f64)
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: Vec<(&'static str, String)>,
+        value: f64,
+    ) -> &mut Self {
+        self.sample(name, help, Kind::Gauge, labels, value)
+    }
+
+    fn sample(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        kind: Kind,
+        labels: Vec<(&'static str, String)>,
+        value: f64,
+    ) -> &mut Self {
+        let i = match self.families.iter().position(|family| family.name == name) {
+            Some(i) => i,
+            None => {
+                self.families.push(Family {
+                    name,
+                    help,
+                    kind,
+                    samples: vec![],
+                });
+                self.families.len() - 1
+            }
+        };
+        self.families[i].samples.push(Sample { labels, value });
+        self
+    }
+}
+
+/// Renders the metrics of every peer of `manager`
+#[cfg(feature = "tokio")]
+pub fn render(manager: &PeerManager) -> String {
+    MetricsRenderer::new().manager(manager).render()
+}
+
+/// Serves `GET /metrics` with the output of `render` on every connection accepted from
+/// `listener`, until accepting fails.
+///
+/// This is a minimal HTTP/1.1 endpoint for scrapers, closing each connection after one
+/// response; put a real web server in front of it for anything else.
+#[cfg(feature = "tokio")]
+pub async fn serve<F>(listener: tokio::net::TcpListener, render: F) -> std::io::Result<()>
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let render = std::sync::Arc::new(render);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let render = render.clone();
+        tokio::spawn(async move {
+            let _ = http::respond(stream, &*render).await;
+        });
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod http {
+    use std::io;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Largest request head accepted
+    const MAX_HEAD: usize = 8 * 1024;
+    const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub(super) async fn respond(
+        mut stream: TcpStream,
+        render: &(dyn Fn() -> String + Send + Sync),
+    ) -> io::Result<()> {
+        let head = match tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await {
+            Ok(head) => head?,
+            Err(_) => return Ok(()),
+        };
+        let mut request_line = head.split_whitespace();
+        let method = request_line.next().unwrap_or_default();
+        let path = request_line.next().unwrap_or_default();
+        let path = path.split('?').next().unwrap_or_default();
+
+        let (status, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", render()),
+            ("GET", _) => ("404 Not Found", "Not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    /// Reads up to the end of the request head, returning its first line
+    async fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            if head.len() > MAX_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request too large",
+                ));
+            }
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        Ok(head.lines().next().unwrap_or_default().to_string())
+    }
+}
+
+fn afi_label(afi: Afi) -> String {
+    match afi {
+        Afi::Ipv4 => "ipv4".to_string(),
+        Afi::Ipv6 => "ipv6".to_string(),
+        Afi::Unknown(value) => value.to_string(),
+    }
+}
+
+fn safi_label(safi: Safi) -> String {
+    match safi {
+        Safi::Unicast => "unicast".to_string(),
+        Safi::Multicast => "multicast".to_string(),
+        Safi::Unknown(value) => value.to_string(),
+    }
+}
+
+/// `10s`, `5m` or `1h`
+#[cfg(feature = "tokio")]
+fn window_label(window: std::time::Duration) -> String {
+    match window.as_secs() {
+        seconds if seconds % 3600 == 0 => format!("{}h", seconds / 3600),
+        seconds if seconds % 60 == 0 => format!("{}m", seconds / 60),
+        seconds => format!("{seconds}s"),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use crate::monitor::{FamilyCount, WindowCounts};
+
+    #[test]
+    fn test_render() {
+        let snapshot = PeerMonitorSnapshot {
+            peer: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            uptime: Duration::from_secs(60),
+            prefixes: vec![FamilyCount {
+                afi: Afi::Ipv6,
+                safi: Safi::Unknown(128),
+                count: 3,
+            }],
+            announcements: WindowCounts {
+                last_minute: 1,
+                last_5_minutes: 2,
+                last_hour: 3,
+                total: 4,
+            },
+            withdrawals: WindowCounts::default(),
+            origin_asns: 2,
+            treat_as_withdraw: 0,
+            end_of_rib: vec![],
+        };
+        let mut renderer = MetricsRenderer::new();
+        renderer
+            .rib("loc\"rib", 10)
+            .monitoring(&[snapshot])
+            .rib("adj-rib-in", 7)
+            .rib_memory(
+                "adj-rib-in",
+                &RibMemoryStats {
+                    routes: 7,
+                    attribute_sets: 2,
+                    key_bytes: 448,
+                    attribute_bytes: 300,
+                    index_bytes: 1024,
+                },
+            )
+            .flaps(&FlapTracker::default(), SystemTime::UNIX_EPOCH);
+        let text = renderer.render();
+
+        assert!(text.starts_with(
+            "# HELP bgp_rib_routes Routes held in the RIB\n\
+             # TYPE bgp_rib_routes gauge\n\
+             bgp_rib_routes{rib=\"loc\\\"rib\"} 10\n\
+             bgp_rib_routes{rib=\"adj-rib-in\"} 7\n"
+        ));
+        assert!(text.contains("\nbgp_prefixes{peer=\"192.0.2.1\",afi=\"ipv6\",safi=\"128\"} 3\n"));
+        assert!(text.contains("\nbgp_announcements_total{peer=\"192.0.2.1\"} 4\n"));
+        assert!(text.contains("\nbgp_announcements{peer=\"192.0.2.1\",window=\"5m\"} 2\n"));
+        assert!(text.contains("# TYPE bgp_withdrawals gauge\n"));
+        assert!(
+            text.contains("\nbgp_rib_memory_bytes{rib=\"adj-rib-in\",kind=\"attributes\"} 300\n")
+        );
+        assert!(text.contains("\nbgp_rib_attribute_sets{rib=\"adj-rib-in\"} 2\n"));
+        assert!(text.ends_with("\nbgp_suppressed_routes 0\n"));
+        assert_eq!(text.matches("# TYPE").count(), 12);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_scrape_after_exchange() {
+        use std::sync::Arc;
+
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+        use crate::bgp_message::BgpMessage;
+        use crate::session::{BgpListener, ChannelObserver, PeerConfig, SessionEvent};
+        use crate::update_message::UpdateMessageBuilder;
+
+        let config = |remote: Ipv4Addr, asn: u32| {
+            PeerConfig::builder(IpAddr::V4(remote), asn, Ipv4Addr::new(10, 0, 0, asn as u8))
+                .build()
+                .unwrap()
+        };
+        let peer = Ipv4Addr::new(127, 0, 0, 6);
+        let mut remote = BgpListener::bind((peer, 0), [config(Ipv4Addr::LOCALHOST, 65006)])
+            .await
+            .unwrap();
+        let mut local = config(peer, 65000);
+        local.remote_port = remote.local_addr().port();
+
+        let (observer, mut events) = ChannelObserver::new(64);
+        let mut manager = PeerManager::new(observer);
+        manager.add_peer(local).unwrap();
+        let session = remote.accept().await.unwrap();
+
+        let update = UpdateMessageBuilder::new()
+            .announce("198.51.100.0/24".parse().unwrap())
+            .announce("203.0.113.0/24".parse().unwrap())
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65006, 64500].into(),
+                }]
+                .into(),
+            })
+            .next_hop("127.0.0.6".parse().unwrap())
+            .build();
+        session.send(BgpMessage::Update(update)).await.unwrap();
+        loop {
+            if let Some(SessionEvent::Update(..)) = events.recv().await {
+                break;
+            }
+        }
+
+        let manager = Arc::new(manager);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let scraped = manager.clone();
+        tokio::spawn(serve(listener, move || render(&scraped)));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        for series in [
+            "bgp_peer_up{peer=\"127.0.0.6\",asn=\"65006\"} 1",
+            "bgp_peer_sessions_total{peer=\"127.0.0.6\"} 1",
+            "bgp_messages_received_total{peer=\"127.0.0.6\",type=\"update\"} 1",
+            "bgp_messages_received_total{peer=\"127.0.0.6\",type=\"open\"} 1",
+            "bgp_parse_errors_total{peer=\"127.0.0.6\"} 0",
+            "bgp_prefixes{peer=\"127.0.0.6\",afi=\"ipv4\",safi=\"unicast\"} 2",
+            "bgp_announcements_total{peer=\"127.0.0.6\"} 2",
+            "bgp_origin_asns{peer=\"127.0.0.6\"} 1",
+            "bgp_updates_per_second{peer=\"127.0.0.6\",window=\"10s\"} 0.1",
+            "bgp_prefix_changes_per_second{peer=\"127.0.0.6\",window=\"1m\"} 0.03333333333333333",
+            "bgp_total_updates_per_second{window=\"10s\"} 0.1",
+        ] {
+            assert!(response.contains(&format!("\n{series}\n")), "{series}");
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        drop(session);
+    }
+}
@@ -0,0 +1,298 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use crate::rib::{RouteEvent, RouteEventKind};
+use crate::trie::PrefixTrie;
+use crate::update_message::{IpAddrPrefix, ParsePrefixError};
+
+/// Address space that should never appear in the global routing table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BogonKind {
+    /// 0.0.0.0/0 or ::/0 itself
+    DefaultRoute,
+    /// "This network", 0.0.0.0/8
+    ThisNetwork,
+    /// RFC 1918 and IPv6 unique local addresses (RFC 4193)
+    Private,
+    /// Carrier-grade NAT space (RFC 6598)
+    SharedAddressSpace,
+    Loopback,
+    LinkLocal,
+    /// Documentation ranges (RFC 5737, RFC 3849, RFC 9637)
+    Documentation,
+    Multicast,
+    /// Class E, 240.0.0.0/4
+    Reserved,
+    /// Added with [`BogonChecker::add`]
+    Custom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {error}")]
+pub struct BogonListError {
+    pub line: usize,
+    pub error: ParsePrefixError,
+}
+
+/// A route announced from bogon space
+#[derive(Debug, Clone, PartialEq)]
+pub struct BogonAlert {
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    pub kind: BogonKind,
+    pub at: SystemTime,
+}
+
+const BUILT_IN: &[(&str, BogonKind)] = &[
+    ("0.0.0.0/8", BogonKind::ThisNetwork),
+    ("10.0.0.0/8", BogonKind::Private),
+    ("100.64.0.0/10", BogonKind::SharedAddressSpace),
+    ("127.0.0.0/8", BogonKind::Loopback),
+    ("169.254.0.0/16", BogonKind::LinkLocal),
+    ("172.16.0.0/12", BogonKind::Private),
+    ("192.0.2.0/24", BogonKind::Documentation),
+    ("192.168.0.0/16", BogonKind::Private),
+    ("198.51.100.0/24", BogonKind::Documentation),
+    ("203.0.113.0/24", BogonKind::Documentation),
+    ("224.0.0.0/4", BogonKind::Multicast),
+    ("240.0.0.0/4", BogonKind::Reserved),
+    ("::1/128", BogonKind::Loopback),
+    ("2001:db8::/32", BogonKind::Documentation),
+    ("3fff::/20", BogonKind::Documentation),
+    ("fc00::/7", BogonKind::Private),
+    ("fe80::/10", BogonKind::LinkLocal),
+    ("ff00::/8", BogonKind::Multicast),
+];
+
+/// Classifies prefixes falling into bogon space.
+///
+/// Starts out with the well-known martian ranges of both families; more prefixes, such as
+/// address space of our own that is not allocated yet, can be added at any time. A prefix
+/// is a bogon when one of the ranges covers it, except for the default route which only
+/// matches exactly. Ranges are kept in a [`PrefixTrie`].
+#[derive(Debug, Clone)]
+pub struct BogonChecker {
+    ranges: PrefixTrie<BogonKind>,
+}
+
+impl BogonChecker {
+    pub fn new() -> Self {
+        let mut checker = BogonChecker {
+            ranges: PrefixTrie::new(),
+        };
+        for default in ["0.0.0.0/0", "::/0"] {
+            checker.insert(default.parse().unwrap(), BogonKind::DefaultRoute);
+        }
+        for (prefix, kind) in BUILT_IN {
+            checker.insert(prefix.parse().unwrap(), *kind);
+        }
+        checker
+    }
+
+    /// Treats `prefix` and everything within it as bogon space
+    pub fn add(&mut self, prefix: IpAddrPrefix) {
+        self.insert(prefix, BogonKind::Custom);
+    }
+
+    /// Adds one prefix per line, skipping blank lines and `#` comments, and returns how many
+    /// were added. Nothing is added when a line fails to parse.
+    pub fn load(&mut self, list: &str) -> Result<usize, BogonListError> {
+        let mut prefixes = vec![];
+        for (i, line) in list.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let prefix = line
+                .parse()
+                .map_err(|error| BogonListError { line: i + 1, error })?;
+            prefixes.push(prefix);
+        }
+        let added = prefixes.len();
+        prefixes.into_iter().for_each(|prefix| self.add(prefix));
+        Ok(added)
+    }
+
+    /// The kind of bogon space `prefix` falls in, built-in ranges taking precedence over
+    /// added ones
+    pub fn classify(&self, prefix: &IpAddrPrefix) -> Option<BogonKind> {
+        self.ranges
+            .covering(prefix)
+            .filter(|(range, kind)| {
+                **kind != BogonKind::DefaultRoute || range.length() == prefix.length()
+            })
+            .map(|(_, kind)| *kind)
+            .min_by_key(|kind| *kind == BogonKind::Custom)
+    }
+
+    /// Raises an alert for announcements of bogon space
+    pub fn observe(&self, event: &RouteEvent) -> Option<BogonAlert> {
+        if let RouteEventKind::Withdrawn = event.kind {
+            return None;
+        }
+        Some(BogonAlert {
+            peer: event.peer,
+            prefix: event.prefix.clone(),
+            kind: self.classify(&event.prefix)?,
+            at: event.timestamp,
+        })
+    }
+
+    fn insert(&mut self, prefix: IpAddrPrefix, kind: BogonKind) {
+        if self.ranges.get(&prefix).is_none() {
+            self.ranges.insert(&prefix, kind);
+        }
+    }
+}
+
+impl Default for BogonChecker {
+    fn default() -> Self {
+        BogonChecker::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    use crate::address_family::Safi;
+
+    fn classify(prefix: &str) -> Option<BogonKind> {
+        BogonChecker::new().classify(&prefix.parse().unwrap())
+    }
+
+    #[test]
+    fn test_private() {
+        assert_eq!(classify("10.1.0.0/16"), Some(BogonKind::Private));
+        assert_eq!(classify("172.16.0.0/12"), Some(BogonKind::Private));
+        assert_eq!(classify("172.31.255.0/24"), Some(BogonKind::Private));
+        assert_eq!(classify("192.168.1.0/24"), Some(BogonKind::Private));
+        assert_eq!(classify("fd12:3456::/32"), Some(BogonKind::Private));
+        assert_eq!(classify("172.32.0.0/16"), None);
+    }
+
+    #[test]
+    fn test_shared_address_space() {
+        assert_eq!(
+            classify("100.64.0.0/10"),
+            Some(BogonKind::SharedAddressSpace)
+        );
+        assert_eq!(
+            classify("100.127.0.0/16"),
+            Some(BogonKind::SharedAddressSpace)
+        );
+        assert_eq!(classify("100.128.0.0/16"), None);
+    }
+
+    #[test]
+    fn test_loopback_and_link_local() {
+        assert_eq!(classify("127.0.0.0/8"), Some(BogonKind::Loopback));
+        assert_eq!(classify("::1/128"), Some(BogonKind::Loopback));
+        assert_eq!(classify("169.254.10.0/24"), Some(BogonKind::LinkLocal));
+        assert_eq!(classify("fe80::/64"), Some(BogonKind::LinkLocal));
+    }
+
+    #[test]
+    fn test_documentation() {
+        for prefix in [
+            "192.0.2.0/24",
+            "198.51.100.0/25",
+            "203.0.113.0/24",
+            "2001:db8:1::/48",
+            "3fff:1::/32",
+        ] {
+            assert_eq!(classify(prefix), Some(BogonKind::Documentation), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn test_class_e_and_multicast() {
+        assert_eq!(classify("240.0.0.0/4"), Some(BogonKind::Reserved));
+        assert_eq!(classify("255.255.255.255/32"), Some(BogonKind::Reserved));
+        assert_eq!(classify("224.0.0.0/24"), Some(BogonKind::Multicast));
+        assert_eq!(classify("ff02::/16"), Some(BogonKind::Multicast));
+        assert_eq!(classify("0.0.0.0/8"), Some(BogonKind::ThisNetwork));
+    }
+
+    #[test]
+    fn test_default_route() {
+        assert_eq!(classify("0.0.0.0/0"), Some(BogonKind::DefaultRoute));
+        assert_eq!(classify("::/0"), Some(BogonKind::DefaultRoute));
+    }
+
+    #[test]
+    fn test_ordinary_space() {
+        for prefix in [
+            "8.8.8.0/24",
+            "1.0.0.0/8",
+            "128.0.0.0/1",
+            "192.0.3.0/24",
+            "2001:db9::/32",
+            "2a00::/12",
+        ] {
+            assert_eq!(classify(prefix), None, "{prefix}");
+        }
+    }
+
+    #[test]
+    fn test_added_prefixes() {
+        let mut checker = BogonChecker::new();
+        let added = checker
+            .load("# not announced yet\n45.0.0.0/16\n\n2a0f:1::/32 # lab\n")
+            .unwrap();
+        assert_eq!(added, 2);
+        assert_eq!(
+            checker.classify(&"45.0.1.0/24".parse().unwrap()),
+            Some(BogonKind::Custom)
+        );
+        assert_eq!(
+            checker.classify(&"2a0f:1:2::/48".parse().unwrap()),
+            Some(BogonKind::Custom)
+        );
+        // Built-in ranges keep their kind
+        checker.add("10.0.0.0/8".parse().unwrap());
+        assert_eq!(
+            checker.classify(&"10.0.0.0/8".parse().unwrap()),
+            Some(BogonKind::Private)
+        );
+
+        let error = checker.load("46.0.0.0/16\nnonsense").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(checker.classify(&"46.0.0.0/16".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_alert_on_announcement() {
+        let checker = BogonChecker::new();
+        let event = |prefix: &str, kind| RouteEvent {
+            peer: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            timestamp: SystemTime::UNIX_EPOCH,
+            prefix: prefix.parse().unwrap(),
+            safi: Safi::Unicast,
+            path_id: None,
+            kind,
+            rpki: None,
+        };
+        let announced = || RouteEventKind::Announced {
+            attrs: Arc::new([]),
+        };
+
+        let alert = checker
+            .observe(&event("192.168.0.0/16", announced()))
+            .unwrap();
+        assert_eq!(alert.kind, BogonKind::Private);
+        assert_eq!(alert.prefix, "192.168.0.0/16".parse().unwrap());
+        assert_eq!(checker.observe(&event("8.8.8.0/24", announced())), None);
+        assert_eq!(
+            checker.observe(&event("192.168.0.0/16", RouteEventKind::Withdrawn)),
+            None
+        );
+    }
+}
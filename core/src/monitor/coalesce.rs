@@ -0,0 +1,428 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::address_family::Safi;
+use crate::rib::{AttributeSet, RouteEvent, RouteEventKind};
+use crate::update_message::IpAddrPrefix;
+
+/// Settings of the [`Coalescer`]; the default passes every event straight through
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CoalesceConfig {
+    /// How long an announcement holds back later events of its route, `None` passes
+    /// announcements through unless a window of the route is already open
+    pub announced: Option<Duration>,
+    pub withdrawn: Option<Duration>,
+    pub reannounced: Option<Duration>,
+    /// Limit on the events passed on per peer
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Token bucket refilled at `per_second` tokens up to `burst`, one token per event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// What the [`Coalescer`] passes on
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoalescedEvent {
+    /// A route event that had no others merged into it
+    Route(RouteEvent),
+    /// Several events of the same route within a window
+    Summary(RouteSummary),
+    /// The peer's rate limit dropped `count` events since its last marker
+    EventsSuppressed { peer: IpAddr, count: u64 },
+}
+
+/// Events of a route merged by the [`Coalescer`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteSummary {
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    pub safi: Safi,
+    pub path_id: Option<u32>,
+    /// Events merged, at least two
+    pub transitions: u32,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// Attributes after the first event, `None` if it was a withdrawal
+    pub first: Option<AttributeSet>,
+    /// Attributes after the last event, `None` if the route ended up withdrawn
+    pub last: Option<AttributeSet>,
+}
+
+/// Merges bursts of events for the same route and rate limits what's left per peer.
+///
+/// The first event of a route opens a window of the length configured for its kind; events of
+/// the route until the window closes are merged into a [`RouteSummary`]. Output keeps the
+/// order of the first event of each window, so an event only passes once every window opened
+/// before it has closed. Time is taken from event timestamps and the `now` given to
+/// [`Coalescer::poll`], never from the clock.
+#[derive(Debug, Clone)]
+pub struct Coalescer {
+    config: CoalesceConfig,
+    /// Windows in the order they opened
+    pending: VecDeque<Window>,
+    /// Sequence number of the front of `pending`
+    front: u64,
+    /// Open windows by route
+    open: HashMap<RouteKey, u64>,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RouteKey {
+    peer: IpAddr,
+    prefix: IpAddrPrefix,
+    safi: Safi,
+    path_id: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    closes: SystemTime,
+    first: RouteEvent,
+    last: Option<RouteEvent>,
+    transitions: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    refilled: SystemTime,
+    suppressed: u64,
+}
+
+impl RouteKey {
+    fn of(event: &RouteEvent) -> Self {
+        RouteKey {
+            peer: event.peer,
+            prefix: event.prefix.clone(),
+            safi: event.safi,
+            path_id: event.path_id,
+        }
+    }
+}
+
+impl Window {
+    fn into_event(self) -> CoalescedEvent {
+        let Some(last) = self.last else {
+            return CoalescedEvent::Route(self.first);
+        };
+        CoalescedEvent::Summary(RouteSummary {
+            peer: self.first.peer,
+            prefix: self.first.prefix,
+            safi: self.first.safi,
+            path_id: self.first.path_id,
+            transitions: self.transitions,
+            first_seen: self.first.timestamp,
+            last_seen: last.timestamp,
+            first: attributes(&self.first.kind),
+            last: attributes(&last.kind),
+        })
+    }
+}
+
+fn attributes(kind: &RouteEventKind) -> Option<AttributeSet> {
+    match kind {
+        RouteEventKind::Announced { attrs } => Some(attrs.clone()),
+        RouteEventKind::Reannounced { new_attrs, .. } => Some(new_attrs.clone()),
+        RouteEventKind::Withdrawn => None,
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: SystemTime) {
+        if let Ok(elapsed) = now.duration_since(self.refilled) {
+            self.tokens =
+                (self.tokens + elapsed.as_secs_f64() * limit.per_second).min(limit.burst as f64);
+            self.refilled = now;
+        }
+    }
+}
+
+impl Coalescer {
+    pub fn new(config: CoalesceConfig) -> Self {
+        Coalescer {
+            config,
+            pending: VecDeque::new(),
+            front: 0,
+            open: HashMap::new(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes in an event, returning whatever became due by its timestamp
+    pub fn push(&mut self, event: RouteEvent) -> Vec<CoalescedEvent> {
+        let now = event.timestamp;
+        let key = RouteKey::of(&event);
+        if let Some(sequence) = self.open.get(&key) {
+            let window = &mut self.pending[(sequence - self.front) as usize];
+            if now < window.closes {
+                window.transitions += 1;
+                window.last = Some(event);
+                return self.poll(now);
+            }
+            // Closed but held back by an earlier window
+            self.open.remove(&key);
+        }
+
+        let length = match event.kind {
+            RouteEventKind::Announced { .. } => self.config.announced,
+            RouteEventKind::Withdrawn => self.config.withdrawn,
+            RouteEventKind::Reannounced { .. } => self.config.reannounced,
+        };
+        if length.is_some() {
+            self.open
+                .insert(key, self.front + self.pending.len() as u64);
+        }
+        self.pending.push_back(Window {
+            closes: now + length.unwrap_or_default(),
+            first: event,
+            last: None,
+            transitions: 1,
+        });
+        self.poll(now)
+    }
+
+    /// Passes on the windows closed by `now` and reports peers whose rate limit allows
+    /// reporting what it dropped
+    pub fn poll(&mut self, now: SystemTime) -> Vec<CoalescedEvent> {
+        let mut out = vec![];
+        while self
+            .pending
+            .front()
+            .is_some_and(|window| window.closes <= now)
+        {
+            let window = self.pop_front();
+            self.limit(window, now, &mut out);
+        }
+
+        if let Some(limit) = self.config.rate_limit {
+            let mut peers: Vec<(&IpAddr, &mut Bucket)> = self
+                .buckets
+                .iter_mut()
+                .filter(|(_, bucket)| bucket.suppressed > 0)
+                .collect();
+            peers.sort_by_key(|(peer, _)| **peer);
+            for (peer, bucket) in peers {
+                bucket.refill(&limit, now);
+                if bucket.tokens >= 1.0 {
+                    out.push(CoalescedEvent::EventsSuppressed {
+                        peer: *peer,
+                        count: bucket.suppressed,
+                    });
+                    bucket.suppressed = 0;
+                }
+            }
+        }
+        out
+    }
+
+    /// Passes on every open window regardless of its length, for example on shutdown
+    pub fn flush(&mut self, now: SystemTime) -> Vec<CoalescedEvent> {
+        for window in &mut self.pending {
+            window.closes = window.closes.min(now);
+        }
+        self.poll(now)
+    }
+
+    /// Events held back in open windows, merged ones counted once
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn pop_front(&mut self) -> Window {
+        let window = self.pending.pop_front().unwrap();
+        let key = RouteKey::of(&window.first);
+        if self.open.get(&key) == Some(&self.front) {
+            self.open.remove(&key);
+        }
+        self.front += 1;
+        window
+    }
+
+    fn limit(&mut self, window: Window, now: SystemTime, out: &mut Vec<CoalescedEvent>) {
+        let Some(limit) = self.config.rate_limit else {
+            out.push(window.into_event());
+            return;
+        };
+        let peer = window.first.peer;
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            refilled: now,
+            suppressed: 0,
+        });
+        bucket.refill(&limit, now);
+        if bucket.tokens < 1.0 {
+            bucket.suppressed += 1;
+            return;
+        }
+        if bucket.suppressed > 0 {
+            out.push(CoalescedEvent::EventsSuppressed {
+                peer,
+                count: bucket.suppressed,
+            });
+            bucket.suppressed = 0;
+        }
+        bucket.tokens -= 1.0;
+        out.push(window.into_event());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    const START: u64 = 1_700_000_000;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(START + secs)
+    }
+
+    fn event(peer: u8, prefix: &str, secs: u64, announced: bool) -> RouteEvent {
+        let kind = if announced {
+            RouteEventKind::Announced {
+                attrs: Arc::from(vec![]),
+            }
+        } else {
+            RouteEventKind::Withdrawn
+        };
+        RouteEvent {
+            peer: IpAddr::V4(Ipv4Addr::new(192, 0, 2, peer)),
+            timestamp: at(secs),
+            prefix: prefix.parse().unwrap(),
+            safi: Safi::Unicast,
+            path_id: None,
+            kind,
+            rpki: None,
+        }
+    }
+
+    fn describe(events: Vec<CoalescedEvent>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                CoalescedEvent::Route(event) => format!("{} {:?}", event.prefix, event.kind),
+                CoalescedEvent::Summary(summary) => format!(
+                    "{} x{} {}-{} {}",
+                    summary.prefix,
+                    summary.transitions,
+                    summary.first_seen.duration_since(at(0)).unwrap().as_secs(),
+                    summary.last_seen.duration_since(at(0)).unwrap().as_secs(),
+                    if summary.last.is_some() {
+                        "announced"
+                    } else {
+                        "withdrawn"
+                    },
+                ),
+                CoalescedEvent::EventsSuppressed { peer, count } => {
+                    format!("{peer} suppressed {count}")
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flapping_prefix_summarized() {
+        let window = Some(Duration::from_secs(10));
+        let mut coalescer = Coalescer::new(CoalesceConfig {
+            announced: window,
+            withdrawn: window,
+            ..Default::default()
+        });
+
+        let mut out = vec![];
+        for secs in 0..6 {
+            out.extend(coalescer.push(event(1, "198.51.100.0/24", secs, secs % 2 == 0)));
+        }
+        out.extend(coalescer.push(event(1, "203.0.113.0/24", 3, true)));
+        assert!(out.is_empty());
+        assert_eq!(coalescer.pending(), 2);
+
+        // Both windows close by now, in the order they opened
+        assert_eq!(
+            describe(coalescer.poll(at(13))),
+            vec![
+                "198.51.100.0/24 x6 0-5 withdrawn".to_string(),
+                "203.0.113.0/24 Announced { attrs: [] }".to_string(),
+            ]
+        );
+
+        // A new window opens once the old one is gone
+        coalescer.push(event(1, "198.51.100.0/24", 20, true));
+        assert_eq!(
+            describe(coalescer.flush(at(21))),
+            vec!["198.51.100.0/24 Announced { attrs: [] }".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_passthrough_waits_for_earlier_windows() {
+        // Only withdrawals are coalesced
+        let mut coalescer = Coalescer::new(CoalesceConfig {
+            withdrawn: Some(Duration::from_secs(5)),
+            ..Default::default()
+        });
+        assert!(
+            coalescer
+                .push(event(1, "198.51.100.0/24", 0, false))
+                .is_empty()
+        );
+        assert!(
+            coalescer
+                .push(event(1, "203.0.113.0/24", 1, true))
+                .is_empty()
+        );
+        assert_eq!(
+            describe(coalescer.push(event(1, "198.51.100.0/24", 6, true))),
+            vec![
+                "198.51.100.0/24 Withdrawn".to_string(),
+                "203.0.113.0/24 Announced { attrs: [] }".to_string(),
+                "198.51.100.0/24 Announced { attrs: [] }".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_per_peer() {
+        let mut coalescer = Coalescer::new(CoalesceConfig {
+            rate_limit: Some(RateLimit {
+                per_second: 1.0,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+
+        let mut out = vec![];
+        for i in 0..5 {
+            out.extend(coalescer.push(event(1, &format!("10.0.{i}.0/24"), 0, true)));
+        }
+        // Another peer has its own bucket
+        out.extend(coalescer.push(event(2, "10.1.0.0/24", 0, true)));
+        assert_eq!(
+            describe(out),
+            vec![
+                "10.0.0.0/24 Announced { attrs: [] }".to_string(),
+                "10.0.1.0/24 Announced { attrs: [] }".to_string(),
+                "10.1.0.0/24 Announced { attrs: [] }".to_string(),
+            ]
+        );
+
+        // The marker goes out as soon as a token is back, ahead of later events
+        assert!(coalescer.poll(at(0)).is_empty());
+        assert_eq!(
+            describe(coalescer.poll(at(1))),
+            vec!["192.0.2.1 suppressed 3".to_string()]
+        );
+        assert_eq!(
+            describe(coalescer.push(event(1, "10.0.9.0/24", 1, true))),
+            vec!["10.0.9.0/24 Announced { attrs: [] }".to_string()]
+        );
+        assert!(coalescer.poll(at(100)).is_empty());
+    }
+}
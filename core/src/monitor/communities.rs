@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::attribute::{AttributeValue, Community, PathAttribute};
+use crate::rib::RibChange;
+
+/// How many routes carry each community, kept up to date from the [`RibChange`]s of every
+/// peer, to audit tags such as blackhole or traffic engineering communities.
+///
+/// A route carrying a community several times counts once. Counts are of routes, so a prefix
+/// received from two peers counts twice in [`CommunityStats::count`] and once in each peer's
+/// [`CommunityStats::peer_count`]. Only standard communities (RFC 1997) are counted, large
+/// communities aren't decoded.
+///
+/// By default every community is counted exactly, so memory grows with the number of distinct
+/// communities times the peers using them. [`CommunityStats::approximate`] bounds it instead.
+#[derive(Debug, Clone, Default)]
+pub struct CommunityStats {
+    counts: HashMap<Community, u64>,
+    peers: HashMap<IpAddr, HashMap<Community, u64>>,
+    sampling: Option<Sampling>,
+    /// Communities not counted once sampling ended, per peer
+    untracked: HashMap<IpAddr, u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Sampling {
+    /// Routes announced before the tracked communities are chosen
+    routes: u64,
+    min_count: u64,
+    seen: u64,
+    /// The communities still counted, once sampling ended
+    tracked: Option<HashSet<Community>>,
+}
+
+/// Routes carrying a community
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommunityCount {
+    pub community: Community,
+    pub routes: u64,
+}
+
+/// Point in time view of [`CommunityStats`], most used communities first
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommunityStatsSnapshot {
+    pub communities: Vec<CommunityCount>,
+    pub peers: Vec<PeerCommunities>,
+    /// Communities on routes that aren't counted individually, see
+    /// [`CommunityStats::approximate`]
+    pub untracked: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PeerCommunities {
+    pub peer: IpAddr,
+    pub communities: Vec<CommunityCount>,
+    pub untracked: u64,
+}
+
+impl CommunityStats {
+    /// Counts every community exactly
+    pub fn new() -> Self {
+        CommunityStats::default()
+    }
+
+    /// Counts every community over the first `routes` announcements, then keeps counting only
+    /// those found on at least `min_count` of them.
+    ///
+    /// Communities dropped after sampling, or first seen after it, are only counted in
+    /// [`CommunityStatsSnapshot::untracked`], so memory stays bounded by the communities that
+    /// were common during sampling.
+    pub fn approximate(routes: u64, min_count: u64) -> Self {
+        CommunityStats {
+            sampling: Some(Sampling {
+                routes,
+                min_count,
+                seen: 0,
+                tracked: None,
+            }),
+            ..CommunityStats::default()
+        }
+    }
+
+    pub fn apply(&mut self, peer: IpAddr, change: &RibChange) {
+        match change {
+            RibChange::Announced { attributes, .. } => {
+                self.add(peer, attributes);
+                self.sampled();
+            }
+            RibChange::Replaced { old, new, .. } => {
+                self.remove(peer, old);
+                self.add(peer, new);
+            }
+            RibChange::Unchanged { .. } => {}
+            RibChange::Withdrawn { attributes, .. } => self.remove(peer, attributes),
+        }
+    }
+
+    /// Routes carrying `community`, from every peer
+    pub fn count(&self, community: Community) -> u64 {
+        self.counts.get(&community).copied().unwrap_or(0)
+    }
+
+    pub fn peer_count(&self, peer: IpAddr, community: Community) -> u64 {
+        self.peers
+            .get(&peer)
+            .and_then(|counts| counts.get(&community))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The `n` communities on the most routes, most first
+    pub fn top(&self, n: usize) -> Vec<CommunityCount> {
+        top(&self.counts, n)
+    }
+
+    /// The `n` communities on the most routes of `peer`, most first
+    pub fn top_for_peer(&self, peer: IpAddr, n: usize) -> Vec<CommunityCount> {
+        self.peers
+            .get(&peer)
+            .map_or_else(Vec::new, |counts| top(counts, n))
+    }
+
+    /// How many routes of each peer carry `community`, in peer order
+    pub fn peers_with(&self, community: Community) -> Vec<(IpAddr, u64)> {
+        let mut peers: Vec<(IpAddr, u64)> = self
+            .peers
+            .iter()
+            .filter_map(|(peer, counts)| Some((*peer, *counts.get(&community)?)))
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// Distinct communities counted
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn snapshot(&self) -> CommunityStatsSnapshot {
+        let mut peers: Vec<PeerCommunities> = self
+            .peers
+            .keys()
+            .chain(self.untracked.keys())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|&peer| PeerCommunities {
+                peer,
+                communities: self.top_for_peer(peer, usize::MAX),
+                untracked: self.untracked.get(&peer).copied().unwrap_or(0),
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.peer);
+        CommunityStatsSnapshot {
+            communities: self.top(usize::MAX),
+            peers,
+            untracked: self.untracked.values().sum(),
+        }
+    }
+
+    fn add(&mut self, peer: IpAddr, attributes: &[PathAttribute]) {
+        for community in communities(attributes) {
+            if !self.is_tracked(community) {
+                *self.untracked.entry(peer).or_default() += 1;
+                continue;
+            }
+            *self.counts.entry(community).or_default() += 1;
+            *self
+                .peers
+                .entry(peer)
+                .or_default()
+                .entry(community)
+                .or_default() += 1;
+        }
+    }
+
+    fn remove(&mut self, peer: IpAddr, attributes: &[PathAttribute]) {
+        for community in communities(attributes) {
+            if !self.is_tracked(community) {
+                decrement(&mut self.untracked, peer);
+                continue;
+            }
+            decrement(&mut self.counts, community);
+            if let Some(counts) = self.peers.get_mut(&peer) {
+                decrement(counts, community);
+                if counts.is_empty() {
+                    self.peers.remove(&peer);
+                }
+            }
+        }
+    }
+
+    fn is_tracked(&self, community: Community) -> bool {
+        match &self.sampling {
+            Some(Sampling {
+                tracked: Some(tracked),
+                ..
+            }) => tracked.contains(&community),
+            _ => true,
+        }
+    }
+
+    /// Ends sampling once enough routes were announced, dropping the rare communities
+    fn sampled(&mut self) {
+        let Some(sampling) = &mut self.sampling else {
+            return;
+        };
+        if sampling.tracked.is_some() {
+            return;
+        }
+        sampling.seen += 1;
+        if sampling.seen < sampling.routes {
+            return;
+        }
+        let min_count = sampling.min_count;
+        let rare: Vec<Community> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count < min_count)
+            .map(|(community, _)| *community)
+            .collect();
+        for community in &rare {
+            self.counts.remove(community);
+        }
+        for (peer, counts) in &mut self.peers {
+            for community in &rare {
+                if let Some(count) = counts.remove(community) {
+                    *self.untracked.entry(*peer).or_default() += count;
+                }
+            }
+        }
+        self.peers.retain(|_, counts| !counts.is_empty());
+        sampling.tracked = Some(self.counts.keys().copied().collect());
+    }
+}
+
+/// The distinct communities of a route
+fn communities(attributes: &[PathAttribute]) -> Vec<Community> {
+    let mut communities: Vec<Community> = attributes
+        .iter()
+        .filter_map(|attribute| match &attribute.value {
+            AttributeValue::Communities(communities) => Some(&communities.communities),
+            _ => None,
+        })
+        .flatten()
+        .copied()
+        .collect();
+    communities.sort_unstable();
+    communities.dedup();
+    communities
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, u64>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+fn top(counts: &HashMap<Community, u64>, n: usize) -> Vec<CommunityCount> {
+    let mut top: Vec<CommunityCount> = counts
+        .iter()
+        .map(|(community, routes)| CommunityCount {
+            community: *community,
+            routes: *routes,
+        })
+        .collect();
+    top.sort_by(|a, b| b.routes.cmp(&a.routes).then(a.community.cmp(&b.community)));
+    top.truncate(n);
+    top
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::rib::RibIn;
+    use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+    fn community(value: u16) -> Community {
+        Community { asn: 64500, value }
+    }
+
+    fn announce(prefixes: &[&str], communities: &[Community]) -> UpdateMessage {
+        let builder = prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, prefix| {
+                builder.announce(prefix.parse().unwrap())
+            });
+        communities
+            .iter()
+            .fold(builder, |builder, community| builder.community(*community))
+            .next_hop("192.0.2.1".parse().unwrap())
+            .build()
+    }
+
+    fn withdraw(prefixes: &[&str]) -> UpdateMessage {
+        prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, prefix| {
+                builder.withdraw(prefix.parse().unwrap())
+            })
+            .build()
+    }
+
+    fn recount(ribs: &[(IpAddr, RibIn)]) -> CommunityStats {
+        let mut stats = CommunityStats::new();
+        for (peer, rib) in ribs {
+            for (key, attributes) in rib.iter() {
+                stats.apply(
+                    *peer,
+                    &RibChange::Announced {
+                        key: key.clone(),
+                        attributes: attributes.clone(),
+                    },
+                );
+            }
+        }
+        stats
+    }
+
+    #[test]
+    fn test_incremental_matches_recount() {
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let second: IpAddr = "192.0.2.2".parse().unwrap();
+        let script = [
+            (
+                first,
+                announce(
+                    &["10.0.0.0/8", "10.1.0.0/16"],
+                    &[community(1), Community::BLACKHOLE],
+                ),
+            ),
+            (
+                second,
+                announce(&["10.0.0.0/8"], &[community(1), community(1)]),
+            ),
+            (first, announce(&["10.1.0.0/16"], &[community(2)])),
+            (
+                second,
+                announce(&["192.0.2.0/24"], &[Community::BLACKHOLE, community(3)]),
+            ),
+            (first, withdraw(&["10.0.0.0/8", "203.0.113.0/24"])),
+            (first, announce(&["10.1.0.0/16"], &[community(2)])),
+            (second, announce(&["198.51.100.0/24"], &[])),
+            (second, withdraw(&["192.0.2.0/24"])),
+            (first, announce(&["172.16.0.0/12"], &[Community::BLACKHOLE])),
+        ];
+
+        let mut ribs = [(first, RibIn::new()), (second, RibIn::new())];
+        let mut stats = CommunityStats::new();
+        for (peer, update) in &script {
+            let (_, rib) = ribs.iter_mut().find(|(p, _)| p == peer).unwrap();
+            for change in rib.apply(update) {
+                stats.apply(*peer, &change);
+            }
+        }
+        assert_eq!(stats.snapshot(), recount(&ribs).snapshot());
+
+        assert_eq!(
+            stats.top(2),
+            vec![
+                CommunityCount {
+                    community: community(1),
+                    routes: 1
+                },
+                CommunityCount {
+                    community: community(2),
+                    routes: 1
+                },
+            ]
+        );
+        assert_eq!(stats.count(Community::BLACKHOLE), 1);
+        assert_eq!(stats.count(community(3)), 0);
+        assert_eq!(stats.peers_with(Community::BLACKHOLE), vec![(first, 1)]);
+        assert_eq!(stats.peer_count(second, community(1)), 1);
+        assert_eq!(stats.len(), 3);
+
+        // Withdrawing everything leaves nothing behind
+        for (peer, rib) in &mut ribs {
+            for change in rib.clear() {
+                stats.apply(*peer, &change);
+            }
+        }
+        assert!(stats.is_empty());
+        assert_eq!(
+            stats.snapshot(),
+            CommunityStatsSnapshot {
+                communities: vec![],
+                peers: vec![],
+                untracked: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_approximate_keeps_common_communities() {
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut rib = RibIn::new();
+        let mut stats = CommunityStats::approximate(3, 2);
+        let mut apply = |stats: &mut CommunityStats, update: UpdateMessage| {
+            for change in rib.apply(&update) {
+                stats.apply(peer, &change);
+            }
+        };
+
+        apply(
+            &mut stats,
+            announce(&["10.0.0.0/8"], &[Community::BLACKHOLE, community(1)]),
+        );
+        apply(
+            &mut stats,
+            announce(&["10.1.0.0/16"], &[Community::BLACKHOLE]),
+        );
+        assert_eq!(stats.len(), 2);
+        // The third route ends sampling, community 1 is too rare to keep
+        apply(
+            &mut stats,
+            announce(&["10.2.0.0/16"], &[Community::BLACKHOLE]),
+        );
+        assert_eq!(stats.top(10).len(), 1);
+        assert_eq!(stats.snapshot().untracked, 1);
+
+        apply(
+            &mut stats,
+            announce(&["10.3.0.0/16"], &[Community::BLACKHOLE, community(2)]),
+        );
+        assert_eq!(stats.count(Community::BLACKHOLE), 4);
+        assert_eq!(
+            (stats.count(community(2)), stats.snapshot().untracked),
+            (0, 2)
+        );
+
+        apply(&mut stats, withdraw(&["10.0.0.0/8", "10.3.0.0/16"]));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.communities[0].routes, 2);
+        assert_eq!(snapshot.untracked, 0);
+        assert_eq!(snapshot.peers[0].untracked, 0);
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::rib::{RouteEvent, RouteEventKind};
+use crate::update_message::IpAddrPrefix;
+
+/// Route flap dampening parameters (RFC 2439), defaulting to the values routers commonly use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DampeningConfig {
+    /// Time for a penalty to decay to half its value
+    pub half_life: Duration,
+    /// Penalty at which a route counts as suppressed
+    pub suppress: f64,
+    /// Penalty below which a suppressed route is usable again
+    pub reuse: f64,
+    /// Longest a route stays suppressed without further flaps, this caps the penalty
+    pub max_suppress_time: Duration,
+    pub withdrawal_penalty: f64,
+    pub attribute_change_penalty: f64,
+}
+
+/// A route crossed a dampening threshold
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlapEvent {
+    FlapSuppressed {
+        peer: IpAddr,
+        prefix: IpAddrPrefix,
+        penalty: f64,
+        at: SystemTime,
+    },
+    FlapReuse {
+        peer: IpAddr,
+        prefix: IpAddrPrefix,
+        at: SystemTime,
+    },
+}
+
+/// The flap history of a route as of a given time
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlapStatus {
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    pub penalty: f64,
+    pub suppressed: bool,
+    /// Withdrawals and attribute changes since the entry was created
+    pub flaps: u32,
+}
+
+/// Tracks a dampening penalty per peer and prefix from route events.
+///
+/// Time is taken from the event timestamps, never from the system clock. Entries are created
+/// by the first flap and evicted by [`FlapTracker::expire`] once their penalty decays below
+/// half the reuse threshold, so memory only grows with the routes currently flapping.
+#[derive(Debug, Clone, Default)]
+pub struct FlapTracker {
+    config: DampeningConfig,
+    routes: HashMap<(IpAddr, IpAddrPrefix), FlapState>,
+}
+
+#[derive(Debug, Clone)]
+struct FlapState {
+    penalty: f64,
+    updated: SystemTime,
+    suppressed: bool,
+    flaps: u32,
+}
+
+impl Default for DampeningConfig {
+    fn default() -> Self {
+        DampeningConfig {
+            half_life: Duration::from_secs(15 * 60),
+            suppress: 2000.0,
+            reuse: 750.0,
+            max_suppress_time: Duration::from_secs(60 * 60),
+            withdrawal_penalty: 1000.0,
+            attribute_change_penalty: 500.0,
+        }
+    }
+}
+
+impl DampeningConfig {
+    /// The penalty that takes `max_suppress_time` to decay to the reuse threshold
+    pub fn max_penalty(&self) -> f64 {
+        self.reuse * (self.max_suppress_time.as_secs_f64() / self.half_life.as_secs_f64()).exp2()
+    }
+
+    fn decay(&self, penalty: f64, elapsed: Duration) -> f64 {
+        penalty * (-elapsed.as_secs_f64() / self.half_life.as_secs_f64()).exp2()
+    }
+}
+
+impl FlapState {
+    fn decay(&mut self, config: &DampeningConfig, now: SystemTime) {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        self.penalty = config.decay(self.penalty, elapsed);
+        self.updated = self.updated.max(now);
+    }
+}
+
+impl FlapTracker {
+    pub fn new(config: DampeningConfig) -> Self {
+        FlapTracker {
+            config,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Charges the penalty for a withdrawal or attribute change and reports a crossed
+    /// threshold
+    pub fn observe(&mut self, event: &RouteEvent) -> Option<FlapEvent> {
+        let increment = match event.kind {
+            RouteEventKind::Withdrawn => self.config.withdrawal_penalty,
+            RouteEventKind::Reannounced { .. } => self.config.attribute_change_penalty,
+            RouteEventKind::Announced { .. } => 0.0,
+        };
+        let key = (event.peer, event.prefix.clone());
+        if increment == 0.0 && !self.routes.contains_key(&key) {
+            return None;
+        }
+
+        let at = event.timestamp;
+        let state = self.routes.entry(key).or_insert(FlapState {
+            penalty: 0.0,
+            updated: at,
+            suppressed: false,
+            flaps: 0,
+        });
+        state.decay(&self.config, at);
+        if increment > 0.0 {
+            state.penalty = (state.penalty + increment).min(self.config.max_penalty());
+            state.flaps += 1;
+        }
+
+        let (peer, prefix) = (event.peer, event.prefix.clone());
+        if !state.suppressed && state.penalty >= self.config.suppress {
+            state.suppressed = true;
+            let penalty = state.penalty;
+            return Some(FlapEvent::FlapSuppressed {
+                peer,
+                prefix,
+                penalty,
+                at,
+            });
+        }
+        if state.suppressed && state.penalty < self.config.reuse {
+            state.suppressed = false;
+            return Some(FlapEvent::FlapReuse { peer, prefix, at });
+        }
+        None
+    }
+
+    /// Decays every penalty to `now`, reporting routes that became usable again and evicting
+    /// the ones that stopped flapping
+    pub fn expire(&mut self, now: SystemTime) -> Vec<FlapEvent> {
+        let config = self.config;
+        let mut events = vec![];
+        self.routes.retain(|(peer, prefix), state| {
+            state.decay(&config, now);
+            if state.suppressed && state.penalty < config.reuse {
+                state.suppressed = false;
+                events.push(FlapEvent::FlapReuse {
+                    peer: *peer,
+                    prefix: prefix.clone(),
+                    at: now,
+                });
+            }
+            state.penalty >= config.reuse / 2.0
+        });
+        events
+    }
+
+    /// The route's penalty decayed to `now`, zero when it isn't tracked
+    pub fn penalty(&self, peer: IpAddr, prefix: &IpAddrPrefix, now: SystemTime) -> f64 {
+        self.routes
+            .get(&(peer, prefix.clone()))
+            .map_or(0.0, |state| self.status(peer, prefix, state, now).penalty)
+    }
+
+    /// The `n` routes with the highest penalties as of `now`, highest first
+    pub fn top(&self, n: usize, now: SystemTime) -> Vec<FlapStatus> {
+        let mut statuses: Vec<FlapStatus> = self
+            .routes
+            .iter()
+            .map(|((peer, prefix), state)| self.status(*peer, prefix, state, now))
+            .collect();
+        statuses.sort_by(|a, b| b.penalty.total_cmp(&a.penalty));
+        statuses.truncate(n);
+        statuses
+    }
+
+    /// Number of tracked routes
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    fn status(
+        &self,
+        peer: IpAddr,
+        prefix: &IpAddrPrefix,
+        state: &FlapState,
+        now: SystemTime,
+    ) -> FlapStatus {
+        let elapsed = now.duration_since(state.updated).unwrap_or_default();
+        FlapStatus {
+            peer,
+            prefix: prefix.clone(),
+            penalty: self.config.decay(state.penalty, elapsed),
+            suppressed: state.suppressed,
+            flaps: state.flaps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    use crate::address_family::Safi;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn at(minutes: u32) -> SystemTime {
+        SystemTime::UNIX_EPOCH + MINUTE * minutes
+    }
+
+    fn prefix() -> IpAddrPrefix {
+        "198.51.100.0/24".parse().unwrap()
+    }
+
+    fn event(minutes: u32, kind: RouteEventKind) -> RouteEvent {
+        RouteEvent {
+            peer: PEER,
+            timestamp: at(minutes),
+            prefix: prefix(),
+            safi: Safi::Unicast,
+            path_id: None,
+            kind,
+            rpki: None,
+        }
+    }
+
+    fn announced() -> RouteEventKind {
+        RouteEventKind::Announced {
+            attrs: Arc::new([]),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_flap_sequence() {
+        let mut tracker = FlapTracker::default();
+        assert_eq!(tracker.observe(&event(0, announced())), None);
+        assert!(tracker.is_empty());
+
+        assert_eq!(tracker.observe(&event(0, RouteEventKind::Withdrawn)), None);
+        assert_eq!(tracker.observe(&event(1, announced())), None);
+        assert_close(tracker.penalty(PEER, &prefix(), at(1)), 954.84);
+        assert_eq!(tracker.observe(&event(2, RouteEventKind::Withdrawn)), None);
+        assert_close(tracker.penalty(PEER, &prefix(), at(2)), 1911.72);
+        assert_eq!(tracker.observe(&event(3, announced())), None);
+
+        let Some(FlapEvent::FlapSuppressed {
+            penalty, at: when, ..
+        }) = tracker.observe(&event(4, RouteEventKind::Withdrawn))
+        else {
+            panic!("expected the route to be suppressed");
+        };
+        assert_close(penalty, 2742.96);
+        assert_eq!(when, at(4));
+        assert_eq!(tracker.top(1, at(4))[0].flaps, 3);
+        assert!(tracker.top(1, at(4))[0].suppressed);
+
+        // Reuse is reached 28 minutes later
+        assert!(tracker.expire(at(32)).is_empty());
+        assert_eq!(
+            tracker.expire(at(34)),
+            vec![FlapEvent::FlapReuse {
+                peer: PEER,
+                prefix: prefix(),
+                at: at(34),
+            }]
+        );
+        assert_close(tracker.penalty(PEER, &prefix(), at(34)), 685.74);
+
+        // Evicted once below half the reuse threshold
+        tracker.expire(at(46));
+        assert_eq!(tracker.len(), 1);
+        tracker.expire(at(48));
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.penalty(PEER, &prefix(), at(48)), 0.0);
+    }
+
+    #[test]
+    fn test_reuse_on_event() {
+        let mut tracker = FlapTracker::default();
+        for minute in 0..3 {
+            tracker.observe(&event(minute, RouteEventKind::Withdrawn));
+        }
+        assert!(tracker.top(1, at(2))[0].suppressed);
+        let reannounced = RouteEventKind::Reannounced {
+            old_attrs: Arc::new([]),
+            new_attrs: Arc::new([]),
+        };
+        assert_eq!(
+            tracker.observe(&event(60, reannounced)),
+            Some(FlapEvent::FlapReuse {
+                peer: PEER,
+                prefix: prefix(),
+                at: at(60),
+            })
+        );
+    }
+
+    #[test]
+    fn test_penalty_is_capped() {
+        let config = DampeningConfig::default();
+        assert_close(config.max_penalty(), 12000.0);
+        let mut tracker = FlapTracker::new(config);
+        for _ in 0..50 {
+            tracker.observe(&event(0, RouteEventKind::Withdrawn));
+        }
+        assert_close(tracker.penalty(PEER, &prefix(), at(0)), 12000.0);
+        // Without further flaps the route is reused after the maximum suppress time
+        assert!(tracker.expire(at(59)).is_empty());
+        assert_eq!(tracker.expire(at(61)).len(), 1);
+    }
+
+    #[test]
+    fn test_top_flapping() {
+        let mut tracker = FlapTracker::default();
+        for (i, flaps) in [1, 3, 2].into_iter().enumerate() {
+            let prefix: IpAddrPrefix = format!("10.{i}.0.0/16").parse().unwrap();
+            for _ in 0..flaps {
+                let mut event = event(0, RouteEventKind::Withdrawn);
+                event.prefix = prefix.clone();
+                tracker.observe(&event);
+            }
+        }
+        let top: Vec<_> = tracker
+            .top(2, at(0))
+            .into_iter()
+            .map(|status| (status.prefix.to_string(), status.flaps))
+            .collect();
+        assert_eq!(
+            top,
+            vec![
+                ("10.1.0.0/16".to_string(), 3),
+                ("10.2.0.0/16".to_string(), 2)
+            ]
+        );
+    }
+}
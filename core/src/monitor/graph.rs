@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::attribute::{AsPath, AsPathSegmentType};
+
+/// An adjacency between two ASes, as observed in AS_PATHs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsEdge {
+    /// The lower ASN
+    pub a: u32,
+    /// The higher ASN
+    pub b: u32,
+    /// Paths the adjacency was observed in
+    pub count: u32,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// An adjacency observed for the first time, as when two ASes start to interconnect or one
+/// leaks routes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewEdge {
+    pub a: u32,
+    pub b: u32,
+    pub at: SystemTime,
+}
+
+/// The AS-level topology seen in AS_PATHs: ASNs linked by every adjacent pair observed.
+///
+/// Edges are undirected. Prepending doesn't make an AS its own neighbour. AS_SETs contribute
+/// edges conservatively: an aggregate's set doesn't say which member neighbours the ASes
+/// around it, so a set with several members ends the sequence of adjacencies, while a set with
+/// a single member counts as that ASN. Confederation segments hold member ASNs private to the
+/// confederation and end it too.
+/// Time is taken from the observation timestamps.
+///
+/// An edge takes 16 bytes of metadata, counts and seconds since the epoch, plus 4 bytes on
+/// each side of the adjacency lists, so the DFZ's half a million edges stay within a few tens
+/// of megabytes.
+#[derive(Debug, Clone, Default)]
+pub struct AsGraph {
+    edges: HashMap<(u32, u32), Edge>,
+    neighbors: HashMap<u32, Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    count: u32,
+    /// Seconds since the epoch
+    first_seen: u32,
+    last_seen: u32,
+}
+
+impl AsGraph {
+    pub fn new() -> Self {
+        AsGraph::default()
+    }
+
+    /// Adds the adjacencies of a path observed at `at`, reporting those never seen before
+    pub fn observe(&mut self, as_path: &AsPath, at: SystemTime) -> Vec<NewEdge> {
+        let now = seconds(at);
+        let mut new_edges = vec![];
+        let mut previous = None;
+        for segment in &as_path.segments {
+            let asns: &[u32] = match segment.segment_type {
+                AsPathSegmentType::AsSequence => &segment.asns,
+                AsPathSegmentType::AsSet if segment.asns.len() == 1 => &segment.asns,
+                _ => {
+                    previous = None;
+                    continue;
+                }
+            };
+            for &asn in asns {
+                if let Some(previous) = previous.filter(|previous| *previous != asn) {
+                    let (a, b) = (asn.min(previous), asn.max(previous));
+                    if self.add(a, b, now) {
+                        new_edges.push(NewEdge { a, b, at });
+                    }
+                }
+                previous = Some(asn);
+            }
+        }
+        new_edges
+    }
+
+    /// Counts a sighting of the edge, returning whether it's new
+    fn add(&mut self, a: u32, b: u32, now: u32) -> bool {
+        if let Some(edge) = self.edges.get_mut(&(a, b)) {
+            edge.count = edge.count.saturating_add(1);
+            edge.first_seen = edge.first_seen.min(now);
+            edge.last_seen = edge.last_seen.max(now);
+            return false;
+        }
+        self.edges.insert(
+            (a, b),
+            Edge {
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+            },
+        );
+        self.neighbors.entry(a).or_default().push(b);
+        self.neighbors.entry(b).or_default().push(a);
+        true
+    }
+
+    /// The ASes adjacent to `asn`, in ASN order
+    pub fn neighbors(&self, asn: u32) -> Vec<u32> {
+        let mut neighbors = self.neighbors.get(&asn).cloned().unwrap_or_default();
+        neighbors.sort_unstable();
+        neighbors
+    }
+
+    pub fn degree(&self, asn: u32) -> usize {
+        self.neighbors.get(&asn).map_or(0, Vec::len)
+    }
+
+    pub fn edge(&self, a: u32, b: u32) -> Option<AsEdge> {
+        let (a, b) = (a.min(b), a.max(b));
+        self.edges.get(&(a, b)).map(|edge| edge.public(a, b))
+    }
+
+    /// Every edge, ordered by ASNs
+    pub fn edges(&self) -> Vec<AsEdge> {
+        let mut edges: Vec<AsEdge> = self
+            .edges
+            .iter()
+            .map(|(&(a, b), edge)| edge.public(a, b))
+            .collect();
+        edges.sort_by_key(|edge| (edge.a, edge.b));
+        edges
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Writes the graph in Graphviz DOT, edges labelled with their counts
+    pub fn write_dot(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "graph as_graph {{")?;
+        for edge in self.edges() {
+            writeln!(
+                out,
+                "  AS{} -- AS{} [label=\"{}\"];",
+                edge.a, edge.b, edge.count
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Writes one CSV line per edge after a header, times as seconds since the epoch
+    pub fn write_csv(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "as_a,as_b,count,first_seen,last_seen")?;
+        for edge in self.edges() {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                edge.a,
+                edge.b,
+                edge.count,
+                seconds(edge.first_seen),
+                seconds(edge.last_seen)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Edge {
+    fn public(&self, a: u32, b: u32) -> AsEdge {
+        AsEdge {
+            a,
+            b,
+            count: self.count,
+            first_seen: UNIX_EPOCH + Duration::from_secs(self.first_seen.into()),
+            last_seen: UNIX_EPOCH + Duration::from_secs(self.last_seen.into()),
+        }
+    }
+}
+
+fn seconds(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .min(u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    use crate::attribute::AsPathSegment;
+
+    /// Parses the paths of `tests/data/as_paths.txt`
+    fn fixture() -> Vec<AsPath> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/as_paths.txt");
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let mut segments = vec![];
+                for (i, part) in line.split(['{', '}']).enumerate() {
+                    let asns: Vec<u32> = part
+                        .split_whitespace()
+                        .map(|asn| asn.parse().unwrap())
+                        .collect();
+                    if asns.is_empty() {
+                        continue;
+                    }
+                    let segment_type = match i % 2 {
+                        0 => AsPathSegmentType::AsSequence,
+                        _ => AsPathSegmentType::AsSet,
+                    };
+                    segments.push(AsPathSegment {
+                        segment_type,
+                        asns: asns.into(),
+                    });
+                }
+                AsPath {
+                    segments: segments.into(),
+                }
+            })
+            .collect()
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    #[test]
+    fn test_graph_from_fixture() {
+        let mut graph = AsGraph::new();
+        let mut new_edges = vec![];
+        for (i, path) in fixture().iter().enumerate() {
+            let found = graph.observe(path, at(i as u64 * 60));
+            new_edges.push(
+                found
+                    .iter()
+                    .map(|edge| (edge.a, edge.b))
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(
+            new_edges,
+            vec![
+                vec![(1299, 3356), (1299, 64500)],
+                // Prepending adds nothing
+                vec![],
+                vec![(174, 3356), (174, 64501)],
+                vec![],
+                vec![(1299, 6939)],
+                // The set's members may neighbour 64510 or not
+                vec![(6939, 64510)],
+                // A single member set counts as that AS
+                vec![(3356, 64520), (64520, 64521)],
+                vec![],
+            ]
+        );
+        assert_eq!((graph.node_count(), graph.edge_count()), (9, 8));
+        assert_eq!(graph.neighbors(1299), vec![3356, 6939, 64500]);
+        assert_eq!(graph.degree(3356), 3);
+        assert_eq!(graph.neighbors(64511), Vec::<u32>::new());
+
+        let edge = graph.edge(64500, 1299).unwrap();
+        assert_eq!((edge.a, edge.b, edge.count), (1299, 64500, 4));
+        assert_eq!((edge.first_seen, edge.last_seen), (at(0), at(420)));
+
+        let mut dot = vec![];
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("graph as_graph {\n  AS174 -- AS3356 [label=\"1\"];\n"));
+        assert!(dot.contains("\n  AS1299 -- AS64500 [label=\"4\"];\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let mut csv = vec![];
+        graph.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "as_a,as_b,count,first_seen,last_seen");
+        assert!(lines.contains(&"1299,6939,2,1700000240,1700000420"));
+    }
+
+    #[test]
+    fn test_out_of_order_sightings() {
+        let mut graph = AsGraph::new();
+        let path = AsPath {
+            segments: [AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns: [64500, 64501].into(),
+            }]
+            .into(),
+        };
+        assert_eq!(graph.observe(&path, at(100)).len(), 1);
+        assert!(graph.observe(&path, at(50)).is_empty());
+        let edge = graph.edge(64500, 64501).unwrap();
+        assert_eq!((edge.first_seen, edge.last_seen), (at(50), at(100)));
+    }
+}
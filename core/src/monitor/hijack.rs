@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::rib::{RouteEvent, RouteEventKind};
+use crate::trie::PrefixTrie;
+use crate::update_message::IpAddrPrefix;
+
+use super::origin_as;
+
+/// How the [`HijackDetector`] treats announcements of monitored space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HijackConfig {
+    /// Alert on more-specifics even when they come from a legitimate origin
+    pub alert_more_specifics: bool,
+    /// How long an alert for the same prefix, origin and peer isn't raised again
+    pub suppress_for: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HijackKind {
+    /// A monitored prefix announced from an unexpected origin
+    OriginMismatch,
+    /// A more-specific of a monitored prefix from an unexpected origin
+    SubPrefix,
+    /// A more-specific from a legitimate origin, see [`HijackConfig::alert_more_specifics`]
+    MoreSpecific,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HijackAlert {
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    /// The monitored prefix the announcement falls in
+    pub monitored: IpAddrPrefix,
+    /// `None` when the AS_PATH ends in an AS_SET
+    pub origin: Option<u32>,
+    pub kind: HijackKind,
+    pub at: SystemTime,
+}
+
+/// Raises alerts when peers announce our prefixes, or parts of them, from the wrong origin.
+///
+/// Announcements are matched against the most specific monitored prefix covering them. The
+/// origin is the last ASN of the AS_PATH; as in RFC 6811, a path ending in an AS_SET has no
+/// origin and never matches. Time is taken from the event timestamps.
+#[derive(Debug, Clone)]
+pub struct HijackDetector {
+    config: HijackConfig,
+    monitored: Vec<(IpAddrPrefix, Vec<u32>)>,
+    /// Indexes into `monitored` by prefix
+    trie: PrefixTrie<Vec<u32>>,
+    /// When each (prefix, origin, peer) was last alerted on
+    alerted: HashMap<(IpAddrPrefix, Option<u32>, IpAddr), SystemTime>,
+}
+
+impl Default for HijackConfig {
+    fn default() -> Self {
+        HijackConfig {
+            alert_more_specifics: false,
+            suppress_for: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl HijackDetector {
+    pub fn new(config: HijackConfig) -> Self {
+        HijackDetector {
+            config,
+            monitored: vec![],
+            trie: PrefixTrie::new(),
+            alerted: HashMap::new(),
+        }
+    }
+
+    /// Monitors `prefix` and its more-specifics, which only `origins` may announce
+    pub fn monitor(&mut self, prefix: IpAddrPrefix, origins: impl IntoIterator<Item = u32>) {
+        let i = self.monitored.len() as u32;
+        match self.trie.get_mut(&prefix) {
+            Some(monitored) => monitored.push(i),
+            None => {
+                self.trie.insert(&prefix, vec![i]);
+            }
+        }
+        self.monitored.push((prefix, origins.into_iter().collect()));
+    }
+
+    pub fn observe(&mut self, event: &RouteEvent) -> Option<HijackAlert> {
+        let attrs = match &event.kind {
+            RouteEventKind::Announced { attrs }
+            | RouteEventKind::Reannounced {
+                new_attrs: attrs, ..
+            } => attrs,
+            RouteEventKind::Withdrawn => return None,
+        };
+        // Prefixes monitored twice allow the origins of both
+        let (_, matches) = self.trie.covering(&event.prefix).last()?;
+        let (monitored, _) = &self.monitored[*matches.first()? as usize];
+
+        let origin = origin_as(attrs);
+        let legitimate = origin.is_some_and(|origin| {
+            matches
+                .iter()
+                .any(|&i| self.monitored[i as usize].1.contains(&origin))
+        });
+        let exact = *monitored == event.prefix;
+        let kind = match (exact, legitimate) {
+            (true, true) => return None,
+            (true, false) => HijackKind::OriginMismatch,
+            (false, false) => HijackKind::SubPrefix,
+            (false, true) if self.config.alert_more_specifics => HijackKind::MoreSpecific,
+            (false, true) => return None,
+        };
+
+        let key = (event.prefix.clone(), origin, event.peer);
+        if let Some(&last) = self.alerted.get(&key)
+            && event.timestamp.duration_since(last).unwrap_or_default() < self.config.suppress_for
+        {
+            return None;
+        }
+        self.alerted.insert(key, event.timestamp);
+
+        Some(HijackAlert {
+            peer: event.peer,
+            prefix: event.prefix.clone(),
+            monitored: monitored.clone(),
+            origin,
+            kind,
+            at: event.timestamp,
+        })
+    }
+
+    /// Forgets alerts that no longer suppress anything at `now`
+    pub fn expire(&mut self, now: SystemTime) {
+        let suppress_for = self.config.suppress_for;
+        self.alerted
+            .retain(|_, last| now.duration_since(*last).unwrap_or_default() < suppress_for);
+    }
+}
+
+impl Default for HijackDetector {
+    fn default() -> Self {
+        HijackDetector::new(HijackConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::attribute::AsPathSegmentType::{AsSequence, AsSet};
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::rib::RouteEventSource;
+    use crate::update_message::UpdateMessageBuilder;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn detector(config: HijackConfig) -> HijackDetector {
+        let mut detector = HijackDetector::new(config);
+        detector.monitor("203.0.113.0/24".parse().unwrap(), [64500]);
+        detector.monitor("2001:db8::/32".parse().unwrap(), [64500, 64501]);
+        detector
+    }
+
+    fn alert(
+        detector: &mut HijackDetector,
+        peer: IpAddr,
+        prefix: &str,
+        segments: &[(AsPathSegmentType, &[u32])],
+        minutes: u64,
+    ) -> Option<HijackAlert> {
+        let as_path = AsPath {
+            segments: segments
+                .iter()
+                .map(|(segment_type, asns)| AsPathSegment {
+                    segment_type: *segment_type,
+                    asns: asns.to_vec().into(),
+                })
+                .collect(),
+        };
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix.parse().unwrap())
+            .as_path(as_path)
+            .next_hop("198.51.100.1".parse().unwrap())
+            .build();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(minutes * 60);
+        let events = RouteEventSource::stateless(peer).events(&update, at);
+        detector.observe(&events[0])
+    }
+
+    #[test]
+    fn test_legitimate_announcement() {
+        let mut detector = detector(HijackConfig::default());
+        let path = [(AsSequence, &[65001, 64500][..])];
+        assert_eq!(alert(&mut detector, PEER, "203.0.113.0/24", &path, 0), None);
+        assert_eq!(
+            alert(&mut detector, PEER, "203.0.113.128/25", &path, 0),
+            None
+        );
+        let other_origin = [(AsSequence, &[65001, 64501][..])];
+        assert_eq!(
+            alert(&mut detector, PEER, "2001:db8:1::/48", &other_origin, 0),
+            None
+        );
+        // Unmonitored space
+        assert_eq!(
+            alert(&mut detector, PEER, "198.51.100.0/24", &other_origin, 0),
+            None
+        );
+        assert_eq!(
+            alert(&mut detector, PEER, "203.0.112.0/23", &other_origin, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_same_prefix_different_origin() {
+        let mut detector = detector(HijackConfig::default());
+        let hijack = [(AsSequence, &[65001, 65666][..])];
+        let found = alert(&mut detector, PEER, "203.0.113.0/24", &hijack, 0).unwrap();
+        assert_eq!(found.kind, HijackKind::OriginMismatch);
+        assert_eq!(found.origin, Some(65666));
+        assert_eq!(found.monitored, "203.0.113.0/24".parse().unwrap());
+
+        // An AS_SET has no origin to match, even when it holds ours
+        let aggregated = [(AsSequence, &[65001][..]), (AsSet, &[64500][..])];
+        let found = alert(&mut detector, PEER, "203.0.113.0/24", &aggregated, 0).unwrap();
+        assert_eq!(
+            (found.kind, found.origin),
+            (HijackKind::OriginMismatch, None)
+        );
+    }
+
+    #[test]
+    fn test_sub_prefix() {
+        let mut detector = detector(HijackConfig::default());
+        let hijack = [(AsSequence, &[65001, 65666][..])];
+        let found = alert(&mut detector, PEER, "203.0.113.0/25", &hijack, 0).unwrap();
+        assert_eq!(found.kind, HijackKind::SubPrefix);
+        assert_eq!(found.prefix, "203.0.113.0/25".parse().unwrap());
+
+        let found = alert(&mut detector, PEER, "2001:db8:ff00::/40", &hijack, 0).unwrap();
+        assert_eq!(found.monitored, "2001:db8::/32".parse().unwrap());
+    }
+
+    #[test]
+    fn test_any_more_specific() {
+        let config = HijackConfig {
+            alert_more_specifics: true,
+            ..HijackConfig::default()
+        };
+        let mut detector = detector(config);
+        let path = [(AsSequence, &[65001, 64500][..])];
+        assert_eq!(alert(&mut detector, PEER, "203.0.113.0/24", &path, 0), None);
+        let found = alert(&mut detector, PEER, "203.0.113.0/26", &path, 0).unwrap();
+        assert_eq!(found.kind, HijackKind::MoreSpecific);
+    }
+
+    #[test]
+    fn test_repeat_alerts_suppressed() {
+        let mut detector = detector(HijackConfig {
+            alert_more_specifics: false,
+            suppress_for: Duration::from_secs(10 * 60),
+        });
+        let hijack = [(AsSequence, &[65001, 65666][..])];
+        assert!(alert(&mut detector, PEER, "203.0.113.0/24", &hijack, 0).is_some());
+        assert!(alert(&mut detector, PEER, "203.0.113.0/24", &hijack, 5).is_none());
+        // Another peer, prefix or origin is a new alert
+        assert!(alert(&mut detector, OTHER_PEER, "203.0.113.0/24", &hijack, 5).is_some());
+        assert!(alert(&mut detector, PEER, "203.0.113.0/25", &hijack, 5).is_some());
+        let another = [(AsSequence, &[65001, 65667][..])];
+        assert!(alert(&mut detector, PEER, "203.0.113.0/24", &another, 5).is_some());
+
+        assert!(alert(&mut detector, PEER, "203.0.113.0/24", &hijack, 10).is_some());
+
+        detector.expire(SystemTime::UNIX_EPOCH + Duration::from_secs(30 * 60));
+        assert!(detector.alerted.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+mod bogon;
+mod coalesce;
+mod communities;
+mod flap;
+mod graph;
+mod hijack;
+mod origin;
+mod path;
+mod peer;
+mod watch;
+mod window;
+
+pub use bogon::{BogonAlert, BogonChecker, BogonKind, BogonListError};
+pub use coalesce::{CoalesceConfig, CoalescedEvent, Coalescer, RateLimit, RouteSummary};
+pub use communities::{CommunityCount, CommunityStats, CommunityStatsSnapshot, PeerCommunities};
+pub use flap::{DampeningConfig, FlapEvent, FlapStatus, FlapTracker};
+pub use graph::{AsEdge, AsGraph, NewEdge};
+pub use hijack::{HijackAlert, HijackConfig, HijackDetector, HijackKind};
+pub use origin::{OriginEvent, OriginRecord, OriginTracker};
+pub use path::{PathAnomaly, PathAnomalyConfig, PathAnomalyDetector, PathAnomalyKind};
+pub use peer::{Convergence, FamilyCount, PeerMonitor, PeerMonitorSnapshot, WindowCounts};
+#[cfg(feature = "tokio")]
+pub use watch::Watch;
+pub use watch::{Monitor, WatchId, WatchOptions};
+pub use window::{RateWindow, WindowedCounter};
+
+use crate::attribute::{AttributeValue, PathAttribute};
+
+/// The ASN that originated a route: the last ASN of the AS_PATH when it ends in an
+/// AS_SEQUENCE
+pub(crate) fn origin_as(attributes: &[PathAttribute]) -> Option<u32> {
+    let as_path = attributes
+        .iter()
+        .find_map(|attribute| match &attribute.value {
+            AttributeValue::AsPath(as_path) => Some(as_path),
+            _ => None,
+        })?;
+    as_path.origin_as()
+}
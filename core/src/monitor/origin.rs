@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::rib::{RouteEvent, RouteEventKind};
+use crate::update_message::IpAddrPrefix;
+
+use super::origin_as;
+
+/// A change in the origins a prefix is announced from
+#[derive(Debug, Clone, PartialEq)]
+pub enum OriginEvent {
+    /// A peer now announces the prefix from a different origin than before
+    OriginChanged {
+        prefix: IpAddrPrefix,
+        old: u32,
+        new: u32,
+        peer: IpAddr,
+        at: SystemTime,
+    },
+    /// The prefix is announced from several origins, or from a different set of them
+    Moas {
+        prefix: IpAddrPrefix,
+        origins: Vec<u32>,
+        at: SystemTime,
+    },
+    /// The prefix is announced from a single origin again, or not at all
+    MoasResolved {
+        prefix: IpAddrPrefix,
+        origin: Option<u32>,
+        at: SystemTime,
+    },
+}
+
+/// An origin a peer announced a prefix from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OriginRecord {
+    pub peer: IpAddr,
+    pub origin: u32,
+    pub first_seen: SystemTime,
+    /// When the peer last announced the prefix from the origin, or stopped doing so
+    pub last_seen: SystemTime,
+    /// Whether the peer currently announces the prefix from the origin
+    pub active: bool,
+}
+
+/// Remembers which origin ASNs each prefix was recently announced from, detecting origin
+/// changes and multiple origin AS (MOAS) conflicts.
+///
+/// A peer's origin for a prefix is remembered while it's announced and for the retention
+/// window afterwards, so a route that comes back from another origin after a withdrawal still
+/// counts as a change. Routes whose AS_PATH ends in an AS_SET have no origin, so they count as
+/// withdrawals.
+/// Time is taken from the event timestamps.
+///
+/// Prefixes are keyed by address and length without heap allocations, peers are interned and
+/// timestamps kept as seconds, so a prefix costs about 60 bytes plus 16 per origin record.
+/// With every peer announcing the same origin, a full IPv4 table of 1M prefixes from 10 peers
+/// takes roughly 220 MB until [`OriginTracker::expire`] drops withdrawn routes.
+#[derive(Debug, Clone)]
+pub struct OriginTracker {
+    retention: Duration,
+    prefixes: HashMap<PrefixKey, Vec<Entry>>,
+    peers: Vec<IpAddr>,
+    peer_ids: HashMap<IpAddr, u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PrefixKey {
+    addr: IpAddr,
+    length: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    peer: u32,
+    origin: u32,
+    /// Seconds since the epoch
+    first_seen: u32,
+    last_seen: u32,
+    active: bool,
+}
+
+impl OriginTracker {
+    /// Keeps origins that are no longer announced for `retention`
+    pub fn new(retention: Duration) -> Self {
+        OriginTracker {
+            retention,
+            prefixes: HashMap::new(),
+            peers: vec![],
+            peer_ids: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, event: &RouteEvent) -> Vec<OriginEvent> {
+        let key = PrefixKey::from(&event.prefix);
+        let now = seconds(event.timestamp);
+        let origin = match &event.kind {
+            RouteEventKind::Announced { attrs }
+            | RouteEventKind::Reannounced {
+                new_attrs: attrs, ..
+            } => origin_as(attrs),
+            RouteEventKind::Withdrawn => None,
+        };
+        let peer = match (origin, self.peer_ids.get(&event.peer)) {
+            (_, Some(&peer)) => peer,
+            (Some(_), None) => self.intern(event.peer),
+            // A peer we know nothing about can't withdraw anything
+            (None, None) => return vec![],
+        };
+        if origin.is_none() && !self.prefixes.contains_key(&key) {
+            return vec![];
+        }
+        let retention = self.retention.as_secs();
+        let entries = self.prefixes.entry(key).or_default();
+        let before = active_origins(entries);
+
+        let previous = entries
+            .iter()
+            .filter(|entry| entry.peer == peer)
+            .filter(|entry| entry.active || now.saturating_sub(entry.last_seen) as u64 <= retention)
+            .max_by_key(|entry| (entry.active, entry.last_seen))
+            .map(|entry| entry.origin);
+        for entry in entries
+            .iter_mut()
+            .filter(|entry| entry.peer == peer && entry.active)
+        {
+            entry.active = false;
+            entry.last_seen = now;
+        }
+
+        let mut events = vec![];
+        if let Some(origin) = origin {
+            match entries
+                .iter_mut()
+                .find(|entry| entry.peer == peer && entry.origin == origin)
+            {
+                Some(entry) => {
+                    entry.active = true;
+                    entry.last_seen = now;
+                }
+                None => entries.push(Entry {
+                    peer,
+                    origin,
+                    first_seen: now,
+                    last_seen: now,
+                    active: true,
+                }),
+            }
+            if let Some(old) = previous.filter(|&old| old != origin) {
+                events.push(OriginEvent::OriginChanged {
+                    prefix: event.prefix.clone(),
+                    old,
+                    new: origin,
+                    peer: event.peer,
+                    at: event.timestamp,
+                });
+            }
+        }
+
+        let after = active_origins(entries);
+        if after.len() > 1 && after != before {
+            events.push(OriginEvent::Moas {
+                prefix: event.prefix.clone(),
+                origins: after,
+                at: event.timestamp,
+            });
+        } else if before.len() > 1 && after.len() <= 1 {
+            events.push(OriginEvent::MoasResolved {
+                prefix: event.prefix.clone(),
+                origin: after.first().copied(),
+                at: event.timestamp,
+            });
+        }
+        events
+    }
+
+    /// Drops origins that stopped being announced more than the retention window before `now`
+    pub fn expire(&mut self, now: SystemTime) {
+        let (now, retention) = (seconds(now), self.retention.as_secs());
+        self.prefixes.retain(|_, entries| {
+            entries.retain(|entry| {
+                entry.active || now.saturating_sub(entry.last_seen) as u64 <= retention
+            });
+            !entries.is_empty()
+        });
+    }
+
+    /// What the tracker remembers about `prefix`
+    pub fn origins(&self, prefix: &IpAddrPrefix) -> Vec<OriginRecord> {
+        let Some(entries) = self.prefixes.get(&PrefixKey::from(prefix)) else {
+            return vec![];
+        };
+        entries
+            .iter()
+            .map(|entry| OriginRecord {
+                peer: self.peers[entry.peer as usize],
+                origin: entry.origin,
+                first_seen: UNIX_EPOCH + Duration::from_secs(entry.first_seen as u64),
+                last_seen: UNIX_EPOCH + Duration::from_secs(entry.last_seen as u64),
+                active: entry.active,
+            })
+            .collect()
+    }
+
+    /// Number of prefixes with remembered origins
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    fn intern(&mut self, peer: IpAddr) -> u32 {
+        let id = self.peers.len() as u32;
+        self.peers.push(peer);
+        self.peer_ids.insert(peer, id);
+        id
+    }
+}
+
+impl From<&IpAddrPrefix> for PrefixKey {
+    fn from(prefix: &IpAddrPrefix) -> Self {
+        PrefixKey {
+            addr: prefix.addr(),
+            length: prefix.length(),
+        }
+    }
+}
+
+/// Distinct origins currently announced, ascending
+fn active_origins(entries: &[Entry]) -> Vec<u32> {
+    let mut origins: Vec<u32> = entries
+        .iter()
+        .filter(|entry| entry.active)
+        .map(|entry| entry.origin)
+        .collect();
+    origins.sort_unstable();
+    origins.dedup();
+    origins
+}
+
+fn seconds(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .min(u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::rib::RouteEventSource;
+    use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+    const FIRST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SECOND: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn prefix() -> IpAddrPrefix {
+        "203.0.113.0/24".parse().unwrap()
+    }
+
+    fn announce(origin: u32) -> UpdateMessage {
+        UpdateMessageBuilder::new()
+            .announce(prefix())
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, origin].into(),
+                }]
+                .into(),
+            })
+            .next_hop("198.51.100.1".parse().unwrap())
+            .build()
+    }
+
+    fn withdraw() -> UpdateMessage {
+        UpdateMessageBuilder::new().withdraw(prefix()).build()
+    }
+
+    fn at(minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + minutes * 60)
+    }
+
+    /// Replays UPDATEs from several peers through per-peer RIBs
+    struct Replay {
+        tracker: OriginTracker,
+        sources: HashMap<IpAddr, RouteEventSource>,
+    }
+
+    impl Replay {
+        fn new() -> Self {
+            Replay {
+                tracker: OriginTracker::new(Duration::from_secs(30 * 60)),
+                sources: HashMap::new(),
+            }
+        }
+
+        fn update(
+            &mut self,
+            peer: IpAddr,
+            update: UpdateMessage,
+            minutes: u64,
+        ) -> Vec<OriginEvent> {
+            let source = self
+                .sources
+                .entry(peer)
+                .or_insert_with(|| RouteEventSource::with_rib(peer));
+            source
+                .events(&update, at(minutes))
+                .iter()
+                .flat_map(|event| self.tracker.observe(event))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_moas_created_and_resolved() {
+        let mut replay = Replay::new();
+        assert!(replay.update(FIRST, announce(64500), 0).is_empty());
+        assert!(replay.update(SECOND, announce(64500), 1).is_empty());
+
+        // The second peer starts seeing another origin
+        assert_eq!(
+            replay.update(SECOND, announce(64666), 2),
+            vec![
+                OriginEvent::OriginChanged {
+                    prefix: prefix(),
+                    old: 64500,
+                    new: 64666,
+                    peer: SECOND,
+                    at: at(2)
+                },
+                OriginEvent::Moas {
+                    prefix: prefix(),
+                    origins: vec![64500, 64666],
+                    at: at(2)
+                }
+            ]
+        );
+        // Nothing new while the conflict lasts
+        assert!(replay.update(SECOND, announce(64666), 3).is_empty());
+
+        assert_eq!(
+            replay.update(SECOND, withdraw(), 4),
+            vec![OriginEvent::MoasResolved {
+                prefix: prefix(),
+                origin: Some(64500),
+                at: at(4)
+            }]
+        );
+
+        let records = replay.tracker.origins(&prefix());
+        assert_eq!(records.len(), 3);
+        assert!(
+            records
+                .iter()
+                .all(|record| record.active == (record.peer == FIRST))
+        );
+
+        // Within retention a return to the original origin is still a change
+        let events = replay.update(SECOND, announce(64500), 10);
+        assert!(matches!(
+            events[..],
+            [OriginEvent::OriginChanged {
+                old: 64666,
+                new: 64500,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut replay = Replay::new();
+        replay.update(FIRST, announce(64500), 0);
+        replay.update(FIRST, withdraw(), 1);
+
+        replay.tracker.expire(at(20));
+        assert_eq!(replay.tracker.len(), 1);
+        replay.tracker.expire(at(40));
+        assert!(replay.tracker.is_empty());
+
+        // The old origin has been forgotten
+        assert!(replay.update(FIRST, announce(64501), 41).is_empty());
+        let records = replay.tracker.origins(&prefix());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].first_seen, at(41));
+    }
+}
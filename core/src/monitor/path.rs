@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use crate::asn::Asn;
+use crate::attribute::{AsPathSegmentType, Attributes};
+use crate::rib::{RouteEvent, RouteEventKind};
+use crate::update_message::IpAddrPrefix;
+
+/// Thresholds of the [`PathAnomalyDetector`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathAnomalyConfig {
+    /// Hops a re-announced path may grow by before it's flagged
+    pub max_growth: u32,
+    /// Flag paths holding private, reserved or documentation ASNs or AS_TRANS, which don't
+    /// belong in the global routing table. Confederation segments aren't checked.
+    pub special_asns: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathAnomalyKind {
+    /// The path doesn't start with the ASN of the peer that sent it
+    UnexpectedFirstHop { expected: u32, actual: Option<u32> },
+    /// The path got longer than the previous announcement of the route by more than allowed
+    PathGrowth { old_len: u32, new_len: u32 },
+    /// The first ASN of the path that [`Asn::is_special`] holds for
+    SpecialAsn { asn: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathAnomaly {
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    pub kind: PathAnomalyKind,
+    pub at: SystemTime,
+}
+
+/// Flags suspicious AS paths in route events.
+///
+/// The first hop is only checked for peers registered with [`PathAnomalyDetector::add_peer`],
+/// which should be the eBGP peers expected to put their own ASN first; route servers don't.
+/// Growth is measured against the attributes the RIB held before, so it needs events from a
+/// [`RouteEventSource`] with a RIB.
+///
+/// [`RouteEventSource`]: crate::rib::RouteEventSource
+#[derive(Debug, Clone, Default)]
+pub struct PathAnomalyDetector {
+    config: PathAnomalyConfig,
+    peers: HashMap<IpAddr, u32>,
+}
+
+impl Default for PathAnomalyConfig {
+    fn default() -> Self {
+        PathAnomalyConfig {
+            max_growth: 3,
+            special_asns: false,
+        }
+    }
+}
+
+impl PathAnomalyDetector {
+    pub fn new(config: PathAnomalyConfig) -> Self {
+        PathAnomalyDetector {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Expects the routes of `peer` to start with `asn`
+    pub fn add_peer(&mut self, peer: IpAddr, asn: u32) {
+        self.peers.insert(peer, asn);
+    }
+
+    pub fn remove_peer(&mut self, peer: IpAddr) {
+        self.peers.remove(&peer);
+    }
+
+    pub fn observe(&self, event: &RouteEvent) -> Vec<PathAnomaly> {
+        let (old, new) = match &event.kind {
+            RouteEventKind::Announced { attrs } => (None, attrs),
+            RouteEventKind::Reannounced {
+                old_attrs,
+                new_attrs,
+            } => (Some(old_attrs), new_attrs),
+            RouteEventKind::Withdrawn => return vec![],
+        };
+        let Some(path) = Attributes::new(new).as_path() else {
+            return vec![];
+        };
+
+        let mut kinds = vec![];
+        if let Some(&expected) = self.peers.get(&event.peer) {
+            let actual = path.first_hop();
+            if actual != Some(expected) {
+                kinds.push(PathAnomalyKind::UnexpectedFirstHop { expected, actual });
+            }
+        }
+        if let Some(old_path) = old.and_then(|old| Attributes::new(old).as_path()) {
+            let (old_len, new_len) = (old_path.path_len(), path.path_len());
+            if new_len > old_len + self.config.max_growth {
+                kinds.push(PathAnomalyKind::PathGrowth { old_len, new_len });
+            }
+        }
+        if self.config.special_asns {
+            let special = path
+                .segments
+                .iter()
+                .filter(|segment| {
+                    matches!(
+                        segment.segment_type,
+                        AsPathSegmentType::AsSequence | AsPathSegmentType::AsSet
+                    )
+                })
+                .flat_map(|segment| segment.asns.iter().copied())
+                .find(|&asn| Asn(asn).is_special());
+            if let Some(asn) = special {
+                kinds.push(PathAnomalyKind::SpecialAsn { asn });
+            }
+        }
+
+        kinds
+            .into_iter()
+            .map(|kind| PathAnomaly {
+                peer: event.peer,
+                prefix: event.prefix.clone(),
+                kind,
+                at: event.timestamp,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::rib::RouteEventSource;
+    use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn announce(segments: &[(AsPathSegmentType, &[u32])]) -> UpdateMessage {
+        let as_path = AsPath {
+            segments: segments
+                .iter()
+                .map(|(segment_type, asns)| AsPathSegment {
+                    segment_type: *segment_type,
+                    asns: asns.to_vec().into(),
+                })
+                .collect(),
+        };
+        UpdateMessageBuilder::new()
+            .announce("198.51.100.0/24".parse().unwrap())
+            .as_path(as_path)
+            .next_hop(PEER)
+            .build()
+    }
+
+    fn kinds(
+        detector: &PathAnomalyDetector,
+        source: &mut RouteEventSource,
+        update: &UpdateMessage,
+    ) -> Vec<PathAnomalyKind> {
+        source
+            .events(update, SystemTime::UNIX_EPOCH)
+            .iter()
+            .flat_map(|event| detector.observe(event))
+            .map(|anomaly| anomaly.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_unexpected_first_hop() {
+        use AsPathSegmentType::*;
+
+        let mut detector = PathAnomalyDetector::default();
+        detector.add_peer(PEER, 65001);
+        let mut source = RouteEventSource::stateless(PEER);
+
+        assert!(
+            kinds(
+                &detector,
+                &mut source,
+                &announce(&[(AsSequence, &[65001, 65002])])
+            )
+            .is_empty()
+        );
+        assert_eq!(
+            kinds(
+                &detector,
+                &mut source,
+                &announce(&[(AsSequence, &[65009, 65002])])
+            ),
+            vec![PathAnomalyKind::UnexpectedFirstHop {
+                expected: 65001,
+                actual: Some(65009)
+            }]
+        );
+        assert_eq!(
+            kinds(
+                &detector,
+                &mut source,
+                &announce(&[(AsSet, &[65001, 65002])])
+            ),
+            vec![PathAnomalyKind::UnexpectedFirstHop {
+                expected: 65001,
+                actual: None
+            }]
+        );
+
+        // Unregistered peers, such as iBGP peers sending empty paths, aren't checked
+        detector.remove_peer(PEER);
+        assert!(kinds(&detector, &mut source, &announce(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_path_growth() {
+        use AsPathSegmentType::*;
+
+        let detector = PathAnomalyDetector::new(PathAnomalyConfig {
+            max_growth: 2,
+            ..Default::default()
+        });
+        let mut source = RouteEventSource::with_rib(PEER);
+
+        let short = announce(&[(AsSequence, &[65001, 65002])]);
+        assert!(kinds(&detector, &mut source, &short).is_empty());
+        // Within the allowed growth, an AS_SET counting once
+        let longer = announce(&[(AsSequence, &[65001, 65003, 65002]), (AsSet, &[1, 2, 3])]);
+        assert!(kinds(&detector, &mut source, &longer).is_empty());
+
+        let prepended = announce(&[(
+            AsSequence,
+            &[65001, 65003, 65002, 65002, 65002, 65002, 65002],
+        )]);
+        assert_eq!(
+            kinds(&detector, &mut source, &prepended),
+            vec![PathAnomalyKind::PathGrowth {
+                old_len: 4,
+                new_len: 7
+            }]
+        );
+        // Shrinking is fine
+        assert!(kinds(&detector, &mut source, &short).is_empty());
+        // From an empty iBGP path
+        assert!(kinds(&detector, &mut source, &announce(&[])).is_empty());
+        assert_eq!(
+            kinds(&detector, &mut source, &prepended),
+            vec![PathAnomalyKind::PathGrowth {
+                old_len: 0,
+                new_len: 7
+            }]
+        );
+    }
+
+    #[test]
+    fn test_special_asns() {
+        use AsPathSegmentType::*;
+
+        let detector = PathAnomalyDetector::new(PathAnomalyConfig {
+            special_asns: true,
+            ..Default::default()
+        });
+        let mut source = RouteEventSource::stateless(PEER);
+
+        assert!(
+            kinds(
+                &detector,
+                &mut source,
+                &announce(&[(AsSequence, &[3356, 1299])])
+            )
+            .is_empty()
+        );
+        // Private ASNs are expected within a confederation
+        let confed = announce(&[(AsConfedSequence, &[65001]), (AsSequence, &[3356])]);
+        assert!(kinds(&detector, &mut source, &confed).is_empty());
+        for (path, asn) in [
+            (announce(&[(AsSequence, &[3356, 64512, 65535])]), 64512),
+            (
+                announce(&[(AsSequence, &[3356]), (AsSet, &[1299, 64496])]),
+                64496,
+            ),
+            (announce(&[(AsSequence, &[3356, 23456])]), 23456),
+            (announce(&[(AsSequence, &[0])]), 0),
+        ] {
+            assert_eq!(
+                kinds(&detector, &mut source, &path),
+                vec![PathAnomalyKind::SpecialAsn { asn }]
+            );
+        }
+
+        // Off by default
+        let detector = PathAnomalyDetector::default();
+        let private = announce(&[(AsSequence, &[3356, 64512])]);
+        assert!(kinds(&detector, &mut source, &private).is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::address_family::{Afi, Safi};
+use crate::rib::{RibChange, RibIn};
+use crate::update_message::UpdateMessage;
+
+use super::origin_as;
+use super::window::WindowedCounter;
+
+/// Width of the buckets behind the windowed counters
+const BUCKET: Duration = Duration::from_secs(10);
+/// Buckets kept, enough for the longest window
+const BUCKETS: usize = 360;
+
+const WINDOWS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(60 * 60),
+];
+
+/// Monitoring statistics of a single peer's session, fed with every UPDATE it sends.
+///
+/// Keeps the peer's routes to count prefixes per family, so memory grows with the size of the
+/// table the peer advertises.
+#[derive(Debug, Clone)]
+pub struct PeerMonitor {
+    peer: IpAddr,
+    established: Instant,
+    rib: RibIn,
+    announcements: WindowedCounter,
+    withdrawals: WindowedCounter,
+    origins: HashSet<u32>,
+    treat_as_withdraw: u64,
+    end_of_rib: BTreeMap<(Afi, Safi), Duration>,
+}
+
+/// Point in time view of a [`PeerMonitor`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PeerMonitorSnapshot {
+    pub peer: IpAddr,
+    pub uptime: Duration,
+    pub prefixes: Vec<FamilyCount>,
+    /// Routes announced, including re-announcements
+    pub announcements: WindowCounts,
+    pub withdrawals: WindowCounts,
+    /// Distinct origin ASNs of the routes announced during the session
+    pub origin_asns: usize,
+    /// UPDATEs handled as withdrawals under RFC 7606 revised error handling
+    pub treat_as_withdraw: u64,
+    /// Families the peer finished its initial advertisement for, with the time it took
+    pub end_of_rib: Vec<Convergence>,
+}
+
+/// Routes currently received for a family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FamilyCount {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub count: usize,
+}
+
+/// Events over the recent past
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WindowCounts {
+    pub last_minute: u64,
+    pub last_5_minutes: u64,
+    pub last_hour: u64,
+    /// Since the session was established
+    pub total: u64,
+}
+
+/// Time from establishing the session to the peer's End-of-RIB marker for a family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Convergence {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub after: Duration,
+}
+
+impl PeerMonitor {
+    /// Starts monitoring a session established at `established`
+    pub fn new(peer: IpAddr, established: Instant) -> Self {
+        PeerMonitor {
+            peer,
+            established,
+            rib: RibIn::new(),
+            announcements: WindowedCounter::new(established, BUCKET, BUCKETS),
+            withdrawals: WindowedCounter::new(established, BUCKET, BUCKETS),
+            origins: HashSet::new(),
+            treat_as_withdraw: 0,
+            end_of_rib: BTreeMap::new(),
+        }
+    }
+
+    pub fn on_update(&mut self, update: &UpdateMessage, now: Instant) {
+        if let Some(family) = update.end_of_rib_family() {
+            self.end_of_rib
+                .entry(family)
+                .or_insert_with(|| now.saturating_duration_since(self.established));
+            return;
+        }
+
+        let (mut announced, mut withdrawn) = (0, 0);
+        for change in self.rib.apply(update) {
+            match change {
+                RibChange::Announced { attributes, .. }
+                | RibChange::Replaced {
+                    new: attributes, ..
+                }
+                | RibChange::Unchanged { attributes, .. } => {
+                    announced += 1;
+                    self.origins.extend(origin_as(&attributes));
+                }
+                RibChange::Withdrawn { .. } => withdrawn += 1,
+            }
+        }
+        if announced > 0 {
+            self.announcements.add(now, announced);
+        }
+        if withdrawn > 0 {
+            self.withdrawals.add(now, withdrawn);
+        }
+    }
+
+    /// Counts an UPDATE whose routes were withdrawn because of malformed attributes
+    pub fn on_treat_as_withdraw(&mut self) {
+        self.treat_as_withdraw += 1;
+    }
+
+    pub fn prefix_count(&self, afi: Afi, safi: Safi) -> usize {
+        self.rib.prefix_count(afi, safi)
+    }
+
+    pub fn snapshot(&self, now: Instant) -> PeerMonitorSnapshot {
+        let mut prefixes: Vec<FamilyCount> = self
+            .rib
+            .iter()
+            .map(|(key, _)| key.family())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|(afi, safi)| FamilyCount {
+                afi,
+                safi,
+                count: self.rib.prefix_count(afi, safi),
+            })
+            .collect();
+        prefixes.sort_by_key(|family| (u16::from(family.afi), u8::from(family.safi)));
+
+        PeerMonitorSnapshot {
+            peer: self.peer,
+            uptime: now.saturating_duration_since(self.established),
+            prefixes,
+            announcements: window_counts(&self.announcements, now),
+            withdrawals: window_counts(&self.withdrawals, now),
+            origin_asns: self.origins.len(),
+            treat_as_withdraw: self.treat_as_withdraw,
+            end_of_rib: self
+                .end_of_rib
+                .iter()
+                .map(|(&(afi, safi), &after)| Convergence { afi, safi, after })
+                .collect(),
+        }
+    }
+}
+
+fn window_counts(counter: &WindowedCounter, now: Instant) -> WindowCounts {
+    let [last_minute, last_5_minutes, last_hour] = WINDOWS.map(|window| counter.sum(now, window));
+    WindowCounts {
+        last_minute,
+        last_5_minutes,
+        last_hour,
+        total: counter.total(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    fn announce(prefixes: &[&str], origin: u32) -> UpdateMessage {
+        let as_path = AsPath {
+            segments: [AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns: [65002, origin].into(),
+            }]
+            .into(),
+        };
+        let builder = UpdateMessageBuilder::new()
+            .as_path(as_path)
+            .next_hop("192.0.2.1".parse().unwrap());
+        prefixes
+            .iter()
+            .fold(builder, |builder, prefix| {
+                builder.announce(prefix.parse().unwrap())
+            })
+            .build()
+    }
+
+    fn withdraw(prefixes: &[&str]) -> UpdateMessage {
+        UpdateMessage {
+            withdrawn_routes: prefixes
+                .iter()
+                .map(|prefix| prefix.parse::<IpAddrPrefix>().unwrap())
+                .collect(),
+            path_attributes: vec![],
+            nlri: vec![],
+        }
+    }
+
+    #[test]
+    fn test_peer_statistics() {
+        let start = Instant::now();
+        let mut monitor = PeerMonitor::new(IpAddr::V4(Ipv4Addr::LOCALHOST), start);
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        monitor.on_update(&announce(&["10.0.0.0/24", "10.0.1.0/24"], 64500), at(0));
+        monitor.on_update(&announce(&["10.0.2.0/24"], 64501), at(5));
+        monitor.on_update(&UpdateMessage::end_of_rib(Afi::Ipv4, Safi::Unicast), at(12));
+        // Only the first marker of a family counts
+        monitor.on_update(&UpdateMessage::end_of_rib(Afi::Ipv4, Safi::Unicast), at(20));
+        monitor.on_update(&announce(&["10.0.0.0/24"], 64502), at(120));
+        monitor.on_update(&withdraw(&["10.0.1.0/24", "10.9.9.0/24"]), at(400));
+        monitor.on_treat_as_withdraw();
+
+        let snapshot = monitor.snapshot(at(410));
+        assert_eq!(snapshot.uptime, Duration::from_secs(410));
+        assert_eq!(
+            snapshot.prefixes,
+            vec![FamilyCount {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                count: 2
+            }]
+        );
+        assert_eq!(
+            snapshot.announcements,
+            WindowCounts {
+                last_minute: 0,
+                last_5_minutes: 1,
+                last_hour: 4,
+                total: 4
+            }
+        );
+        // Withdrawing a route the peer never announced isn't counted
+        assert_eq!(
+            snapshot.withdrawals,
+            WindowCounts {
+                last_minute: 1,
+                last_5_minutes: 1,
+                last_hour: 1,
+                total: 1
+            }
+        );
+        assert_eq!(snapshot.origin_asns, 3);
+        assert_eq!(snapshot.treat_as_withdraw, 1);
+        assert_eq!(
+            snapshot.end_of_rib,
+            vec![Convergence {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                after: Duration::from_secs(12)
+            }]
+        );
+
+        let later = monitor.snapshot(at(2 * 60 * 60));
+        assert_eq!(later.announcements.last_hour, 0);
+        assert_eq!(later.announcements.total, 4);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let start = Instant::now();
+        let mut monitor = PeerMonitor::new(IpAddr::V4(Ipv4Addr::LOCALHOST), start);
+        monitor.on_update(&announce(&["10.0.0.0/24"], 64500), start);
+        let serialized = toml::to_string(&monitor.snapshot(start)).unwrap();
+        assert!(serialized.contains("peer = \"127.0.0.1\""));
+        assert!(serialized.contains("origin_asns = 1"));
+        assert!(serialized.contains("[[prefixes]]"));
+    }
+}
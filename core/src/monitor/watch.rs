@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+#[cfg(feature = "tokio")]
+use tokio::sync::mpsc;
+
+use crate::rib::{RibIn, RouteEvent, RouteEventKind, RouteEventSource};
+use crate::timestamped::Timestamped;
+use crate::trie::PrefixTrie;
+use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+/// Which route events a watch receives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// Also match prefixes within the watched one
+    pub include_more_specifics: bool,
+    /// Only match routes from these peers, or from every peer when empty
+    pub peers: Vec<IpAddr>,
+    /// Deliver the routes currently held for matching prefixes as announcements first, which
+    /// scans the routes of every peer once
+    pub replay: bool,
+}
+
+/// Identifies a watch to [`Monitor::unwatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u32);
+
+/// The route events of a watch registered with [`Monitor::watch`].
+///
+/// Events are buffered until received, so a subscriber that falls behind costs memory rather
+/// than missing events. Dropping the handle removes the watch.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct Watch {
+    id: WatchId,
+    events: mpsc::UnboundedReceiver<RouteEvent>,
+}
+
+/// Keeps the routes of every peer and hands the events of watched prefixes to their
+/// subscribers.
+///
+/// Watches are indexed by prefix in a [`PrefixTrie`], so dispatching an event only visits the
+/// watches on the path to its prefix, however many there are.
+pub struct Monitor {
+    sources: HashMap<IpAddr, RouteEventSource>,
+    watches: HashMap<u32, Subscription>,
+    next_id: u32,
+    /// Watch ids by prefix
+    trie: PrefixTrie<Vec<u32>>,
+}
+
+struct Subscription {
+    prefix: IpAddrPrefix,
+    options: WatchOptions,
+    sink: Sink,
+}
+
+enum Sink {
+    Callback(Box<dyn FnMut(&RouteEvent) + Send>),
+    #[cfg(feature = "tokio")]
+    Channel(mpsc::UnboundedSender<RouteEvent>),
+}
+
+#[cfg(feature = "tokio")]
+impl Watch {
+    pub fn id(&self) -> WatchId {
+        self.id
+    }
+
+    /// The next matching event, `None` once the watch was removed
+    pub async fn recv(&mut self) -> Option<RouteEvent> {
+        self.events.recv().await
+    }
+
+    /// The next event if one is buffered
+    pub fn try_recv(&mut self) -> Option<RouteEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Sink {
+    /// Whether the subscriber is still there
+    fn deliver(&mut self, event: &RouteEvent) -> bool {
+        match self {
+            Sink::Callback(callback) => {
+                callback(event);
+                true
+            }
+            #[cfg(feature = "tokio")]
+            Sink::Channel(sender) => sender.send(event.clone()).is_ok(),
+        }
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            sources: HashMap::new(),
+            watches: HashMap::new(),
+            next_id: 0,
+            trie: PrefixTrie::new(),
+        }
+    }
+
+    /// Subscribes to the events of `prefix`, and of the prefixes within it with
+    /// [`WatchOptions::include_more_specifics`]
+    #[cfg(feature = "tokio")]
+    pub fn watch(&mut self, prefix: IpAddrPrefix, options: WatchOptions) -> Watch {
+        let (sender, events) = mpsc::unbounded_channel();
+        let id = self.register(prefix, options, Sink::Channel(sender));
+        Watch { id, events }
+    }
+
+    /// Like [`Monitor::watch`], calling `callback` with every matching event
+    pub fn watch_with(
+        &mut self,
+        prefix: IpAddrPrefix,
+        options: WatchOptions,
+        callback: impl FnMut(&RouteEvent) + Send + 'static,
+    ) -> WatchId {
+        self.register(prefix, options, Sink::Callback(Box::new(callback)))
+    }
+
+    /// Removes a watch, returning whether it existed
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let Some(subscription) = self.watches.remove(&id.0) else {
+            return false;
+        };
+        if let Some(ids) = self.trie.get_mut(&subscription.prefix) {
+            ids.retain(|&i| i != id.0);
+            if ids.is_empty() {
+                self.trie.remove(&subscription.prefix);
+            }
+        }
+        true
+    }
+
+    /// Number of registered watches
+    pub fn watches(&self) -> usize {
+        self.watches.len()
+    }
+
+    /// Applies an UPDATE from `peer` to its routes and notifies the matching watches
+    pub fn apply(&mut self, peer: IpAddr, update: &Timestamped<UpdateMessage>) -> Vec<RouteEvent> {
+        let events = self
+            .sources
+            .entry(peer)
+            .or_insert_with(|| RouteEventSource::with_rib(peer))
+            .received(update);
+        self.dispatch(&events);
+        events
+    }
+
+    /// Forgets the routes of `peer`, as when its session goes down, reporting them as withdrawn
+    pub fn remove_peer(&mut self, peer: IpAddr, timestamp: SystemTime) -> Vec<RouteEvent> {
+        let Some(source) = self.sources.remove(&peer) else {
+            return vec![];
+        };
+        let events: Vec<RouteEvent> = source
+            .rib()
+            .into_iter()
+            .flat_map(RibIn::iter)
+            .map(|(key, _)| RouteEvent {
+                peer,
+                timestamp,
+                prefix: key.prefix.clone(),
+                safi: key.safi,
+                path_id: key.path_id,
+                kind: RouteEventKind::Withdrawn,
+                rpki: None,
+            })
+            .collect();
+        self.dispatch(&events);
+        events
+    }
+
+    /// The routes currently held for `peer`
+    pub fn rib(&self, peer: IpAddr) -> Option<&RibIn> {
+        self.sources.get(&peer)?.rib()
+    }
+
+    fn register(&mut self, prefix: IpAddrPrefix, options: WatchOptions, sink: Sink) -> WatchId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut subscription = Subscription {
+            prefix,
+            options,
+            sink,
+        };
+        if subscription.options.replay {
+            self.replay(&mut subscription);
+        }
+        match self.trie.get_mut(&subscription.prefix) {
+            Some(ids) => ids.push(id),
+            None => {
+                self.trie.insert(&subscription.prefix, vec![id]);
+            }
+        }
+        self.watches.insert(id, subscription);
+        WatchId(id)
+    }
+
+    /// Delivers the current routes matching a new watch, ordered by peer and prefix
+    fn replay(&self, subscription: &mut Subscription) {
+        let now = SystemTime::now();
+        let mut events: Vec<RouteEvent> = self
+            .sources
+            .iter()
+            .filter(|(peer, _)| subscription.accepts_peer(**peer))
+            .flat_map(|(peer, source)| {
+                source
+                    .rib()
+                    .into_iter()
+                    .flat_map(RibIn::iter)
+                    .map(move |(key, attrs)| (peer, key, attrs))
+            })
+            .filter(|(_, key, _)| subscription.accepts_prefix(&key.prefix))
+            .map(|(peer, key, attrs)| RouteEvent {
+                peer: *peer,
+                timestamp: now,
+                prefix: key.prefix.clone(),
+                safi: key.safi,
+                path_id: key.path_id,
+                kind: RouteEventKind::Announced {
+                    attrs: attrs.clone(),
+                },
+                rpki: None,
+            })
+            .collect();
+        events.sort_by(|a, b| (a.peer, &a.prefix, a.path_id).cmp(&(b.peer, &b.prefix, b.path_id)));
+        for event in &events {
+            subscription.sink.deliver(event);
+        }
+    }
+
+    fn dispatch(&mut self, events: &[RouteEvent]) {
+        let mut closed = vec![];
+        let mut matched = vec![];
+        for event in events {
+            matched.clear();
+            for (prefix, ids) in self.trie.covering(&event.prefix) {
+                let exact = prefix.length() == event.prefix.length();
+                matched.extend(ids.iter().map(|&id| (id, exact)));
+            }
+            for &(id, exact) in &matched {
+                let Some(subscription) = self.watches.get_mut(&id) else {
+                    continue;
+                };
+                if !(exact || subscription.options.include_more_specifics)
+                    || !subscription.accepts_peer(event.peer)
+                {
+                    continue;
+                }
+                if !subscription.sink.deliver(event) {
+                    closed.push(WatchId(id));
+                }
+            }
+        }
+        for id in closed {
+            self.unwatch(id);
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+impl Subscription {
+    fn accepts_peer(&self, peer: IpAddr) -> bool {
+        self.options.peers.is_empty() || self.options.peers.contains(&peer)
+    }
+
+    fn accepts_prefix(&self, prefix: &IpAddrPrefix) -> bool {
+        let watched = &self.prefix;
+        if watched.afi() != prefix.afi() {
+            return false;
+        }
+        if !self.options.include_more_specifics {
+            return watched == prefix;
+        }
+        prefix.length() >= watched.length()
+            && (0..watched.length()).all(|i| watched.bit(i) == prefix.bit(i))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+
+    use crate::update_message::UpdateMessageBuilder;
+
+    const FIRST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SECOND: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    fn announce(prefixes: &[&str]) -> Timestamped<UpdateMessage> {
+        let update = prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, p| {
+                builder.announce(prefix(p))
+            })
+            .next_hop("198.51.100.1".parse().unwrap())
+            .build();
+        Timestamped::now(update)
+    }
+
+    fn withdraw(prefixes: &[&str]) -> Timestamped<UpdateMessage> {
+        let update = prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, p| {
+                builder.withdraw(prefix(p))
+            })
+            .build();
+        Timestamped::now(update)
+    }
+
+    /// Records the prefixes a callback watch sees
+    fn recorder(
+        monitor: &mut Monitor,
+        watched: &str,
+        options: WatchOptions,
+    ) -> Arc<Mutex<Vec<String>>> {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = seen.clone();
+        monitor.watch_with(prefix(watched), options, move |event| {
+            sink.lock().unwrap().push(event.prefix.to_string())
+        });
+        seen
+    }
+
+    #[test]
+    fn test_exact_and_more_specifics() {
+        let mut monitor = Monitor::new();
+        let exact = recorder(&mut monitor, "10.0.0.0/8", WatchOptions::default());
+        let covering = recorder(
+            &mut monitor,
+            "10.0.0.0/8",
+            WatchOptions {
+                include_more_specifics: true,
+                ..WatchOptions::default()
+            },
+        );
+        let only_second = recorder(
+            &mut monitor,
+            "10.1.0.0/16",
+            WatchOptions {
+                peers: vec![SECOND],
+                ..WatchOptions::default()
+            },
+        );
+
+        monitor.apply(
+            FIRST,
+            &announce(&["10.0.0.0/8", "10.1.0.0/16", "11.0.0.0/8"]),
+        );
+        monitor.apply(SECOND, &announce(&["10.1.0.0/16"]));
+        monitor.apply(FIRST, &withdraw(&["10.1.0.0/16"]));
+
+        assert_eq!(*exact.lock().unwrap(), vec!["10.0.0.0/8"]);
+        assert_eq!(
+            *covering.lock().unwrap(),
+            vec!["10.0.0.0/8", "10.1.0.0/16", "10.1.0.0/16", "10.1.0.0/16"]
+        );
+        assert_eq!(*only_second.lock().unwrap(), vec!["10.1.0.0/16"]);
+    }
+
+    #[test]
+    fn test_unwatch() {
+        let mut monitor = Monitor::new();
+        let seen = Arc::new(Mutex::new(0));
+        let counter = seen.clone();
+        let id = monitor.watch_with(
+            prefix("2001:db8::/32"),
+            WatchOptions::default(),
+            move |_| *counter.lock().unwrap() += 1,
+        );
+        monitor.apply(FIRST, &announce(&["2001:db8::/32"]));
+        assert!(monitor.unwatch(id));
+        assert!(!monitor.unwatch(id));
+        monitor.apply(FIRST, &withdraw(&["2001:db8::/32"]));
+        assert_eq!(*seen.lock().unwrap(), 1);
+        assert_eq!(monitor.watches(), 0);
+    }
+
+    #[test]
+    fn test_replay_current_routes() {
+        let mut monitor = Monitor::new();
+        monitor.apply(
+            FIRST,
+            &announce(&["10.2.0.0/16", "10.1.0.0/16", "12.0.0.0/8"]),
+        );
+        monitor.apply(SECOND, &announce(&["10.3.0.0/16"]));
+        monitor.apply(FIRST, &withdraw(&["10.2.0.0/16"]));
+
+        let options = WatchOptions {
+            include_more_specifics: true,
+            replay: true,
+            ..WatchOptions::default()
+        };
+        let seen = recorder(&mut monitor, "10.0.0.0/8", options);
+        assert_eq!(*seen.lock().unwrap(), vec!["10.1.0.0/16", "10.3.0.0/16"]);
+
+        // Followed by live events, and withdrawals when a peer goes away
+        monitor.remove_peer(SECOND, SystemTime::now());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["10.1.0.0/16", "10.3.0.0/16", "10.3.0.0/16"]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_overlapping_channel_watches() {
+        let mut monitor = Monitor::new();
+        let options = WatchOptions {
+            include_more_specifics: true,
+            ..WatchOptions::default()
+        };
+        let mut wide = monitor.watch(prefix("10.0.0.0/8"), options.clone());
+        let mut narrow = monitor.watch(prefix("10.1.0.0/16"), options);
+        let dropped = monitor.watch(prefix("10.1.0.0/16"), WatchOptions::default());
+        drop(dropped);
+
+        monitor.apply(FIRST, &announce(&["10.1.1.0/24"]));
+        let from_wide = wide.recv().await.unwrap();
+        let from_narrow = narrow.recv().await.unwrap();
+        assert_eq!(from_wide, from_narrow);
+        assert_eq!(from_wide.prefix, prefix("10.1.1.0/24"));
+        assert!(matches!(from_wide.kind, RouteEventKind::Announced { .. }));
+
+        monitor.apply(FIRST, &announce(&["10.2.0.0/16", "10.1.0.0/16"]));
+        assert_eq!(wide.try_recv().unwrap().prefix, prefix("10.2.0.0/16"));
+        assert_eq!(wide.try_recv().unwrap().prefix, prefix("10.1.0.0/16"));
+        assert_eq!(narrow.try_recv().unwrap().prefix, prefix("10.1.0.0/16"));
+        assert!(narrow.try_recv().is_none());
+        // The dropped watch was removed once it had an event to deliver
+        assert_eq!(monitor.watches(), 2);
+    }
+}
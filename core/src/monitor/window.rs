@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts events over sliding windows of recent time.
+///
+/// Events land in fixed width buckets kept in a ring, so memory doesn't grow with the event
+/// rate and window sums are accurate to one bucket width. Windows longer than the ring are
+/// truncated to it.
+#[derive(Debug, Clone)]
+pub struct WindowedCounter {
+    start: Instant,
+    bucket: Duration,
+    buckets: Vec<u64>,
+    /// Number of the newest bucket since `start`, `None` before the first event
+    head: Option<u64>,
+    total: u64,
+}
+
+impl WindowedCounter {
+    pub fn new(start: Instant, bucket: Duration, buckets: usize) -> Self {
+        assert!(!bucket.is_zero() && buckets > 0);
+        WindowedCounter {
+            start,
+            bucket,
+            buckets: vec![0; buckets],
+            head: None,
+            total: 0,
+        }
+    }
+
+    /// Time covered by the ring
+    pub fn span(&self) -> Duration {
+        self.bucket * self.buckets.len() as u32
+    }
+
+    pub fn add(&mut self, now: Instant, count: u64) {
+        let current = self.bucket_number(now);
+        let len = self.buckets.len() as u64;
+        match self.head {
+            // Time never runs backwards for the ring, late events count toward the newest bucket
+            Some(head) if current <= head => {
+                self.buckets[(head % len) as usize] += count;
+                self.total += count;
+                return;
+            }
+            // Reset every bucket skipped since the last event, at most one full turn
+            Some(head) => {
+                for number in (head + 1).max(current.saturating_sub(len - 1))..=current {
+                    self.buckets[(number % len) as usize] = 0;
+                }
+            }
+            None => self.buckets.fill(0),
+        }
+        self.head = Some(current);
+        self.buckets[(current % len) as usize] += count;
+        self.total += count;
+    }
+
+    /// Events in the last `window` up to `now`
+    pub fn sum(&self, now: Instant, window: Duration) -> u64 {
+        let Some(head) = self.head else {
+            return 0;
+        };
+        let current = self.bucket_number(now).max(head);
+        let len = self.buckets.len() as u64;
+        let wanted = (window.as_nanos().div_ceil(self.bucket.as_nanos()) as u64).min(len);
+        let oldest = (current + 1).saturating_sub(wanted);
+        // Buckets older than one turn before the head have been overwritten
+        let oldest = oldest.max((head + 1).saturating_sub(len));
+        (oldest..=head)
+            .map(|number| self.buckets[(number % len) as usize])
+            .sum()
+    }
+
+    /// Events since the counter was created
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn bucket_number(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_nanos() / self.bucket.as_nanos()) as u64
+    }
+}
+
+/// Counts events per second over the last few minutes, cheap enough to record every message
+/// from the session task and readable at any time from another.
+///
+/// Each second of the ring is a single atomic holding both the second it counts and the
+/// count, so recording is one compare-and-swap and buckets left over from an earlier turn of
+/// the ring are told apart by their second instead of being cleared. Sums are accurate to one
+/// second; events older than the ring when they are recorded are dropped.
+#[derive(Debug)]
+pub struct RateWindow {
+    start: Instant,
+    buckets: Box<[AtomicU64]>,
+}
+
+/// Bits of a bucket holding its count, the others hold the second plus one
+const COUNT_BITS: u32 = 32;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+
+impl RateWindow {
+    /// Keeps the last `seconds` seconds, counted from `start`
+    pub fn new(start: Instant, seconds: usize) -> Self {
+        assert!(seconds > 0);
+        RateWindow {
+            start,
+            buckets: (0..seconds).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Time covered by the ring
+    pub fn span(&self) -> Duration {
+        Duration::from_secs(self.buckets.len() as u64)
+    }
+
+    pub fn record(&self, now: Instant, count: u64) {
+        let stamp = self.stamp(now);
+        let bucket = &self.buckets[(stamp % self.buckets.len() as u64) as usize];
+        let _ = bucket.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            match (current >> COUNT_BITS).cmp(&stamp) {
+                std::cmp::Ordering::Equal => {
+                    let total = ((current & COUNT_MASK) + count).min(COUNT_MASK);
+                    Some(stamp << COUNT_BITS | total)
+                }
+                std::cmp::Ordering::Less => Some(stamp << COUNT_BITS | count.min(COUNT_MASK)),
+                // The bucket already counts a later second
+                std::cmp::Ordering::Greater => None,
+            }
+        });
+    }
+
+    /// Events in the last `window` up to `now`, the current second included
+    pub fn count(&self, now: Instant, window: Duration) -> u64 {
+        let current = self.stamp(now);
+        let seconds = window.as_secs().clamp(1, self.buckets.len() as u64);
+        let oldest = (current + 1).saturating_sub(seconds).max(1);
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .filter(|bucket| (oldest..=current).contains(&(bucket >> COUNT_BITS)))
+            .map(|bucket| bucket & COUNT_MASK)
+            .sum()
+    }
+
+    /// Events per second averaged over the last `window`
+    pub fn rate(&self, now: Instant, window: Duration) -> f64 {
+        let seconds = window.as_secs().clamp(1, self.buckets.len() as u64);
+        self.count(now, window) as f64 / seconds as f64
+    }
+
+    /// The second `now` falls in, plus one so that zero marks an unused bucket
+    fn stamp(&self, now: Instant) -> u64 {
+        let second = now.saturating_duration_since(self.start).as_secs();
+        (second + 1).min(u32::MAX as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_sums_within_windows() {
+        let start = Instant::now();
+        let mut counter = WindowedCounter::new(start, Duration::from_secs(10), 6);
+        counter.add(start, 1);
+        counter.add(start + Duration::from_secs(9), 2);
+        counter.add(start + Duration::from_secs(25), 4);
+
+        let now = start + Duration::from_secs(29);
+        assert_eq!(counter.sum(now, Duration::from_secs(10)), 4);
+        assert_eq!(counter.sum(now, Duration::from_secs(20)), 4);
+        assert_eq!(counter.sum(now, Duration::from_secs(30)), 7);
+        assert_eq!(counter.sum(now, MINUTE), 7);
+        // Longer windows are capped to the ring
+        assert_eq!(counter.sum(now, 10 * MINUTE), 7);
+
+        // Reading later without new events lets old buckets fall out of the window
+        assert_eq!(counter.sum(start + Duration::from_secs(55), MINUTE), 7);
+        assert_eq!(counter.sum(start + Duration::from_secs(65), MINUTE), 4);
+        assert_eq!(counter.sum(start + Duration::from_secs(95), MINUTE), 0);
+    }
+
+    #[test]
+    fn test_bucket_rollover() {
+        let start = Instant::now();
+        let mut counter = WindowedCounter::new(start, Duration::from_secs(10), 6);
+        for second in (0..60).step_by(10) {
+            counter.add(start + Duration::from_secs(second), 1);
+        }
+        assert_eq!(counter.sum(start + Duration::from_secs(59), MINUTE), 6);
+
+        // The seventh bucket reuses the first slot
+        counter.add(start + Duration::from_secs(60), 10);
+        assert_eq!(counter.sum(start + Duration::from_secs(60), MINUTE), 15);
+        assert_eq!(
+            counter.sum(start + Duration::from_secs(60), Duration::from_secs(10)),
+            10
+        );
+
+        // A gap longer than the ring clears every slot
+        counter.add(start + 5 * MINUTE, 3);
+        assert_eq!(counter.sum(start + 5 * MINUTE, MINUTE), 3);
+        assert_eq!(counter.total(), 19);
+
+        // A gap of a few buckets only clears the skipped slots
+        counter.add(start + 5 * MINUTE + Duration::from_secs(30), 1);
+        assert_eq!(
+            counter.sum(start + 5 * MINUTE + Duration::from_secs(30), MINUTE),
+            4
+        );
+        assert_eq!(
+            counter.sum(
+                start + 5 * MINUTE + Duration::from_secs(30),
+                Duration::from_secs(20)
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn test_late_events_count_toward_newest_bucket() {
+        let start = Instant::now();
+        let mut counter = WindowedCounter::new(start, Duration::from_secs(10), 6);
+        counter.add(start + Duration::from_secs(30), 1);
+        counter.add(start, 1);
+        assert_eq!(
+            counter.sum(start + Duration::from_secs(30), Duration::from_secs(10)),
+            2
+        );
+    }
+
+    #[test]
+    fn test_rate_window_rollover() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let rate = RateWindow::new(start, 10);
+        rate.record(at(0), 5);
+        rate.record(at(999), 5);
+        rate.record(at(1_000), 2);
+        rate.record(at(9_500), 3);
+
+        assert_eq!(rate.count(at(9_900), Duration::from_secs(10)), 15);
+        assert_eq!(rate.count(at(9_900), Duration::from_secs(1)), 3);
+        assert_eq!(rate.rate(at(9_900), Duration::from_secs(10)), 1.5);
+        // Windows longer than the ring are capped to it
+        assert_eq!(rate.count(at(9_900), Duration::from_secs(60)), 15);
+
+        // The eleventh second reuses the first bucket
+        rate.record(at(10_000), 1);
+        assert_eq!(rate.count(at(10_000), Duration::from_secs(10)), 6);
+        assert_eq!(rate.count(at(11_000), Duration::from_secs(10)), 4);
+        assert_eq!(rate.count(at(11_000), Duration::from_secs(2)), 1);
+    }
+
+    #[test]
+    fn test_rate_window_idle_gaps() {
+        let start = Instant::now();
+        let rate = RateWindow::new(start, 10);
+        rate.record(start, 4);
+        rate.record(start + Duration::from_secs(3), 1);
+
+        // Reading after a gap longer than the window sees nothing, without any event clearing
+        // the buckets
+        assert_eq!(rate.count(start + MINUTE, Duration::from_secs(10)), 0);
+        assert_eq!(rate.rate(start + MINUTE, Duration::from_secs(10)), 0.0);
+
+        // A bucket from several turns ago is reset rather than added to
+        rate.record(start + MINUTE + Duration::from_secs(3), 2);
+        assert_eq!(
+            rate.count(start + MINUTE + Duration::from_secs(5), MINUTE),
+            2
+        );
+
+        // Events older than the ring can't displace newer ones
+        rate.record(start + Duration::from_secs(3), 7);
+        assert_eq!(
+            rate.count(start + MINUTE + Duration::from_secs(5), MINUTE),
+            2
+        );
+        // Reading before the first second doesn't underflow
+        assert_eq!(RateWindow::new(start + MINUTE, 10).count(start, MINUTE), 0);
+    }
+}
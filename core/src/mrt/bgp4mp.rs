@@ -0,0 +1,168 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use bytes::{Buf, Bytes};
+
+use super::MrtError;
+use crate::bgp_message::BgpMessage;
+use crate::journal::Direction;
+
+/// BGP4MP subtypes
+pub(super) const STATE_CHANGE: u16 = 0;
+pub(super) const MESSAGE: u16 = 1;
+pub(super) const MESSAGE_AS4: u16 = 4;
+pub(super) const STATE_CHANGE_AS4: u16 = 5;
+pub(super) const MESSAGE_LOCAL: u16 = 6;
+pub(super) const MESSAGE_AS4_LOCAL: u16 = 7;
+pub(super) const MESSAGE_ADDPATH: u16 = 8;
+pub(super) const MESSAGE_AS4_ADDPATH: u16 = 9;
+pub(super) const MESSAGE_LOCAL_ADDPATH: u16 = 10;
+pub(super) const MESSAGE_AS4_LOCAL_ADDPATH: u16 = 11;
+
+/// A BGP4MP or BGP4MP_ET record: a message exchanged with a peer or a change of the session
+/// state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bgp4mp {
+    pub peer: PeerInfo,
+    /// Whether the subtype has 4 octet ASNs, in the record and in the AS_PATH of UPDATEs
+    pub four_octet_as: bool,
+    /// Whether the subtype is an `_ADDPATH` one (RFC 8050), whose UPDATE prefixes carry path
+    /// identifiers
+    pub add_path: bool,
+    pub body: Bgp4mpBody,
+}
+
+/// The session a BGP4MP record belongs to, as seen from the collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub peer_asn: u32,
+    pub local_asn: u32,
+    pub interface_index: u16,
+    /// Of the same address family as `local_addr`
+    pub peer_addr: IpAddr,
+    pub local_addr: IpAddr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bgp4mpBody {
+    StateChange {
+        old: FsmState,
+        new: FsmState,
+    },
+    /// Received from the peer or, for the `_LOCAL` subtypes, sent by the collector
+    Message {
+        direction: Direction,
+        message: BgpMessage,
+    },
+}
+
+/// Session states of the RFC 4271 FSM, as numbered in STATE_CHANGE records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsmState {
+    Idle,
+    Connect,
+    Active,
+    OpenSent,
+    OpenConfirm,
+    Established,
+    Unknown(u16),
+}
+
+impl From<u16> for FsmState {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => FsmState::Idle,
+            2 => FsmState::Connect,
+            3 => FsmState::Active,
+            4 => FsmState::OpenSent,
+            5 => FsmState::OpenConfirm,
+            6 => FsmState::Established,
+            value => FsmState::Unknown(value),
+        }
+    }
+}
+
+impl From<FsmState> for u16 {
+    fn from(state: FsmState) -> Self {
+        match state {
+            FsmState::Idle => 1,
+            FsmState::Connect => 2,
+            FsmState::Active => 3,
+            FsmState::OpenSent => 4,
+            FsmState::OpenConfirm => 5,
+            FsmState::Established => 6,
+            FsmState::Unknown(value) => value,
+        }
+    }
+}
+
+impl Bgp4mp {
+    /// Decodes the body of a record of `subtype`, after the microseconds of BGP4MP_ET
+    pub(super) fn decode(subtype: u16, body: &mut Bytes, offset: u64) -> Result<Self, MrtError> {
+        let malformed = |reason| MrtError::Malformed { offset, reason };
+        let (four_octet_as, add_path) = match subtype {
+            STATE_CHANGE | MESSAGE | MESSAGE_LOCAL => (false, false),
+            STATE_CHANGE_AS4 | MESSAGE_AS4 | MESSAGE_AS4_LOCAL => (true, false),
+            MESSAGE_ADDPATH | MESSAGE_LOCAL_ADDPATH => (false, true),
+            MESSAGE_AS4_ADDPATH | MESSAGE_AS4_LOCAL_ADDPATH => (true, true),
+            _ => return Err(malformed("unsupported BGP4MP subtype")),
+        };
+
+        let asn_len = if four_octet_as { 4 } else { 2 };
+        if body.len() < 2 * asn_len + 2 + 2 {
+            return Err(malformed("truncated BGP4MP header"));
+        }
+        let mut asn = || match four_octet_as {
+            true => body.get_u32(),
+            false => body.get_u16().into(),
+        };
+        let (peer_asn, local_asn) = (asn(), asn());
+        let interface_index = body.get_u16();
+        let (peer_addr, local_addr) = match body.get_u16() {
+            1 if body.len() >= 2 * 4 => (
+                IpAddr::V4(Ipv4Addr::from(body.get_u32())),
+                IpAddr::V4(Ipv4Addr::from(body.get_u32())),
+            ),
+            2 if body.len() >= 2 * 16 => (
+                IpAddr::V6(Ipv6Addr::from(body.get_u128())),
+                IpAddr::V6(Ipv6Addr::from(body.get_u128())),
+            ),
+            1 | 2 => return Err(malformed("truncated BGP4MP header")),
+            _ => return Err(malformed("unsupported address family")),
+        };
+
+        let body = match subtype {
+            STATE_CHANGE | STATE_CHANGE_AS4 => {
+                if body.len() < 4 {
+                    return Err(malformed("truncated state change"));
+                }
+                Bgp4mpBody::StateChange {
+                    old: body.get_u16().into(),
+                    new: body.get_u16().into(),
+                }
+            }
+            _ => Bgp4mpBody::Message {
+                direction: match subtype {
+                    MESSAGE_LOCAL
+                    | MESSAGE_AS4_LOCAL
+                    | MESSAGE_LOCAL_ADDPATH
+                    | MESSAGE_AS4_LOCAL_ADDPATH => Direction::Sent,
+                    _ => Direction::Received,
+                },
+                message: BgpMessage::decode_framed(body, four_octet_as, add_path)
+                    .map_err(|err| MrtError::Message { offset, err })?,
+            },
+        };
+        Ok(Bgp4mp {
+            peer: PeerInfo {
+                peer_asn,
+                local_asn,
+                interface_index,
+                peer_addr,
+                local_addr,
+            },
+            four_octet_as,
+            add_path,
+            body,
+        })
+    }
+}
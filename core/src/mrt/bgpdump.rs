@@ -0,0 +1,528 @@
+//! Records as lines in the format of `bgpdump -m`, one per route:
+//!
+//! ```text
+//! TABLE_DUMP2|1700000000|A|192.0.2.1|64500|203.0.113.0/24|64500 64511|IGP|192.0.2.1|0|0|64500:1|NAG||
+//! BGP4MP|1700000000|A|192.0.2.1|64500|198.51.100.0/24|64500 {64510,64511}|IGP|192.0.2.1|100|10||AG|64511 192.0.2.9|
+//! BGP4MP|1700000000|W|192.0.2.1|64500|198.51.100.0/24
+//! BGP4MP|1700000000|STATE|192.0.2.1|64500|5|6
+//! ```
+//!
+//! Times are seconds since the Unix epoch. Routes carry the AS path, origin, next hop, local
+//! preference and MED (0 when missing), communities, whether ATOMIC_AGGREGATE is set and the
+//! aggregator; session states are numbered as in RFC 6396. ADD-PATH path identifiers, which
+//! bgpdump doesn't print, are only in [`DumpLine::to_json`], and only when present.
+//!
+//! [`DumpLine::enriched`] adds the names of the peer and origin ASes and the routed prefix
+//! covering the line's with its origins, as told by an [`Enricher`], as fields after the
+//! others:
+//!
+//! ```text
+//! BGP4MP|1700000000|W|192.0.2.1|64500|198.51.100.0/24|EXAMPLE-NET|||198.51.100.0/24|64500 64501|
+//! ```
+
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::attribute::{AsPath, OriginType, PathAttribute};
+use crate::bgp_message::BgpMessage;
+use crate::enrich::{Enricher, PrefixInfo};
+use crate::json::Json;
+use crate::monitor::origin_as;
+use crate::rib::{Fields, announced_keys, withdrawn_keys};
+use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+use super::{Bgp4mpBody, FsmState, MrtBody, MrtRecord, PeerEntry};
+
+/// A line of output, borrowing the attributes of the record it comes from
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpLine<'a> {
+    /// `TABLE_DUMP2` or `BGP4MP`
+    pub source: &'static str,
+    pub timestamp: SystemTime,
+    pub peer_addr: IpAddr,
+    pub peer_asn: u32,
+    pub entry: DumpEntry<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpEntry<'a> {
+    /// A route of a RIB dump or an UPDATE, `A`
+    Route {
+        prefix: IpAddrPrefix,
+        attributes: &'a [PathAttribute],
+    },
+    /// `W`
+    Withdrawal { prefix: IpAddrPrefix },
+    /// `STATE`
+    StateChange { old: FsmState, new: FsmState },
+}
+
+/// Turns records into [`DumpLine`]s, keeping the PEER_INDEX_TABLE the RIB records after it
+/// refer to.
+///
+/// Records without routes or state changes, such as KEEPALIVEs, have no lines.
+#[derive(Debug, Default)]
+pub struct DumpLines {
+    peers: Vec<PeerEntry>,
+    unknown_peers: u64,
+}
+
+impl DumpLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines<'a>(&mut self, record: &'a MrtRecord) -> Vec<DumpLine<'a>> {
+        let timestamp = record.header.timestamp;
+        match &record.body {
+            MrtBody::PeerIndexTable(table) => {
+                self.peers = table.peers.clone();
+                vec![]
+            }
+            MrtBody::Rib(rib) => rib
+                .entries
+                .iter()
+                .filter_map(|entry| {
+                    let Some(peer) = self.peers.get(usize::from(entry.peer_index)) else {
+                        self.unknown_peers += 1;
+                        return None;
+                    };
+                    Some(DumpLine {
+                        source: "TABLE_DUMP2",
+                        timestamp,
+                        peer_addr: peer.addr,
+                        peer_asn: peer.asn,
+                        entry: DumpEntry::Route {
+                            prefix: rib.prefix.clone().with_path_id(entry.path_id),
+                            attributes: &entry.attributes,
+                        },
+                    })
+                })
+                .collect(),
+            MrtBody::TableDump(table_dump) => vec![DumpLine {
+                source: "TABLE_DUMP",
+                timestamp,
+                peer_addr: table_dump.peer.addr,
+                peer_asn: table_dump.peer.asn,
+                entry: DumpEntry::Route {
+                    prefix: table_dump.prefix.clone(),
+                    attributes: &table_dump.entry.attributes,
+                },
+            }],
+            MrtBody::Bgp4mp(bgp4mp) => {
+                let line = |entry| DumpLine {
+                    source: "BGP4MP",
+                    timestamp,
+                    peer_addr: bgp4mp.peer.peer_addr,
+                    peer_asn: bgp4mp.peer.peer_asn,
+                    entry,
+                };
+                match &bgp4mp.body {
+                    Bgp4mpBody::StateChange { old, new } => {
+                        vec![line(DumpEntry::StateChange {
+                            old: *old,
+                            new: *new,
+                        })]
+                    }
+                    Bgp4mpBody::Message {
+                        message: BgpMessage::Update(update),
+                        ..
+                    } => DumpLine::update(
+                        timestamp,
+                        bgp4mp.peer.peer_addr,
+                        bgp4mp.peer.peer_asn,
+                        update,
+                    ),
+                    Bgp4mpBody::Message { .. } => vec![],
+                }
+            }
+            MrtBody::Unsupported(_) => vec![],
+        }
+    }
+
+    /// RIB entries left out because the PEER_INDEX_TABLE before them doesn't list their peer
+    pub fn unknown_peers(&self) -> u64 {
+        self.unknown_peers
+    }
+}
+
+impl<'a> DumpLine<'a> {
+    /// The `BGP4MP` lines of an UPDATE received from a peer, withdrawals first
+    pub fn update(
+        timestamp: SystemTime,
+        peer_addr: IpAddr,
+        peer_asn: u32,
+        update: &'a UpdateMessage,
+    ) -> Vec<Self> {
+        let line = |entry| DumpLine {
+            source: "BGP4MP",
+            timestamp,
+            peer_addr,
+            peer_asn,
+            entry,
+        };
+        withdrawn_keys(update)
+            .map(|key| {
+                line(DumpEntry::Withdrawal {
+                    prefix: key.prefix.with_path_id(key.path_id),
+                })
+            })
+            .chain(announced_keys(update).map(|key| {
+                line(DumpEntry::Route {
+                    prefix: key.prefix.with_path_id(key.path_id),
+                    attributes: &update.path_attributes,
+                })
+            }))
+            .collect()
+    }
+
+    pub fn prefix(&self) -> Option<&IpAddrPrefix> {
+        match &self.entry {
+            DumpEntry::Route { prefix, .. } | DumpEntry::Withdrawal { prefix } => Some(prefix),
+            DumpEntry::StateChange { .. } => None,
+        }
+    }
+
+    /// The attributes of an `A` line
+    pub fn attributes(&self) -> Option<&[PathAttribute]> {
+        match &self.entry {
+            DumpEntry::Route { attributes, .. } => Some(attributes),
+            _ => None,
+        }
+    }
+
+    /// The line as a JSON object with a member per field, named after the field; local
+    /// preference, MED and aggregator are `null` when missing
+    pub fn to_json(&self) -> Json {
+        let mut members = vec![
+            ("type", Json::from(self.source)),
+            ("timestamp", Json::Number(seconds(self.timestamp) as f64)),
+            ("entry", Json::from(self.kind())),
+            ("peer", Json::from(self.peer_addr.to_string())),
+            ("peer_asn", Json::from(self.peer_asn)),
+        ];
+        if let DumpEntry::Route { prefix, .. } | DumpEntry::Withdrawal { prefix } = &self.entry {
+            members.push(("prefix", Json::from(prefix.to_string())));
+            if let Some(path_id) = prefix.path_id() {
+                members.push(("path_id", Json::from(path_id)));
+            }
+        }
+        match &self.entry {
+            DumpEntry::Route { prefix, attributes } => {
+                let fields = Fields::new(attributes);
+                let optional = |value: Option<u32>| value.map_or(Json::Null, Json::from);
+                members.extend([
+                    (
+                        "as_path",
+                        Json::from(fields.as_path.map(AsPath::to_string).unwrap_or_default()),
+                    ),
+                    ("origin", Json::from(origin(fields.origin))),
+                    (
+                        "next_hop",
+                        Json::from(fields.next_hop(prefix.afi()).to_string()),
+                    ),
+                    ("local_pref", optional(fields.local_pref)),
+                    ("med", optional(fields.med)),
+                    (
+                        "communities",
+                        Json::Array(
+                            fields
+                                .communities
+                                .iter()
+                                .map(|c| Json::from(format!("{}:{}", c.asn, c.value)))
+                                .collect(),
+                        ),
+                    ),
+                    ("atomic_aggregate", Json::Bool(fields.atomic_aggregate)),
+                    (
+                        "aggregator",
+                        fields.aggregator.map_or(Json::Null, |aggregator| {
+                            Json::from(format!("{} {}", aggregator.asn, aggregator.ip))
+                        }),
+                    ),
+                ]);
+            }
+            DumpEntry::Withdrawal { .. } => {}
+            DumpEntry::StateChange { old, new } => members.extend([
+                ("old_state", Json::from(u32::from(u16::from(*old)))),
+                ("new_state", Json::from(u32::from(u16::from(*new)))),
+            ]),
+        }
+        Json::object(members)
+    }
+
+    /// The line with what `enricher` knows about its ASes and prefix
+    pub fn enriched<'e>(&'e self, enricher: &'e dyn Enricher) -> EnrichedLine<'e, 'a> {
+        let origin_as = self.attributes().and_then(origin_as);
+        EnrichedLine {
+            line: self,
+            peer_as_name: enricher.asn_name(self.peer_asn),
+            origin_as,
+            origin_as_name: origin_as.and_then(|asn| enricher.asn_name(asn)),
+            routed: self
+                .prefix()
+                .and_then(|prefix| enricher.prefix_info(prefix)),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.entry {
+            DumpEntry::Route { .. } => "A",
+            DumpEntry::Withdrawal { .. } => "W",
+            DumpEntry::StateChange { .. } => "STATE",
+        }
+    }
+}
+
+impl fmt::Display for DumpLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}|{}|{}|{}|{}|",
+            self.source,
+            seconds(self.timestamp),
+            self.kind(),
+            self.peer_addr,
+            self.peer_asn
+        )?;
+        match &self.entry {
+            DumpEntry::Route { prefix, attributes } => {
+                let fields = Fields::new(attributes);
+                let communities: Vec<_> = fields
+                    .communities
+                    .iter()
+                    .map(|community| format!("{}:{}", community.asn, community.value))
+                    .collect();
+                write!(
+                    f,
+                    "{prefix}|{}|{}|{}|{}|{}|{}|{}|",
+                    fields.as_path.map(AsPath::to_string).unwrap_or_default(),
+                    origin(fields.origin),
+                    fields.next_hop(prefix.afi()),
+                    fields.local_pref.unwrap_or(0),
+                    fields.med.unwrap_or(0),
+                    communities.join(" "),
+                    if fields.atomic_aggregate { "AG" } else { "NAG" },
+                )?;
+                if let Some(aggregator) = fields.aggregator {
+                    write!(f, "{} {}", aggregator.asn, aggregator.ip)?;
+                }
+                f.write_str("|")
+            }
+            DumpEntry::Withdrawal { prefix } => write!(f, "{prefix}"),
+            DumpEntry::StateChange { old, new } => {
+                write!(f, "{}|{}", u16::from(*old), u16::from(*new))
+            }
+        }
+    }
+}
+
+/// A [`DumpLine`] with names and routing data, printed with the peer AS name, origin AS, its
+/// name, routed prefix and its space separated origins as further fields, empty when unknown
+#[derive(Debug, Clone)]
+pub struct EnrichedLine<'e, 'a> {
+    pub line: &'e DumpLine<'a>,
+    pub peer_as_name: Option<String>,
+    /// The last AS of the path of an `A` line
+    pub origin_as: Option<u32>,
+    pub origin_as_name: Option<String>,
+    pub routed: Option<PrefixInfo>,
+}
+
+impl EnrichedLine<'_, '_> {
+    /// [`DumpLine::to_json`] with `peer_as_name`, `origin_as`, `origin_as_name`,
+    /// `routed_prefix` and `routed_origins` members, `null` when unknown
+    pub fn to_json(&self) -> Json {
+        let Json::Object(mut members) = self.line.to_json() else {
+            unreachable!("lines are objects")
+        };
+        let name = |name: &Option<String>| name.clone().map_or(Json::Null, Json::from);
+        members.extend(
+            [
+                ("peer_as_name", name(&self.peer_as_name)),
+                ("origin_as", self.origin_as.map_or(Json::Null, Json::from)),
+                ("origin_as_name", name(&self.origin_as_name)),
+                (
+                    "routed_prefix",
+                    self.routed
+                        .as_ref()
+                        .map_or(Json::Null, |routed| Json::from(routed.prefix.to_string())),
+                ),
+                (
+                    "routed_origins",
+                    self.routed.as_ref().map_or(Json::Null, |routed| {
+                        Json::Array(routed.origins.iter().copied().map(Json::from).collect())
+                    }),
+                ),
+            ]
+            .map(|(key, value)| (key.to_owned(), value)),
+        );
+        Json::Object(members)
+    }
+}
+
+impl fmt::Display for EnrichedLine<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.line)?;
+        // Only `A` lines end in a separator
+        if !matches!(self.line.entry, DumpEntry::Route { .. }) {
+            f.write_str("|")?;
+        }
+        write!(f, "{}|", self.peer_as_name.as_deref().unwrap_or_default())?;
+        if let Some(origin_as) = self.origin_as {
+            write!(f, "{origin_as}")?;
+        }
+        write!(
+            f,
+            "|{}|",
+            self.origin_as_name.as_deref().unwrap_or_default()
+        )?;
+        if let Some(routed) = &self.routed {
+            let origins: Vec<_> = routed.origins.iter().map(u32::to_string).collect();
+            write!(f, "{}|{}", routed.prefix, origins.join(" "))?;
+        } else {
+            f.write_str("|")?;
+        }
+        f.write_str("|")
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn origin(origin: Option<OriginType>) -> &'static str {
+    match origin {
+        Some(OriginType::Igp) => "IGP",
+        Some(OriginType::Egp) => "EGP",
+        Some(OriginType::Incomplete) => "INCOMPLETE",
+        None => "",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    use crate::enrich::FileEnricher;
+    use crate::mrt::MrtReader;
+
+    fn dump(fixture: &str) -> Vec<String> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(fixture);
+        let mut lines = DumpLines::new();
+        let mut dumped = vec![];
+        for record in MrtReader::open(path).unwrap().flatten() {
+            dumped.extend(lines.lines(&record).iter().map(ToString::to_string));
+        }
+        dumped
+    }
+
+    /// A dump of two peers, one of them IPv6 with a 4 octet ASN
+    #[test]
+    fn test_rib_dump_lines() {
+        assert_eq!(
+            dump("rib_dump.mrt"),
+            [
+                "TABLE_DUMP2|1700000000|A|192.0.2.1|64500|198.51.100.0/24|64500 {64511,64512}|\
+                 INCOMPLETE|192.0.2.1|0|0||AG|64500 192.0.2.9|",
+                "TABLE_DUMP2|1700000000|A|192.0.2.1|64500|203.0.113.0/24|64500 64510|IGP|\
+                 192.0.2.1|0|10|64500:1 64500:2|NAG||",
+                "TABLE_DUMP2|1700000000|A|2001:db8::2|4200000000|203.0.113.0/24|\
+                 4200000000 64510|IGP|2001:db8::2|200|0||NAG||",
+                "TABLE_DUMP2|1700000000|A|2001:db8::2|4200000000|2001:db8:1000::/36|\
+                 4200000000 64510|IGP|2001:db8::2|200|0||NAG||",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_lines() {
+        assert_eq!(
+            dump("bgp4mp_updates.mrt"),
+            [
+                "BGP4MP|1700000000|STATE|192.0.2.1|3356|5|6",
+                "BGP4MP|1700000001|A|192.0.2.1|3356|203.0.113.0/24|3356 1299 4200000000|IGP|\
+                 192.0.2.1|0|0|3356:2|NAG||",
+                "BGP4MP|1700000002|A|10.0.0.2|7018|198.18.0.0/15|7018 4200000000|IGP|\
+                 10.0.0.2|0|0||NAG||",
+                "BGP4MP|1700000004|A|2001:db8::1|6939|2001:db8:1000::/36|6939 64500|IGP|\
+                 2001:db8::1|0|0||NAG||",
+                "BGP4MP|1700000005|W|192.0.2.1|3356|203.0.113.0/24",
+                "BGP4MP|1700000008|STATE|10.0.0.2|7018|6|1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_and_unknown_peers() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/rib_dump.mrt");
+        let records: Vec<_> = MrtReader::open(path).unwrap().flatten().collect();
+        let mut lines = DumpLines::new();
+        assert!(lines.lines(&records[0]).is_empty());
+        assert_eq!(
+            lines.lines(&records[1])[0].to_json().to_string(),
+            "{\"type\":\"TABLE_DUMP2\",\"timestamp\":1700000000,\"entry\":\"A\",\
+             \"peer\":\"192.0.2.1\",\"peer_asn\":64500,\"prefix\":\"198.51.100.0/24\",\
+             \"as_path\":\"64500 {64511,64512}\",\"origin\":\"INCOMPLETE\",\
+             \"next_hop\":\"192.0.2.1\",\"local_pref\":null,\"med\":null,\"communities\":[],\
+             \"atomic_aggregate\":true,\"aggregator\":\"64500 192.0.2.9\"}"
+        );
+
+        // RIB records without the PEER_INDEX_TABLE before them
+        let mut lines = DumpLines::new();
+        assert!(lines.lines(&records[2]).is_empty());
+        assert_eq!(lines.unknown_peers(), 2);
+    }
+
+    #[test]
+    fn test_enriched_lines() {
+        let mut enricher = FileEnricher::new();
+        enricher
+            .load_asn_names("3356 LEVEL3\n4200000000 PRIVATE\n")
+            .unwrap();
+        enricher
+            .load_pfx2as("203.0.113.0\t24\t64500_4200000000\n")
+            .unwrap();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bgp4mp_updates.mrt");
+        let records: Vec<_> = MrtReader::open(path).unwrap().flatten().collect();
+        let mut lines = DumpLines::new();
+        let enriched: Vec<_> = records
+            .iter()
+            .flat_map(|record| lines.lines(record))
+            .map(|line| line.enriched(&enricher).to_string())
+            .collect();
+        assert_eq!(
+            enriched,
+            [
+                "BGP4MP|1700000000|STATE|192.0.2.1|3356|5|6|LEVEL3|||||",
+                "BGP4MP|1700000001|A|192.0.2.1|3356|203.0.113.0/24|3356 1299 4200000000|IGP|\
+                 192.0.2.1|0|0|3356:2|NAG||LEVEL3|4200000000|PRIVATE|203.0.113.0/24|\
+                 64500 4200000000|",
+                "BGP4MP|1700000002|A|10.0.0.2|7018|198.18.0.0/15|7018 4200000000|IGP|\
+                 10.0.0.2|0|0||NAG|||4200000000|PRIVATE|||",
+                "BGP4MP|1700000004|A|2001:db8::1|6939|2001:db8:1000::/36|6939 64500|IGP|\
+                 2001:db8::1|0|0||NAG|||64500||||",
+                "BGP4MP|1700000005|W|192.0.2.1|3356|203.0.113.0/24|LEVEL3|||\
+                 203.0.113.0/24|64500 4200000000|",
+                "BGP4MP|1700000008|STATE|10.0.0.2|7018|6|1||||||",
+            ]
+        );
+
+        let update = &records[1];
+        let line = &lines.lines(update)[0];
+        let json = line.enriched(&enricher).to_json();
+        assert_eq!(json["peer_as_name"], Json::from("LEVEL3"));
+        assert_eq!(json["origin_as"], Json::from(4_200_000_000));
+        assert_eq!(json["origin_as_name"], Json::from("PRIVATE"));
+        assert_eq!(json["routed_prefix"], Json::from("203.0.113.0/24"));
+        assert_eq!(json["routed_origins"].to_string(), "[64500,4200000000]");
+        assert_eq!(json["prefix"], line.to_json()["prefix"]);
+    }
+}
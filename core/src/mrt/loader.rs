@@ -0,0 +1,155 @@
+use std::time::UNIX_EPOCH;
+
+use super::{MrtBody, MrtRecord, PeerEntry, RibEntry};
+use crate::rib::{AttrSetInterner, Clocks, InternerStats, RibIn, RibKey};
+
+/// Builds the Adj-RIB-In of every peer of a TABLE_DUMP_V2 or legacy TABLE_DUMP dump from its
+/// records.
+///
+/// Routes are keyed on their prefix, SAFI and path identifier, so the several paths a peer
+/// sent for a prefix with ADD-PATH are all kept. Their age is from when they were received.
+/// TABLE_DUMP peers are told apart by address and ASN, in the order they first appear.
+/// Attribute sets are interned across all peers, as dumps repeat the same few many times.
+#[derive(Debug, Default)]
+pub struct RibLoader {
+    peers: Vec<PeerEntry>,
+    /// Parallel to `peers`
+    ribs: Vec<RibIn>,
+    unknown_peers: u64,
+    interner: AttrSetInterner,
+}
+
+impl RibLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the routes of a RIB record, or starts over from the peers of a PEER_INDEX_TABLE.
+    /// Other records are ignored.
+    pub fn load(&mut self, record: &MrtRecord) {
+        let clocks = Clocks::now();
+        let age = |entry: &RibEntry| {
+            let originated = entry
+                .originated
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let originated = originated.as_millis() as u64;
+            clocks.to_age(originated, originated)
+        };
+        match &record.body {
+            MrtBody::PeerIndexTable(table) => {
+                self.peers = table.peers.clone();
+                self.ribs = vec![RibIn::new(); self.peers.len()];
+            }
+            MrtBody::Rib(rib) => {
+                for entry in &rib.entries {
+                    let Some(peer_rib) = self.ribs.get_mut(usize::from(entry.peer_index)) else {
+                        self.unknown_peers += 1;
+                        continue;
+                    };
+                    let key = RibKey {
+                        prefix: rib.prefix.clone(),
+                        safi: rib.safi,
+                        path_id: entry.path_id,
+                    };
+                    let attributes = self.interner.intern(&entry.attributes);
+                    let _ = peer_rib.insert(key, attributes, age(entry));
+                }
+            }
+            MrtBody::TableDump(table_dump) => {
+                let index = match self.peers.iter().position(|peer| {
+                    (peer.addr, peer.asn) == (table_dump.peer.addr, table_dump.peer.asn)
+                }) {
+                    Some(index) => index,
+                    None => {
+                        self.peers.push(table_dump.peer);
+                        self.ribs.push(RibIn::new());
+                        self.peers.len() - 1
+                    }
+                };
+                let key = RibKey::unicast(table_dump.prefix.clone());
+                let entry = &table_dump.entry;
+                let attributes = self.interner.intern(&entry.attributes);
+                let _ = self.ribs[index].insert(key, attributes, age(entry));
+            }
+            _ => {}
+        }
+    }
+
+    /// RIB entries skipped for referring to peers missing from the PEER_INDEX_TABLE
+    pub fn unknown_peers(&self) -> u64 {
+        self.unknown_peers
+    }
+
+    /// How many attribute sets of the routes loaded were shared
+    pub fn interner_stats(&self) -> InternerStats {
+        self.interner.stats()
+    }
+
+    /// The RIB of every peer, in the order of the PEER_INDEX_TABLE
+    pub fn into_ribs(self) -> Vec<(PeerEntry, RibIn)> {
+        self.peers.into_iter().zip(self.ribs).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    use crate::address_family::Safi;
+    use crate::mrt::MrtReader;
+
+    #[test]
+    fn test_keeps_every_path() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/addpath.mrt");
+        let mut loader = RibLoader::new();
+        for record in MrtReader::open(path).unwrap() {
+            loader.load(&record.unwrap());
+        }
+        assert_eq!(loader.unknown_peers(), 0);
+
+        let ribs = loader.into_ribs();
+        let peers: Vec<_> = ribs
+            .iter()
+            .map(|(peer, rib)| (peer.asn, rib.len()))
+            .collect();
+        assert_eq!(peers, [(64500, 2), (4_200_000_000, 2)]);
+
+        let (_, rib) = &ribs[0];
+        let key = |path_id| RibKey {
+            prefix: "203.0.113.0/24".parse().unwrap(),
+            safi: Safi::Unicast,
+            path_id: Some(path_id),
+        };
+        assert!(rib.get(&key(1)).is_some());
+        assert!(rib.get(&key(2)).is_some());
+        assert_ne!(rib.get(&key(1)), rib.get(&key(2)));
+        assert!(
+            rib.get(&RibKey::unicast("203.0.113.0/24".parse().unwrap()))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_table_dump_peers() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/table_dump_v1.mrt");
+        let mut loader = RibLoader::new();
+        for record in MrtReader::open(path).unwrap() {
+            loader.load(&record.unwrap());
+        }
+        let peers: Vec<_> = loader
+            .into_ribs()
+            .iter()
+            .map(|(peer, rib)| (peer.addr.to_string(), peer.asn, rib.len()))
+            .collect();
+        assert_eq!(
+            peers,
+            [
+                ("192.0.2.1".to_owned(), 7018, 1),
+                ("192.0.2.5".to_owned(), 3356, 1),
+                ("2001:db8::1".to_owned(), 6939, 2),
+            ]
+        );
+    }
+}
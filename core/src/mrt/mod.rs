@@ -0,0 +1,544 @@
+//! Reading the MRT routing information export format (RFC 6396) that route collectors such as
+//! RouteViews and RIPE RIS archive their BGP feeds in.
+//!
+//! A file is a sequence of records, each a common header followed by a body of the announced
+//! length, all integers big endian:
+//!
+//! ```text
+//! timestamp seconds: u32 | type: u16 | subtype: u16 | length: u32 | body
+//! ```
+//!
+//! The `_ET` types start the body with the microseconds of the timestamp. [`MrtReader`] decodes
+//! BGP4MP and BGP4MP_ET records, the unicast and multicast RIB records of TABLE_DUMP_V2,
+//! including the `_ADDPATH` subtypes of RFC 8050, and those of the legacy TABLE_DUMP, and hands
+//! out other types undecoded, and [`MrtReader::open`] reads gzipped and bzipped files as they
+//! come from the archives.
+//! [`RibLoader`] builds the RIB of every peer of a dump. [`MrtWriter`] writes the same records.
+//! With the `rayon` feature, `MrtReader::par_records` decodes a stream on the rayon thread pool.
+
+mod bgp4mp;
+mod bgpdump;
+mod loader;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod table_dump;
+mod table_dump_v2;
+mod writer;
+
+pub use bgp4mp::{Bgp4mp, Bgp4mpBody, FsmState, PeerInfo};
+pub use bgpdump::{DumpEntry, DumpLine, DumpLines, EnrichedLine};
+pub use loader::RibLoader;
+#[cfg(feature = "rayon")]
+pub use parallel::ParRecords;
+pub use table_dump::TableDump;
+pub use table_dump_v2::{PeerEntry, PeerIndexTable, RibEntry, RibRecord};
+pub use writer::MrtWriter;
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, Bytes};
+use thiserror::Error;
+
+use crate::bgp_message::MessageDecodeError;
+use crate::compression::Decompress;
+
+/// MRT type codes
+pub const TABLE_DUMP: u16 = 12;
+pub const TABLE_DUMP_V2: u16 = 13;
+pub const BGP4MP: u16 = 16;
+pub const BGP4MP_ET: u16 = 17;
+
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum MrtError {
+    #[error("MRT I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("MRT file ends in a truncated record at offset {offset}")]
+    Truncated { offset: u64 },
+    #[error("malformed MRT record at offset {offset}: {reason}")]
+    Malformed { offset: u64, reason: &'static str },
+    #[error("BGP message of the MRT record at offset {offset} doesn't decode: {err}")]
+    Message {
+        offset: u64,
+        #[source]
+        err: MessageDecodeError,
+    },
+}
+
+/// The common header of every record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MrtHeader {
+    /// Including the microseconds of `_ET` types
+    pub timestamp: SystemTime,
+    pub mrt_type: u16,
+    pub subtype: u16,
+    /// Of the body, including the microseconds of `_ET` types
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MrtRecord {
+    pub header: MrtHeader,
+    pub body: MrtBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MrtBody {
+    Bgp4mp(Bgp4mp),
+    PeerIndexTable(PeerIndexTable),
+    Rib(RibRecord),
+    /// A route of a legacy TABLE_DUMP, with its peer
+    TableDump(TableDump),
+    /// A type this reader doesn't decode, the body as recorded
+    Unsupported(Bytes),
+}
+
+/// Iterates over the records of an MRT stream.
+///
+/// A record that doesn't decode is reported and skipped, the next one is read from where its
+/// announced length ends. Since a truncated record or a failing reader leaves no way to find
+/// the next one, those errors end the iteration.
+pub struct MrtReader<R> {
+    reader: R,
+    offset: u64,
+    done: bool,
+}
+
+impl MrtReader<Decompress<BufReader<File>>> {
+    /// Opens a file, decompressing it if it's gzipped or bzipped
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(MrtReader::new(Decompress::new(file)?))
+    }
+}
+
+impl<R: Read> MrtReader<R> {
+    pub fn new(reader: R) -> Self {
+        MrtReader {
+            reader,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Where in the stream the next record starts
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The next header and body, `None` at the end of the stream
+    fn read_raw(&mut self) -> Result<Option<(MrtHeader, Bytes)>, MrtError> {
+        let offset = self.offset;
+        let mut header = [0; HEADER_LEN];
+        let read = read_full(&mut self.reader, &mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < HEADER_LEN {
+            return Err(MrtError::Truncated { offset });
+        }
+        let mut header = &header[..];
+        let seconds = header.get_u32();
+        let header = MrtHeader {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.into()),
+            mrt_type: header.get_u16(),
+            subtype: header.get_u16(),
+            length: header.get_u32(),
+        };
+
+        let mut body = Vec::new();
+        (&mut self.reader)
+            .take(header.length.into())
+            .read_to_end(&mut body)?;
+        if body.len() < header.length as usize {
+            return Err(MrtError::Truncated { offset });
+        }
+        self.offset += (HEADER_LEN + body.len()) as u64;
+        Ok(Some((header, body.into())))
+    }
+}
+
+impl<R: Read> Iterator for MrtReader<R> {
+    type Item = Result<MrtRecord, MrtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let offset = self.offset;
+        let (header, body) = match self.read_raw() {
+            Ok(Some(raw)) => raw,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        };
+        Some(MrtRecord::decode(header, body, offset))
+    }
+}
+
+impl MrtRecord {
+    /// Decodes the body of the record starting at `offset`, which errors refer to
+    fn decode(mut header: MrtHeader, mut body: Bytes, offset: u64) -> Result<Self, MrtError> {
+        let body = match header.mrt_type {
+            BGP4MP | BGP4MP_ET => {
+                if header.mrt_type == BGP4MP_ET {
+                    if body.len() < 4 {
+                        return Err(MrtError::Malformed {
+                            offset,
+                            reason: "truncated microsecond timestamp",
+                        });
+                    }
+                    header.timestamp += Duration::from_micros(body.get_u32().into());
+                }
+                MrtBody::Bgp4mp(Bgp4mp::decode(header.subtype, &mut body, offset)?)
+            }
+            TABLE_DUMP => match header.subtype {
+                table_dump::AFI_IPV4 | table_dump::AFI_IPV6 => {
+                    MrtBody::TableDump(TableDump::decode(header.subtype, &mut body, offset)?)
+                }
+                _ => MrtBody::Unsupported(body),
+            },
+            TABLE_DUMP_V2 => match header.subtype {
+                table_dump_v2::PEER_INDEX_TABLE => {
+                    MrtBody::PeerIndexTable(PeerIndexTable::decode(&mut body, offset)?)
+                }
+                table_dump_v2::RIB_IPV4_UNICAST..=table_dump_v2::RIB_IPV6_MULTICAST
+                | table_dump_v2::RIB_IPV4_UNICAST_ADDPATH
+                    ..=table_dump_v2::RIB_IPV6_MULTICAST_ADDPATH => {
+                    MrtBody::Rib(RibRecord::decode(header.subtype, &mut body, offset)?)
+                }
+                _ => MrtBody::Unsupported(body),
+            },
+            _ => MrtBody::Unsupported(body),
+        };
+        Ok(MrtRecord { header, body })
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning how much was read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::attribute::{AsPathSegment, AsPathSegmentType, AttributeValue};
+    use crate::bgp_message::BgpMessage;
+    use crate::header::HeaderParseError;
+    use crate::journal::Direction;
+    use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+    fn fixture() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bgp4mp_updates.mrt")
+    }
+
+    fn at(secs: u64, micros: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(1_700_000_000 + secs)
+            + Duration::from_micros(micros)
+    }
+
+    fn as_path(message: &BgpMessage) -> Vec<u32> {
+        let BgpMessage::Update(update) = message else {
+            panic!("{message:?} isn't an UPDATE");
+        };
+        match &update.attributes().as_path().unwrap().segments[..] {
+            [
+                AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns,
+                },
+            ] => asns.to_vec(),
+            segments => panic!("unexpected AS_PATH {segments:?}"),
+        }
+    }
+
+    /// A RouteViews style updates file: BGP4MP_MESSAGE_AS4 from 4 octet peers, BGP4MP_MESSAGE
+    /// and STATE_CHANGE from a 2 octet peer, an ET record, a message sent by the collector, an
+    /// empty PEER_INDEX_TABLE and one with a corrupt BGP marker
+    #[test]
+    fn test_read_updates_file() {
+        let records: Vec<_> = MrtReader::open(fixture()).unwrap().collect();
+        assert_eq!(records.len(), 10);
+
+        let bgp4mp = |i: usize| -> &Bgp4mp {
+            match &records[i].as_ref().unwrap().body {
+                MrtBody::Bgp4mp(bgp4mp) => bgp4mp,
+                body => panic!("record {i} is {body:?}"),
+            }
+        };
+        let message = |i: usize| match &bgp4mp(i).body {
+            Bgp4mpBody::Message { direction, message } => (*direction, message),
+            body => panic!("record {i} is {body:?}"),
+        };
+
+        let state_change = bgp4mp(0);
+        assert_eq!(
+            state_change.peer,
+            PeerInfo {
+                peer_asn: 3356,
+                local_asn: 6447,
+                interface_index: 0,
+                peer_addr: "192.0.2.1".parse().unwrap(),
+                local_addr: "198.51.100.254".parse().unwrap(),
+            }
+        );
+        assert_eq!(
+            state_change.body,
+            Bgp4mpBody::StateChange {
+                old: FsmState::OpenConfirm,
+                new: FsmState::Established
+            }
+        );
+
+        let (direction, update) = message(1);
+        assert_eq!(direction, Direction::Received);
+        assert_eq!(as_path(update), vec![3356, 1299, 4_200_000_000]);
+
+        // The 2 octet AS_PATH, with the 4 octet ASN restored from AS4_PATH
+        assert!(!bgp4mp(2).four_octet_as);
+        assert_eq!(
+            (bgp4mp(2).peer.peer_asn, bgp4mp(2).peer.local_asn),
+            (7018, 6447)
+        );
+        assert_eq!(as_path(message(2).1), vec![7018, 4_200_000_000]);
+
+        let Err(MrtError::Message { offset, err }) = &records[3] else {
+            panic!("record 3 is {:?}", records[3]);
+        };
+        assert!(matches!(
+            err,
+            MessageDecodeError::Header(HeaderParseError::MalformedMarkerField)
+        ));
+        let mut reader = MrtReader::open(fixture()).unwrap();
+        reader.by_ref().take(3).for_each(drop);
+        assert_eq!(reader.offset(), *offset);
+
+        assert_eq!(
+            bgp4mp(4).peer.peer_addr,
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(as_path(message(4).1), vec![6939, 64500]);
+
+        let BgpMessage::Update(withdrawal) = message(5).1 else {
+            panic!("record 5 isn't an UPDATE");
+        };
+        assert_eq!(withdrawal.withdrawn_routes.len(), 1);
+
+        let keepalive = records[6].as_ref().unwrap();
+        assert_eq!(keepalive.header.mrt_type, BGP4MP_ET);
+        assert_eq!(keepalive.header.timestamp, at(6, 500_000));
+        assert_eq!(message(6), (Direction::Received, &BgpMessage::Keepalive));
+        assert_eq!(message(7), (Direction::Sent, &BgpMessage::Keepalive));
+
+        let peer_index = records[8].as_ref().unwrap();
+        assert_eq!(peer_index.header.mrt_type, TABLE_DUMP_V2);
+        assert_eq!(
+            peer_index.body,
+            MrtBody::PeerIndexTable(PeerIndexTable {
+                collector_id: Ipv4Addr::new(198, 51, 100, 254),
+                view_name: String::new(),
+                peers: vec![],
+            })
+        );
+
+        assert_eq!(records[9].as_ref().unwrap().header.timestamp, at(8, 0));
+        assert_eq!(
+            bgp4mp(9).body,
+            Bgp4mpBody::StateChange {
+                old: FsmState::Established,
+                new: FsmState::Idle
+            }
+        );
+    }
+
+    /// A dump of a collector keeping several paths per prefix and peer: RIB_IPV4_UNICAST_ADDPATH
+    /// and RIB_IPV6_UNICAST_ADDPATH records with two paths each, then a
+    /// BGP4MP_ET/MESSAGE_AS4_ADDPATH UPDATE withdrawing one path and announcing another prefix
+    #[test]
+    fn test_read_add_path_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/addpath.mrt");
+        let records: Vec<_> = MrtReader::open(&path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 4);
+
+        let path_ids = |i: usize| match &records[i].body {
+            MrtBody::Rib(rib) => rib
+                .entries
+                .iter()
+                .map(|entry| (entry.peer_index, entry.path_id))
+                .collect::<Vec<_>>(),
+            body => panic!("record {i} is {body:?}"),
+        };
+        assert_eq!(path_ids(1), [(0, Some(1)), (0, Some(2))]);
+        assert_eq!(path_ids(2), [(1, Some(1)), (1, Some(2))]);
+
+        let MrtBody::Bgp4mp(bgp4mp) = &records[3].body else {
+            panic!("record 3 is {:?}", records[3]);
+        };
+        assert!(bgp4mp.four_octet_as && bgp4mp.add_path);
+        let Bgp4mpBody::Message {
+            message: BgpMessage::Update(update),
+            ..
+        } = &bgp4mp.body
+        else {
+            panic!("record 3 is {bgp4mp:?}");
+        };
+        let prefixes = |prefixes: &[IpAddrPrefix]| {
+            prefixes
+                .iter()
+                .map(|prefix| (prefix.to_string(), prefix.path_id()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            prefixes(&update.withdrawn_routes),
+            [("203.0.113.0/24".to_owned(), Some(2))]
+        );
+        assert_eq!(
+            prefixes(&update.nlri),
+            [("198.51.100.0/24".to_owned(), Some(1))]
+        );
+
+        // The same records under the subtypes without path identifiers don't decode
+        let mut bytes = std::fs::read(&path).unwrap();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let subtype = u16::from_be_bytes([bytes[offset + 6], bytes[offset + 7]]);
+            let plain = match subtype {
+                table_dump_v2::RIB_IPV4_UNICAST_ADDPATH => table_dump_v2::RIB_IPV4_UNICAST,
+                table_dump_v2::RIB_IPV6_UNICAST_ADDPATH => table_dump_v2::RIB_IPV6_UNICAST,
+                bgp4mp::MESSAGE_AS4_ADDPATH => bgp4mp::MESSAGE_AS4,
+                subtype => subtype,
+            };
+            bytes[offset + 6..offset + 8].copy_from_slice(&plain.to_be_bytes());
+            let length = u32::from_be_bytes(bytes[offset + 8..offset + 12].try_into().unwrap());
+            offset += HEADER_LEN + length as usize;
+        }
+        let records: Vec<_> = MrtReader::new(&bytes[..]).collect();
+        assert!(records[0].is_ok());
+        for record in &records[1..] {
+            assert!(record.is_err(), "{record:?}");
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_read_compressed_files() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        for (plain, compressed) in [
+            ("rib_dump.mrt", "rib_dump.mrt.gz"),
+            ("bgp4mp_updates.mrt", "bgp4mp_updates.mrt.bz2"),
+        ] {
+            // Broken records of the fixtures are compared by their errors
+            let read = |name: &str| -> Vec<_> {
+                MrtReader::open(data.join(name))
+                    .unwrap()
+                    .map(|record| record.map_err(|error| error.to_string()))
+                    .collect()
+            };
+            let records = read(plain);
+            assert!(records.len() > 1);
+            assert_eq!(read(compressed), records, "{compressed}");
+        }
+    }
+
+    /// A TABLE_DUMP RIB of an old archive: IPv4 routes with 2 octet AS_PATHs, one with an
+    /// AS4_PATH, and IPv6 routes with a full and an abbreviated MP_REACH_NLRI
+    #[test]
+    fn test_read_table_dump() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/table_dump_v1.mrt");
+        let records: Vec<_> = MrtReader::open(path)
+            .unwrap()
+            .map(|record| match record.unwrap().body {
+                MrtBody::TableDump(table_dump) => table_dump,
+                body => panic!("unexpected {body:?}"),
+            })
+            .collect();
+        assert_eq!(records.len(), 4);
+
+        let first = &records[0];
+        assert_eq!((first.view, first.sequence), (0, 0));
+        assert_eq!(first.prefix, "198.51.100.0/24".parse().unwrap());
+        assert_eq!(
+            first.peer,
+            PeerEntry {
+                router_id: Ipv4Addr::UNSPECIFIED,
+                addr: "192.0.2.1".parse().unwrap(),
+                asn: 7018,
+            }
+        );
+        assert_eq!(
+            first.entry.originated,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_100_000_000 - 3600)
+        );
+        let as_path = |table_dump: &TableDump| {
+            as_path(&BgpMessage::Update(UpdateMessage {
+                withdrawn_routes: vec![],
+                path_attributes: table_dump.entry.attributes.clone(),
+                nlri: vec![],
+            }))
+        };
+        // AS_TRANS restored from AS4_PATH, which is gone
+        assert_eq!(as_path(first), [7018, 3356, 4_200_000_000]);
+        assert_eq!(first.entry.attributes.len(), 4);
+        assert_eq!(as_path(&records[1]), [3356, 1299]);
+
+        for table_dump in &records[2..] {
+            assert_eq!(
+                table_dump.peer.addr,
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            );
+            let next_hop =
+                table_dump
+                    .entry
+                    .attributes
+                    .iter()
+                    .find_map(|attribute| match &attribute.value {
+                        AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach.next_hop),
+                        _ => None,
+                    });
+            assert_eq!(next_hop, Some("2001:db8::1".parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes = std::fs::read(fixture()).unwrap();
+        let mut records = MrtReader::new(&bytes[..bytes.len() - 3]);
+        let last = records.by_ref().last().unwrap();
+        let length = records.offset();
+        assert!(matches!(last, Err(MrtError::Truncated { offset }) if offset == length));
+        assert!(records.next().is_none());
+
+        // A cut within the header
+        let mut records = MrtReader::new(&bytes[..5]);
+        assert!(matches!(
+            records.next(),
+            Some(Err(MrtError::Truncated { offset: 0 }))
+        ));
+        assert!(records.next().is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::vec;
+
+use bytes::Bytes;
+
+use super::{MrtError, MrtHeader, MrtReader, MrtRecord};
+
+/// Records handed to a decoding task at once
+const CHUNK_LEN: usize = 256;
+
+/// A record as read, not yet decoded, with its offset
+type Raw = Result<(MrtHeader, Bytes, u64), MrtError>;
+
+impl<R: Read> MrtReader<R> {
+    /// Decodes the records on the rayon thread pool, yielding them in the order of the stream
+    /// just like iterating the reader does.
+    ///
+    /// The iteration reads the stream and cuts it into chunks of records, found by their
+    /// lengths, that the pool decodes. Every record decodes on its own, RIB records keeping
+    /// only the index into the PEER_INDEX_TABLE, so chunks don't wait on one another. A few
+    /// chunks per pool thread are in flight at most, which bounds those decoded ahead of the
+    /// one the iteration waits for.
+    pub fn par_records(self) -> ParRecords<R> {
+        self.par_records_with(2 * rayon::current_num_threads(), CHUNK_LEN)
+    }
+
+    fn par_records_with(self, in_flight: usize, chunk_len: usize) -> ParRecords<R> {
+        let (decoded, results) = mpsc::channel();
+        ParRecords {
+            reader: Some(self),
+            chunk_len,
+            in_flight,
+            decoded,
+            results,
+            pending: BTreeMap::new(),
+            sent: 0,
+            next: 0,
+            current: vec![].into_iter(),
+        }
+    }
+}
+
+/// The records of [`MrtReader::par_records`], in the order of the stream
+pub struct ParRecords<R> {
+    /// `None` once the stream ended or failed
+    reader: Option<MrtReader<R>>,
+    chunk_len: usize,
+    in_flight: usize,
+    decoded: Sender<(u64, Vec<Result<MrtRecord, MrtError>>)>,
+    results: Receiver<(u64, Vec<Result<MrtRecord, MrtError>>)>,
+    /// Chunks decoded ahead of `next`
+    pending: BTreeMap<u64, Vec<Result<MrtRecord, MrtError>>>,
+    /// Chunks handed to the pool
+    sent: u64,
+    next: u64,
+    current: vec::IntoIter<Result<MrtRecord, MrtError>>,
+}
+
+impl<R: Read> ParRecords<R> {
+    /// Hands chunks to the pool until as many as allowed are in flight
+    fn fill(&mut self) {
+        while self.sent - self.next < self.in_flight as u64 {
+            let Some(reader) = &mut self.reader else {
+                return;
+            };
+            let mut chunk = Vec::with_capacity(self.chunk_len);
+            while chunk.len() < self.chunk_len {
+                let offset = reader.offset;
+                match reader.read_raw() {
+                    Ok(Some((header, body))) => chunk.push(Ok((header, body, offset))),
+                    Ok(None) => {
+                        self.reader = None;
+                        break;
+                    }
+                    Err(err) => {
+                        chunk.push(Err(err));
+                        self.reader = None;
+                        break;
+                    }
+                }
+            }
+            if chunk.is_empty() {
+                return;
+            }
+
+            let (index, decoded) = (self.sent, self.decoded.clone());
+            rayon::spawn(move || {
+                // Fails once the iteration was dropped
+                let _ = decoded.send((index, decode(chunk)));
+            });
+            self.sent += 1;
+        }
+    }
+}
+
+fn decode(chunk: Vec<Raw>) -> Vec<Result<MrtRecord, MrtError>> {
+    chunk
+        .into_iter()
+        .map(|raw| raw.and_then(|(header, body, offset)| MrtRecord::decode(header, body, offset)))
+        .collect()
+}
+
+impl<R: Read> Iterator for ParRecords<R> {
+    type Item = Result<MrtRecord, MrtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+            self.fill();
+            if self.next == self.sent {
+                return None;
+            }
+            let chunk = match self.pending.remove(&self.next) {
+                Some(chunk) => chunk,
+                None => {
+                    let (index, chunk) = self.results.recv().ok()?;
+                    self.pending.insert(index, chunk);
+                    continue;
+                }
+            };
+            self.next += 1;
+            self.current = chunk.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use crate::mrt::{MrtWriter, PeerInfo};
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn outcomes(records: impl Iterator<Item = Result<MrtRecord, MrtError>>) -> Vec<String> {
+        records.map(|record| format!("{record:?}")).collect()
+    }
+
+    #[test]
+    fn test_same_as_sequential() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        for name in ["rib_dump.mrt.gz", "bgp4mp_updates.mrt.bz2", "addpath.mrt"] {
+            let sequential = outcomes(MrtReader::open(data.join(name)).unwrap());
+            let parallel = outcomes(MrtReader::open(data.join(name)).unwrap().par_records());
+            assert_eq!(parallel, sequential, "{name}");
+        }
+
+        // Enough records for many small chunks, then a truncated one
+        let mut writer = MrtWriter::new(vec![]);
+        let peer = PeerInfo {
+            peer_asn: 64500,
+            local_asn: 64501,
+            interface_index: 0,
+            peer_addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            local_addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+        };
+        for i in 0..1000 {
+            let update = UpdateMessageBuilder::new()
+                .announce(format!("10.{}.{}.0/24", i / 256, i % 256).parse().unwrap())
+                .next_hop(peer.peer_addr)
+                .build();
+            let at = SystemTime::UNIX_EPOCH + Duration::from_secs(i);
+            writer.write_update(&peer, at, &update).unwrap();
+        }
+        let mut stream = writer.into_inner();
+        stream.truncate(stream.len() - 1);
+        let sequential = outcomes(MrtReader::new(Cursor::new(stream.clone())));
+        for (in_flight, chunk_len) in [(1, 1), (4, 7), (3, 1000)] {
+            let parallel =
+                MrtReader::new(Cursor::new(stream.clone())).par_records_with(in_flight, chunk_len);
+            assert_eq!(outcomes(parallel), sequential);
+        }
+        assert_eq!(sequential.len(), 1000);
+        assert!(sequential[999].starts_with("Err(Truncated"));
+    }
+
+    #[test]
+    fn test_stops_when_dropped() {
+        let stream = Cursor::new(vec![0; 1 << 20]);
+        let mut records = MrtReader::new(stream).par_records_with(2, 1);
+        assert!(records.next().unwrap().is_ok());
+        drop(records);
+    }
+}
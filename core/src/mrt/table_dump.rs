@@ -0,0 +1,107 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, Bytes};
+
+use super::MrtError;
+use super::table_dump_v2::{MP_REACH_NLRI, PeerEntry, RibEntry, decode_mp_reach};
+use crate::address_family::{Afi, Safi};
+use crate::attribute::PathAttribute;
+use crate::update_message::{IpAddrPrefix, ParserConfig, merge_as4_attributes};
+
+/// TABLE_DUMP subtypes
+pub(super) const AFI_IPV4: u16 = 1;
+pub(super) const AFI_IPV6: u16 = 2;
+
+/// A TABLE_DUMP record, as in dumps from before TABLE_DUMP_V2: one peer's route for a
+/// prefix, with the peer in the record rather than in a PEER_INDEX_TABLE.
+///
+/// AS_PATHs have 2 octet ASNs, restored from AS4_PATH where the peer sent one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDump {
+    pub view: u16,
+    /// Wraps around in dumps of more than 65536 routes
+    pub sequence: u16,
+    pub prefix: IpAddrPrefix,
+    /// With an unspecified router ID, which the format doesn't carry
+    pub peer: PeerEntry,
+    /// With a peer index of 0
+    pub entry: RibEntry,
+}
+
+impl TableDump {
+    pub(super) fn decode(subtype: u16, body: &mut Bytes, offset: u64) -> Result<Self, MrtError> {
+        let malformed = |reason| MrtError::Malformed { offset, reason };
+        let (afi, addr_len) = match subtype {
+            AFI_IPV4 => (Afi::Ipv4, 4),
+            _ => (Afi::Ipv6, 16),
+        };
+        let addr = |body: &mut Bytes| match afi {
+            Afi::Ipv4 => IpAddr::V4(Ipv4Addr::from(body.get_u32())),
+            _ => IpAddr::V6(Ipv6Addr::from(body.get_u128())),
+        };
+
+        if body.len() < 2 + 2 + addr_len + 1 + 1 + 4 + addr_len + 2 + 2 {
+            return Err(malformed("truncated TABLE_DUMP record"));
+        }
+        let view = body.get_u16();
+        let sequence = body.get_u16();
+        let prefix_addr = addr(body);
+        let prefix =
+            IpAddrPrefix::new(prefix_addr, body.get_u8()).ok_or(malformed("invalid prefix"))?;
+        // The status is unused and always 1
+        body.advance(1);
+        let originated = SystemTime::UNIX_EPOCH + Duration::from_secs(body.get_u32().into());
+        let peer = PeerEntry {
+            router_id: Ipv4Addr::UNSPECIFIED,
+            addr: addr(body),
+            asn: body.get_u16().into(),
+        };
+        let attributes_len = body.get_u16() as usize;
+        if body.len() < attributes_len {
+            return Err(malformed("truncated TABLE_DUMP record"));
+        }
+
+        let mut data = body.split_to(attributes_len);
+        // TABLE_DUMP predates 4 octet ASNs
+        let two_octet_as = ParserConfig {
+            four_octet_as: false,
+            ..ParserConfig::default()
+        };
+        let mut attributes = vec![];
+        while !data.is_empty() {
+            // Writers differ on whether MP_REACH_NLRI is abbreviated as in TABLE_DUMP_V2
+            let abbreviated = match data.get(1) {
+                Some(&MP_REACH_NLRI) => {
+                    let mut abbreviated = data.clone();
+                    decode_mp_reach(&mut abbreviated, afi, Safi::Unicast)
+                        .map(|attribute| (attribute, abbreviated))
+                }
+                _ => None,
+            };
+            let attribute = match abbreviated {
+                Some((attribute, rest)) => {
+                    data = rest;
+                    attribute
+                }
+                None => PathAttribute::decode(&mut data, &two_octet_as)
+                    .map_err(|_| malformed("malformed path attribute"))?,
+            };
+            attributes.push(attribute);
+        }
+        merge_as4_attributes(&mut attributes, &two_octet_as);
+
+        Ok(TableDump {
+            view,
+            sequence,
+            prefix,
+            peer,
+            entry: RibEntry {
+                peer_index: 0,
+                originated,
+                path_id: None,
+                attributes,
+            },
+        })
+    }
+}
@@ -0,0 +1,329 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::MrtError;
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{
+    AttributeType, AttributeValue, MpReachNlri, PathAttribute, PathAttributeFlags,
+};
+use crate::update_message::{IpAddrPrefix, ParserConfig};
+
+/// TABLE_DUMP_V2 subtypes
+pub(super) const PEER_INDEX_TABLE: u16 = 1;
+pub(super) const RIB_IPV4_UNICAST: u16 = 2;
+pub(super) const RIB_IPV4_MULTICAST: u16 = 3;
+pub(super) const RIB_IPV6_UNICAST: u16 = 4;
+pub(super) const RIB_IPV6_MULTICAST: u16 = 5;
+pub(super) const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+pub(super) const RIB_IPV4_MULTICAST_ADDPATH: u16 = 9;
+pub(super) const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+pub(super) const RIB_IPV6_MULTICAST_ADDPATH: u16 = 11;
+
+/// Peer type bits of PEER_INDEX_TABLE entries
+const PEER_IPV6: u8 = 0x01;
+const PEER_AS4: u8 = 0x02;
+
+pub(super) const MP_REACH_NLRI: u8 = 14;
+
+/// The peers that the RIB records of a dump refer to by index, which comes first in the dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIndexTable {
+    pub collector_id: Ipv4Addr,
+    pub view_name: String,
+    pub peers: Vec<PeerEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerEntry {
+    pub router_id: Ipv4Addr,
+    pub addr: IpAddr,
+    pub asn: u32,
+}
+
+/// The routes of every peer for a prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct RibRecord {
+    /// Counts up from 0 through the RIB records of a dump
+    pub sequence: u32,
+    pub prefix: IpAddrPrefix,
+    pub safi: Safi,
+    pub entries: Vec<RibEntry>,
+}
+
+/// A peer's route for the prefix of a [`RibRecord`].
+///
+/// MP_REACH_NLRI holds the family of the record and the next hop, without prefixes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RibEntry {
+    /// Into the peers of the [`PeerIndexTable`]
+    pub peer_index: u16,
+    /// When the route was received
+    pub originated: SystemTime,
+    /// The ADD-PATH path identifier, in records of the `_ADDPATH` subtypes (RFC 8050)
+    pub path_id: Option<u32>,
+    pub attributes: Vec<PathAttribute>,
+}
+
+impl PeerIndexTable {
+    pub(super) fn decode(body: &mut Bytes, offset: u64) -> Result<Self, MrtError> {
+        let malformed = |reason| MrtError::Malformed { offset, reason };
+        if body.len() < 4 + 2 {
+            return Err(malformed("truncated peer index table"));
+        }
+        let collector_id = Ipv4Addr::from(body.get_u32());
+        let name_len = body.get_u16() as usize;
+        if body.len() < name_len + 2 {
+            return Err(malformed("truncated peer index table"));
+        }
+        let view_name = String::from_utf8_lossy(&body.split_to(name_len)).into_owned();
+
+        let count = body.get_u16();
+        // No more than the smallest entries, of 11 octets, would fill
+        let mut peers = Vec::with_capacity((count as usize).min(body.len() / 11));
+        for _ in 0..count {
+            if body.is_empty() {
+                return Err(malformed("truncated peer entry"));
+            }
+            let peer_type = body.get_u8();
+            let addr_len = if peer_type & PEER_IPV6 != 0 { 16 } else { 4 };
+            let asn_len = if peer_type & PEER_AS4 != 0 { 4 } else { 2 };
+            if body.len() < 4 + addr_len + asn_len {
+                return Err(malformed("truncated peer entry"));
+            }
+            let router_id = Ipv4Addr::from(body.get_u32());
+            let addr = match addr_len {
+                4 => IpAddr::V4(Ipv4Addr::from(body.get_u32())),
+                _ => IpAddr::V6(Ipv6Addr::from(body.get_u128())),
+            };
+            let asn = match asn_len {
+                4 => body.get_u32(),
+                _ => body.get_u16().into(),
+            };
+            peers.push(PeerEntry {
+                router_id,
+                addr,
+                asn,
+            });
+        }
+        Ok(PeerIndexTable {
+            collector_id,
+            view_name,
+            peers,
+        })
+    }
+
+    pub(super) fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.collector_id.to_bits());
+        buf.put_u16(self.view_name.len() as u16);
+        buf.put_slice(self.view_name.as_bytes());
+        buf.put_u16(self.peers.len() as u16);
+        for peer in &self.peers {
+            match peer.addr {
+                IpAddr::V4(addr) => {
+                    buf.put_u8(PEER_AS4);
+                    buf.put_u32(peer.router_id.to_bits());
+                    buf.put_u32(addr.to_bits());
+                }
+                IpAddr::V6(addr) => {
+                    buf.put_u8(PEER_AS4 | PEER_IPV6);
+                    buf.put_u32(peer.router_id.to_bits());
+                    buf.put_u128(addr.to_bits());
+                }
+            }
+            buf.put_u32(peer.asn);
+        }
+    }
+}
+
+impl RibRecord {
+    /// The subtype of records for the family, `None` for families other than IPv4 and IPv6
+    /// unicast and multicast
+    pub(super) fn subtype(afi: Afi, safi: Safi) -> Option<u16> {
+        match (afi, safi) {
+            (Afi::Ipv4, Safi::Unicast) => Some(RIB_IPV4_UNICAST),
+            (Afi::Ipv4, Safi::Multicast) => Some(RIB_IPV4_MULTICAST),
+            (Afi::Ipv6, Safi::Unicast) => Some(RIB_IPV6_UNICAST),
+            (Afi::Ipv6, Safi::Multicast) => Some(RIB_IPV6_MULTICAST),
+            _ => None,
+        }
+    }
+
+    pub(super) fn decode(subtype: u16, body: &mut Bytes, offset: u64) -> Result<Self, MrtError> {
+        let malformed = |reason| MrtError::Malformed { offset, reason };
+        let (afi, safi) = match subtype {
+            RIB_IPV4_UNICAST | RIB_IPV4_UNICAST_ADDPATH => (Afi::Ipv4, Safi::Unicast),
+            RIB_IPV4_MULTICAST | RIB_IPV4_MULTICAST_ADDPATH => (Afi::Ipv4, Safi::Multicast),
+            RIB_IPV6_UNICAST | RIB_IPV6_UNICAST_ADDPATH => (Afi::Ipv6, Safi::Unicast),
+            _ => (Afi::Ipv6, Safi::Multicast),
+        };
+        let add_path = subtype >= RIB_IPV4_UNICAST_ADDPATH;
+        let addr_len = if afi == Afi::Ipv4 { 4 } else { 16 };
+
+        if body.len() < 4 + 1 {
+            return Err(malformed("truncated RIB record"));
+        }
+        let sequence = body.get_u32();
+        let prefix_len = 1 + (body[0] as usize).div_ceil(8);
+        if body.len() < prefix_len + 2 {
+            return Err(malformed("truncated RIB record"));
+        }
+        let prefix = IpAddrPrefix::decode_stream(
+            &mut body.split_to(prefix_len),
+            addr_len,
+            false,
+            &ParserConfig::default(),
+        )
+        .map_err(|_| malformed("invalid prefix"))?
+        .remove(0);
+
+        let count = body.get_u16();
+        let path_id_len = if add_path { 4 } else { 0 };
+        let mut entries =
+            Vec::with_capacity((count as usize).min(body.len() / (2 + 4 + path_id_len + 2)));
+        for _ in 0..count {
+            if body.len() < 2 + 4 + path_id_len + 2 {
+                return Err(malformed("truncated RIB entry"));
+            }
+            let peer_index = body.get_u16();
+            let originated = SystemTime::UNIX_EPOCH + Duration::from_secs(body.get_u32().into());
+            let path_id = add_path.then(|| body.get_u32());
+            let attributes_len = body.get_u16() as usize;
+            if body.len() < attributes_len {
+                return Err(malformed("truncated RIB entry"));
+            }
+            let mut data = body.split_to(attributes_len);
+            let mut attributes = vec![];
+            while !data.is_empty() {
+                let attribute = match data.get(1) {
+                    Some(&MP_REACH_NLRI) => decode_mp_reach(&mut data, afi, safi),
+                    _ => PathAttribute::try_decode(&mut data).ok(),
+                };
+                attributes.push(attribute.ok_or(malformed("malformed path attribute"))?);
+            }
+            entries.push(RibEntry {
+                peer_index,
+                originated,
+                path_id,
+                attributes,
+            });
+        }
+        Ok(RibRecord {
+            sequence,
+            prefix,
+            safi,
+            entries,
+        })
+    }
+}
+
+/// Encodes what precedes the entries of a RIB record
+pub(super) fn encode_rib_header(
+    sequence: u32,
+    prefix: &IpAddrPrefix,
+    entries: u16,
+    buf: &mut BytesMut,
+) {
+    buf.put_u32(sequence);
+    prefix.encode(buf);
+    buf.put_u16(entries);
+}
+
+pub(super) fn encode_rib_entry(
+    peer_index: u16,
+    originated: SystemTime,
+    attributes: &[PathAttribute],
+    buf: &mut BytesMut,
+) {
+    buf.put_u16(peer_index);
+    let originated = originated
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    buf.put_u32(originated.as_secs() as u32);
+    let length_at = buf.len();
+    buf.put_u16(0);
+    for attribute in attributes {
+        match &attribute.value {
+            AttributeValue::MpReachNlri(mp_reach) => PathAttribute {
+                flags: attribute.flags,
+                type_code: AttributeType::MpReachNlri,
+                value: AttributeValue::Unknown(mp_reach_next_hop(mp_reach)),
+            }
+            .encode(buf),
+            // Withdrawals have no place in a RIB
+            AttributeValue::MpUnreachNlri(_) => {}
+            _ => attribute.encode(buf),
+        }
+    }
+    let length = (buf.len() - length_at - 2) as u16;
+    buf[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Decodes the abbreviated MP_REACH_NLRI of RIB entries, only the next hop (RFC 6396 section
+/// 4.3.4)
+pub(super) fn decode_mp_reach(data: &mut Bytes, afi: Afi, safi: Safi) -> Option<PathAttribute> {
+    let flags = PathAttributeFlags::from_byte(*data.first()?);
+    let header_len = if flags.extended_length { 4 } else { 3 };
+    if data.len() < header_len {
+        return None;
+    }
+    let length = match header_len {
+        4 => u16::from_be_bytes([data[2], data[3]]) as usize,
+        _ => data[2] as usize,
+    };
+    if data.len() < header_len + length {
+        return None;
+    }
+    data.advance(header_len);
+    let mut value = data.split_to(length);
+    if value.is_empty() {
+        return None;
+    }
+
+    let next_hop_len = value.get_u8().into();
+    if value.len() != next_hop_len {
+        return None;
+    }
+    let (next_hop, link_local) = match next_hop_len {
+        4 => (IpAddr::V4(Ipv4Addr::from(value.get_u32())), None),
+        16 => (IpAddr::V6(Ipv6Addr::from(value.get_u128())), None),
+        32 => (
+            IpAddr::V6(Ipv6Addr::from(value.get_u128())),
+            Some(Ipv6Addr::from(value.get_u128())),
+        ),
+        _ => return None,
+    };
+    Some(PathAttribute {
+        flags,
+        type_code: AttributeType::MpReachNlri,
+        value: AttributeValue::MpReachNlri(Box::new(MpReachNlri {
+            afi,
+            safi,
+            next_hop,
+            link_local,
+            nlri: vec![],
+        })),
+    })
+}
+
+/// The value of an abbreviated MP_REACH_NLRI
+fn mp_reach_next_hop(mp_reach: &MpReachNlri) -> Bytes {
+    let mut value = BytesMut::with_capacity(1 + 32);
+    match (mp_reach.next_hop, mp_reach.link_local) {
+        (IpAddr::V4(next_hop), _) => {
+            value.put_u8(4);
+            value.put_u32(next_hop.to_bits());
+        }
+        (IpAddr::V6(next_hop), None) => {
+            value.put_u8(16);
+            value.put_u128(next_hop.to_bits());
+        }
+        (IpAddr::V6(next_hop), Some(link_local)) => {
+            value.put_u8(32);
+            value.put_u128(next_hop.to_bits());
+            value.put_u128(link_local.to_bits());
+        }
+    }
+    value.freeze()
+}
@@ -0,0 +1,462 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+
+use bytes::{BufMut, BytesMut};
+
+use super::bgp4mp::{
+    MESSAGE_AS4, MESSAGE_AS4_ADDPATH, MESSAGE_AS4_LOCAL, MESSAGE_AS4_LOCAL_ADDPATH,
+    STATE_CHANGE_AS4,
+};
+use super::table_dump_v2::{self, PEER_INDEX_TABLE, PeerEntry, PeerIndexTable, RibRecord};
+use super::{BGP4MP, BGP4MP_ET, FsmState, PeerInfo, TABLE_DUMP_V2};
+use crate::bgp_message::BgpMessage;
+use crate::header::{BgpHeader, BgpMessageType};
+use crate::journal::Direction;
+use crate::rib::{RibIn, RibPeer, announced_keys, withdrawn_keys};
+use crate::update_message::UpdateMessage;
+
+/// Writes MRT files that tools such as bgpdump and bgpkit read, from messages exchanged with
+/// peers and from Adj-RIB-Ins.
+///
+/// Messages and state changes are written as BGP4MP with 4 octet ASNs, timestamped to the
+/// second or, with [`MrtWriter::extended_timestamps`], as BGP4MP_ET to the microsecond.
+/// UPDATEs whose prefixes carry ADD-PATH path identifiers get the `_ADDPATH` subtypes. RIBs
+/// are dumped as TABLE_DUMP_V2, which has no such variant.
+pub struct MrtWriter<W> {
+    writer: W,
+    extended_timestamps: bool,
+}
+
+impl MrtWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(MrtWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> MrtWriter<W> {
+    pub fn new(writer: W) -> Self {
+        MrtWriter {
+            writer,
+            extended_timestamps: false,
+        }
+    }
+
+    /// Writes BGP4MP_ET records, with the microseconds of timestamps
+    pub fn extended_timestamps(mut self, extended_timestamps: bool) -> Self {
+        self.extended_timestamps = extended_timestamps;
+        self
+    }
+
+    /// Records an UPDATE received from `peer` at `at`
+    pub fn write_update(
+        &mut self,
+        peer: &PeerInfo,
+        at: SystemTime,
+        update: &UpdateMessage,
+    ) -> io::Result<()> {
+        let body = update.to_bytes();
+        let header = BgpHeader {
+            marker: BgpHeader::MARKER_VALUE,
+            length: BgpHeader::MIN_LEN + body.len() as u16,
+            message_type: BgpMessageType::Update,
+        };
+        let subtype = match has_path_ids(update) {
+            true => MESSAGE_AS4_ADDPATH,
+            false => MESSAGE_AS4,
+        };
+        self.write_bgp4mp(peer, at, subtype, |buf| {
+            buf.put_slice(&header.to_bytes());
+            buf.put_slice(&body);
+        })
+    }
+
+    /// Records a message received from `peer` or, as BGP4MP_MESSAGE_AS4_LOCAL, sent to it
+    pub fn write_message(
+        &mut self,
+        peer: &PeerInfo,
+        at: SystemTime,
+        direction: Direction,
+        message: &BgpMessage,
+    ) -> io::Result<()> {
+        let add_path = matches!(message, BgpMessage::Update(update) if has_path_ids(update));
+        let subtype = match (direction, add_path) {
+            (Direction::Received, false) => MESSAGE_AS4,
+            (Direction::Received, true) => MESSAGE_AS4_ADDPATH,
+            (Direction::Sent, false) => MESSAGE_AS4_LOCAL,
+            (Direction::Sent, true) => MESSAGE_AS4_LOCAL_ADDPATH,
+        };
+        self.write_bgp4mp(peer, at, subtype, |buf| buf.put_slice(&message.to_bytes()))
+    }
+
+    pub fn write_state_change(
+        &mut self,
+        peer: &PeerInfo,
+        at: SystemTime,
+        old: FsmState,
+        new: FsmState,
+    ) -> io::Result<()> {
+        self.write_bgp4mp(peer, at, STATE_CHANGE_AS4, |buf| {
+            buf.put_u16(old.into());
+            buf.put_u16(new.into());
+        })
+    }
+
+    /// Dumps the routes of `peers` as of `at`: a PEER_INDEX_TABLE listing the peers followed
+    /// by a RIB record per prefix, IPv4 unicast first and in prefix order.
+    ///
+    /// Entries are timestamped with when their route last changed. Routes of families other
+    /// than IPv4 and IPv6 unicast and multicast are left out, and routes a peer announced with
+    /// several ADD-PATH path identifiers become one entry each.
+    pub fn dump_rib(
+        &mut self,
+        at: SystemTime,
+        collector_id: Ipv4Addr,
+        peers: &[(RibPeer, &RibIn)],
+    ) -> io::Result<()> {
+        let table = PeerIndexTable {
+            collector_id,
+            view_name: String::new(),
+            peers: peers
+                .iter()
+                .map(|(peer, _)| PeerEntry {
+                    router_id: peer.router_id,
+                    addr: peer.addr,
+                    asn: peer.asn,
+                })
+                .collect(),
+        };
+        let mut body = BytesMut::new();
+        table.encode(&mut body);
+        self.write_record(at, TABLE_DUMP_V2, PEER_INDEX_TABLE, &body)?;
+
+        let now = Instant::now();
+        // Entries of each prefix by subtype, so IPv4 unicast comes first
+        let mut prefixes = BTreeMap::new();
+        for (index, (_, rib)) in peers.iter().enumerate() {
+            for (key, attributes, age) in rib.iter_ages() {
+                let Some(subtype) = RibRecord::subtype(key.prefix.afi(), key.safi) else {
+                    continue;
+                };
+                let originated = at.checked_sub(age.age(now)).unwrap_or(at);
+                prefixes
+                    .entry((subtype, &key.prefix))
+                    .or_insert_with(Vec::new)
+                    .push((index as u16, originated, attributes));
+            }
+        }
+        for (sequence, ((subtype, prefix), entries)) in prefixes.into_iter().enumerate() {
+            body.clear();
+            table_dump_v2::encode_rib_header(
+                sequence as u32,
+                prefix,
+                entries.len() as u16,
+                &mut body,
+            );
+            for (peer_index, originated, attributes) in entries {
+                table_dump_v2::encode_rib_entry(peer_index, originated, attributes, &mut body);
+            }
+            self.write_record(at, TABLE_DUMP_V2, subtype, &body)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_bgp4mp(
+        &mut self,
+        peer: &PeerInfo,
+        at: SystemTime,
+        subtype: u16,
+        message: impl FnOnce(&mut BytesMut),
+    ) -> io::Result<()> {
+        let mut body = BytesMut::new();
+        body.put_u32(peer.peer_asn);
+        body.put_u32(peer.local_asn);
+        body.put_u16(peer.interface_index);
+        match (peer.peer_addr, peer.local_addr) {
+            (IpAddr::V4(peer_addr), IpAddr::V4(local_addr)) => {
+                body.put_u16(1);
+                body.put_u32(peer_addr.to_bits());
+                body.put_u32(local_addr.to_bits());
+            }
+            (IpAddr::V6(peer_addr), IpAddr::V6(local_addr)) => {
+                body.put_u16(2);
+                body.put_u128(peer_addr.to_bits());
+                body.put_u128(local_addr.to_bits());
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "peer and local addresses of different families",
+                ));
+            }
+        }
+        message(&mut body);
+
+        let mrt_type = if self.extended_timestamps {
+            BGP4MP_ET
+        } else {
+            BGP4MP
+        };
+        self.write_record(at, mrt_type, subtype, &body)
+    }
+
+    fn write_record(
+        &mut self,
+        at: SystemTime,
+        mrt_type: u16,
+        subtype: u16,
+        body: &[u8],
+    ) -> io::Result<()> {
+        let since_epoch = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let extended = mrt_type == BGP4MP_ET;
+        let mut header = [0; 16];
+        let mut fields = &mut header[..];
+        fields.put_u32(since_epoch.as_secs() as u32);
+        fields.put_u16(mrt_type);
+        fields.put_u16(subtype);
+        fields.put_u32(body.len() as u32 + if extended { 4 } else { 0 });
+        fields.put_u32(since_epoch.subsec_micros());
+        let header_len = if extended { 16 } else { 12 };
+        self.writer.write_all(&header[..header_len])?;
+        self.writer.write_all(body)
+    }
+}
+
+/// Whether the prefixes of an UPDATE are encoded with path identifiers
+fn has_path_ids(update: &UpdateMessage) -> bool {
+    announced_keys(update)
+        .chain(withdrawn_keys(update))
+        .any(|key| key.path_id.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::address_family::{Afi, Safi};
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::mrt::{Bgp4mpBody, MrtBody, MrtReader, MrtRecord};
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    fn read(bytes: &[u8]) -> Vec<MrtRecord> {
+        MrtReader::new(bytes).map(Result::unwrap).collect()
+    }
+
+    fn peer() -> PeerInfo {
+        PeerInfo {
+            peer_asn: 4_200_000_000,
+            local_asn: 65000,
+            interface_index: 0,
+            peer_addr: "2001:db8::1".parse().unwrap(),
+            local_addr: "2001:db8::fe".parse().unwrap(),
+        }
+    }
+
+    fn update(prefix: &str, as_path: &[u32]) -> UpdateMessage {
+        let prefix: IpAddrPrefix = prefix.parse().unwrap();
+        let next_hop = match prefix.afi() {
+            Afi::Ipv4 => "192.0.2.1",
+            _ => "2001:db8::1",
+        };
+        UpdateMessageBuilder::new()
+            .announce(prefix)
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: as_path.to_vec().into(),
+                }]
+                .into(),
+            })
+            .next_hop(next_hop.parse().unwrap())
+            .build()
+    }
+
+    /// Rewriting the state change and the first UPDATE of the reader's fixture gives back the
+    /// same bytes
+    #[test]
+    fn test_golden_records() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bgp4mp_updates.mrt");
+        let bytes = std::fs::read(path).unwrap();
+        let mut reader = MrtReader::new(&bytes[..]);
+        let mut writer = MrtWriter::new(vec![]);
+        for _ in 0..2 {
+            let record = reader.next().unwrap().unwrap();
+            let MrtBody::Bgp4mp(bgp4mp) = record.body else {
+                panic!("{record:?} isn't a BGP4MP record");
+            };
+            match bgp4mp.body {
+                Bgp4mpBody::StateChange { old, new } => writer
+                    .write_state_change(&bgp4mp.peer, record.header.timestamp, old, new)
+                    .unwrap(),
+                Bgp4mpBody::Message {
+                    message: BgpMessage::Update(update),
+                    ..
+                } => writer
+                    .write_update(&bgp4mp.peer, record.header.timestamp, &update)
+                    .unwrap(),
+                body => panic!("unexpected {body:?}"),
+            }
+        }
+        let written = writer.into_inner();
+        assert_eq!(written, bytes[..reader.offset() as usize]);
+    }
+
+    #[test]
+    fn test_bgp4mp_round_trip() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        let mut writer = MrtWriter::new(vec![]).extended_timestamps(true);
+        let update = update("2001:db8:1000::/36", &[4_200_000_000, 64500]);
+        writer
+            .write_state_change(&peer(), at, FsmState::OpenConfirm, FsmState::Established)
+            .unwrap();
+        writer.write_update(&peer(), at, &update).unwrap();
+        writer
+            .write_message(&peer(), at, Direction::Sent, &BgpMessage::Keepalive)
+            .unwrap();
+        let records = read(&writer.into_inner());
+
+        let bodies: Vec<Bgp4mpBody> = records
+            .into_iter()
+            .map(|record| {
+                assert_eq!(record.header.mrt_type, BGP4MP_ET);
+                assert_eq!(record.header.timestamp, at);
+                let MrtBody::Bgp4mp(bgp4mp) = record.body else {
+                    panic!("{record:?} isn't a BGP4MP record");
+                };
+                assert_eq!(bgp4mp.peer, peer());
+                assert!(bgp4mp.four_octet_as);
+                bgp4mp.body
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                Bgp4mpBody::StateChange {
+                    old: FsmState::OpenConfirm,
+                    new: FsmState::Established
+                },
+                Bgp4mpBody::Message {
+                    direction: Direction::Received,
+                    message: BgpMessage::Update(update),
+                },
+                Bgp4mpBody::Message {
+                    direction: Direction::Sent,
+                    message: BgpMessage::Keepalive,
+                },
+            ]
+        );
+
+        // Without extended timestamps the microseconds are dropped
+        let mut writer = MrtWriter::new(vec![]);
+        writer
+            .write_message(&peer(), at, Direction::Received, &BgpMessage::Keepalive)
+            .unwrap();
+        let record = &read(&writer.into_inner())[0];
+        assert_eq!(record.header.mrt_type, BGP4MP);
+        assert_eq!(
+            record.header.timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        let mut mixed = peer();
+        mixed.local_addr = "192.0.2.254".parse().unwrap();
+        let mut writer = MrtWriter::new(vec![]);
+        let err = writer
+            .write_message(&mixed, at, Direction::Received, &BgpMessage::Keepalive)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_dump_rib_round_trip() {
+        let peers = [
+            RibPeer {
+                addr: "192.0.2.1".parse().unwrap(),
+                asn: 64500,
+                router_id: Ipv4Addr::new(192, 0, 2, 1),
+                external: true,
+            },
+            RibPeer {
+                addr: "2001:db8::2".parse().unwrap(),
+                asn: 4_200_000_000,
+                router_id: Ipv4Addr::new(192, 0, 2, 2),
+                external: true,
+            },
+        ];
+        let mut first = RibIn::new();
+        first.apply(&update("203.0.113.0/24", &[64500, 64510]));
+        first.apply(&update("198.51.100.0/24", &[64500]));
+        first.apply(&update("2001:db8:1000::/36", &[64500, 64520]));
+        let mut second = RibIn::new();
+        second.apply(&update("203.0.113.0/24", &[4_200_000_000, 64510]));
+
+        let at = SystemTime::now();
+        let mut writer = MrtWriter::new(vec![]);
+        writer
+            .dump_rib(
+                at,
+                Ipv4Addr::new(198, 51, 100, 254),
+                &[(peers[0], &first), (peers[1], &second)],
+            )
+            .unwrap();
+        let records = read(&writer.into_inner());
+        assert_eq!(records.len(), 1 + 3);
+
+        let MrtBody::PeerIndexTable(table) = &records[0].body else {
+            panic!("{:?} isn't a peer index table", records[0]);
+        };
+        assert_eq!(table.collector_id, Ipv4Addr::new(198, 51, 100, 254));
+        assert_eq!(
+            table.peers,
+            peers
+                .iter()
+                .map(|peer| PeerEntry {
+                    router_id: peer.router_id,
+                    addr: peer.addr,
+                    asn: peer.asn
+                })
+                .collect::<Vec<_>>()
+        );
+
+        let ribs = [&first, &second];
+        let mut dumped = vec![];
+        for (sequence, record) in records[1..].iter().enumerate() {
+            let MrtBody::Rib(rib) = &record.body else {
+                panic!("{record:?} isn't a RIB record");
+            };
+            assert_eq!((rib.sequence, rib.safi), (sequence as u32, Safi::Unicast));
+            for entry in &rib.entries {
+                let peer = &peers[entry.peer_index as usize];
+                let attributes = ribs[entry.peer_index as usize].lookup(&rib.prefix).unwrap();
+                assert_eq!(entry.attributes[..], attributes[..]);
+                assert!(entry.originated <= at);
+                assert!(entry.originated > at - Duration::from_secs(5));
+                dumped.push((rib.prefix.to_string(), peer.asn));
+            }
+        }
+        assert_eq!(
+            dumped,
+            vec![
+                ("198.51.100.0/24".to_string(), 64500),
+                ("203.0.113.0/24".to_string(), 64500),
+                ("203.0.113.0/24".to_string(), 4_200_000_000),
+                ("2001:db8:1000::/36".to_string(), 64500),
+            ]
+        );
+    }
+}
@@ -1,21 +1,39 @@
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
 
+use crate::header::BgpHeader;
+use crate::spans;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct NotificationMessage {
-    error_codes: NotificationErrorCode,
-    data: Vec<u8>,
+    pub error_codes: NotificationErrorCode,
+    pub data: Vec<u8>,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum NotificationErrorCode {
     Header(HeaderSubErr),
     OpenMessage(OpenMessageSubErr),
     UpdateMessage(UpdateMessageSubErr),
     HoldTimeExpired,
     FiniteStateMachine,
-    Cease,
+    Cease(CeaseSubErr),
     Unknown(u8, u8),
 }
 
 #[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum HeaderSubErr {
     ConnectionNotSyncronized = 1,
     BadMessageLength = 2,
@@ -23,6 +41,12 @@ pub enum HeaderSubErr {
 }
 
 #[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum OpenMessageSubErr {
     UnsupportedVersionNumber = 1,
     BadPeerAS = 2,
@@ -30,9 +54,39 @@ pub enum OpenMessageSubErr {
     UnsupportedOptionalParameter = 4,
     AuthenticationFailure = 5,
     UnacceptableHoldTime = 6,
+    /// RFC 5492
+    UnsupportedCapability = 7,
+    /// RFC 9234
+    RoleMismatch = 8,
+}
+
+/// Cease subcodes (RFC 4486, RFC 8538)
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CeaseSubErr {
+    MaximumNumberOfPrefixesReached = 1,
+    AdministrativeShutdown = 2,
+    PeerDeconfigured = 3,
+    AdministrativeReset = 4,
+    ConnectionRejected = 5,
+    OtherConfigurationChange = 6,
+    ConnectionCollisionResolution = 7,
+    OutOfResources = 8,
+    HardReset = 9,
 }
 
 #[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum UpdateMessageSubErr {
     MalformedAttributeList = 1,
     UnrecognizedWellKnownAttribute = 2,
@@ -47,46 +101,115 @@ pub enum UpdateMessageSubErr {
     MalformedAsPath = 11,
 }
 
+/// Why a NOTIFICATION message failed to decode
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NotificationParseError {
+    #[error("NOTIFICATION message body length {0} is shorter than 2")]
+    MessageLength(usize),
+    /// An error subcode not defined for its error code, see [`NotificationErrorCode::Unknown`]
+    #[error("Unknown subcode {subcode} of error code {code}")]
+    UnknownSubcode { code: u8, subcode: u8 },
+}
+
 impl NotificationMessage {
     const MIN_LEN: usize = 21;
 
-    fn try_decode(data: &mut Bytes) -> Result<Self, String> {
-        if data.len() < Self::MIN_LEN {
-            return Err("Insufficient data for notification message".to_string());
+    pub fn new(error_codes: NotificationErrorCode, data: Vec<u8>) -> Self {
+        NotificationMessage { error_codes, data }
+    }
+
+    pub fn try_decode(data: &mut Bytes) -> Result<Self, NotificationParseError> {
+        if data.len() < Self::MIN_LEN - BgpHeader::MIN_LEN as usize {
+            return Err(NotificationParseError::MessageLength(data.len()));
         }
 
         let err_code = data.get_u8();
+        spans::consumed(data, 1, || format!("error code: {err_code}"));
         let err_sub_code = data.get_u8();
+        spans::consumed(data, 1, || format!("error subcode: {err_sub_code}"));
 
+        // Subcodes keep being added, and 0 is the unspecific one of every code, so unrecognised
+        // ones are kept rather than rejected
+        let unknown = |_| NotificationErrorCode::Unknown(err_code, err_sub_code);
         let notification_err_code = match err_code {
-            1 => NotificationErrorCode::Header(HeaderSubErr::try_from(err_sub_code)?),
-            2 => NotificationErrorCode::OpenMessage(OpenMessageSubErr::try_from(err_sub_code)?),
-            3 => NotificationErrorCode::UpdateMessage(UpdateMessageSubErr::try_from(err_sub_code)?),
+            1 => HeaderSubErr::try_from(err_sub_code)
+                .map_or_else(unknown, NotificationErrorCode::Header),
+            2 => OpenMessageSubErr::try_from(err_sub_code)
+                .map_or_else(unknown, NotificationErrorCode::OpenMessage),
+            3 => UpdateMessageSubErr::try_from(err_sub_code)
+                .map_or_else(unknown, NotificationErrorCode::UpdateMessage),
             4 => NotificationErrorCode::HoldTimeExpired,
             5 => NotificationErrorCode::FiniteStateMachine,
-            6 => NotificationErrorCode::Cease,
+            6 => CeaseSubErr::try_from(err_sub_code)
+                .map_or_else(unknown, NotificationErrorCode::Cease),
             _ => NotificationErrorCode::Unknown(err_code, err_sub_code),
         };
 
-        todo!()
+        let len = data.len();
+        let notification_data = data.copy_to_bytes(len).to_vec();
+        if len > 0 {
+            spans::consumed(data, len, || "data".into());
+        }
+        Ok(NotificationMessage {
+            error_codes: notification_err_code,
+            data: notification_data,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::with_capacity(2 + self.data.len());
+
+        buffer.put_u8(self.error_codes.code());
+        buffer.put_u8(self.error_codes.subcode());
+        buffer.put_slice(&self.data);
+
+        buffer.freeze()
+    }
+}
+
+impl NotificationErrorCode {
+    pub fn code(&self) -> u8 {
+        match self {
+            NotificationErrorCode::Header(_) => 1,
+            NotificationErrorCode::OpenMessage(_) => 2,
+            NotificationErrorCode::UpdateMessage(_) => 3,
+            NotificationErrorCode::HoldTimeExpired => 4,
+            NotificationErrorCode::FiniteStateMachine => 5,
+            NotificationErrorCode::Cease(_) => 6,
+            NotificationErrorCode::Unknown(code, _) => *code,
+        }
+    }
+
+    pub fn subcode(&self) -> u8 {
+        match self {
+            NotificationErrorCode::Header(sub_err) => *sub_err as u8,
+            NotificationErrorCode::OpenMessage(sub_err) => *sub_err as u8,
+            NotificationErrorCode::UpdateMessage(sub_err) => *sub_err as u8,
+            NotificationErrorCode::Cease(sub_err) => *sub_err as u8,
+            NotificationErrorCode::Unknown(_, subcode) => *subcode,
+            _ => 0,
+        }
     }
 }
 
 impl TryFrom<u8> for HeaderSubErr {
-    type Error = String;
+    type Error = NotificationParseError;
 
-    fn try_from(value: u8) -> Result<Self, String> {
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             1 => Ok(Self::ConnectionNotSyncronized),
             2 => Ok(Self::BadMessageLength),
             3 => Ok(Self::BadMessageType),
-            _ => Err(format!("Unknown header sub error code {}", value)),
+            _ => Err(NotificationParseError::UnknownSubcode {
+                code: 1,
+                subcode: value,
+            }),
         }
     }
 }
 
 impl TryFrom<u8> for OpenMessageSubErr {
-    type Error = String;
+    type Error = NotificationParseError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
@@ -96,13 +219,40 @@ impl TryFrom<u8> for OpenMessageSubErr {
             4 => Ok(Self::UnsupportedOptionalParameter),
             5 => Ok(Self::AuthenticationFailure),
             6 => Ok(Self::UnacceptableHoldTime),
-            _ => Err(format!("Unknown OPEN message error code {}", value)),
+            7 => Ok(Self::UnsupportedCapability),
+            8 => Ok(Self::RoleMismatch),
+            _ => Err(NotificationParseError::UnknownSubcode {
+                code: 2,
+                subcode: value,
+            }),
+        }
+    }
+}
+
+impl TryFrom<u8> for CeaseSubErr {
+    type Error = NotificationParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::MaximumNumberOfPrefixesReached),
+            2 => Ok(Self::AdministrativeShutdown),
+            3 => Ok(Self::PeerDeconfigured),
+            4 => Ok(Self::AdministrativeReset),
+            5 => Ok(Self::ConnectionRejected),
+            6 => Ok(Self::OtherConfigurationChange),
+            7 => Ok(Self::ConnectionCollisionResolution),
+            8 => Ok(Self::OutOfResources),
+            9 => Ok(Self::HardReset),
+            _ => Err(NotificationParseError::UnknownSubcode {
+                code: 6,
+                subcode: value,
+            }),
         }
     }
 }
 
 impl TryFrom<u8> for UpdateMessageSubErr {
-    type Error = String;
+    type Error = NotificationParseError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
@@ -117,7 +267,95 @@ impl TryFrom<u8> for UpdateMessageSubErr {
             9 => Ok(Self::OptionalAttributeError),
             10 => Ok(Self::InvalidNetworkField),
             11 => Ok(Self::MalformedAsPath),
-            _ => Err(format!("Unknown UPDATE message error code: {}", value)),
+            _ => Err(NotificationParseError::UnknownSubcode {
+                code: 3,
+                subcode: value,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notification_round_trip() {
+        let notification = NotificationMessage::new(
+            NotificationErrorCode::OpenMessage(OpenMessageSubErr::BadPeerAS),
+            vec![0xfd, 0xe9],
+        );
+
+        let mut data = notification.to_bytes();
+        assert_eq!(&data[..], &[2, 2, 0xfd, 0xe9]);
+        assert_eq!(NotificationMessage::try_decode(&mut data), Ok(notification));
+    }
+
+    #[test]
+    fn test_notification_hold_time_expired() {
+        let mut data = Bytes::from_static(&[4, 0]);
+        let notification = NotificationMessage::try_decode(&mut data).unwrap();
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::HoldTimeExpired
+        );
+        assert!(notification.data.is_empty());
+    }
+
+    #[test]
+    fn test_notification_cease_subcodes() {
+        let mut data = Bytes::from_static(&[6, 5]);
+        let notification = NotificationMessage::try_decode(&mut data).unwrap();
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::Cease(CeaseSubErr::ConnectionRejected)
+        );
+
+        let mut data = Bytes::from_static(&[6, 0]);
+        let notification = NotificationMessage::try_decode(&mut data).unwrap();
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::Unknown(6, 0)
+        );
+        assert_eq!(&notification.to_bytes()[..], &[6, 0]);
+    }
+
+    #[test]
+    fn test_notification_unknown_subcodes() {
+        let mut data = Bytes::from_static(&[2, 7]);
+        assert_eq!(
+            NotificationMessage::try_decode(&mut data)
+                .unwrap()
+                .error_codes,
+            NotificationErrorCode::OpenMessage(OpenMessageSubErr::UnsupportedCapability)
+        );
+
+        // Unspecific and not yet assigned subcodes are kept as they came
+        for (code, subcode) in [(2, 0), (3, 0), (1, 4), (2, 11), (3, 12)] {
+            let mut data = Bytes::from(vec![code, subcode, 0xab]);
+            let notification = NotificationMessage::try_decode(&mut data).unwrap();
+            assert_eq!(
+                notification.error_codes,
+                NotificationErrorCode::Unknown(code, subcode)
+            );
+            assert_eq!(&notification.to_bytes()[..], &[code, subcode, 0xab]);
         }
     }
+
+    #[test]
+    fn test_notification_errors() {
+        let mut data = Bytes::from_static(&[6]);
+        assert_eq!(
+            NotificationMessage::try_decode(&mut data),
+            Err(NotificationParseError::MessageLength(1))
+        );
+
+        assert_eq!(
+            HeaderSubErr::try_from(0),
+            Err(NotificationParseError::UnknownSubcode {
+                code: 1,
+                subcode: 0
+            })
+        );
+    }
 }
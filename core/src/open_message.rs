@@ -1,43 +1,224 @@
 use std::net::Ipv4Addr;
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
 
+use crate::capability::Capability;
+use crate::notification_message::OpenMessageSubErr;
+use crate::spans;
+use crate::validate::Validate;
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct OpenMessage {
-    version: u8,
-    my_autonomous_system: u16,
-    hold_time: u16,
-    bgp_id: Ipv4Addr,
-    optional_params: Vec<OptionalParam>,
+    pub version: u8,
+    pub my_autonomous_system: u16,
+    pub hold_time: u16,
+    pub bgp_id: Ipv4Addr,
+    pub optional_params: Vec<OptionalParam>,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct OptionalParam {
-    param_type: u8,
-    param_value: Vec<u8>,
+    pub param_type: u8,
+    pub param_value: Vec<u8>,
 }
 
 struct OptionalParamVec(Vec<OptionalParam>);
 
+/// Why an OPEN message, or the capabilities it carries, failed to decode
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum OpenParseError {
+    #[error("OPEN message body length {0} is shorter than {min}", min = OpenMessage::MIN_LEN)]
+    MessageLength(usize),
+    #[error("Optional parameters length {declared} exceeds the remaining {remaining}")]
+    ParamsLength { declared: u8, remaining: usize },
+    #[error("Truncated optional parameter header")]
+    TruncatedParam,
+    #[error("Optional parameter {param_type} length {length} exceeds the remaining {remaining}")]
+    ParamLength {
+        param_type: u8,
+        length: u8,
+        remaining: usize,
+    },
+    #[error("Truncated capability header")]
+    TruncatedCapability,
+    #[error("Capability {code} length {length} exceeds the remaining {remaining}")]
+    CapabilityLength {
+        code: u8,
+        length: u8,
+        remaining: usize,
+    },
+    /// With the value of the capability
+    #[error("Invalid ADD-PATH send/receive value")]
+    AddPath(Bytes),
+    #[error("Unsupported BGP version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unacceptable hold time {0}")]
+    UnacceptableHoldTime(u16),
+    #[error("Bad BGP identifier {0}")]
+    BadBgpIdentifier(Ipv4Addr),
+}
+
+impl OpenParseError {
+    /// The OPEN Message Error subcode of the NOTIFICATION answering the error, `None` for
+    /// malformed optional parameters, which no subcode covers
+    pub fn sub_err(&self) -> Option<OpenMessageSubErr> {
+        match self {
+            OpenParseError::MessageLength(_)
+            | OpenParseError::ParamsLength { .. }
+            | OpenParseError::TruncatedParam
+            | OpenParseError::ParamLength { .. } => None,
+            OpenParseError::TruncatedCapability
+            | OpenParseError::CapabilityLength { .. }
+            | OpenParseError::AddPath(_) => Some(OpenMessageSubErr::UnsupportedOptionalParameter),
+            OpenParseError::UnsupportedVersion(_) => {
+                Some(OpenMessageSubErr::UnsupportedVersionNumber)
+            }
+            OpenParseError::UnacceptableHoldTime(_) => {
+                Some(OpenMessageSubErr::UnacceptableHoldTime)
+            }
+            OpenParseError::BadBgpIdentifier(_) => Some(OpenMessageSubErr::BadBgpIdentifier),
+        }
+    }
+}
+
+impl OpenMessage {
+    pub const VERSION: u8 = 4;
+    pub const MIN_LEN: usize = 10;
+    /// Placeholder ASN used in the 2 octet field by speakers with a 4 octet ASN (RFC 6793)
+    pub const AS_TRANS: u16 = 23456;
+
+    /// Builds an OPEN advertising the given capabilities in a single Capabilities parameter
+    pub fn new(asn: u32, hold_time: u16, bgp_id: Ipv4Addr, capabilities: &[Capability]) -> Self {
+        let my_autonomous_system = u16::try_from(asn).unwrap_or(Self::AS_TRANS);
+
+        let mut optional_params = Vec::new();
+        if !capabilities.is_empty() {
+            let mut value = BytesMut::new();
+            for capability in capabilities {
+                capability.encode(&mut value);
+            }
+            optional_params.push(OptionalParam {
+                param_type: OptionalParam::CAPABILITIES,
+                param_value: value.to_vec(),
+            });
+        }
+
+        OpenMessage {
+            version: Self::VERSION,
+            my_autonomous_system,
+            hold_time,
+            bgp_id,
+            optional_params,
+        }
+    }
+
+    /// Collects the capabilities from every Capabilities optional parameter
+    pub fn capabilities(&self) -> Result<Vec<Capability>, OpenParseError> {
+        let mut capabilities = Vec::new();
+        for param in &self.optional_params {
+            if param.param_type == OptionalParam::CAPABILITIES {
+                let mut value = Bytes::copy_from_slice(&param.param_value);
+                capabilities.extend(Capability::decode_list(&mut value)?);
+            }
+        }
+        Ok(capabilities)
+    }
+
+    /// The sender's ASN, preferring the 4 octet AS capability over the 2 octet field
+    pub fn asn(&self) -> u32 {
+        self.capabilities()
+            .unwrap_or_default()
+            .iter()
+            .find_map(|capability| match capability {
+                Capability::FourOctetAs { asn } => Some(*asn),
+                _ => None,
+            })
+            .unwrap_or(self.my_autonomous_system as u32)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::with_capacity(Self::MIN_LEN);
+
+        buffer.put_u8(self.version);
+        buffer.put_u16(self.my_autonomous_system);
+        buffer.put_u16(self.hold_time);
+        buffer.put_u32(self.bgp_id.to_bits());
+
+        let params_len: usize = self
+            .optional_params
+            .iter()
+            .map(|param| 2 + param.param_value.len())
+            .sum();
+        buffer.put_u8(params_len as u8);
+        for param in &self.optional_params {
+            buffer.put_u8(param.param_type);
+            buffer.put_u8(param.param_value.len() as u8);
+            buffer.put_slice(&param.param_value);
+        }
+
+        buffer.freeze()
+    }
+}
+
+impl OptionalParam {
+    pub const CAPABILITIES: u8 = 2;
+}
+
+impl Validate<OpenParseError> for OpenMessage {
+    fn validate(&self) -> Result<(), OpenParseError> {
+        if self.version != Self::VERSION {
+            return Err(OpenParseError::UnsupportedVersion(self.version));
+        }
+        // Hold time must be zero or at least three seconds
+        if self.hold_time == 1 || self.hold_time == 2 {
+            return Err(OpenParseError::UnacceptableHoldTime(self.hold_time));
+        }
+        if self.bgp_id.is_unspecified() || self.bgp_id.is_multicast() {
+            return Err(OpenParseError::BadBgpIdentifier(self.bgp_id));
+        }
+        self.capabilities()?;
+
+        Ok(())
+    }
+}
+
 impl TryFrom<&mut Bytes> for OpenMessage {
-    type Error = String;
+    type Error = OpenParseError;
+
+    fn try_from(value: &mut Bytes) -> Result<Self, OpenParseError> {
+        if value.len() < Self::MIN_LEN {
+            return Err(OpenParseError::MessageLength(value.len()));
+        }
 
-    fn try_from(value: &mut Bytes) -> Result<Self, String> {
         let version = value.get_u8();
+        spans::consumed(value, 1, || format!("version: {version}"));
         let my_autonomous_system = value.get_u16();
+        spans::consumed(value, 2, || format!("my AS: {my_autonomous_system}"));
         let hold_time = value.get_u16();
+        spans::consumed(value, 2, || format!("hold time: {hold_time}"));
         let bgp_id = value.get_u32();
+        spans::consumed(value, 4, || {
+            format!("BGP identifier: {}", Ipv4Addr::from_bits(bgp_id))
+        });
 
         let optional_params_len = value.get_u8();
-
-        let mut params_bytes = value.split_to(optional_params_len as usize);
-        if optional_params_len as usize > params_bytes.len() {
-            return Err(format!(
-                "Optional parameters length {} is shorter than specified {}",
-                params_bytes.len(),
-                optional_params_len
-            ));
+        spans::consumed(value, 1, || {
+            format!("optional parameters length: {optional_params_len}")
+        });
+        if optional_params_len as usize > value.len() {
+            return Err(OpenParseError::ParamsLength {
+                declared: optional_params_len,
+                remaining: value.len(),
+            });
         }
 
+        let mut params_bytes = value.split_to(optional_params_len as usize);
+        spans::enter(spans::mark(&params_bytes), || "optional parameters".into());
         let optional_params = OptionalParamVec::try_from(&mut params_bytes)?.0;
+        spans::leave(&params_bytes);
 
         Ok(OpenMessage {
             version,
@@ -50,21 +231,36 @@ impl TryFrom<&mut Bytes> for OpenMessage {
 }
 
 impl TryFrom<&mut Bytes> for OptionalParamVec {
-    type Error = String;
+    type Error = OpenParseError;
 
-    fn try_from(value: &mut Bytes) -> Result<Self, String> {
+    fn try_from(value: &mut Bytes) -> Result<Self, OpenParseError> {
         let mut params: Vec<OptionalParam> = Vec::new();
 
         while value.has_remaining() {
+            if value.len() < 2 {
+                return Err(OpenParseError::TruncatedParam);
+            }
+            spans::enter(spans::mark(value), || "optional parameter".into());
             let code = value.get_u8();
+            spans::consumed(value, 1, || format!("type: {code}"));
             let length = value.get_u8();
+            spans::consumed(value, 1, || format!("length: {length}"));
             if value.len() < length as usize {
-                return Err("oof".to_string());
+                return Err(OpenParseError::ParamLength {
+                    param_type: code,
+                    length,
+                    remaining: value.len(),
+                });
+            }
+            let data = value.copy_to_bytes(length as usize);
+            // Capabilities are only decoded on demand, so here just for their fields
+            if spans::active() && code == OptionalParam::CAPABILITIES {
+                let _ = Capability::decode_list(&mut data.clone());
             }
-            let data = value.copy_to_bytes(length as usize).to_vec();
+            spans::leave(value);
             params.push(OptionalParam {
                 param_type: code,
-                param_value: data,
+                param_value: data.to_vec(),
             });
         }
 
@@ -75,6 +271,7 @@ impl TryFrom<&mut Bytes> for OptionalParamVec {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::address_family::{Afi, Safi};
     use std::net::Ipv4Addr;
 
     use bytes::{BufMut, BytesMut};
@@ -96,10 +293,112 @@ mod test {
         if let Ok(msg) = open_message {
             assert_eq!(msg.version, 4);
             assert_eq!(msg.optional_params.len(), 1);
-            if let Some(param) = msg.optional_params.get(0) {
+            if let Some(param) = msg.optional_params.first() {
                 assert_eq!(param.param_type, 1);
-                assert_eq!(param.param_value.get(0), Some(0).as_ref());
+                assert_eq!(param.param_value.first(), Some(0).as_ref());
             }
         }
     }
+
+    #[test]
+    fn test_open_round_trip() {
+        let capabilities = [
+            Capability::MultiProtocol {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+            },
+            Capability::FourOctetAs { asn: 4200000001 },
+        ];
+        let open = OpenMessage::new(4200000001, 90, Ipv4Addr::new(10, 0, 0, 1), &capabilities);
+        assert_eq!(open.my_autonomous_system, OpenMessage::AS_TRANS);
+
+        let decoded = OpenMessage::try_from(&mut open.to_bytes()).unwrap();
+        assert_eq!(decoded, open);
+        assert_eq!(decoded.asn(), 4200000001);
+        assert_eq!(decoded.capabilities().unwrap(), capabilities);
+    }
+
+    #[test]
+    fn test_open_validate() {
+        let open = OpenMessage::new(65001, 90, Ipv4Addr::new(10, 0, 0, 1), &[]);
+        assert_eq!(open.validate(), Ok(()));
+
+        let mut bad = open.clone();
+        bad.version = 3;
+        assert_eq!(bad.validate(), Err(OpenParseError::UnsupportedVersion(3)));
+        assert_eq!(
+            bad.validate().unwrap_err().sub_err(),
+            Some(OpenMessageSubErr::UnsupportedVersionNumber)
+        );
+
+        let mut bad = open.clone();
+        bad.hold_time = 2;
+        assert_eq!(bad.validate(), Err(OpenParseError::UnacceptableHoldTime(2)));
+
+        let mut bad = open.clone();
+        bad.bgp_id = Ipv4Addr::UNSPECIFIED;
+        assert_eq!(
+            bad.validate(),
+            Err(OpenParseError::BadBgpIdentifier(Ipv4Addr::UNSPECIFIED))
+        );
+
+        let mut bad = open;
+        bad.optional_params.push(OptionalParam {
+            param_type: OptionalParam::CAPABILITIES,
+            param_value: vec![1],
+        });
+        let err = bad.validate().unwrap_err();
+        assert_eq!(err, OpenParseError::TruncatedCapability);
+        assert_eq!(
+            err.sub_err(),
+            Some(OpenMessageSubErr::UnsupportedOptionalParameter)
+        );
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let open = OpenMessage::new(65001, 90, Ipv4Addr::new(10, 0, 0, 1), &[]).to_bytes();
+        assert_eq!(
+            OpenMessage::try_from(&mut open.slice(..9)),
+            Err(OpenParseError::MessageLength(9))
+        );
+
+        let with_params = |params: &[u8]| {
+            let mut data = BytesMut::from(&open[..9]);
+            data.put_u8(params.len() as u8);
+            data.put_slice(params);
+            OpenMessage::try_from(&mut data.freeze())
+        };
+        assert_eq!(with_params(&[2]), Err(OpenParseError::TruncatedParam));
+        assert_eq!(
+            with_params(&[2, 3, 0]),
+            Err(OpenParseError::ParamLength {
+                param_type: 2,
+                length: 3,
+                remaining: 1
+            })
+        );
+
+        let capabilities = |value: &[u8]| with_params(&[&[2, value.len() as u8], value].concat());
+        assert_eq!(
+            capabilities(&[65]).unwrap().capabilities(),
+            Err(OpenParseError::TruncatedCapability)
+        );
+        assert_eq!(
+            capabilities(&[65, 4, 0]).unwrap().capabilities(),
+            Err(OpenParseError::CapabilityLength {
+                code: 65,
+                length: 4,
+                remaining: 1
+            })
+        );
+        // A send/receive value of 4
+        let add_path = [0, 1, 1, 4];
+        assert_eq!(
+            capabilities(&[&[69, 4][..], &add_path].concat())
+                .unwrap()
+                .capabilities(),
+            Err(OpenParseError::AddPath(Bytes::copy_from_slice(&add_path)))
+        );
+    }
 }
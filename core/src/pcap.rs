@@ -0,0 +1,833 @@
+//! BGP messages recovered from packet captures of BGP sessions.
+//!
+//! Reads classic pcap files (not pcapng) of Ethernet, raw IP, BSD loopback and Linux cooked
+//! captures. Each direction of a TCP connection is reassembled by sequence number, putting
+//! out-of-order segments back in order and dropping retransmitted bytes, and then split into
+//! messages. Flows count as BGP when either port is 179, or when a segment starts with a BGP
+//! header.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, Bytes, BytesMut};
+use thiserror::Error;
+
+use crate::bgp_message::BgpMessage;
+use crate::capability::Capability;
+use crate::header::BgpHeader;
+use crate::timestamped::Timestamped;
+
+const BGP_PORT: u16 = 179;
+const MARKER: [u8; 16] = [0xff; 16];
+
+/// Link types
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+
+const TCP_SYN: u8 = 0x02;
+
+/// Out-of-order bytes kept waiting for a missing segment, after which the segment is taken
+/// as lost from the capture
+const MAX_PENDING: usize = 256 * 1024;
+/// Data segments of a flow on other ports searched for a BGP header
+const DETECT_SEGMENTS: u8 = 8;
+
+#[derive(Debug, Error)]
+pub enum PcapError {
+    #[error("pcap I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a pcap file")]
+    Format,
+    #[error("unsupported pcap link type {0}")]
+    LinkType(u32),
+}
+
+/// One direction of a TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowId {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+impl FlowId {
+    /// The other direction of the connection
+    pub fn reverse(&self) -> Self {
+        FlowId {
+            src: self.dst,
+            dst: self.src,
+        }
+    }
+}
+
+impl fmt::Display for FlowId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.src, self.dst)
+    }
+}
+
+/// What the capture held besides the messages, see [`PcapExtractor::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractStats {
+    pub packets: u64,
+    /// TCP segments carrying data of BGP flows
+    pub segments: u64,
+    /// Segments that arrived ahead of a missing one
+    pub out_of_order: u64,
+    /// Segments repeating bytes already received, in whole or in part
+    pub retransmitted: u64,
+    /// Missing segments skipped over, after which the flow resynchronised on the next header
+    pub gaps: u64,
+    /// Messages with a valid header that failed to decode
+    pub undecodable: u64,
+    /// The capture ends in the middle of a packet record
+    pub truncated: bool,
+}
+
+/// Iterates over the BGP messages of a pcap file in capture order, each timestamped with the
+/// packet that completed it.
+///
+/// Damage to the capture never ends the iteration early: lost segments are skipped, messages
+/// that don't decode are counted in [`ExtractStats`], and a truncated file yields everything
+/// before the truncation.
+pub struct PcapExtractor<R> {
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u32,
+    flows: HashMap<FlowId, Flow>,
+    /// Whether the OPEN of each flow carried the 4 octet AS capability
+    four_octet_as: HashMap<FlowId, bool>,
+    ready: VecDeque<(FlowId, Timestamped<BgpMessage>)>,
+    stats: ExtractStats,
+    /// Time of the last packet read
+    last_packet: SystemTime,
+    done: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlowKind {
+    Bgp,
+    /// Data segments searched for a BGP header so far
+    Unknown(u8),
+    Other,
+}
+
+struct Flow {
+    kind: FlowKind,
+    next_seq: Option<u32>,
+    /// At a message boundary: from the SYN, or once a header was found
+    synced: bool,
+    buf: BytesMut,
+    /// Segments ahead of `next_seq`
+    pending: Vec<(u32, Bytes)>,
+    pending_len: usize,
+}
+
+impl PcapExtractor<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapExtractor<R> {
+    /// Reads the file header
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut header = [0; 24];
+        reader
+            .read_exact(&mut header)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => PcapError::Format,
+                _ => err.into(),
+            })?;
+        let (big_endian, nanos) = match header[..4] {
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            _ => return Err(PcapError::Format),
+        };
+        let link_type = read_u32(&header[20..24], big_endian) & 0x0fff_ffff;
+        if ![
+            LINKTYPE_NULL,
+            LINKTYPE_ETHERNET,
+            LINKTYPE_RAW,
+            LINKTYPE_LINUX_SLL,
+        ]
+        .contains(&link_type)
+        {
+            return Err(PcapError::LinkType(link_type));
+        }
+        Ok(PcapExtractor {
+            reader,
+            big_endian,
+            nanos,
+            link_type,
+            flows: HashMap::new(),
+            four_octet_as: HashMap::new(),
+            ready: VecDeque::new(),
+            stats: ExtractStats::default(),
+            last_packet: SystemTime::UNIX_EPOCH,
+            done: false,
+        })
+    }
+
+    pub fn stats(&self) -> ExtractStats {
+        self.stats
+    }
+
+    /// The next packet record, `None` at the end of the file or of a truncated one
+    fn read_packet(&mut self) -> Option<(SystemTime, Vec<u8>)> {
+        let mut header = [0; 16];
+        let mut read = 0;
+        while read < header.len() {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) => {
+                    self.stats.truncated |= read > 0;
+                    return None;
+                }
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.stats.truncated = true;
+                    return None;
+                }
+            }
+        }
+        let seconds = read_u32(&header[0..4], self.big_endian);
+        let fraction = read_u32(&header[4..8], self.big_endian);
+        let length = read_u32(&header[8..12], self.big_endian) as usize;
+
+        let mut data = Vec::with_capacity(length.min(1 << 18));
+        match (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)
+        {
+            Ok(_) if data.len() == length => {}
+            _ => {
+                self.stats.truncated = true;
+                return None;
+            }
+        }
+        let fraction = match self.nanos {
+            true => Duration::from_nanos(fraction.into()),
+            false => Duration::from_micros(fraction.into()),
+        };
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.into()) + fraction;
+        Some((at, data))
+    }
+
+    fn packet(&mut self, at: SystemTime, data: &[u8]) {
+        self.stats.packets += 1;
+        let Some((ethertype, ip)) = link_payload(self.link_type, data) else {
+            return;
+        };
+        let Some((src, dst, tcp)) = ip_payload(ethertype, ip) else {
+            return;
+        };
+        let Some(segment) = Segment::decode(tcp) else {
+            return;
+        };
+
+        let flow_id = FlowId {
+            src: SocketAddr::new(src, segment.src_port),
+            dst: SocketAddr::new(dst, segment.dst_port),
+        };
+        let flow = self.flows.entry(flow_id).or_insert_with(|| Flow {
+            kind: match (segment.src_port, segment.dst_port) {
+                (BGP_PORT, _) | (_, BGP_PORT) => FlowKind::Bgp,
+                _ => FlowKind::Unknown(0),
+            },
+            next_seq: None,
+            synced: false,
+            buf: BytesMut::new(),
+            pending: vec![],
+            pending_len: 0,
+        });
+
+        if segment.flags & TCP_SYN != 0 {
+            flow.next_seq = Some(segment.seq.wrapping_add(1));
+            flow.synced = true;
+            flow.buf.clear();
+            flow.pending.clear();
+            flow.pending_len = 0;
+            return;
+        }
+        if segment.payload.is_empty() {
+            return;
+        }
+        if let FlowKind::Unknown(searched) = flow.kind {
+            if !starts_with_header(segment.payload) {
+                flow.kind = match searched + 1 {
+                    DETECT_SEGMENTS => FlowKind::Other,
+                    searched => FlowKind::Unknown(searched),
+                };
+                return;
+            }
+            flow.kind = FlowKind::Bgp;
+            flow.next_seq = Some(segment.seq);
+            flow.synced = true;
+        }
+        if flow.kind == FlowKind::Other {
+            return;
+        }
+
+        self.stats.segments += 1;
+        flow.segment(
+            segment.seq,
+            Bytes::copy_from_slice(segment.payload),
+            &mut self.stats,
+        );
+        self.decode(flow_id, at);
+    }
+
+    /// Decodes the messages completed in the flow's buffer
+    fn decode(&mut self, flow_id: FlowId, at: SystemTime) {
+        loop {
+            let Some(flow) = self.flows.get_mut(&flow_id) else {
+                return;
+            };
+            let Some(mut frame) = flow.next_frame() else {
+                return;
+            };
+            // Until both OPENs were seen, assume the 4 octet ASNs almost every session has
+            let four_octet_as = [flow_id, flow_id.reverse()]
+                .iter()
+                .all(|flow| self.four_octet_as.get(flow).copied().unwrap_or(true));
+            match BgpMessage::decode_framed(&mut frame, four_octet_as, false) {
+                Ok(message) => {
+                    if let BgpMessage::Open(open) = &message {
+                        let capable = open
+                            .capabilities()
+                            .unwrap_or_default()
+                            .iter()
+                            .any(|capability| capability.code() == Capability::FOUR_OCTET_AS);
+                        self.four_octet_as.insert(flow_id, capable);
+                    }
+                    self.ready
+                        .push_back((flow_id, Timestamped::at(at, message)));
+                }
+                Err(_) => self.stats.undecodable += 1,
+            }
+        }
+    }
+
+    /// Skips the gaps left in every flow at the end of the capture
+    fn finish(&mut self, at: SystemTime) {
+        let mut flow_ids: Vec<FlowId> = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| !flow.pending.is_empty())
+            .map(|(flow_id, _)| *flow_id)
+            .collect();
+        flow_ids.sort();
+        for flow_id in flow_ids {
+            let flow = self.flows.get_mut(&flow_id).unwrap();
+            while !flow.pending.is_empty() {
+                flow.skip_gap(&mut self.stats);
+            }
+            self.decode(flow_id, at);
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapExtractor<R> {
+    type Item = (FlowId, Timestamped<BgpMessage>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.ready.pop_front() {
+                return Some(message);
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_packet() {
+                Some((at, data)) => {
+                    self.last_packet = at;
+                    self.packet(at, &data);
+                }
+                None => {
+                    self.done = true;
+                    self.finish(self.last_packet);
+                }
+            }
+        }
+    }
+}
+
+impl Flow {
+    fn segment(&mut self, seq: u32, payload: Bytes, stats: &mut ExtractStats) {
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        if (seq.wrapping_sub(next_seq) as i32) > 0 {
+            stats.out_of_order += 1;
+            self.pending_len += payload.len();
+            self.pending.push((seq, payload));
+            if self.pending_len > MAX_PENDING {
+                self.skip_gap(stats);
+            }
+            return;
+        }
+        self.append(seq, payload, stats);
+        self.drain(stats);
+    }
+
+    /// Appends the pending segments the received bytes caught up with
+    fn drain(&mut self, stats: &mut ExtractStats) {
+        while let Some(i) = self
+            .pending
+            .iter()
+            .position(|(seq, _)| (seq.wrapping_sub(self.next_seq.unwrap()) as i32) <= 0)
+        {
+            let (seq, payload) = self.pending.swap_remove(i);
+            self.pending_len -= payload.len();
+            self.append(seq, payload, stats);
+        }
+    }
+
+    /// Appends the bytes of a segment at or before `next_seq` that weren't received yet
+    fn append(&mut self, seq: u32, mut payload: Bytes, stats: &mut ExtractStats) {
+        let next_seq = self.next_seq.unwrap();
+        let received = next_seq.wrapping_sub(seq) as usize;
+        if received >= payload.len() {
+            stats.retransmitted += 1;
+            return;
+        }
+        if received > 0 {
+            stats.retransmitted += 1;
+            payload.advance(received);
+        }
+        self.next_seq = Some(next_seq.wrapping_add(payload.len() as u32));
+        self.buf.extend_from_slice(&payload);
+    }
+
+    /// Gives up on the missing bytes before the earliest pending segment
+    fn skip_gap(&mut self, stats: &mut ExtractStats) {
+        let next_seq = self.next_seq.unwrap();
+        let Some(&(seq, _)) = self
+            .pending
+            .iter()
+            .min_by_key(|(seq, _)| seq.wrapping_sub(next_seq))
+        else {
+            return;
+        };
+        stats.gaps += 1;
+        self.buf.clear();
+        self.synced = false;
+        self.next_seq = Some(seq);
+        self.drain(stats);
+    }
+
+    /// Takes the next complete message off the buffer, with its header
+    fn next_frame(&mut self) -> Option<Bytes> {
+        loop {
+            if !self.synced {
+                match self.buf.windows(MARKER.len()).position(|w| w == MARKER) {
+                    Some(start) => {
+                        self.buf.advance(start);
+                        self.synced = true;
+                    }
+                    None => {
+                        let keep = self.buf.len().min(MARKER.len() - 1);
+                        self.buf.advance(self.buf.len() - keep);
+                        return None;
+                    }
+                }
+            }
+            if self.buf.len() < BgpHeader::MIN_LEN as usize {
+                return None;
+            }
+            let length = u16::from_be_bytes([self.buf[16], self.buf[17]]);
+            if self.buf[..16] != MARKER || length < BgpHeader::MIN_LEN {
+                // Lost track of the boundaries, look for the next header
+                self.buf.advance(1);
+                self.synced = false;
+                continue;
+            }
+            if self.buf.len() < length as usize {
+                return None;
+            }
+            return Some(self.buf.split_to(length as usize).freeze());
+        }
+    }
+}
+
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    fn decode(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 20 {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < 20 || data.len() < header_len {
+            return None;
+        }
+        Some(Segment {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dst_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            flags: data[13],
+            payload: &data[header_len..],
+        })
+    }
+}
+
+/// The ethertype and IP packet of a link layer frame
+fn link_payload(link_type: u32, data: &[u8]) -> Option<(u16, &[u8])> {
+    match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+            let mut offset = 14;
+            while ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes([*data.get(offset + 2)?, *data.get(offset + 3)?]);
+                offset += 4;
+            }
+            Some((ethertype, data.get(offset..)?))
+        }
+        LINKTYPE_LINUX_SLL => {
+            let ethertype = u16::from_be_bytes([*data.get(14)?, *data.get(15)?]);
+            Some((ethertype, data.get(16..)?))
+        }
+        // The address family in host byte order, so go by the IP version instead
+        LINKTYPE_NULL => ip_version(data.get(4..)?),
+        _ => ip_version(data),
+    }
+}
+
+fn ip_version(data: &[u8]) -> Option<(u16, &[u8])> {
+    match data.first()? >> 4 {
+        4 => Some((ETHERTYPE_IPV4, data)),
+        6 => Some((ETHERTYPE_IPV6, data)),
+        _ => None,
+    }
+}
+
+/// The addresses and TCP segment of an unfragmented IP packet
+fn ip_payload(ethertype: u16, data: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = (*data.first()? & 0x0f) as usize * 4;
+            if data.len() < header_len.max(20) {
+                return None;
+            }
+            // More fragments, or a fragment offset
+            if u16::from_be_bytes([data[6], data[7]]) & 0x3fff != 0 || data[9] != IPPROTO_TCP {
+                return None;
+            }
+            // Ethernet pads short frames
+            let total_len = (u16::from_be_bytes([data[2], data[3]]) as usize).min(data.len());
+            let src = Ipv4Addr::new(data[12], data[13], data[14], data[15]);
+            let dst = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+            Some((src.into(), dst.into(), data.get(header_len..total_len)?))
+        }
+        ETHERTYPE_IPV6 => {
+            if data.len() < 40 {
+                return None;
+            }
+            let payload_len = u16::from_be_bytes([data[4], data[5]]) as usize;
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&data[8..24]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&data[24..40]).unwrap());
+            let mut next_header = data[6];
+            let mut payload = data.get(40..(40 + payload_len).min(data.len()))?;
+            // Hop-by-hop, routing and destination options; fragments aren't reassembled
+            while matches!(next_header, 0 | 43 | 60) {
+                let len = (*payload.get(1)? as usize + 1) * 8;
+                next_header = payload[0];
+                payload = payload.get(len..)?;
+            }
+            (next_header == IPPROTO_TCP).then_some((src.into(), dst.into(), payload))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a segment of a flow on other ports starts with a plausible BGP header
+fn starts_with_header(payload: &[u8]) -> bool {
+    payload.len() >= BgpHeader::MIN_LEN as usize
+        && payload[..16] == MARKER
+        && u16::from_be_bytes([payload[16], payload[17]]) >= BgpHeader::MIN_LEN
+        && (1..=5).contains(&payload[18])
+}
+
+fn read_u32(data: &[u8], big_endian: bool) -> u32 {
+    let bytes = [data[0], data[1], data[2], data[3]];
+    match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, AttributeValue};
+    use crate::open_message::OpenMessage;
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    const CLIENT: [u8; 4] = [192, 0, 2, 1];
+    const SERVER: [u8; 4] = [192, 0, 2, 2];
+
+    /// A little endian, microsecond pcap of Ethernet frames
+    struct Capture(Vec<u8>);
+
+    impl Capture {
+        fn new() -> Self {
+            let mut data = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+            data.extend([0; 8]);
+            data.extend(65535u32.to_le_bytes());
+            data.extend(LINKTYPE_ETHERNET.to_le_bytes());
+            Capture(data)
+        }
+
+        /// A TCP segment from `src` to `dst`, captured `millis` after the first
+        fn segment(
+            &mut self,
+            millis: u32,
+            (src, src_port): ([u8; 4], u16),
+            (dst, dst_port): ([u8; 4], u16),
+            seq: u32,
+            flags: u8,
+            payload: &[u8],
+        ) {
+            let mut frame = vec![0; 12];
+            frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+            let total_len = 20 + 20 + payload.len() as u16;
+            frame.extend([0x45, 0]);
+            frame.extend(total_len.to_be_bytes());
+            frame.extend([0, 0, 0x40, 0, 64, IPPROTO_TCP, 0, 0]);
+            frame.extend(src);
+            frame.extend(dst);
+            frame.extend(src_port.to_be_bytes());
+            frame.extend(dst_port.to_be_bytes());
+            frame.extend(seq.to_be_bytes());
+            frame.extend([0, 0, 0, 0, 0x50, flags | 0x10, 0xff, 0xff, 0, 0, 0, 0]);
+            frame.extend(payload);
+
+            self.0.extend((1_700_000_000 + millis / 1000).to_le_bytes());
+            self.0.extend((millis % 1000 * 1000).to_le_bytes());
+            self.0.extend((frame.len() as u32).to_le_bytes());
+            self.0.extend((frame.len() as u32).to_le_bytes());
+            self.0.extend(frame);
+        }
+
+        fn extract(&self) -> (Vec<(FlowId, Timestamped<BgpMessage>)>, ExtractStats) {
+            let mut extractor = PcapExtractor::new(&self.0[..]).unwrap();
+            let messages = extractor.by_ref().collect();
+            (messages, extractor.stats())
+        }
+    }
+
+    fn update(prefix: &str) -> BgpMessage {
+        BgpMessage::Update(
+            UpdateMessageBuilder::new()
+                .announce(prefix.parse::<IpAddrPrefix>().unwrap())
+                .next_hop("192.0.2.1".parse().unwrap())
+                .build(),
+        )
+    }
+
+    fn session() -> Vec<BgpMessage> {
+        vec![
+            BgpMessage::Open(OpenMessage::new(
+                65001,
+                90,
+                Ipv4Addr::from(CLIENT),
+                &[Capability::FourOctetAs { asn: 65001 }],
+            )),
+            BgpMessage::Keepalive,
+            update("198.51.100.0/24"),
+            update("203.0.113.0/24"),
+            update("10.0.0.0/8"),
+            BgpMessage::Keepalive,
+        ]
+    }
+
+    fn stream(messages: &[BgpMessage]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|message| message.to_bytes().to_vec())
+            .collect()
+    }
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_out_of_order_and_retransmitted() {
+        let client = (CLIENT, 40000);
+        let server = (SERVER, BGP_PORT);
+        let data = stream(&session());
+        // Boundaries that split headers and put several messages in one segment
+        let cuts = [0, 7, 40, 70, 90, 130, data.len()];
+        let chunk = |i: usize| (1000 + cuts[i] as u32, &data[cuts[i]..cuts[i + 1]]);
+
+        let mut capture = Capture::new();
+        capture.segment(0, client, server, 999, TCP_SYN, &[]);
+        capture.segment(1, server, client, 5000, TCP_SYN, &[]);
+        for (millis, i) in [(2, 0), (3, 2), (4, 1), (5, 1), (6, 4), (7, 3)] {
+            let (seq, payload) = chunk(i);
+            capture.segment(millis, client, server, seq, 0, payload);
+        }
+        // The final chunk, repeating the last 10 bytes received
+        let overlap = 10;
+        let seq = chunk(5).0 - overlap as u32;
+        capture.segment(8, client, server, seq, 0, &data[cuts[5] - overlap..]);
+        capture.segment(
+            9,
+            server,
+            client,
+            5001,
+            0,
+            &stream(&[BgpMessage::Keepalive]),
+        );
+
+        let (messages, stats) = capture.extract();
+        let flow = FlowId {
+            src: SocketAddr::from(client),
+            dst: SocketAddr::from(server),
+        };
+        let from_client: Vec<_> = messages
+            .iter()
+            .filter(|(id, _)| *id == flow)
+            .map(|(_, message)| message.value.clone())
+            .collect();
+        assert_eq!(from_client, session());
+
+        // Each message is timestamped by the segment completing it
+        let received: Vec<_> = messages
+            .iter()
+            .map(|(_, message)| message.received)
+            .collect();
+        assert_eq!(
+            received,
+            vec![at(4), at(4), at(7), at(8), at(8), at(8), at(9)]
+        );
+        assert_eq!(messages[6].0, flow.reverse());
+        assert_eq!(stats.out_of_order, 2);
+        assert_eq!(stats.retransmitted, 2);
+        assert_eq!(
+            (stats.gaps, stats.undecodable, stats.truncated),
+            (0, 0, false)
+        );
+    }
+
+    #[test]
+    fn test_detection_and_lost_segment() {
+        // Neither port is 179 and the capture starts after the handshake
+        let client = (CLIENT, 40000);
+        let server = (SERVER, 1179);
+        let messages = session();
+        let data = stream(&messages);
+        let open_len = messages[0].to_bytes().len();
+        let mut capture = Capture::new();
+        capture.segment(0, client, server, 100, 0, &data[..open_len + 5]);
+        // The rest of the KEEPALIVE and part of the first UPDATE are lost
+        let resumed = open_len + 30;
+        capture.segment(2, client, server, 100 + resumed as u32, 0, &data[resumed..]);
+        // Not BGP
+        for i in 0..DETECT_SEGMENTS as u32 {
+            capture.segment(3, (CLIENT, 5000), (SERVER, 80), 1 + i * 4, 0, b"GET ");
+        }
+
+        let (extracted, stats) = capture.extract();
+        let extracted: Vec<_> = extracted.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(extracted.len(), 4);
+        assert_eq!(extracted[0].value, messages[0]);
+        assert_eq!(extracted[0].received, at(0));
+        // Recovered at the end of the capture, from the next header after the gap
+        assert_eq!(
+            extracted[1..]
+                .iter()
+                .map(|message| message.value.clone())
+                .collect::<Vec<_>>(),
+            messages[3..]
+        );
+        assert_eq!(stats.gaps, 1);
+        assert_eq!(stats.segments, 2);
+    }
+
+    #[test]
+    fn test_truncated_capture() {
+        let client = (CLIENT, 40000);
+        let server = (SERVER, BGP_PORT);
+        let data = stream(&session());
+        let mut capture = Capture::new();
+        capture.segment(0, client, server, 999, TCP_SYN, &[]);
+        capture.segment(1, client, server, 1000, 0, &data[..60]);
+        capture.segment(2, client, server, 1060, 0, &data[60..]);
+        capture.0.truncate(capture.0.len() - 20);
+
+        let (messages, stats) = capture.extract();
+        assert_eq!(messages.len(), 2);
+        assert!(stats.truncated);
+
+        assert!(matches!(
+            PcapExtractor::new(&b"not a capture at all..."[..]),
+            Err(PcapError::Format)
+        ));
+    }
+
+    #[test]
+    fn test_two_octet_session() {
+        let client = (CLIENT, 40000);
+        let server = (SERVER, BGP_PORT);
+        let open = |asn| BgpMessage::Open(OpenMessage::new(asn, 90, Ipv4Addr::from(CLIENT), &[]));
+        // ORIGIN, AS_PATH of 2 octet ASNs, NEXT_HOP and 192.0.2.0/24
+        let mut update = vec![0xff; 16];
+        update.extend([0, 47, 2, 0, 0, 0, 20]);
+        update.extend([0x40, 1, 1, 0]);
+        update.extend([0x40, 2, 6, 2, 2, 0xfd, 0xe9, 0xfd, 0xea]);
+        update.extend([0x40, 3, 4, 192, 0, 2, 1]);
+        update.extend([24, 192, 0, 2]);
+
+        let mut capture = Capture::new();
+        capture.segment(0, client, server, 999, TCP_SYN, &[]);
+        capture.segment(0, server, client, 4999, TCP_SYN, &[]);
+        capture.segment(1, server, client, 5000, 0, &stream(&[open(65002)]));
+        let mut data = stream(&[open(65001)]);
+        data.extend(update);
+        capture.segment(2, client, server, 1000, 0, &data);
+
+        let (messages, _) = capture.extract();
+        let BgpMessage::Update(update) = &messages[2].1.value else {
+            panic!("{:?} isn't an UPDATE", messages[2]);
+        };
+        let as_path = update
+            .path_attributes
+            .iter()
+            .find_map(|attribute| match &attribute.value {
+                AttributeValue::AsPath(as_path) => Some(&**as_path),
+                _ => None,
+            });
+        assert_eq!(
+            as_path,
+            Some(&AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 65002].into(),
+                }]
+                .into()
+            })
+        );
+    }
+}
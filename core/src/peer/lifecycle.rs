@@ -0,0 +1,206 @@
+#[cfg(feature = "bmp")]
+use crate::bmp;
+use crate::notification_message::{CeaseSubErr, NotificationErrorCode, NotificationMessage};
+use crate::open_message::OpenMessage;
+
+use super::PeerInfo;
+use super::negotiated::Negotiated;
+#[cfg(feature = "bmp")]
+use super::shutdown::SessionEnd;
+use super::shutdown::ShutdownReason;
+
+/// The FSM event of the hold timer running out (RFC 4271 section 8.1.3)
+#[cfg(feature = "bmp")]
+const HOLD_TIMER_EXPIRES: u16 = 10;
+
+/// A session with a peer was established
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerUp {
+    pub peer: PeerInfo,
+    pub negotiated: Negotiated,
+    pub local_open: OpenMessage,
+    pub remote_open: OpenMessage,
+}
+
+/// A session with a peer ended
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerDown {
+    pub peer: PeerInfo,
+    pub reason: PeerDownReason,
+    /// Graceful Restart was negotiated for the session
+    pub graceful_restart: bool,
+}
+
+/// Why a session ended, along the lines of the BMP Peer Down reasons (RFC 7854 section 4.9)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerDownReason {
+    /// We closed the session with a Cease, for example on an administrative shutdown
+    LocalShutdown(ShutdownReason),
+    /// We sent Hold Timer Expired
+    HoldTimerExpired,
+    /// We rejected a malformed message with this NOTIFICATION
+    LocalError(NotificationMessage),
+    /// The session was closed locally without a NOTIFICATION
+    LocalClose,
+    /// The peer sent this NOTIFICATION
+    RemoteNotification(NotificationMessage),
+    /// The peer gave up this session for another connection with us
+    CollisionResolution,
+    /// The TCP connection failed or the peer closed it without a NOTIFICATION
+    ConnectionLost,
+}
+
+impl PeerDown {
+    /// Whether the peer's routes should be kept as stale rather than withdrawn, which Graceful
+    /// Restart (RFC 4724) asks for when the session drops without a NOTIFICATION
+    pub fn retains_routes(&self) -> bool {
+        self.graceful_restart && self.reason == PeerDownReason::ConnectionLost
+    }
+}
+
+impl PeerDownReason {
+    /// Interprets the reason of a BMP Peer Down, so peers monitored over BMP go down as those
+    /// of native sessions do
+    #[cfg(feature = "bmp")]
+    pub fn from_bmp(reason: bmp::PeerDownReason) -> Self {
+        match reason {
+            bmp::PeerDownReason::LocalNotification(notification) => {
+                match notification.error_codes {
+                    NotificationErrorCode::HoldTimeExpired => PeerDownReason::HoldTimerExpired,
+                    NotificationErrorCode::Cease(subcode) => {
+                        match local_shutdown(subcode, &notification) {
+                            Some(reason) => PeerDownReason::LocalShutdown(reason),
+                            None => PeerDownReason::LocalError(notification),
+                        }
+                    }
+                    _ => PeerDownReason::LocalError(notification),
+                }
+            }
+            bmp::PeerDownReason::LocalNoNotification {
+                fsm_event: HOLD_TIMER_EXPIRES,
+            } => PeerDownReason::HoldTimerExpired,
+            bmp::PeerDownReason::RemoteNotification(notification) => {
+                match notification.error_codes {
+                    NotificationErrorCode::Cease(CeaseSubErr::ConnectionCollisionResolution) => {
+                        PeerDownReason::CollisionResolution
+                    }
+                    _ => PeerDownReason::RemoteNotification(notification),
+                }
+            }
+            bmp::PeerDownReason::RemoteNoNotification => PeerDownReason::ConnectionLost,
+            bmp::PeerDownReason::Deconfigured => {
+                PeerDownReason::LocalShutdown(ShutdownReason::PeerDeconfigured)
+            }
+            bmp::PeerDownReason::LocalNoNotification { .. }
+            | bmp::PeerDownReason::LocalClosed(_)
+            | bmp::PeerDownReason::Unknown { .. } => PeerDownReason::LocalClose,
+        }
+    }
+
+    /// The BMP Peer Down reason code
+    pub fn bmp_code(&self) -> u8 {
+        match self {
+            PeerDownReason::LocalShutdown(_)
+            | PeerDownReason::HoldTimerExpired
+            | PeerDownReason::LocalError(_) => 1,
+            PeerDownReason::LocalClose => 2,
+            PeerDownReason::RemoteNotification(_) | PeerDownReason::CollisionResolution => 3,
+            PeerDownReason::ConnectionLost => 4,
+        }
+    }
+
+    /// The NOTIFICATION that closed the session, from either side
+    pub fn notification(&self) -> Option<NotificationMessage> {
+        match self {
+            PeerDownReason::LocalShutdown(reason) => Some(reason.notification()),
+            PeerDownReason::HoldTimerExpired => Some(NotificationMessage::new(
+                NotificationErrorCode::HoldTimeExpired,
+                vec![],
+            )),
+            PeerDownReason::LocalError(notification)
+            | PeerDownReason::RemoteNotification(notification) => Some(notification.clone()),
+            PeerDownReason::CollisionResolution => Some(NotificationMessage::new(
+                NotificationErrorCode::Cease(CeaseSubErr::ConnectionCollisionResolution),
+                vec![],
+            )),
+            PeerDownReason::LocalClose | PeerDownReason::ConnectionLost => None,
+        }
+    }
+}
+
+/// The shutdown a Cease the router sent stands for, `None` for those reporting errors
+#[cfg(feature = "bmp")]
+fn local_shutdown(
+    subcode: CeaseSubErr,
+    notification: &NotificationMessage,
+) -> Option<ShutdownReason> {
+    let message = match SessionEnd::from_notification(notification) {
+        Some(SessionEnd::RemoteCease { message, .. }) => message,
+        _ => None,
+    };
+    Some(match subcode {
+        CeaseSubErr::AdministrativeShutdown => ShutdownReason::AdministrativeShutdown(message),
+        CeaseSubErr::PeerDeconfigured => ShutdownReason::PeerDeconfigured,
+        CeaseSubErr::AdministrativeReset => ShutdownReason::AdministrativeReset(message),
+        CeaseSubErr::OtherConfigurationChange => ShutdownReason::OtherConfigurationChange,
+        CeaseSubErr::OutOfResources => ShutdownReason::OutOfResources,
+        CeaseSubErr::HardReset => ShutdownReason::HardReset,
+        _ => return None,
+    })
+}
+
+#[cfg(all(test, feature = "bmp"))]
+mod test {
+    use super::*;
+
+    /// A peer going down over BMP is reported as the same peer would be over a session
+    #[test]
+    fn test_bmp_down_reasons() {
+        let admin = ShutdownReason::AdministrativeShutdown(Some("maintenance".to_string()));
+        for (bmp_reason, reason) in [
+            (
+                bmp::PeerDownReason::LocalNotification(admin.notification()),
+                PeerDownReason::LocalShutdown(admin.clone()),
+            ),
+            (
+                bmp::PeerDownReason::LocalNotification(NotificationMessage::new(
+                    NotificationErrorCode::HoldTimeExpired,
+                    vec![],
+                )),
+                PeerDownReason::HoldTimerExpired,
+            ),
+            (
+                bmp::PeerDownReason::LocalNoNotification { fsm_event: 10 },
+                PeerDownReason::HoldTimerExpired,
+            ),
+            (
+                bmp::PeerDownReason::LocalNoNotification { fsm_event: 18 },
+                PeerDownReason::LocalClose,
+            ),
+            (
+                bmp::PeerDownReason::RemoteNotification(
+                    PeerDownReason::CollisionResolution.notification().unwrap(),
+                ),
+                PeerDownReason::CollisionResolution,
+            ),
+            (
+                bmp::PeerDownReason::RemoteNoNotification,
+                PeerDownReason::ConnectionLost,
+            ),
+            (
+                bmp::PeerDownReason::Deconfigured,
+                PeerDownReason::LocalShutdown(ShutdownReason::PeerDeconfigured),
+            ),
+            (
+                bmp::PeerDownReason::LocalClosed(vec![]),
+                PeerDownReason::LocalClose,
+            ),
+        ] {
+            assert_eq!(
+                PeerDownReason::from_bmp(bmp_reason.clone()),
+                reason,
+                "{bmp_reason:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The events of peers going up and down, shared by sessions, BMP monitoring and archives
+//! without needing the `tokio` session machinery.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+mod lifecycle;
+mod negotiated;
+pub(crate) mod shutdown;
+
+pub use lifecycle::{PeerDown, PeerDownReason, PeerUp};
+pub use negotiated::Negotiated;
+pub use shutdown::{SessionEnd, ShutdownReason};
+
+/// Identifies the peer an observed event came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub peer_addr: SocketAddr,
+    /// The peer's ASN, 4 octet when the peer supports it
+    pub asn: u32,
+    pub router_id: Ipv4Addr,
+    /// The BMP router the peer is monitored through, `None` for our own sessions
+    pub router: Option<IpAddr>,
+}
@@ -0,0 +1,184 @@
+use crate::address_family::{Afi, Safi};
+use crate::capability::{AddPathDirection, AddPathFamily, Capability};
+use crate::open_message::OpenMessage;
+use crate::update_message::ParserConfig;
+
+/// Session parameters agreed from both OPEN messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated {
+    /// Lower of both proposed hold times, zero disables keepalives and the hold timer
+    pub hold_time: u16,
+    pub four_octet_as: bool,
+    pub route_refresh: bool,
+    /// Re-advertisements are bracketed by BoRR/EoRR (RFC 7313)
+    pub enhanced_route_refresh: bool,
+    /// Address families both sides advertised, IPv4 unicast when either omits Multiprotocol
+    pub families: Vec<(Afi, Safi)>,
+    /// ADD-PATH directions from our point of view
+    pub add_path: Vec<AddPathFamily>,
+    /// Both sides advertised Graceful Restart (RFC 4724), so the peer's routes are kept as
+    /// stale when the session drops without a NOTIFICATION
+    pub graceful_restart: bool,
+}
+
+impl Negotiated {
+    pub fn new(local_open: &OpenMessage, remote_open: &OpenMessage) -> Self {
+        let local = local_open.capabilities().unwrap_or_default();
+        let remote = remote_open.capabilities().unwrap_or_default();
+        let both = |code: u8| {
+            local.iter().any(|capability| capability.code() == code)
+                && remote.iter().any(|capability| capability.code() == code)
+        };
+
+        let local_families = families(&local);
+        let remote_families = families(&remote);
+        let families = local_families
+            .into_iter()
+            .filter(|family| remote_families.contains(family))
+            .collect();
+
+        Negotiated {
+            hold_time: local_open.hold_time.min(remote_open.hold_time),
+            four_octet_as: both(Capability::FOUR_OCTET_AS),
+            route_refresh: both(Capability::ROUTE_REFRESH),
+            enhanced_route_refresh: both(Capability::ROUTE_REFRESH)
+                && both(Capability::ENHANCED_ROUTE_REFRESH),
+            families,
+            add_path: add_path(&local, &remote),
+            graceful_restart: both(Capability::GRACEFUL_RESTART),
+        }
+    }
+
+    /// Whether the peer may send us multiple paths per prefix for this family
+    pub fn add_path_receive(&self, afi: Afi, safi: Safi) -> bool {
+        self.add_path.iter().any(|family| {
+            family.afi == afi && family.safi == safi && family.direction.can_receive()
+        })
+    }
+
+    /// How the peer encodes its UPDATEs: the ASN width, and path identifiers in the families
+    /// it may send multiple paths for
+    pub fn parser_config(&self) -> ParserConfig {
+        ParserConfig {
+            four_octet_as: self.four_octet_as,
+            add_path: self
+                .add_path
+                .iter()
+                .filter(|family| family.direction.can_receive())
+                .map(|family| (family.afi, family.safi))
+                .collect(),
+            ..ParserConfig::default()
+        }
+    }
+}
+
+fn families(capabilities: &[Capability]) -> Vec<(Afi, Safi)> {
+    let families: Vec<(Afi, Safi)> = capabilities
+        .iter()
+        .filter_map(|capability| match capability {
+            Capability::MultiProtocol { afi, safi } => Some((*afi, *safi)),
+            _ => None,
+        })
+        .collect();
+
+    if families.is_empty() {
+        return vec![(Afi::Ipv4, Safi::Unicast)];
+    }
+    families
+}
+
+fn add_path_families(capabilities: &[Capability]) -> impl Iterator<Item = &AddPathFamily> {
+    capabilities.iter().flat_map(|capability| match capability {
+        Capability::AddPath(families) => families.as_slice(),
+        _ => &[],
+    })
+}
+
+fn add_path(local: &[Capability], remote: &[Capability]) -> Vec<AddPathFamily> {
+    add_path_families(local)
+        .filter_map(|ours| {
+            let theirs = add_path_families(remote)
+                .find(|theirs| theirs.afi == ours.afi && theirs.safi == ours.safi)?;
+            let send = ours.direction.can_send() && theirs.direction.can_receive();
+            let receive = ours.direction.can_receive() && theirs.direction.can_send();
+            let direction = match (send, receive) {
+                (true, true) => AddPathDirection::Both,
+                (true, false) => AddPathDirection::Send,
+                (false, true) => AddPathDirection::Receive,
+                (false, false) => return None,
+            };
+            Some(AddPathFamily {
+                afi: ours.afi,
+                safi: ours.safi,
+                direction,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn open(hold_time: u16, capabilities: &[Capability]) -> OpenMessage {
+        OpenMessage::new(65000, hold_time, Ipv4Addr::new(10, 0, 0, 1), capabilities)
+    }
+
+    #[test]
+    fn test_negotiate_capabilities() {
+        let add_path = |direction| {
+            Capability::AddPath(vec![AddPathFamily {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                direction,
+            }])
+        };
+        let local = open(
+            90,
+            &[
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv4,
+                    safi: Safi::Unicast,
+                },
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv6,
+                    safi: Safi::Unicast,
+                },
+                Capability::RouteRefresh,
+                add_path(AddPathDirection::Both),
+            ],
+        );
+        let remote = open(
+            30,
+            &[
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv4,
+                    safi: Safi::Unicast,
+                },
+                Capability::FourOctetAs { asn: 65010 },
+                add_path(AddPathDirection::Send),
+            ],
+        );
+
+        let negotiated = Negotiated::new(&local, &remote);
+        assert_eq!(negotiated.hold_time, 30);
+        assert!(!negotiated.route_refresh);
+        assert!(!negotiated.four_octet_as);
+        assert_eq!(negotiated.families, vec![(Afi::Ipv4, Safi::Unicast)]);
+        assert_eq!(negotiated.add_path[0].direction, AddPathDirection::Receive);
+        assert!(negotiated.add_path_receive(Afi::Ipv4, Safi::Unicast));
+        let config = negotiated.parser_config();
+        assert!(!config.four_octet_as);
+        assert!(config.add_path.contains(Afi::Ipv4, Safi::Unicast));
+        assert!(!config.add_path.contains(Afi::Ipv6, Safi::Unicast));
+    }
+
+    #[test]
+    fn test_negotiate_implicit_ipv4() {
+        let negotiated = Negotiated::new(&open(0, &[]), &open(90, &[]));
+        assert_eq!(negotiated.hold_time, 0);
+        assert_eq!(negotiated.families, vec![(Afi::Ipv4, Safi::Unicast)]);
+        assert!(negotiated.add_path.is_empty());
+    }
+}
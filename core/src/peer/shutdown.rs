@@ -0,0 +1,159 @@
+use crate::address_family::{Afi, Safi};
+use crate::notification_message::{CeaseSubErr, NotificationErrorCode, NotificationMessage};
+
+/// Why we are closing a session, each mapping to a Cease subcode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Optionally carries an RFC 9003 shutdown communication
+    AdministrativeShutdown(Option<String>),
+    PeerDeconfigured,
+    /// Optionally carries an RFC 9003 shutdown communication
+    AdministrativeReset(Option<String>),
+    OtherConfigurationChange,
+    OutOfResources,
+    HardReset,
+    /// The peer sent more routes of a family than the configured limit
+    MaximumPrefixes {
+        afi: Afi,
+        safi: Safi,
+        limit: u32,
+    },
+}
+
+/// How a session ended without an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The session handle was closed or dropped
+    Closed,
+    LocalShutdown(ShutdownReason),
+    /// The peer sent a Cease NOTIFICATION; `subcode` is `None` when unspecified or unknown
+    RemoteCease {
+        subcode: Option<CeaseSubErr>,
+        message: Option<String>,
+    },
+}
+
+impl ShutdownReason {
+    /// Longest shutdown communication RFC 9003 allows, in octets
+    pub const MAX_MESSAGE_LEN: usize = 255;
+
+    pub fn subcode(&self) -> CeaseSubErr {
+        match self {
+            ShutdownReason::AdministrativeShutdown(_) => CeaseSubErr::AdministrativeShutdown,
+            ShutdownReason::PeerDeconfigured => CeaseSubErr::PeerDeconfigured,
+            ShutdownReason::AdministrativeReset(_) => CeaseSubErr::AdministrativeReset,
+            ShutdownReason::OtherConfigurationChange => CeaseSubErr::OtherConfigurationChange,
+            ShutdownReason::OutOfResources => CeaseSubErr::OutOfResources,
+            ShutdownReason::HardReset => CeaseSubErr::HardReset,
+            ShutdownReason::MaximumPrefixes { .. } => CeaseSubErr::MaximumNumberOfPrefixesReached,
+        }
+    }
+
+    pub fn notification(&self) -> NotificationMessage {
+        let data = match self {
+            ShutdownReason::AdministrativeShutdown(Some(message))
+            | ShutdownReason::AdministrativeReset(Some(message)) => encode_communication(message),
+            // AFI, SAFI and the upper bound (RFC 4486)
+            ShutdownReason::MaximumPrefixes { afi, safi, limit } => {
+                let mut data = Vec::with_capacity(7);
+                data.extend_from_slice(&u16::from(*afi).to_be_bytes());
+                data.push(u8::from(*safi));
+                data.extend_from_slice(&limit.to_be_bytes());
+                data
+            }
+            _ => vec![],
+        };
+
+        NotificationMessage::new(NotificationErrorCode::Cease(self.subcode()), data)
+    }
+}
+
+impl SessionEnd {
+    /// Interprets a received NOTIFICATION, `None` when it isn't a Cease
+    pub fn from_notification(notification: &NotificationMessage) -> Option<Self> {
+        let subcode = match notification.error_codes {
+            NotificationErrorCode::Cease(subcode) => Some(subcode),
+            NotificationErrorCode::Unknown(6, _) => None,
+            _ => return None,
+        };
+
+        let message = match subcode {
+            Some(CeaseSubErr::AdministrativeShutdown | CeaseSubErr::AdministrativeReset) => {
+                decode_communication(&notification.data)
+            }
+            _ => None,
+        };
+
+        Some(SessionEnd::RemoteCease { subcode, message })
+    }
+}
+
+/// Length prefixed UTF-8, truncated on a character boundary to fit the length octet
+pub(crate) fn encode_communication(message: &str) -> Vec<u8> {
+    let mut end = message.len().min(ShutdownReason::MAX_MESSAGE_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut data = Vec::with_capacity(end + 1);
+    data.push(end as u8);
+    data.extend_from_slice(&message.as_bytes()[..end]);
+    data
+}
+
+fn decode_communication(data: &[u8]) -> Option<String> {
+    let (&length, message) = data.split_first()?;
+    let message = message.get(..length as usize)?;
+    if message.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(message).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_communication() {
+        let reason = ShutdownReason::AdministrativeShutdown(Some("maintenance".to_string()));
+        let notification = reason.notification();
+        assert_eq!(notification.data[0], 11);
+        assert_eq!(
+            SessionEnd::from_notification(&notification),
+            Some(SessionEnd::RemoteCease {
+                subcode: Some(CeaseSubErr::AdministrativeShutdown),
+                message: Some("maintenance".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_maximum_prefixes_data() {
+        let reason = ShutdownReason::MaximumPrefixes {
+            afi: Afi::Ipv6,
+            safi: Safi::Unicast,
+            limit: 100_000,
+        };
+        let notification = reason.notification();
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::Cease(CeaseSubErr::MaximumNumberOfPrefixesReached)
+        );
+        assert_eq!(notification.data, vec![0, 2, 1, 0, 1, 0x86, 0xa0]);
+    }
+
+    #[test]
+    fn test_shutdown_communication_truncated() {
+        let message = "é".repeat(200);
+        let data = encode_communication(&message);
+        assert_eq!(data[0], 254);
+        assert_eq!(data.len(), 255);
+    }
+
+    #[test]
+    fn test_non_cease_notification() {
+        let notification = NotificationMessage::new(NotificationErrorCode::HoldTimeExpired, vec![]);
+        assert_eq!(SessionEnd::from_notification(&notification), None);
+    }
+}
@@ -0,0 +1,431 @@
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::attribute::AsPath;
+use crate::monitor::origin_as;
+use crate::rpki::RpkiStatus;
+use crate::update_message::IpAddrPrefix;
+
+use super::show::Fields;
+use super::{AttributeSet, RibSnapshot, RouteEvent, RouteEventKind};
+
+/// The columns of [`RibSnapshot::write_csv`], in order. New columns are only ever appended.
+pub const SNAPSHOT_CSV_COLUMNS: [&str; 10] = [
+    "prefix",
+    "prefix_len",
+    "peer",
+    "origin_as",
+    "as_path",
+    "next_hop",
+    "med",
+    "local_pref",
+    "communities",
+    "timestamp",
+];
+
+/// The columns of [`RouteEventCsvWriter`], in order. New columns are only ever appended.
+pub const EVENT_CSV_COLUMNS: [&str; 13] = [
+    "timestamp",
+    "kind",
+    "peer",
+    "prefix",
+    "prefix_len",
+    "path_id",
+    "origin_as",
+    "as_path",
+    "next_hop",
+    "med",
+    "local_pref",
+    "communities",
+    "rpki",
+];
+
+impl RibSnapshot {
+    /// Writes a header line and a line per route of `peer`'s RIB.
+    ///
+    /// AS paths and communities are space separated, AS_SETs written as `{a,b}`, and
+    /// timestamps are when the route last changed in RFC 3339 UTC, empty for version 1
+    /// snapshots. Fields are quoted as RFC 4180 describes, so AS_SETs come out quoted.
+    pub fn write_csv<W: Write>(&self, peer: IpAddr, mut writer: W) -> io::Result<()> {
+        let routes = self
+            .routes()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        write_record(&mut writer, SNAPSHOT_CSV_COLUMNS)?;
+        for route in routes {
+            let attributes = Attributes::new(&route.key.prefix, &route.attributes);
+            let timestamp = route
+                .ages
+                .map(|(_, changed)| rfc3339(UNIX_EPOCH + Duration::from_millis(changed)))
+                .unwrap_or_default();
+            write_record(
+                &mut writer,
+                [
+                    route.key.prefix.addr().to_string(),
+                    route.key.prefix.length().to_string(),
+                    peer.to_string(),
+                    attributes.origin_as,
+                    attributes.as_path,
+                    attributes.next_hop,
+                    attributes.med,
+                    attributes.local_pref,
+                    attributes.communities,
+                    timestamp,
+                ],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes [`RouteEvent`]s as CSV as they happen, one line each after a header line.
+///
+/// Withdrawals leave the attribute columns empty, re-announcements carry the new
+/// attributes. Formatting is as for [`RibSnapshot::write_csv`].
+#[derive(Debug)]
+pub struct RouteEventCsvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RouteEventCsvWriter<W> {
+    /// Writes the header line
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        write_record(&mut writer, EVENT_CSV_COLUMNS)?;
+        Ok(RouteEventCsvWriter { writer })
+    }
+
+    pub fn write(&mut self, event: &RouteEvent) -> io::Result<()> {
+        let (kind, attributes) = match &event.kind {
+            RouteEventKind::Announced { attrs } => ("announced", Some(attrs)),
+            RouteEventKind::Withdrawn => ("withdrawn", None),
+            RouteEventKind::Reannounced { new_attrs, .. } => ("reannounced", Some(new_attrs)),
+        };
+        let attributes = attributes
+            .map(|attributes| Attributes::new(&event.prefix, attributes))
+            .unwrap_or_default();
+        let rpki = match event.rpki {
+            Some(RpkiStatus::Valid) => "valid",
+            Some(RpkiStatus::Invalid) => "invalid",
+            Some(RpkiStatus::NotFound) => "not_found",
+            None => "",
+        };
+        write_record(
+            &mut self.writer,
+            [
+                rfc3339(event.timestamp),
+                kind.to_owned(),
+                event.peer.to_string(),
+                event.prefix.addr().to_string(),
+                event.prefix.length().to_string(),
+                event
+                    .path_id
+                    .map(|path_id| path_id.to_string())
+                    .unwrap_or_default(),
+                attributes.origin_as,
+                attributes.as_path,
+                attributes.next_hop,
+                attributes.med,
+                attributes.local_pref,
+                attributes.communities,
+                rpki.to_owned(),
+            ],
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// The attribute columns, formatted
+#[derive(Default)]
+struct Attributes {
+    origin_as: String,
+    as_path: String,
+    next_hop: String,
+    med: String,
+    local_pref: String,
+    communities: String,
+}
+
+impl Attributes {
+    fn new(prefix: &IpAddrPrefix, attributes: &AttributeSet) -> Self {
+        let fields = Fields::new(attributes);
+        let communities: Vec<_> = fields
+            .communities
+            .iter()
+            .map(|community| format!("{}:{}", community.asn, community.value))
+            .collect();
+        Attributes {
+            origin_as: origin_as(attributes)
+                .map(|asn| asn.to_string())
+                .unwrap_or_default(),
+            as_path: fields.as_path.map(AsPath::to_string).unwrap_or_default(),
+            next_hop: fields.next_hop(prefix.afi()).to_string(),
+            med: fields.med.map(|med| med.to_string()).unwrap_or_default(),
+            local_pref: fields
+                .local_pref
+                .map(|pref| pref.to_string())
+                .unwrap_or_default(),
+            communities: communities.join(" "),
+        }
+    }
+}
+
+fn write_record<W: Write, S: AsRef<str>>(
+    writer: &mut W,
+    fields: impl IntoIterator<Item = S>,
+) -> io::Result<()> {
+    let mut line = String::new();
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\r', '\n']) {
+            line.push('"');
+            line.push_str(&field.replace('"', "\"\""));
+            line.push('"');
+        } else {
+            line.push_str(field);
+        }
+    }
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// `2024-01-31T12:00:00.250Z`, times before the epoch as the epoch
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days since the epoch, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::address_family::Safi;
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, Community};
+    use crate::rib::{RibIn, RouteEventSource};
+    use crate::update_message::UpdateMessageBuilder;
+
+    /// Splits RFC 4180 CSV into records, independently of the writer
+    fn parse(text: &str) -> Vec<Vec<String>> {
+        let mut records = vec![];
+        let mut record = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') => quoted = true,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (false, c) => field.push(c),
+            }
+        }
+        assert!(field.is_empty() && record.is_empty(), "unterminated record");
+        records
+    }
+
+    fn update() -> UpdateMessageBuilder {
+        UpdateMessageBuilder::new()
+            .as_path(AsPath {
+                segments: [
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSequence,
+                        asns: [65001, 4_200_000_000].into(),
+                    },
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSet,
+                        asns: [64511, 64512].into(),
+                    },
+                ]
+                .into(),
+            })
+            .next_hop("192.0.2.1".parse().unwrap())
+            .med(10)
+            .community(Community {
+                asn: 65001,
+                value: 100,
+            })
+            .community(Community {
+                asn: 65001,
+                value: 200,
+            })
+    }
+
+    #[test]
+    fn test_snapshot_csv() {
+        let mut rib = RibIn::new();
+        rib.apply(&update().announce("192.0.2.0/24".parse().unwrap()).build());
+        rib.apply(
+            &UpdateMessageBuilder::new()
+                .as_path(AsPath {
+                    segments: [AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSequence,
+                        asns: [65001].into(),
+                    }]
+                    .into(),
+                })
+                .next_hop("2001:db8::1".parse().unwrap())
+                .local_pref(200)
+                .announce("2001:db8::/32".parse().unwrap())
+                .build(),
+        );
+        let before = SystemTime::now() - Duration::from_secs(1);
+
+        let mut out = vec![];
+        rib.snapshot()
+            .write_csv(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), &mut out)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut records = parse(&text);
+        records.sort();
+
+        assert_eq!(records[2], SNAPSHOT_CSV_COLUMNS);
+        assert_eq!(
+            records[..2].iter().map(|r| &r[..9]).collect::<Vec<_>>(),
+            [
+                vec![
+                    "192.0.2.0",
+                    "24",
+                    "10.0.0.1",
+                    "",
+                    "65001 4200000000 {64511,64512}",
+                    "192.0.2.1",
+                    "10",
+                    "",
+                    "65001:100 65001:200",
+                ],
+                vec![
+                    "2001:db8::",
+                    "32",
+                    "10.0.0.1",
+                    "65001",
+                    "65001",
+                    "2001:db8::1",
+                    "",
+                    "200",
+                    "",
+                ],
+            ]
+        );
+        // The AS_SET's comma is quoted
+        assert!(text.contains(",\"65001 4200000000 {64511,64512}\","));
+        for record in &records[..2] {
+            assert!(record[9] > rfc3339(before), "{record:?}");
+        }
+    }
+
+    #[test]
+    fn test_event_csv() {
+        let mut source = RouteEventSource::with_rib(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let mut events = source.events(
+            &update().announce("192.0.2.0/24".parse().unwrap()).build(),
+            at,
+        );
+        events.extend(
+            source.events(
+                &update()
+                    .med(20)
+                    .announce("192.0.2.0/24".parse().unwrap())
+                    .build(),
+                at + Duration::from_secs(1),
+            ),
+        );
+        events.extend(
+            source.events(
+                &UpdateMessageBuilder::new()
+                    .withdraw("192.0.2.0/24".parse().unwrap())
+                    .build(),
+                at + Duration::from_secs(2),
+            ),
+        );
+        events[0].path_id = Some(7);
+        events[0].rpki = Some(RpkiStatus::NotFound);
+        assert_eq!(events[0].safi, Safi::Unicast);
+
+        let mut writer = RouteEventCsvWriter::new(vec![]).unwrap();
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        let text = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(
+            text,
+            "timestamp,kind,peer,prefix,prefix_len,path_id,origin_as,as_path,next_hop,med,\
+             local_pref,communities,rpki\n\
+             2023-11-14T22:13:20.250Z,announced,10.0.0.1,192.0.2.0,24,7,,\
+             \"65001 4200000000 {64511,64512}\",192.0.2.1,10,,65001:100 65001:200,not_found\n\
+             2023-11-14T22:13:21.250Z,reannounced,10.0.0.1,192.0.2.0,24,,,\
+             \"65001 4200000000 {64511,64512}\",192.0.2.1,20,,65001:100 65001:200,\n\
+             2023-11-14T22:13:22.250Z,withdrawn,10.0.0.1,192.0.2.0,24,,,,,,,,\n"
+        );
+        let records = parse(&text);
+        assert_eq!(records.len(), 4);
+        assert!(
+            records
+                .iter()
+                .all(|record| record.len() == EVENT_CSV_COLUMNS.len())
+        );
+        assert_eq!(records[1][7], "65001 4200000000 {64511,64512}");
+    }
+
+    #[test]
+    fn test_quoting_and_dates() {
+        let mut out = vec![];
+        write_record(&mut out, ["plain", "a,b", "say \"hi\"", "two\nlines", ""]).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\n");
+        assert_eq!(
+            parse(&text),
+            [["plain", "a,b", "say \"hi\"", "two\nlines", ""]]
+        );
+
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(4_102_444_799)),
+            "2099-12-31T23:59:59.000Z"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! The RFC 4271 decision process as a comparison of two paths, for ranking candidates or
+//! checking policy outcomes without feeding a [`LocRib`](super::LocRib).
+
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
+use crate::attribute::{Attributes, OriginType, PathAttribute};
+
+use super::{DecisionConfig, RibPeer};
+
+/// LOCAL_PREF assumed for paths without one, as routers do for eBGP-learned routes
+const DEFAULT_LOCAL_PREF: u32 = 100;
+
+/// What the decision process compares of one path: values from its attributes, with
+/// defaults for missing ones, and the peer it was learned from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteAttrs {
+    pub local_pref: u32,
+    /// See [`AsPath::path_len`](crate::message::AsPath::path_len)
+    pub as_path_len: u32,
+    pub origin: OriginType,
+    pub med: Option<u32>,
+    /// The AS the path was learned from, whose paths MED is compared between
+    pub neighbor_as: Option<u32>,
+    /// NEXT_HOP, or the MP_REACH_NLRI next hop without one
+    pub next_hop: Option<IpAddr>,
+    pub peer: RibPeer,
+}
+
+/// How paths are compared beyond their own values
+#[derive(Clone, Copy, Default)]
+pub struct ComparisonCtx<'a> {
+    pub config: DecisionConfig,
+    /// The IGP cost of reaching a path's next hop, lower being preferred. Without it the
+    /// step is skipped.
+    pub igp_metric: Option<&'a dyn Fn(&RouteAttrs) -> u32>,
+}
+
+/// A tie-break of the decision process, in the order they apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecisionStep {
+    /// Highest LOCAL_PREF
+    LocalPref,
+    /// Shortest AS_PATH, unless [`DecisionConfig::ignore_as_path_length`]
+    AsPathLength,
+    /// Lowest ORIGIN
+    Origin,
+    /// Lowest MED, between paths from the same neighbor AS unless
+    /// [`DecisionConfig::always_compare_med`]. A missing MED counts as 0, or as the worst with
+    /// [`DecisionConfig::missing_med_as_worst`].
+    Med,
+    /// eBGP over iBGP
+    External,
+    /// Lowest [`ComparisonCtx::igp_metric`]
+    IgpMetric,
+    /// Lowest router ID
+    RouterId,
+    /// Lowest peer address
+    PeerAddr,
+}
+
+impl DecisionStep {
+    pub const ALL: [DecisionStep; 8] = [
+        DecisionStep::LocalPref,
+        DecisionStep::AsPathLength,
+        DecisionStep::Origin,
+        DecisionStep::Med,
+        DecisionStep::External,
+        DecisionStep::IgpMetric,
+        DecisionStep::RouterId,
+        DecisionStep::PeerAddr,
+    ];
+
+    /// Orders two paths by this step alone, the preferred one first
+    pub fn compare(self, a: &RouteAttrs, b: &RouteAttrs, ctx: &ComparisonCtx) -> Ordering {
+        match self {
+            DecisionStep::LocalPref => b.local_pref.cmp(&a.local_pref),
+            DecisionStep::AsPathLength if ctx.config.ignore_as_path_length => Ordering::Equal,
+            DecisionStep::AsPathLength => a.as_path_len.cmp(&b.as_path_len),
+            DecisionStep::Origin => a.origin.cmp(&b.origin),
+            DecisionStep::Med
+                if ctx.config.always_compare_med || a.neighbor_as == b.neighbor_as =>
+            {
+                let missing = match ctx.config.missing_med_as_worst {
+                    true => u32::MAX,
+                    false => 0,
+                };
+                a.med.unwrap_or(missing).cmp(&b.med.unwrap_or(missing))
+            }
+            DecisionStep::Med => Ordering::Equal,
+            DecisionStep::External => b.peer.external.cmp(&a.peer.external),
+            DecisionStep::IgpMetric => match ctx.igp_metric {
+                Some(metric) => metric(a).cmp(&metric(b)),
+                None => Ordering::Equal,
+            },
+            DecisionStep::RouterId => a.peer.router_id.cmp(&b.peer.router_id),
+            DecisionStep::PeerAddr => a.peer.addr.cmp(&b.peer.addr),
+        }
+    }
+}
+
+impl RouteAttrs {
+    pub fn new(peer: RibPeer, attributes: &[PathAttribute]) -> Self {
+        let attributes = Attributes::new(attributes);
+        let as_path = attributes.as_path();
+        RouteAttrs {
+            local_pref: attributes
+                .local_pref()
+                .map_or(DEFAULT_LOCAL_PREF, |local_pref| local_pref.pref),
+            as_path_len: as_path.map_or(0, |as_path| as_path.path_len()),
+            origin: attributes
+                .origin()
+                .map_or(OriginType::Incomplete, |origin| origin.origin_type),
+            med: attributes.med().map(|med| med.med),
+            neighbor_as: as_path.and_then(|as_path| as_path.first_hop()),
+            next_hop: attributes
+                .next_hop()
+                .map(|next_hop| IpAddr::V4(next_hop.ip))
+                .or_else(|| attributes.mp_reach().map(|mp_reach| mp_reach.next_hop)),
+            peer,
+        }
+    }
+}
+
+/// Orders two paths of the same route, the preferred one first, by the first
+/// [`DecisionStep`] that tells them apart.
+///
+/// Only paths from the same peer compare equal. Comparing MED between paths from the same
+/// neighbor AS only can make the order cyclic, so use [`rank_paths`] to sort more than two.
+pub fn compare_paths(a: &RouteAttrs, b: &RouteAttrs, ctx: &ComparisonCtx) -> Ordering {
+    DecisionStep::ALL
+        .iter()
+        .map(|step| step.compare(a, b, ctx))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The indices of `paths` from the most to the least preferred.
+///
+/// Paths from the same neighbor AS are ranked among themselves first, MED included, and the
+/// groups then merged by comparing their best remaining paths, as deterministic MED does.
+/// Unlike sorting with [`compare_paths`] this is a total order whatever the MEDs.
+pub fn rank_paths(paths: &[RouteAttrs], ctx: &ComparisonCtx) -> Vec<usize> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (i, path) in paths.iter().enumerate() {
+        let same_as = |group: &&mut Vec<usize>| paths[group[0]].neighbor_as == path.neighbor_as;
+        match groups.iter_mut().find(same_as) {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    for group in &mut groups {
+        group.sort_by(|&a, &b| compare_paths(&paths[a], &paths[b], ctx));
+        group.reverse();
+    }
+
+    let mut ranked = Vec::with_capacity(paths.len());
+    while let Some(best) = (0..groups.len()).reduce(|best, i| {
+        let (a, b) = (groups[i].last().unwrap(), groups[best].last().unwrap());
+        match compare_paths(&paths[*a], &paths[*b], ctx) {
+            Ordering::Less => i,
+            _ => best,
+        }
+    }) {
+        ranked.push(groups[best].pop().unwrap());
+        if groups[best].is_empty() {
+            groups.swap_remove(best);
+        }
+    }
+    ranked
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn path(n: u8, neighbor_as: u32) -> RouteAttrs {
+        RouteAttrs {
+            local_pref: DEFAULT_LOCAL_PREF,
+            as_path_len: 1,
+            origin: OriginType::Igp,
+            med: None,
+            neighbor_as: Some(neighbor_as),
+            next_hop: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, n))),
+            peer: RibPeer {
+                addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)),
+                asn: neighbor_as,
+                router_id: Ipv4Addr::new(192, 0, 2, n),
+                external: true,
+            },
+        }
+    }
+
+    /// The step that decides between `a` and `b`, checking it orders them both ways round
+    fn deciding_step(a: &RouteAttrs, b: &RouteAttrs, ctx: &ComparisonCtx) -> DecisionStep {
+        assert_eq!(compare_paths(a, b, ctx), Ordering::Less);
+        assert_eq!(compare_paths(b, a, ctx), Ordering::Greater);
+        *DecisionStep::ALL
+            .iter()
+            .find(|step| step.compare(a, b, ctx).is_ne())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_steps() {
+        let ctx = ComparisonCtx::default();
+        let (a, b) = (path(1, 65001), path(2, 65001));
+        assert_eq!(deciding_step(&a, &b, &ctx), DecisionStep::RouterId);
+
+        let preferred = RouteAttrs {
+            local_pref: 200,
+            as_path_len: 5,
+            ..b
+        };
+        assert_eq!(deciding_step(&preferred, &a, &ctx), DecisionStep::LocalPref);
+
+        let short = RouteAttrs {
+            as_path_len: 0,
+            origin: OriginType::Incomplete,
+            ..b
+        };
+        assert_eq!(deciding_step(&short, &a, &ctx), DecisionStep::AsPathLength);
+
+        let egp = RouteAttrs {
+            origin: OriginType::Egp,
+            ..a
+        };
+        assert_eq!(deciding_step(&a, &egp, &ctx), DecisionStep::Origin);
+
+        let low_med = RouteAttrs { med: Some(0), ..b };
+        let high_med = RouteAttrs { med: Some(10), ..a };
+        assert_eq!(deciding_step(&low_med, &high_med, &ctx), DecisionStep::Med);
+
+        let mut internal = b;
+        internal.peer.external = false;
+        assert_eq!(deciding_step(&a, &internal, &ctx), DecisionStep::External);
+
+        let mut same_router = b;
+        same_router.peer.router_id = a.peer.router_id;
+        assert_eq!(
+            deciding_step(&a, &same_router, &ctx),
+            DecisionStep::PeerAddr
+        );
+
+        assert_eq!(compare_paths(&a, &a, &ctx), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_knobs() {
+        let long = RouteAttrs {
+            as_path_len: 3,
+            ..path(1, 65001)
+        };
+        let short = RouteAttrs {
+            origin: OriginType::Incomplete,
+            ..path(2, 65002)
+        };
+        let mut ctx = ComparisonCtx::default();
+        assert_eq!(
+            deciding_step(&short, &long, &ctx),
+            DecisionStep::AsPathLength
+        );
+        ctx.config.ignore_as_path_length = true;
+        assert_eq!(deciding_step(&long, &short, &ctx), DecisionStep::Origin);
+
+        // MED only counts between paths from another neighbor AS when always compared
+        let high_med = RouteAttrs {
+            med: Some(20),
+            ..path(1, 65001)
+        };
+        let low_med = RouteAttrs {
+            med: Some(10),
+            ..path(2, 65002)
+        };
+        let mut ctx = ComparisonCtx::default();
+        assert_eq!(
+            deciding_step(&high_med, &low_med, &ctx),
+            DecisionStep::RouterId
+        );
+        ctx.config.always_compare_med = true;
+        assert_eq!(deciding_step(&low_med, &high_med, &ctx), DecisionStep::Med);
+
+        // A missing MED counts as 0 unless it is the worst
+        let missing = path(2, 65001);
+        let ctx = ComparisonCtx::default();
+        assert_eq!(deciding_step(&missing, &high_med, &ctx), DecisionStep::Med);
+        let mut ctx = ComparisonCtx::default();
+        ctx.config.missing_med_as_worst = true;
+        assert_eq!(deciding_step(&high_med, &missing, &ctx), DecisionStep::Med);
+    }
+
+    #[test]
+    fn test_igp_metric() {
+        let (near, far) = (path(2, 65001), path(1, 65002));
+        let metric = |path: &RouteAttrs| match path.next_hop {
+            Some(IpAddr::V4(addr)) if addr.octets()[3] == 2 => 10,
+            _ => 20,
+        };
+        let ctx = ComparisonCtx {
+            igp_metric: Some(&metric),
+            ..ComparisonCtx::default()
+        };
+        assert_eq!(deciding_step(&near, &far, &ctx), DecisionStep::IgpMetric);
+
+        // The external step comes first
+        let mut internal = near;
+        internal.peer.external = false;
+        assert_eq!(deciding_step(&far, &internal, &ctx), DecisionStep::External);
+    }
+
+    #[test]
+    fn test_rank_paths() {
+        // Pairwise, a beats b on MED, b beats c and c beats a on router ID
+        let a = RouteAttrs {
+            med: Some(10),
+            ..path(3, 65001)
+        };
+        let b = RouteAttrs {
+            med: Some(20),
+            ..path(1, 65001)
+        };
+        let c = path(2, 65002);
+        let ctx = ComparisonCtx::default();
+        assert_eq!(compare_paths(&a, &b, &ctx), Ordering::Less);
+        assert_eq!(compare_paths(&b, &c, &ctx), Ordering::Less);
+        assert_eq!(compare_paths(&c, &a, &ctx), Ordering::Less);
+
+        // Deterministic MED settles 65001's paths first, whatever the input order
+        let paths = [a, b, c];
+        let ranked = |order: [usize; 3]| {
+            let shuffled = order.map(|i| paths[i]);
+            rank_paths(&shuffled, &ctx)
+                .into_iter()
+                .map(|i| order[i])
+                .collect::<Vec<_>>()
+        };
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0], [1, 0, 2]] {
+            assert_eq!(ranked(order), [2, 0, 1]);
+        }
+
+        // Without cycles the ranking is the sort order
+        let mut paths: Vec<_> = (1..=6)
+            .map(|n| RouteAttrs {
+                local_pref: 100 + u32::from(n % 3),
+                med: Some(u32::from(n)),
+                ..path(n, 65001)
+            })
+            .collect();
+        let ranked: Vec<_> = rank_paths(&paths, &ctx)
+            .into_iter()
+            .map(|i| paths[i])
+            .collect();
+        paths.sort_by(|a, b| compare_paths(a, b, &ctx));
+        assert_eq!(ranked, paths);
+        assert!(rank_paths(&[], &ctx).is_empty());
+    }
+}
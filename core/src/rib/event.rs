@@ -0,0 +1,310 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use crate::address_family::Safi;
+use crate::rpki::{RpkiStatus, SharedRoaTable};
+use crate::timestamped::Timestamped;
+use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+use super::rib_in::{announced_keys, attribute_set, withdrawn_keys};
+use super::{AttributeSet, RibChange, RibIn, RibKey};
+
+/// A change to a single route of a peer
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RouteEvent {
+    pub peer: IpAddr,
+    pub timestamp: SystemTime,
+    pub prefix: IpAddrPrefix,
+    pub safi: Safi,
+    pub path_id: Option<u32>,
+    pub kind: RouteEventKind,
+    /// Origin validation of the announced route, when the source validates
+    pub rpki: Option<RpkiStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RouteEventKind {
+    Announced {
+        attrs: AttributeSet,
+    },
+    Withdrawn,
+    /// The route was replaced by one with different attributes
+    Reannounced {
+        old_attrs: AttributeSet,
+        new_attrs: AttributeSet,
+    },
+}
+
+/// Turns the UPDATEs of one peer into [`RouteEvent`]s.
+///
+/// With a RIB, withdrawals of unknown routes are dropped and replacements are reported as
+/// [`RouteEventKind::Reannounced`]. Without one every announcement is reported as
+/// [`RouteEventKind::Announced`] and every withdrawal as [`RouteEventKind::Withdrawn`].
+#[derive(Debug, Clone)]
+pub struct RouteEventSource {
+    peer: IpAddr,
+    rib: Option<RibIn>,
+    suppress_duplicates: bool,
+    roas: Option<SharedRoaTable>,
+}
+
+impl RouteEvent {
+    fn new(peer: IpAddr, timestamp: SystemTime, key: RibKey, kind: RouteEventKind) -> Self {
+        RouteEvent {
+            peer,
+            timestamp,
+            prefix: key.prefix,
+            safi: key.safi,
+            path_id: key.path_id,
+            kind,
+            rpki: None,
+        }
+    }
+}
+
+impl RouteEventSource {
+    /// Keeps an Adj-RIB-In for the peer to tell replacements and duplicates apart
+    pub fn with_rib(peer: IpAddr) -> Self {
+        RouteEventSource {
+            peer,
+            rib: Some(RibIn::new()),
+            suppress_duplicates: false,
+            roas: None,
+        }
+    }
+
+    pub fn stateless(peer: IpAddr) -> Self {
+        RouteEventSource {
+            peer,
+            rib: None,
+            suppress_duplicates: false,
+            roas: None,
+        }
+    }
+
+    /// Drops re-announcements with unchanged attributes, as sent after a route refresh.
+    ///
+    /// Has no effect without a RIB.
+    pub fn suppress_duplicates(mut self, suppress: bool) -> Self {
+        self.suppress_duplicates = suppress;
+        self
+    }
+
+    /// Tags announcements with their origin validation against the current ROAs
+    pub fn validate_with(mut self, roas: SharedRoaTable) -> Self {
+        self.roas = Some(roas);
+        self
+    }
+
+    pub fn rib(&self) -> Option<&RibIn> {
+        self.rib.as_ref()
+    }
+
+    pub fn events(&mut self, update: &UpdateMessage, timestamp: SystemTime) -> Vec<RouteEvent> {
+        let mut events = self.route_events(update, timestamp);
+        if let Some(roas) = &self.roas {
+            let roas = roas.load();
+            for event in &mut events {
+                event.rpki = match &event.kind {
+                    RouteEventKind::Announced { attrs }
+                    | RouteEventKind::Reannounced {
+                        new_attrs: attrs, ..
+                    } => Some(roas.validate_route(&event.prefix, attrs)),
+                    RouteEventKind::Withdrawn => None,
+                };
+            }
+        }
+        events
+    }
+
+    /// Events of an UPDATE stamped with the time it was received
+    pub fn received(&mut self, update: &Timestamped<UpdateMessage>) -> Vec<RouteEvent> {
+        self.events(update, update.received)
+    }
+
+    fn route_events(&mut self, update: &UpdateMessage, timestamp: SystemTime) -> Vec<RouteEvent> {
+        let peer = self.peer;
+        let Some(rib) = &mut self.rib else {
+            let withdrawn = withdrawn_keys(update)
+                .map(|key| RouteEvent::new(peer, timestamp, key, RouteEventKind::Withdrawn));
+            let mut announced = announced_keys(update).peekable();
+            let attrs = announced
+                .peek()
+                .map(|_| attribute_set(&update.path_attributes));
+            let announced = announced.map(|key| {
+                let attrs = attrs.clone().unwrap();
+                RouteEvent::new(peer, timestamp, key, RouteEventKind::Announced { attrs })
+            });
+            return withdrawn.chain(announced).collect();
+        };
+
+        rib.apply(update)
+            .into_iter()
+            .filter_map(|change| {
+                let (key, kind) = match change {
+                    RibChange::Announced { key, attributes } => {
+                        (key, RouteEventKind::Announced { attrs: attributes })
+                    }
+                    RibChange::Unchanged { .. } if self.suppress_duplicates => return None,
+                    RibChange::Unchanged { key, attributes } => {
+                        (key, RouteEventKind::Announced { attrs: attributes })
+                    }
+                    RibChange::Replaced { key, old, new } => (
+                        key,
+                        RouteEventKind::Reannounced {
+                            old_attrs: old,
+                            new_attrs: new,
+                        },
+                    ),
+                    RibChange::Withdrawn { key, .. } => (key, RouteEventKind::Withdrawn),
+                };
+                Some(RouteEvent::new(peer, timestamp, key, kind))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::update_message::UpdateMessageBuilder;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn prefix() -> IpAddrPrefix {
+        IpAddrPrefix::new("198.51.100.0".parse().unwrap(), 24).unwrap()
+    }
+
+    fn announce(med: u32) -> UpdateMessage {
+        UpdateMessageBuilder::new()
+            .announce(prefix())
+            .next_hop(PEER)
+            .med(med)
+            .build()
+    }
+
+    fn withdraw() -> UpdateMessage {
+        UpdateMessageBuilder::new().withdraw(prefix()).build()
+    }
+
+    fn kinds(source: &mut RouteEventSource, update: &UpdateMessage) -> Vec<RouteEventKind> {
+        source
+            .events(update, SystemTime::UNIX_EPOCH)
+            .into_iter()
+            .map(|event| {
+                assert_eq!((event.peer, &event.prefix), (PEER, &prefix()));
+                event.kind
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_announcements() {
+        let mut source = RouteEventSource::with_rib(PEER);
+        let [RouteEventKind::Announced { attrs }] = &kinds(&mut source, &announce(10))[..] else {
+            panic!("expected an announcement");
+        };
+        let attrs = attrs.clone();
+
+        let duplicate = kinds(&mut source, &announce(10));
+        assert_eq!(duplicate, vec![RouteEventKind::Announced { attrs }]);
+
+        let mut suppressing = RouteEventSource::with_rib(PEER).suppress_duplicates(true);
+        assert_eq!(kinds(&mut suppressing, &announce(10)).len(), 1);
+        assert!(kinds(&mut suppressing, &announce(10)).is_empty());
+
+        let changed = kinds(&mut suppressing, &announce(20));
+        let [
+            RouteEventKind::Reannounced {
+                old_attrs,
+                new_attrs,
+            },
+        ] = &changed[..]
+        else {
+            panic!("expected a re-announcement, got {changed:?}");
+        };
+        assert_ne!(old_attrs, new_attrs);
+        assert_eq!(
+            kinds(&mut suppressing, &withdraw()),
+            vec![RouteEventKind::Withdrawn]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_of_unknown_prefix() {
+        let mut source = RouteEventSource::with_rib(PEER);
+        assert!(kinds(&mut source, &withdraw()).is_empty());
+
+        let mut stateless = RouteEventSource::stateless(PEER);
+        assert_eq!(
+            kinds(&mut stateless, &withdraw()),
+            vec![RouteEventKind::Withdrawn]
+        );
+        let announced = kinds(&mut stateless, &announce(10));
+        assert!(matches!(announced[..], [RouteEventKind::Announced { .. }]));
+        assert!(matches!(
+            kinds(&mut stateless, &announce(10))[..],
+            [RouteEventKind::Announced { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_receive_timestamp() {
+        let received = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let update = Timestamped::at(received, announce(10));
+        let events = RouteEventSource::with_rib(PEER).received(&update);
+        assert_eq!(events[0].timestamp, received);
+    }
+
+    #[test]
+    fn test_rpki_status() {
+        let roas = SharedRoaTable::new("198.51.100.0/22 24 AS64496".parse().unwrap());
+        let mut source = RouteEventSource::with_rib(PEER).validate_with(roas.clone());
+        fn status(
+            source: &mut RouteEventSource,
+            update: &UpdateMessage,
+        ) -> Vec<Option<RpkiStatus>> {
+            source
+                .events(update, SystemTime::UNIX_EPOCH)
+                .into_iter()
+                .map(|event| event.rpki)
+                .collect()
+        }
+
+        // The builder's AS_PATH is empty, so the route has no origin
+        assert_eq!(
+            status(&mut source, &announce(10)),
+            vec![Some(RpkiStatus::Invalid)]
+        );
+        roas.replace(Default::default());
+        assert_eq!(
+            status(&mut source, &announce(20)),
+            vec![Some(RpkiStatus::NotFound)]
+        );
+        assert_eq!(status(&mut source, &withdraw()), vec![None]);
+
+        let mut unvalidated = RouteEventSource::stateless(PEER);
+        assert_eq!(status(&mut unvalidated, &announce(10)), vec![None]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let mut source = RouteEventSource::with_rib(PEER);
+        let event = source
+            .events(&announce(10), SystemTime::UNIX_EPOCH)
+            .remove(0);
+        let serialized = toml::to_string(&event).unwrap();
+        assert!(serialized.contains("prefix = \"198.51.100.0/24\""));
+        assert!(serialized.contains("safi = \"unicast\""));
+        assert!(serialized.contains("[[kind.announced.attrs]]"));
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use crate::attribute::{Attributes, PathAttribute, semantic_eq};
+
+use super::{AttributeSet, attribute_set};
+
+/// Sweeps of dead entries wait until at least this many are held
+const MIN_SWEEP: usize = 1024;
+
+/// Hands out one shared [`AttributeSet`] for every set of path attributes meaning the same.
+///
+/// A full table holds a million prefixes but only tens of thousands of distinct attribute
+/// sets, so routes interned here share them rather than each holding a copy. Sets are looked
+/// up by [fingerprint](Attributes::fingerprint) and compared with
+/// [`semantic_eq`](crate::message::semantic_eq), so sets differing only in attribute order
+/// share the one interned first.
+///
+/// Sets are held weakly: one is dropped with the last route using it, and the interner
+/// forgets it on a later sweep.
+#[derive(Debug, Clone, Default)]
+pub struct AttrSetInterner {
+    sets: HashMap<u64, Vec<Weak<[PathAttribute]>>>,
+    /// Entries in `sets`, dropped sets included
+    entries: usize,
+    /// Entries that trigger the next sweep
+    sweep_at: usize,
+    hits: u64,
+    misses: u64,
+}
+
+/// How well an [`AttrSetInterner`] shares sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InternerStats {
+    /// Sets found already interned
+    pub hits: u64,
+    /// Sets interned anew
+    pub misses: u64,
+    /// Sets still used by some route
+    pub resident: usize,
+}
+
+impl AttrSetInterner {
+    pub fn new() -> Self {
+        AttrSetInterner::default()
+    }
+
+    /// The shared set of `path_attributes` as stored per route, without the prefixes of the
+    /// MP attributes
+    pub fn intern(&mut self, path_attributes: &[PathAttribute]) -> AttributeSet {
+        let attributes = attribute_set(path_attributes);
+        let fingerprint = Attributes::new(&attributes).fingerprint();
+        let bucket = self.sets.entry(fingerprint).or_default();
+        let interned = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|interned| semantic_eq(interned, &attributes));
+        if let Some(interned) = interned {
+            self.hits += 1;
+            return interned;
+        }
+
+        self.misses += 1;
+        bucket.push(Arc::downgrade(&attributes));
+        self.entries += 1;
+        if self.entries >= self.sweep_at.max(MIN_SWEEP) {
+            self.sweep();
+        }
+        attributes
+    }
+
+    /// Forgets the sets no route uses any more
+    pub fn sweep(&mut self) {
+        self.sets.retain(|_, bucket| {
+            bucket.retain(|set| set.strong_count() > 0);
+            !bucket.is_empty()
+        });
+        self.entries = self.sets.values().map(Vec::len).sum();
+        // Sweeping again once as many sets were added keeps the cost per set constant
+        self.sweep_at = self.entries * 2;
+    }
+
+    pub fn stats(&self) -> InternerStats {
+        InternerStats {
+            hits: self.hits,
+            misses: self.misses,
+            resident: self
+                .sets
+                .values()
+                .flatten()
+                .filter(|set| set.strong_count() > 0)
+                .count(),
+        }
+    }
+}
+
+impl InternerStats {
+    /// The share of sets found already interned, 0 before any
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::IpAddr;
+
+    use crate::attribute::{AttributeType, AttributeValue};
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    fn update(prefix: &str, next_hop: &str, med: u32) -> Vec<PathAttribute> {
+        let next_hop: IpAddr = next_hop.parse().unwrap();
+        UpdateMessageBuilder::new()
+            .announce(prefix.parse::<IpAddrPrefix>().unwrap())
+            .next_hop(next_hop)
+            .med(med)
+            .build()
+            .path_attributes
+    }
+
+    #[test]
+    fn test_shares_equal_sets() {
+        let mut interner = AttrSetInterner::new();
+        let a = interner.intern(&update("10.0.0.0/8", "192.0.2.1", 10));
+        let b = interner.intern(&update("10.1.0.0/16", "192.0.2.1", 10));
+        let c = interner.intern(&update("10.0.0.0/8", "192.0.2.1", 20));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+
+        // In another order
+        let mut reordered = update("10.0.0.0/8", "192.0.2.1", 10);
+        reordered.reverse();
+        assert!(Arc::ptr_eq(&a, &interner.intern(&reordered)));
+
+        // IPv6 prefixes in MP_REACH_NLRI aren't part of the set
+        let ipv6 = |prefix: &str| update(prefix, "2001:db8::1", 10);
+        let d = interner.intern(&ipv6("2001:db8:1::/48"));
+        assert!(Arc::ptr_eq(&d, &interner.intern(&ipv6("2001:db8:2::/48"))));
+        let mp_reach = d
+            .iter()
+            .find(|attribute| attribute.type_code == AttributeType::MpReachNlri)
+            .unwrap();
+        assert!(matches!(
+            &mp_reach.value,
+            AttributeValue::MpReachNlri(mp_reach) if mp_reach.nlri.is_empty()
+        ));
+
+        let stats = interner.stats();
+        assert_eq!((stats.hits, stats.misses, stats.resident), (3, 3, 3));
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_drops_unused_sets() {
+        let mut interner = AttrSetInterner::new();
+        let kept = interner.intern(&update("10.0.0.0/8", "192.0.2.1", 0));
+        for med in 1..=MIN_SWEEP as u32 {
+            interner.intern(&update("10.0.0.0/8", "192.0.2.1", med));
+        }
+        assert_eq!(interner.stats().resident, 1);
+        // The last sweep left only the kept set and those interned since
+        assert!(interner.entries < MIN_SWEEP);
+
+        interner.sweep();
+        assert_eq!(interner.entries, 1);
+        drop(kept);
+        interner.sweep();
+        assert_eq!(interner.entries, 0);
+        assert!(interner.sets.is_empty());
+    }
+}
@@ -0,0 +1,642 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::update_message::IpAddrPrefix;
+
+use super::{
+    AttributeSet, ComparisonCtx, RibChange, RibKey, RouteAttrs, compare_paths, rank_paths,
+};
+
+/// A peer feeding the Loc-RIB, with what the decision process needs to know about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RibPeer {
+    pub addr: IpAddr,
+    pub asn: u32,
+    pub router_id: Ipv4Addr,
+    /// Whether the session is eBGP rather than iBGP
+    pub external: bool,
+}
+
+/// A route as learned from one peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerPath {
+    pub peer: RibPeer,
+    pub attributes: AttributeSet,
+}
+
+/// Knobs of the decision process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecisionConfig {
+    /// Compares MED between paths from different neighbor ASes too
+    pub always_compare_med: bool,
+    /// Picks the best path of each neighbor AS first and then the best among those, so the
+    /// outcome doesn't depend on which paths happen to be compared with each other
+    pub deterministic_med: bool,
+    /// Treats a missing MED as the worst rather than as 0
+    pub missing_med_as_worst: bool,
+    /// Skips the AS_PATH length step
+    pub ignore_as_path_length: bool,
+}
+
+/// The best path of a route changed, `None` when there was or is no path at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestPathChange {
+    pub key: RibKey,
+    pub old: Option<PeerPath>,
+    pub new: Option<PeerPath>,
+}
+
+/// Loc-RIB: the best path per route across the Adj-RIB-Ins of several peers.
+///
+/// Paths are selected with the RFC 4271 decision process of [`compare_paths`]: highest
+/// LOCAL_PREF, shortest AS_PATH, lowest ORIGIN, lowest MED between paths from the same neighbor
+/// AS, eBGP over iBGP, lowest router ID and finally lowest peer address.
+#[derive(Debug, Clone, Default)]
+pub struct LocRib {
+    config: DecisionConfig,
+    routes: HashMap<RibKey, Candidates>,
+}
+
+#[derive(Debug, Clone)]
+struct Candidates {
+    /// Ordered by peer address, so that selection doesn't depend on arrival order
+    paths: Vec<PeerPath>,
+    best: usize,
+}
+
+impl LocRib {
+    pub fn new(config: DecisionConfig) -> Self {
+        LocRib {
+            config,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Applies the changes an Adj-RIB-In of `peer` reported and returns the resulting best
+    /// path changes
+    pub fn apply(&mut self, peer: &RibPeer, changes: &[RibChange]) -> Vec<BestPathChange> {
+        let _span = span!("peer.addr" = peer.addr, "peer.asn" = peer.asn);
+        changes
+            .iter()
+            .filter_map(|change| match change {
+                RibChange::Announced { key, attributes }
+                | RibChange::Unchanged { key, attributes }
+                | RibChange::Replaced {
+                    key,
+                    new: attributes,
+                    ..
+                } => self.insert(key, *peer, attributes.clone()),
+                RibChange::Withdrawn { key, .. } => self.remove(key, peer.addr),
+            })
+            .inspect(|change| event!(Debug, "best path changed", "prefix" = change.key.prefix))
+            .collect()
+    }
+
+    /// Drops every path of a peer, as when its session goes down
+    pub fn remove_peer(&mut self, peer: IpAddr) -> Vec<BestPathChange> {
+        let keys: Vec<RibKey> = self
+            .routes
+            .iter()
+            .filter(|(_, candidates)| candidates.position(peer).is_ok())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter()
+            .filter_map(|key| self.remove(key, peer))
+            .collect()
+    }
+
+    fn insert(
+        &mut self,
+        key: &RibKey,
+        peer: RibPeer,
+        attributes: AttributeSet,
+    ) -> Option<BestPathChange> {
+        let path = PeerPath { peer, attributes };
+        let Some(candidates) = self.routes.get_mut(key) else {
+            self.routes.insert(
+                key.clone(),
+                Candidates {
+                    paths: vec![path.clone()],
+                    best: 0,
+                },
+            );
+            return Some(BestPathChange {
+                key: key.clone(),
+                old: None,
+                new: Some(path),
+            });
+        };
+
+        let old = candidates.best().clone();
+        match candidates.position(peer.addr) {
+            Ok(i) => candidates.paths[i] = path,
+            Err(i) => candidates.paths.insert(i, path),
+        }
+        candidates.select(&self.config);
+        let new = candidates.best();
+        (*new != old).then(|| BestPathChange {
+            key: key.clone(),
+            old: Some(old),
+            new: Some(new.clone()),
+        })
+    }
+
+    fn remove(&mut self, key: &RibKey, peer: IpAddr) -> Option<BestPathChange> {
+        let candidates = self.routes.get_mut(key)?;
+        let i = candidates.position(peer).ok()?;
+        let removed = candidates.paths.remove(i);
+        if candidates.paths.is_empty() {
+            self.routes.remove(key);
+            return Some(BestPathChange {
+                key: key.clone(),
+                old: Some(removed),
+                new: None,
+            });
+        }
+
+        let was_best = candidates.best == i;
+        candidates.select(&self.config);
+        was_best.then(|| BestPathChange {
+            key: key.clone(),
+            old: Some(removed),
+            new: Some(candidates.best().clone()),
+        })
+    }
+
+    /// The best path of a unicast route without path identifier
+    pub fn best(&self, prefix: &IpAddrPrefix) -> Option<&PeerPath> {
+        self.get(&RibKey::unicast(prefix.clone()))
+    }
+
+    pub fn get(&self, key: &RibKey) -> Option<&PeerPath> {
+        self.routes.get(key).map(Candidates::best)
+    }
+
+    /// The best path of the most specific unicast route without path identifier covering
+    /// `addr`
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(&RibKey, &PeerPath)> {
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        (0..=width).rev().find_map(|length| {
+            let key = RibKey::unicast(IpAddrPrefix::new(addr, length)?);
+            let (key, candidates) = self.routes.get_key_value(&key)?;
+            Some((key, candidates.best()))
+        })
+    }
+
+    /// Every path known for a route, ordered by peer address
+    pub fn candidates(&self, key: &RibKey) -> &[PeerPath] {
+        self.routes
+            .get(key)
+            .map_or(&[], |candidates| &candidates.paths)
+    }
+
+    /// Number of routes with a best path
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The best path of every route, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&RibKey, &PeerPath)> {
+        self.routes
+            .iter()
+            .map(|(key, candidates)| (key, candidates.best()))
+    }
+}
+
+impl Candidates {
+    fn best(&self) -> &PeerPath {
+        &self.paths[self.best]
+    }
+
+    fn position(&self, peer: IpAddr) -> Result<usize, usize> {
+        self.paths
+            .binary_search_by(|path| path.peer.addr.cmp(&peer))
+    }
+
+    /// MED makes the comparison intransitive, so the winner is found by comparing each path
+    /// against the best one so far, in peer address order, rather than by sorting. Deterministic
+    /// MED ranks the paths by neighbor AS instead.
+    fn select(&mut self, config: &DecisionConfig) {
+        let ctx = ComparisonCtx {
+            config: *config,
+            ..ComparisonCtx::default()
+        };
+        let attrs: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| RouteAttrs::new(path.peer, &path.attributes))
+            .collect();
+        if config.deterministic_med {
+            self.best = rank_paths(&attrs, &ctx)[0];
+            return;
+        }
+        self.best = (1..attrs.len()).fold(0, |best, i| {
+            match compare_paths(&attrs[i], &attrs[best], &ctx) {
+                Ordering::Less => i,
+                _ => best,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, OriginType};
+    use crate::rib::RibIn;
+    use crate::rib::rib_in::attribute_set;
+    use crate::update_message::UpdateMessageBuilder;
+
+    const LOCAL_ASN: u32 = 65000;
+
+    fn prefix() -> IpAddrPrefix {
+        IpAddrPrefix::new("198.51.100.0".parse().unwrap(), 24).unwrap()
+    }
+
+    fn peer(n: u8, asn: u32) -> RibPeer {
+        RibPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)),
+            asn,
+            router_id: Ipv4Addr::new(192, 0, 2, n),
+            external: asn != LOCAL_ASN,
+        }
+    }
+
+    fn segment(segment_type: AsPathSegmentType, asns: &[u32]) -> AsPathSegment {
+        AsPathSegment {
+            segment_type,
+            asns: asns.to_vec().into(),
+        }
+    }
+
+    fn sequence(asns: &[u32]) -> AsPath {
+        AsPath {
+            segments: [segment(AsPathSegmentType::AsSequence, asns)].into(),
+        }
+    }
+
+    fn attributes(
+        build: impl FnOnce(UpdateMessageBuilder) -> UpdateMessageBuilder,
+    ) -> AttributeSet {
+        let builder = UpdateMessageBuilder::new()
+            .announce(prefix())
+            .next_hop("192.0.2.1".parse().unwrap());
+        attribute_set(&build(builder).build().path_attributes)
+    }
+
+    /// Feeds the paths into a fresh Loc-RIB and returns the index of the winner
+    fn winner(config: DecisionConfig, paths: &[(RibPeer, AttributeSet)]) -> usize {
+        let mut rib = LocRib::new(config);
+        for (peer, attributes) in paths {
+            let change = RibChange::Announced {
+                key: RibKey::unicast(prefix()),
+                attributes: attributes.clone(),
+            };
+            rib.apply(peer, &[change]);
+        }
+        let best = rib.best(&prefix()).unwrap();
+        paths
+            .iter()
+            .position(|(peer, _)| *peer == best.peer)
+            .unwrap()
+    }
+
+    fn default_winner(paths: &[(RibPeer, AttributeSet)]) -> usize {
+        let forward = winner(DecisionConfig::default(), paths);
+        let reversed: Vec<_> = paths.iter().rev().cloned().collect();
+        assert_eq!(
+            winner(DecisionConfig::default(), &reversed),
+            paths.len() - 1 - forward,
+            "selection depends on arrival order"
+        );
+        forward
+    }
+
+    #[test]
+    fn test_highest_local_pref() {
+        let paths = [
+            (peer(1, LOCAL_ASN), attributes(|b| b.local_pref(100))),
+            (
+                peer(2, LOCAL_ASN),
+                attributes(|b| b.local_pref(200).as_path(sequence(&[1, 2, 3]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+
+        // A missing LOCAL_PREF counts as 100
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001]))),
+            ),
+            (peer(2, LOCAL_ASN), attributes(|b| b.local_pref(99))),
+        ];
+        assert_eq!(default_winner(&paths), 0);
+    }
+
+    #[test]
+    fn test_shortest_as_path() {
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001, 2, 3]))),
+            ),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002, 3]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+
+        // An AS_SET counts as one AS
+        let with_set = AsPath {
+            segments: [
+                segment(AsPathSegmentType::AsSequence, &[65001]),
+                segment(AsPathSegmentType::AsSet, &[1, 2, 3, 4]),
+            ]
+            .into(),
+        };
+        let paths = [
+            (peer(1, 65001), attributes(|b| b.as_path(with_set))),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002, 5, 6]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 0);
+
+        // Confederation segments don't count
+        let with_confed = AsPath {
+            segments: [
+                segment(AsPathSegmentType::AsConfedSequence, &[64512, 64513, 64514]),
+                segment(AsPathSegmentType::AsSequence, &[65001]),
+            ]
+            .into(),
+        };
+        let paths = [
+            (peer(1, LOCAL_ASN), attributes(|b| b.as_path(with_confed))),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002, 5]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 0);
+    }
+
+    #[test]
+    fn test_lowest_origin() {
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.origin(OriginType::Incomplete)),
+            ),
+            (peer(2, 65002), attributes(|b| b.origin(OriginType::Egp))),
+            (
+                peer(3, 65003),
+                attributes(|b| b.origin(OriginType::Incomplete)),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+    }
+
+    #[test]
+    fn test_med_from_same_neighbor_as() {
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(20)),
+            ),
+            (
+                peer(2, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(10)),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+
+        // A missing MED counts as 0
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(10)),
+            ),
+            (
+                peer(2, 65001),
+                attributes(|b| b.as_path(sequence(&[65001]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+    }
+
+    #[test]
+    fn test_med_across_neighbor_ases() {
+        // Peer 1 has the lower router ID, peer 2 the lower MED from another AS
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(20)),
+            ),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002])).med(10)),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 0);
+
+        let config = DecisionConfig {
+            always_compare_med: true,
+            ..DecisionConfig::default()
+        };
+        assert_eq!(winner(config, &paths), 1);
+    }
+
+    #[test]
+    fn test_med_knobs() {
+        // The classic case where MED makes the outcome depend on the comparison order: A beats
+        // B on MED, B beats C and C beats A on router ID
+        let paths = [
+            (
+                peer(3, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(10)),
+            ),
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(20)),
+            ),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002]))),
+            ),
+        ];
+        let knobs = |always_compare_med, deterministic_med, missing_med_as_worst| DecisionConfig {
+            always_compare_med,
+            deterministic_med,
+            missing_med_as_worst,
+            ..DecisionConfig::default()
+        };
+        let table = [
+            // Peer address order compares B with C, then the winner B with A
+            (knobs(false, false, false), 0),
+            // A wins AS 65001, then loses to C on router ID
+            (knobs(false, true, false), 2),
+            (knobs(false, true, true), 2),
+            (knobs(false, false, true), 0),
+            // C's missing MED counts as 0, or as worse than any
+            (knobs(true, false, false), 2),
+            (knobs(true, false, true), 0),
+            (knobs(true, true, false), 2),
+            (knobs(true, true, true), 0),
+        ];
+        for (config, expected) in table {
+            assert_eq!(winner(config, &paths), expected, "{config:?}");
+            let reversed: Vec<_> = paths.iter().rev().cloned().collect();
+            assert_eq!(winner(config, &reversed), 2 - expected, "{config:?}");
+        }
+
+        // Between paths from one AS, a missing MED beats or loses to any
+        let paths = [
+            (
+                peer(1, 65001),
+                attributes(|b| b.as_path(sequence(&[65001])).med(5)),
+            ),
+            (
+                peer(2, 65001),
+                attributes(|b| b.as_path(sequence(&[65001]))),
+            ),
+        ];
+        assert_eq!(winner(knobs(false, false, false), &paths), 1);
+        assert_eq!(winner(knobs(false, false, true), &paths), 0);
+    }
+
+    #[test]
+    fn test_ebgp_over_ibgp() {
+        let paths = [
+            (
+                peer(1, LOCAL_ASN),
+                attributes(|b| b.as_path(sequence(&[65001]))),
+            ),
+            (
+                peer(2, 65001),
+                attributes(|b| b.as_path(sequence(&[65001]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+    }
+
+    #[test]
+    fn test_lowest_router_id() {
+        let mut high = peer(1, 65001);
+        high.router_id = Ipv4Addr::new(192, 0, 2, 200);
+        let paths = [
+            (high, attributes(|b| b.as_path(sequence(&[65001])))),
+            (
+                peer(2, 65002),
+                attributes(|b| b.as_path(sequence(&[65002]))),
+            ),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+    }
+
+    #[test]
+    fn test_lowest_peer_address() {
+        let mut first = peer(2, 65001);
+        let mut second = peer(1, 65001);
+        first.router_id = Ipv4Addr::new(192, 0, 2, 1);
+        second.router_id = first.router_id;
+        let paths = [
+            (first, attributes(|b| b.as_path(sequence(&[65001])))),
+            (second, attributes(|b| b.as_path(sequence(&[65001])))),
+        ];
+        assert_eq!(default_winner(&paths), 1);
+    }
+
+    #[test]
+    fn test_best_path_changes() {
+        let (first, second) = (peer(1, 65001), peer(2, 65002));
+        let mut loc_rib = LocRib::new(DecisionConfig::default());
+        let (mut first_in, mut second_in) = (RibIn::new(), RibIn::new());
+        let key = RibKey::unicast(prefix());
+
+        let short = UpdateMessageBuilder::new()
+            .announce(prefix())
+            .next_hop("10.0.0.1".parse().unwrap())
+            .as_path(sequence(&[65001]))
+            .build();
+        let changes = loc_rib.apply(&first, &first_in.apply(&short));
+        assert!(
+            matches!(&changes[..], [BestPathChange { old: None, new: Some(new), .. }] if new.peer == first)
+        );
+
+        // A worse path from another peer doesn't change the winner
+        let long = UpdateMessageBuilder::new()
+            .announce(prefix())
+            .next_hop("10.0.0.2".parse().unwrap())
+            .as_path(sequence(&[65002, 65003]))
+            .build();
+        assert!(loc_rib.apply(&second, &second_in.apply(&long)).is_empty());
+        assert_eq!(loc_rib.candidates(&key).len(), 2);
+
+        // Lengthening the best path flips the winner
+        let longer = UpdateMessageBuilder::new()
+            .announce(prefix())
+            .next_hop("10.0.0.1".parse().unwrap())
+            .as_path(sequence(&[65001, 65004, 65005]))
+            .build();
+        let changes = loc_rib.apply(&first, &first_in.apply(&longer));
+        let [
+            BestPathChange {
+                old: Some(old),
+                new: Some(new),
+                ..
+            },
+        ] = &changes[..]
+        else {
+            panic!("expected the winner to flip, got {changes:?}");
+        };
+        assert_eq!((old.peer, new.peer), (first, second));
+
+        // Withdrawing the losing path changes nothing, dropping the winner leaves no path
+        let withdraw = UpdateMessageBuilder::new().withdraw(prefix()).build();
+        assert!(loc_rib.apply(&first, &first_in.apply(&withdraw)).is_empty());
+        assert_eq!(loc_rib.iter().count(), 1);
+        let changes = loc_rib.remove_peer(second.addr);
+        assert!(
+            matches!(&changes[..], [BestPathChange { old: Some(old), new: None, .. }] if old.peer == second)
+        );
+        assert!(loc_rib.is_empty());
+        assert_eq!(loc_rib.best(&prefix()), None);
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let mut loc_rib = LocRib::new(DecisionConfig::default());
+        let announce = |prefix: &str| RibChange::Announced {
+            key: RibKey::unicast(prefix.parse().unwrap()),
+            attributes: attributes(|b| b),
+        };
+        let changes = [
+            "0.0.0.0/0",
+            "198.51.0.0/16",
+            "198.51.100.0/24",
+            "2001:db8::/32",
+        ]
+        .map(announce);
+        loc_rib.apply(&peer(1, 65001), &changes);
+
+        let matched = |addr: &str| {
+            loc_rib
+                .longest_match(addr.parse().unwrap())
+                .map(|(key, _)| key.prefix.to_string())
+        };
+        assert_eq!(matched("198.51.100.7").unwrap(), "198.51.100.0/24");
+        assert_eq!(matched("198.51.7.1").unwrap(), "198.51.0.0/16");
+        assert_eq!(matched("192.0.2.1").unwrap(), "0.0.0.0/0");
+        assert_eq!(matched("2001:db8::1").unwrap(), "2001:db8::/32");
+        assert_eq!(matched("2001:db9::1"), None);
+    }
+}
@@ -0,0 +1,68 @@
+use std::mem::size_of;
+
+use crate::attribute::{AsPath, AttributeValue, MpReachNlri, MpUnreachNlri, PathAttribute};
+use crate::list::SmallList;
+use crate::update_message::IpAddrPrefix;
+
+/// What a [`RibIn`](super::RibIn) takes in memory, in octets estimated from the sizes of what
+/// it holds rather than measured from the allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RibMemoryStats {
+    pub routes: usize,
+    /// Distinct attribute sets the routes share
+    pub attribute_sets: usize,
+    /// Route keys, in the route table, the index by change time and the stale set
+    pub key_bytes: usize,
+    /// The attribute sets, each counted once however many routes share it
+    pub attribute_bytes: usize,
+    /// The rest of the route table and change index: route values, spare capacity and
+    /// bookkeeping
+    pub index_bytes: usize,
+}
+
+impl RibMemoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.key_bytes + self.attribute_bytes + self.index_bytes
+    }
+}
+
+/// Octets an attribute set takes behind its `Arc`, its reference counts included
+pub(super) fn set_bytes(attributes: &[PathAttribute]) -> usize {
+    2 * size_of::<usize>()
+        + attributes
+            .iter()
+            .map(|attribute| size_of::<PathAttribute>() + value_heap_bytes(&attribute.value))
+            .sum::<usize>()
+}
+
+/// Octets a value holds apart from itself, spare capacity left out
+fn value_heap_bytes(value: &AttributeValue) -> usize {
+    match value {
+        AttributeValue::AsPath(as_path) => {
+            size_of::<AsPath>()
+                + list_bytes(&as_path.segments)
+                + as_path
+                    .segments
+                    .iter()
+                    .map(|segment| list_bytes(&segment.asns))
+                    .sum::<usize>()
+        }
+        AttributeValue::Communities(communities) => list_bytes(&communities.communities),
+        AttributeValue::MpReachNlri(mp_reach) => {
+            size_of::<MpReachNlri>() + mp_reach.nlri.len() * size_of::<IpAddrPrefix>()
+        }
+        AttributeValue::MpUnreachNlri(mp_unreach) => {
+            size_of::<MpUnreachNlri>()
+                + mp_unreach.withdrawn_routes.len() * size_of::<IpAddrPrefix>()
+        }
+        AttributeValue::Unknown(value) => value.len(),
+        _ => 0,
+    }
+}
+
+fn list_bytes<T, const N: usize>(list: &SmallList<T, N>) -> usize {
+    match list.spilled() {
+        true => list.len() * size_of::<T>(),
+        false => 0,
+    }
+}
@@ -0,0 +1,34 @@
+mod csv;
+mod decision;
+mod event;
+mod intern;
+mod loc_rib;
+mod memory;
+mod rib_in;
+#[cfg(feature = "tokio")]
+mod sharded;
+mod show;
+mod snapshot;
+
+#[cfg(feature = "ws")]
+pub(crate) use csv::rfc3339;
+pub use csv::{EVENT_CSV_COLUMNS, RouteEventCsvWriter, SNAPSHOT_CSV_COLUMNS};
+pub use decision::{ComparisonCtx, DecisionStep, RouteAttrs, compare_paths, rank_paths};
+pub use event::{RouteEvent, RouteEventKind, RouteEventSource};
+pub use intern::{AttrSetInterner, InternerStats};
+pub use loc_rib::{BestPathChange, DecisionConfig, LocRib, PeerPath, RibPeer};
+pub use memory::RibMemoryStats;
+pub(crate) use rib_in::attribute_set;
+pub use rib_in::{AttributeSet, RibChange, RibIn, RibKey, RouteAge};
+#[cfg(feature = "mrt")]
+pub(crate) use rib_in::{announced_keys, withdrawn_keys};
+#[cfg(feature = "tokio")]
+pub use sharded::ShardedLocRib;
+#[cfg(feature = "mrt")]
+pub(crate) use show::Fields;
+pub use show::RouteTableFormatter;
+#[cfg(feature = "mrt")]
+pub(crate) use snapshot::Clocks;
+#[cfg(feature = "cbor")]
+pub(crate) use snapshot::decode_prefix;
+pub use snapshot::{RibSnapshot, SNAPSHOT_VERSION, SnapshotError};
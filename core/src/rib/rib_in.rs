@@ -0,0 +1,1000 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{AttributeValue, MpReachNlri, PathAttribute, SemanticAttributes};
+use crate::filter::RouteFilter;
+use crate::route_refresh_message::{RouteRefreshMessage, RouteRefreshSubtype};
+use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+use super::memory::set_bytes;
+use super::{AttrSetInterner, InternerStats, RibMemoryStats};
+
+/// Path attributes of a route, shared by every prefix announced in the same UPDATE.
+///
+/// MP_REACH_NLRI keeps its next hop but not its prefixes, MP_UNREACH_NLRI is dropped.
+pub type AttributeSet = Arc<[PathAttribute]>;
+
+/// Identifies a route: a prefix may be present once per SAFI and, with ADD-PATH, once per
+/// path identifier
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RibKey {
+    pub prefix: IpAddrPrefix,
+    pub safi: Safi,
+    pub path_id: Option<u32>,
+}
+
+/// What applying an UPDATE did to a single route
+#[derive(Debug, Clone, PartialEq)]
+pub enum RibChange {
+    Announced {
+        key: RibKey,
+        attributes: AttributeSet,
+    },
+    /// Re-announced with different attributes, implicitly withdrawing the previous route
+    Replaced {
+        key: RibKey,
+        old: AttributeSet,
+        new: AttributeSet,
+    },
+    /// Re-announced with the same attributes, as happens after a route refresh, however
+    /// ordered (see [`semantic_eq`](crate::message::semantic_eq))
+    Unchanged {
+        key: RibKey,
+        attributes: AttributeSet,
+    },
+    Withdrawn {
+        key: RibKey,
+        attributes: AttributeSet,
+    },
+}
+
+/// When a route was installed and last changed, see [`RibIn::age`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteAge {
+    /// When the peer first announced the route
+    pub installed: Instant,
+    /// When the route was installed or its attributes last changed
+    pub changed: Instant,
+    /// Set when either time, restored or loaded from a wall clock time, was before the
+    /// monotonic clock could reach, and was taken as the earliest it can
+    pub imprecise: bool,
+}
+
+/// Adj-RIB-In: the routes a single peer currently advertises to us.
+///
+/// Every route remembers its [`RouteAge`], and routes are indexed by the time they last
+/// changed for [`RibIn::changed_since`], which costs a second copy of each key. Attribute
+/// sets are shared through an [`AttrSetInterner`], across UPDATEs too.
+#[derive(Debug, Clone, Default)]
+pub struct RibIn {
+    routes: HashMap<RibKey, Route>,
+    /// Keys by the time their route last changed
+    changes: BTreeMap<Instant, HashSet<RibKey>>,
+    /// Routes per address family
+    counts: HashMap<(Afi, Safi), usize>,
+    filter: Option<Arc<dyn RouteFilter>>,
+    filtered: u64,
+    /// Routes kept from a session that went down, until re-announced or swept
+    stale: HashSet<RibKey>,
+    interner: AttrSetInterner,
+    /// The attribute sets of the routes, by address, for [`RibIn::memory_stats`]
+    sets: HashMap<usize, SetRefs>,
+    /// Of every set in `sets`
+    attribute_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    attributes: AttributeSet,
+    age: RouteAge,
+}
+
+/// How many routes use an attribute set, and its size
+#[derive(Debug, Clone, Copy)]
+struct SetRefs {
+    routes: usize,
+    bytes: usize,
+}
+
+impl RouteAge {
+    /// A route installed at `now`
+    pub fn new(now: Instant) -> Self {
+        RouteAge {
+            installed: now,
+            changed: now,
+            imprecise: false,
+        }
+    }
+
+    /// Time since the route last changed, as a router shows it
+    pub fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.changed)
+    }
+}
+
+impl RibKey {
+    /// A unicast route without path identifier
+    pub fn unicast(prefix: IpAddrPrefix) -> Self {
+        RibKey {
+            prefix,
+            safi: Safi::Unicast,
+            path_id: None,
+        }
+    }
+
+    pub fn family(&self) -> (Afi, Safi) {
+        (self.prefix.afi(), self.safi)
+    }
+
+    /// The key of a decoded prefix, its ADD-PATH path identifier moved from the prefix to the
+    /// key
+    fn decoded(prefix: &IpAddrPrefix, safi: Safi) -> Self {
+        RibKey {
+            prefix: prefix.clone().with_path_id(None),
+            safi,
+            path_id: prefix.path_id(),
+        }
+    }
+}
+
+impl RibChange {
+    pub fn key(&self) -> &RibKey {
+        match self {
+            RibChange::Announced { key, .. }
+            | RibChange::Replaced { key, .. }
+            | RibChange::Unchanged { key, .. }
+            | RibChange::Withdrawn { key, .. } => key,
+        }
+    }
+}
+
+impl RibIn {
+    pub fn new() -> Self {
+        RibIn::default()
+    }
+
+    /// Only stores announcements `filter` permits, counting the others in
+    /// [`RibIn::filtered`]
+    pub fn with_filter(mut self, filter: Arc<dyn RouteFilter>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Replaces the filter of a RIB that already holds routes, dropping the stored routes
+    /// `filter` denies and reporting them as withdrawn.
+    ///
+    /// Routes the old filter rejected weren't kept, so the peer has to re-advertise them:
+    /// request a route refresh of every family and pass its BoRR and EoRR markers to
+    /// [`RibIn::apply_route_refresh`].
+    pub fn set_filter(&mut self, filter: Arc<dyn RouteFilter>) -> Vec<RibChange> {
+        let denied: Vec<RibKey> = self
+            .routes
+            .iter()
+            .filter(|(key, route)| !filter.permits(key, &route.attributes))
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.filter = Some(filter);
+        denied
+            .into_iter()
+            .filter_map(|key| {
+                let attributes = self.remove(&key)?;
+                Some(RibChange::Withdrawn { key, attributes })
+            })
+            .collect()
+    }
+
+    /// Applies an UPDATE, withdrawals first, and reports every route it touched.
+    ///
+    /// Withdrawals of routes that aren't present change nothing and aren't reported. A filtered
+    /// announcement withdraws the route it would have replaced.
+    pub fn apply(&mut self, update: &UpdateMessage) -> Vec<RibChange> {
+        self.apply_at(update, Instant::now())
+    }
+
+    /// Like [`RibIn::apply`], with the UPDATE received at `now`
+    pub fn apply_at(&mut self, update: &UpdateMessage, now: Instant) -> Vec<RibChange> {
+        let mut changes = vec![];
+        for key in withdrawn_keys(update) {
+            if let Some(attributes) = self.remove(&key) {
+                changes.push(RibChange::Withdrawn { key, attributes });
+            }
+        }
+
+        let mut announced = announced_keys(update).peekable();
+        if announced.peek().is_none() {
+            return changes;
+        }
+        let attributes = self.interner.intern(&update.path_attributes);
+        for key in announced {
+            if let Some(filter) = &self.filter
+                && !filter.permits(&key, &update.path_attributes)
+            {
+                self.filtered += 1;
+                event!(Debug, "route filtered", "prefix" = key.prefix);
+                if let Some(attributes) = self.remove(&key) {
+                    changes.push(RibChange::Withdrawn { key, attributes });
+                }
+                continue;
+            }
+            changes.push(self.insert(key, attributes.clone(), RouteAge::new(now)));
+        }
+        changes
+    }
+
+    /// Installs a route of the given age, or replaces the attributes of a present one as of
+    /// `age.changed`
+    pub(crate) fn insert(
+        &mut self,
+        key: RibKey,
+        attributes: AttributeSet,
+        age: RouteAge,
+    ) -> RibChange {
+        if !self.stale.is_empty() {
+            self.stale.remove(&key);
+        }
+        match self.routes.entry(key) {
+            Entry::Vacant(entry) => {
+                let key = entry.key().clone();
+                entry.insert(Route {
+                    attributes: attributes.clone(),
+                    age,
+                });
+                self.track(&attributes);
+                self.changes
+                    .entry(age.changed)
+                    .or_default()
+                    .insert(key.clone());
+                *self.counts.entry(key.family()).or_default() += 1;
+                RibChange::Announced { key, attributes }
+            }
+            Entry::Occupied(mut entry) => {
+                let key = entry.key().clone();
+                let route = entry.get_mut();
+                if SemanticAttributes(&route.attributes) == SemanticAttributes(&attributes) {
+                    // Keep the set already shared with other prefixes, and the route's age
+                    return RibChange::Unchanged {
+                        key,
+                        attributes: route.attributes.clone(),
+                    };
+                }
+                let old = std::mem::replace(&mut route.attributes, attributes.clone());
+                let changed = std::mem::replace(&mut route.age.changed, age.changed);
+                route.age.imprecise |= age.imprecise;
+                self.untrack(&old);
+                self.track(&attributes);
+                unindex(&mut self.changes, changed, &key);
+                self.changes
+                    .entry(age.changed)
+                    .or_default()
+                    .insert(key.clone());
+                RibChange::Replaced {
+                    key,
+                    old,
+                    new: attributes,
+                }
+            }
+        }
+    }
+
+    pub(super) fn reserve(&mut self, additional: usize) {
+        self.routes.reserve(additional);
+    }
+
+    fn remove(&mut self, key: &RibKey) -> Option<AttributeSet> {
+        let Route { attributes, age } = self.routes.remove(key)?;
+        self.untrack(&attributes);
+        unindex(&mut self.changes, age.changed, key);
+        if !self.stale.is_empty() {
+            self.stale.remove(key);
+        }
+        if let Some(count) = self.counts.get_mut(&key.family()) {
+            *count -= 1;
+        }
+        Some(attributes)
+    }
+
+    /// The attributes of a unicast route without path identifier
+    pub fn lookup(&self, prefix: &IpAddrPrefix) -> Option<&AttributeSet> {
+        self.get(&RibKey::unicast(prefix.clone()))
+    }
+
+    pub fn get(&self, key: &RibKey) -> Option<&AttributeSet> {
+        self.routes.get(key).map(|route| &route.attributes)
+    }
+
+    pub fn age(&self, key: &RibKey) -> Option<RouteAge> {
+        self.routes.get(key).map(|route| route.age)
+    }
+
+    /// Routes installed or changed at or after `since`, oldest change first
+    pub fn changed_since(
+        &self,
+        since: Instant,
+    ) -> impl Iterator<Item = (&RibKey, &AttributeSet, RouteAge)> {
+        self.changes
+            .range(since..)
+            .flat_map(|(_, keys)| keys)
+            .map(|key| {
+                let route = &self.routes[key];
+                (key, &route.attributes, route.age)
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Number of routes of an address family
+    pub fn prefix_count(&self, afi: Afi, safi: Safi) -> usize {
+        self.counts.get(&(afi, safi)).copied().unwrap_or_default()
+    }
+
+    /// Announcements the filter rejected so far
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    /// How many announced attribute sets were shared with routes already present
+    pub fn interner_stats(&self) -> InternerStats {
+        self.interner.stats()
+    }
+
+    /// Every route, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&RibKey, &AttributeSet)> {
+        self.routes
+            .iter()
+            .map(|(key, route)| (key, &route.attributes))
+    }
+
+    /// Every route with its age, in no particular order
+    pub fn iter_ages(&self) -> impl Iterator<Item = (&RibKey, &AttributeSet, RouteAge)> {
+        self.routes
+            .iter()
+            .map(|(key, route)| (key, &route.attributes, route.age))
+    }
+
+    /// Drops every route, as when the session goes down, reporting them as withdrawn
+    pub fn clear(&mut self) -> Vec<RibChange> {
+        self.sets.clear();
+        self.attribute_bytes = 0;
+        self.counts.clear();
+        self.stale.clear();
+        self.changes.clear();
+        self.routes
+            .drain()
+            .map(|(key, route)| RibChange::Withdrawn {
+                key,
+                attributes: route.attributes,
+            })
+            .collect()
+    }
+}
+
+impl RibIn {
+    /// Counts a route using `attributes`
+    fn track(&mut self, attributes: &AttributeSet) {
+        let refs = self
+            .sets
+            .entry(set_address(attributes))
+            .or_insert_with(|| SetRefs {
+                routes: 0,
+                bytes: set_bytes(attributes),
+            });
+        if refs.routes == 0 {
+            self.attribute_bytes += refs.bytes;
+        }
+        refs.routes += 1;
+    }
+
+    /// Counts a route no longer using `attributes`
+    fn untrack(&mut self, attributes: &AttributeSet) {
+        let address = set_address(attributes);
+        let Some(refs) = self.sets.get_mut(&address) else {
+            return;
+        };
+        refs.routes -= 1;
+        if refs.routes == 0 {
+            self.attribute_bytes -= refs.bytes;
+            self.sets.remove(&address);
+        }
+    }
+
+    /// The memory the RIB takes, from sizes tracked as routes come and go
+    pub fn memory_stats(&self) -> RibMemoryStats {
+        RibMemoryStats {
+            routes: self.routes.len(),
+            attribute_sets: self.sets.len(),
+            key_bytes: (2 * self.routes.len() + self.stale.len()) * size_of::<RibKey>(),
+            attribute_bytes: self.attribute_bytes,
+            index_bytes: self.table_bytes(),
+        }
+    }
+
+    /// [`RibIn::memory_stats`] found by walking every route and attribute set instead, for
+    /// checking the tracked figures. The index also counts the spare capacity and control
+    /// octets of the change index.
+    pub fn deep_measure(&self) -> RibMemoryStats {
+        let indexed: usize = self.changes.values().map(HashSet::len).sum();
+        let spare: usize = self
+            .changes
+            .values()
+            .map(|keys| (keys.capacity() - keys.len()) * size_of::<RibKey>() + keys.capacity())
+            .sum();
+        let mut sets = HashSet::new();
+        let attribute_bytes = self
+            .routes
+            .values()
+            .filter(|route| sets.insert(set_address(&route.attributes)))
+            .map(|route| set_bytes(&route.attributes))
+            .sum();
+        RibMemoryStats {
+            routes: self.routes.len(),
+            attribute_sets: sets.len(),
+            key_bytes: (self.routes.len() + indexed + self.stale.len()) * size_of::<RibKey>(),
+            attribute_bytes,
+            index_bytes: self.table_bytes() + spare,
+        }
+    }
+
+    /// The hash tables and change index without their keys, a control octet per slot
+    fn table_bytes(&self) -> usize {
+        let key = size_of::<RibKey>();
+        let routes =
+            self.routes.capacity() * (size_of::<(RibKey, Route)>() + 1) - self.routes.len() * key;
+        let stale = self.stale.capacity() * (key + 1) - self.stale.len() * key;
+        let changes = self.changes.len() * size_of::<(Instant, HashSet<RibKey>)>();
+        let sets = self.sets.capacity() * (size_of::<(usize, SetRefs)>() + 1);
+        routes + stale + changes + sets
+    }
+}
+
+impl RibIn {
+    /// Keeps every route as stale across a Graceful Restart (RFC 4724): a route stops being
+    /// stale once the peer announces it again, [`RibIn::sweep_stale`] drops the others.
+    ///
+    /// Returns the number of routes marked.
+    pub fn mark_stale(&mut self) -> usize {
+        self.stale.extend(self.routes.keys().cloned());
+        self.stale.len()
+    }
+
+    pub fn is_stale(&self, key: &RibKey) -> bool {
+        self.stale.contains(key)
+    }
+
+    pub fn stale_count(&self) -> usize {
+        self.stale.len()
+    }
+
+    /// Drops the routes still stale, once the peer sent End-of-RIB or its restart time ran
+    /// out, reporting them as withdrawn
+    pub fn sweep_stale(&mut self) -> Vec<RibChange> {
+        let stale: Vec<RibKey> = self.stale.drain().collect();
+        stale
+            .into_iter()
+            .filter_map(|key| {
+                let attributes = self.remove(&key)?;
+                Some(RibChange::Withdrawn { key, attributes })
+            })
+            .collect()
+    }
+
+    /// Follows an Enhanced Route Refresh (RFC 7313) of the peer: BoRR marks the routes of the
+    /// family stale, EoRR drops those the peer didn't re-advertise and reports them as withdrawn.
+    pub fn apply_route_refresh(&mut self, route_refresh: &RouteRefreshMessage) -> Vec<RibChange> {
+        let family = (route_refresh.afi, route_refresh.safi);
+        match route_refresh.subtype {
+            RouteRefreshSubtype::Begin => {
+                let keys = self.routes.keys().filter(|key| key.family() == family);
+                self.stale.extend(keys.cloned());
+                vec![]
+            }
+            RouteRefreshSubtype::End => {
+                let stale: Vec<RibKey> = self
+                    .stale
+                    .extract_if(|key| key.family() == family)
+                    .collect();
+                stale
+                    .into_iter()
+                    .filter_map(|key| {
+                        let attributes = self.remove(&key)?;
+                        Some(RibChange::Withdrawn { key, attributes })
+                    })
+                    .collect()
+            }
+            RouteRefreshSubtype::Request | RouteRefreshSubtype::Unknown(_) => vec![],
+        }
+    }
+
+    /// Handles the peer's session going down: routes are marked stale when
+    /// [`PeerDown::retains_routes`], and otherwise dropped and reported as withdrawn
+    ///
+    /// [`PeerDown::retains_routes`]: crate::peer::PeerDown::retains_routes
+    pub fn peer_down(&mut self, down: &crate::peer::PeerDown) -> Vec<RibChange> {
+        if down.retains_routes() {
+            self.mark_stale();
+            return vec![];
+        }
+        self.clear()
+    }
+}
+
+fn set_address(attributes: &AttributeSet) -> usize {
+    Arc::as_ptr(attributes) as *const PathAttribute as usize
+}
+
+fn unindex(changes: &mut BTreeMap<Instant, HashSet<RibKey>>, changed: Instant, key: &RibKey) {
+    let Some(keys) = changes.get_mut(&changed) else {
+        return;
+    };
+    keys.remove(key);
+    if keys.is_empty() {
+        changes.remove(&changed);
+    }
+}
+
+/// Routes removed by an UPDATE, from the withdrawn routes and MP_UNREACH_NLRI
+pub(crate) fn withdrawn_keys(update: &UpdateMessage) -> impl Iterator<Item = RibKey> + '_ {
+    let mp_withdrawn = update
+        .path_attributes
+        .iter()
+        .filter_map(|attribute| match &attribute.value {
+            AttributeValue::MpUnreachNlri(mp_unreach) => Some(
+                mp_unreach
+                    .withdrawn_routes
+                    .iter()
+                    .map(|prefix| (prefix, mp_unreach.safi)),
+            ),
+            _ => None,
+        })
+        .flatten();
+    update
+        .withdrawn_routes
+        .iter()
+        .map(|prefix| (prefix, Safi::Unicast))
+        .chain(mp_withdrawn)
+        .map(|(prefix, safi)| RibKey::decoded(prefix, safi))
+}
+
+/// Routes announced by an UPDATE, from the NLRI and MP_REACH_NLRI
+pub(crate) fn announced_keys(update: &UpdateMessage) -> impl Iterator<Item = RibKey> + '_ {
+    let mp_announced = update
+        .path_attributes
+        .iter()
+        .filter_map(|attribute| match &attribute.value {
+            AttributeValue::MpReachNlri(mp_reach) => {
+                Some(mp_reach.nlri.iter().map(|prefix| (prefix, mp_reach.safi)))
+            }
+            _ => None,
+        })
+        .flatten();
+    update
+        .nlri
+        .iter()
+        .map(|prefix| (prefix, Safi::Unicast))
+        .chain(mp_announced)
+        .map(|(prefix, safi)| RibKey::decoded(prefix, safi))
+}
+
+/// The attributes stored per route, without the prefixes of the MP attributes
+pub(crate) fn attribute_set(path_attributes: &[PathAttribute]) -> AttributeSet {
+    path_attributes
+        .iter()
+        .filter_map(|attribute| match &attribute.value {
+            AttributeValue::MpUnreachNlri(_) => None,
+            AttributeValue::MpReachNlri(mp_reach) => Some(PathAttribute {
+                value: AttributeValue::MpReachNlri(Box::new(MpReachNlri {
+                    nlri: vec![],
+                    ..MpReachNlri::clone(mp_reach)
+                })),
+                ..attribute.clone()
+            }),
+            _ => Some(attribute.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::IpAddr;
+
+    use crate::attribute::Community;
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        let (addr, length) = s.split_once('/').unwrap();
+        IpAddrPrefix::new(addr.parse().unwrap(), length.parse().unwrap()).unwrap()
+    }
+
+    fn announce(prefixes: &[&str], next_hop: &str, med: u32) -> UpdateMessage {
+        let next_hop: IpAddr = next_hop.parse().unwrap();
+        prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, p| {
+                builder.announce(prefix(p))
+            })
+            .next_hop(next_hop)
+            .med(med)
+            .build()
+    }
+
+    fn withdraw(prefixes: &[&str]) -> UpdateMessage {
+        prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, p| {
+                builder.withdraw(prefix(p))
+            })
+            .build()
+    }
+
+    fn kinds(changes: &[RibChange]) -> Vec<(&'static str, String)> {
+        changes
+            .iter()
+            .map(|change| {
+                let kind = match change {
+                    RibChange::Announced { .. } => "announced",
+                    RibChange::Replaced { .. } => "replaced",
+                    RibChange::Unchanged { .. } => "unchanged",
+                    RibChange::Withdrawn { .. } => "withdrawn",
+                };
+                (kind, change.key().prefix.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_script() {
+        let mut rib = RibIn::new();
+
+        let first = rib.apply(&announce(&["10.0.0.0/8", "192.0.2.0/24"], "192.0.2.1", 10));
+        assert_eq!(
+            kinds(&first),
+            vec![
+                ("announced", "10.0.0.0/8".to_string()),
+                ("announced", "192.0.2.0/24".to_string()),
+            ]
+        );
+        let shared = rib.lookup(&prefix("10.0.0.0/8")).unwrap();
+        assert!(Arc::ptr_eq(
+            shared,
+            rib.lookup(&prefix("192.0.2.0/24")).unwrap()
+        ));
+
+        let second = rib.apply(&announce(&["192.0.2.0/24"], "192.0.2.1", 20));
+        let [RibChange::Replaced { old, new, .. }] = &second[..] else {
+            panic!("expected an implicit withdraw, got {second:?}");
+        };
+        assert_ne!(old, new);
+
+        let third = rib.apply(&announce(&["10.0.0.0/8"], "192.0.2.1", 10));
+        assert_eq!(kinds(&third), vec![("unchanged", "10.0.0.0/8".to_string())]);
+
+        let fourth = rib.apply(&withdraw(&["10.0.0.0/8", "198.51.100.0/24"]));
+        assert_eq!(
+            kinds(&fourth),
+            vec![("withdrawn", "10.0.0.0/8".to_string())]
+        );
+
+        let fifth = rib.apply(&announce(&["2001:db8::/32"], "2001:db8::1", 10));
+        assert_eq!(
+            kinds(&fifth),
+            vec![("announced", "2001:db8::/32".to_string())]
+        );
+        let attributes = rib.lookup(&prefix("2001:db8::/32")).unwrap();
+        assert!(attributes.iter().any(|attribute| matches!(
+            &attribute.value,
+            AttributeValue::MpReachNlri(mp_reach) if mp_reach.nlri.is_empty()
+        )));
+
+        assert_eq!(rib.len(), 2);
+        assert_eq!(rib.prefix_count(Afi::Ipv4, Safi::Unicast), 1);
+        assert_eq!(rib.prefix_count(Afi::Ipv6, Safi::Unicast), 1);
+        assert_eq!(rib.lookup(&prefix("10.0.0.0/8")), None);
+        assert_eq!(rib.lookup(&prefix("192.0.2.0/24")), Some(new));
+
+        let sixth = rib.apply(&withdraw(&["2001:db8::/32"]));
+        assert_eq!(
+            kinds(&sixth),
+            vec![("withdrawn", "2001:db8::/32".to_string())]
+        );
+        assert_eq!(rib.prefix_count(Afi::Ipv6, Safi::Unicast), 0);
+
+        let mut remaining: Vec<_> = rib.iter().map(|(key, _)| key.clone()).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![RibKey::unicast(prefix("192.0.2.0/24"))]);
+        assert_eq!(
+            kinds(&rib.clear()),
+            vec![("withdrawn", "192.0.2.0/24".to_string())]
+        );
+        assert!(rib.is_empty());
+    }
+
+    #[test]
+    fn test_withdraw_then_announce_in_one_update() {
+        let mut rib = RibIn::new();
+        rib.apply(&announce(&["192.0.2.0/24"], "192.0.2.1", 10));
+
+        let mut update = announce(&["192.0.2.0/24"], "192.0.2.1", 20);
+        update.withdrawn_routes.push(prefix("192.0.2.0/24"));
+        assert_eq!(
+            kinds(&rib.apply(&update)),
+            vec![
+                ("withdrawn", "192.0.2.0/24".to_string()),
+                ("announced", "192.0.2.0/24".to_string()),
+            ]
+        );
+        assert_eq!(rib.len(), 1);
+    }
+
+    #[test]
+    fn test_add_path_routes() {
+        let mut rib = RibIn::new();
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix("192.0.2.0/24").with_path_id(Some(1)))
+            .announce(prefix("192.0.2.0/24").with_path_id(Some(2)))
+            .next_hop("192.0.2.1".parse().unwrap())
+            .build();
+        let changes = rib.apply(&update);
+        let keys: Vec<_> = changes.iter().map(|change| change.key().clone()).collect();
+        let key = |path_id| RibKey {
+            path_id: Some(path_id),
+            ..RibKey::unicast(prefix("192.0.2.0/24"))
+        };
+        assert_eq!(keys, [key(1), key(2)]);
+
+        let withdrawal = UpdateMessageBuilder::new()
+            .withdraw(prefix("192.0.2.0/24").with_path_id(Some(1)))
+            .build();
+        assert_eq!(
+            kinds(&rib.apply(&withdrawal)),
+            [("withdrawn", "192.0.2.0/24".to_string())]
+        );
+        assert_eq!(
+            rib.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            [&key(2)]
+        );
+    }
+
+    #[test]
+    fn test_graceful_restart_stale_routes() {
+        let mut rib = RibIn::new();
+        rib.apply(&announce(&["10.0.0.0/8", "192.0.2.0/24"], "192.0.2.1", 10));
+        assert_eq!(rib.mark_stale(), 2);
+
+        // Re-announced routes are fresh again, even when unchanged
+        rib.apply(&announce(&["10.0.0.0/8"], "192.0.2.1", 10));
+        assert!(!rib.is_stale(&RibKey::unicast(prefix("10.0.0.0/8"))));
+        assert!(rib.is_stale(&RibKey::unicast(prefix("192.0.2.0/24"))));
+        assert_eq!(rib.stale_count(), 1);
+
+        assert_eq!(
+            kinds(&rib.sweep_stale()),
+            vec![("withdrawn", "192.0.2.0/24".to_string())]
+        );
+        assert_eq!((rib.len(), rib.stale_count()), (1, 0));
+
+        rib.mark_stale();
+        rib.apply(&withdraw(&["10.0.0.0/8"]));
+        assert_eq!(rib.stale_count(), 0);
+        assert!(rib.sweep_stale().is_empty());
+    }
+
+    #[test]
+    fn test_filter_change_rebuilt_by_route_refresh() {
+        let list = |s: &str| -> Arc<dyn RouteFilter> {
+            Arc::new(s.parse::<crate::filter::PrefixList>().unwrap())
+        };
+        let refresh = |subtype| RouteRefreshMessage {
+            afi: Afi::Ipv4,
+            safi: Safi::Unicast,
+            subtype,
+        };
+        let mut rib = RibIn::new().with_filter(list("permit 10.0.0.0/8 le 24"));
+        rib.apply(&announce(
+            &["10.1.0.0/16", "10.2.0.0/16", "192.0.2.0/24"],
+            "192.0.2.1",
+            10,
+        ));
+        assert_eq!(rib.len(), 2);
+
+        let changes = rib.set_filter(list("permit 10.1.0.0/16\npermit 192.0.2.0/24"));
+        assert_eq!(
+            kinds(&changes),
+            vec![("withdrawn", "10.2.0.0/16".to_string())]
+        );
+        assert_eq!(rib.len(), 1);
+
+        // The peer re-advertises what it still has, 10.1.0.0/16 is gone meanwhile
+        assert!(
+            rib.apply_route_refresh(&refresh(RouteRefreshSubtype::Begin))
+                .is_empty()
+        );
+        assert_eq!(rib.stale_count(), 1);
+        rib.apply(&announce(&["10.2.0.0/16", "192.0.2.0/24"], "192.0.2.1", 10));
+        assert_eq!(
+            kinds(&rib.apply_route_refresh(&refresh(RouteRefreshSubtype::End))),
+            vec![("withdrawn", "10.1.0.0/16".to_string())]
+        );
+        assert_eq!((rib.len(), rib.stale_count()), (1, 0));
+        assert!(rib.lookup(&prefix("192.0.2.0/24")).is_some());
+        assert_eq!(rib.filtered(), 2);
+    }
+
+    #[test]
+    fn test_route_age() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut rib = RibIn::new();
+        rib.apply_at(
+            &announce(&["10.0.0.0/8", "192.0.2.0/24"], "192.0.2.1", 10),
+            at(0),
+        );
+
+        // An implicit withdraw with identical attributes keeps the age
+        let changes = rib.apply_at(&announce(&["10.0.0.0/8"], "192.0.2.1", 10), at(60));
+        assert_eq!(
+            kinds(&changes),
+            vec![("unchanged", "10.0.0.0/8".to_string())]
+        );
+        let key = RibKey::unicast(prefix("10.0.0.0/8"));
+        assert_eq!(rib.age(&key), Some(RouteAge::new(at(0))));
+        assert_eq!(rib.age(&key).unwrap().age(at(90)), Duration::from_secs(90));
+
+        // One with different attributes resets it, but the route stays installed since then
+        rib.apply_at(&announce(&["192.0.2.0/24"], "192.0.2.1", 20), at(120));
+        let other = RibKey::unicast(prefix("192.0.2.0/24"));
+        assert_eq!(
+            rib.age(&other),
+            Some(RouteAge {
+                installed: at(0),
+                changed: at(120),
+                imprecise: false,
+            })
+        );
+
+        let changed = |rib: &RibIn, since| -> Vec<String> {
+            rib.changed_since(since)
+                .map(|(key, _, _)| key.prefix.to_string())
+                .collect()
+        };
+        assert_eq!(changed(&rib, at(1)), vec!["192.0.2.0/24".to_string()]);
+        assert_eq!(changed(&rib, at(0)).len(), 2);
+        assert!(changed(&rib, at(121)).is_empty());
+
+        rib.apply_at(&withdraw(&["192.0.2.0/24"]), at(150));
+        rib.apply_at(&announce(&["10.0.0.0/8"], "192.0.2.1", 30), at(180));
+        rib.apply_at(&announce(&["2001:db8::/32"], "2001:db8::1", 10), at(200));
+        assert_eq!(
+            changed(&rib, at(1)),
+            vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()]
+        );
+        rib.clear();
+        assert!(changed(&rib, at(0)).is_empty());
+
+        // A full table arriving at once, withdrawn in one go
+        let prefixes: Vec<String> = (0..65536)
+            .map(|i| format!("10.{}.{}.0/24", i / 256, i % 256))
+            .collect();
+        let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+        for chunk in prefixes.chunks(4096) {
+            rib.apply_at(&announce(chunk, "192.0.2.1", 10), at(300));
+        }
+        assert_eq!(changed(&rib, at(300)).len(), 65536);
+        for chunk in prefixes.chunks(4096) {
+            rib.apply_at(&withdraw(chunk), at(360));
+        }
+        assert!(changed(&rib, at(0)).is_empty());
+    }
+
+    #[test]
+    fn test_reordered_attributes_unchanged() {
+        let mut rib = RibIn::new();
+        let community = |value| Community { asn: 65001, value };
+        let mut update = UpdateMessageBuilder::new()
+            .announce(prefix("192.0.2.0/24"))
+            .as_path("65001 65002".parse().unwrap())
+            .next_hop("192.0.2.1".parse().unwrap())
+            .community(community(1))
+            .community(community(2))
+            .build();
+        rib.apply(&update);
+
+        update.path_attributes.reverse();
+        update
+            .attributes_mut()
+            .set_communities(vec![community(2), community(1)]);
+        assert_eq!(
+            kinds(&rib.apply(&update)),
+            [("unchanged", "192.0.2.0/24".to_string())]
+        );
+
+        // AS_PATH order matters
+        for attribute in &mut update.path_attributes {
+            if let AttributeValue::AsPath(as_path) = &mut attribute.value {
+                **as_path = "65002 65001".parse().unwrap();
+            }
+        }
+        assert_eq!(
+            kinds(&rib.apply(&update)),
+            [("replaced", "192.0.2.0/24".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_shares_attribute_sets_across_updates() {
+        let mut rib = RibIn::new();
+        rib.apply(&announce(&["10.0.0.0/8"], "192.0.2.1", 10));
+        rib.apply(&announce(&["10.1.0.0/16"], "192.0.2.1", 10));
+        rib.apply(&announce(&["10.2.0.0/16"], "192.0.2.1", 20));
+        assert!(Arc::ptr_eq(
+            rib.lookup(&prefix("10.0.0.0/8")).unwrap(),
+            rib.lookup(&prefix("10.1.0.0/16")).unwrap()
+        ));
+        let stats = rib.interner_stats();
+        assert_eq!((stats.hits, stats.misses, stats.resident), (1, 2, 2));
+
+        rib.apply(&withdraw(&["10.0.0.0/8", "10.2.0.0/16"]));
+        assert_eq!(rib.interner_stats().resident, 1);
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let mut rib = RibIn::new();
+        let empty = rib.memory_stats();
+        assert_eq!(empty.total_bytes(), 0);
+
+        let prefixes: Vec<String> = (0..100).map(|i| format!("10.{i}.0.0/16")).collect();
+        for (i, chunk) in prefixes.chunks(10).enumerate() {
+            let chunk: Vec<&str> = chunk.iter().map(String::as_str).collect();
+            rib.apply(&announce(&chunk, "192.0.2.1", i as u32 % 5));
+        }
+        // Replacing, some with a set already present
+        rib.apply(&announce(&["10.0.0.0/16", "10.1.0.0/16"], "192.0.2.1", 4));
+        rib.apply(&announce(&["10.2.0.0/16"], "192.0.2.2", 0));
+        rib.mark_stale();
+
+        let stats = rib.memory_stats();
+        assert_eq!((stats.routes, stats.attribute_sets), (100, 6));
+        let deep = rib.deep_measure();
+        assert_eq!(
+            (
+                deep.routes,
+                deep.attribute_sets,
+                deep.key_bytes,
+                deep.attribute_bytes
+            ),
+            (
+                stats.routes,
+                stats.attribute_sets,
+                stats.key_bytes,
+                stats.attribute_bytes
+            )
+        );
+        assert!(deep.index_bytes >= stats.index_bytes);
+        let set = rib.lookup(&prefix("10.3.0.0/16")).unwrap();
+        assert!(stats.attribute_bytes >= 6 * set.len() * size_of::<PathAttribute>());
+
+        let refs: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+        rib.apply(&withdraw(&refs));
+        let stats = rib.memory_stats();
+        assert_eq!(
+            (
+                stats.routes,
+                stats.attribute_sets,
+                stats.key_bytes,
+                stats.attribute_bytes
+            ),
+            (0, 0, 0, 0)
+        );
+        assert_eq!(rib.deep_measure().attribute_bytes, 0);
+    }
+}
@@ -0,0 +1,390 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::loc_rib::{BestPathChange, DecisionConfig, LocRib, PeerPath, RibPeer};
+use super::{RibChange, RibKey};
+
+/// Commands a shard queues before the senders wait
+const SHARD_QUEUE: usize = 1024;
+
+/// A [`LocRib`] split by route across worker tasks, so that the changes of many full-table
+/// peers are applied in parallel.
+///
+/// Each peer's [`RibIn`] stays with the task reading its session, which hands the changes to
+/// [`ShardedLocRib::submit`] or [`ShardedLocRib::apply`]; every shard then runs the decision
+/// process for the routes hashed to it. Queries over all routes ask every shard and merge the
+/// answers. Clones share the shards, which stop once the last clone is dropped.
+///
+/// Changes from one task are applied in the order submitted, and the decision process doesn't
+/// depend on the order paths arrive in, so the result is the same as feeding a single
+/// [`LocRib`].
+///
+/// [`RibIn`]: super::RibIn
+#[derive(Debug, Clone)]
+pub struct ShardedLocRib {
+    shards: Arc<[mpsc::Sender<Command>]>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Apply {
+        peer: RibPeer,
+        changes: Vec<RibChange>,
+        reply: Option<oneshot::Sender<Vec<BestPathChange>>>,
+    },
+    RemovePeer {
+        peer: IpAddr,
+        reply: oneshot::Sender<Vec<BestPathChange>>,
+    },
+    Get {
+        key: RibKey,
+        reply: oneshot::Sender<Option<PeerPath>>,
+    },
+    LongestMatch {
+        addr: IpAddr,
+        reply: oneshot::Sender<Option<(RibKey, PeerPath)>>,
+    },
+    Snapshot {
+        reply: oneshot::Sender<Vec<(RibKey, PeerPath)>>,
+    },
+    Len {
+        reply: oneshot::Sender<usize>,
+    },
+}
+
+impl ShardedLocRib {
+    /// Spawns `shards` workers on the current runtime
+    ///
+    /// # Panics
+    ///
+    /// When `shards` is zero
+    pub fn spawn(config: DecisionConfig, shards: usize) -> Self {
+        assert!(shards > 0, "a sharded Loc-RIB needs at least one shard");
+        let shards = (0..shards)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel(SHARD_QUEUE);
+                tokio::spawn(run(LocRib::new(config), receiver));
+                sender
+            })
+            .collect();
+        ShardedLocRib { shards }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Applies the changes an Adj-RIB-In of `peer` reported and returns the resulting best
+    /// path changes, grouped by shard
+    pub async fn apply(&self, peer: &RibPeer, changes: &[RibChange]) -> Vec<BestPathChange> {
+        let mut replies = vec![];
+        for (shard, changes) in self.partition(changes) {
+            let (reply, receiver) = oneshot::channel();
+            let command = Command::Apply {
+                peer: *peer,
+                changes,
+                reply: Some(reply),
+            };
+            if self.shards[shard].send(command).await.is_ok() {
+                replies.push(receiver);
+            }
+        }
+        let mut best_path_changes = vec![];
+        for reply in replies {
+            best_path_changes.extend(reply.await.unwrap_or_default());
+        }
+        best_path_changes
+    }
+
+    /// Queues the changes without waiting for them to be applied, waiting only while a shard's
+    /// queue is full
+    pub async fn submit(&self, peer: &RibPeer, changes: &[RibChange]) {
+        for (shard, changes) in self.partition(changes) {
+            let command = Command::Apply {
+                peer: *peer,
+                changes,
+                reply: None,
+            };
+            let _ = self.shards[shard].send(command).await;
+        }
+    }
+
+    /// Drops every path of a peer, as when its session goes down
+    pub async fn remove_peer(&self, peer: IpAddr) -> Vec<BestPathChange> {
+        self.gather(|reply| Command::RemovePeer { peer, reply })
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    pub async fn get(&self, key: &RibKey) -> Option<PeerPath> {
+        let (reply, receiver) = oneshot::channel();
+        let command = Command::Get {
+            key: key.clone(),
+            reply,
+        };
+        self.shards[self.shard(key)].send(command).await.ok()?;
+        receiver.await.ok()?
+    }
+
+    /// The best path of the most specific unicast route covering `addr`, see
+    /// [`LocRib::longest_match`]
+    pub async fn longest_match(&self, addr: IpAddr) -> Option<(RibKey, PeerPath)> {
+        self.gather(|reply| Command::LongestMatch { addr, reply })
+            .await
+            .into_iter()
+            .flatten()
+            .max_by_key(|(key, _)| key.prefix.length())
+    }
+
+    /// The best path of every route, ordered by key
+    pub async fn snapshot(&self) -> Vec<(RibKey, PeerPath)> {
+        let mut routes: Vec<(RibKey, PeerPath)> = self
+            .gather(|reply| Command::Snapshot { reply })
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        routes
+    }
+
+    /// Number of routes with a best path
+    pub async fn len(&self) -> usize {
+        self.gather(|reply| Command::Len { reply })
+            .await
+            .into_iter()
+            .sum()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    fn shard(&self, key: &RibKey) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Splits changes by shard, keeping their order
+    fn partition(&self, changes: &[RibChange]) -> Vec<(usize, Vec<RibChange>)> {
+        let mut partitioned: Vec<Vec<RibChange>> = vec![vec![]; self.shards.len()];
+        for change in changes {
+            partitioned[self.shard(change.key())].push(change.clone());
+        }
+        partitioned
+            .into_iter()
+            .enumerate()
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect()
+    }
+
+    /// Sends a query to every shard and collects the answers of those still running
+    async fn gather<T>(&self, command: impl Fn(oneshot::Sender<T>) -> Command) -> Vec<T> {
+        let mut replies = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let (reply, receiver) = oneshot::channel();
+            if shard.send(command(reply)).await.is_ok() {
+                replies.push(receiver);
+            }
+        }
+        let mut answers = Vec::with_capacity(replies.len());
+        for reply in replies {
+            answers.extend(reply.await.ok());
+        }
+        answers
+    }
+}
+
+async fn run(mut rib: LocRib, mut commands: mpsc::Receiver<Command>) {
+    while let Some(command) = commands.recv().await {
+        // Nobody waiting for an answer is no reason to stop
+        match command {
+            Command::Apply {
+                peer,
+                changes,
+                reply,
+            } => {
+                let best_path_changes = rib.apply(&peer, &changes);
+                if let Some(reply) = reply {
+                    let _ = reply.send(best_path_changes);
+                }
+            }
+            Command::RemovePeer { peer, reply } => {
+                let _ = reply.send(rib.remove_peer(peer));
+            }
+            Command::Get { key, reply } => {
+                let _ = reply.send(rib.get(&key).cloned());
+            }
+            Command::LongestMatch { addr, reply } => {
+                let matched = rib
+                    .longest_match(addr)
+                    .map(|(key, path)| (key.clone(), path.clone()));
+                let _ = reply.send(matched);
+            }
+            Command::Snapshot { reply } => {
+                let routes = rib
+                    .iter()
+                    .map(|(key, path)| (key.clone(), path.clone()))
+                    .collect();
+                let _ = reply.send(routes);
+            }
+            Command::Len { reply } => {
+                let _ = reply.send(rib.len());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+    use crate::rib::RibIn;
+    use crate::update_message::{IpAddrPrefix, UpdateMessage, UpdateMessageBuilder};
+
+    const PEERS: u8 = 6;
+    const PREFIXES: u32 = 500;
+    const UPDATES: u32 = 400;
+
+    fn peer(n: u8) -> RibPeer {
+        RibPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)),
+            asn: 65000 + n as u32,
+            router_id: Ipv4Addr::new(192, 0, 2, n),
+            external: true,
+        }
+    }
+
+    /// The UPDATEs a synthetic peer sends: random paths to random prefixes, some withdrawn
+    fn updates(n: u8) -> Vec<UpdateMessage> {
+        let mut state = 0x9e37_79b9_u32.wrapping_mul(n as u32 + 1);
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        (0..UPDATES)
+            .map(|_| {
+                let addr = IpAddr::V4(Ipv4Addr::from_bits(
+                    0x0a00_0000 + ((random() % PREFIXES) << 8),
+                ));
+                let prefix = IpAddrPrefix::new(addr, 24).unwrap();
+                if random() % 4 == 0 {
+                    return UpdateMessageBuilder::new().withdraw(prefix).build();
+                }
+                let length = 1 + random() % 4;
+                UpdateMessageBuilder::new()
+                    .announce(prefix)
+                    .as_path(AsPath {
+                        segments: [AsPathSegment {
+                            segment_type: AsPathSegmentType::AsSequence,
+                            asns: (0..length).map(|i| 65000 + n as u32 + i).collect(),
+                        }]
+                        .into(),
+                    })
+                    .med(random() % 3)
+                    .next_hop(peer(n).addr)
+                    .build()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_peers_match_sequential() {
+        let sharded = ShardedLocRib::spawn(DecisionConfig::default(), 4);
+        let peers: Vec<_> = (1..=PEERS)
+            .map(|n| {
+                let sharded = sharded.clone();
+                tokio::spawn(async move {
+                    let mut rib_in = RibIn::new();
+                    for update in updates(n) {
+                        sharded.submit(&peer(n), &rib_in.apply(&update)).await;
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in peers {
+            task.await.unwrap();
+        }
+
+        let mut reference = LocRib::new(DecisionConfig::default());
+        for n in 1..=PEERS {
+            let mut rib_in = RibIn::new();
+            for update in updates(n) {
+                reference.apply(&peer(n), &rib_in.apply(&update));
+            }
+        }
+        let mut expected: Vec<(RibKey, PeerPath)> = reference
+            .iter()
+            .map(|(key, path)| (key.clone(), path.clone()))
+            .collect();
+        expected.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        assert!(!expected.is_empty());
+        assert_eq!(sharded.snapshot().await, expected);
+        assert_eq!(sharded.len().await, reference.len());
+        let (key, path) = &expected[0];
+        assert_eq!(sharded.get(key).await.as_ref(), Some(path));
+        let (matched, _) = sharded.longest_match(key.prefix.addr()).await.unwrap();
+        assert_eq!(&matched, key);
+
+        // Removing a peer agrees too
+        let removed = sharded.remove_peer(peer(1).addr).await;
+        let mut expected_removed = reference.remove_peer(peer(1).addr);
+        let mut removed_keys: Vec<_> = removed.into_iter().map(|change| change.key).collect();
+        removed_keys.sort();
+        expected_removed.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            removed_keys,
+            expected_removed
+                .into_iter()
+                .map(|change| change.key)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(sharded.len().await, reference.len());
+    }
+
+    #[tokio::test]
+    async fn test_apply_reports_best_path_changes() {
+        let sharded = ShardedLocRib::spawn(DecisionConfig::default(), 3);
+        assert_eq!(sharded.shards(), 3);
+        let mut rib_in = RibIn::new();
+        let update = UpdateMessageBuilder::new()
+            .announce("198.51.100.0/24".parse().unwrap())
+            .announce("198.51.0.0/16".parse().unwrap())
+            .announce("203.0.113.0/24".parse().unwrap())
+            .next_hop(peer(1).addr)
+            .build();
+        let changes = sharded.apply(&peer(1), &rib_in.apply(&update)).await;
+        assert_eq!(changes.len(), 3);
+        assert!(changes.iter().all(|change| change.old.is_none()));
+
+        let matched = sharded
+            .longest_match("198.51.100.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(matched.0.prefix.to_string(), "198.51.100.0/24");
+        let matched = sharded
+            .longest_match("198.51.7.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(matched.0.prefix.to_string(), "198.51.0.0/16");
+        assert!(
+            sharded
+                .longest_match("192.0.2.1".parse().unwrap())
+                .await
+                .is_none()
+        );
+        assert!(!sharded.is_empty().await);
+    }
+}
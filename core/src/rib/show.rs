@@ -0,0 +1,461 @@
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::address_family::Afi;
+use crate::attribute::{
+    Aggregator, AsPath, AttributeValue, Community, MpReachNlri, OriginType, PathAttribute,
+};
+use crate::update_message::IpAddrPrefix;
+
+use super::{AttributeSet, LocRib, PeerPath, RibIn, RibKey};
+
+const HEADER: &str = "Status codes: * valid, > best, i - internal\n\
+                      Origin codes: i - IGP, e - EGP, ? - incomplete\n\n";
+
+/// Renders routes the way routers show their BGP table, e.g.
+///
+/// ```text
+///    Network       Next Hop    Metric LocPrf Weight Path
+/// *> 10.0.0.0/8    192.0.2.1        0    100      0 65001 65002 i
+/// * i              192.0.2.200           100      0 3356 65002 ?
+/// ```
+///
+/// Columns are as wide as their widest value, further paths of a prefix leave the network
+/// blank. Weight is local to a router and never
+/// carried in BGP, so it is always 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteTableFormatter {
+    header: bool,
+}
+
+/// One line of the table
+struct Row {
+    status: &'static str,
+    /// `None` for further paths of the prefix on the line above
+    network: Option<String>,
+    next_hop: String,
+    metric: String,
+    local_pref: String,
+    path: String,
+}
+
+impl RouteTableFormatter {
+    pub fn new() -> Self {
+        RouteTableFormatter { header: true }
+    }
+
+    /// Starts with the legend of status and origin codes, on by default
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Every path of every route, `>` marking the best ones and `i` those learned over iBGP
+    pub fn format_loc_rib(&self, rib: &LocRib) -> String {
+        let mut keys: Vec<_> = rib.iter().map(|(key, _)| key).collect();
+        keys.sort();
+        let mut rows = vec![];
+        for key in keys {
+            let best = rib.get(key);
+            for (i, path) in rib.candidates(key).iter().enumerate() {
+                let status = match (
+                    best.is_some_and(|best| std::ptr::eq(best, path)),
+                    path.peer.external,
+                ) {
+                    (true, true) => "*> ",
+                    (true, false) => "*>i",
+                    (false, true) => "*  ",
+                    (false, false) => "* i",
+                };
+                rows.push(Row::new(status, key, i == 0, &path.attributes));
+            }
+        }
+        self.render(&rows)
+    }
+
+    /// The routes of a single peer
+    pub fn format_rib_in(&self, rib: &RibIn) -> String {
+        let mut routes: Vec<_> = rib.iter().collect();
+        routes.sort_by_key(|(key, _)| *key);
+        let rows: Vec<_> = routes
+            .into_iter()
+            .map(|(key, attributes)| Row::new("*  ", key, true, attributes))
+            .collect();
+        self.render(&rows)
+    }
+
+    /// A single route
+    pub fn format_route(&self, key: &RibKey, attributes: &AttributeSet) -> String {
+        self.render(&[Row::new("*  ", key, true, attributes)])
+    }
+
+    /// Every path of one route with all of its attributes, as `show ip bgp <prefix>` does,
+    /// `None` when the Loc-RIB has no such route
+    pub fn format_detail(&self, rib: &LocRib, prefix: &IpAddrPrefix) -> Option<String> {
+        let key = RibKey::unicast(prefix.clone());
+        let best = rib.get(&key)?;
+        let paths = rib.candidates(&key);
+        let best_index = paths.iter().position(|path| std::ptr::eq(path, best))?;
+
+        let mut out = format!("BGP routing table entry for {prefix}\n");
+        let _ = writeln!(
+            out,
+            "Paths: ({} available, best #{})",
+            paths.len(),
+            best_index + 1
+        );
+        for (i, path) in paths.iter().enumerate() {
+            detail(&mut out, prefix.afi(), path, i == best_index);
+        }
+        Some(out)
+    }
+
+    fn render(&self, rows: &[Row]) -> String {
+        let width = |title: &str, column: fn(&Row) -> &str| {
+            rows.iter()
+                .map(|row| column(row).len())
+                .fold(title.len(), usize::max)
+        };
+        let network = width("Network", |row| row.network.as_deref().unwrap_or_default());
+        let next_hop = width("Next Hop", |row| &row.next_hop);
+        let metric = width("Metric", |row| &row.metric);
+        let local_pref = width("LocPrf", |row| &row.local_pref);
+
+        let mut out = String::new();
+        if self.header {
+            out.push_str(HEADER);
+        }
+        let _ = writeln!(
+            out,
+            "{:3}{:network$} {:next_hop$} {:>metric$} {:>local_pref$} Weight Path",
+            "", "Network", "Next Hop", "Metric", "LocPrf"
+        );
+        for row in rows {
+            let line = format!(
+                "{}{:network$} {:next_hop$} {:>metric$} {:>local_pref$} {:>6} {}",
+                row.status,
+                row.network.as_deref().unwrap_or_default(),
+                row.next_hop,
+                row.metric,
+                row.local_pref,
+                0,
+                row.path,
+            );
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Default for RouteTableFormatter {
+    fn default() -> Self {
+        RouteTableFormatter::new()
+    }
+}
+
+impl Row {
+    fn new(status: &'static str, key: &RibKey, first: bool, attributes: &AttributeSet) -> Self {
+        let fields = Fields::new(attributes);
+        let mut path = fields.as_path.map(AsPath::to_string).unwrap_or_default();
+        if !path.is_empty() {
+            path.push(' ');
+        }
+        path.push(match fields.origin {
+            Some(OriginType::Igp) => 'i',
+            Some(OriginType::Egp) => 'e',
+            Some(OriginType::Incomplete) | None => '?',
+        });
+        Row {
+            status,
+            network: first.then(|| key.prefix.to_string()),
+            next_hop: fields.next_hop(key.prefix.afi()).to_string(),
+            metric: fields.med.map(|med| med.to_string()).unwrap_or_default(),
+            local_pref: fields
+                .local_pref
+                .map(|pref| pref.to_string())
+                .unwrap_or_default(),
+            path,
+        }
+    }
+}
+
+fn detail(out: &mut String, afi: Afi, path: &PeerPath, best: bool) {
+    let fields = Fields::new(&path.attributes);
+    let as_path = fields.as_path.map(AsPath::to_string).unwrap_or_default();
+    let _ = writeln!(
+        out,
+        "  {}",
+        if as_path.is_empty() {
+            "Local"
+        } else {
+            &as_path
+        }
+    );
+    let _ = write!(
+        out,
+        "    {} from {} ({})",
+        fields.next_hop(afi),
+        path.peer.addr,
+        path.peer.router_id
+    );
+    if let Some(link_local) = fields.link_local(afi) {
+        let _ = write!(out, ", link-local {link_local}");
+    }
+    out.push('\n');
+
+    let origin = match fields.origin {
+        Some(OriginType::Igp) => "IGP",
+        Some(OriginType::Egp) => "EGP",
+        Some(OriginType::Incomplete) | None => "incomplete",
+    };
+    let _ = write!(out, "      Origin {origin}");
+    if let Some(med) = fields.med {
+        let _ = write!(out, ", metric {med}");
+    }
+    if let Some(local_pref) = fields.local_pref {
+        let _ = write!(out, ", localpref {local_pref}");
+    }
+    out.push_str(", valid");
+    out.push_str(if path.peer.external {
+        ", external"
+    } else {
+        ", internal"
+    });
+    if fields.atomic_aggregate {
+        out.push_str(", atomic-aggregate");
+    }
+    if best {
+        out.push_str(", best");
+    }
+    out.push('\n');
+
+    if !fields.communities.is_empty() {
+        let communities: Vec<_> = fields
+            .communities
+            .iter()
+            .map(Community::to_string)
+            .collect();
+        let _ = writeln!(out, "      Community: {}", communities.join(" "));
+    }
+    if let Some(aggregator) = fields.aggregator {
+        let _ = writeln!(
+            out,
+            "      Aggregator: AS {}, {}",
+            aggregator.asn, aggregator.ip
+        );
+    }
+    for (type_code, length) in fields.unknown {
+        let _ = writeln!(out, "      Unknown attribute {type_code}, {length} bytes");
+    }
+}
+
+/// The attributes shown, picked out of an [`AttributeSet`]
+#[derive(Default)]
+pub(crate) struct Fields<'a> {
+    pub origin: Option<OriginType>,
+    pub as_path: Option<&'a AsPath>,
+    next_hop: Option<Ipv4Addr>,
+    mp_reach: Option<&'a MpReachNlri>,
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+    pub atomic_aggregate: bool,
+    pub aggregator: Option<&'a Aggregator>,
+    pub communities: &'a [Community],
+    /// Type code and length of attributes without a dedicated field
+    unknown: Vec<(u8, usize)>,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(attributes: &'a [PathAttribute]) -> Self {
+        let mut fields = Fields::default();
+        for attribute in attributes.iter() {
+            match &attribute.value {
+                AttributeValue::Origin(origin) => fields.origin = Some(origin.origin_type),
+                AttributeValue::AsPath(as_path) => fields.as_path = Some(as_path),
+                AttributeValue::NextHop(next_hop) => fields.next_hop = Some(next_hop.ip),
+                AttributeValue::MpReachNlri(mp_reach) => fields.mp_reach = Some(mp_reach),
+                AttributeValue::MultiExitDisc(med) => fields.med = Some(med.med),
+                AttributeValue::LocalPref(pref) => fields.local_pref = Some(pref.pref),
+                AttributeValue::AtomicAggregate => fields.atomic_aggregate = true,
+                AttributeValue::Aggregator(aggregator) => fields.aggregator = Some(aggregator),
+                AttributeValue::Communities(communities) => {
+                    fields.communities = &communities.communities
+                }
+                AttributeValue::MpUnreachNlri(_) => {}
+                AttributeValue::Unknown(value) => fields
+                    .unknown
+                    .push((u8::from(&attribute.type_code), value.len())),
+            }
+        }
+        fields
+    }
+
+    /// The next hop for prefixes of a family, the unspecified address when there is none.
+    ///
+    /// An UPDATE with IPv4 and IPv6 prefixes leaves MP_REACH_NLRI on the IPv4 routes too.
+    pub fn next_hop(&self, afi: Afi) -> IpAddr {
+        let mp_next_hop = self
+            .mp_reach
+            .filter(|mp_reach| mp_reach.afi == afi)
+            .map(|mp_reach| mp_reach.next_hop);
+        match afi {
+            Afi::Ipv4 => self
+                .next_hop
+                .map(IpAddr::V4)
+                .or(mp_next_hop)
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            _ => mp_next_hop.unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        }
+    }
+
+    fn link_local(&self, afi: Afi) -> Option<Ipv6Addr> {
+        self.mp_reach
+            .filter(|mp_reach| mp_reach.afi == afi)
+            .and_then(|mp_reach| mp_reach.link_local)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::attribute::{
+        AsPathSegment, AsPathSegmentType, AttributeType, PathAttribute, PathAttributeFlags,
+    };
+    use crate::rib::{DecisionConfig, RibPeer};
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn snapshot(name: &str) -> String {
+        fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn peer(n: u8, asn: u32) -> RibPeer {
+        RibPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, n)),
+            asn,
+            router_id: Ipv4Addr::new(10, 0, 0, n),
+            external: asn != 65000,
+        }
+    }
+
+    fn path(segments: &[(AsPathSegmentType, &[u32])]) -> AsPath {
+        AsPath {
+            segments: segments
+                .iter()
+                .map(|(segment_type, asns)| AsPathSegment {
+                    segment_type: *segment_type,
+                    asns: asns.to_vec().into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn table() -> LocRib {
+        use AsPathSegmentType::*;
+
+        let mut rib = LocRib::new(DecisionConfig::default());
+        let mut feed = |peer: RibPeer, builder: UpdateMessageBuilder| {
+            let mut rib_in = RibIn::new();
+            let changes = rib_in.apply(&builder.build());
+            rib.apply(&peer, &changes);
+        };
+        let prefix = |s: &str| s.parse::<IpAddrPrefix>().unwrap();
+
+        feed(
+            peer(1, 65001),
+            UpdateMessageBuilder::new()
+                .as_path(path(&[(AsSequence, &[65001, 65002])]))
+                .next_hop("192.0.2.1".parse().unwrap())
+                .med(0)
+                .community(Community {
+                    asn: 65001,
+                    value: 100,
+                })
+                .announce(prefix("10.0.0.0/8"))
+                .announce(prefix("172.16.0.0/12")),
+        );
+        feed(
+            peer(2, 65000),
+            UpdateMessageBuilder::new()
+                .origin(OriginType::Incomplete)
+                .as_path(path(&[
+                    (AsConfedSequence, &[65010]),
+                    (AsSequence, &[3356, 65002]),
+                ]))
+                .next_hop("192.0.2.200".parse().unwrap())
+                .local_pref(200)
+                .attribute(PathAttribute {
+                    flags: PathAttributeFlags::optional_transitive(),
+                    type_code: AttributeType::Aggregator,
+                    value: AttributeValue::Aggregator(Aggregator {
+                        asn: 65002,
+                        ip: Ipv4Addr::new(10, 1, 1, 1),
+                    }),
+                })
+                .announce(prefix("10.0.0.0/8")),
+        );
+        feed(
+            peer(3, 64496),
+            UpdateMessageBuilder::new()
+                .origin(OriginType::Egp)
+                .as_path(path(&[
+                    (AsSequence, &[64496, 174]),
+                    (AsSet, &[64511, 64512]),
+                ]))
+                .next_hop("192.0.2.3".parse().unwrap())
+                .mp_next_hop(
+                    "2001:db8::3".parse().unwrap(),
+                    Some("fe80::3".parse().unwrap()),
+                )
+                .med(4294967295)
+                .announce(prefix("2001:db8:1000::/36"))
+                .announce(prefix("172.16.0.0/12")),
+        );
+        rib
+    }
+
+    #[test]
+    fn test_loc_rib_table() {
+        let rib = table();
+        assert_eq!(
+            RouteTableFormatter::new().format_loc_rib(&rib),
+            snapshot("show_ip_bgp.txt")
+        );
+        // Without the legend, a single route
+        let key = RibKey::unicast("10.0.0.0/8".parse().unwrap());
+        let best = rib.get(&key).unwrap();
+        assert_eq!(
+            RouteTableFormatter::new()
+                .header(false)
+                .format_route(&key, &best.attributes),
+            "   Network    Next Hop    Metric LocPrf Weight Path\n\
+             *  10.0.0.0/8 192.0.2.200           200      0 (65010) 3356 65002 ?\n"
+        );
+    }
+
+    #[test]
+    fn test_detail() {
+        let rib = table();
+        let formatter = RouteTableFormatter::new();
+        let detail = formatter.format_detail(&rib, &"10.0.0.0/8".parse().unwrap());
+        assert_eq!(detail.unwrap(), snapshot("show_ip_bgp_detail.txt"));
+        let detail = formatter.format_detail(&rib, &"2001:db8:1000::/36".parse().unwrap());
+        assert!(
+            detail
+                .unwrap()
+                .contains("    2001:db8::3 from 192.0.2.3 (10.0.0.3), link-local fe80::3\n")
+        );
+        assert_eq!(
+            formatter.format_detail(&rib, &"10.0.0.0/9".parse().unwrap()),
+            None
+        );
+    }
+}
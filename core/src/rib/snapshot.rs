@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::PathAttribute;
+use crate::fingerprint::fnv1a;
+use crate::update_message::IpAddrPrefix;
+
+use super::{AttributeSet, RibIn, RibKey, RouteAge};
+
+const MAGIC: &[u8; 6] = b"BGPRIB";
+/// Bumped whenever the layout below changes
+pub const SNAPSHOT_VERSION: u16 = 2;
+/// The first version, without route ages
+const VERSION_WITHOUT_AGES: u16 = 1;
+/// Magic, version and checksum
+const HEADER_LEN: usize = 6 + 2 + 8;
+/// AFI, SAFI, path identifier flag, prefix length and attribute set index
+const MIN_ROUTE_LEN: usize = 2 + 1 + 1 + 1 + 4;
+/// Installed and changed times
+const AGE_LEN: usize = 8 + 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("not a RIB snapshot")]
+    BadMagic,
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(u16),
+    #[error("snapshot checksum mismatch")]
+    Checksum,
+    #[error("snapshot truncated")]
+    Truncated,
+    #[error("malformed attribute set {0}")]
+    MalformedAttributes(u32),
+    #[error("malformed route {0}")]
+    MalformedRoute(u64),
+}
+
+/// Binary image of a [`RibIn`], for persisting it or handing it to offline analysis.
+///
+/// The layout, all integers big endian, is
+///
+/// ```text
+/// "BGPRIB" | version: u16 | FNV-1a checksum of the rest: u64
+/// attribute sets: u32 | routes: u64
+/// per attribute set: length: u32 | path attributes as encoded in an UPDATE
+/// per route: AFI: u16 | SAFI: u8 | has path id: u8 | [path id: u32]
+///            | prefix as encoded in NLRI | attribute set index: u32
+///            | installed: u64 | changed: u64
+/// ```
+///
+/// Route ages are stored as milliseconds since the Unix epoch, so they survive a restart, though
+/// those from before the monotonic clock reaches come back [imprecise](RouteAge::imprecise).
+/// Version 1 snapshots have none and their routes are restored as installed at the restore.
+///
+/// Attribute sets shared by several routes are written once and shared again after
+/// [`RibIn::restore`]. The snapshot only holds the encoded bytes, so taking or restoring one
+/// costs a single buffer the size of the encoding on top of the RIB itself, about 28 bytes per
+/// IPv4 route plus the distinct attribute sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RibSnapshot {
+    bytes: Bytes,
+}
+
+impl RibSnapshot {
+    /// Checks the header and checksum of a snapshot read back from storage
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, SnapshotError> {
+        if bytes.len() < MAGIC.len() + 2 {
+            return Err(SnapshotError::Truncated);
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if !(VERSION_WITHOUT_AGES..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(SnapshotError::Truncated);
+        }
+        let checksum = u64::from_be_bytes(bytes[8..HEADER_LEN].try_into().unwrap());
+        if fnv1a(&bytes[HEADER_LEN..]) != checksum {
+            return Err(SnapshotError::Checksum);
+        }
+        Ok(RibSnapshot { bytes })
+    }
+
+    /// The encoded snapshot, as written to storage
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    pub fn version(&self) -> u16 {
+        u16::from_be_bytes([self.bytes[6], self.bytes[7]])
+    }
+}
+
+/// Converts between the monotonic and the wall clock, as of a single reading of both
+#[derive(Clone, Copy)]
+pub(crate) struct Clocks {
+    instant: Instant,
+    wall: SystemTime,
+}
+
+impl Clocks {
+    pub(crate) fn now() -> Self {
+        Clocks {
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Milliseconds since the epoch
+    fn to_wall(self, instant: Instant) -> u64 {
+        let wall = match self.instant.checked_duration_since(instant) {
+            Some(ago) => self.wall.checked_sub(ago).unwrap_or(UNIX_EPOCH),
+            None => self.wall + instant.duration_since(self.instant),
+        };
+        wall.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    /// `None` for times from before the monotonic clock can reach, which on some platforms
+    /// starts at boot
+    fn to_instant(self, millis: u64) -> Option<Instant> {
+        let wall = UNIX_EPOCH + Duration::from_millis(millis);
+        match self.wall.duration_since(wall) {
+            Ok(ago) => self.instant.checked_sub(ago),
+            Err(ahead) => Some(self.instant + ahead.duration()),
+        }
+    }
+
+    /// The earliest instant the monotonic clock can express
+    fn earliest(self) -> Instant {
+        let back = |nanos: u128| {
+            let duration = Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            );
+            self.instant.checked_sub(duration)
+        };
+        let (mut low, mut high) = (0, u64::MAX as u128 * 1_000_000_000);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            match back(mid) {
+                Some(_) => low = mid,
+                None => high = mid - 1,
+            }
+        }
+        back(low).unwrap_or(self.instant)
+    }
+
+    /// The age of a route installed and last changed at these milliseconds since the epoch.
+    ///
+    /// Times the monotonic clock can't reach, as after a reboot, are taken as its earliest, so
+    /// such routes still count as older than any other, and the age is flagged imprecise.
+    pub(crate) fn to_age(self, installed: u64, changed: u64) -> RouteAge {
+        let (installed, changed) = (self.to_instant(installed), self.to_instant(changed));
+        RouteAge {
+            installed: installed.unwrap_or_else(|| self.earliest()),
+            changed: changed.unwrap_or_else(|| self.earliest()),
+            imprecise: installed.is_none() || changed.is_none(),
+        }
+    }
+}
+
+impl RibIn {
+    /// Encodes every route, see [`RibSnapshot`] for the format
+    pub fn snapshot(&self) -> RibSnapshot {
+        let clocks = Clocks::now();
+        let mut bytes = BytesMut::with_capacity(HEADER_LEN + 12 + self.len() * (13 + AGE_LEN));
+        bytes.put_slice(MAGIC);
+        bytes.put_u16(SNAPSHOT_VERSION);
+        // Checksum and attribute set count are filled in once known
+        bytes.put_u64(0);
+        bytes.put_u32(0);
+        bytes.put_u64(self.len() as u64);
+
+        // Encodes into a single buffer in two passes, over the sets and then the routes
+        let mut sets: HashMap<*const PathAttribute, u32> = HashMap::new();
+        for (_, attributes) in self.iter() {
+            let next = sets.len() as u32;
+            if let Entry::Vacant(entry) = sets.entry(Arc::as_ptr(attributes) as *const _) {
+                entry.insert(next);
+                let length_at = bytes.len();
+                bytes.put_u32(0);
+                for attribute in attributes.iter() {
+                    attribute.encode(&mut bytes);
+                }
+                let length = (bytes.len() - length_at - 4) as u32;
+                bytes[length_at..length_at + 4].copy_from_slice(&length.to_be_bytes());
+            }
+        }
+        for (key, attributes, age) in self.iter_ages() {
+            bytes.put_u16(key.prefix.afi().into());
+            bytes.put_u8(key.safi.into());
+            match key.path_id {
+                Some(path_id) => {
+                    bytes.put_u8(1);
+                    bytes.put_u32(path_id);
+                }
+                None => bytes.put_u8(0),
+            }
+            key.prefix.encode(&mut bytes);
+            bytes.put_u32(sets[&(Arc::as_ptr(attributes) as *const _)]);
+            bytes.put_u64(clocks.to_wall(age.installed));
+            bytes.put_u64(clocks.to_wall(age.changed));
+        }
+
+        bytes[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&(sets.len() as u32).to_be_bytes());
+        let checksum = fnv1a(&bytes[HEADER_LEN..]);
+        bytes[8..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+        RibSnapshot {
+            bytes: bytes.freeze(),
+        }
+    }
+
+    /// Rebuilds a RIB from a snapshot. Filters aren't part of the snapshot, so the routes are
+    /// restored as they were stored.
+    pub fn restore(snapshot: &RibSnapshot) -> Result<RibIn, SnapshotError> {
+        let clocks = Clocks::now();
+        let routes = snapshot.routes()?;
+        let mut rib = RibIn::new();
+        rib.reserve(routes.len());
+        for route in routes {
+            let age = match route.ages {
+                None => RouteAge::new(clocks.instant),
+                Some((installed, changed)) => clocks.to_age(installed, changed),
+            };
+            let _ = rib.insert(route.key, route.attributes, age);
+        }
+        Ok(rib)
+    }
+}
+
+/// A route as stored in a snapshot
+pub(super) struct StoredRoute {
+    pub key: RibKey,
+    pub attributes: AttributeSet,
+    /// Installed and changed, in milliseconds since the epoch; `None` in version 1
+    pub ages: Option<(u64, u64)>,
+}
+
+impl RibSnapshot {
+    /// Decodes every route, attribute sets shared as when the snapshot was taken
+    pub(super) fn routes(&self) -> Result<Vec<StoredRoute>, SnapshotError> {
+        let age_len = match self.version() {
+            VERSION_WITHOUT_AGES => 0,
+            _ => AGE_LEN,
+        };
+        let mut data = self.bytes.slice(HEADER_LEN..);
+        if data.len() < 12 {
+            return Err(SnapshotError::Truncated);
+        }
+        let set_count = data.get_u32();
+        let route_count = data.get_u64();
+
+        let mut sets: Vec<AttributeSet> =
+            Vec::with_capacity((set_count as usize).min(data.len() / 4));
+        for i in 0..set_count {
+            if data.len() < 4 {
+                return Err(SnapshotError::Truncated);
+            }
+            let length = data.get_u32() as usize;
+            if data.len() < length {
+                return Err(SnapshotError::Truncated);
+            }
+            let mut encoded = data.split_to(length);
+            let mut attributes = vec![];
+            while !encoded.is_empty() {
+                let attribute = PathAttribute::try_decode(&mut encoded)
+                    .map_err(|_| SnapshotError::MalformedAttributes(i))?;
+                attributes.push(attribute);
+            }
+            sets.push(attributes.into());
+        }
+
+        let mut routes =
+            Vec::with_capacity((route_count as usize).min(data.len() / (MIN_ROUTE_LEN + age_len)));
+        for i in 0..route_count {
+            if data.len() < MIN_ROUTE_LEN + age_len {
+                return Err(SnapshotError::Truncated);
+            }
+            let malformed = SnapshotError::MalformedRoute(i);
+            let afi = Afi::from(data.get_u16());
+            let safi = Safi::from(data.get_u8());
+            let path_id = match data.get_u8() {
+                0 => None,
+                1 if data.len() >= 4 => Some(data.get_u32()),
+                1 => return Err(SnapshotError::Truncated),
+                _ => return Err(malformed),
+            };
+            let prefix = decode_prefix(&mut data, afi).ok_or(malformed.clone())?;
+            if data.len() < 4 + age_len {
+                return Err(SnapshotError::Truncated);
+            }
+            let attributes = sets.get(data.get_u32() as usize).ok_or(malformed)?;
+            let ages = match age_len {
+                0 => None,
+                _ => Some((data.get_u64(), data.get_u64())),
+            };
+            routes.push(StoredRoute {
+                key: RibKey {
+                    prefix,
+                    safi,
+                    path_id,
+                },
+                attributes: attributes.clone(),
+                ages,
+            });
+        }
+        if !data.is_empty() {
+            return Err(SnapshotError::MalformedRoute(route_count));
+        }
+        Ok(routes)
+    }
+}
+
+pub(crate) fn decode_prefix(data: &mut Bytes, afi: Afi) -> Option<IpAddrPrefix> {
+    let mut octets = [0; 16];
+    let width = match afi {
+        Afi::Ipv4 => 4,
+        Afi::Ipv6 => 16,
+        Afi::Unknown(_) => return None,
+    };
+    let length = data.get_u8();
+    let byte_len = (length as usize).div_ceil(8);
+    if byte_len > width || data.len() < byte_len {
+        return None;
+    }
+    data.copy_to_slice(&mut octets[..byte_len]);
+    let addr = match afi {
+        Afi::Ipv4 => IpAddr::from(<[u8; 4]>::try_from(&octets[..4]).unwrap()),
+        _ => IpAddr::from(octets),
+    };
+    IpAddrPrefix::new(addr, length)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, Community};
+    use crate::update_message::{UpdateMessage, UpdateMessageBuilder};
+
+    fn update(prefixes: &[&str], next_hop: &str, med: u32) -> UpdateMessage {
+        prefixes
+            .iter()
+            .fold(UpdateMessageBuilder::new(), |builder, prefix| {
+                builder.announce(prefix.parse().unwrap())
+            })
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 4_200_000_000].into(),
+                }]
+                .into(),
+            })
+            .community(Community {
+                asn: 65001,
+                value: 100,
+            })
+            .next_hop(next_hop.parse().unwrap())
+            .med(med)
+            .build()
+    }
+
+    fn rib() -> RibIn {
+        let mut rib = RibIn::new();
+        rib.apply(&update(
+            &["192.0.2.0/24", "198.51.100.0/25", "10.0.0.0/8"],
+            "192.0.2.1",
+            10,
+        ));
+        rib.apply(&update(&["2001:db8::/32", "0.0.0.0/0"], "2001:db8::1", 20));
+        rib
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rib = rib();
+        let snapshot = rib.snapshot();
+        let read_back = RibSnapshot::from_bytes(snapshot.as_bytes().clone()).unwrap();
+        let restored = RibIn::restore(&read_back).unwrap();
+
+        assert_eq!(restored.len(), 5);
+        assert_eq!(restored.prefix_count(Afi::Ipv6, Safi::Unicast), 1);
+        for (key, attributes) in rib.iter() {
+            assert_eq!(restored.get(key), Some(attributes), "{}", key.prefix);
+        }
+        // Routes from one UPDATE share their attributes again
+        let first = restored.lookup(&"192.0.2.0/24".parse().unwrap()).unwrap();
+        let second = restored.lookup(&"10.0.0.0/8".parse().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(first, second));
+
+        let empty = RibIn::new().snapshot();
+        assert!(RibIn::restore(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ages_survive_round_trip() {
+        let now = Instant::now();
+        let mut rib = RibIn::new();
+        rib.apply_at(&update(&["192.0.2.0/24"], "192.0.2.1", 10), now);
+        let later = now + Duration::from_secs(90);
+        rib.apply_at(&update(&["192.0.2.0/24"], "192.0.2.1", 20), later);
+
+        let key = RibKey::unicast("192.0.2.0/24".parse().unwrap());
+        let restored = RibIn::restore(&rib.snapshot()).unwrap();
+        let age = restored.age(&key).unwrap();
+        let close =
+            |a: Instant, b: Instant| a.max(b).duration_since(a.min(b)) <= Duration::from_millis(5);
+        assert!(close(age.installed, now), "{age:?}");
+        assert!(close(age.changed, later), "{age:?}");
+        assert_eq!(
+            restored
+                .changed_since(later - Duration::from_secs(1))
+                .count(),
+            1
+        );
+
+        // A version 1 snapshot has no ages, its routes count as installed by the restore
+        let bytes = rib.snapshot().into_bytes();
+        let mut v1 = bytes[..bytes.len() - AGE_LEN].to_vec();
+        v1[7] = 1;
+        let checksum = fnv1a(&v1[HEADER_LEN..]);
+        v1[8..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+        let snapshot = RibSnapshot::from_bytes(v1.into()).unwrap();
+        assert_eq!(snapshot.version(), 1);
+        let before = Instant::now();
+        let restored = RibIn::restore(&snapshot).unwrap();
+        assert!(restored.age(&key).unwrap().installed >= before);
+    }
+
+    #[test]
+    fn test_ages_before_the_monotonic_clock() {
+        let clocks = Clocks::now();
+        let millis = |wall: SystemTime| wall.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let an_hour_ago = millis(clocks.wall - Duration::from_secs(3600));
+
+        // As if the host had booted just now
+        let booted = Clocks {
+            instant: clocks.earliest(),
+            wall: clocks.wall,
+        };
+        assert_eq!(booted.to_instant(an_hour_ago), None);
+        let age = booted.to_age(an_hour_ago, millis(clocks.wall));
+        assert_eq!(
+            age,
+            RouteAge {
+                installed: booted.instant,
+                changed: booted.instant,
+                imprecise: true,
+            }
+        );
+
+        // The epoch, older than any uptime, is never taken for now
+        let age = clocks.to_age(0, 0);
+        assert!(age.changed < clocks.to_instant(an_hour_ago).unwrap());
+        match age.imprecise {
+            true => assert_eq!(age.changed, clocks.earliest()),
+            false => assert_eq!(clocks.to_wall(age.changed), 0),
+        }
+        let mut rib = RibIn::new();
+        let key = RibKey::unicast("192.0.2.0/24".parse().unwrap());
+        let _ = rib.insert(key, AttributeSet::default(), age);
+        assert_eq!(rib.changed_since(clocks.instant).count(), 0);
+    }
+
+    #[test]
+    fn test_corrupted_snapshot() {
+        let bytes = rib().snapshot().into_bytes();
+
+        let mut wrong_magic = bytes.to_vec();
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            RibSnapshot::from_bytes(wrong_magic.into()),
+            Err(SnapshotError::BadMagic)
+        );
+        let mut future = bytes.to_vec();
+        future[7] = 9;
+        assert_eq!(
+            RibSnapshot::from_bytes(future.into()),
+            Err(SnapshotError::UnsupportedVersion(9))
+        );
+        let mut flipped = bytes.to_vec();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x40;
+        assert_eq!(
+            RibSnapshot::from_bytes(flipped.into()),
+            Err(SnapshotError::Checksum)
+        );
+        for length in [0, 3, 10, HEADER_LEN + 5, bytes.len() - 1] {
+            assert!(RibSnapshot::from_bytes(bytes.slice(..length)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_damage_behind_valid_checksum() {
+        // Every truncation of the body, with the checksum fixed up, fails without panicking
+        let bytes = rib().snapshot().into_bytes();
+        for length in HEADER_LEN..bytes.len() {
+            let body = &bytes[HEADER_LEN..length];
+            let mut damaged = bytes[..8].to_vec();
+            damaged.extend_from_slice(&fnv1a(body).to_be_bytes());
+            damaged.extend_from_slice(body);
+            let snapshot = RibSnapshot::from_bytes(damaged.into()).unwrap();
+            assert!(RibIn::restore(&snapshot).is_err(), "{length}");
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! Parsing of the messages RIPE RIS Live streams over its websocket.
+//!
+//! Each frame is a JSON object `{"type": "ris_message", "data": {...}}` describing a BGP
+//! message one of the RIS collectors exchanged with a peer. The websocket itself is left to
+//! the caller; hand the text of every frame to [`RisLiveMessage::parse`].
+//!
+//! RIS gives AS_SETs as nested arrays in `path`, communities as `[asn, value]` pairs, ASNs
+//! sometimes as strings and IPv6 next hops as `"global,link-local"`, all of which are
+//! accepted. `WITHDRAWAL` is taken as an UPDATE that only withdraws.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{
+    Aggregator, AsPath, AsPathSegment, AsPathSegmentType, AttributeType, AttributeValue, Community,
+    OriginType, PathAttribute, PathAttributeFlags,
+};
+use crate::bgp_message::BgpMessage;
+use crate::capability::Capability;
+use crate::journal::Direction;
+use crate::json::{Json, ParseJsonError};
+use crate::list::SmallList;
+use crate::notification_message::{NotificationErrorCode, NotificationMessage};
+use crate::open_message::OpenMessage;
+use crate::timestamped::Timestamped;
+use crate::update_message::{IpAddrPrefix, UpdateMessage, UpdateMessageBuilder};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RisLiveError {
+    #[error(transparent)]
+    Json(#[from] ParseJsonError),
+    /// A `ris_error` message, such as for a bad subscription
+    #[error("RIS Live error: {0}")]
+    Server(String),
+    #[error("missing {0}")]
+    Missing(&'static str),
+    #[error("invalid {field}: {value}")]
+    Invalid { field: &'static str, value: String },
+    #[error("unsupported message type {0:?}")]
+    Type(String),
+}
+
+/// The collector session a message was seen on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RisPeer {
+    /// The collector, such as `rrc21`
+    pub host: String,
+    pub addr: IpAddr,
+    pub asn: u32,
+}
+
+/// A BGP message, or change of session state, seen by a RIS collector
+#[derive(Debug, Clone, PartialEq)]
+pub struct RisLiveMessage {
+    pub timestamp: SystemTime,
+    pub peer: RisPeer,
+    /// RIS's identifier for the message, unique within the stream
+    pub id: Option<String>,
+    pub kind: RisMessageKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RisMessageKind {
+    Update(UpdateMessage),
+    Keepalive,
+    /// An OPEN, either from the peer or from the collector
+    Open {
+        direction: Direction,
+        open: OpenMessage,
+    },
+    Notification(NotificationMessage),
+    /// The collector's session with the peer went up or down, such as `"connected"`
+    PeerState(String),
+}
+
+impl RisLiveMessage {
+    /// Parses the text of a websocket frame; frames other than `ris_message`, such as
+    /// `ris_subscribe_ok` and `pong`, give `None`
+    pub fn parse(text: &str) -> Result<Option<Self>, RisLiveError> {
+        Self::from_json(&text.parse()?)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Option<Self>, RisLiveError> {
+        match json.get("type").and_then(Json::as_str) {
+            Some("ris_message") => {}
+            Some("ris_error") => {
+                let message = json
+                    .get("data")
+                    .and_then(|data| data.get("message"))
+                    .and_then(Json::as_str)
+                    .unwrap_or_default();
+                return Err(RisLiveError::Server(message.to_owned()));
+            }
+            Some(_) => return Ok(None),
+            None => return Err(RisLiveError::Missing("type")),
+        }
+        let data = json.get("data").ok_or(RisLiveError::Missing("data"))?;
+
+        let timestamp = data
+            .get("timestamp")
+            .ok_or(RisLiveError::Missing("timestamp"))?;
+        let timestamp = match timestamp {
+            Json::Number(seconds) if *seconds >= 0.0 && seconds.is_finite() => {
+                SystemTime::UNIX_EPOCH + Duration::from_secs_f64(*seconds)
+            }
+            _ => return Err(invalid("timestamp", timestamp)),
+        };
+        let peer = RisPeer {
+            host: string(data, "host")?.to_owned(),
+            addr: parse(data, "peer")?,
+            asn: asn("peer_asn", data.get("peer_asn"))?,
+        };
+        let id = data.get("id").and_then(Json::as_str).map(str::to_owned);
+
+        let kind = match string(data, "type")? {
+            "UPDATE" | "WITHDRAWAL" => RisMessageKind::Update(update(data)?),
+            "KEEPALIVE" => RisMessageKind::Keepalive,
+            "OPEN" => {
+                let direction = match string(data, "direction")? {
+                    "received" => Direction::Received,
+                    "sent" => Direction::Sent,
+                    _ => return Err(invalid("direction", &data["direction"])),
+                };
+                RisMessageKind::Open {
+                    direction,
+                    open: open(data)?,
+                }
+            }
+            "NOTIFICATION" => RisMessageKind::Notification(notification(data)?),
+            "RIS_PEER_STATE" => RisMessageKind::PeerState(string(data, "state")?.to_owned()),
+            other => return Err(RisLiveError::Type(other.to_owned())),
+        };
+        Ok(Some(RisLiveMessage {
+            timestamp,
+            peer,
+            id,
+            kind,
+        }))
+    }
+
+    /// The BGP message with the time the collector saw it, `None` for peer state changes
+    pub fn into_bgp(self) -> Option<(RisPeer, Timestamped<BgpMessage>)> {
+        let message = match self.kind {
+            RisMessageKind::Update(update) => BgpMessage::Update(update),
+            RisMessageKind::Keepalive => BgpMessage::Keepalive,
+            RisMessageKind::Open { open, .. } => BgpMessage::Open(open),
+            RisMessageKind::Notification(notification) => BgpMessage::Notification(notification),
+            RisMessageKind::PeerState(_) => return None,
+        };
+        Some((self.peer, Timestamped::at(self.timestamp, message)))
+    }
+}
+
+fn invalid(field: &'static str, value: &Json) -> RisLiveError {
+    RisLiveError::Invalid {
+        field,
+        value: value.to_string(),
+    }
+}
+
+fn string<'a>(data: &'a Json, field: &'static str) -> Result<&'a str, RisLiveError> {
+    let value = data.get(field).ok_or(RisLiveError::Missing(field))?;
+    value.as_str().ok_or_else(|| invalid(field, value))
+}
+
+fn parse<T: std::str::FromStr>(data: &Json, field: &'static str) -> Result<T, RisLiveError> {
+    string(data, field)?
+        .parse()
+        .map_err(|_| invalid(field, &data[field]))
+}
+
+fn number<T: TryFrom<u64>>(field: &'static str, value: &Json) -> Result<T, RisLiveError> {
+    value
+        .as_u64()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| invalid(field, value))
+}
+
+/// ASNs come as numbers in paths but as strings for peers
+fn asn(field: &'static str, value: Option<&Json>) -> Result<u32, RisLiveError> {
+    match value {
+        None => Err(RisLiveError::Missing(field)),
+        Some(Json::String(asn)) => asn
+            .parse()
+            .map_err(|_| invalid(field, &Json::from(asn.as_str()))),
+        Some(value) => number(field, value),
+    }
+}
+
+fn update(data: &Json) -> Result<UpdateMessage, RisLiveError> {
+    let mut builder = UpdateMessageBuilder::new();
+
+    if let Some(path) = data.get("path") {
+        let mut segments = SmallList::new();
+        for element in path.as_array().ok_or_else(|| invalid("path", path))? {
+            match element {
+                Json::Array(set) => segments.push(AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSet,
+                    asns: set
+                        .iter()
+                        .map(|asn| number("path", asn))
+                        .collect::<Result<_, _>>()?,
+                }),
+                asn => {
+                    let asn = number("path", asn)?;
+                    match segments.last_mut() {
+                        Some(last) if last.segment_type == AsPathSegmentType::AsSequence => {
+                            last.asns.push(asn)
+                        }
+                        _ => segments.push(AsPathSegment {
+                            segment_type: AsPathSegmentType::AsSequence,
+                            asns: [asn].into(),
+                        }),
+                    }
+                }
+            }
+        }
+        builder = builder.as_path(AsPath { segments });
+    }
+    if let Some(origin) = data.get("origin") {
+        let origin = match origin.as_str().map(str::to_ascii_lowercase).as_deref() {
+            Some("igp") => OriginType::Igp,
+            Some("egp") => OriginType::Egp,
+            Some("incomplete") => OriginType::Incomplete,
+            _ => return Err(invalid("origin", origin)),
+        };
+        builder = builder.origin(origin);
+    }
+    if let Some(med) = data.get("med") {
+        builder = builder.med(number("med", med)?);
+    }
+    if let Some(local_pref) = data.get("local_pref") {
+        builder = builder.local_pref(number("local_pref", local_pref)?);
+    }
+    if let Some(communities) = data.get("community") {
+        for pair in communities
+            .as_array()
+            .ok_or_else(|| invalid("community", communities))?
+        {
+            let community = match pair.as_array() {
+                Some([asn, value]) => Community {
+                    asn: number("community", asn)?,
+                    value: number("community", value)?,
+                },
+                _ => return Err(invalid("community", pair)),
+            };
+            builder = builder.community(community);
+        }
+    }
+    if let Some(aggregator) = data.get("aggregator") {
+        let (asn, ip) = aggregator
+            .as_str()
+            .and_then(|s| s.split_once(':'))
+            .and_then(|(asn, ip)| Some((asn.parse().ok()?, ip.parse::<Ipv4Addr>().ok()?)))
+            .ok_or_else(|| invalid("aggregator", aggregator))?;
+        builder = builder.attribute(PathAttribute {
+            flags: PathAttributeFlags::optional_transitive(),
+            type_code: AttributeType::Aggregator,
+            value: AttributeValue::Aggregator(Aggregator { asn, ip }),
+        });
+    }
+
+    for announcement in data
+        .get("announcements")
+        .and_then(Json::as_array)
+        .unwrap_or_default()
+    {
+        let next_hop = string(announcement, "next_hop")?;
+        let (global, link_local) = match next_hop.split_once(',') {
+            Some((global, link_local)) => (global, Some(link_local)),
+            None => (next_hop, None),
+        };
+        let invalid_next_hop = || invalid("next_hop", &Json::from(next_hop));
+        let global: IpAddr = global.parse().map_err(|_| invalid_next_hop())?;
+        let link_local = link_local
+            .map(|link_local| link_local.parse::<Ipv6Addr>())
+            .transpose()
+            .map_err(|_| invalid_next_hop())?;
+
+        let prefixes = prefixes("prefixes", announcement.get("prefixes"))?;
+        // A prefix's family, not the next hop's, decides where it goes on the wire
+        match prefixes.first().map(IpAddrPrefix::afi) {
+            Some(Afi::Ipv4) => builder = builder.next_hop(global),
+            Some(_) => builder = builder.mp_next_hop(global, link_local),
+            None => {}
+        }
+        for prefix in prefixes {
+            builder = builder.announce(prefix);
+        }
+    }
+    for prefix in prefixes("withdrawals", data.get("withdrawals"))? {
+        builder = builder.withdraw(prefix);
+    }
+
+    let update = builder.build();
+    if update.withdrawn_routes.is_empty()
+        && update.nlri.is_empty()
+        && update.path_attributes.is_empty()
+    {
+        // RIS reports End-of-RIB as an UPDATE without any prefixes
+        return Ok(UpdateMessage::end_of_rib(Afi::Ipv4, Safi::Unicast));
+    }
+    Ok(update)
+}
+
+fn prefixes(field: &'static str, value: Option<&Json>) -> Result<Vec<IpAddrPrefix>, RisLiveError> {
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
+    value
+        .as_array()
+        .ok_or_else(|| invalid(field, value))?
+        .iter()
+        .map(|prefix| {
+            prefix
+                .as_str()
+                .and_then(|prefix| prefix.parse().ok())
+                .ok_or_else(|| invalid(field, prefix))
+        })
+        .collect()
+}
+
+fn open(data: &Json) -> Result<OpenMessage, RisLiveError> {
+    let my_asn = asn("asn", data.get("asn"))?;
+    let hold_time = number("hold_time", data.get("hold_time").unwrap_or(&Json::Null))?;
+    let router_id = parse(data, "router_id")?;
+
+    let mut capabilities = vec![];
+    if let Some(advertised) = data.get("capabilities") {
+        for (code, capability) in advertised
+            .as_object()
+            .ok_or_else(|| invalid("capabilities", advertised))?
+        {
+            let code: u8 = code
+                .parse()
+                .map_err(|_| invalid("capabilities", &Json::from(code.as_str())))?;
+            match code {
+                Capability::MULTI_PROTOCOL => {
+                    let families = capability
+                        .get("families")
+                        .and_then(Json::as_array)
+                        .unwrap_or_default();
+                    for family in families {
+                        let (afi, safi) = family
+                            .as_str()
+                            .and_then(|family| family.split_once('/'))
+                            .and_then(|family| match family {
+                                ("ipv4", safi) => Some((Afi::Ipv4, safi)),
+                                ("ipv6", safi) => Some((Afi::Ipv6, safi)),
+                                _ => None,
+                            })
+                            .and_then(|(afi, safi)| match safi {
+                                "unicast" => Some((afi, Safi::Unicast)),
+                                "multicast" => Some((afi, Safi::Multicast)),
+                                _ => None,
+                            })
+                            .ok_or_else(|| invalid("capabilities", family))?;
+                        capabilities.push(Capability::MultiProtocol { afi, safi });
+                    }
+                }
+                Capability::ROUTE_REFRESH => capabilities.push(Capability::RouteRefresh),
+                Capability::FOUR_OCTET_AS => capabilities.push(Capability::FourOctetAs {
+                    asn: asn("capabilities", capability.get("asn4"))?,
+                }),
+                Capability::ENHANCED_ROUTE_REFRESH => {
+                    capabilities.push(Capability::EnhancedRouteRefresh)
+                }
+                // RIS describes the others in ways that don't map back to the wire
+                code => capabilities.push(Capability::Unknown {
+                    code,
+                    value: Default::default(),
+                }),
+            }
+        }
+    }
+    Ok(OpenMessage::new(
+        my_asn,
+        hold_time,
+        router_id,
+        &capabilities,
+    ))
+}
+
+fn notification(data: &Json) -> Result<NotificationMessage, RisLiveError> {
+    let notification = data
+        .get("notification")
+        .ok_or(RisLiveError::Missing("notification"))?;
+    let code = number("notification", &notification["code"])?;
+    let subcode = number("notification", &notification["subcode"])?;
+    let payload = match notification.get("data") {
+        None => vec![],
+        Some(data) => data
+            .as_str()
+            .filter(|hex| hex.len() % 2 == 0)
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect()
+            })
+            .ok_or_else(|| invalid("notification", data))?,
+    };
+
+    // Decoded as on the wire, so subcodes are checked the same way
+    let mut wire = vec![code, subcode];
+    wire.extend(&payload);
+    match NotificationMessage::try_decode(&mut wire.into()) {
+        Ok(notification) => Ok(notification),
+        Err(_) => Ok(NotificationMessage::new(
+            NotificationErrorCode::Unknown(code, subcode),
+            payload,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::notification_message::CeaseSubErr;
+
+    fn fixture() -> Vec<Result<Option<RisLiveMessage>, RisLiveError>> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/ris_live.jsonl");
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(RisLiveMessage::parse)
+            .collect()
+    }
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let messages: Vec<_> = fixture().into_iter().map(Result::unwrap).collect();
+        assert_eq!(messages.len(), 9);
+        assert_eq!(messages[0], None);
+        let messages: Vec<_> = messages.into_iter().flatten().collect();
+
+        let first = &messages[0];
+        assert_eq!(
+            first.peer,
+            RisPeer {
+                host: "rrc21".into(),
+                addr: "37.49.237.175".parse().unwrap(),
+                asn: 199524,
+            }
+        );
+        assert_eq!(
+            first.timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250)
+        );
+        let expected = UpdateMessageBuilder::new()
+            .origin(OriginType::Igp)
+            .as_path(AsPath {
+                segments: [
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSequence,
+                        asns: [199524, 1299, 3356].into(),
+                    },
+                    AsPathSegment {
+                        segment_type: AsPathSegmentType::AsSet,
+                        asns: [64512, 64513].into(),
+                    },
+                ]
+                .into(),
+            })
+            .next_hop("37.49.237.175".parse().unwrap())
+            .med(0)
+            .community(Community {
+                asn: 1299,
+                value: 35000,
+            })
+            .community(Community {
+                asn: 3356,
+                value: 100,
+            })
+            .attribute(PathAttribute {
+                flags: PathAttributeFlags::optional_transitive(),
+                type_code: AttributeType::Aggregator,
+                value: AttributeValue::Aggregator(Aggregator {
+                    asn: 64513,
+                    ip: Ipv4Addr::new(10, 0, 0, 1),
+                }),
+            })
+            .announce(prefix("193.0.0.0/21"))
+            .announce(prefix("193.0.10.0/23"))
+            .build();
+        assert_eq!(first.kind, RisMessageKind::Update(expected));
+
+        // IPv6 with a link-local next hop
+        let RisMessageKind::Update(update) = &messages[1].kind else {
+            panic!("{:?}", messages[1]);
+        };
+        assert_eq!(messages[1].peer.asn, 6939);
+        let mp_reach = update
+            .path_attributes
+            .iter()
+            .find_map(|attribute| match &attribute.value {
+                AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            mp_reach.next_hop,
+            "2001:7f8:4::1b1b:1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(mp_reach.link_local, Some("fe80::1".parse().unwrap()));
+        assert_eq!(mp_reach.nlri, vec![prefix("2001:67c:2e8::/48")]);
+
+        let RisMessageKind::Update(withdrawal) = &messages[2].kind else {
+            panic!("{:?}", messages[2]);
+        };
+        assert_eq!(withdrawal.withdrawn_routes, vec![prefix("193.0.0.0/21")]);
+        assert!(withdrawal.path_attributes.is_empty());
+
+        assert_eq!(messages[3].kind, RisMessageKind::Keepalive);
+
+        let RisMessageKind::Open { direction, open } = &messages[4].kind else {
+            panic!("{:?}", messages[4]);
+        };
+        assert_eq!(*direction, Direction::Received);
+        assert_eq!(open.asn(), 199524);
+        assert_eq!(open.hold_time, 180);
+        assert_eq!(
+            open.capabilities().unwrap(),
+            vec![
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv4,
+                    safi: Safi::Unicast
+                },
+                Capability::MultiProtocol {
+                    afi: Afi::Ipv6,
+                    safi: Safi::Unicast
+                },
+                Capability::RouteRefresh,
+                Capability::FourOctetAs { asn: 199524 },
+            ]
+        );
+
+        assert_eq!(
+            messages[5].kind,
+            RisMessageKind::Notification(NotificationMessage::new(
+                NotificationErrorCode::Cease(CeaseSubErr::AdministrativeShutdown),
+                b"maintenance".to_vec(),
+            ))
+        );
+        assert_eq!(
+            messages[6].kind,
+            RisMessageKind::PeerState("connected".into())
+        );
+        assert_eq!(
+            messages[7].kind,
+            RisMessageKind::Update(UpdateMessage::end_of_rib(Afi::Ipv4, Safi::Unicast))
+        );
+
+        let (peer, message) = messages[3].clone().into_bgp().unwrap();
+        assert_eq!(peer.host, "rrc21");
+        assert_eq!(message.value, BgpMessage::Keepalive);
+        assert_eq!(messages[6].clone().into_bgp(), None);
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        assert_eq!(
+            RisLiveMessage::parse(r#"{"type": "ris_error", "data": {"message": "Unknown host"}}"#),
+            Err(RisLiveError::Server("Unknown host".into()))
+        );
+        let base = r#""timestamp": 1.0, "peer": "192.0.2.1", "peer_asn": "64500", "host": "rrc00""#;
+        for (data, err) in [
+            (r#""type": "STATE""#, RisLiveError::Type("STATE".into())),
+            (
+                r#""type": "UPDATE", "path": [64500, "x"]"#,
+                RisLiveError::Invalid {
+                    field: "path",
+                    value: r#""x""#.into(),
+                },
+            ),
+            (
+                r#""type": "UPDATE", "community": [[64500, 70000]]"#,
+                RisLiveError::Invalid {
+                    field: "community",
+                    value: "70000".into(),
+                },
+            ),
+            (
+                r#""type": "UPDATE", "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["10.0.0.0/33"]}]"#,
+                RisLiveError::Invalid {
+                    field: "prefixes",
+                    value: r#""10.0.0.0/33""#.into(),
+                },
+            ),
+            (
+                r#""type": "NOTIFICATION""#,
+                RisLiveError::Missing("notification"),
+            ),
+        ] {
+            let text = format!(r#"{{"type": "ris_message", "data": {{{base}, {data}}}}}"#);
+            assert_eq!(RisLiveMessage::parse(&text), Err(err), "{data}");
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::address_family::Afi;
+use crate::attribute::{
+    AsPath, AttributeType, AttributeValue, Community, OriginType, PathAttribute,
+};
+use crate::header::BgpHeader;
+use crate::rib::AttrSetInterner;
+use crate::update_message::{IpAddrPrefix, UpdateMessage, UpdateMessageBuilder};
+
+/// A single prefix with its path attributes.
+///
+/// The AS path, communities and other attributes are behind `Arc`s, so the routes of one
+/// UPDATE share them rather than each holding a copy.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Route {
+    pub prefix: IpAddrPrefix,
+    pub origin: Option<OriginType>,
+    pub as_path: Option<Arc<AsPath>>,
+    /// NEXT_HOP for IPv4 prefixes, the MP_REACH_NLRI next hop otherwise
+    pub next_hop: Option<IpAddr>,
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+    pub communities: Arc<[Community]>,
+    /// Attributes without a dedicated field, such as AGGREGATOR. LARGE_COMMUNITIES and
+    /// EXTENDED_COMMUNITIES aren't decoded, so they end up here too.
+    pub other: Arc<[PathAttribute]>,
+}
+
+impl Route {
+    /// A route for `prefix` without any attributes
+    pub fn new(prefix: IpAddrPrefix) -> Self {
+        Route {
+            prefix,
+            origin: None,
+            as_path: None,
+            next_hop: None,
+            med: None,
+            local_pref: None,
+            communities: Arc::new([]),
+            other: Arc::new([]),
+        }
+    }
+
+    /// Whether both routes carry the same attributes and so fit into one UPDATE
+    pub fn same_attributes(&self, other: &Route) -> bool {
+        self.origin == other.origin
+            && self.as_path == other.as_path
+            && self.next_hop == other.next_hop
+            && self.med == other.med
+            && self.local_pref == other.local_pref
+            && self.communities == other.communities
+            && self.other == other.other
+    }
+
+    /// A builder announcing this route's prefix with its attributes
+    pub fn to_builder(&self) -> UpdateMessageBuilder {
+        let mut builder = UpdateMessageBuilder::new().announce(self.prefix.clone());
+        if let Some(origin) = self.origin {
+            builder = builder.origin(origin);
+        }
+        if let Some(as_path) = &self.as_path {
+            builder = builder.as_path(AsPath::clone(as_path));
+        }
+        if let Some(next_hop) = self.next_hop {
+            builder = builder.next_hop(next_hop);
+        }
+        if let Some(med) = self.med {
+            builder = builder.med(med);
+        }
+        if let Some(local_pref) = self.local_pref {
+            builder = builder.local_pref(local_pref);
+        }
+        for community in self.communities.iter() {
+            builder = builder.community(*community);
+        }
+        for attribute in self.other.iter() {
+            builder = builder.attribute(attribute.clone());
+        }
+        builder
+    }
+}
+
+impl UpdateMessage {
+    /// The routes announced: the NLRI with NEXT_HOP and the MP_REACH_NLRI prefixes with its
+    /// next hop, all sharing the remaining attributes. Withdrawals are left out, as is the
+    /// link-local address of an IPv6 next hop.
+    pub fn routes(&self) -> Vec<Route> {
+        self.routes_sharing(self.other_attributes().into())
+    }
+
+    /// Like [`UpdateMessage::routes`], with [`Route::other`] shared through `interner` with the
+    /// routes of other UPDATEs
+    pub fn routes_interned(&self, interner: &mut AttrSetInterner) -> Vec<Route> {
+        self.routes_sharing(interner.intern(&self.other_attributes()))
+    }
+
+    /// The attributes without a dedicated field of [`Route`]
+    fn other_attributes(&self) -> Vec<PathAttribute> {
+        self.path_attributes
+            .iter()
+            .filter(|attribute| {
+                !matches!(
+                    attribute.type_code,
+                    AttributeType::Origin
+                        | AttributeType::AsPath
+                        | AttributeType::NextHop
+                        | AttributeType::MultiExitDisc
+                        | AttributeType::LocalPref
+                        | AttributeType::Communities
+                        | AttributeType::MpReachNlri
+                        | AttributeType::MpUnreachNlri
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn routes_sharing(&self, other: Arc<[PathAttribute]>) -> Vec<Route> {
+        let attributes = self.attributes();
+        let as_path = attributes.as_path().cloned().map(Arc::new);
+        let communities: Arc<[Community]> = match attributes.communities() {
+            Some(communities) => communities.communities[..].into(),
+            None => Arc::new([]),
+        };
+        let route = |prefix: &IpAddrPrefix, next_hop| Route {
+            prefix: prefix.clone(),
+            origin: attributes.origin().map(|origin| origin.origin_type),
+            as_path: as_path.clone(),
+            next_hop,
+            med: attributes.med().map(|med| med.med),
+            local_pref: attributes.local_pref().map(|local_pref| local_pref.pref),
+            communities: communities.clone(),
+            other: other.clone(),
+        };
+
+        let next_hop = attributes
+            .next_hop()
+            .map(|next_hop| IpAddr::V4(next_hop.ip));
+        let mut routes: Vec<_> = self
+            .nlri
+            .iter()
+            .map(|prefix| route(prefix, next_hop))
+            .collect();
+        if let Some(mp_reach) = attributes.mp_reach() {
+            routes.extend(
+                mp_reach
+                    .nlri
+                    .iter()
+                    .map(|prefix| route(prefix, Some(mp_reach.next_hop))),
+            );
+        }
+        routes
+    }
+
+    /// Groups routes with the same attributes into UPDATEs, as few as the message size
+    /// allows, the inverse of [`UpdateMessage::routes`]
+    pub fn from_routes(
+        routes: impl IntoIterator<Item = Route>,
+        four_octet_as: bool,
+    ) -> Vec<UpdateMessage> {
+        let mut groups: Vec<Vec<Route>> = vec![];
+        for route in routes {
+            match groups
+                .iter_mut()
+                .find(|group| group[0].same_attributes(&route))
+            {
+                Some(group) => group.push(route),
+                None => groups.push(vec![route]),
+            }
+        }
+
+        let mut updates = vec![];
+        for group in groups {
+            let attributes = |route: &Route| {
+                let update = route.to_builder().four_octet_as(four_octet_as).build();
+                (update.encoded_len(), update)
+            };
+            let (mut len, mut update) = attributes(&group[0]);
+            for route in &group[1..] {
+                if !pack(&mut update, &mut len, &route.prefix, false) {
+                    let (next_len, next) = attributes(route);
+                    updates.push(std::mem::replace(&mut update, next));
+                    len = next_len;
+                }
+            }
+            updates.push(update);
+        }
+        updates
+    }
+}
+
+/// Largest UPDATE body, the message without its header
+pub(crate) const MAX_BODY_LEN: usize = (BgpHeader::MAX_LEN - BgpHeader::MIN_LEN) as usize;
+
+/// Adds `prefix` to the announced prefixes of its family in `update`, or to the withdrawn ones
+/// if `withdraw`, unless the body would outgrow [`MAX_BODY_LEN`] or `update` has none of them
+/// yet. `len` is the [`UpdateMessage::encoded_len`] of `update`, kept current.
+pub(crate) fn pack(
+    update: &mut UpdateMessage,
+    len: &mut usize,
+    prefix: &IpAddrPrefix,
+    withdraw: bool,
+) -> bool {
+    if prefix.afi() == Afi::Ipv4 {
+        let prefixes = match withdraw {
+            true => &mut update.withdrawn_routes,
+            false => &mut update.nlri,
+        };
+        if prefixes.is_empty() || *len + prefix.encoded_len() > MAX_BODY_LEN {
+            return false;
+        }
+        prefixes.push(prefix.clone());
+        *len += prefix.encoded_len();
+        return true;
+    }
+
+    fn mp_prefixes(value: &mut AttributeValue) -> Option<&mut Vec<IpAddrPrefix>> {
+        match value {
+            AttributeValue::MpReachNlri(mp_reach) => Some(&mut mp_reach.nlri),
+            AttributeValue::MpUnreachNlri(mp_unreach) => Some(&mut mp_unreach.withdrawn_routes),
+            _ => None,
+        }
+    }
+    let type_code = match withdraw {
+        true => AttributeType::MpUnreachNlri,
+        false => AttributeType::MpReachNlri,
+    };
+    let Some(attribute) = update
+        .path_attributes
+        .iter_mut()
+        .find(|attribute| attribute.type_code == type_code)
+    else {
+        return false;
+    };
+    let attribute_len = attribute.encoded_len();
+    let Some(prefixes) = mp_prefixes(&mut attribute.value) else {
+        return false;
+    };
+    prefixes.push(prefix.clone());
+    // The attribute's length field may have grown too
+    let grown = *len - attribute_len + attribute.encoded_len();
+    if grown > MAX_BODY_LEN {
+        mp_prefixes(&mut attribute.value).and_then(Vec::pop);
+        return false;
+    }
+    *len = grown;
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use bytes::Bytes;
+
+    use crate::attribute::{AttributeValue, PathAttributeFlags};
+
+    fn prefix(prefix: &str) -> IpAddrPrefix {
+        prefix.parse().unwrap()
+    }
+
+    /// An UPDATE with two IPv4 prefixes in NLRI, two IPv6 ones in MP_REACH_NLRI and a
+    /// LARGE_COMMUNITIES attribute, decoded from the wire
+    fn dual_stack_update() -> UpdateMessage {
+        let large_communities = PathAttribute {
+            flags: PathAttributeFlags::optional_transitive(),
+            type_code: AttributeType::Unknown(32),
+            value: AttributeValue::Unknown(Bytes::from_static(&[
+                0, 0, 0xfb, 0xf4, 0, 0, 0, 1, 0, 0, 0, 2,
+            ])),
+        };
+        let update = UpdateMessageBuilder::new()
+            .origin(OriginType::Egp)
+            .as_path("65001 65002".parse().unwrap())
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .mp_next_hop("2001:db8::1".parse().unwrap(), None)
+            .med(10)
+            .community(Community::NO_EXPORT)
+            .attribute(large_communities)
+            .announce(prefix("198.51.100.0/24"))
+            .announce(prefix("2001:db8:1::/48"))
+            .announce(prefix("203.0.113.0/24"))
+            .announce(prefix("2001:db8:2::/48"))
+            .build();
+        UpdateMessage::try_decode(&mut update.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_routes() {
+        let update = dual_stack_update();
+        let routes = update.routes();
+        let prefixes: Vec<_> = routes
+            .iter()
+            .map(|route| route.prefix.to_string())
+            .collect();
+        assert_eq!(
+            prefixes,
+            [
+                "198.51.100.0/24",
+                "203.0.113.0/24",
+                "2001:db8:1::/48",
+                "2001:db8:2::/48"
+            ]
+        );
+
+        let ipv4 = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let ipv6 = Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+        assert_eq!(routes[0].next_hop, ipv4);
+        assert_eq!(routes[3].next_hop, ipv6);
+        for route in &routes {
+            assert_eq!(route.origin, Some(OriginType::Egp));
+            assert_eq!(route.as_path.as_deref().unwrap().to_string(), "65001 65002");
+            assert_eq!(route.med, Some(10));
+            assert_eq!(route.local_pref, None);
+            assert_eq!(&route.communities[..], [Community::NO_EXPORT]);
+            assert_eq!(route.other.len(), 1);
+            assert_eq!(route.other[0].type_code, AttributeType::Unknown(32));
+        }
+
+        // The attributes are shared, not copied per prefix
+        assert!(Arc::ptr_eq(
+            routes[0].as_path.as_ref().unwrap(),
+            routes[3].as_path.as_ref().unwrap()
+        ));
+        assert!(Arc::ptr_eq(&routes[0].communities, &routes[3].communities));
+        assert!(Arc::ptr_eq(&routes[0].other, &routes[3].other));
+
+        assert!(UpdateMessageBuilder::new().build().routes().is_empty());
+    }
+
+    #[test]
+    fn test_routes_interned() {
+        let mut interner = AttrSetInterner::new();
+        let first = dual_stack_update().routes_interned(&mut interner);
+        let second = dual_stack_update().routes_interned(&mut interner);
+        assert_eq!(first, dual_stack_update().routes());
+        // Shared across UPDATEs too
+        assert!(Arc::ptr_eq(&first[0].other, &second[3].other));
+        assert_eq!(interner.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_from_routes() {
+        let routes = dual_stack_update().routes();
+        let mut other_med = routes[1].clone();
+        other_med.med = Some(20);
+
+        let mut all = routes.clone();
+        all.push(Route {
+            prefix: prefix("192.0.2.0/24"),
+            ..other_med
+        });
+        let updates = UpdateMessage::from_routes(all.clone(), true);
+        // One UPDATE per next hop, and one for the route with a different MED
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0].nlri.len(), 2);
+        assert_eq!(updates[1].attributes().mp_reach().unwrap().nlri.len(), 2);
+        assert_eq!(updates[2].nlri, [prefix("192.0.2.0/24")]);
+
+        let round_trip: Vec<_> = updates
+            .iter()
+            .flat_map(|update| {
+                UpdateMessage::try_decode(&mut update.to_bytes())
+                    .unwrap()
+                    .routes()
+            })
+            .collect();
+        assert_eq!(round_trip, all);
+    }
+
+    #[test]
+    fn test_from_routes_splits_large_groups() {
+        let ipv4 = (0..2000u32).map(|i| Route {
+            next_hop: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            ..Route::new(
+                IpAddrPrefix::new(Ipv4Addr::from_bits(0x0a00_0000 | (i << 8)).into(), 24).unwrap(),
+            )
+        });
+        let ipv6 = (0..2000u128).map(|i| Route {
+            next_hop: Some("2001:db8::1".parse().unwrap()),
+            ..Route::new(
+                IpAddrPrefix::new(Ipv6Addr::from_bits(0x2001_0db8 << 96 | i << 80).into(), 48)
+                    .unwrap(),
+            )
+        });
+        let updates = UpdateMessage::from_routes(ipv4.chain(ipv6), true);
+        assert!(updates.len() > 2);
+        let announced = |update: &UpdateMessage| {
+            let mp_nlri = update
+                .attributes()
+                .mp_reach()
+                .map(|mp_reach| mp_reach.nlri.len());
+            update.nlri.len() + mp_nlri.unwrap_or(0)
+        };
+        assert_eq!(updates.iter().map(announced).sum::<usize>(), 4000);
+
+        // Each UPDATE is too full for the next prefix, or of another family
+        for pair in updates.windows(2) {
+            let next_prefix = match pair[1].nlri.first() {
+                Some(prefix) => prefix.clone(),
+                None => pair[1].attributes().mp_reach().unwrap().nlri[0].clone(),
+            };
+            let mut update = pair[0].clone();
+            let mut len = update.encoded_len();
+            assert!(!pack(&mut update, &mut len, &next_prefix, false));
+        }
+        for update in updates {
+            assert_eq!(update.encoded_len(), update.to_bytes().len());
+            assert!(update.to_bytes().len() <= MAX_BODY_LEN);
+        }
+    }
+}
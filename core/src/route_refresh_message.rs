@@ -0,0 +1,106 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::address_family::{Afi, Safi};
+use crate::spans;
+
+/// ROUTE-REFRESH message (RFC 2918) with the Enhanced Route Refresh subtypes (RFC 7313)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RouteRefreshMessage {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub subtype: RouteRefreshSubtype,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RouteRefreshSubtype {
+    /// Asks the peer to re-advertise the family
+    Request,
+    /// Beginning of Route Refresh, the peer starts re-advertising
+    Begin,
+    /// End of Route Refresh, the re-advertisement is complete
+    End,
+    Unknown(u8),
+}
+
+impl RouteRefreshMessage {
+    pub const LEN: usize = 4;
+
+    pub fn request(afi: Afi, safi: Safi) -> Self {
+        RouteRefreshMessage {
+            afi,
+            safi,
+            subtype: RouteRefreshSubtype::Request,
+        }
+    }
+
+    pub fn try_decode(data: &mut Bytes) -> Result<Self, String> {
+        if data.len() != Self::LEN {
+            return Err(format!(
+                "ROUTE-REFRESH body is {} bytes, expected {}",
+                data.len(),
+                Self::LEN
+            ));
+        }
+
+        let afi = Afi::from(data.get_u16());
+        spans::consumed(data, 2, || format!("AFI: {afi:?}"));
+        let subtype = RouteRefreshSubtype::from(data.get_u8());
+        spans::consumed(data, 1, || format!("subtype: {subtype:?}"));
+        let safi = Safi::from(data.get_u8());
+        spans::consumed(data, 1, || format!("SAFI: {safi:?}"));
+        Ok(RouteRefreshMessage { afi, safi, subtype })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::with_capacity(Self::LEN);
+        buffer.put_u16(self.afi.into());
+        buffer.put_u8(self.subtype.into());
+        buffer.put_u8(self.safi.into());
+        buffer.freeze()
+    }
+}
+
+impl From<u8> for RouteRefreshSubtype {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => RouteRefreshSubtype::Request,
+            1 => RouteRefreshSubtype::Begin,
+            2 => RouteRefreshSubtype::End,
+            _ => RouteRefreshSubtype::Unknown(value),
+        }
+    }
+}
+
+impl From<RouteRefreshSubtype> for u8 {
+    fn from(subtype: RouteRefreshSubtype) -> Self {
+        match subtype {
+            RouteRefreshSubtype::Request => 0,
+            RouteRefreshSubtype::Begin => 1,
+            RouteRefreshSubtype::End => 2,
+            RouteRefreshSubtype::Unknown(value) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route_refresh_round_trip() {
+        let message = RouteRefreshMessage {
+            afi: Afi::Ipv6,
+            safi: Safi::Unicast,
+            subtype: RouteRefreshSubtype::End,
+        };
+        let mut data = message.to_bytes();
+        assert_eq!(&data[..], &[0, 2, 2, 1]);
+        assert_eq!(RouteRefreshMessage::try_decode(&mut data).unwrap(), message);
+    }
+
+    #[test]
+    fn test_route_refresh_bad_length() {
+        let mut data = Bytes::from_static(&[0, 1, 0, 1, 0]);
+        assert!(RouteRefreshMessage::try_decode(&mut data).is_err());
+    }
+}
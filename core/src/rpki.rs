@@ -0,0 +1,533 @@
+//! Route origin validation (RFC 6811) against a table of ROAs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::address_family::Afi;
+use crate::attribute::PathAttribute;
+use crate::monitor::origin_as;
+use crate::update_message::IpAddrPrefix;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RoaError {
+    #[error("malformed ROA {0:?}")]
+    Malformed(String),
+    #[error("max length {max_length} is invalid for {prefix}")]
+    InvalidMaxLength {
+        prefix: IpAddrPrefix,
+        max_length: u8,
+    },
+    #[error("line {line}: {error}")]
+    Line { line: usize, error: Box<RoaError> },
+}
+
+/// Validation state of a route (RFC 6811)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RpkiStatus {
+    /// A ROA covers the route and authorizes its origin
+    Valid,
+    /// ROAs cover the route but none authorizes its origin or length
+    Invalid,
+    /// No ROA covers the route
+    NotFound,
+}
+
+/// A validated ROA payload: `asn` may originate `prefix` and its more-specifics up to
+/// `max_length`.
+///
+/// ROAs for AS 0 (RFC 6483) authorize no origin, they only make covered routes invalid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Roa {
+    prefix: IpAddrPrefix,
+    max_length: u8,
+    asn: u32,
+}
+
+/// ROAs indexed by prefix for origin validation.
+///
+/// Looking up a route checks every prefix length up to the route's that any ROA uses, so a
+/// lookup costs at most one hash probe per distinct ROA length.
+///
+/// Parses from lines of `<prefix> <max length> <asn>` such as `192.0.2.0/24 24 AS64496`, with
+/// `#` comments. With the `serde` feature it also deserializes from the locally added
+/// assertions of a SLURM file (RFC 8416).
+#[derive(Debug, Clone, Default)]
+pub struct RoaTable {
+    roas: HashMap<IpAddrPrefix, Vec<(u8, u32)>>,
+    /// Distinct prefix lengths of the ROAs, ascending
+    ipv4_lengths: Vec<u8>,
+    ipv6_lengths: Vec<u8>,
+    len: usize,
+}
+
+/// A [`RoaTable`] that can be replaced while validation is running.
+///
+/// Clones share the table. Readers get the table current at the time of the call and keep it
+/// alive while they use it, so a refresh never blocks on or disturbs in-flight validations.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRoaTable {
+    current: Arc<RwLock<Arc<RoaTable>>>,
+}
+
+impl Roa {
+    /// Fails unless `length <= max_length <= width`
+    pub fn new(prefix: IpAddrPrefix, max_length: u8, asn: u32) -> Result<Self, RoaError> {
+        if max_length < prefix.length() || max_length > prefix.max_length() {
+            return Err(RoaError::InvalidMaxLength { prefix, max_length });
+        }
+        Ok(Roa {
+            prefix,
+            max_length,
+            asn,
+        })
+    }
+
+    pub fn prefix(&self) -> &IpAddrPrefix {
+        &self.prefix
+    }
+
+    pub fn max_length(&self) -> u8 {
+        self.max_length
+    }
+
+    pub fn asn(&self) -> u32 {
+        self.asn
+    }
+}
+
+impl fmt::Display for Roa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} AS{}", self.prefix, self.max_length, self.asn)
+    }
+}
+
+impl FromStr for Roa {
+    type Err = RoaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || RoaError::Malformed(s.to_string());
+        let [prefix, max_length, asn] = s.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(malformed());
+        };
+        let prefix: IpAddrPrefix = prefix.parse().map_err(|_| malformed())?;
+        let max_length = max_length.parse().map_err(|_| malformed())?;
+        let asn = asn.strip_prefix("AS").unwrap_or(asn);
+        let asn = asn.parse().map_err(|_| malformed())?;
+        Roa::new(prefix, max_length, asn)
+    }
+}
+
+impl RoaTable {
+    pub fn new() -> Self {
+        RoaTable::default()
+    }
+
+    pub fn insert(&mut self, roa: Roa) {
+        let lengths = match roa.prefix.afi() {
+            Afi::Ipv4 => &mut self.ipv4_lengths,
+            _ => &mut self.ipv6_lengths,
+        };
+        if let Err(i) = lengths.binary_search(&roa.prefix.length()) {
+            lengths.insert(i, roa.prefix.length());
+        }
+
+        let authorizations = self.roas.entry(roa.prefix).or_default();
+        if !authorizations.contains(&(roa.max_length, roa.asn)) {
+            authorizations.push((roa.max_length, roa.asn));
+            self.len += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Validates a route originated by `origin_as`
+    pub fn validate(&self, prefix: &IpAddrPrefix, origin_as: u32) -> RpkiStatus {
+        self.validate_origin(prefix, Some(origin_as))
+    }
+
+    /// Validates a route with the origin of its AS_PATH.
+    ///
+    /// Routes whose AS_PATH ends in an AS_SET have no origin and are never valid (RFC 6811).
+    pub fn validate_route(
+        &self,
+        prefix: &IpAddrPrefix,
+        attributes: &[PathAttribute],
+    ) -> RpkiStatus {
+        self.validate_origin(prefix, origin_as(attributes))
+    }
+
+    /// The RFC 6811 algorithm: a route is valid when a covering ROA matches both its origin
+    /// and length, invalid when it's covered but none matches
+    pub fn validate_origin(&self, prefix: &IpAddrPrefix, origin_as: Option<u32>) -> RpkiStatus {
+        let mut status = RpkiStatus::NotFound;
+        for (max_length, asn) in self.covering(prefix) {
+            // AS 0 is never a route's origin, so its ROAs match nothing
+            if prefix.length() <= max_length && asn != 0 && Some(asn) == origin_as {
+                return RpkiStatus::Valid;
+            }
+            status = RpkiStatus::Invalid;
+        }
+        status
+    }
+
+    /// Every ROA whose prefix covers `prefix`, as `(max_length, asn)`
+    fn covering<'a>(&'a self, prefix: &'a IpAddrPrefix) -> impl Iterator<Item = (u8, u32)> + 'a {
+        let lengths = match prefix.afi() {
+            Afi::Ipv4 => &self.ipv4_lengths,
+            _ => &self.ipv6_lengths,
+        };
+        lengths
+            .iter()
+            .take_while(|&&length| length <= prefix.length())
+            .filter_map(|&length| {
+                let covering = IpAddrPrefix::new(prefix.addr(), length)?;
+                self.roas.get(&covering)
+            })
+            .flatten()
+            .copied()
+    }
+}
+
+impl FromIterator<Roa> for RoaTable {
+    fn from_iter<I: IntoIterator<Item = Roa>>(iter: I) -> Self {
+        let mut table = RoaTable::new();
+        iter.into_iter().for_each(|roa| table.insert(roa));
+        table
+    }
+}
+
+impl FromStr for RoaTable {
+    type Err = RoaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = RoaTable::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let roa = line.parse().map_err(|error| RoaError::Line {
+                line: i + 1,
+                error: Box::new(error),
+            })?;
+            table.insert(roa);
+        }
+        Ok(table)
+    }
+}
+
+impl SharedRoaTable {
+    pub fn new(table: RoaTable) -> Self {
+        SharedRoaTable {
+            current: Arc::new(RwLock::new(Arc::new(table))),
+        }
+    }
+
+    /// The current table
+    pub fn load(&self) -> Arc<RoaTable> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Swaps in a refreshed table, returning the previous one
+    pub fn replace(&self, table: RoaTable) -> Arc<RoaTable> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(table))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod slurm {
+    use super::{Roa, RoaError, RoaTable};
+    use crate::update_message::IpAddrPrefix;
+
+    /// The parts of a SLURM file that add ROAs
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Slurm {
+        locally_added_assertions: Assertions,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Assertions {
+        prefix_assertions: Vec<PrefixAssertion>,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PrefixAssertion {
+        asn: u32,
+        prefix: String,
+        max_prefix_length: Option<u8>,
+    }
+
+    impl<'de> serde::Deserialize<'de> for RoaTable {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let slurm = Slurm::deserialize(deserializer)?;
+            slurm
+                .locally_added_assertions
+                .prefix_assertions
+                .into_iter()
+                .map(|assertion| {
+                    let prefix: IpAddrPrefix = assertion
+                        .prefix
+                        .parse()
+                        .map_err(|_| RoaError::Malformed(assertion.prefix.clone()))?;
+                    let max_length = assertion.max_prefix_length.unwrap_or(prefix.length());
+                    Roa::new(prefix, max_length, assertion.asn)
+                })
+                .collect::<Result<RoaTable, RoaError>>()
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    fn table(roas: &str) -> RoaTable {
+        roas.parse().unwrap()
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let roas = table("192.0.2.0/24 24 AS64496");
+        assert_eq!(
+            roas.validate(&prefix("192.0.2.0/24"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("192.0.2.0/24"), 64497),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("192.0.0.0/16"), 64496),
+            RpkiStatus::NotFound
+        );
+        assert_eq!(
+            roas.validate(&prefix("198.51.100.0/24"), 64496),
+            RpkiStatus::NotFound
+        );
+    }
+
+    #[test]
+    fn test_more_specific_beyond_max_length() {
+        let roas = table("10.0.0.0/16 20 64496");
+        assert_eq!(
+            roas.validate(&prefix("10.0.16.0/20"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("10.0.16.0/21"), 64496),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("10.0.16.0/24"), 64496),
+            RpkiStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_as0_roa() {
+        let roas = table("203.0.113.0/24 32 AS0");
+        assert_eq!(
+            roas.validate(&prefix("203.0.113.0/24"), 0),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("203.0.113.128/25"), 64496),
+            RpkiStatus::Invalid
+        );
+
+        // Another ROA still authorizes its origin
+        let roas = table("203.0.113.0/24 32 AS0\n203.0.113.0/24 24 AS64496");
+        assert_eq!(
+            roas.validate(&prefix("203.0.113.0/24"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("203.0.113.0/25"), 64496),
+            RpkiStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_overlapping_roas() {
+        let roas = table(
+            "# the aggregate and a customer's more-specific
+            10.0.0.0/8 8 AS64496
+            10.1.0.0/16 24 AS64511",
+        );
+        assert_eq!(roas.len(), 2);
+        assert_eq!(
+            roas.validate(&prefix("10.0.0.0/8"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("10.1.0.0/16"), 64511),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("10.1.2.0/24"), 64511),
+            RpkiStatus::Valid
+        );
+        // Covered by both, matched by neither
+        assert_eq!(
+            roas.validate(&prefix("10.1.0.0/16"), 64496),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("10.2.0.0/16"), 64511),
+            RpkiStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let roas = table("2001:db8::/32 48 AS64496");
+        assert_eq!(
+            roas.validate(&prefix("2001:db8:1::/48"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("2001:db8:1::/49"), 64496),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("2001:db9::/32"), 64496),
+            RpkiStatus::NotFound
+        );
+        // Families don't cover each other
+        assert_eq!(
+            roas.validate(&prefix("32.1.13.184/32"), 64496),
+            RpkiStatus::NotFound
+        );
+    }
+
+    #[test]
+    fn test_as_set_origin() {
+        use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType};
+        use crate::update_message::UpdateMessageBuilder;
+
+        let roas = table("192.0.2.0/24 24 AS64496");
+        let segment = |segment_type, asns: &[u32]| AsPathSegment {
+            segment_type,
+            asns: asns.to_vec().into(),
+        };
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix("192.0.2.0/24"))
+            .as_path(AsPath {
+                segments: [
+                    segment(AsPathSegmentType::AsSequence, &[64500, 64496]),
+                    segment(AsPathSegmentType::AsSet, &[64496]),
+                ]
+                .into(),
+            })
+            .next_hop("198.51.100.1".parse().unwrap())
+            .build();
+        assert_eq!(
+            roas.validate_route(&prefix("192.0.2.0/24"), &update.path_attributes),
+            RpkiStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            "10.0.0.0/16 12 AS1".parse::<Roa>(),
+            Err(RoaError::InvalidMaxLength { max_length: 12, .. })
+        ));
+        assert!(matches!(
+            "10.0.0.0/16 33 AS1".parse::<Roa>(),
+            Err(RoaError::InvalidMaxLength { .. })
+        ));
+        assert!(matches!(
+            "10.0.0.0/16 AS1".parse::<Roa>(),
+            Err(RoaError::Malformed(_))
+        ));
+        assert_eq!(
+            "10.0.0.0/8 8 1\nbogus".parse::<RoaTable>().unwrap_err(),
+            RoaError::Line {
+                line: 2,
+                error: Box::new(RoaError::Malformed("bogus".to_string()))
+            }
+        );
+        let roa: Roa = "10.0.0.0/8 16 AS64496".parse().unwrap();
+        assert_eq!(roa.to_string(), "10.0.0.0/8 16 AS64496");
+    }
+
+    #[test]
+    fn test_replace_shared_table() {
+        let shared = SharedRoaTable::new(table("192.0.2.0/24 24 AS64496"));
+        let before = shared.load();
+        let validator = shared.clone();
+
+        let previous = shared.replace(table("192.0.2.0/24 24 AS64497"));
+        assert!(Arc::ptr_eq(&before, &previous));
+        // Holders of the old table are unaffected, new loads see the refresh
+        assert_eq!(
+            before.validate(&prefix("192.0.2.0/24"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            validator.load().validate(&prefix("192.0.2.0/24"), 64496),
+            RpkiStatus::Invalid
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_slurm() {
+        let slurm = r#"
+            slurmVersion = 1
+
+            [validationOutputFilters]
+            prefixFilters = []
+            bgpsecFilters = []
+
+            [locallyAddedAssertions]
+            bgpsecAssertions = []
+
+            [[locallyAddedAssertions.prefixAssertions]]
+            asn = 64496
+            prefix = "198.51.100.0/24"
+            comment = "My other important route"
+
+            [[locallyAddedAssertions.prefixAssertions]]
+            asn = 64496
+            prefix = "2001:db8::/32"
+            maxPrefixLength = 48
+        "#;
+        let roas: RoaTable = toml::from_str(slurm).unwrap();
+        assert_eq!(roas.len(), 2);
+        assert_eq!(
+            roas.validate(&prefix("198.51.100.0/24"), 64496),
+            RpkiStatus::Valid
+        );
+        assert_eq!(
+            roas.validate(&prefix("198.51.100.0/25"), 64496),
+            RpkiStatus::Invalid
+        );
+        assert_eq!(
+            roas.validate(&prefix("2001:db8:ff::/48"), 64496),
+            RpkiStatus::Valid
+        );
+    }
+}
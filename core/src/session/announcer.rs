@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{AsPath, OriginType};
+use crate::route::{Route, pack};
+use crate::update_message::{IpAddrPrefix, UpdateMessage, UpdateMessageBuilder};
+
+use super::error::SessionError;
+use super::established::Command;
+
+/// How long changes are collected before they are sent, so bursts share UPDATEs
+pub(crate) const ANNOUNCE_DELAY: Duration = Duration::from_millis(200);
+/// LOCAL_PREF sent to internal peers when a route doesn't set one
+const DEFAULT_LOCAL_PREF: u32 = 100;
+
+/// Advertises routes over an established session, see [`EstablishedSession::announcer`].
+///
+/// Changes are queued and coalesced for a short moment before they go out. After the first
+/// batch the session sends an End-of-RIB marker for every negotiated family. The announcer
+/// doesn't keep the session alive; once it ends every call fails with
+/// [`SessionError::SessionClosed`].
+///
+/// [`EstablishedSession::announcer`]: super::EstablishedSession::announcer
+#[derive(Debug, Clone)]
+pub struct Announcer {
+    commands: mpsc::WeakSender<Command>,
+    families: Vec<(Afi, Safi)>,
+    defaults: RouteDefaults,
+}
+
+/// Attributes filled into routes that leave them out
+#[derive(Debug, Clone)]
+pub(crate) struct RouteDefaults {
+    pub(crate) local_asn: u32,
+    /// The peer is in another AS, so our ASN is prepended to AS_PATH
+    pub(crate) external: bool,
+    pub(crate) next_hop: Option<IpAddr>,
+}
+
+impl Announcer {
+    pub(crate) fn new(
+        commands: mpsc::WeakSender<Command>,
+        families: Vec<(Afi, Safi)>,
+        defaults: RouteDefaults,
+    ) -> Self {
+        Announcer {
+            commands,
+            families,
+            defaults,
+        }
+    }
+
+    /// Advertises `route`, replacing any earlier announcement of its prefix.
+    ///
+    /// ORIGIN defaults to IGP and the configured next hop is used when the route has none.
+    /// Towards external peers our ASN is prepended to AS_PATH, internal peers get a
+    /// LOCAL_PREF of 100 unless the route sets one.
+    pub async fn announce(&self, route: Route) -> Result<(), SessionError> {
+        let route = self.complete(route)?;
+        self.send(Command::Announce(route)).await
+    }
+
+    pub async fn withdraw(&self, prefix: IpAddrPrefix) -> Result<(), SessionError> {
+        self.check_family(&prefix)?;
+        self.send(Command::Withdraw(prefix)).await
+    }
+
+    fn complete(&self, mut route: Route) -> Result<Route, SessionError> {
+        self.check_family(&route.prefix)?;
+
+        route.origin.get_or_insert(OriginType::Igp);
+        let as_path = Arc::make_mut(
+            route
+                .as_path
+                .get_or_insert_with(|| Arc::new(AsPath::default())),
+        );
+        if self.defaults.external {
+            as_path.prepend(self.defaults.local_asn, 1);
+        } else {
+            route.local_pref.get_or_insert(DEFAULT_LOCAL_PREF);
+        }
+
+        let same_family = |next_hop: &IpAddr| next_hop.is_ipv4() == route.prefix.addr().is_ipv4();
+        if route.next_hop.is_none() {
+            route.next_hop = self.defaults.next_hop.filter(same_family);
+        }
+        if route.next_hop.is_none() {
+            return Err(SessionError::NoNextHop(route.prefix));
+        }
+        Ok(route)
+    }
+
+    fn check_family(&self, prefix: &IpAddrPrefix) -> Result<(), SessionError> {
+        let family = (prefix.afi(), Safi::Unicast);
+        if !self.families.contains(&family) {
+            return Err(SessionError::FamilyNotNegotiated(family.0, family.1));
+        }
+        Ok(())
+    }
+
+    async fn send(&self, command: Command) -> Result<(), SessionError> {
+        let commands = self.commands.upgrade().ok_or(SessionError::SessionClosed)?;
+        commands
+            .send(command)
+            .await
+            .map_err(|_| SessionError::SessionClosed)
+    }
+}
+
+/// Changes waiting to be sent, coalesced against what the peer already has
+#[derive(Debug, Default)]
+pub(crate) struct PendingRoutes {
+    changes: BTreeMap<IpAddrPrefix, Option<Route>>,
+    /// Routes the peer currently has from us
+    advertised: BTreeMap<IpAddrPrefix, Route>,
+    end_of_rib_sent: bool,
+}
+
+impl PendingRoutes {
+    pub(crate) fn announce(&mut self, route: Route) {
+        self.changes.insert(route.prefix.clone(), Some(route));
+    }
+
+    pub(crate) fn withdraw(&mut self, prefix: IpAddrPrefix) {
+        self.changes.insert(prefix, None);
+    }
+
+    /// Turns the queued changes into UPDATEs, followed by End-of-RIB markers for `families`
+    /// after the first batch
+    pub(crate) fn flush(
+        &mut self,
+        families: &[(Afi, Safi)],
+        four_octet_as: bool,
+    ) -> Vec<UpdateMessage> {
+        let mut withdrawn = vec![];
+        let mut announced = vec![];
+        for (prefix, change) in std::mem::take(&mut self.changes) {
+            match change {
+                Some(route) => {
+                    if self.advertised.get(&prefix) == Some(&route) {
+                        continue;
+                    }
+                    self.advertised.insert(prefix, route.clone());
+                    announced.push(route);
+                }
+                None => {
+                    if self.advertised.remove(&prefix).is_some() {
+                        withdrawn.push(prefix);
+                    }
+                }
+            }
+        }
+
+        // IPv4 prefixes sort first, so each family fills its own UPDATEs
+        let mut updates = vec![];
+        let mut pending: Option<(usize, UpdateMessage)> = None;
+        for prefix in &withdrawn {
+            if let Some((len, update)) = &mut pending
+                && pack(update, len, prefix, true)
+            {
+                continue;
+            }
+            let update = UpdateMessageBuilder::new().withdraw(prefix.clone()).build();
+            let previous = pending.replace((update.encoded_len(), update));
+            updates.extend(previous.map(|(_, update)| update));
+        }
+        updates.extend(pending.map(|(_, update)| update));
+
+        updates.extend(UpdateMessage::from_routes(announced, four_octet_as));
+
+        if !self.end_of_rib_sent {
+            self.end_of_rib_sent = true;
+            updates.extend(
+                families
+                    .iter()
+                    .map(|(afi, safi)| UpdateMessage::end_of_rib(*afi, *safi)),
+            );
+        }
+        updates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::attribute::AttributeValue;
+    use crate::bgp_message::BgpMessage;
+    use crate::capability::Capability;
+    use crate::header::BgpHeader;
+    use crate::session::established::test::{connected_pair, local_config};
+
+    const FAMILIES: [(Afi, Safi); 2] = [(Afi::Ipv4, Safi::Unicast), (Afi::Ipv6, Safi::Unicast)];
+
+    fn prefix(addr: impl Into<IpAddr>, length: u8) -> IpAddrPrefix {
+        IpAddrPrefix::new(addr.into(), length).unwrap()
+    }
+
+    fn route(addr: [u8; 4], med: u32) -> Route {
+        Route {
+            origin: Some(OriginType::Igp),
+            next_hop: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            med: Some(med),
+            ..Route::new(prefix(Ipv4Addr::from(addr), 24))
+        }
+    }
+
+    #[test]
+    fn test_flush_coalesces_changes() {
+        let mut pending = PendingRoutes::default();
+        pending.announce(route([198, 51, 100, 0], 10));
+        pending.announce(route([203, 0, 113, 0], 10));
+        pending.announce(route([192, 0, 2, 0], 20));
+        pending.withdraw(prefix(Ipv4Addr::new(192, 0, 2, 0), 24));
+
+        let updates = pending.flush(&FAMILIES[..1], true);
+        // Both MED 10 routes share an UPDATE, the withdrawn one never went out
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].nlri.len(), 2);
+        assert!(updates[0].withdrawn_routes.is_empty());
+        assert_eq!(
+            updates[1].end_of_rib_family(),
+            Some((Afi::Ipv4, Safi::Unicast))
+        );
+
+        // Repeating an announcement is suppressed, End-of-RIB is only sent once
+        pending.announce(route([198, 51, 100, 0], 10));
+        assert!(pending.flush(&FAMILIES[..1], true).is_empty());
+
+        pending.withdraw(prefix(Ipv4Addr::new(203, 0, 113, 0), 24));
+        let updates = pending.flush(&FAMILIES[..1], true);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            updates[0].withdrawn_routes,
+            vec![prefix(Ipv4Addr::new(203, 0, 113, 0), 24)]
+        );
+    }
+
+    #[test]
+    fn test_flush_splits_large_batches() {
+        let mut pending = PendingRoutes::default();
+        for i in 0..2000u32 {
+            let addr = Ipv4Addr::from_bits(0x0a00_0000 | (i << 8));
+            pending.announce(Route {
+                next_hop: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                ..Route::new(prefix(addr, 24))
+            });
+        }
+
+        let updates = pending.flush(&[], true);
+        assert!(updates.len() > 1);
+        assert_eq!(
+            updates
+                .iter()
+                .map(|update| update.nlri.len())
+                .sum::<usize>(),
+            2000
+        );
+        for update in updates {
+            assert!(BgpMessage::Update(update).to_bytes().len() <= BgpHeader::MAX_LEN as usize);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_beacons_reach_peer() {
+        let multiprotocol = FAMILIES.map(|(afi, safi)| Capability::MultiProtocol { afi, safi });
+        let mut local = local_config(90);
+        local.capabilities = multiprotocol.to_vec();
+        local.next_hop = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut remote = local_config(90);
+        remote.local_asn = 65002;
+        remote.capabilities = multiprotocol.to_vec();
+        let (local, mut remote) = connected_pair(local, remote).await;
+
+        let announcer = local.announcer();
+        let beacons = [
+            prefix(Ipv4Addr::new(198, 51, 100, 0), 24),
+            prefix(Ipv4Addr::new(203, 0, 113, 0), 24),
+        ];
+        for beacon in &beacons {
+            announcer
+                .announce(Route::new(beacon.clone()))
+                .await
+                .unwrap();
+        }
+        let ipv6_beacon = prefix(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), 48);
+        announcer
+            .announce(Route {
+                next_hop: Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+                ..Route::new(ipv6_beacon.clone())
+            })
+            .await
+            .unwrap();
+        // No IPv6 next hop is configured
+        assert!(matches!(
+            announcer.announce(Route::new(ipv6_beacon.clone())).await,
+            Err(SessionError::NoNextHop(_))
+        ));
+
+        let mut received = vec![];
+        let mut end_of_rib = vec![];
+        while end_of_rib.len() < FAMILIES.len() {
+            let Some(BgpMessage::Update(update)) = remote.recv().await else {
+                continue;
+            };
+            if let Some(family) = update.end_of_rib_family() {
+                end_of_rib.push(family);
+                continue;
+            }
+            for attribute in &update.path_attributes {
+                match &attribute.value {
+                    AttributeValue::AsPath(as_path) => {
+                        assert_eq!(as_path.segments[0].asns, vec![65001])
+                    }
+                    AttributeValue::MpReachNlri(mp_reach) => {
+                        received.extend(mp_reach.nlri.iter().cloned())
+                    }
+                    _ => {}
+                }
+            }
+            received.extend(update.nlri);
+        }
+
+        assert_eq!(received, [&beacons[..], &[ipv6_beacon]].concat());
+        assert_eq!(end_of_rib, FAMILIES);
+    }
+}
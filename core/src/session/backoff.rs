@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::config::BackoffConfig;
+
+/// Reconnection schedule of a single peer
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: BackoffConfig,
+    /// Consecutive failed attempts since the last established session
+    attempts: u32,
+    /// Failures still inside the damping window, oldest first
+    failures: VecDeque<Instant>,
+    next_retry: Option<Instant>,
+    down: bool,
+}
+
+/// Point in time view of a peer's [`Backoff`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffStatus {
+    pub attempts: u32,
+    /// Failures counted towards the damping penalty
+    pub recent_failures: usize,
+    /// When the next connection attempt is due, `None` while connecting or established
+    pub next_retry: Option<Instant>,
+    /// Retries are exhausted and the peer is no longer attempted
+    pub down: bool,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Backoff {
+            config,
+            attempts: 0,
+            failures: VecDeque::new(),
+            next_retry: None,
+            down: false,
+        }
+    }
+
+    /// Records a failed connection attempt or a session that ended and schedules the next
+    /// attempt, returning how long to wait or `None` once retries are exhausted
+    pub fn fail(&mut self, now: Instant) -> Option<Duration> {
+        if self
+            .config
+            .max_retries
+            .is_some_and(|max| self.attempts >= max)
+        {
+            self.attempts += 1;
+            self.next_retry = None;
+            self.down = true;
+            return None;
+        }
+
+        let exponential = self
+            .config
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(self.config.cap);
+        let mut delay = exponential.mul_f64(jitter_factor(self.config.jitter));
+
+        if let Some(damping) = self.config.damping {
+            while self
+                .failures
+                .front()
+                .is_some_and(|failure| now.duration_since(*failure) >= damping.window)
+            {
+                self.failures.pop_front();
+            }
+            self.failures.push_back(now);
+
+            let excess = (self.failures.len() as u32 + 1).saturating_sub(damping.threshold);
+            delay += damping.penalty.saturating_mul(excess);
+        }
+
+        self.attempts += 1;
+        self.next_retry = Some(now + delay);
+        Some(delay)
+    }
+
+    /// Records an established session; consecutive attempts start over but damping keeps
+    /// counting, so a peer that flaps right after establishing is still slowed down
+    pub fn succeed(&mut self) {
+        self.attempts = 0;
+        self.next_retry = None;
+    }
+
+    /// Marks the scheduled attempt as started
+    pub fn retrying(&mut self) {
+        self.next_retry = None;
+    }
+
+    pub fn status(&self) -> BackoffStatus {
+        BackoffStatus {
+            attempts: self.attempts,
+            recent_failures: self.failures.len(),
+            next_retry: self.next_retry,
+            down: self.down,
+        }
+    }
+}
+
+/// A random factor within `1.0 ± jitter`
+fn jitter_factor(jitter: f64) -> f64 {
+    if jitter == 0.0 {
+        return 1.0;
+    }
+    // Every RandomState is keyed differently, which is all the randomness this needs
+    let random = RandomState::new().build_hasher().finish();
+    let unit = (random % 10_001) as f64 / 10_000.0;
+    1.0 - jitter + 2.0 * jitter * unit
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::time;
+
+    use crate::session::config::DampingConfig;
+
+    fn config() -> BackoffConfig {
+        BackoffConfig {
+            base: Duration::from_secs(1),
+            cap: Duration::from_secs(10),
+            jitter: 0.0,
+            max_retries: None,
+            damping: None,
+        }
+    }
+
+    fn delays(backoff: &mut Backoff, count: usize) -> Vec<u64> {
+        (0..count)
+            .map(|_| backoff.fail(Instant::now()).unwrap().as_secs())
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exponential_sequence() {
+        let mut backoff = Backoff::new(config());
+        assert_eq!(delays(&mut backoff, 6), vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(
+            backoff.status().next_retry,
+            Some(Instant::now() + Duration::from_secs(10))
+        );
+
+        backoff.succeed();
+        assert_eq!(delays(&mut backoff, 2), vec![1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_retries() {
+        let mut backoff = Backoff::new(BackoffConfig {
+            max_retries: Some(2),
+            ..config()
+        });
+        assert_eq!(delays(&mut backoff, 2), vec![1, 2]);
+        assert_eq!(backoff.fail(Instant::now()), None);
+        let status = backoff.status();
+        assert!(status.down);
+        assert_eq!(status.attempts, 3);
+        assert_eq!(status.next_retry, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_damping_penalty() {
+        let mut backoff = Backoff::new(BackoffConfig {
+            damping: Some(DampingConfig {
+                window: Duration::from_secs(60),
+                threshold: 2,
+                penalty: Duration::from_secs(30),
+            }),
+            ..config()
+        });
+
+        // Sessions establish in between, only damping remembers the earlier failures
+        let mut sequence = vec![];
+        for _ in 0..3 {
+            sequence.push(backoff.fail(Instant::now()).unwrap().as_secs());
+            backoff.succeed();
+            time::advance(Duration::from_secs(10)).await;
+        }
+        assert_eq!(sequence, vec![1, 31, 61]);
+
+        // Failures age out of the window
+        time::advance(Duration::from_secs(60)).await;
+        assert_eq!(backoff.fail(Instant::now()), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.status().recent_failures, 1);
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        for _ in 0..100 {
+            let factor = jitter_factor(0.25);
+            assert!((0.75..=1.25).contains(&factor));
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::rib::RouteEvent;
+use crate::timestamped::Timestamped;
+
+use super::observer::SessionEvent;
+use crate::peer::{PeerDown, PeerUp};
+
+/// What the [`EventBus`] carries
+#[derive(Debug, Clone)]
+pub enum BusEvent {
+    Session(SessionEvent),
+    Route(RouteEvent),
+    PeerUp(PeerUp),
+    PeerDown(PeerDown),
+}
+
+/// An event as delivered to every subscriber, shared rather than cloned per subscriber
+pub type BusMessage = Arc<Timestamped<BusEvent>>;
+
+/// How the [`EventBus`] treats subscribers that fall behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// A subscriber whose queue is full loses its oldest events, counted in
+    /// [`SubscriberStats::dropped`]; publishing never waits
+    DropOldest,
+    /// Publishing waits until every subscriber has room in its queue
+    Backpressure,
+}
+
+/// Counters of a single subscriber, see [`EventBus::stats`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberStats {
+    pub name: String,
+    pub received: u64,
+    /// Events lost under [`LagPolicy::DropOldest`]
+    pub dropped: u64,
+    /// Events waiting to be received
+    pub queued: usize,
+}
+
+/// Fans a stream of events out to several consumers, such as alerting, metrics and
+/// persistence, each with a queue of `capacity` events.
+///
+/// With [`LagPolicy::Backpressure`], [`EventBus::publish`] waits for the slowest subscriber.
+/// Publishing from the task that reads a session, for example in a loop over
+/// [`EstablishedSession::recv_timestamped`], then stops reading from the peer until the
+/// subscribers catch up.
+///
+/// [`EstablishedSession::recv_timestamped`]: super::EstablishedSession::recv_timestamped
+#[derive(Debug)]
+pub struct EventBus {
+    policy: LagPolicy,
+    capacity: usize,
+    broadcast: broadcast::Sender<BusMessage>,
+    /// Events broadcast so far
+    published: AtomicU64,
+    subscribers: Mutex<Vec<Registration>>,
+}
+
+#[derive(Debug)]
+struct Registration {
+    counters: Arc<Counters>,
+    /// Events broadcast before the subscription
+    subscribed_at: u64,
+    /// The subscriber's queue under [`LagPolicy::Backpressure`]
+    sender: Option<mpsc::Sender<BusMessage>>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    name: String,
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Receives the events published on an [`EventBus`]; dropping it unsubscribes
+#[derive(Debug)]
+pub struct Subscriber {
+    counters: Arc<Counters>,
+    events: Queue,
+}
+
+#[derive(Debug)]
+enum Queue {
+    Broadcast(broadcast::Receiver<BusMessage>),
+    Bounded(mpsc::Receiver<BusMessage>),
+}
+
+impl BusEvent {
+    /// The peer the event is about
+    pub fn peer(&self) -> IpAddr {
+        match self {
+            BusEvent::Route(event) => event.peer,
+            BusEvent::PeerUp(PeerUp { peer, .. }) | BusEvent::PeerDown(PeerDown { peer, .. }) => {
+                peer.peer_addr.ip()
+            }
+            BusEvent::Session(event) => match event {
+                SessionEvent::Established(peer)
+                | SessionEvent::Update(peer, _)
+                | SessionEvent::Notification(peer, _)
+                | SessionEvent::Keepalive(peer)
+                | SessionEvent::RouteRefresh(peer, _)
+                | SessionEvent::MaxPrefixes(peer, _)
+                | SessionEvent::Closed(peer, _) => peer.peer_addr.ip(),
+                SessionEvent::RetriesExhausted(addr, _) => addr.ip(),
+            },
+        }
+    }
+}
+
+impl EventBus {
+    /// # Panics
+    ///
+    /// When `capacity` is zero
+    pub fn new(policy: LagPolicy, capacity: usize) -> Self {
+        assert!(capacity > 0, "event bus capacity must not be zero");
+        let (broadcast, _) = broadcast::channel(capacity);
+        EventBus {
+            policy,
+            capacity,
+            broadcast,
+            published: AtomicU64::new(0),
+            subscribers: Mutex::default(),
+        }
+    }
+
+    /// Receives every event published from now on under `name`, for statistics
+    pub fn subscribe(&self, name: impl Into<String>) -> Subscriber {
+        let counters = Arc::new(Counters {
+            name: name.into(),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let (events, sender) = match self.policy {
+            LagPolicy::DropOldest => (Queue::Broadcast(self.broadcast.subscribe()), None),
+            LagPolicy::Backpressure => {
+                let (sender, receiver) = mpsc::channel(self.capacity);
+                (Queue::Bounded(receiver), Some(sender))
+            }
+        };
+        self.subscribers.lock().unwrap().push(Registration {
+            counters: counters.clone(),
+            subscribed_at: self.published.load(Ordering::Relaxed),
+            sender,
+        });
+        Subscriber { counters, events }
+    }
+
+    /// Stops delivering events to `subscriber`, which is the same as dropping it
+    pub fn unsubscribe(&self, subscriber: Subscriber) {
+        let counters = subscriber.counters.clone();
+        drop(subscriber);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|registration| !Arc::ptr_eq(&registration.counters, &counters));
+    }
+
+    /// Delivers `event` to every current subscriber
+    pub async fn publish(&self, event: Timestamped<BusEvent>) {
+        let event = Arc::new(event);
+        match self.policy {
+            LagPolicy::DropOldest => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+                // Fails only without subscribers
+                let _ = self.broadcast.send(event);
+            }
+            LagPolicy::Backpressure => {
+                let senders: Vec<mpsc::Sender<BusMessage>> = self
+                    .subscribers
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter_map(|registration| registration.sender.clone())
+                    .collect();
+                for sender in senders {
+                    // A dropped subscriber is pruned with the next stats or subscription
+                    let _ = sender.send(event.clone()).await;
+                }
+            }
+        }
+    }
+
+    /// Counters of every current subscriber, in subscription order
+    pub fn stats(&self) -> Vec<SubscriberStats> {
+        let published = self.published.load(Ordering::Relaxed);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|registration| Arc::strong_count(&registration.counters) > 1);
+        subscribers
+            .iter()
+            .map(|registration| {
+                let received = registration.counters.received.load(Ordering::Relaxed);
+                let dropped = registration.counters.dropped.load(Ordering::Relaxed);
+                let queued = match &registration.sender {
+                    Some(sender) => sender.max_capacity() - sender.capacity(),
+                    // Drops are only counted once the subscriber notices them, but the queue
+                    // never holds more than its capacity
+                    None => (published - registration.subscribed_at - received - dropped)
+                        .min(self.capacity as u64) as usize,
+                };
+                SubscriberStats {
+                    name: registration.counters.name.clone(),
+                    received,
+                    dropped,
+                    queued,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Subscriber {
+    pub fn name(&self) -> &str {
+        &self.counters.name
+    }
+
+    /// The next event, `None` once the bus is gone and every event was received
+    pub async fn recv(&mut self) -> Option<BusMessage> {
+        let event = match &mut self.events {
+            Queue::Broadcast(receiver) => loop {
+                match receiver.recv().await {
+                    Ok(event) => break event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        self.counters.dropped.fetch_add(skipped, Ordering::Relaxed);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+            Queue::Bounded(receiver) => receiver.recv().await?,
+        };
+        self.counters.received.fetch_add(1, Ordering::Relaxed);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, SystemTime};
+
+    use crate::address_family::Safi;
+    use crate::rib::RouteEventKind;
+
+    fn event(i: u8) -> Timestamped<BusEvent> {
+        Timestamped::now(BusEvent::Route(RouteEvent {
+            peer: IpAddr::V4(Ipv4Addr::new(192, 0, 2, i)),
+            timestamp: SystemTime::UNIX_EPOCH,
+            prefix: "198.51.100.0/24".parse().unwrap(),
+            safi: Safi::Unicast,
+            path_id: None,
+            kind: RouteEventKind::Withdrawn,
+            rpki: None,
+        }))
+    }
+
+    fn peer_of(message: &BusMessage) -> u8 {
+        match message.peer() {
+            IpAddr::V4(addr) => addr.octets()[3],
+            IpAddr::V6(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_drops_oldest() {
+        let bus = EventBus::new(LagPolicy::DropOldest, 4);
+        let mut fast = bus.subscribe("metrics");
+        let mut slow = bus.subscribe("persistence");
+
+        for i in 1..=10 {
+            bus.publish(event(i)).await;
+            assert_eq!(peer_of(&fast.recv().await.unwrap()), i);
+        }
+        assert_eq!(bus.stats()[1].queued, 4);
+
+        // Only the last four are left for the slow subscriber
+        for i in 7..=10 {
+            assert_eq!(peer_of(&slow.recv().await.unwrap()), i);
+        }
+        let stats = bus.stats();
+        assert_eq!(
+            stats,
+            vec![
+                SubscriberStats {
+                    name: "metrics".to_string(),
+                    received: 10,
+                    dropped: 0,
+                    queued: 0,
+                },
+                SubscriberStats {
+                    name: "persistence".to_string(),
+                    received: 4,
+                    dropped: 6,
+                    queued: 0,
+                }
+            ]
+        );
+
+        bus.unsubscribe(fast);
+        drop(slow);
+        assert!(bus.stats().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backpressure_waits_for_slowest() {
+        let bus = Arc::new(EventBus::new(LagPolicy::Backpressure, 2));
+        let mut subscriber = bus.subscribe("alerting");
+
+        let publisher = {
+            let bus = bus.clone();
+            tokio::spawn(async move {
+                for i in 1..=5 {
+                    bus.publish(event(i)).await;
+                }
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!publisher.is_finished());
+        assert_eq!(bus.stats()[0].queued, 2);
+
+        for i in 1..=5 {
+            assert_eq!(peer_of(&subscriber.recv().await.unwrap()), i);
+        }
+        publisher.await.unwrap();
+        assert_eq!(bus.stats()[0].received, 5);
+        assert_eq!(bus.stats()[0].dropped, 0);
+    }
+}
@@ -0,0 +1,326 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+use crate::bgp_message::{BgpMessage, MessageDecodeError, parse_packet};
+use crate::header::{BgpHeader, BgpMessageType};
+use crate::journal::{JournalRecord, JournalWriter};
+use crate::timestamped::Timestamped;
+use crate::update_message::ParserConfig;
+
+use super::error::SessionError;
+use super::stats::SessionStats;
+
+/// Splits a byte stream into decoded BGP messages
+pub struct MessageReader<R> {
+    inner: R,
+    buf: BytesMut,
+    stats: Option<SessionStats>,
+    journal: Option<(JournalWriter, IpAddr)>,
+    /// The address and, once known, ASN of the peer, for diagnostics
+    peer: Option<(IpAddr, Option<u32>)>,
+    config: ParserConfig,
+    /// When the last read returned, and so when every message completed in `buf` arrived
+    last_read: (SystemTime, Instant),
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(inner: R) -> Self {
+        MessageReader {
+            inner,
+            buf: BytesMut::with_capacity(BgpHeader::MAX_LEN as usize),
+            stats: None,
+            journal: None,
+            peer: None,
+            config: ParserConfig::default(),
+            last_read: (SystemTime::now(), Instant::now()),
+        }
+    }
+
+    /// Records every message read, or that failed to decode, in `stats`
+    pub fn with_stats(mut self, stats: SessionStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Records every complete message read from `peer` in a journal, including those that
+    /// fail to decode
+    pub fn with_journal(mut self, writer: JournalWriter, peer: IpAddr) -> Self {
+        self.journal = Some((writer, peer));
+        self
+    }
+
+    /// Decodes messages in a [`crate::trace`] span carrying the peer's address and ASN
+    pub fn with_peer(mut self, addr: IpAddr, asn: Option<u32>) -> Self {
+        self.peer = Some((addr, asn));
+        self
+    }
+
+    /// Decodes UPDATEs with the ASN width and ADD-PATH families of `config`, as negotiated in
+    /// the OPEN exchange, instead of 4 octet ASNs and no path identifiers
+    pub fn with_config(mut self, config: ParserConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reads the next message, returning `None` when the stream ends on a message boundary.
+    ///
+    /// This method is cancel safe: partially received messages stay buffered.
+    pub async fn next(&mut self) -> Result<Option<BgpMessage>, SessionError> {
+        Ok(self.next_timestamped().await?.map(Timestamped::into_inner))
+    }
+
+    /// Like [`MessageReader::next`], with the time the final byte of the message arrived.
+    ///
+    /// Buffered messages are always decoded before reading more, so the time the last read
+    /// returned is the time the message was completed.
+    pub async fn next_timestamped(
+        &mut self,
+    ) -> Result<Option<Timestamped<BgpMessage>>, SessionError> {
+        loop {
+            if let Some(message) = self.decode_frame()? {
+                let (received, monotonic) = self.last_read;
+                return Ok(Some(Timestamped {
+                    received,
+                    monotonic: monotonic.into_std(),
+                    value: message,
+                }));
+            }
+
+            let read = self.inner.read_buf(&mut self.buf).await?;
+            self.last_read = (SystemTime::now(), Instant::now());
+            if read == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    fn decode_frame(&mut self) -> Result<Option<BgpMessage>, MessageDecodeError> {
+        if self.buf.len() < BgpHeader::MIN_LEN as usize {
+            return Ok(None);
+        }
+
+        let header = BgpHeader::from_slice(&self.buf).inspect_err(|_| {
+            if let Some(stats) = &self.stats {
+                stats.record_parse_error(0);
+            }
+        })?;
+        if self.buf.len() < header.length as usize {
+            return Ok(None);
+        }
+
+        let length = header.length as usize;
+        // Most messages of a quiet session, with nothing to decode past the header
+        if header.message_type == BgpMessageType::Keepalive
+            && header.length == BgpHeader::MIN_LEN
+            && self.journal.is_none()
+        {
+            self.buf.advance(length);
+            if let Some(stats) = &self.stats {
+                stats.record_received(&BgpMessage::Keepalive, length);
+            }
+            return Ok(Some(BgpMessage::Keepalive));
+        }
+
+        let mut frame = self.buf.split_to(length).freeze();
+        if let Some((journal, peer)) = &self.journal {
+            let (received, _) = self.last_read;
+            journal.record(JournalRecord::received(
+                *peer,
+                Timestamped::at(received, frame.clone()),
+            ));
+        }
+
+        let _span = self.peer.map(|(addr, asn)| {
+            span!(
+                "peer.addr" = addr,
+                "peer.asn" = asn.map_or(String::new(), |asn| asn.to_string()),
+            )
+        });
+        let decoded = parse_packet(&mut frame, &self.config).map(|(_, message)| message);
+        if let Some(stats) = &self.stats {
+            match &decoded {
+                Ok(message) => stats.record_received(message, length),
+                Err(_) => stats.record_parse_error(length),
+            }
+        }
+        decoded.map(Some)
+    }
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &BgpMessage,
+) -> io::Result<()> {
+    writer.write_all(&message.to_bytes()).await
+}
+
+/// Writes a message and records it in `stats`
+pub(crate) async fn write_counted<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &BgpMessage,
+    stats: &SessionStats,
+) -> io::Result<()> {
+    let bytes = message.to_bytes();
+    writer.write_all(&bytes).await?;
+    stats.record_sent(message, bytes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::header::HeaderParseError;
+    use crate::notification_message::{NotificationErrorCode, NotificationMessage};
+
+    #[tokio::test]
+    async fn test_reader_splits_stream() {
+        let notification = BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::HoldTimeExpired,
+            vec![],
+        ));
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&BgpMessage::Keepalive.to_bytes());
+        stream.extend_from_slice(&notification.to_bytes());
+
+        let mut reader = MessageReader::new(&stream[..]);
+        assert_eq!(reader.next().await.unwrap(), Some(BgpMessage::Keepalive));
+        assert_eq!(reader.next().await.unwrap(), Some(notification));
+        assert_eq!(reader.next().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_reader_counts_keepalives() {
+        let stream = BgpMessage::Keepalive.to_bytes().repeat(3);
+        let stats = SessionStats::new();
+        let mut reader = MessageReader::new(&stream[..]).with_stats(stats.clone());
+        while let Some(message) = reader.next().await.unwrap() {
+            assert_eq!(message, BgpMessage::Keepalive);
+        }
+        assert_eq!(stats.messages_in(BgpMessageType::Keepalive), 3);
+        assert_eq!(stats.bytes_in(), 3 * 19);
+
+        // A keepalive with a body isn't one
+        let mut stream = BgpMessage::Keepalive.to_bytes().to_vec();
+        (stream[17], stream[18]) = (20, 4);
+        stream.push(0);
+        let mut reader = MessageReader::new(&stream[..]).with_stats(stats.clone());
+        assert!(matches!(
+            reader.next().await,
+            Err(SessionError::Decode(MessageDecodeError::Header(
+                HeaderParseError::LengthFieldOutOfRange { actual: 20, .. }
+            )))
+        ));
+        assert_eq!(stats.parse_errors(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reader_timestamps_final_byte() {
+        let (mut remote, local) = tokio::io::duplex(64);
+        let mut reader = MessageReader::new(local);
+        let keepalive = BgpMessage::Keepalive.to_bytes();
+        let start = Instant::now();
+
+        remote.write_all(&keepalive[..10]).await.unwrap();
+        let read = tokio::spawn(async move {
+            let message = reader.next_timestamped().await.unwrap().unwrap();
+            (message, reader)
+        });
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        remote.write_all(&keepalive[10..]).await.unwrap();
+        // Both messages arrive in one read
+        remote.write_all(&keepalive).await.unwrap();
+        let (first, mut reader) = read.await.unwrap();
+        assert_eq!(*first, BgpMessage::Keepalive);
+        assert_eq!(first.monotonic - start.into_std(), Duration::from_secs(2));
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let second = reader.next_timestamped().await.unwrap().unwrap();
+        assert_eq!(second.monotonic, first.monotonic);
+    }
+
+    #[tokio::test]
+    async fn test_reader_journals_raw_messages() {
+        use crate::journal::{Journal, JournalReader, RotationPolicy};
+
+        let dir = std::env::temp_dir().join(format!("bgp-codec-journal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (writer, task) = Journal::open(&dir, RotationPolicy::default())
+            .unwrap()
+            .spawn(8);
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let mut stream = BgpMessage::Keepalive.to_bytes().to_vec();
+        // A KEEPALIVE with a body fails to decode but is still journaled
+        stream.extend_from_slice(&BgpMessage::Keepalive.to_bytes());
+        stream[BgpHeader::MIN_LEN as usize + 17] = 20;
+        stream.push(0);
+        let mut reader = MessageReader::new(&stream[..]).with_journal(writer, peer);
+        let first = reader.next_timestamped().await.unwrap().unwrap();
+        assert!(reader.next().await.is_err());
+        drop(reader);
+        task.await.unwrap().unwrap();
+
+        let records: Vec<_> = JournalReader::open(&dir)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].peer, peer);
+        assert_eq!(records[0].received, first.received);
+        assert_eq!(records[0].message().unwrap().value, BgpMessage::Keepalive);
+        assert_eq!(records[1].bytes.len(), 20);
+        assert!(records[1].message().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reader_truncated_message() {
+        let keepalive = BgpMessage::Keepalive.to_bytes();
+        let mut reader = MessageReader::new(&keepalive[..10]);
+        assert!(matches!(reader.next().await, Err(SessionError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reader_bad_marker() {
+        let mut keepalive = BgpMessage::Keepalive.to_bytes().to_vec();
+        keepalive[0] = 0;
+        let mut reader = MessageReader::new(&keepalive[..]);
+        assert!(matches!(reader.next().await, Err(SessionError::Decode(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reader_error_carries_data() {
+        // An UPDATE with an invalid ORIGIN after a MED
+        let body = [
+            0, 0, 0, 11, 0x80, 0x04, 0x04, 0, 0, 0, 1, 0x40, 0x01, 0x01, 0x05,
+        ];
+        let mut message = BgpHeader::new(19 + body.len() as u16, BgpMessageType::Update)
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        message.extend_from_slice(&body);
+
+        let mut reader = MessageReader::new(&message[..]);
+        let Err(SessionError::Decode(err)) = reader.next().await else {
+            panic!("decoded an invalid ORIGIN");
+        };
+        let MessageDecodeError::Update(update_err) = &err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(update_err.span, Some(11..15));
+        assert_eq!(
+            &err.notification().unwrap().to_bytes()[..],
+            &[3, 6, 0x40, 0x01, 0x01, 0x05]
+        );
+    }
+}
@@ -0,0 +1,622 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::address_family::{Afi, Safi};
+use crate::capability::{AddPathDirection, AddPathFamily, Capability};
+use crate::journal::JournalWriter;
+use crate::open_message::OpenMessage;
+
+/// Parameters for a single BGP peering
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(try_from = "PeerConfigBuilder")
+)]
+pub struct PeerConfig {
+    pub remote_addr: IpAddr,
+    pub remote_port: u16,
+    /// Expected peer ASN, `None` accepts any (typical for route collectors)
+    pub remote_asn: Option<u32>,
+    pub local_asn: u32,
+    pub router_id: Ipv4Addr,
+    pub hold_time: u16,
+    pub capabilities: Vec<Capability>,
+    /// ADD-PATH directions advertised per address family
+    pub add_path: Vec<AddPathFamily>,
+    /// Only wait for the peer to connect, never dial out
+    pub passive: bool,
+    pub backoff: BackoffConfig,
+    /// TCP MD5 signature key (RFC 2385) shared with the peer
+    #[cfg(feature = "md5sig")]
+    pub md5_password: Option<String>,
+    /// GTSM (RFC 5082) minimum TTL accepted from the peer, 255 for a directly connected peer
+    pub ttl_security: Option<u8>,
+    /// Hold back our OPEN after connecting until the peer's arrives or this much time passed
+    pub delay_open: Option<Duration>,
+    /// Next hop of announced routes that don't carry their own
+    pub next_hop: Option<IpAddr>,
+    /// Source address when dialing the peer; passive sessions use the listener's address
+    pub local_addr: Option<SocketAddr>,
+    /// Network device or VRF the session is bound to (`SO_BINDTODEVICE`, Linux only)
+    pub bind_device: Option<String>,
+    /// Limit on the routes the peer may send per address family
+    pub max_prefixes: Option<MaxPrefixPolicy>,
+    /// Where every message received from the peer is recorded raw
+    pub journal: Option<JournalWriter>,
+}
+
+/// How many routes a peer may send per address family and what happens beyond that
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct MaxPrefixPolicy {
+    pub limit: u32,
+    /// Percentage of the limit at which a warning is raised
+    #[cfg_attr(feature = "serde", serde(default = "default_warning_threshold"))]
+    pub warning_threshold: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub action: MaxPrefixAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MaxPrefixAction {
+    /// Only reports the limit being exceeded
+    #[default]
+    WarnOnly,
+    /// Closes the session with Cease/Maximum Number of Prefixes Reached, reconnecting after
+    /// `restart_after` or never when it is `None`
+    Teardown {
+        #[cfg_attr(feature = "serde", serde(default, with = "option_duration_secs"))]
+        restart_after: Option<Duration>,
+    },
+}
+
+/// Delay between reconnection attempts, doubling from `base` up to `cap` per consecutive failure
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct BackoffConfig {
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub base: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub cap: Duration,
+    /// Each delay is scaled by a random factor within `1.0 ± jitter`
+    pub jitter: f64,
+    /// Consecutive failures after which the peer is given up on, `None` retries forever
+    pub max_retries: Option<u32>,
+    pub damping: Option<DampingConfig>,
+}
+
+/// Extra delay for peers that keep failing, even when their sessions establish in between
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct DampingConfig {
+    /// How long a failure counts against the peer
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub window: Duration,
+    /// Failures within the window before the penalty applies
+    pub threshold: u32,
+    /// Added to the delay for every failure within the window from the threshold on
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub penalty: Duration,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    #[error("Hold time {0} is not zero or at least 3 seconds")]
+    UnacceptableHoldTime(u16),
+    #[error("Router ID must not be 0.0.0.0")]
+    InvalidRouterId,
+    #[error("Backoff base {base:?} exceeds cap {cap:?}")]
+    InvalidBackoff { base: Duration, cap: Duration },
+    #[error("Backoff jitter {0} is not between 0 and 1")]
+    InvalidJitter(f64),
+    #[error("Peer {0} is already configured")]
+    DuplicatePeer(IpAddr),
+    #[error("TTL security minimum TTL must be between 1 and 255")]
+    InvalidTtlSecurity,
+    #[error("TCP MD5 password is {0} bytes, longer than the 80 byte maximum")]
+    Md5PasswordTooLong(usize),
+    #[error("Failed to apply socket option {option}: {reason}")]
+    SocketOption {
+        option: &'static str,
+        reason: String,
+    },
+    #[error("Socket option {0} is not supported on this platform")]
+    Unsupported(&'static str),
+    #[error("Cannot bind to local address {addr}: {reason}")]
+    LocalAddr { addr: SocketAddr, reason: String },
+    #[error("Maximum prefix warning threshold {0}% is not between 1 and 100")]
+    InvalidWarningThreshold(u8),
+    #[error("A listener bound to device {listener:?} cannot serve a peer bound to {peer:?}")]
+    ConflictingBindDevice {
+        listener: Option<String>,
+        peer: String,
+    },
+}
+
+/// Validating builder for [`PeerConfig`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct PeerConfigBuilder {
+    remote_addr: IpAddr,
+    #[cfg_attr(feature = "serde", serde(default = "default_port"))]
+    remote_port: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
+    remote_asn: Option<u32>,
+    local_asn: u32,
+    router_id: Ipv4Addr,
+    #[cfg_attr(feature = "serde", serde(default = "default_hold_time"))]
+    hold_time: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
+    capabilities: Vec<Capability>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    add_path: Vec<AddPathFamily>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    passive: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    backoff: BackoffConfig,
+    #[cfg(feature = "md5sig")]
+    #[cfg_attr(feature = "serde", serde(default))]
+    md5_password: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    ttl_security: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(default, with = "option_duration_secs"))]
+    delay_open: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    next_hop: Option<IpAddr>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    local_addr: Option<SocketAddr>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    bind_device: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_prefixes: Option<MaxPrefixPolicy>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journal: Option<JournalWriter>,
+}
+
+/// Longest key the kernel accepts for TCP MD5 signatures
+#[cfg(feature = "md5sig")]
+pub(crate) const MD5_MAX_KEY_LEN: usize = 80;
+
+impl PeerConfig {
+    pub const DEFAULT_PORT: u16 = 179;
+    pub const DEFAULT_HOLD_TIME: u16 = 90;
+
+    pub fn new(remote_addr: IpAddr, local_asn: u32, router_id: Ipv4Addr) -> Self {
+        PeerConfig {
+            remote_addr,
+            remote_port: Self::DEFAULT_PORT,
+            remote_asn: None,
+            local_asn,
+            router_id,
+            hold_time: Self::DEFAULT_HOLD_TIME,
+            capabilities: vec![],
+            add_path: vec![],
+            passive: false,
+            backoff: BackoffConfig::default(),
+            #[cfg(feature = "md5sig")]
+            md5_password: None,
+            ttl_security: None,
+            delay_open: None,
+            next_hop: None,
+            local_addr: None,
+            bind_device: None,
+            max_prefixes: None,
+            journal: None,
+        }
+    }
+
+    pub fn builder(remote_addr: IpAddr, local_asn: u32, router_id: Ipv4Addr) -> PeerConfigBuilder {
+        PeerConfigBuilder {
+            remote_addr,
+            remote_port: Self::DEFAULT_PORT,
+            remote_asn: None,
+            local_asn,
+            router_id,
+            hold_time: Self::DEFAULT_HOLD_TIME,
+            capabilities: vec![],
+            add_path: vec![],
+            passive: false,
+            backoff: BackoffConfig::default(),
+            #[cfg(feature = "md5sig")]
+            md5_password: None,
+            ttl_security: None,
+            delay_open: None,
+            next_hop: None,
+            local_addr: None,
+            bind_device: None,
+            max_prefixes: None,
+            journal: None,
+        }
+    }
+
+    pub fn remote_socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.remote_addr, self.remote_port)
+    }
+
+    /// The OPEN we send, always advertising 4 octet AS support
+    pub fn local_open(&self) -> OpenMessage {
+        let mut capabilities = self.capabilities.clone();
+        if !capabilities
+            .iter()
+            .any(|capability| matches!(capability, Capability::FourOctetAs { .. }))
+        {
+            capabilities.push(Capability::FourOctetAs {
+                asn: self.local_asn,
+            });
+        }
+        if !self.add_path.is_empty() {
+            capabilities.push(Capability::AddPath(self.add_path.clone()));
+        }
+
+        OpenMessage::new(
+            self.local_asn,
+            self.hold_time,
+            self.router_id,
+            &capabilities,
+        )
+    }
+}
+
+impl PeerConfigBuilder {
+    pub fn remote_port(mut self, remote_port: u16) -> Self {
+        self.remote_port = remote_port;
+        self
+    }
+
+    pub fn remote_asn(mut self, remote_asn: u32) -> Self {
+        self.remote_asn = Some(remote_asn);
+        self
+    }
+
+    pub fn accept_any_remote_asn(mut self) -> Self {
+        self.remote_asn = None;
+        self
+    }
+
+    pub fn hold_time(mut self, hold_time: u16) -> Self {
+        self.hold_time = hold_time;
+        self
+    }
+
+    pub fn capability(mut self, capability: Capability) -> Self {
+        self.capabilities.push(capability);
+        self
+    }
+
+    pub fn add_path(mut self, afi: Afi, safi: Safi, direction: AddPathDirection) -> Self {
+        self.add_path.push(AddPathFamily {
+            afi,
+            safi,
+            direction,
+        });
+        self
+    }
+
+    pub fn passive(mut self, passive: bool) -> Self {
+        self.passive = passive;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Enables GTSM, discarding segments from the peer with a TTL below `min_ttl`
+    pub fn ttl_security(mut self, min_ttl: u8) -> Self {
+        self.ttl_security = Some(min_ttl);
+        self
+    }
+
+    /// Enables DelayOpen, waiting up to `delay` for the peer's OPEN before sending ours
+    pub fn delay_open(mut self, delay: Duration) -> Self {
+        self.delay_open = Some(delay);
+        self
+    }
+
+    /// Next hop of announced routes that don't carry their own
+    pub fn next_hop(mut self, next_hop: IpAddr) -> Self {
+        self.next_hop = Some(next_hop);
+        self
+    }
+
+    /// Dials the peer from `local_addr`, port zero picks any free port
+    pub fn local_addr(mut self, local_addr: SocketAddr) -> Self {
+        self.local_addr = Some(local_addr);
+        self
+    }
+
+    pub fn bind_device(mut self, device: impl Into<String>) -> Self {
+        self.bind_device = Some(device.into());
+        self
+    }
+
+    pub fn max_prefixes(mut self, policy: MaxPrefixPolicy) -> Self {
+        self.max_prefixes = Some(policy);
+        self
+    }
+
+    pub fn journal(mut self, writer: JournalWriter) -> Self {
+        self.journal = Some(writer);
+        self
+    }
+
+    #[cfg(feature = "md5sig")]
+    pub fn md5_password(mut self, password: impl Into<String>) -> Self {
+        self.md5_password = Some(password.into());
+        self
+    }
+
+    pub fn build(self) -> Result<PeerConfig, ConfigError> {
+        if self.hold_time == 1 || self.hold_time == 2 {
+            return Err(ConfigError::UnacceptableHoldTime(self.hold_time));
+        }
+        if self.router_id.is_unspecified() {
+            return Err(ConfigError::InvalidRouterId);
+        }
+        if self.backoff.base > self.backoff.cap {
+            return Err(ConfigError::InvalidBackoff {
+                base: self.backoff.base,
+                cap: self.backoff.cap,
+            });
+        }
+        if !(0.0..=1.0).contains(&self.backoff.jitter) {
+            return Err(ConfigError::InvalidJitter(self.backoff.jitter));
+        }
+        if let Some(addr) = self.local_addr
+            && addr.is_ipv4() != self.remote_addr.to_canonical().is_ipv4()
+        {
+            return Err(ConfigError::LocalAddr {
+                addr,
+                reason: "address family differs from the peer's".to_string(),
+            });
+        }
+        if let Some(policy) = self.max_prefixes
+            && !(1..=100).contains(&policy.warning_threshold)
+        {
+            return Err(ConfigError::InvalidWarningThreshold(
+                policy.warning_threshold,
+            ));
+        }
+        if self.ttl_security == Some(0) {
+            return Err(ConfigError::InvalidTtlSecurity);
+        }
+        #[cfg(feature = "md5sig")]
+        if let Some(password) = &self.md5_password
+            && password.len() > MD5_MAX_KEY_LEN
+        {
+            return Err(ConfigError::Md5PasswordTooLong(password.len()));
+        }
+
+        Ok(PeerConfig {
+            remote_addr: self.remote_addr,
+            remote_port: self.remote_port,
+            remote_asn: self.remote_asn,
+            local_asn: self.local_asn,
+            router_id: self.router_id,
+            hold_time: self.hold_time,
+            capabilities: self.capabilities,
+            add_path: self.add_path,
+            passive: self.passive,
+            backoff: self.backoff,
+            #[cfg(feature = "md5sig")]
+            md5_password: self.md5_password,
+            ttl_security: self.ttl_security,
+            delay_open: self.delay_open,
+            next_hop: self.next_hop,
+            local_addr: self.local_addr,
+            bind_device: self.bind_device,
+            max_prefixes: self.max_prefixes,
+            journal: self.journal,
+        })
+    }
+}
+
+impl TryFrom<PeerConfigBuilder> for PeerConfig {
+    type Error = ConfigError;
+
+    fn try_from(builder: PeerConfigBuilder) -> Result<Self, ConfigError> {
+        builder.build()
+    }
+}
+
+impl MaxPrefixPolicy {
+    pub const DEFAULT_WARNING_THRESHOLD: u8 = 75;
+
+    pub fn new(limit: u32, action: MaxPrefixAction) -> Self {
+        MaxPrefixPolicy {
+            limit,
+            warning_threshold: Self::DEFAULT_WARNING_THRESHOLD,
+            action,
+        }
+    }
+
+    /// Route count at which the warning is raised
+    pub fn warning_count(&self) -> u64 {
+        (self.limit as u64 * self.warning_threshold as u64).div_ceil(100)
+    }
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            base: Duration::from_secs(5),
+            cap: Duration::from_secs(120),
+            jitter: 0.25,
+            max_retries: None,
+            damping: Some(DampingConfig::default()),
+        }
+    }
+}
+
+impl Default for DampingConfig {
+    fn default() -> Self {
+        DampingConfig {
+            window: Duration::from_secs(600),
+            threshold: 3,
+            penalty: Duration::from_secs(60),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_port() -> u16 {
+    PeerConfig::DEFAULT_PORT
+}
+
+#[cfg(feature = "serde")]
+fn default_hold_time() -> u16 {
+    PeerConfig::DEFAULT_HOLD_TIME
+}
+
+#[cfg(feature = "serde")]
+fn default_warning_threshold() -> u8 {
+    MaxPrefixPolicy::DEFAULT_WARNING_THRESHOLD
+}
+
+/// Durations in config files are whole seconds
+#[cfg(feature = "serde")]
+mod duration_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod option_duration_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<u64>::deserialize(deserializer).map(|secs| secs.map(Duration::from_secs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn builder() -> PeerConfigBuilder {
+        PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 1),
+        )
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        assert_eq!(
+            builder().hold_time(2).build(),
+            Err(ConfigError::UnacceptableHoldTime(2))
+        );
+        assert!(builder().hold_time(0).build().is_ok());
+        assert!(builder().hold_time(3).build().is_ok());
+
+        let unspecified = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            65000,
+            Ipv4Addr::UNSPECIFIED,
+        );
+        assert_eq!(unspecified.build(), Err(ConfigError::InvalidRouterId));
+
+        let mut policy = MaxPrefixPolicy::new(1000, MaxPrefixAction::WarnOnly);
+        policy.warning_threshold = 101;
+        assert_eq!(
+            builder().max_prefixes(policy).build(),
+            Err(ConfigError::InvalidWarningThreshold(101))
+        );
+        policy.warning_threshold = 100;
+        assert!(builder().max_prefixes(policy).build().is_ok());
+    }
+
+    #[test]
+    fn test_local_open_capabilities() {
+        let config = builder()
+            .remote_asn(65010)
+            .capability(Capability::RouteRefresh)
+            .add_path(Afi::Ipv4, Safi::Unicast, AddPathDirection::Receive)
+            .build()
+            .unwrap();
+
+        let capabilities = config.local_open().capabilities().unwrap();
+        assert_eq!(
+            capabilities,
+            vec![
+                Capability::RouteRefresh,
+                Capability::FourOctetAs { asn: 65000 },
+                Capability::AddPath(config.add_path.clone()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_toml() {
+        let config: PeerConfig = toml::from_str(
+            r#"
+            remote_addr = "192.0.2.1"
+            remote_asn = 65010
+            local_asn = 65000
+            router_id = "10.0.0.1"
+            hold_time = 30
+            capabilities = ["route_refresh", { multi_protocol = { afi = "ipv6", safi = "unicast" } }]
+            add_path = [{ afi = "ipv4", safi = "unicast", direction = "both" }]
+            passive = true
+            backoff = { base = 1, cap = 60, max_retries = 5 }
+            delay_open = 5
+            max_prefixes = { limit = 1000, action = { teardown = { restart_after = 300 } } }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.remote_port, PeerConfig::DEFAULT_PORT);
+        assert_eq!(config.hold_time, 30);
+        assert!(config.passive);
+        assert_eq!(config.backoff.cap, Duration::from_secs(60));
+        assert_eq!(config.backoff.max_retries, Some(5));
+        assert_eq!(config.backoff.damping, Some(DampingConfig::default()));
+        assert_eq!(config.delay_open, Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.max_prefixes,
+            Some(MaxPrefixPolicy::new(
+                1000,
+                MaxPrefixAction::Teardown {
+                    restart_after: Some(Duration::from_secs(300))
+                }
+            ))
+        );
+        assert_eq!(
+            config.capabilities[1],
+            Capability::MultiProtocol {
+                afi: Afi::Ipv6,
+                safi: Safi::Unicast
+            }
+        );
+
+        let invalid = toml::from_str::<PeerConfig>(
+            r#"
+            remote_addr = "192.0.2.1"
+            local_asn = 65000
+            router_id = "10.0.0.1"
+            hold_time = 1
+            "#,
+        );
+        assert!(invalid.is_err());
+    }
+}
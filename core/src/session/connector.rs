@@ -0,0 +1,509 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::bgp_message::BgpMessage;
+use crate::notification_message::{NotificationErrorCode, NotificationMessage, OpenMessageSubErr};
+use crate::open_message::OpenMessage;
+use crate::validate::Validate;
+
+use super::codec::{MessageReader, write_counted};
+use super::config::PeerConfig;
+use super::error::SessionError;
+use super::established::EstablishedSession;
+use super::socket::peer_socket;
+use super::stats::SessionStats;
+
+/// The hold timer while waiting for the peer's OPEN, before any hold time is negotiated, as
+/// RFC 4271 section 8.2.2 suggests
+const OPEN_HOLD_TIME: Duration = Duration::from_secs(240);
+
+/// Active side of a BGP session
+pub struct Peer;
+
+impl Peer {
+    /// Connects to the configured peer and performs the OPEN/KEEPALIVE exchange
+    pub async fn connect(config: PeerConfig) -> Result<EstablishedSession, SessionError> {
+        let stream = peer_socket(&config)?
+            .connect(config.remote_socket_addr())
+            .await?;
+        establish(stream, &config).await
+    }
+}
+
+/// Runs the OPEN/KEEPALIVE exchange on a connected stream.
+///
+/// The exchange is symmetric, so the same procedure serves both the active and passive side.
+pub(crate) async fn establish(
+    stream: TcpStream,
+    config: &PeerConfig,
+) -> Result<EstablishedSession, SessionError> {
+    let peer_addr = stream.peer_addr()?;
+    let (read_half, write_half) = stream.into_split();
+    establish_over(read_half, write_half, peer_addr, config).await
+}
+
+/// Runs the OPEN/KEEPALIVE exchange over any transport
+pub(crate) async fn establish_over<R, W>(
+    read_half: R,
+    mut write_half: W,
+    peer_addr: SocketAddr,
+    config: &PeerConfig,
+) -> Result<EstablishedSession, SessionError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let stats = SessionStats::new();
+    let mut reader = MessageReader::new(read_half)
+        .with_stats(stats.clone())
+        .with_peer(peer_addr.ip(), config.remote_asn);
+    if let Some(journal) = &config.journal {
+        reader = reader.with_journal(journal.clone(), peer_addr.ip());
+    }
+
+    let early_open = match config.delay_open {
+        Some(delay) => match delay_open(&mut reader, delay).await {
+            Ok(early_open) => early_open,
+            Err(err) => return Err(fail(&mut write_half, err, &stats).await),
+        },
+        None => None,
+    };
+
+    let local_open = config.local_open();
+    write_counted(
+        &mut write_half,
+        &BgpMessage::Open(local_open.clone()),
+        &stats,
+    )
+    .await?;
+
+    let exchanged = time::timeout(
+        OPEN_HOLD_TIME,
+        exchange_opens(&mut reader, config, early_open),
+    )
+    .await;
+    let remote_open = match exchanged.unwrap_or(Err(SessionError::HoldTimerExpired)) {
+        Ok(remote_open) => remote_open,
+        Err(err) => return Err(fail(&mut write_half, err, &stats).await),
+    };
+
+    write_counted(&mut write_half, &BgpMessage::Keepalive, &stats).await?;
+
+    // The negotiated hold time now runs, still bounded when it's zero as the handshake isn't
+    // done
+    let hold_time = match config.hold_time.min(remote_open.hold_time) {
+        0 => OPEN_HOLD_TIME,
+        hold_time => Duration::from_secs(hold_time.into()),
+    };
+    let Ok(confirmed) = time::timeout(hold_time, reader.next()).await else {
+        let err = SessionError::HoldTimerExpired;
+        return Err(fail(&mut write_half, err, &stats).await);
+    };
+    match confirmed {
+        Ok(Some(BgpMessage::Keepalive)) => {}
+        Ok(Some(BgpMessage::Notification(notification))) => {
+            return Err(SessionError::Notification(notification));
+        }
+        Ok(Some(message)) => {
+            let err = SessionError::UnexpectedMessage(message.message_type());
+            return Err(fail(&mut write_half, err, &stats).await);
+        }
+        Ok(None) => return Err(SessionError::ConnectionClosed),
+        Err(err) => return Err(fail(&mut write_half, err, &stats).await),
+    }
+
+    Ok(EstablishedSession::spawn(
+        peer_addr,
+        config.clone(),
+        local_open,
+        remote_open,
+        reader,
+        write_half,
+        stats,
+    ))
+}
+
+/// Waits out the DelayOpen timer, returning the peer's OPEN if it arrived first.
+///
+/// Anything else the peer sends in the meantime ends the attempt before our OPEN goes out.
+async fn delay_open<R: AsyncRead + Unpin>(
+    reader: &mut MessageReader<R>,
+    delay: Duration,
+) -> Result<Option<OpenMessage>, SessionError> {
+    let Ok(received) = time::timeout(delay, reader.next()).await else {
+        return Ok(None);
+    };
+    match received? {
+        Some(BgpMessage::Open(open)) => Ok(Some(open)),
+        Some(BgpMessage::Notification(notification)) => {
+            Err(SessionError::Notification(notification))
+        }
+        Some(message) => Err(SessionError::UnexpectedMessage(message.message_type())),
+        None => Err(SessionError::ConnectionClosed),
+    }
+}
+
+async fn exchange_opens<R: AsyncRead + Unpin>(
+    reader: &mut MessageReader<R>,
+    config: &PeerConfig,
+    early_open: Option<OpenMessage>,
+) -> Result<OpenMessage, SessionError> {
+    let received = match early_open {
+        Some(open) => Some(BgpMessage::Open(open)),
+        None => reader.next().await?,
+    };
+    let remote_open = match received {
+        Some(BgpMessage::Open(open)) => open,
+        Some(BgpMessage::Notification(notification)) => {
+            return Err(SessionError::Notification(notification));
+        }
+        Some(message) => return Err(SessionError::UnexpectedMessage(message.message_type())),
+        None => return Err(SessionError::ConnectionClosed),
+    };
+
+    remote_open.validate().map_err(|err| {
+        SessionError::OpenRejected(
+            err.sub_err()
+                .unwrap_or(OpenMessageSubErr::UnsupportedOptionalParameter),
+        )
+    })?;
+    // A config assembled by hand may itself propose one or two seconds
+    negotiate_hold_time(config.hold_time, remote_open.hold_time)
+        .map_err(SessionError::OpenRejected)?;
+
+    if let Some(remote_asn) = config.remote_asn
+        && remote_open.asn() != remote_asn
+    {
+        return Err(SessionError::OpenRejected(OpenMessageSubErr::BadPeerAS));
+    }
+
+    Ok(remote_open)
+}
+
+/// The operative hold time, the lower of both proposals, which must be zero or at least three
+/// seconds
+pub(crate) fn negotiate_hold_time(local: u16, remote: u16) -> Result<u16, OpenMessageSubErr> {
+    match local.min(remote) {
+        1 | 2 => Err(OpenMessageSubErr::UnacceptableHoldTime),
+        hold_time => Ok(hold_time),
+    }
+}
+
+/// Sends the NOTIFICATION matching a handshake failure before the connection is dropped
+async fn fail<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    err: SessionError,
+    stats: &SessionStats,
+) -> SessionError {
+    let notification = match &err {
+        SessionError::OpenRejected(sub_err) => {
+            NotificationMessage::new(NotificationErrorCode::OpenMessage(*sub_err), vec![])
+        }
+        SessionError::Decode(decode_err) => match decode_err.notification() {
+            Some(notification) => notification,
+            None => return err,
+        },
+        SessionError::UnexpectedMessage(_) => {
+            NotificationMessage::new(NotificationErrorCode::FiniteStateMachine, vec![])
+        }
+        SessionError::HoldTimerExpired => {
+            NotificationMessage::new(NotificationErrorCode::HoldTimeExpired, vec![])
+        }
+        _ => return err,
+    };
+
+    // The session is failing regardless, so a write error here adds nothing
+    let _ = write_counted(writer, &BgpMessage::Notification(notification), stats).await;
+    err
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time;
+
+    use crate::notification_message::CeaseSubErr;
+    use crate::session::established::test::{FakePeer, establish_with_fake_peer, local_config};
+    use crate::update_message::UpdateMessage;
+
+    fn config(local_asn: u32, router_id: Ipv4Addr) -> PeerConfig {
+        PeerConfig::new(IpAddr::V4(Ipv4Addr::LOCALHOST), local_asn, router_id)
+    }
+
+    async fn accept_one(
+        config: PeerConfig,
+    ) -> (
+        u16,
+        tokio::task::JoinHandle<Result<EstablishedSession, SessionError>>,
+    ) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            establish(stream, &config).await
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_negotiate_hold_time() {
+        assert_eq!(negotiate_hold_time(90, 30), Ok(30));
+        assert_eq!(negotiate_hold_time(3, 180), Ok(3));
+        assert_eq!(negotiate_hold_time(0, 90), Ok(0));
+        assert_eq!(
+            negotiate_hold_time(2, 90),
+            Err(OpenMessageSubErr::UnacceptableHoldTime)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_establishes_session() {
+        let (port, passive) = accept_one(config(65002, Ipv4Addr::new(10, 0, 0, 2))).await;
+
+        let mut active_config = config(65001, Ipv4Addr::new(10, 0, 0, 1));
+        active_config.remote_port = port;
+        active_config.remote_asn = Some(65002);
+        let active = Peer::connect(active_config).await.unwrap();
+        let mut passive = passive.await.unwrap().unwrap();
+
+        assert_eq!(active.remote_open().asn(), 65002);
+        assert_eq!(active.config().remote_asn, Some(65002));
+        assert_eq!(active.negotiated().hold_time, PeerConfig::DEFAULT_HOLD_TIME);
+        assert!(active.negotiated().four_octet_as);
+        assert_eq!(passive.remote_open().bgp_id, Ipv4Addr::new(10, 0, 0, 1));
+
+        let update = BgpMessage::Update(UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        });
+        active.send(update.clone()).await.unwrap();
+        assert_eq!(passive.recv().await, Some(update));
+    }
+
+    #[tokio::test]
+    async fn test_connect_bad_peer_as() {
+        let (port, passive) = accept_one(config(65002, Ipv4Addr::new(10, 0, 0, 2))).await;
+
+        let mut active_config = config(65001, Ipv4Addr::new(10, 0, 0, 1));
+        active_config.remote_port = port;
+        active_config.remote_asn = Some(65099);
+        let err = Peer::connect(active_config).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SessionError::OpenRejected(OpenMessageSubErr::BadPeerAS)
+        ));
+
+        match passive.await.unwrap() {
+            Err(SessionError::Notification(notification)) => assert_eq!(
+                notification.error_codes,
+                NotificationErrorCode::OpenMessage(OpenMessageSubErr::BadPeerAS)
+            ),
+            other => panic!("Unexpected handshake result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hold_time_is_lower_proposal() {
+        let (session, _peer) = establish_with_fake_peer(local_config(30), 90).await;
+        assert_eq!(session.hold_time(), 30);
+        assert_eq!(session.hold_remaining(), Some(Duration::from_secs(30)));
+
+        let (session, _peer) = establish_with_fake_peer(local_config(90), 45).await;
+        assert_eq!(session.hold_time(), 45);
+        assert_eq!(session.hold_remaining(), Some(Duration::from_secs(45)));
+    }
+
+    /// Starts a handshake against a [`FakePeer`] that the test drives by hand
+    fn handshake(
+        config: PeerConfig,
+    ) -> (
+        tokio::task::JoinHandle<Result<EstablishedSession, SessionError>>,
+        FakePeer,
+    ) {
+        let (local, remote) = tokio::io::duplex(4096);
+        let (local_read, local_write) = tokio::io::split(local);
+        let (remote_read, remote_write) = tokio::io::split(remote);
+        let peer = FakePeer {
+            reader: MessageReader::new(remote_read),
+            writer: remote_write,
+        };
+
+        let peer_addr = config.remote_socket_addr();
+        let session = tokio::spawn(async move {
+            establish_over(local_read, local_write, peer_addr, &config).await
+        });
+        (session, peer)
+    }
+
+    fn remote_open() -> BgpMessage {
+        BgpMessage::Open(OpenMessage::new(65002, 90, Ipv4Addr::new(10, 0, 0, 2), &[]))
+    }
+
+    fn delay_open_config(delay: u64) -> PeerConfig {
+        let mut config = local_config(90);
+        config.delay_open = Some(Duration::from_secs(delay));
+        config
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unacceptable_hold_time_rejected() {
+        // Set directly, the builder would refuse it
+        let (session, mut peer) = handshake(local_config(2));
+
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        peer.send(remote_open()).await;
+        assert_eq!(
+            peer.recv().await,
+            Some(BgpMessage::Notification(NotificationMessage::new(
+                NotificationErrorCode::OpenMessage(OpenMessageSubErr::UnacceptableHoldTime),
+                vec![]
+            )))
+        );
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::OpenRejected(
+                OpenMessageSubErr::UnacceptableHoldTime
+            ))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_peer_expires_hold_timer() {
+        let hold_timer_expired = BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::HoldTimeExpired,
+            vec![],
+        ));
+
+        // No OPEN
+        let (session, mut peer) = handshake(local_config(90));
+        let start = time::Instant::now();
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        assert_eq!(peer.recv().await, Some(hold_timer_expired.clone()));
+        assert_eq!(start.elapsed(), OPEN_HOLD_TIME);
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::HoldTimerExpired)
+        ));
+
+        // An OPEN but no KEEPALIVE, within the hold time it proposed
+        let (session, mut peer) = handshake(local_config(90));
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        peer.send(BgpMessage::Open(OpenMessage::new(
+            65002,
+            30,
+            Ipv4Addr::new(10, 0, 0, 2),
+            &[],
+        )))
+        .await;
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        let start = time::Instant::now();
+        assert_eq!(peer.recv().await, Some(hold_timer_expired));
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::HoldTimerExpired)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_hold_time_disables_keepalives() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(90), 0).await;
+        assert_eq!(session.hold_time(), 0);
+        assert_eq!(session.hold_remaining(), None);
+
+        // Neither side speaks for an hour and the session stays up
+        assert!(
+            time::timeout(Duration::from_secs(3600), peer.recv())
+                .await
+                .is_err()
+        );
+        assert_eq!(session.keepalives_sent(), 0);
+        assert_eq!(session.hold_remaining(), None);
+
+        session.send(BgpMessage::Keepalive).await.unwrap();
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_open_timer_expires() {
+        let (session, mut peer) = handshake(delay_open_config(5));
+        let start = time::Instant::now();
+
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        peer.send(remote_open()).await;
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        peer.send(BgpMessage::Keepalive).await;
+        assert!(session.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_open_answers_peer_open() {
+        let (session, mut peer) = handshake(delay_open_config(30));
+        let start = time::Instant::now();
+
+        peer.send(remote_open()).await;
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        peer.send(BgpMessage::Keepalive).await;
+
+        let session = session.await.unwrap().unwrap();
+        assert_eq!(session.remote_open().asn(), 65002);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_open_notification() {
+        let (session, mut peer) = handshake(delay_open_config(30));
+        let notification = NotificationMessage::new(
+            NotificationErrorCode::Cease(CeaseSubErr::ConnectionRejected),
+            vec![],
+        );
+
+        peer.send(BgpMessage::Notification(notification.clone()))
+            .await;
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::Notification(received)) if received == notification
+        ));
+        // The connection closes without our OPEN or a NOTIFICATION in response
+        assert_eq!(peer.recv().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_open_unexpected_message() {
+        let (session, mut peer) = handshake(delay_open_config(30));
+
+        peer.send(BgpMessage::Keepalive).await;
+        assert_eq!(
+            peer.recv().await,
+            Some(BgpMessage::Notification(NotificationMessage::new(
+                NotificationErrorCode::FiniteStateMachine,
+                vec![]
+            )))
+        );
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::UnexpectedMessage(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_open_connection_closed() {
+        let (session, peer) = handshake(delay_open_config(30));
+
+        drop(peer);
+        assert!(matches!(
+            session.await.unwrap(),
+            Err(SessionError::ConnectionClosed)
+        ));
+    }
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::address_family::{Afi, Safi};
+use crate::bgp_message::MessageDecodeError;
+use crate::header::BgpMessageType;
+use crate::notification_message::{NotificationMessage, OpenMessageSubErr};
+use crate::update_message::IpAddrPrefix;
+
+use super::config::ConfigError;
+use super::replay::ReplayError;
+
+/// Why a session failed; cheap to clone so observers can share it
+#[derive(Error, Debug, Clone)]
+pub enum SessionError {
+    #[error("I/O error: {0}")]
+    Io(#[source] Arc<io::Error>),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Decode(#[from] MessageDecodeError),
+    #[error("Peer OPEN rejected: {0:?}")]
+    OpenRejected(OpenMessageSubErr),
+    #[error("Peer sent NOTIFICATION {0:?}")]
+    Notification(NotificationMessage),
+    #[error("Unexpected {0:?} message while establishing session")]
+    UnexpectedMessage(BgpMessageType),
+    #[error("Route Refresh capability was not negotiated")]
+    RouteRefreshNotNegotiated,
+    #[error("Address family {0:?}/{1:?} was not negotiated")]
+    FamilyNotNegotiated(Afi, Safi),
+    #[error("No next hop for {0} in the route or the config")]
+    NoNextHop(IpAddrPrefix),
+    #[error("Hold timer expired")]
+    HoldTimerExpired,
+    #[error("Connection closed by peer")]
+    ConnectionClosed,
+    #[error("Session is no longer running")]
+    SessionClosed,
+    #[error(transparent)]
+    Replay(Arc<ReplayError>),
+}
+
+impl From<io::Error> for SessionError {
+    fn from(err: io::Error) -> Self {
+        SessionError::Io(Arc::new(err))
+    }
+}
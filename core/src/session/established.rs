@@ -0,0 +1,1255 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::future::{self, Future};
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::address_family::{Afi, Safi};
+use crate::bgp_message::BgpMessage;
+use crate::notification_message::{NotificationErrorCode, NotificationMessage};
+use crate::open_message::OpenMessage;
+use crate::route::Route;
+use crate::route_refresh_message::{RouteRefreshMessage, RouteRefreshSubtype};
+use crate::timestamped::Timestamped;
+use crate::update_message::IpAddrPrefix;
+
+use super::announcer::{ANNOUNCE_DELAY, Announcer, PendingRoutes, RouteDefaults};
+use super::codec::{MessageReader, write_counted};
+use super::config::PeerConfig;
+use super::error::SessionError;
+use super::observer::{SessionObserver, dispatch};
+use super::prefix_limit::{MaxPrefixEvent, PrefixLimit};
+use super::stats::SessionStats;
+use crate::peer::{Negotiated, PeerInfo, SessionEnd, ShutdownReason};
+
+const CHANNEL_CAPACITY: usize = 1024;
+/// How long a shutdown waits for the peer to close its side after our NOTIFICATION
+const SHUTDOWN_LINGER: Duration = Duration::from_secs(3);
+
+/// A session that completed the OPEN exchange.
+///
+/// A background task owns the connection; received messages are delivered through
+/// [`EstablishedSession::recv`] and dropping the session closes the connection.
+#[derive(Debug)]
+pub struct EstablishedSession {
+    peer_addr: SocketAddr,
+    config: PeerConfig,
+    local_open: OpenMessage,
+    remote_open: OpenMessage,
+    negotiated: Negotiated,
+    state: Arc<SessionState>,
+    stats: SessionStats,
+    inbound: mpsc::Receiver<Inbound>,
+    outbound: mpsc::Sender<Command>,
+    task: JoinHandle<Result<SessionEnd, SessionError>>,
+}
+
+/// What the background task hands to the session handle
+#[derive(Debug)]
+enum Inbound {
+    Message(Timestamped<BgpMessage>),
+    MaxPrefixes(MaxPrefixEvent),
+}
+
+/// Requests from the session handle to its background task
+#[derive(Debug)]
+pub(crate) enum Command {
+    Send(BgpMessage),
+    /// Sends a ROUTE-REFRESH request, signalling `completion` when the peer's EoRR arrives
+    Refresh {
+        afi: Afi,
+        safi: Safi,
+        completion: Option<oneshot::Sender<()>>,
+    },
+    Announce(Route),
+    Withdraw(IpAddrPrefix),
+    Shutdown(ShutdownReason),
+}
+
+/// Resolves once the peer finished re-advertising a family after
+/// [`Refresher::request_refresh`]
+#[derive(Debug)]
+pub struct RefreshCompletion(oneshot::Receiver<()>);
+
+impl Future for RefreshCompletion {
+    type Output = Result<(), SessionError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map_err(|_| SessionError::SessionClosed)
+    }
+}
+
+/// Asks the peer of a running session to re-advertise, see [`EstablishedSession::refresher`].
+///
+/// Like the [`Announcer`] it doesn't keep the session alive; once it ends every request fails
+/// with [`SessionError::SessionClosed`].
+#[derive(Debug, Clone)]
+pub struct Refresher {
+    commands: mpsc::WeakSender<Command>,
+    route_refresh: bool,
+    enhanced: bool,
+    families: Vec<(Afi, Safi)>,
+}
+
+impl Refresher {
+    /// Asks the peer to re-advertise a family, for example after a filter change.
+    ///
+    /// With Enhanced Route Refresh negotiated the returned [`RefreshCompletion`] resolves when
+    /// the peer's End of Route Refresh arrives; otherwise there is no way to tell when the
+    /// re-advertisement is done and `None` is returned.
+    pub async fn request_refresh(
+        &self,
+        afi: Afi,
+        safi: Safi,
+    ) -> Result<Option<RefreshCompletion>, SessionError> {
+        if !self.route_refresh {
+            return Err(SessionError::RouteRefreshNotNegotiated);
+        }
+        if !self.families.contains(&(afi, safi)) {
+            return Err(SessionError::FamilyNotNegotiated(afi, safi));
+        }
+        let commands = self.commands.upgrade().ok_or(SessionError::SessionClosed)?;
+
+        let (completion, receiver) = if self.enhanced {
+            let (sender, receiver) = oneshot::channel();
+            (Some(sender), Some(RefreshCompletion(receiver)))
+        } else {
+            (None, None)
+        };
+        commands
+            .send(Command::Refresh {
+                afi,
+                safi,
+                completion,
+            })
+            .await
+            .map_err(|_| SessionError::SessionClosed)?;
+        Ok(receiver)
+    }
+
+    /// The families a refresh can be requested for
+    pub fn families(&self) -> &[(Afi, Safi)] {
+        &self.families
+    }
+}
+
+/// State shared between the session handle and its background task
+#[derive(Debug, Default)]
+struct SessionState {
+    /// When the hold timer expires, `None` when the hold time negotiated to zero
+    hold_deadline: Mutex<Option<Instant>>,
+    keepalives_sent: AtomicU64,
+    keepalives_received: AtomicU64,
+}
+
+impl EstablishedSession {
+    pub(crate) fn spawn<R, W>(
+        peer_addr: SocketAddr,
+        config: PeerConfig,
+        local_open: OpenMessage,
+        remote_open: OpenMessage,
+        reader: MessageReader<R>,
+        writer: W,
+        stats: SessionStats,
+    ) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let negotiated = Negotiated::new(&local_open, &remote_open);
+        let state = Arc::new(SessionState::default());
+
+        let (inbound_tx, inbound) = mpsc::channel(CHANNEL_CAPACITY);
+        let (outbound, outbound_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let driver = Driver {
+            hold_time: Duration::from_secs(negotiated.hold_time as u64),
+            families: negotiated.families.clone(),
+            four_octet_as: negotiated.four_octet_as,
+            state: state.clone(),
+            stats: stats.clone(),
+            inbound: inbound_tx,
+            outbound: outbound_rx,
+            refreshes: HashMap::new(),
+            routes: PendingRoutes::default(),
+            prefix_limit: config.max_prefixes.map(PrefixLimit::new),
+            backlog: VecDeque::new(),
+        };
+        let reader = reader
+            .with_peer(peer_addr.ip(), Some(remote_open.asn()))
+            .with_config(negotiated.parser_config());
+        let task = tokio::spawn(driver.run(reader, writer));
+
+        EstablishedSession {
+            peer_addr,
+            config,
+            local_open,
+            remote_open,
+            negotiated,
+            state,
+            stats,
+            inbound,
+            outbound,
+            task,
+        }
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// The configuration the session was established with
+    pub fn config(&self) -> &PeerConfig {
+        &self.config
+    }
+
+    /// The effective parameters agreed with the peer
+    pub fn negotiated(&self) -> &Negotiated {
+        &self.negotiated
+    }
+
+    pub fn local_open(&self) -> &OpenMessage {
+        &self.local_open
+    }
+
+    pub fn remote_open(&self) -> &OpenMessage {
+        &self.remote_open
+    }
+
+    /// Negotiated hold time in seconds, the lower of both OPENs; zero disables the hold timer
+    pub fn hold_time(&self) -> u16 {
+        self.negotiated.hold_time
+    }
+
+    /// Time left before the hold timer expires, `None` when the hold timer is disabled
+    pub fn hold_remaining(&self) -> Option<Duration> {
+        self.state
+            .hold_deadline
+            .lock()
+            .unwrap()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Live traffic counters, covering the OPEN exchange as well
+    pub fn stats(&self) -> SessionStats {
+        self.stats.clone()
+    }
+
+    pub fn keepalives_sent(&self) -> u64 {
+        self.state.keepalives_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn keepalives_received(&self) -> u64 {
+        self.state.keepalives_received.load(Ordering::Relaxed)
+    }
+
+    /// Receives the next message from the peer, `None` once the session has ended.
+    ///
+    /// After the stream ends, [`EstablishedSession::close`] reports why.
+    pub async fn recv(&mut self) -> Option<BgpMessage> {
+        Some(self.recv_timestamped().await?.into_inner())
+    }
+
+    /// Like [`EstablishedSession::recv`], with the time the message was read off the socket
+    pub async fn recv_timestamped(&mut self) -> Option<Timestamped<BgpMessage>> {
+        loop {
+            if let Inbound::Message(message) = self.inbound.recv().await? {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Identifies this peer to a [`SessionObserver`]
+    pub fn peer_info(&self) -> PeerInfo {
+        PeerInfo {
+            peer_addr: self.peer_addr,
+            asn: self.remote_open.asn(),
+            router_id: self.remote_open.bgp_id,
+            router: None,
+        }
+    }
+
+    /// Drives the session to its end, reporting all traffic to `observer` instead of
+    /// through [`EstablishedSession::recv`]
+    pub async fn run<O: SessionObserver>(self, observer: O) -> Result<SessionEnd, SessionError> {
+        self.run_until(observer, future::pending()).await
+    }
+
+    /// Like [`EstablishedSession::run`], but shuts the session down for the returned reason
+    /// once `stop` completes
+    pub async fn run_until<O: SessionObserver>(
+        mut self,
+        observer: O,
+        stop: impl Future<Output = ShutdownReason>,
+    ) -> Result<SessionEnd, SessionError> {
+        let peer = self.peer_info();
+        observer.on_established(&peer);
+        tokio::pin!(stop);
+
+        loop {
+            let inbound = tokio::select! {
+                inbound = self.inbound.recv() => inbound,
+                reason = &mut stop => {
+                    let end = self.shutdown(reason).await;
+                    observer.on_close(&peer, end.as_ref());
+                    return end;
+                }
+            };
+            let message = match inbound {
+                Some(Inbound::Message(message)) => message,
+                Some(Inbound::MaxPrefixes(event)) => {
+                    observer.on_max_prefixes(&peer, &event);
+                    continue;
+                }
+                None => break,
+            };
+            dispatch(&observer, &peer, message);
+        }
+
+        let end = self.close().await;
+        observer.on_close(&peer, end.as_ref());
+        end
+    }
+
+    /// Advertises routes to the peer; grab it before handing the session to
+    /// [`EstablishedSession::run`]
+    pub fn announcer(&self) -> Announcer {
+        let defaults = RouteDefaults {
+            local_asn: self.config.local_asn,
+            external: self.remote_open.asn() != self.config.local_asn,
+            next_hop: self.config.next_hop,
+        };
+        Announcer::new(
+            self.outbound.downgrade(),
+            self.negotiated.families.clone(),
+            defaults,
+        )
+    }
+
+    /// A handle that observes whether this session is still running without keeping it alive
+    pub(crate) fn liveness(&self) -> mpsc::WeakSender<Command> {
+        self.outbound.downgrade()
+    }
+
+    pub async fn send(&self, message: BgpMessage) -> Result<(), SessionError> {
+        self.outbound
+            .send(Command::Send(message))
+            .await
+            .map_err(|_| SessionError::SessionClosed)
+    }
+
+    /// Asks the peer to re-advertise a family, see [`Refresher::request_refresh`]
+    pub async fn request_refresh(
+        &self,
+        afi: Afi,
+        safi: Safi,
+    ) -> Result<Option<RefreshCompletion>, SessionError> {
+        self.refresher().request_refresh(afi, safi).await
+    }
+
+    /// Requests route refreshes; grab it before handing the session to
+    /// [`EstablishedSession::run`]
+    pub fn refresher(&self) -> Refresher {
+        Refresher {
+            commands: self.outbound.downgrade(),
+            route_refresh: self.negotiated.route_refresh,
+            enhanced: self.negotiated.enhanced_route_refresh,
+            families: self.negotiated.families.clone(),
+        }
+    }
+
+    /// Sends a Cease NOTIFICATION for `reason`, half-closes the connection and waits briefly
+    /// for the peer to close its side
+    pub async fn shutdown(self, reason: ShutdownReason) -> Result<SessionEnd, SessionError> {
+        // When the session already ended the task result explains how
+        let _ = self.outbound.send(Command::Shutdown(reason)).await;
+        self.close().await
+    }
+
+    /// Stops sending, closes the connection and reports how the session ended
+    pub async fn close(self) -> Result<SessionEnd, SessionError> {
+        drop(self.outbound);
+        self.task.await.unwrap_or(Err(SessionError::SessionClosed))
+    }
+}
+
+struct Driver {
+    hold_time: Duration,
+    families: Vec<(Afi, Safi)>,
+    four_octet_as: bool,
+    state: Arc<SessionState>,
+    stats: SessionStats,
+    inbound: mpsc::Sender<Inbound>,
+    outbound: mpsc::Receiver<Command>,
+    /// Callers waiting for the peer's EoRR, per family
+    refreshes: HashMap<(Afi, Safi), Vec<oneshot::Sender<()>>>,
+    /// Announcements collected until the flush timer fires
+    routes: PendingRoutes,
+    /// Route counts, when the config limits them
+    prefix_limit: Option<PrefixLimit>,
+    /// Prefix limit events waiting for room in `inbound`, delivered before the next read
+    backlog: VecDeque<Inbound>,
+}
+
+impl Driver {
+    async fn run<R, W>(
+        mut self,
+        mut reader: MessageReader<R>,
+        mut writer: W,
+    ) -> Result<SessionEnd, SessionError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let hold_enabled = !self.hold_time.is_zero();
+        let hold_timer = time::sleep(self.hold_time);
+        tokio::pin!(hold_timer);
+        self.restart_hold_timer(hold_timer.as_mut());
+
+        let keepalive_interval = keepalive_interval(self.hold_time);
+        let keepalive_timer = time::sleep(keepalive_interval.unwrap_or_default());
+        tokio::pin!(keepalive_timer);
+        self.restart_keepalive_timer(keepalive_timer.as_mut());
+
+        let flush_timer = time::sleep(ANNOUNCE_DELAY);
+        tokio::pin!(flush_timer);
+        let mut flush_pending = false;
+
+        // Room for a message is reserved in `inbound` before reading one, so a slow consumer
+        // leaves messages in the socket instead of holding up the timers
+        let mut slot: Option<mpsc::OwnedPermit<Inbound>> = None;
+        let mut consumer_gone = false;
+        let mut waiting_since = Instant::now();
+
+        loop {
+            let reading = slot.is_some() || consumer_gone;
+            tokio::select! {
+                () = &mut hold_timer, if hold_enabled && reading => {
+                    let notification =
+                        NotificationMessage::new(NotificationErrorCode::HoldTimeExpired, vec![]);
+                    let notification = BgpMessage::Notification(notification);
+                    let _ = write_counted(&mut writer, &notification, &self.stats).await;
+                    return Err(SessionError::HoldTimerExpired);
+                }
+                () = &mut keepalive_timer, if keepalive_interval.is_some() => {
+                    self.write(&mut writer, &BgpMessage::Keepalive, keepalive_timer.as_mut())
+                        .await?;
+                }
+                () = &mut flush_timer, if flush_pending => {
+                    flush_pending = false;
+                    for update in self.routes.flush(&self.families, self.four_octet_as) {
+                        let update = BgpMessage::Update(update);
+                        self.write(&mut writer, &update, keepalive_timer.as_mut()).await?;
+                    }
+                }
+                reserved = self.inbound.clone().reserve_owned(), if !reading => {
+                    match reserved {
+                        Ok(permit) => match self.backlog.pop_front() {
+                            Some(inbound) => _ = permit.send(inbound),
+                            None => slot = Some(permit),
+                        },
+                        // Nobody listens any more, which doesn't end the session
+                        Err(_) => {
+                            consumer_gone = true;
+                            self.backlog.clear();
+                        }
+                    }
+                    // The peer isn't to blame for what sat unread
+                    self.defer_hold_timer(hold_timer.as_mut(), waiting_since.elapsed());
+                    waiting_since = Instant::now();
+                }
+                received = reader.next_timestamped(), if reading => {
+                    if let Ok(Some(_)) = received {
+                        self.restart_hold_timer(hold_timer.as_mut());
+                    }
+                    match received {
+                        Ok(Some(message)) => {
+                            waiting_since = Instant::now();
+                            let slot = slot.take();
+                            if let BgpMessage::Notification(notification) = &*message {
+                                let notification = notification.clone();
+                                if let Some(slot) = slot {
+                                    slot.send(Inbound::Message(message));
+                                }
+                                return SessionEnd::from_notification(&notification)
+                                    .ok_or(SessionError::Notification(notification));
+                            }
+                            match &*message {
+                                BgpMessage::Keepalive => {
+                                    self.state.keepalives_received.fetch_add(1, Ordering::Relaxed);
+                                }
+                                BgpMessage::RouteRefresh(RouteRefreshMessage {
+                                    afi,
+                                    safi,
+                                    subtype: RouteRefreshSubtype::End,
+                                }) => {
+                                    for completion in
+                                        self.refreshes.remove(&(*afi, *safi)).unwrap_or_default()
+                                    {
+                                        let _ = completion.send(());
+                                    }
+                                }
+                                _ => {}
+                            }
+                            let events = match (&mut self.prefix_limit, &*message) {
+                                (Some(limit), BgpMessage::Update(update)) => limit.apply(update),
+                                _ => vec![],
+                            };
+                            if let Some(slot) = slot {
+                                slot.send(Inbound::Message(message));
+                            }
+                            if let Some(reason) = self.max_prefixes(events, consumer_gone) {
+                                for inbound in self.backlog.drain(..) {
+                                    let _ = self.inbound.send(inbound).await;
+                                }
+                                return shutdown(&mut reader, &mut writer, reason, &self.stats).await;
+                            }
+                        }
+                        Ok(None) => return Err(SessionError::ConnectionClosed),
+                        Err(SessionError::Decode(err)) => {
+                            if let Some(notification) = err.notification() {
+                                let notification = BgpMessage::Notification(notification);
+                                let _ = write_counted(&mut writer, &notification, &self.stats).await;
+                            }
+                            return Err(SessionError::Decode(err));
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                command = self.outbound.recv() => match command {
+                    Some(Command::Send(message)) => {
+                        self.write(&mut writer, &message, keepalive_timer.as_mut()).await?
+                    }
+                    Some(Command::Refresh { afi, safi, completion }) => {
+                        let request = BgpMessage::RouteRefresh(RouteRefreshMessage::request(afi, safi));
+                        self.write(&mut writer, &request, keepalive_timer.as_mut()).await?;
+                        if let Some(completion) = completion {
+                            self.refreshes.entry((afi, safi)).or_default().push(completion);
+                        }
+                    }
+                    Some(Command::Announce(route)) => {
+                        self.routes.announce(route);
+                        schedule_flush(&mut flush_pending, flush_timer.as_mut());
+                    }
+                    Some(Command::Withdraw(prefix)) => {
+                        self.routes.withdraw(prefix);
+                        schedule_flush(&mut flush_pending, flush_timer.as_mut());
+                    }
+                    Some(Command::Shutdown(reason)) => {
+                        return shutdown(&mut reader, &mut writer, reason, &self.stats).await;
+                    }
+                    None => return Ok(SessionEnd::Closed),
+                },
+            }
+        }
+    }
+
+    /// Queues crossed prefix limits for the consumer, unless it's `gone`, returning why to
+    /// close the session when the limit is enforced
+    fn max_prefixes(&mut self, events: Vec<MaxPrefixEvent>, gone: bool) -> Option<ShutdownReason> {
+        let mut teardown = None;
+        for event in events {
+            if let MaxPrefixEvent::Exceeded {
+                afi, safi, limit, ..
+            } = event
+                && self
+                    .prefix_limit
+                    .as_ref()
+                    .is_some_and(PrefixLimit::teardown)
+            {
+                teardown.get_or_insert(ShutdownReason::MaximumPrefixes { afi, safi, limit });
+            }
+            if !gone {
+                self.backlog.push_back(Inbound::MaxPrefixes(event));
+            }
+        }
+        teardown
+    }
+
+    /// Writes a message; anything we send refreshes the peer's hold timer, so it also defers
+    /// the next KEEPALIVE
+    async fn write<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        message: &BgpMessage,
+        keepalive_timer: Pin<&mut time::Sleep>,
+    ) -> Result<(), SessionError> {
+        write_counted(writer, message, &self.stats).await?;
+        if *message == BgpMessage::Keepalive {
+            self.state.keepalives_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        self.restart_keepalive_timer(keepalive_timer);
+        Ok(())
+    }
+
+    fn restart_keepalive_timer(&self, keepalive_timer: Pin<&mut time::Sleep>) {
+        if let Some(interval) = keepalive_interval(self.hold_time) {
+            keepalive_timer.reset(Instant::now() + jitter(interval));
+        }
+    }
+
+    /// Pushes the hold timer back by the time reads were `paused`
+    fn defer_hold_timer(&self, hold_timer: Pin<&mut time::Sleep>, paused: Duration) {
+        let mut hold_deadline = self.state.hold_deadline.lock().unwrap();
+        if let Some(deadline) = hold_deadline.as_mut()
+            && !paused.is_zero()
+        {
+            *deadline += paused;
+            hold_timer.reset(*deadline);
+        }
+    }
+
+    fn restart_hold_timer(&self, hold_timer: Pin<&mut time::Sleep>) {
+        let mut hold_deadline = self.state.hold_deadline.lock().unwrap();
+        if self.hold_time.is_zero() {
+            *hold_deadline = None;
+            return;
+        }
+
+        let deadline = Instant::now() + self.hold_time;
+        hold_timer.reset(deadline);
+        *hold_deadline = Some(deadline);
+    }
+}
+
+/// Starts the coalescing delay unless queued changes are already waiting for it
+fn schedule_flush(flush_pending: &mut bool, flush_timer: Pin<&mut time::Sleep>) {
+    if !*flush_pending {
+        *flush_pending = true;
+        flush_timer.reset(Instant::now() + ANNOUNCE_DELAY);
+    }
+}
+
+/// Flushes the Cease NOTIFICATION, half-closes and waits for the peer's FIN
+async fn shutdown<R, W>(
+    reader: &mut MessageReader<R>,
+    writer: &mut W,
+    reason: ShutdownReason,
+    stats: &SessionStats,
+) -> Result<SessionEnd, SessionError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let notification = BgpMessage::Notification(reason.notification());
+    write_counted(writer, &notification, stats).await?;
+    writer.flush().await?;
+    writer.shutdown().await?;
+
+    // Whatever the peer still sends is irrelevant, only its FIN matters
+    let _ = time::timeout(SHUTDOWN_LINGER, async {
+        while let Ok(Some(_)) = reader.next().await {}
+    })
+    .await;
+
+    Ok(SessionEnd::LocalShutdown(reason))
+}
+
+/// KEEPALIVE interval of one third of the hold time with a one second floor, `None` when the
+/// hold time is zero
+fn keepalive_interval(hold_time: Duration) -> Option<Duration> {
+    if hold_time.is_zero() {
+        return None;
+    }
+    Some((hold_time / 3).max(Duration::from_secs(1)))
+}
+
+/// Scales an interval by a random factor in 0.75..=1.25 so many sessions don't synchronize
+fn jitter(interval: Duration) -> Duration {
+    // Every RandomState is keyed differently, which is all the randomness this needs
+    let random = RandomState::new().build_hasher().finish();
+    let factor = 0.75 + (random % 501) as f64 / 1000.0;
+    interval.mul_f64(factor)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, AttributeValue};
+    use crate::capability::{AddPathDirection, Capability};
+    use crate::session::PeerConfig;
+    use crate::session::codec::write_message;
+    use crate::session::connector::establish_over;
+    use crate::update_message::UpdateMessageBuilder;
+
+    /// The far end of an in-memory session, driven by hand from tests
+    pub(crate) struct FakePeer {
+        pub(crate) reader: MessageReader<ReadHalf<DuplexStream>>,
+        pub(crate) writer: WriteHalf<DuplexStream>,
+    }
+
+    impl FakePeer {
+        pub(crate) async fn send(&mut self, message: BgpMessage) {
+            write_message(&mut self.writer, &message).await.unwrap();
+        }
+
+        pub(crate) async fn recv(&mut self) -> Option<BgpMessage> {
+            self.reader.next().await.unwrap()
+        }
+    }
+
+    pub(crate) fn local_config(hold_time: u16) -> PeerConfig {
+        let mut config = PeerConfig::new(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        config.hold_time = hold_time;
+        config
+    }
+
+    /// Two in-process speakers with a session between them
+    pub(crate) async fn connected_pair(
+        local: PeerConfig,
+        remote: PeerConfig,
+    ) -> (EstablishedSession, EstablishedSession) {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let (local_read, local_write) = tokio::io::split(local_stream);
+        let (remote_read, remote_write) = tokio::io::split(remote_stream);
+        let local_addr = local.remote_socket_addr();
+        let remote_addr = remote.remote_socket_addr();
+
+        let (local, remote) = tokio::join!(
+            establish_over(local_read, local_write, local_addr, &local),
+            establish_over(remote_read, remote_write, remote_addr, &remote),
+        );
+        (local.unwrap(), remote.unwrap())
+    }
+
+    fn refresh_config(capabilities: &[Capability]) -> PeerConfig {
+        let mut config = local_config(90);
+        config.capabilities = capabilities.to_vec();
+        config
+    }
+
+    /// Establishes a session against a [`FakePeer`] proposing `peer_hold_time`
+    pub(crate) async fn establish_with_fake_peer(
+        config: PeerConfig,
+        peer_hold_time: u16,
+    ) -> (EstablishedSession, FakePeer) {
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let (local_read, local_write) = tokio::io::split(local);
+        let (remote_read, remote_write) = tokio::io::split(remote);
+        let mut peer = FakePeer {
+            reader: MessageReader::new(remote_read),
+            writer: remote_write,
+        };
+
+        let peer_addr = config.remote_socket_addr();
+        let session = tokio::spawn(async move {
+            establish_over(local_read, local_write, peer_addr, &config).await
+        });
+
+        assert!(matches!(peer.recv().await, Some(BgpMessage::Open(_))));
+        peer.send(BgpMessage::Open(OpenMessage::new(
+            65002,
+            peer_hold_time,
+            Ipv4Addr::new(10, 0, 0, 2),
+            &[],
+        )))
+        .await;
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        peer.send(BgpMessage::Keepalive).await;
+
+        (session.await.unwrap().unwrap(), peer)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hold_timer_expires() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(90), 3).await;
+        assert_eq!(session.hold_time(), 3);
+
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(session.hold_remaining(), Some(Duration::from_secs(1)));
+
+        // Any received message restarts the timer
+        peer.send(BgpMessage::Keepalive).await;
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(session.hold_remaining().unwrap() > Duration::from_secs(2));
+
+        let mut received = peer.recv().await;
+        while received == Some(BgpMessage::Keepalive) {
+            received = peer.recv().await;
+        }
+        assert_eq!(
+            received,
+            Some(BgpMessage::Notification(NotificationMessage::new(
+                NotificationErrorCode::HoldTimeExpired,
+                vec![]
+            )))
+        );
+        assert!(matches!(
+            session.close().await,
+            Err(SessionError::HoldTimerExpired)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_consumer_keeps_timers() {
+        let (mut session, mut peer) = establish_with_fake_peer(local_config(90), 3).await;
+
+        // Twice what the channel holds, and nobody receiving
+        for _ in 0..2 * CHANNEL_CAPACITY {
+            peer.send(beacon()).await;
+        }
+        // KEEPALIVEs still go out, and the hold timer doesn't expire on what sits unread
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(30) {
+            assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        }
+
+        for _ in 0..2 * CHANNEL_CAPACITY {
+            assert_eq!(recv_skipping_keepalives(&mut session).await, Some(beacon()));
+        }
+        peer.send(BgpMessage::Keepalive).await;
+        assert_eq!(session.recv().await, Some(BgpMessage::Keepalive));
+        assert_eq!(session.keepalives_received(), 1);
+    }
+
+    async fn next_keepalive(peer: &mut FakePeer) -> Duration {
+        let start = Instant::now();
+        assert_eq!(peer.recv().await, Some(BgpMessage::Keepalive));
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_cadence() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(9), 9).await;
+
+        for _ in 0..5 {
+            let elapsed = next_keepalive(&mut peer).await;
+            assert!(elapsed >= Duration::from_millis(2250), "{:?}", elapsed);
+            assert!(elapsed <= Duration::from_millis(3750), "{:?}", elapsed);
+            peer.send(BgpMessage::Keepalive).await;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+        // The handshake KEEPALIVE is sent before the session counters exist
+        assert_eq!(session.keepalives_sent(), 5);
+        assert_eq!(session.keepalives_received(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_defers_keepalive() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(9), 9).await;
+        next_keepalive(&mut peer).await;
+
+        time::sleep(Duration::from_secs(2)).await;
+        let update = BgpMessage::Update(crate::update_message::UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        });
+        session.send(update.clone()).await.unwrap();
+        assert_eq!(peer.recv().await, Some(update));
+        // Keep our hold timer from expiring when both intervals jitter long
+        peer.send(BgpMessage::Keepalive).await;
+
+        // The next KEEPALIVE is scheduled from the UPDATE, not the previous KEEPALIVE
+        let elapsed = next_keepalive(&mut peer).await;
+        assert!(elapsed >= Duration::from_millis(2250), "{:?}", elapsed);
+        assert_eq!(session.keepalives_sent(), 2);
+    }
+
+    #[test]
+    fn test_keepalive_interval() {
+        assert_eq!(keepalive_interval(Duration::ZERO), None);
+        assert_eq!(
+            keepalive_interval(Duration::from_secs(3)),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            keepalive_interval(Duration::from_secs(90)),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_sends_cease() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(0), 0).await;
+
+        let shutdown = tokio::spawn(session.shutdown(ShutdownReason::AdministrativeShutdown(
+            Some("maintenance".to_string()),
+        )));
+
+        let notification = peer.recv().await.unwrap();
+        let mut expected = vec![0xff; 16];
+        expected.extend_from_slice(&[0, 33, 3, 6, 2, 11]);
+        expected.extend_from_slice(b"maintenance");
+        assert_eq!(&notification.to_bytes()[..], &expected[..]);
+
+        // The half-close reaches the peer, which then closes its side
+        assert_eq!(peer.recv().await, None);
+        drop(peer);
+
+        assert_eq!(
+            shutdown.await.unwrap().unwrap(),
+            SessionEnd::LocalShutdown(ShutdownReason::AdministrativeShutdown(Some(
+                "maintenance".to_string()
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_cease_ends_session() {
+        let (mut session, mut peer) = establish_with_fake_peer(local_config(0), 0).await;
+
+        let cease = ShutdownReason::AdministrativeReset(Some("upgrade".to_string()));
+        peer.send(BgpMessage::Notification(cease.notification()))
+            .await;
+
+        assert!(matches!(
+            session.recv().await,
+            Some(BgpMessage::Notification(_))
+        ));
+        assert_eq!(session.recv().await, None);
+        assert_eq!(
+            session.close().await.unwrap(),
+            SessionEnd::RemoteCease {
+                subcode: Some(crate::notification_message::CeaseSubErr::AdministrativeReset),
+                message: Some("upgrade".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_notification_not_answered() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(0), 0).await;
+
+        // Too short for an error subcode
+        let mut malformed = vec![0xff; 16];
+        malformed.extend_from_slice(&[0, 20, 3, 6]);
+        peer.writer.write_all(&malformed).await.unwrap();
+
+        // The connection is closed without a NOTIFICATION of our own
+        assert_eq!(peer.recv().await, None);
+        assert!(matches!(
+            session.close().await,
+            Err(SessionError::Decode(
+                crate::bgp_message::MessageDecodeError::Notification(_)
+            ))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hold_timer_disabled() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(0), 90).await;
+        assert_eq!(session.hold_time(), 0);
+        assert_eq!(session.hold_remaining(), None);
+
+        time::sleep(Duration::from_secs(3600)).await;
+        peer.send(BgpMessage::Keepalive).await;
+        assert!(session.close().await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_requires_capability() {
+        let (local, _remote) = connected_pair(
+            refresh_config(&[Capability::RouteRefresh]),
+            refresh_config(&[]),
+        )
+        .await;
+        assert!(matches!(
+            local.request_refresh(Afi::Ipv4, Safi::Unicast).await,
+            Err(SessionError::RouteRefreshNotNegotiated)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_triggers_resend() {
+        let (mut local, mut remote) = connected_pair(
+            refresh_config(&[Capability::RouteRefresh]),
+            refresh_config(&[Capability::RouteRefresh]),
+        )
+        .await;
+        assert!(matches!(
+            local.request_refresh(Afi::Ipv6, Safi::Unicast).await,
+            Err(SessionError::FamilyNotNegotiated(Afi::Ipv6, Safi::Unicast))
+        ));
+
+        let completion = local.request_refresh(Afi::Ipv4, Safi::Unicast).await;
+        assert!(completion.unwrap().is_none());
+        assert_eq!(
+            recv_skipping_keepalives(&mut remote).await,
+            Some(BgpMessage::RouteRefresh(RouteRefreshMessage::request(
+                Afi::Ipv4,
+                Safi::Unicast
+            )))
+        );
+
+        remote.send(beacon()).await.unwrap();
+        assert_eq!(recv_skipping_keepalives(&mut local).await, Some(beacon()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_two_octet_peer() {
+        // The fake peer's OPEN has no capabilities, 4 octet ASNs included
+        let (mut local, mut peer) = establish_with_fake_peer(local_config(90), 90).await;
+        assert!(!local.negotiated().four_octet_as);
+
+        let as_path = AsPath {
+            segments: [AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns: [65002, 4_200_000_000].into(),
+            }]
+            .into(),
+        };
+        let update = UpdateMessageBuilder::new()
+            .announce("198.51.100.0/24".parse().unwrap())
+            .as_path(as_path.clone())
+            .next_hop(Ipv4Addr::new(192, 0, 2, 1).into())
+            .four_octet_as(false)
+            .build();
+        peer.send(BgpMessage::Update(update)).await;
+
+        let Some(BgpMessage::Update(received)) = recv_skipping_keepalives(&mut local).await else {
+            panic!("no UPDATE");
+        };
+        assert!(
+            received
+                .path_attributes
+                .iter()
+                .any(|attribute| attribute.value
+                    == AttributeValue::AsPath(Box::new(as_path.clone())))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_add_path_peer() {
+        let local = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+        )
+        .add_path(Afi::Ipv4, Safi::Unicast, AddPathDirection::Receive)
+        .build()
+        .unwrap();
+        let remote = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            65002,
+            Ipv4Addr::new(10, 0, 0, 2),
+        )
+        .add_path(Afi::Ipv4, Safi::Unicast, AddPathDirection::Send)
+        .build()
+        .unwrap();
+        let (mut local, remote) = connected_pair(local, remote).await;
+        assert!(
+            local
+                .negotiated()
+                .add_path_receive(Afi::Ipv4, Safi::Unicast)
+        );
+
+        let prefix: IpAddrPrefix = "198.51.100.0/24".parse().unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix.clone().with_path_id(Some(1)))
+            .announce(prefix.with_path_id(Some(2)))
+            .next_hop(Ipv4Addr::new(192, 0, 2, 1).into())
+            .build();
+        remote
+            .send(BgpMessage::Update(update.clone()))
+            .await
+            .unwrap();
+
+        let Some(BgpMessage::Update(received)) = recv_skipping_keepalives(&mut local).await else {
+            panic!("no UPDATE");
+        };
+        assert_eq!(received.nlri, update.nlri);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_enhanced_refresh_completion() {
+        let capabilities = [Capability::RouteRefresh, Capability::EnhancedRouteRefresh];
+        let (mut local, mut remote) =
+            connected_pair(refresh_config(&capabilities), refresh_config(&capabilities)).await;
+        assert!(local.negotiated().enhanced_route_refresh);
+
+        let completion = local
+            .request_refresh(Afi::Ipv4, Safi::Unicast)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let responder = tokio::spawn(async move {
+            let request = recv_skipping_keepalives(&mut remote).await;
+            assert!(matches!(
+                request,
+                Some(BgpMessage::RouteRefresh(RouteRefreshMessage {
+                    subtype: RouteRefreshSubtype::Request,
+                    ..
+                }))
+            ));
+            for message in [
+                marker(RouteRefreshSubtype::Begin),
+                beacon(),
+                marker(RouteRefreshSubtype::End),
+            ] {
+                remote.send(message).await.unwrap();
+            }
+            remote
+        });
+
+        completion.await.unwrap();
+        assert_eq!(
+            recv_skipping_keepalives(&mut local).await,
+            Some(marker(RouteRefreshSubtype::Begin))
+        );
+        assert_eq!(recv_skipping_keepalives(&mut local).await, Some(beacon()));
+        assert_eq!(
+            recv_skipping_keepalives(&mut local).await,
+            Some(marker(RouteRefreshSubtype::End))
+        );
+        drop(responder.await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_completion_fails_when_session_ends() {
+        let capabilities = [Capability::RouteRefresh, Capability::EnhancedRouteRefresh];
+        let (local, remote) =
+            connected_pair(refresh_config(&capabilities), refresh_config(&capabilities)).await;
+
+        let completion = local
+            .request_refresh(Afi::Ipv4, Safi::Unicast)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(remote);
+        assert!(matches!(completion.await, Err(SessionError::SessionClosed)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_filter_change_rebuilds_rib() {
+        use crate::filter::{PrefixList, RouteFilter};
+        use crate::rib::RibIn;
+        use crate::session::{ChannelObserver, SessionEvent};
+        use crate::update_message::UpdateMessageBuilder;
+
+        let capabilities = [Capability::RouteRefresh, Capability::EnhancedRouteRefresh];
+        let (local, mut remote) =
+            connected_pair(refresh_config(&capabilities), refresh_config(&capabilities)).await;
+        let refresher = local.refresher();
+        let (observer, mut events) = ChannelObserver::new(64);
+        let running = tokio::spawn(local.run_until(observer, future::pending()));
+
+        let announce = |prefixes: &[&str]| {
+            let update = prefixes
+                .iter()
+                .fold(UpdateMessageBuilder::new(), |builder, prefix| {
+                    builder.announce(prefix.parse().unwrap())
+                })
+                .next_hop("192.0.2.1".parse().unwrap())
+                .build();
+            BgpMessage::Update(update)
+        };
+        let list = |s: &str| -> Arc<dyn RouteFilter> { Arc::new(s.parse::<PrefixList>().unwrap()) };
+        // Applies events to the RIB up to the next EoRR or, without one, the next UPDATE
+        async fn apply(
+            events: &mut mpsc::Receiver<SessionEvent>,
+            rib: &mut RibIn,
+            until_eorr: bool,
+        ) {
+            while let Some(event) = events.recv().await {
+                match event {
+                    SessionEvent::Update(_, update) => {
+                        rib.apply(&update.value);
+                        if !until_eorr {
+                            return;
+                        }
+                    }
+                    SessionEvent::RouteRefresh(_, route_refresh) => {
+                        rib.apply_route_refresh(&route_refresh);
+                        if route_refresh.subtype == RouteRefreshSubtype::End {
+                            return;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut rib = RibIn::new().with_filter(list("permit 10.0.0.0/8 le 24"));
+        remote
+            .send(announce(&["10.1.0.0/16", "10.2.0.0/16", "192.0.2.0/24"]))
+            .await
+            .unwrap();
+        apply(&mut events, &mut rib, false).await;
+        assert_eq!(rib.len(), 2);
+
+        assert_eq!(
+            rib.set_filter(list("permit 10.1.0.0/16\npermit 192.0.2.0/24"))
+                .len(),
+            1
+        );
+        let completion = refresher
+            .request_refresh(Afi::Ipv4, Safi::Unicast)
+            .await
+            .unwrap()
+            .unwrap();
+        // The peer lost 10.1.0.0/16 meanwhile without us seeing the withdrawal
+        assert!(matches!(
+            recv_skipping_keepalives(&mut remote).await,
+            Some(BgpMessage::RouteRefresh(RouteRefreshMessage {
+                subtype: RouteRefreshSubtype::Request,
+                ..
+            }))
+        ));
+        for message in [
+            marker(RouteRefreshSubtype::Begin),
+            announce(&["10.2.0.0/16", "192.0.2.0/24"]),
+            marker(RouteRefreshSubtype::End),
+        ] {
+            remote.send(message).await.unwrap();
+        }
+        completion.await.unwrap();
+        apply(&mut events, &mut rib, true).await;
+
+        let prefixes: Vec<String> = rib.iter().map(|(key, _)| key.prefix.to_string()).collect();
+        assert_eq!(prefixes, ["192.0.2.0/24"]);
+        assert_eq!(rib.stale_count(), 0);
+
+        drop(remote);
+        assert!(running.await.unwrap().is_err());
+        assert!(matches!(
+            refresher.request_refresh(Afi::Ipv4, Safi::Unicast).await,
+            Err(SessionError::SessionClosed)
+        ));
+    }
+
+    async fn recv_skipping_keepalives(session: &mut EstablishedSession) -> Option<BgpMessage> {
+        loop {
+            match session.recv().await {
+                Some(BgpMessage::Keepalive) => continue,
+                message => return message,
+            }
+        }
+    }
+
+    fn marker(subtype: RouteRefreshSubtype) -> BgpMessage {
+        BgpMessage::RouteRefresh(RouteRefreshMessage {
+            afi: Afi::Ipv4,
+            safi: Safi::Unicast,
+            subtype,
+        })
+    }
+
+    fn beacon() -> BgpMessage {
+        BgpMessage::Update(crate::update_message::UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        })
+    }
+}
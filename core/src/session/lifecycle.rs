@@ -0,0 +1,143 @@
+use crate::notification_message::{CeaseSubErr, NotificationErrorCode, NotificationMessage};
+use crate::peer::shutdown::encode_communication;
+use crate::peer::{Negotiated, PeerDown, PeerDownReason, PeerInfo, SessionEnd};
+
+use super::error::SessionError;
+
+impl PeerDown {
+    pub fn new(
+        peer: PeerInfo,
+        end: Result<&SessionEnd, &SessionError>,
+        negotiated: &Negotiated,
+    ) -> Self {
+        PeerDown {
+            peer,
+            reason: PeerDownReason::from_end(end),
+            graceful_restart: negotiated.graceful_restart,
+        }
+    }
+}
+
+impl PeerDownReason {
+    /// Interprets how an established session ended
+    pub fn from_end(end: Result<&SessionEnd, &SessionError>) -> Self {
+        match end {
+            Ok(SessionEnd::LocalShutdown(reason)) => PeerDownReason::LocalShutdown(reason.clone()),
+            Ok(SessionEnd::Closed) => PeerDownReason::LocalClose,
+            Ok(SessionEnd::RemoteCease {
+                subcode: Some(CeaseSubErr::ConnectionCollisionResolution),
+                ..
+            }) => PeerDownReason::CollisionResolution,
+            Ok(SessionEnd::RemoteCease { subcode, message }) => {
+                PeerDownReason::RemoteNotification(cease(*subcode, message.as_deref()))
+            }
+            Err(SessionError::Notification(notification)) => {
+                PeerDownReason::RemoteNotification(notification.clone())
+            }
+            Err(SessionError::HoldTimerExpired) => PeerDownReason::HoldTimerExpired,
+            Err(SessionError::Decode(err)) => match err.notification() {
+                Some(notification) => PeerDownReason::LocalError(notification),
+                // Closed without a NOTIFICATION, as a broken one from the peer isn't answered
+                None => PeerDownReason::LocalClose,
+            },
+            Err(SessionError::Io(_) | SessionError::ConnectionClosed) => {
+                PeerDownReason::ConnectionLost
+            }
+            Err(_) => PeerDownReason::LocalClose,
+        }
+    }
+}
+
+/// Re-encodes a Cease the peer sent, see [`SessionEnd::from_notification`]
+fn cease(subcode: Option<CeaseSubErr>, message: Option<&str>) -> NotificationMessage {
+    let error_codes = match subcode {
+        Some(subcode) => NotificationErrorCode::Cease(subcode),
+        None => NotificationErrorCode::Unknown(6, 0),
+    };
+    let data = match message {
+        Some(message) => encode_communication(message),
+        None => vec![],
+    };
+    NotificationMessage::new(error_codes, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+
+    use crate::bgp_message::MessageDecodeError;
+    use crate::notification_message::{NotificationParseError, UpdateMessageSubErr};
+    use crate::peer::ShutdownReason;
+
+    #[test]
+    fn test_down_reasons() {
+        let admin = ShutdownReason::AdministrativeShutdown(Some("maintenance".to_string()));
+        let reason = PeerDownReason::from_end(Ok(&SessionEnd::LocalShutdown(admin.clone())));
+        assert_eq!(reason, PeerDownReason::LocalShutdown(admin.clone()));
+        assert_eq!(
+            (reason.bmp_code(), reason.notification()),
+            (1, Some(admin.notification()))
+        );
+
+        let reason = PeerDownReason::from_end(Err(&SessionError::HoldTimerExpired));
+        assert_eq!(reason.bmp_code(), 1);
+        assert_eq!(
+            reason.notification().unwrap().error_codes,
+            NotificationErrorCode::HoldTimeExpired
+        );
+
+        let err = MessageDecodeError::KeepaliveLength(3);
+        let reason = PeerDownReason::from_end(Err(&SessionError::Decode(err.clone())));
+        assert_eq!(
+            reason,
+            PeerDownReason::LocalError(err.notification().unwrap())
+        );
+        assert_eq!(reason.bmp_code(), 1);
+
+        let reason = PeerDownReason::from_end(Ok(&SessionEnd::Closed));
+        assert_eq!((reason.bmp_code(), reason.notification()), (2, None));
+
+        // A broken NOTIFICATION from the peer is answered by closing the connection
+        let err = MessageDecodeError::Notification(NotificationParseError::MessageLength(1));
+        let reason = PeerDownReason::from_end(Err(&SessionError::Decode(err)));
+        assert_eq!(reason, PeerDownReason::LocalClose);
+
+        // Cease NOTIFICATIONs arrive decoded, anything else as an error
+        let remote = SessionEnd::RemoteCease {
+            subcode: Some(CeaseSubErr::AdministrativeReset),
+            message: Some("upgrade".to_string()),
+        };
+        let reason = PeerDownReason::from_end(Ok(&remote));
+        assert_eq!(reason.bmp_code(), 3);
+        assert_eq!(
+            SessionEnd::from_notification(&reason.notification().unwrap()),
+            Some(remote)
+        );
+        let notification = NotificationMessage::new(
+            NotificationErrorCode::UpdateMessage(UpdateMessageSubErr::MalformedAsPath),
+            vec![],
+        );
+        let reason =
+            PeerDownReason::from_end(Err(&SessionError::Notification(notification.clone())));
+        assert_eq!(reason, PeerDownReason::RemoteNotification(notification));
+
+        let collision = SessionEnd::RemoteCease {
+            subcode: Some(CeaseSubErr::ConnectionCollisionResolution),
+            message: None,
+        };
+        let reason = PeerDownReason::from_end(Ok(&collision));
+        assert_eq!(reason, PeerDownReason::CollisionResolution);
+        assert_eq!(reason.bmp_code(), 3);
+
+        for err in [
+            SessionError::ConnectionClosed,
+            SessionError::Io(Arc::new(io::Error::from(io::ErrorKind::ConnectionReset))),
+        ] {
+            let reason = PeerDownReason::from_end(Err(&err));
+            assert_eq!(reason, PeerDownReason::ConnectionLost);
+            assert_eq!((reason.bmp_code(), reason.notification()), (4, None));
+        }
+    }
+}
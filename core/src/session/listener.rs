@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{self as tokio_io, AsyncWriteExt};
+use tokio::net::{self, TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::bgp_message::BgpMessage;
+use crate::notification_message::{CeaseSubErr, NotificationErrorCode, NotificationMessage};
+
+use super::codec::write_message;
+use super::config::PeerConfig;
+use super::connector::establish;
+use super::error::SessionError;
+use super::established::{Command, EstablishedSession};
+use super::socket::{listen_socket, set_ttl_security};
+
+const SESSION_QUEUE: usize = 64;
+const REJECT_DRAIN: Duration = Duration::from_secs(1);
+
+type Established = Arc<Mutex<HashMap<IpAddr, mpsc::WeakSender<Command>>>>;
+
+/// Passive side of BGP sessions, accepting connections from configured peers
+#[derive(Debug)]
+pub struct BgpListener {
+    local_addr: SocketAddr,
+    sessions: mpsc::Receiver<EstablishedSession>,
+    task: JoinHandle<()>,
+}
+
+impl BgpListener {
+    /// Binds the listener and starts accepting connections from the given peers.
+    ///
+    /// Connections from unknown addresses are refused with Cease/Connection Rejected and a second
+    /// connection from a peer with an established session is refused with Cease/Connection
+    /// Collision Resolution.
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        peers: impl IntoIterator<Item = PeerConfig>,
+    ) -> Result<Self, SessionError> {
+        let peers: HashMap<IpAddr, PeerConfig> = peers
+            .into_iter()
+            .map(|config| (config.remote_addr.to_canonical(), config))
+            .collect();
+        let addr = net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+        let listener = listen_socket(addr, peers.values())?;
+        let local_addr = listener.local_addr()?;
+
+        let (sessions_tx, sessions) = mpsc::channel(SESSION_QUEUE);
+        let task = tokio::spawn(accept_loop(listener, Arc::new(peers), sessions_tx));
+
+        Ok(BgpListener {
+            local_addr,
+            sessions,
+            task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Waits for the next session to complete the OPEN exchange
+    pub async fn accept(&mut self) -> Option<EstablishedSession> {
+        self.sessions.recv().await
+    }
+}
+
+impl Drop for BgpListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    peers: Arc<HashMap<IpAddr, PeerConfig>>,
+    sessions: mpsc::Sender<EstablishedSession>,
+) {
+    let established: Established = Arc::default();
+
+    while let Ok((stream, remote)) = listener.accept().await {
+        let peers = peers.clone();
+        let established = established.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let remote_ip = remote.ip().to_canonical();
+            let Some(config) = peers.get(&remote_ip) else {
+                reject(stream, CeaseSubErr::ConnectionRejected).await;
+                return;
+            };
+
+            // Set before the OPEN exchange so every later segment from the peer is TTL checked
+            if let Some(min_ttl) = config.ttl_security
+                && set_ttl_security(&stream, remote_ip.is_ipv6(), min_ttl).is_err()
+            {
+                return;
+            }
+
+            if is_established(&established, remote_ip) {
+                reject(stream, CeaseSubErr::ConnectionCollisionResolution).await;
+                return;
+            }
+
+            if let Ok(session) = establish(stream, config).await {
+                // Another connection from the same peer may have won the race
+                if register(&established, remote_ip, &session) {
+                    let _ = sessions.send(session).await;
+                }
+            }
+        });
+    }
+}
+
+fn is_established(established: &Established, remote_ip: IpAddr) -> bool {
+    let mut established = established.lock().unwrap();
+    match established.get(&remote_ip) {
+        Some(liveness) if is_alive(liveness) => true,
+        Some(_) => {
+            established.remove(&remote_ip);
+            false
+        }
+        None => false,
+    }
+}
+
+fn register(established: &Established, remote_ip: IpAddr, session: &EstablishedSession) -> bool {
+    let mut established = established.lock().unwrap();
+    if established.get(&remote_ip).is_some_and(is_alive) {
+        return false;
+    }
+    established.insert(remote_ip, session.liveness());
+    true
+}
+
+fn is_alive(liveness: &mpsc::WeakSender<Command>) -> bool {
+    liveness
+        .upgrade()
+        .is_some_and(|outbound| !outbound.is_closed())
+}
+
+pub(crate) async fn reject(mut stream: TcpStream, sub_err: CeaseSubErr) {
+    let notification = NotificationMessage::new(NotificationErrorCode::Cease(sub_err), vec![]);
+    let _ = write_message(&mut stream, &BgpMessage::Notification(notification)).await;
+
+    // Closing with the peer's OPEN still unread would reset the connection and could discard
+    // the NOTIFICATION, so half-close and drain until the peer hangs up
+    let _ = stream.shutdown().await;
+    let _ = time::timeout(
+        REJECT_DRAIN,
+        tokio_io::copy(&mut stream, &mut tokio_io::sink()),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpSocket;
+
+    use crate::session::{Peer, SessionError};
+
+    fn config(remote_addr: Ipv4Addr, local_asn: u32, router_id: Ipv4Addr) -> PeerConfig {
+        PeerConfig::new(IpAddr::V4(remote_addr), local_asn, router_id)
+    }
+
+    fn collector_peers() -> Vec<PeerConfig> {
+        vec![
+            config(
+                Ipv4Addr::new(127, 0, 0, 1),
+                65000,
+                Ipv4Addr::new(10, 0, 0, 100),
+            ),
+            config(
+                Ipv4Addr::new(127, 0, 0, 2),
+                65000,
+                Ipv4Addr::new(10, 0, 0, 100),
+            ),
+        ]
+    }
+
+    fn router(listener: &BgpListener, router_id: Ipv4Addr) -> PeerConfig {
+        let mut config = config(Ipv4Addr::LOCALHOST, 65010, router_id);
+        config.remote_port = listener.local_addr().port();
+        config.remote_asn = Some(65000);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_listener_accepts_connector() {
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), collector_peers())
+            .await
+            .unwrap();
+
+        let router_id = Ipv4Addr::new(10, 0, 0, 1);
+        let active = Peer::connect(router(&listener, router_id)).await.unwrap();
+        let passive = listener.accept().await.unwrap();
+
+        assert_eq!(passive.remote_open().bgp_id, router_id);
+        assert_eq!(active.remote_open().asn(), 65000);
+    }
+
+    #[tokio::test]
+    async fn test_listener_rejects_unknown_peer() {
+        let peers = vec![config(
+            Ipv4Addr::new(127, 0, 0, 9),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 100),
+        )];
+        let listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), peers)
+            .await
+            .unwrap();
+
+        let err = Peer::connect(router(&listener, Ipv4Addr::new(10, 0, 0, 1)))
+            .await
+            .unwrap_err();
+        match err {
+            SessionError::Notification(notification) => assert_eq!(
+                notification.error_codes,
+                NotificationErrorCode::Cease(CeaseSubErr::ConnectionRejected)
+            ),
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listener_rejects_second_connection() {
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), collector_peers())
+            .await
+            .unwrap();
+
+        let config = router(&listener, Ipv4Addr::new(10, 0, 0, 1));
+        let _first = Peer::connect(config.clone()).await.unwrap();
+        let _accepted = listener.accept().await.unwrap();
+
+        match Peer::connect(config).await.unwrap_err() {
+            SessionError::Notification(notification) => assert_eq!(
+                notification.error_codes,
+                NotificationErrorCode::Cease(CeaseSubErr::ConnectionCollisionResolution)
+            ),
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listener_multiple_peers() {
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), collector_peers())
+            .await
+            .unwrap();
+
+        let mut dialers = Vec::new();
+        for (source, router_id) in [
+            (Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 1)),
+            (Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 2)),
+        ] {
+            let config = router(&listener, router_id);
+            dialers.push(tokio::spawn(async move {
+                let socket = TcpSocket::new_v4()?;
+                socket.bind(SocketAddr::new(IpAddr::V4(source), 0))?;
+                let stream = socket.connect(config.remote_socket_addr()).await?;
+                establish(stream, &config).await
+            }));
+        }
+
+        let mut router_ids = vec![
+            listener.accept().await.unwrap().remote_open().bgp_id,
+            listener.accept().await.unwrap().remote_open().bgp_id,
+        ];
+        router_ids.sort();
+        assert_eq!(
+            router_ids,
+            vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+        for dialer in dialers {
+            assert!(dialer.await.unwrap().is_ok());
+        }
+    }
+}
@@ -0,0 +1,1511 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::net::{self, TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::address_family::{Afi, Safi};
+use crate::bgp_message::BgpMessage;
+use crate::bmp::{
+    BmpMessage, BmpStation, InformationTlv, PeerHeader, StationEvent, TerminationReason,
+    TerminationTlv,
+};
+use crate::monitor::{PeerMonitor, PeerMonitorSnapshot};
+use crate::notification_message::{CeaseSubErr, NotificationMessage};
+use crate::route_refresh_message::RouteRefreshMessage;
+use crate::timestamped::Timestamped;
+use crate::update_message::UpdateMessage;
+
+use super::backoff::{Backoff, BackoffStatus};
+use super::bus::{BusEvent, EventBus};
+use super::config::{BackoffConfig, ConfigError, MaxPrefixAction, PeerConfig};
+use super::connector::{Peer, establish};
+use super::error::SessionError;
+use super::established::{RefreshCompletion, Refresher};
+use super::listener::reject;
+use super::observer::{SessionObserver, dispatch};
+use super::prefix_limit::MaxPrefixEvent;
+use super::replay::ReplaySource;
+use super::socket::{add_listener_peer, listen_socket, set_ttl_security};
+use super::stats::{SessionStats, UpdateRates};
+use crate::peer::{
+    Negotiated, PeerDown, PeerDownReason, PeerInfo, PeerUp, SessionEnd, ShutdownReason,
+};
+
+type Peers = Arc<Mutex<HashMap<IpAddr, ManagedPeer>>>;
+
+/// A peer monitored through BMP: the router, the peer's address and its route distinguisher
+type VirtualKey = (IpAddr, IpAddr, u64);
+
+/// Supervises the sessions of many peers, reconnecting active peers per their
+/// [`BackoffConfig`] and accepting passive peers once [`PeerManager::listen`] was called.
+///
+/// Every peer's traffic is reported to the shared observer. Dropping the manager stops all
+/// sessions without notifying the peers; use [`PeerManager::remove_peer`] for a clean Cease.
+///
+/// [`BackoffConfig`]: super::BackoffConfig
+pub struct PeerManager {
+    observer: Arc<dyn SessionObserver>,
+    bus: Option<Arc<EventBus>>,
+    peers: Peers,
+    listener: Option<(Arc<TcpListener>, JoinHandle<()>)>,
+    bmp: Arc<Mutex<BmpState>>,
+    stations: Vec<JoinHandle<()>>,
+}
+
+struct ManagedPeer {
+    config: PeerConfig,
+    status: Arc<Mutex<PeerStatus>>,
+    /// Connections accepted for a passive peer
+    incoming: Option<mpsc::Sender<TcpStream>>,
+    stop: oneshot::Sender<ShutdownReason>,
+    task: JoinHandle<()>,
+}
+
+/// Connection state of a managed peer, after the RFC 4271 FSM states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// Waiting out the backoff delay, or given up when [`BackoffStatus::down`] is set
+    Idle,
+    /// Connecting or exchanging OPENs
+    Connect,
+    /// Waiting for a passive peer to connect
+    Active,
+    Established,
+}
+
+/// Messages received from a peer across all of its sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageCounters {
+    pub updates: u64,
+    pub notifications: u64,
+    pub keepalives: u64,
+    pub route_refreshes: u64,
+    /// How often a session with the peer was established
+    pub sessions: u64,
+}
+
+/// Point in time view of a managed peer, see [`PeerManager::snapshot`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSnapshot {
+    pub remote_addr: IpAddr,
+    /// The BMP router of a peer monitored through a [`BmpStation`]
+    pub router: Option<IpAddr>,
+    pub passive: bool,
+    pub state: PeerState,
+    /// The peer behind the current session
+    pub peer: Option<PeerInfo>,
+    /// Time since the current session was established
+    pub uptime: Option<Duration>,
+    pub counters: MessageCounters,
+    pub backoff: BackoffStatus,
+}
+
+/// A router that connected to a BMP station, see [`PeerManager::bmp_routers`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BmpRouter {
+    pub addr: IpAddr,
+    pub connected: bool,
+    pub sys_name: Option<String>,
+    pub sys_descr: Option<String>,
+    /// Free-form strings of the Initiation and Termination messages
+    pub information: Vec<String>,
+    /// Why the router ended its last feed
+    pub termination: Option<TerminationReason>,
+}
+
+#[derive(Default)]
+struct BmpState {
+    routers: HashMap<IpAddr, BmpRouter>,
+    peers: HashMap<VirtualKey, Arc<Mutex<PeerStatus>>>,
+}
+
+#[derive(Debug)]
+struct PeerStatus {
+    state: PeerState,
+    peer: Option<PeerInfo>,
+    established_at: Option<Instant>,
+    counters: MessageCounters,
+    backoff: Backoff,
+    /// Statistics of the current or, once it closed, the last session
+    monitor: Option<PeerMonitor>,
+    /// Traffic counters of the current or last session
+    stats: Option<SessionStats>,
+    /// Requests route refreshes of the current session, `None` for replayed and BMP peers
+    refresher: Option<Refresher>,
+}
+
+impl PeerManager {
+    pub fn new(observer: impl SessionObserver + 'static) -> Self {
+        PeerManager {
+            observer: Arc::new(observer),
+            bus: None,
+            peers: Arc::default(),
+            listener: None,
+            bmp: Arc::default(),
+            stations: vec![],
+        }
+    }
+
+    /// Publishes a [`PeerUp`] and a [`PeerDown`] on `bus` for every session of the peers added
+    /// afterwards
+    pub fn with_event_bus(mut self, bus: Arc<EventBus>) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Starts accepting connections from passive peers, returning the bound address.
+    ///
+    /// Connections from unknown or active peers are refused with Cease/Connection Rejected, a
+    /// second connection from a peer with a running session with Cease/Connection Collision
+    /// Resolution.
+    pub async fn listen(&mut self, addr: impl ToSocketAddrs) -> Result<SocketAddr, SessionError> {
+        let addr = net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+        let listener = {
+            let peers = self.peers.lock().unwrap();
+            listen_socket(addr, peers.values().map(|managed| &managed.config))?
+        };
+        let local_addr = listener.local_addr()?;
+
+        let listener = Arc::new(listener);
+        let task = tokio::spawn(accept_loop(listener.clone(), self.peers.clone()));
+        if let Some((_, previous)) = self.listener.replace((listener, task)) {
+            previous.abort();
+        }
+        Ok(local_addr)
+    }
+
+    /// Starts supervising a peer: active peers are dialed right away, passive peers wait for
+    /// their connection to the listener
+    pub fn add_peer(&mut self, config: PeerConfig) -> Result<(), ConfigError> {
+        if let Some((listener, _)) = &self.listener {
+            add_listener_peer(listener, &config)?;
+        }
+        let (incoming, incoming_rx) = if config.passive {
+            let (sender, receiver) = mpsc::channel(1);
+            (Some(sender), Some(receiver))
+        } else {
+            (None, None)
+        };
+        self.register(config, incoming, |supervisor, stop| {
+            supervisor.run(incoming_rx, stop)
+        })
+    }
+
+    /// Feeds a peer from recorded traffic instead of a TCP session, so everything downstream
+    /// can be exercised against a capture.
+    ///
+    /// Only the messages `source` hands out for `config.remote_addr` are used, see
+    /// [`ReplaySource::map_peer`]. The peer is established with its first message and each
+    /// recorded NOTIFICATION or OPEN starts a new session; once the recording runs out the
+    /// session closes and the peer stays idle.
+    pub fn add_replay(
+        &mut self,
+        config: PeerConfig,
+        source: ReplaySource,
+    ) -> Result<(), ConfigError> {
+        self.register(config, None, |supervisor, stop| {
+            supervisor.replay(source, stop)
+        })
+    }
+
+    /// Feeds the peers that routers monitor through `station` to the observer and event bus as
+    /// virtual peers, one for each router and peer.
+    ///
+    /// A Peer Up establishes a virtual peer, Route Monitoring passes on its UPDATEs and a Peer
+    /// Down closes it; Route Monitoring before a Peer Up establishes the peer as well. When a
+    /// router disconnects or connects again, all of its peers go down as if their connections
+    /// were lost.
+    pub fn add_bmp_station(&mut self, station: BmpStation) {
+        let feed = BmpFeed {
+            observer: self.observer.clone(),
+            bus: self.bus.clone(),
+            state: self.bmp.clone(),
+            post_policy: station.is_post_policy(),
+            sessions: HashMap::new(),
+        };
+        self.stations.push(tokio::spawn(feed.run(station)));
+    }
+
+    /// Every router that connected to a BMP station, ordered by address
+    pub fn bmp_routers(&self) -> Vec<BmpRouter> {
+        let mut routers: Vec<BmpRouter> =
+            self.bmp.lock().unwrap().routers.values().cloned().collect();
+        routers.sort_by_key(|router| router.addr);
+        routers
+    }
+
+    fn register<F: Future<Output = ()> + Send + 'static>(
+        &mut self,
+        config: PeerConfig,
+        incoming: Option<mpsc::Sender<TcpStream>>,
+        run: impl FnOnce(Supervisor, oneshot::Receiver<ShutdownReason>) -> F,
+    ) -> Result<(), ConfigError> {
+        let key = config.remote_addr.to_canonical();
+        let mut peers = self.peers.lock().unwrap();
+        if peers.contains_key(&key) {
+            return Err(ConfigError::DuplicatePeer(config.remote_addr));
+        }
+
+        let status = Arc::new(Mutex::new(PeerStatus::new(config.backoff)));
+        let (stop, stop_rx) = oneshot::channel();
+        let supervisor = Supervisor {
+            config: config.clone(),
+            observer: self.observer.clone(),
+            bus: self.bus.clone(),
+            status: status.clone(),
+        };
+        let task = tokio::spawn(run(supervisor, stop_rx));
+
+        peers.insert(
+            key,
+            ManagedPeer {
+                config,
+                status,
+                incoming,
+                stop,
+                task,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stops supervising a peer, closing any running session with Cease/Peer De-configured.
+    ///
+    /// Returns `false` when the peer wasn't configured.
+    pub async fn remove_peer(&mut self, peer: IpAddr) -> bool {
+        let managed = self.peers.lock().unwrap().remove(&peer.to_canonical());
+        let Some(managed) = managed else {
+            return false;
+        };
+
+        let _ = managed.stop.send(ShutdownReason::PeerDeconfigured);
+        let _ = managed.task.await;
+        true
+    }
+
+    /// Asks an established peer to re-advertise a family, for example after the filter of its
+    /// RIB changed; see [`Refresher::request_refresh`].
+    ///
+    /// Fails with [`SessionError::SessionClosed`] while the peer isn't established.
+    pub async fn request_refresh(
+        &self,
+        peer: IpAddr,
+        afi: Afi,
+        safi: Safi,
+    ) -> Result<Option<RefreshCompletion>, SessionError> {
+        let refresher = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(&peer.to_canonical())
+            .and_then(|managed| managed.status.lock().unwrap().refresher.clone())
+            .ok_or(SessionError::SessionClosed)?;
+        refresher.request_refresh(afi, safi).await
+    }
+
+    /// Reconnection state of a peer, for status reporting
+    pub fn backoff(&self, peer: IpAddr) -> Option<BackoffStatus> {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(&peer.to_canonical())
+            .map(|managed| managed.status.lock().unwrap().backoff.status())
+    }
+
+    /// State of every managed peer
+    pub fn snapshot(&self) -> Vec<PeerSnapshot> {
+        let peers = self.peers.lock().unwrap();
+        let mut snapshot: Vec<PeerSnapshot> = peers
+            .values()
+            .map(|managed| {
+                let status = managed.status.lock().unwrap();
+                status.snapshot(managed.config.remote_addr, managed.config.passive, None)
+            })
+            .collect();
+        let bmp = self.bmp.lock().unwrap();
+        snapshot.extend(bmp.peers.iter().map(|(&(router, addr, _), status)| {
+            status.lock().unwrap().snapshot(addr, false, Some(router))
+        }));
+        snapshot.sort_by_key(|peer| (peer.remote_addr, peer.router));
+        snapshot
+    }
+
+    /// Monitoring statistics of every peer that has been established, ordered by address
+    pub fn monitoring(&self) -> Vec<PeerMonitorSnapshot> {
+        let now = Instant::now().into_std();
+        let mut snapshot: Vec<PeerMonitorSnapshot> = self
+            .statuses()
+            .iter()
+            .filter_map(|status| {
+                let status = status.lock().unwrap();
+                status.monitor.as_ref().map(|monitor| monitor.snapshot(now))
+            })
+            .collect();
+        snapshot.sort_by_key(|peer| peer.peer);
+        snapshot
+    }
+
+    /// Traffic counters of the peer's current session, or of the last one once it closed
+    pub fn stats(&self, peer: IpAddr) -> Option<SessionStats> {
+        let peers = self.peers.lock().unwrap();
+        let managed = peers.get(&peer.to_canonical())?;
+        managed.status.lock().unwrap().stats.clone()
+    }
+
+    /// UPDATE traffic received from all peers together, over each of the [`RATE_WINDOWS`]
+    ///
+    /// [`RATE_WINDOWS`]: super::RATE_WINDOWS
+    pub fn rates(&self) -> Vec<UpdateRates> {
+        let now = Instant::now().into_std();
+        let statuses = self.statuses();
+        let stats = statuses
+            .iter()
+            .filter_map(|status| status.lock().unwrap().stats.clone());
+        stats.fold(SessionStats::new().rates_at(now), |mut total, stats| {
+            for (total, rates) in total.iter_mut().zip(stats.rates_at(now)) {
+                total.updates += rates.updates;
+                total.withdrawals += rates.withdrawals;
+                total.prefixes_changed += rates.prefixes_changed;
+            }
+            total
+        })
+    }
+
+    /// The status of every managed and virtual peer
+    fn statuses(&self) -> Vec<Arc<Mutex<PeerStatus>>> {
+        let peers = self.peers.lock().unwrap();
+        let bmp = self.bmp.lock().unwrap();
+        let managed = peers.values().map(|managed| managed.status.clone());
+        managed.chain(bmp.peers.values().cloned()).collect()
+    }
+}
+
+impl Drop for PeerManager {
+    fn drop(&mut self) {
+        if let Some((_, task)) = &self.listener {
+            task.abort();
+        }
+        for task in &self.stations {
+            task.abort();
+        }
+        for managed in self.peers.lock().unwrap().values() {
+            managed.task.abort();
+        }
+    }
+}
+
+/// Hands accepted connections to the supervisor of the passive peer they come from
+async fn accept_loop(listener: Arc<TcpListener>, peers: Peers) {
+    while let Ok((stream, remote)) = listener.accept().await {
+        let remote_ip = remote.ip().to_canonical();
+        let rejection = {
+            let peers = peers.lock().unwrap();
+            match peers.get(&remote_ip) {
+                Some(ManagedPeer {
+                    config,
+                    status,
+                    incoming: Some(incoming),
+                    ..
+                }) => {
+                    let secured = match config.ttl_security {
+                        Some(min_ttl) => {
+                            set_ttl_security(&stream, remote_ip.is_ipv6(), min_ttl).is_ok()
+                        }
+                        None => true,
+                    };
+                    if !secured {
+                        continue;
+                    }
+                    if status.lock().unwrap().state != PeerState::Active {
+                        Some((stream, CeaseSubErr::ConnectionCollisionResolution))
+                    } else {
+                        match incoming.try_send(stream) {
+                            Ok(()) => None,
+                            Err(err) => {
+                                Some((err.into_inner(), CeaseSubErr::ConnectionCollisionResolution))
+                            }
+                        }
+                    }
+                }
+                _ => Some((stream, CeaseSubErr::ConnectionRejected)),
+            }
+        };
+
+        if let Some((stream, sub_err)) = rejection {
+            tokio::spawn(reject(stream, sub_err));
+        }
+    }
+}
+
+/// Runs the sessions of a single peer
+struct Supervisor {
+    config: PeerConfig,
+    observer: Arc<dyn SessionObserver>,
+    bus: Option<Arc<EventBus>>,
+    status: Arc<Mutex<PeerStatus>>,
+}
+
+impl Supervisor {
+    /// Connects, runs the session and starts over until the peer is removed or retries run out
+    async fn run(
+        self,
+        mut incoming: Option<mpsc::Receiver<TcpStream>>,
+        mut stop: oneshot::Receiver<ShutdownReason>,
+    ) {
+        loop {
+            let connect = async {
+                match incoming.as_mut() {
+                    Some(incoming) => {
+                        self.set_state(PeerState::Active);
+                        let stream = incoming.recv().await.ok_or(SessionError::SessionClosed)?;
+                        self.set_state(PeerState::Connect);
+                        establish(stream, &self.config).await
+                    }
+                    None => {
+                        self.set_state(PeerState::Connect);
+                        Peer::connect(self.config.clone()).await
+                    }
+                }
+            };
+            let connected = tokio::select! {
+                connected = connect => connected,
+                _ = &mut stop => return,
+            };
+
+            let end = match connected {
+                Ok(session) => {
+                    let peer = session.peer_info();
+                    self.established(peer, session.stats());
+                    self.status.lock().unwrap().refresher = Some(session.refresher());
+                    let negotiated = session.negotiated().clone();
+                    self.publish(BusEvent::PeerUp(PeerUp {
+                        peer,
+                        negotiated: negotiated.clone(),
+                        local_open: session.local_open().clone(),
+                        remote_open: session.remote_open().clone(),
+                    }))
+                    .await;
+                    let observer = CountingObserver {
+                        status: self.status.clone(),
+                        observer: self.observer.clone(),
+                    };
+                    let stop = async {
+                        (&mut stop)
+                            .await
+                            .unwrap_or(ShutdownReason::PeerDeconfigured)
+                    };
+                    let end = session.run_until(observer, stop).await;
+                    let down = PeerDown::new(peer, end.as_ref(), &negotiated);
+                    self.publish(BusEvent::PeerDown(down)).await;
+                    end
+                }
+                Err(err) => Err(err),
+            };
+            self.closed();
+            match end {
+                // Without a restart delay the peer stays down until it is added again
+                Ok(SessionEnd::LocalShutdown(ShutdownReason::MaximumPrefixes { .. })) => {
+                    let Some(delay) = self.restart_after() else {
+                        return;
+                    };
+                    tokio::select! {
+                        () = time::sleep(delay) => continue,
+                        _ = &mut stop => return,
+                    }
+                }
+                Ok(SessionEnd::LocalShutdown(_)) => return,
+                _ => {}
+            }
+
+            // Passive peers simply wait for their next connection
+            if self.config.passive {
+                continue;
+            }
+            let delay = self.status.lock().unwrap().backoff.fail(Instant::now());
+            let Some(delay) = delay else {
+                let attempts = self.status.lock().unwrap().backoff.status().attempts;
+                self.observer
+                    .on_retries_exhausted(self.config.remote_socket_addr(), attempts);
+                return;
+            };
+            tokio::select! {
+                () = time::sleep(delay) => self.status.lock().unwrap().backoff.retrying(),
+                _ = &mut stop => return,
+            }
+        }
+    }
+
+    /// Hands the recorded messages of the peer to the observer as sessions would
+    async fn replay(self, mut source: ReplaySource, mut stop: oneshot::Receiver<ShutdownReason>) {
+        let addr = self.config.remote_addr.to_canonical();
+        let observer = CountingObserver {
+            status: self.status.clone(),
+            observer: self.observer.clone(),
+        };
+        let mut open = None;
+        let mut session: Option<(PeerInfo, SessionStats)> = None;
+
+        let end = loop {
+            let next = tokio::select! {
+                next = source.next() => next,
+                reason = &mut stop => {
+                    let reason = reason.unwrap_or(ShutdownReason::PeerDeconfigured);
+                    break Ok(SessionEnd::LocalShutdown(reason));
+                }
+            };
+            let message = match next {
+                Some(Ok((peer, message))) if peer.to_canonical() == addr => message,
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => break Err(SessionError::Replay(Arc::new(err))),
+                None => break Ok(SessionEnd::Closed),
+            };
+
+            if let BgpMessage::Open(message) = &message.value {
+                if let Some((peer, _)) = session.take() {
+                    observer.on_close(&peer, Ok(&SessionEnd::Closed));
+                    self.closed();
+                }
+                open = Some((message.asn(), message.bgp_id));
+                continue;
+            }
+            let (peer, stats) = session.get_or_insert_with(|| {
+                let (asn, router_id) =
+                    open.unwrap_or((self.config.remote_asn.unwrap_or(0), Ipv4Addr::UNSPECIFIED));
+                let peer = PeerInfo {
+                    peer_addr: self.config.remote_socket_addr(),
+                    asn,
+                    router_id,
+                    router: None,
+                };
+                let stats = SessionStats::new();
+                self.established(peer, stats.clone());
+                observer.on_established(&peer);
+                (peer, stats)
+            });
+            let peer = *peer;
+            stats.record_received(&message, message.to_bytes().len());
+
+            let notification = match &message.value {
+                BgpMessage::Notification(notification) => Some(notification.clone()),
+                _ => None,
+            };
+            dispatch(&observer, &peer, message);
+            if let Some(notification) = notification {
+                let end = SessionEnd::from_notification(&notification)
+                    .ok_or(SessionError::Notification(notification));
+                observer.on_close(&peer, end.as_ref());
+                self.closed();
+                session = None;
+                open = None;
+            }
+        };
+        if let Some((peer, _)) = session {
+            observer.on_close(&peer, end.as_ref());
+            self.closed();
+        }
+    }
+
+    async fn publish(&self, event: BusEvent) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Timestamped::now(event)).await;
+        }
+    }
+
+    /// How long to wait before reconnecting after the peer exceeded its prefix limit
+    fn restart_after(&self) -> Option<Duration> {
+        match self.config.max_prefixes?.action {
+            MaxPrefixAction::Teardown { restart_after } => restart_after,
+            MaxPrefixAction::WarnOnly => None,
+        }
+    }
+
+    fn set_state(&self, state: PeerState) {
+        let mut status = self.status.lock().unwrap();
+        self.transition(status.state, state, self.config.remote_asn);
+        status.state = state;
+    }
+
+    fn established(&self, peer: PeerInfo, stats: SessionStats) {
+        let mut status = self.status.lock().unwrap();
+        self.transition(status.state, PeerState::Established, Some(peer.asn));
+        status.established(peer, stats);
+    }
+
+    fn closed(&self) {
+        let mut status = self.status.lock().unwrap();
+        let asn = status.peer.map(|peer| peer.asn).or(self.config.remote_asn);
+        self.transition(status.state, PeerState::Idle, asn);
+        status.closed();
+    }
+
+    fn transition(&self, from: PeerState, to: PeerState, asn: Option<u32>) {
+        if from != to {
+            event!(
+                Info,
+                "session state changed",
+                "peer.addr" = self.config.remote_addr,
+                "peer.asn" = asn.map_or(String::new(), |asn| asn.to_string()),
+                "state.from" = format_args!("{from:?}"),
+                "state.to" = format_args!("{to:?}"),
+            );
+        }
+    }
+}
+
+impl PeerStatus {
+    fn new(backoff: BackoffConfig) -> Self {
+        PeerStatus {
+            state: PeerState::Idle,
+            peer: None,
+            established_at: None,
+            counters: MessageCounters::default(),
+            backoff: Backoff::new(backoff),
+            monitor: None,
+            stats: None,
+            refresher: None,
+        }
+    }
+
+    fn established(&mut self, peer: PeerInfo, stats: SessionStats) {
+        self.state = PeerState::Established;
+        self.peer = Some(peer);
+        self.established_at = Some(Instant::now());
+        self.monitor = Some(PeerMonitor::new(
+            peer.peer_addr.ip(),
+            Instant::now().into_std(),
+        ));
+        self.stats = Some(stats);
+        self.counters.sessions += 1;
+        self.backoff.succeed();
+    }
+
+    fn closed(&mut self) {
+        self.state = PeerState::Idle;
+        self.peer = None;
+        self.established_at = None;
+        self.refresher = None;
+    }
+
+    fn snapshot(&self, remote_addr: IpAddr, passive: bool, router: Option<IpAddr>) -> PeerSnapshot {
+        PeerSnapshot {
+            remote_addr,
+            router,
+            passive,
+            state: self.state,
+            peer: self.peer,
+            uptime: self.established_at.map(|since| since.elapsed()),
+            counters: self.counters,
+            backoff: self.backoff.status(),
+        }
+    }
+}
+
+/// Turns the feeds of a [`BmpStation`] into the sessions of virtual peers
+struct BmpFeed {
+    observer: Arc<dyn SessionObserver>,
+    bus: Option<Arc<EventBus>>,
+    state: Arc<Mutex<BmpState>>,
+    post_policy: bool,
+    sessions: HashMap<VirtualKey, VirtualSession>,
+}
+
+struct VirtualSession {
+    peer: PeerInfo,
+    stats: SessionStats,
+    graceful_restart: bool,
+    observer: CountingObserver,
+}
+
+impl BmpFeed {
+    async fn run(mut self, mut station: BmpStation) {
+        while let Some(event) = station.next().await {
+            match event {
+                StationEvent::Connected(router) => {
+                    let connected = BmpRouter {
+                        addr: router,
+                        connected: true,
+                        sys_name: None,
+                        sys_descr: None,
+                        information: vec![],
+                        termination: None,
+                    };
+                    self.state.lock().unwrap().routers.insert(router, connected);
+                }
+                StationEvent::Message(router, message) => self.message(router, message).await,
+                // The router's feed continues with its next message
+                StationEvent::Error(..) => {}
+                StationEvent::Disconnected(router) => self.disconnected(router).await,
+            }
+        }
+    }
+
+    async fn message(&mut self, router: IpAddr, message: BmpMessage) {
+        match message {
+            BmpMessage::Initiation(tlvs) => self.update_router(router, |info| {
+                for tlv in tlvs {
+                    match tlv {
+                        InformationTlv::String(string) => info.information.push(string),
+                        InformationTlv::SysDescr(sys_descr) => info.sys_descr = Some(sys_descr),
+                        InformationTlv::SysName(sys_name) => info.sys_name = Some(sys_name),
+                        InformationTlv::Unknown { .. } => {}
+                    }
+                }
+            }),
+            BmpMessage::Termination(tlvs) => self.update_router(router, |info| {
+                for tlv in tlvs {
+                    match tlv {
+                        TerminationTlv::String(string) => info.information.push(string),
+                        TerminationTlv::Reason(reason) => info.termination = Some(reason),
+                        TerminationTlv::Unknown { .. } => {}
+                    }
+                }
+            }),
+            BmpMessage::PeerUp { peer, peer_up } => {
+                let key = virtual_key(router, &peer);
+                // A session the router never reported down
+                self.close(key, PeerDownReason::LocalClose).await;
+                let negotiated = Negotiated::new(&peer_up.sent_open, &peer_up.received_open);
+                let info = self.establish(
+                    router,
+                    key,
+                    &peer,
+                    peer_up.remote_port,
+                    negotiated.graceful_restart,
+                );
+                self.publish(BusEvent::PeerUp(PeerUp {
+                    peer: info,
+                    negotiated,
+                    local_open: peer_up.sent_open,
+                    remote_open: peer_up.received_open,
+                }))
+                .await;
+            }
+            BmpMessage::RouteMonitoring { peer, update } => {
+                if peer.post_policy() != self.post_policy {
+                    return;
+                }
+                let key = virtual_key(router, &peer);
+                if !self.sessions.contains_key(&key) {
+                    self.establish(router, key, &peer, PeerConfig::DEFAULT_PORT, false);
+                }
+                let session = &self.sessions[&key];
+                // Routers that don't timestamp their messages leave the time zero
+                let received = match peer.timestamp {
+                    SystemTime::UNIX_EPOCH => SystemTime::now(),
+                    timestamp => timestamp,
+                };
+                let message = Timestamped::at(received, BgpMessage::Update(update));
+                session
+                    .stats
+                    .record_received(&message, message.to_bytes().len());
+                dispatch(&session.observer, &session.peer, message);
+            }
+            BmpMessage::PeerDown { peer, reason } => {
+                self.close(virtual_key(router, &peer), PeerDownReason::from_bmp(reason))
+                    .await
+            }
+            BmpMessage::StatisticsReport { .. } | BmpMessage::Other { .. } => {}
+        }
+    }
+
+    fn establish(
+        &mut self,
+        router: IpAddr,
+        key: VirtualKey,
+        header: &PeerHeader,
+        port: u16,
+        graceful_restart: bool,
+    ) -> PeerInfo {
+        let peer = PeerInfo {
+            peer_addr: SocketAddr::new(header.addr, port),
+            asn: header.asn,
+            router_id: header.bgp_id,
+            router: Some(router),
+        };
+        let status = self
+            .state
+            .lock()
+            .unwrap()
+            .peers
+            .entry(key)
+            .or_insert_with(|| Arc::new(Mutex::new(PeerStatus::new(BackoffConfig::default()))))
+            .clone();
+        let stats = SessionStats::new();
+        status.lock().unwrap().established(peer, stats.clone());
+        let observer = CountingObserver {
+            status,
+            observer: self.observer.clone(),
+        };
+        observer.on_established(&peer);
+        self.sessions.insert(
+            key,
+            VirtualSession {
+                peer,
+                stats,
+                graceful_restart,
+                observer,
+            },
+        );
+        peer
+    }
+
+    async fn close(&mut self, key: VirtualKey, reason: PeerDownReason) {
+        let Some(session) = self.sessions.remove(&key) else {
+            return;
+        };
+        let end = session_end(&reason);
+        session.observer.on_close(&session.peer, end.as_ref());
+        self.publish(BusEvent::PeerDown(PeerDown {
+            peer: session.peer,
+            reason,
+            graceful_restart: session.graceful_restart,
+        }))
+        .await;
+        session.observer.status.lock().unwrap().closed();
+    }
+
+    /// Closes the sessions of all the router's peers and forgets the peers
+    async fn disconnected(&mut self, router: IpAddr) {
+        let mut keys: Vec<VirtualKey> = self
+            .sessions
+            .keys()
+            .filter(|(session_router, ..)| *session_router == router)
+            .copied()
+            .collect();
+        keys.sort();
+        for key in keys {
+            self.close(key, PeerDownReason::ConnectionLost).await;
+        }
+        let mut state = self.state.lock().unwrap();
+        state
+            .peers
+            .retain(|(peer_router, ..), _| *peer_router != router);
+        if let Some(info) = state.routers.get_mut(&router) {
+            info.connected = false;
+        }
+    }
+
+    fn update_router(&self, router: IpAddr, update: impl FnOnce(&mut BmpRouter)) {
+        if let Some(info) = self.state.lock().unwrap().routers.get_mut(&router) {
+            update(info);
+        }
+    }
+
+    async fn publish(&self, event: BusEvent) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Timestamped::now(event)).await;
+        }
+    }
+}
+
+fn virtual_key(router: IpAddr, peer: &PeerHeader) -> VirtualKey {
+    (router, peer.addr.to_canonical(), peer.distinguisher)
+}
+
+/// How a virtual peer's session ended, as [`SessionObserver::on_close`] reports it
+fn session_end(reason: &PeerDownReason) -> Result<SessionEnd, SessionError> {
+    match reason {
+        PeerDownReason::LocalShutdown(reason) => Ok(SessionEnd::LocalShutdown(reason.clone())),
+        PeerDownReason::HoldTimerExpired => Err(SessionError::HoldTimerExpired),
+        PeerDownReason::RemoteNotification(notification) => {
+            SessionEnd::from_notification(notification)
+                .ok_or_else(|| SessionError::Notification(notification.clone()))
+        }
+        PeerDownReason::ConnectionLost => Err(SessionError::ConnectionClosed),
+        PeerDownReason::LocalError(_)
+        | PeerDownReason::LocalClose
+        | PeerDownReason::CollisionResolution => Ok(SessionEnd::Closed),
+    }
+}
+
+/// Counts a peer's messages for [`PeerManager::snapshot`] before passing them on
+struct CountingObserver {
+    status: Arc<Mutex<PeerStatus>>,
+    observer: Arc<dyn SessionObserver>,
+}
+
+impl CountingObserver {
+    fn count(&self, counter: impl FnOnce(&mut MessageCounters) -> &mut u64) {
+        *counter(&mut self.status.lock().unwrap().counters) += 1;
+    }
+}
+
+impl SessionObserver for CountingObserver {
+    fn on_established(&self, peer: &PeerInfo) {
+        self.observer.on_established(peer)
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        let mut status = self.status.lock().unwrap();
+        status.counters.updates += 1;
+        if let Some(monitor) = &mut status.monitor {
+            monitor.on_update(update, update.monotonic);
+        }
+        drop(status);
+        self.observer.on_update(peer, update)
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        self.count(|counters| &mut counters.notifications);
+        self.observer.on_notification(peer, notification)
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        self.count(|counters| &mut counters.keepalives);
+        self.observer.on_keepalive(peer)
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        self.count(|counters| &mut counters.route_refreshes);
+        self.observer.on_route_refresh(peer, route_refresh)
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        self.observer.on_max_prefixes(peer, event)
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        self.observer.on_close(peer, end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use tokio::net::{TcpListener, TcpSocket};
+
+    use crate::address_family::{Afi, Safi};
+    use crate::bgp_message::BgpMessage;
+    use crate::capability::Capability;
+    use crate::notification_message::CeaseSubErr;
+    use crate::session::BgpListener;
+    use crate::session::config::{BackoffConfig, MaxPrefixPolicy};
+    use crate::session::observer::{ChannelObserver, SessionEvent};
+
+    const COLLECTOR: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+    fn config(remote: Ipv4Addr, asn: u32) -> PeerConfig {
+        PeerConfig::builder(IpAddr::V4(remote), asn, Ipv4Addr::new(10, 0, 0, asn as u8))
+            .hold_time(30)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnect_backoff_sequence() {
+        // Nothing listens on the port, so every attempt is refused immediately
+        let port = {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let mut config = config(Ipv4Addr::LOCALHOST, 65000);
+        config.remote_port = port;
+        config.backoff = BackoffConfig {
+            base: Duration::from_secs(1),
+            cap: Duration::from_secs(4),
+            jitter: 0.0,
+            max_retries: Some(4),
+            damping: None,
+        };
+
+        let (observer, mut events) = ChannelObserver::new(16);
+        let mut manager = PeerManager::new(observer);
+        let start = Instant::now();
+        manager.add_peer(config.clone()).unwrap();
+
+        let mut retries = vec![];
+        while retries.len() < 4 {
+            time::sleep(Duration::from_millis(100)).await;
+            let status = manager.backoff(config.remote_addr).unwrap();
+            if let Some(next_retry) = status.next_retry
+                && retries.last() != Some(&next_retry)
+            {
+                retries.push(next_retry);
+            }
+        }
+        let offsets: Vec<u64> = retries
+            .iter()
+            .map(|retry| retry.duration_since(start).as_secs())
+            .collect();
+        // Attempts at 0, 1, 3 and 7 seconds, each waiting twice as long up to the cap
+        assert_eq!(offsets, vec![1, 3, 7, 11]);
+
+        match events.recv().await {
+            Some(SessionEvent::RetriesExhausted(peer, attempts)) => {
+                assert_eq!(peer, config.remote_socket_addr());
+                assert_eq!(attempts, 5);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert_eq!(start.elapsed().as_secs(), 11);
+        assert!(manager.backoff(config.remote_addr).unwrap().down);
+        assert_eq!(manager.snapshot()[0].state, PeerState::Idle);
+    }
+
+    /// A remote speaker the manager dials, listening on its own loopback address
+    async fn remote_listener(addr: Ipv4Addr, asn: u32) -> (BgpListener, PeerConfig) {
+        let listener = BgpListener::bind((addr, 0), [config(COLLECTOR, asn)])
+            .await
+            .unwrap();
+        let mut local = config(addr, 65000);
+        local.remote_port = listener.local_addr().port();
+        (listener, local)
+    }
+
+    async fn next_established(events: &mut mpsc::Receiver<SessionEvent>) -> PeerInfo {
+        loop {
+            match events.recv().await.unwrap() {
+                SessionEvent::Established(peer) => return peer,
+                SessionEvent::Keepalive(_) => continue,
+                other => panic!("Unexpected event {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manage_three_peers() {
+        let (observer, mut events) = ChannelObserver::new(64);
+        let mut manager = PeerManager::new(observer);
+        let listen_addr = manager.listen((COLLECTOR, 0)).await.unwrap();
+
+        // Two peers we dial and one that dials us
+        let (mut first, first_config) = remote_listener(Ipv4Addr::new(127, 0, 0, 2), 65002).await;
+        let (mut second, second_config) = remote_listener(Ipv4Addr::new(127, 0, 0, 3), 65003).await;
+        let passive_addr = Ipv4Addr::new(127, 0, 0, 4);
+        let mut passive_config = config(passive_addr, 65000);
+        passive_config.passive = true;
+
+        manager.add_peer(first_config.clone()).unwrap();
+        manager.add_peer(second_config).unwrap();
+        manager.add_peer(passive_config).unwrap();
+        assert!(matches!(
+            manager.add_peer(first_config),
+            Err(ConfigError::DuplicatePeer(_))
+        ));
+
+        let mut first_session = first.accept().await.unwrap();
+        let second_session = second.accept().await.unwrap();
+
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind((passive_addr, 0).into()).unwrap();
+        let stream = socket.connect(listen_addr).await.unwrap();
+        let mut dialer = config(COLLECTOR, 65004);
+        dialer.remote_port = listen_addr.port();
+        let third_session = establish(stream, &dialer).await.unwrap();
+
+        let mut asns = vec![];
+        for _ in 0..3 {
+            asns.push(next_established(&mut events).await.asn);
+        }
+        asns.sort();
+        assert_eq!(asns, vec![65002, 65003, 65004]);
+
+        first_session
+            .send(BgpMessage::Update(UpdateMessage {
+                withdrawn_routes: vec![],
+                path_attributes: vec![],
+                nlri: vec![],
+            }))
+            .await
+            .unwrap();
+        loop {
+            if let Some(SessionEvent::Update(peer, _)) = events.recv().await {
+                assert_eq!(peer.asn, 65002);
+                break;
+            }
+        }
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert!(
+            snapshot
+                .iter()
+                .all(|peer| peer.state == PeerState::Established && peer.uptime.is_some())
+        );
+        assert_eq!(snapshot[0].counters.updates, 1);
+        assert_eq!(snapshot[0].counters.sessions, 1);
+        assert!(snapshot[2].passive);
+        let monitoring = manager.monitoring();
+        assert_eq!(monitoring.len(), 3);
+        assert_eq!(monitoring[0].peer, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)));
+
+        // Removing a peer closes its session with Cease/Peer De-configured
+        assert!(
+            manager
+                .remove_peer(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)))
+                .await
+        );
+        while first_session.recv().await.is_some() {}
+        assert_eq!(
+            first_session.close().await.unwrap(),
+            SessionEnd::RemoteCease {
+                subcode: Some(CeaseSubErr::PeerDeconfigured),
+                message: None
+            }
+        );
+        assert_eq!(manager.snapshot().len(), 2);
+        assert!(
+            !manager
+                .remove_peer(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)))
+                .await
+        );
+
+        // The remaining sessions are untouched
+        assert!(
+            manager
+                .snapshot()
+                .iter()
+                .all(|peer| peer.state == PeerState::Established)
+        );
+        drop((second_session, third_session));
+    }
+
+    #[tokio::test]
+    async fn test_request_refresh() {
+        let (observer, mut events) = ChannelObserver::new(64);
+        let mut manager = PeerManager::new(observer);
+        let addr = Ipv4Addr::new(127, 0, 0, 8);
+        let mut remote_config = config(COLLECTOR, 65008);
+        remote_config.capabilities = vec![Capability::RouteRefresh];
+        let mut remote = BgpListener::bind((addr, 0), [remote_config]).await.unwrap();
+        let mut local = config(addr, 65000);
+        local.remote_port = remote.local_addr().port();
+        local.capabilities = vec![Capability::RouteRefresh];
+        assert!(matches!(
+            manager
+                .request_refresh(IpAddr::V4(addr), Afi::Ipv4, Safi::Unicast)
+                .await,
+            Err(SessionError::SessionClosed)
+        ));
+        manager.add_peer(local).unwrap();
+
+        let mut session = remote.accept().await.unwrap();
+        next_established(&mut events).await;
+        let completion = manager
+            .request_refresh(IpAddr::V4(addr), Afi::Ipv4, Safi::Unicast)
+            .await
+            .unwrap();
+        assert!(completion.is_none());
+        loop {
+            match session.recv().await.unwrap() {
+                BgpMessage::Keepalive => continue,
+                message => {
+                    assert_eq!(
+                        message,
+                        BgpMessage::RouteRefresh(RouteRefreshMessage::request(
+                            Afi::Ipv4,
+                            Safi::Unicast
+                        ))
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart_after_max_prefixes() {
+        let (observer, mut events) = ChannelObserver::new(64);
+        let mut manager = PeerManager::new(observer);
+        let (mut remote, mut local) = remote_listener(Ipv4Addr::new(127, 0, 0, 5), 65005).await;
+        local.max_prefixes = Some(MaxPrefixPolicy::new(
+            1,
+            MaxPrefixAction::Teardown {
+                restart_after: Some(Duration::from_secs(1)),
+            },
+        ));
+        manager.add_peer(local).unwrap();
+
+        let mut session = remote.accept().await.unwrap();
+        next_established(&mut events).await;
+        session
+            .send(BgpMessage::Update(UpdateMessage {
+                withdrawn_routes: vec![],
+                path_attributes: vec![],
+                nlri: vec![
+                    "10.0.0.0/24".parse().unwrap(),
+                    "10.0.1.0/24".parse().unwrap(),
+                ],
+            }))
+            .await
+            .unwrap();
+        while session.recv().await.is_some() {}
+        assert_eq!(
+            session.close().await.unwrap(),
+            SessionEnd::RemoteCease {
+                subcode: Some(CeaseSubErr::MaximumNumberOfPrefixesReached),
+                message: None
+            }
+        );
+
+        // The manager holds off for the restart delay, then connects again
+        let started = Instant::now();
+        let _session = remote.accept().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        loop {
+            if let SessionEvent::Established(_) = events.recv().await.unwrap() {
+                break;
+            }
+        }
+        assert_eq!(manager.snapshot()[0].counters.sessions, 2);
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_on_event_bus() {
+        use crate::capability::{Capability, GracefulRestart, GracefulRestartFamily};
+        use crate::rib::RibIn;
+        use crate::session::{EventBus, LagPolicy, PeerDownReason};
+
+        let graceful_restart = Capability::GracefulRestart(GracefulRestart {
+            restarting: false,
+            restart_time: 120,
+            families: vec![GracefulRestartFamily {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                forwarding_preserved: true,
+            }],
+        });
+        let addr = Ipv4Addr::new(127, 0, 0, 6);
+        let mut remote_config = config(COLLECTOR, 65006);
+        remote_config.capabilities.push(graceful_restart.clone());
+        let mut remote = BgpListener::bind((addr, 0), [remote_config]).await.unwrap();
+        let mut local = config(addr, 65000);
+        local.remote_port = remote.local_addr().port();
+        local.capabilities.push(graceful_restart);
+
+        let bus = Arc::new(EventBus::new(LagPolicy::DropOldest, 16));
+        let mut lifecycle = bus.subscribe("lifecycle");
+        let (observer, mut events) = ChannelObserver::new(16);
+        let mut manager = PeerManager::new(observer).with_event_bus(bus.clone());
+        manager.add_peer(local).unwrap();
+
+        let session = remote.accept().await.unwrap();
+        let BusEvent::PeerUp(up) = &lifecycle.recv().await.unwrap().value else {
+            panic!("expected the session to come up first");
+        };
+        assert_eq!(up.peer.asn, 65006);
+        assert!(up.negotiated.graceful_restart);
+        assert_eq!(up.remote_open.asn(), 65006);
+        assert_eq!(up.local_open.asn(), 65000);
+
+        session
+            .send(BgpMessage::Update(
+                crate::update_message::UpdateMessageBuilder::new()
+                    .announce("192.0.2.0/24".parse().unwrap())
+                    .next_hop(IpAddr::V4(addr))
+                    .build(),
+            ))
+            .await
+            .unwrap();
+        let mut rib = RibIn::new();
+        loop {
+            if let SessionEvent::Update(_, update) = events.recv().await.unwrap() {
+                rib.apply(&update);
+                break;
+            }
+        }
+
+        // Losing the connection keeps the routes of a Graceful Restart peer as stale
+        drop(session);
+        let BusEvent::PeerDown(down) = &lifecycle.recv().await.unwrap().value else {
+            panic!("expected the session to go down");
+        };
+        assert_eq!(down.peer.asn, 65006);
+        assert_eq!(down.reason, PeerDownReason::ConnectionLost);
+        assert!(rib.peer_down(down).is_empty());
+        assert_eq!((rib.len(), rib.stale_count()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_replay_builds_rib() {
+        let recorded = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let configured = config(Ipv4Addr::new(10, 0, 0, 1), 65000);
+        let source = ReplaySource::mrt(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bgp4mp_replay.mrt"),
+        )
+        .unwrap()
+        .map_peer(recorded, configured.remote_addr);
+
+        let (observer, mut events) = ChannelObserver::new(16);
+        let mut manager = PeerManager::new(observer);
+        manager.add_replay(configured.clone(), source).unwrap();
+
+        let mut rib = crate::rib::RibIn::new();
+        let peer = loop {
+            match events.recv().await.unwrap() {
+                SessionEvent::Established(peer) => {
+                    assert_eq!(peer.router_id, Ipv4Addr::new(192, 0, 2, 1))
+                }
+                SessionEvent::Update(_, update) => {
+                    rib.apply(&update);
+                }
+                SessionEvent::Keepalive(_) => {}
+                SessionEvent::Closed(peer, end) => {
+                    assert_eq!(end.unwrap(), SessionEnd::Closed);
+                    break peer;
+                }
+                event => panic!("unexpected {event:?}"),
+            }
+        };
+        assert_eq!(peer.peer_addr.ip(), configured.remote_addr);
+        assert_eq!(peer.asn, 64500);
+
+        // The other recorded peer isn't mapped onto a configured one
+        let mut routes: Vec<(String, Option<u32>)> = rib
+            .iter()
+            .map(|(key, attributes)| {
+                (
+                    key.prefix.to_string(),
+                    crate::monitor::origin_as(attributes),
+                )
+            })
+            .collect();
+        routes.sort();
+        assert_eq!(
+            routes,
+            vec![
+                ("10.0.0.0/8".to_string(), Some(64500)),
+                ("198.51.100.0/24".to_string(), Some(64510)),
+            ]
+        );
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot[0].state, PeerState::Idle);
+        assert_eq!(snapshot[0].counters.updates, 3);
+        assert_eq!(snapshot[0].counters.keepalives, 1);
+        let stats = manager.stats(configured.remote_addr).unwrap();
+        assert_eq!(stats.messages_in(crate::header::BgpMessageType::Update), 3);
+    }
+
+    #[tokio::test]
+    async fn test_bmp_station_builds_ribs() {
+        use tokio::io::AsyncWriteExt;
+
+        use crate::rib::RibIn;
+
+        let data = std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/bmp_session.bin"),
+        )
+        .unwrap();
+        // Where each message of the capture starts
+        let mut offsets = vec![0];
+        while let Some(length) = BmpMessage::frame_len(&data[*offsets.last().unwrap()..]).unwrap() {
+            offsets.push(offsets.last().unwrap() + length);
+        }
+        assert_eq!(offsets.len(), 11);
+
+        let station = BmpStation::bind((COLLECTOR, 0)).await.unwrap();
+        let station_addr = station.local_addr();
+        let (observer, mut events) = ChannelObserver::new(64);
+        let mut manager = PeerManager::new(observer);
+        manager.add_bmp_station(station);
+
+        let router = IpAddr::V4(COLLECTOR);
+        let mut ribs: HashMap<IpAddr, RibIn> = HashMap::new();
+        let apply = |ribs: &mut HashMap<IpAddr, RibIn>, event| match event {
+            SessionEvent::Established(peer) => {
+                assert_eq!(peer.router, Some(router));
+                ribs.insert(peer.peer_addr.ip(), RibIn::new());
+                None
+            }
+            SessionEvent::Update(peer, update) => {
+                ribs.get_mut(&peer.peer_addr.ip()).unwrap().apply(&update);
+                None
+            }
+            SessionEvent::Closed(peer, end) => {
+                ribs.remove(&peer.peer_addr.ip());
+                Some((peer.peer_addr.ip(), end))
+            }
+            event => panic!("unexpected {event:?}"),
+        };
+
+        // Initiation, Peer Up, Route Monitoring of three peers and a Statistics Report
+        let mut feed = TcpStream::connect(station_addr).await.unwrap();
+        feed.write_all(&data[..offsets[6]]).await.unwrap();
+        for _ in 0..4 {
+            assert!(apply(&mut ribs, events.recv().await.unwrap()).is_none());
+        }
+        let routes = |ribs: &HashMap<IpAddr, RibIn>| {
+            let mut routes: Vec<(IpAddr, String)> = ribs
+                .iter()
+                .flat_map(|(peer, rib)| rib.iter().map(|(key, _)| (*peer, key.prefix.to_string())))
+                .collect();
+            routes.sort();
+            routes
+        };
+        // The post-policy routes of the IPv6 peer aren't fed
+        let uplink: IpAddr = "192.0.2.1".parse().unwrap();
+        let legacy: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(
+            routes(&ribs),
+            vec![
+                (legacy, "198.51.100.0/24".to_string()),
+                (uplink, "203.0.113.0/24".to_string()),
+            ]
+        );
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.iter().all(|peer| peer.router == Some(router)
+            && peer.state == PeerState::Established
+            && peer.counters.updates == 1));
+        assert_eq!(snapshot[1].peer.unwrap().peer_addr.port(), 51234);
+        let routers = manager.bmp_routers();
+        assert_eq!(routers[0].sys_name.as_deref(), Some("edge1.example.net"));
+        assert!(routers[0].connected);
+
+        // Connecting again flushes everything the router reported so far
+        let mut feed = TcpStream::connect(station_addr).await.unwrap();
+        let mut closed = vec![apply(&mut ribs, events.recv().await.unwrap()).unwrap()];
+        closed.push(apply(&mut ribs, events.recv().await.unwrap()).unwrap());
+        closed.sort_by_key(|(peer, _)| *peer);
+        assert!(matches!(
+            &closed[..],
+            [
+                (_, Err(SessionError::ConnectionClosed)),
+                (_, Err(SessionError::ConnectionClosed))
+            ]
+        ));
+        assert!(ribs.is_empty());
+        assert!(manager.snapshot().is_empty());
+
+        // The whole session, both peers going down before the router terminates the feed
+        feed.write_all(&data).await.unwrap();
+        drop(feed);
+        for _ in 0..4 {
+            assert!(apply(&mut ribs, events.recv().await.unwrap()).is_none());
+        }
+        let (peer, end) = apply(&mut ribs, events.recv().await.unwrap()).unwrap();
+        assert_eq!(peer, uplink);
+        assert_eq!(
+            end.unwrap(),
+            SessionEnd::LocalShutdown(ShutdownReason::AdministrativeShutdown(None))
+        );
+        let (peer, end) = apply(&mut ribs, events.recv().await.unwrap()).unwrap();
+        assert_eq!(peer, legacy);
+        assert!(matches!(end, Err(SessionError::ConnectionClosed)));
+        assert!(ribs.is_empty());
+
+        while manager.bmp_routers()[0].connected {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let routers = manager.bmp_routers();
+        assert_eq!(
+            routers[0].termination,
+            Some(TerminationReason::AdministrativelyClosed)
+        );
+        assert_eq!(routers[0].information, vec!["shutting down"]);
+    }
+}
@@ -0,0 +1,39 @@
+mod announcer;
+mod backoff;
+mod bus;
+mod codec;
+mod config;
+mod connector;
+mod error;
+mod established;
+mod lifecycle;
+mod listener;
+mod manager;
+mod observer;
+mod prefix_limit;
+mod replay;
+mod socket;
+mod stats;
+
+pub use crate::peer::{
+    Negotiated, PeerDown, PeerDownReason, PeerInfo, PeerUp, SessionEnd, ShutdownReason,
+};
+pub use announcer::Announcer;
+pub use backoff::{Backoff, BackoffStatus};
+pub use bus::{BusEvent, BusMessage, EventBus, LagPolicy, Subscriber, SubscriberStats};
+pub use codec::{MessageReader, write_message};
+pub use config::{
+    BackoffConfig, ConfigError, DampingConfig, MaxPrefixAction, MaxPrefixPolicy, PeerConfig,
+    PeerConfigBuilder,
+};
+pub use connector::Peer;
+pub use error::SessionError;
+pub use established::{EstablishedSession, RefreshCompletion, Refresher};
+pub use listener::BgpListener;
+pub use manager::{BmpRouter, MessageCounters, PeerManager, PeerSnapshot, PeerState};
+pub use observer::{ChannelObserver, FilteredObserver, SessionEvent, SessionObserver};
+pub use prefix_limit::MaxPrefixEvent;
+pub use replay::{ReplayError, ReplaySource, ReplaySpeed};
+pub use stats::{
+    NOTIFICATION_HISTORY, NotificationRecord, RATE_WINDOWS, SessionStats, UpdateRates,
+};
@@ -0,0 +1,548 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::mpsc;
+
+use crate::attribute::AttributeValue;
+use crate::bgp_message::BgpMessage;
+use crate::filter::RouteFilter;
+use crate::notification_message::NotificationMessage;
+use crate::peer::{PeerInfo, SessionEnd};
+use crate::rib::RibKey;
+use crate::route_refresh_message::RouteRefreshMessage;
+use crate::timestamped::Timestamped;
+use crate::update_message::UpdateMessage;
+
+use super::error::SessionError;
+use super::prefix_limit::MaxPrefixEvent;
+
+/// Callbacks for the traffic of established sessions.
+///
+/// Callbacks run inline on the task driving the session, so they must return quickly and must
+/// never block: while a callback runs no further messages are read from that peer and, if it
+/// stalls for long enough, the peer's hold timer expires. Hand slow work off to another task,
+/// for example through a [`ChannelObserver`]. The trait is object safe so observers can be
+/// boxed and combined.
+pub trait SessionObserver: Send + Sync {
+    fn on_established(&self, _peer: &PeerInfo) {}
+
+    /// An UPDATE, with the time it was read off the socket
+    fn on_update(&self, _peer: &PeerInfo, _update: &Timestamped<UpdateMessage>) {}
+
+    /// A NOTIFICATION from the peer, always followed by [`SessionObserver::on_close`]
+    fn on_notification(&self, _peer: &PeerInfo, _notification: &NotificationMessage) {}
+
+    fn on_keepalive(&self, _peer: &PeerInfo) {}
+
+    /// A ROUTE-REFRESH request or, with Enhanced Route Refresh, a BoRR/EoRR marker
+    fn on_route_refresh(&self, _peer: &PeerInfo, _route_refresh: &RouteRefreshMessage) {}
+
+    /// The peer's routes crossed the warning threshold or the limit of its prefix policy
+    fn on_max_prefixes(&self, _peer: &PeerInfo, _event: &MaxPrefixEvent) {}
+
+    /// The session ended, either cleanly or with the error that tore it down
+    fn on_close(&self, _peer: &PeerInfo, _end: Result<&SessionEnd, &SessionError>) {}
+
+    /// A managed peer failed `attempts` times in a row and won't be retried
+    fn on_retries_exhausted(&self, _peer: SocketAddr, _attempts: u32) {}
+}
+
+impl<O: SessionObserver + ?Sized> SessionObserver for Box<O> {
+    fn on_established(&self, peer: &PeerInfo) {
+        (**self).on_established(peer)
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        (**self).on_update(peer, update)
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        (**self).on_notification(peer, notification)
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        (**self).on_keepalive(peer)
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        (**self).on_route_refresh(peer, route_refresh)
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        (**self).on_max_prefixes(peer, event)
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        (**self).on_close(peer, end)
+    }
+
+    fn on_retries_exhausted(&self, peer: SocketAddr, attempts: u32) {
+        (**self).on_retries_exhausted(peer, attempts)
+    }
+}
+
+impl<O: SessionObserver + ?Sized> SessionObserver for Arc<O> {
+    fn on_established(&self, peer: &PeerInfo) {
+        (**self).on_established(peer)
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        (**self).on_update(peer, update)
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        (**self).on_notification(peer, notification)
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        (**self).on_keepalive(peer)
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        (**self).on_route_refresh(peer, route_refresh)
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        (**self).on_max_prefixes(peer, event)
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        (**self).on_close(peer, end)
+    }
+
+    fn on_retries_exhausted(&self, peer: SocketAddr, attempts: u32) {
+        (**self).on_retries_exhausted(peer, attempts)
+    }
+}
+
+/// Every observer sees every event, in order
+impl<O: SessionObserver> SessionObserver for Vec<O> {
+    fn on_established(&self, peer: &PeerInfo) {
+        self.iter()
+            .for_each(|observer| observer.on_established(peer))
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        self.iter()
+            .for_each(|observer| observer.on_update(peer, update))
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        self.iter()
+            .for_each(|observer| observer.on_notification(peer, notification))
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        self.iter().for_each(|observer| observer.on_keepalive(peer))
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        self.iter()
+            .for_each(|observer| observer.on_route_refresh(peer, route_refresh))
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        self.iter()
+            .for_each(|observer| observer.on_max_prefixes(peer, event))
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        self.iter()
+            .for_each(|observer| observer.on_close(peer, end))
+    }
+
+    fn on_retries_exhausted(&self, peer: SocketAddr, attempts: u32) {
+        self.iter()
+            .for_each(|observer| observer.on_retries_exhausted(peer, attempts))
+    }
+}
+
+/// Passes a message received on an established session to its callback
+pub(crate) fn dispatch<O: SessionObserver + ?Sized>(
+    observer: &O,
+    peer: &PeerInfo,
+    message: Timestamped<BgpMessage>,
+) {
+    let Timestamped {
+        received,
+        monotonic,
+        value: message,
+    } = message;
+    match message {
+        BgpMessage::Update(update) => observer.on_update(
+            peer,
+            &Timestamped {
+                received,
+                monotonic,
+                value: update,
+            },
+        ),
+        BgpMessage::Notification(notification) => observer.on_notification(peer, &notification),
+        BgpMessage::Keepalive => observer.on_keepalive(peer),
+        BgpMessage::RouteRefresh(route_refresh) => observer.on_route_refresh(peer, &route_refresh),
+        // The codec never delivers an OPEN once established
+        BgpMessage::Open(_) => {}
+    }
+}
+
+/// An observed event, as delivered by [`ChannelObserver`]
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    Established(PeerInfo),
+    Update(PeerInfo, Timestamped<UpdateMessage>),
+    Notification(PeerInfo, NotificationMessage),
+    Keepalive(PeerInfo),
+    RouteRefresh(PeerInfo, RouteRefreshMessage),
+    MaxPrefixes(PeerInfo, MaxPrefixEvent),
+    Closed(PeerInfo, Result<SessionEnd, SessionError>),
+    RetriesExhausted(SocketAddr, u32),
+}
+
+/// Forwards every event into a tokio channel.
+///
+/// Never blocks the session: when the channel is full the event is dropped and counted in
+/// [`ChannelObserver::dropped`].
+#[derive(Debug)]
+pub struct ChannelObserver {
+    events: mpsc::Sender<SessionEvent>,
+    dropped: AtomicU64,
+}
+
+impl ChannelObserver {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<SessionEvent>) {
+        let (events, receiver) = mpsc::channel(capacity);
+        let observer = ChannelObserver {
+            events,
+            dropped: AtomicU64::new(0),
+        };
+        (observer, receiver)
+    }
+
+    /// Events discarded because the receiver fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn forward(&self, event: SessionEvent) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.events.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SessionObserver for ChannelObserver {
+    fn on_established(&self, peer: &PeerInfo) {
+        self.forward(SessionEvent::Established(*peer))
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        self.forward(SessionEvent::Update(*peer, update.clone()))
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        self.forward(SessionEvent::Notification(*peer, notification.clone()))
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        self.forward(SessionEvent::Keepalive(*peer))
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        self.forward(SessionEvent::RouteRefresh(*peer, *route_refresh))
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        self.forward(SessionEvent::MaxPrefixes(*peer, *event))
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        let end = end.cloned().map_err(SessionError::clone);
+        self.forward(SessionEvent::Closed(*peer, end))
+    }
+
+    fn on_retries_exhausted(&self, peer: SocketAddr, attempts: u32) {
+        self.forward(SessionEvent::RetriesExhausted(peer, attempts))
+    }
+}
+
+/// Removes the announcements a filter rejects from updates before passing them on.
+///
+/// Rejected prefixes are counted in [`FilteredObserver::filtered`]. Updates left with nothing
+/// to announce or withdraw aren't passed on at all, every other event is passed on unchanged.
+#[derive(Debug)]
+pub struct FilteredObserver<O> {
+    inner: O,
+    filter: Arc<dyn RouteFilter>,
+    filtered: AtomicU64,
+}
+
+impl<O: SessionObserver> FilteredObserver<O> {
+    pub fn new(inner: O, filter: Arc<dyn RouteFilter>) -> Self {
+        FilteredObserver {
+            inner,
+            filter,
+            filtered: AtomicU64::new(0),
+        }
+    }
+
+    /// Announced prefixes removed so far
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    /// The update without rejected announcements, `None` when nothing was rejected
+    fn filter(&self, update: &UpdateMessage) -> Option<UpdateMessage> {
+        let attributes = &update.path_attributes;
+        let permits = |key: RibKey| self.filter.permits(&key, attributes);
+        let mut filtered = update.clone();
+        filtered
+            .nlri
+            .retain(|prefix| permits(RibKey::unicast(prefix.clone())));
+        for attribute in &mut filtered.path_attributes {
+            if let AttributeValue::MpReachNlri(mp_reach) = &mut attribute.value {
+                let safi = mp_reach.safi;
+                mp_reach.nlri.retain(|prefix| {
+                    permits(RibKey {
+                        prefix: prefix.clone(),
+                        safi,
+                        path_id: None,
+                    })
+                });
+            }
+        }
+
+        let announced = |update: &UpdateMessage| {
+            let mp_announced =
+                update
+                    .path_attributes
+                    .iter()
+                    .map(|attribute| match &attribute.value {
+                        AttributeValue::MpReachNlri(mp_reach) => mp_reach.nlri.len(),
+                        _ => 0,
+                    });
+            update.nlri.len() + mp_announced.sum::<usize>()
+        };
+        let rejected = announced(update) - announced(&filtered);
+        if rejected == 0 {
+            return None;
+        }
+        self.filtered.fetch_add(rejected as u64, Ordering::Relaxed);
+
+        if announced(&filtered) == 0 {
+            // Without announcements only the withdrawals are left
+            filtered
+                .path_attributes
+                .retain(|attribute| matches!(attribute.value, AttributeValue::MpUnreachNlri(_)));
+        }
+        Some(filtered)
+    }
+}
+
+impl<O: SessionObserver> SessionObserver for FilteredObserver<O> {
+    fn on_established(&self, peer: &PeerInfo) {
+        self.inner.on_established(peer)
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        match self.filter(update) {
+            None => self.inner.on_update(peer, update),
+            Some(filtered)
+                if filtered.withdrawn_routes.is_empty() && filtered.path_attributes.is_empty() => {}
+            Some(filtered) => self.inner.on_update(
+                peer,
+                &Timestamped {
+                    received: update.received,
+                    monotonic: update.monotonic,
+                    value: filtered,
+                },
+            ),
+        }
+    }
+
+    fn on_notification(&self, peer: &PeerInfo, notification: &NotificationMessage) {
+        self.inner.on_notification(peer, notification)
+    }
+
+    fn on_keepalive(&self, peer: &PeerInfo) {
+        self.inner.on_keepalive(peer)
+    }
+
+    fn on_route_refresh(&self, peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        self.inner.on_route_refresh(peer, route_refresh)
+    }
+
+    fn on_max_prefixes(&self, peer: &PeerInfo, event: &MaxPrefixEvent) {
+        self.inner.on_max_prefixes(peer, event)
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        self.inner.on_close(peer, end)
+    }
+
+    fn on_retries_exhausted(&self, peer: SocketAddr, attempts: u32) {
+        self.inner.on_retries_exhausted(peer, attempts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    use crate::bgp_message::BgpMessage;
+    use crate::filter::PrefixList;
+    use crate::notification_message::{CeaseSubErr, NotificationErrorCode};
+    use crate::session::established::test::{establish_with_fake_peer, local_config};
+    use crate::update_message::{IpAddrPrefix, UpdateMessageBuilder};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<&'static str>>);
+
+    impl SessionObserver for Recorder {
+        fn on_established(&self, _peer: &PeerInfo) {
+            self.0.lock().unwrap().push("established");
+        }
+
+        fn on_update(&self, _peer: &PeerInfo, _update: &Timestamped<UpdateMessage>) {
+            self.0.lock().unwrap().push("update");
+        }
+
+        fn on_notification(&self, _peer: &PeerInfo, _notification: &NotificationMessage) {
+            self.0.lock().unwrap().push("notification");
+        }
+
+        fn on_close(&self, _peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+            assert!(matches!(end, Ok(SessionEnd::RemoteCease { .. })));
+            self.0.lock().unwrap().push("close");
+        }
+    }
+
+    fn update() -> UpdateMessage {
+        UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_observers_see_every_event() {
+        let (session, mut peer) = establish_with_fake_peer(local_config(90), 90).await;
+        let info = session.peer_info();
+
+        let recorder = Arc::new(Recorder::default());
+        let (channel, mut events) = ChannelObserver::new(16);
+        let observers: Vec<Box<dyn SessionObserver>> =
+            vec![Box::new(recorder.clone()), Box::new(channel)];
+        let run = tokio::spawn(session.run(observers));
+
+        peer.send(BgpMessage::Update(update())).await;
+        peer.send(BgpMessage::Notification(NotificationMessage::new(
+            NotificationErrorCode::Cease(CeaseSubErr::PeerDeconfigured),
+            vec![],
+        )))
+        .await;
+        assert!(run.await.unwrap().is_ok());
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec!["established", "update", "notification", "close"]
+        );
+        assert!(
+            matches!(events.recv().await, Some(SessionEvent::Established(peer)) if peer == info)
+        );
+        assert!(
+            matches!(events.recv().await, Some(SessionEvent::Update(_, update)) if *update == self::update())
+        );
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::Notification(..))
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::Closed(_, Ok(SessionEnd::RemoteCease { .. })))
+        ));
+    }
+
+    #[test]
+    fn test_channel_observer_drops_when_full() {
+        let (observer, _events) = ChannelObserver::new(1);
+        let peer = PeerInfo {
+            peer_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 179)),
+            asn: 65001,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: None,
+        };
+        observer.on_keepalive(&peer);
+        observer.on_keepalive(&peer);
+        assert_eq!(observer.dropped(), 1);
+    }
+
+    #[test]
+    fn test_filtered_observer() {
+        #[derive(Default)]
+        struct Updates(Mutex<Vec<UpdateMessage>>);
+
+        impl SessionObserver for Updates {
+            fn on_update(&self, _peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+                self.0.lock().unwrap().push(update.value.clone());
+            }
+        }
+
+        let prefix = |s: &str| s.parse::<IpAddrPrefix>().unwrap();
+        let filter = Arc::new(
+            "permit 192.0.2.0/24\npermit 2001:db8::/32"
+                .parse::<PrefixList>()
+                .unwrap(),
+        );
+        let observer = FilteredObserver::new(Updates::default(), filter);
+        let peer = PeerInfo {
+            peer_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 179)),
+            asn: 65001,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: None,
+        };
+
+        let mixed = UpdateMessageBuilder::new()
+            .announce(prefix("192.0.2.0/24"))
+            .announce(prefix("198.51.100.0/24"))
+            .announce(prefix("2001:db8::/32"))
+            .announce(prefix("2001:db8:1::/48"))
+            .next_hop("192.0.2.1".parse().unwrap())
+            .build();
+        let rejected = UpdateMessageBuilder::new()
+            .announce(prefix("198.51.100.0/24"))
+            .next_hop("192.0.2.1".parse().unwrap())
+            .build();
+        let withdrawing = UpdateMessageBuilder::new()
+            .announce(prefix("198.51.100.0/24"))
+            .withdraw(prefix("192.0.2.0/24"))
+            .next_hop("192.0.2.1".parse().unwrap())
+            .build();
+        for update in [&mixed, &rejected, &withdrawing] {
+            observer.on_update(&peer, &Timestamped::now(update.clone()));
+        }
+        assert_eq!(observer.filtered(), 4);
+
+        let updates = observer.inner.0.lock().unwrap();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].nlri, vec![prefix("192.0.2.0/24")]);
+        let mp_nlri: Vec<_> = updates[0]
+            .path_attributes
+            .iter()
+            .filter_map(|attribute| match &attribute.value {
+                AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach.nlri.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(mp_nlri, vec![vec![prefix("2001:db8::/32")]]);
+        assert_eq!(
+            updates[1],
+            UpdateMessageBuilder::new()
+                .withdraw(prefix("192.0.2.0/24"))
+                .build()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use crate::address_family::{Afi, Safi};
+use crate::rib::RibIn;
+use crate::update_message::UpdateMessage;
+
+use super::config::{MaxPrefixAction, MaxPrefixPolicy};
+
+/// A peer's route count for a family crossed a threshold of its [`MaxPrefixPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxPrefixEvent {
+    /// The count reached the warning threshold
+    Warning {
+        afi: Afi,
+        safi: Safi,
+        count: usize,
+        limit: u32,
+    },
+    /// The count exceeded the limit; with [`MaxPrefixAction::Teardown`] the session closes next
+    Exceeded {
+        afi: Afi,
+        safi: Safi,
+        count: usize,
+        limit: u32,
+    },
+}
+
+/// Counts the routes a session received per family against a [`MaxPrefixPolicy`].
+///
+/// Each threshold is reported once and again only after the count dropped back below it.
+#[derive(Debug)]
+pub(crate) struct PrefixLimit {
+    policy: MaxPrefixPolicy,
+    rib: RibIn,
+    warned: HashSet<(Afi, Safi)>,
+    exceeded: HashSet<(Afi, Safi)>,
+}
+
+impl PrefixLimit {
+    pub(crate) fn new(policy: MaxPrefixPolicy) -> Self {
+        PrefixLimit {
+            policy,
+            rib: RibIn::new(),
+            warned: HashSet::new(),
+            exceeded: HashSet::new(),
+        }
+    }
+
+    /// Whether exceeding the limit closes the session
+    pub(crate) fn teardown(&self) -> bool {
+        matches!(self.policy.action, MaxPrefixAction::Teardown { .. })
+    }
+
+    pub(crate) fn apply(&mut self, update: &UpdateMessage) -> Vec<MaxPrefixEvent> {
+        let families: HashSet<(Afi, Safi)> = self
+            .rib
+            .apply(update)
+            .iter()
+            .map(|change| change.key().family())
+            .collect();
+
+        let limit = self.policy.limit;
+        let mut events = vec![];
+        for (afi, safi) in families {
+            let count = self.rib.prefix_count(afi, safi);
+            if count as u64 >= self.policy.warning_count() {
+                if self.warned.insert((afi, safi)) {
+                    events.push(MaxPrefixEvent::Warning {
+                        afi,
+                        safi,
+                        count,
+                        limit,
+                    });
+                }
+            } else {
+                self.warned.remove(&(afi, safi));
+            }
+
+            if count as u64 > limit as u64 {
+                if self.exceeded.insert((afi, safi)) {
+                    events.push(MaxPrefixEvent::Exceeded {
+                        afi,
+                        safi,
+                        count,
+                        limit,
+                    });
+                }
+            } else {
+                self.exceeded.remove(&(afi, safi));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::bgp_message::BgpMessage;
+    use crate::notification_message::{CeaseSubErr, NotificationErrorCode};
+    use crate::session::established::test::{establish_with_fake_peer, local_config};
+    use crate::session::{ChannelObserver, SessionEnd, SessionEvent, ShutdownReason};
+
+    fn announce(prefix: &str) -> BgpMessage {
+        BgpMessage::Update(UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![prefix.parse().unwrap()],
+        })
+    }
+
+    fn withdraw(prefix: &str) -> BgpMessage {
+        BgpMessage::Update(UpdateMessage {
+            withdrawn_routes: vec![prefix.parse().unwrap()],
+            path_attributes: vec![],
+            nlri: vec![],
+        })
+    }
+
+    fn policy(limit: u32, action: MaxPrefixAction) -> MaxPrefixPolicy {
+        MaxPrefixPolicy {
+            warning_threshold: 50,
+            ..MaxPrefixPolicy::new(limit, action)
+        }
+    }
+
+    #[test]
+    fn test_thresholds_reported_once() {
+        let mut limit = PrefixLimit::new(policy(2, MaxPrefixAction::WarnOnly));
+        let update = |message| match message {
+            BgpMessage::Update(update) => update,
+            _ => unreachable!(),
+        };
+
+        let warning = MaxPrefixEvent::Warning {
+            afi: Afi::Ipv4,
+            safi: Safi::Unicast,
+            count: 1,
+            limit: 2,
+        };
+        assert_eq!(limit.apply(&update(announce("10.0.0.0/24"))), vec![warning]);
+        assert_eq!(limit.apply(&update(announce("10.0.1.0/24"))), vec![]);
+        let events = limit.apply(&update(announce("10.0.2.0/24")));
+        assert!(matches!(
+            events[..],
+            [MaxPrefixEvent::Exceeded { count: 3, .. }]
+        ));
+        // A replaced route doesn't change the count
+        assert_eq!(limit.apply(&update(announce("10.0.2.0/24"))), vec![]);
+
+        // Dropping below a threshold re-arms it
+        limit.apply(&update(withdraw("10.0.2.0/24")));
+        assert!(matches!(
+            limit.apply(&update(announce("10.0.3.0/24")))[..],
+            [MaxPrefixEvent::Exceeded { count: 3, .. }]
+        ));
+        assert!(!limit.teardown());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_teardown_when_exceeded() {
+        let mut config = local_config(0);
+        config.max_prefixes = Some(policy(
+            2,
+            MaxPrefixAction::Teardown {
+                restart_after: Some(Duration::from_secs(300)),
+            },
+        ));
+        let (session, mut peer) = establish_with_fake_peer(config, 0).await;
+        let (observer, mut events) = ChannelObserver::new(16);
+        let run = tokio::spawn(session.run(observer));
+
+        for prefix in ["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24"] {
+            peer.send(announce(prefix)).await;
+        }
+        let Some(BgpMessage::Notification(notification)) = peer.recv().await else {
+            panic!("expected a NOTIFICATION");
+        };
+        assert_eq!(
+            notification.error_codes,
+            NotificationErrorCode::Cease(CeaseSubErr::MaximumNumberOfPrefixesReached)
+        );
+        assert_eq!(notification.data, vec![0, 1, 1, 0, 0, 0, 2]);
+        assert_eq!(
+            run.await.unwrap().unwrap(),
+            SessionEnd::LocalShutdown(ShutdownReason::MaximumPrefixes {
+                afi: Afi::Ipv4,
+                safi: Safi::Unicast,
+                limit: 2
+            })
+        );
+
+        let mut limits = vec![];
+        while let Some(event) = events.recv().await {
+            if let SessionEvent::MaxPrefixes(_, event) = event {
+                limits.push(event);
+            }
+        }
+        assert!(matches!(
+            limits[..],
+            [
+                MaxPrefixEvent::Warning { count: 1, .. },
+                MaxPrefixEvent::Exceeded { count: 3, .. }
+            ]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_warn_only_keeps_session() {
+        let mut config = local_config(0);
+        config.max_prefixes = Some(policy(1, MaxPrefixAction::WarnOnly));
+        let (mut session, mut peer) = establish_with_fake_peer(config, 0).await;
+
+        peer.send(announce("10.0.0.0/24")).await;
+        peer.send(announce("10.0.1.0/24")).await;
+        assert!(matches!(session.recv().await, Some(BgpMessage::Update(_))));
+        assert!(matches!(session.recv().await, Some(BgpMessage::Update(_))));
+
+        peer.send(BgpMessage::Keepalive).await;
+        assert_eq!(session.recv().await, Some(BgpMessage::Keepalive));
+    }
+}
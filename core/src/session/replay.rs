@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use thiserror::Error;
+use tokio::time::{self, Instant};
+
+use crate::bgp_message::{BgpMessage, MessageDecodeError};
+use crate::journal::{Direction, JournalError, JournalReader};
+use crate::mrt::{Bgp4mp, Bgp4mpBody, MrtBody, MrtError, MrtReader};
+use crate::timestamped::Timestamped;
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("replay I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+    #[error(transparent)]
+    Mrt(#[from] MrtError),
+    #[error("recorded message from {peer} doesn't decode: {err}")]
+    Decode {
+        peer: IpAddr,
+        #[source]
+        err: MessageDecodeError,
+    },
+}
+
+/// How fast [`ReplaySource`] hands out recorded messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    AsFastAsPossible,
+    /// Keeps the recorded gaps between messages
+    RealTime,
+    /// Shrinks the recorded gaps by the factor, 2.0 replays twice as fast as recorded
+    Scaled(f64),
+}
+
+/// Recorded traffic handed out as a live session would, from a [`Journal`] or the BGP4MP
+/// records of an MRT file.
+///
+/// Messages keep their recorded time in [`Timestamped::received`], so everything that takes
+/// its time from message timestamps sees the original timeline. Only messages received from a
+/// peer are replayed, those we sent are skipped.
+///
+/// [`Journal`]: crate::journal::Journal
+pub struct ReplaySource {
+    records: Box<dyn Iterator<Item = Result<Recorded, ReplayError>> + Send>,
+    speed: ReplaySpeed,
+    peers: HashMap<IpAddr, IpAddr>,
+    /// First recorded time and when it was replayed
+    start: Option<(SystemTime, Instant)>,
+}
+
+struct Recorded {
+    received: SystemTime,
+    peer: IpAddr,
+    message: BgpMessage,
+}
+
+impl ReplaySource {
+    /// Replays the journal in `dir`
+    pub fn journal(dir: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let records = JournalReader::open(dir)?.filter_map(|record| match record {
+            Ok(record) if record.direction == Direction::Received => Some(
+                record
+                    .message()
+                    .map(|message| Recorded {
+                        received: record.received,
+                        peer: record.peer,
+                        message: message.value,
+                    })
+                    .map_err(|err| ReplayError::Decode {
+                        peer: record.peer,
+                        err,
+                    }),
+            ),
+            Ok(_) => None,
+            Err(err) => Some(Err(err.into())),
+        });
+        Ok(Self::new(records))
+    }
+
+    /// Replays the MRT file at `path`
+    pub fn mrt(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let records = MrtReader::open(path)?.filter_map(|record| {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => return Some(Err(err.into())),
+            };
+            match record.body {
+                MrtBody::Bgp4mp(Bgp4mp {
+                    peer,
+                    body:
+                        Bgp4mpBody::Message {
+                            direction: Direction::Received,
+                            message,
+                        },
+                    ..
+                }) => Some(Ok(Recorded {
+                    received: record.header.timestamp,
+                    peer: peer.peer_addr,
+                    message,
+                })),
+                // State changes, messages we sent and table dumps
+                _ => None,
+            }
+        });
+        Ok(Self::new(records))
+    }
+
+    fn new(records: impl Iterator<Item = Result<Recorded, ReplayError>> + Send + 'static) -> Self {
+        ReplaySource {
+            records: Box::new(records),
+            speed: ReplaySpeed::AsFastAsPossible,
+            peers: HashMap::new(),
+            start: None,
+        }
+    }
+
+    pub fn speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Hands out the messages recorded from `recorded` as coming from `peer`
+    pub fn map_peer(mut self, recorded: IpAddr, peer: IpAddr) -> Self {
+        self.peers.insert(recorded.to_canonical(), peer);
+        self
+    }
+
+    /// The next message and the peer it came from, `None` once the recording is exhausted.
+    ///
+    /// Errors don't end the replay, the recording continues with the next message.
+    pub async fn next(&mut self) -> Option<Result<(IpAddr, Timestamped<BgpMessage>), ReplayError>> {
+        let recorded = match self.records.next()? {
+            Ok(recorded) => recorded,
+            Err(err) => return Some(Err(err)),
+        };
+        self.pace(recorded.received).await;
+
+        let peer = match self.peers.get(&recorded.peer.to_canonical()) {
+            Some(peer) => *peer,
+            None => recorded.peer,
+        };
+        Some(Ok((
+            peer,
+            Timestamped::at(recorded.received, recorded.message),
+        )))
+    }
+
+    /// Waits until a message recorded at `received` is due
+    async fn pace(&mut self, received: SystemTime) {
+        let factor = match self.speed {
+            ReplaySpeed::AsFastAsPossible => return,
+            ReplaySpeed::RealTime => 1.0,
+            ReplaySpeed::Scaled(factor) => factor,
+        };
+        let (first, started) = *self.start.get_or_insert((received, Instant::now()));
+        // Messages recorded out of order are replayed right away
+        if let Ok(offset) = received.duration_since(first) {
+            time::sleep_until(started + offset.div_f64(factor)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::journal::{Journal, JournalRecord, RotationPolicy};
+
+    pub(crate) fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn test_mrt_messages() {
+        let mut source = ReplaySource::mrt(fixture("bgp4mp_replay.mrt")).unwrap();
+        let mut replayed = vec![];
+        while let Some(next) = source.next().await {
+            let (peer, message) = next.unwrap();
+            let since_epoch = message
+                .received
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+            replayed.push((peer, message.value.message_type(), since_epoch));
+        }
+
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "2001:db8::2".parse().unwrap();
+        let at = |secs: u64, micros| {
+            Duration::from_secs(1_700_000_000 + secs) + Duration::from_micros(micros)
+        };
+        use crate::header::BgpMessageType::*;
+        assert_eq!(
+            replayed,
+            vec![
+                (peer, Open, at(0, 0)),
+                (peer, Update, at(1, 0)),
+                (other, Update, at(2, 0)),
+                (peer, Keepalive, at(3, 0)),
+                (peer, Update, at(4, 0)),
+                (peer, Update, at(5, 250_000)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_journal_paced_and_mapped() {
+        let dir = std::env::temp_dir().join(format!("bgp-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recorded: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut journal = Journal::open(&dir, RotationPolicy::default()).unwrap();
+        for secs in [0, 10, 30] {
+            let received = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs);
+            let message = Timestamped::at(received, BgpMessage::Keepalive.to_bytes());
+            journal
+                .append(&JournalRecord::received(recorded, message))
+                .unwrap();
+        }
+        journal.flush().unwrap();
+
+        let configured: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut source = ReplaySource::journal(&dir)
+            .unwrap()
+            .speed(ReplaySpeed::Scaled(10.0))
+            .map_peer(recorded, configured);
+        let start = Instant::now();
+        let mut offsets = vec![];
+        while let Some(next) = source.next().await {
+            let (peer, message) = next.unwrap();
+            assert_eq!((peer, message.value), (configured, BgpMessage::Keepalive));
+            offsets.push(start.elapsed());
+        }
+        assert_eq!(offsets, [0, 1, 3].map(Duration::from_secs).to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
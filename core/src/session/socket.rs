@@ -0,0 +1,577 @@
+use std::io;
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::{mem, os::fd::AsRawFd};
+
+use tokio::net::{TcpListener, TcpSocket};
+
+use super::config::{ConfigError, PeerConfig};
+
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Creates the socket for dialing `config`'s peer with every configured socket option applied
+pub(crate) fn peer_socket(config: &PeerConfig) -> Result<TcpSocket, ConfigError> {
+    let socket = new_socket(config.remote_socket_addr()).map_err(socket_error("socket"))?;
+
+    #[cfg(feature = "md5sig")]
+    if let Some(password) = &config.md5_password {
+        md5sig::set(&socket, config.remote_addr, password)?;
+    }
+    if let Some(min_ttl) = config.ttl_security {
+        set_ttl_security(
+            &socket,
+            config.remote_addr.to_canonical().is_ipv6(),
+            min_ttl,
+        )?;
+    }
+    if let Some(device) = &config.bind_device {
+        bind_device(&socket, device)?;
+    }
+    if let Some(addr) = config.local_addr {
+        socket.bind(addr).map_err(|err| ConfigError::LocalAddr {
+            addr,
+            reason: err.to_string(),
+        })?;
+    }
+
+    Ok(socket)
+}
+
+/// Binds a listening socket, installing the socket options every expected peer needs before
+/// any connection is accepted
+pub(crate) fn listen_socket<'a>(
+    addr: SocketAddr,
+    peers: impl IntoIterator<Item = &'a PeerConfig>,
+) -> Result<TcpListener, ConfigError> {
+    let socket = new_socket(addr).map_err(socket_error("socket"))?;
+    socket
+        .set_reuseaddr(true)
+        .map_err(socket_error("SO_REUSEADDR"))?;
+
+    // Peers without a device don't mind, but those with one must agree on it
+    let mut device: Option<&str> = None;
+    for config in peers {
+        listener_peer_options(&socket, addr.is_ipv6(), config)?;
+        match (device, config.bind_device.as_deref()) {
+            (Some(listener), Some(peer)) if listener != peer => {
+                return Err(ConfigError::ConflictingBindDevice {
+                    listener: Some(listener.to_string()),
+                    peer: peer.to_string(),
+                });
+            }
+            (None, Some(peer)) => device = Some(peer),
+            _ => {}
+        }
+    }
+    if let Some(device) = device {
+        bind_device(&socket, device)?;
+    }
+
+    socket.bind(addr).map_err(socket_error("bind"))?;
+    socket
+        .listen(LISTEN_BACKLOG)
+        .map_err(socket_error("listen"))
+}
+
+/// Installs the options for a peer configured after the listener started
+pub(crate) fn add_listener_peer(
+    listener: &TcpListener,
+    config: &PeerConfig,
+) -> Result<(), ConfigError> {
+    let ipv6 = listener
+        .local_addr()
+        .map_err(socket_error("getsockname"))?
+        .is_ipv6();
+    // Rebinding the listener would cut off the peers already using it
+    if let Some(peer) = &config.bind_device {
+        let device = bound_device(listener)?;
+        if device.as_ref() != Some(peer) {
+            return Err(ConfigError::ConflictingBindDevice {
+                listener: device,
+                peer: peer.clone(),
+            });
+        }
+    }
+    listener_peer_options(listener, ipv6, config)
+}
+
+/// Restricts the socket to a network device or VRF
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &impl AsRawFd, device: &str) -> Result<(), ConfigError> {
+    // SAFETY: the pointer and length describe the live bytes of `device`
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            device.as_ptr().cast(),
+            device.len() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        return Err(ConfigError::SocketOption {
+            option: "SO_BINDTODEVICE",
+            reason: format!("{device}: {err}"),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device<S>(_socket: &S, _device: &str) -> Result<(), ConfigError> {
+    Err(ConfigError::Unsupported("SO_BINDTODEVICE"))
+}
+
+#[cfg(target_os = "linux")]
+fn bound_device(socket: &impl AsRawFd) -> Result<Option<String>, ConfigError> {
+    let mut name = [0u8; libc::IFNAMSIZ];
+    let mut len = name.len() as libc::socklen_t;
+    // SAFETY: the pointer and length describe a live, writable buffer
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(socket_error("SO_BINDTODEVICE")(io::Error::last_os_error()));
+    }
+
+    let name = &name[..len as usize];
+    let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+    Ok((!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bound_device<S>(_socket: &S) -> Result<Option<String>, ConfigError> {
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn listener_peer_options(
+    listener: &impl AsRawFd,
+    ipv6: bool,
+    config: &PeerConfig,
+) -> Result<(), ConfigError> {
+    #[cfg(feature = "md5sig")]
+    if let Some(password) = &config.md5_password {
+        md5sig::set(listener, config.remote_addr, password)?;
+    }
+    // The minimum is applied per accepted connection, but the SYN-ACK must already leave with
+    // the maximum TTL or a GTSM peer discards it
+    if config.ttl_security.is_some() {
+        set_max_ttl(listener, ipv6)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn listener_peer_options<S>(
+    listener: &S,
+    ipv6: bool,
+    config: &PeerConfig,
+) -> Result<(), ConfigError> {
+    #[cfg(feature = "md5sig")]
+    if let Some(password) = &config.md5_password {
+        md5sig::set(listener, config.remote_addr, password)?;
+    }
+    if config.ttl_security.is_some() {
+        set_max_ttl(listener, ipv6)?;
+    }
+    Ok(())
+}
+
+/// Applies GTSM (RFC 5082): we send with the maximum TTL and the kernel discards any segment
+/// from the peer arriving with a TTL below `min_ttl`.
+///
+/// There is no portable fallback: a stream socket can't observe the TTL of individual inbound
+/// segments, so platforms without `IP_MINTTL` report [`ConfigError::Unsupported`] rather than
+/// silently running the session unprotected.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_ttl_security(
+    socket: &impl AsRawFd,
+    ipv6: bool,
+    min_ttl: u8,
+) -> Result<(), ConfigError> {
+    set_max_ttl(socket, ipv6)?;
+    let (level, option) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            ("IPV6_MINHOPCOUNT", libc::IPV6_MINHOPCOUNT),
+        )
+    } else {
+        (libc::IPPROTO_IP, ("IP_MINTTL", libc::IP_MINTTL))
+    };
+    setsockopt(socket, level, option, &(min_ttl as libc::c_int))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_ttl_security<S>(
+    _socket: &S,
+    _ipv6: bool,
+    _min_ttl: u8,
+) -> Result<(), ConfigError> {
+    Err(ConfigError::Unsupported("IP_MINTTL"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_max_ttl(socket: &impl AsRawFd, ipv6: bool) -> Result<(), ConfigError> {
+    let (level, option) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            ("IPV6_UNICAST_HOPS", libc::IPV6_UNICAST_HOPS),
+        )
+    } else {
+        (libc::IPPROTO_IP, ("IP_TTL", libc::IP_TTL))
+    };
+    setsockopt(socket, level, option, &(u8::MAX as libc::c_int))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_max_ttl<S>(_socket: &S, _ipv6: bool) -> Result<(), ConfigError> {
+    Err(ConfigError::Unsupported("IP_MINTTL"))
+}
+
+#[cfg(target_os = "linux")]
+fn setsockopt<T>(
+    socket: &impl AsRawFd,
+    level: libc::c_int,
+    (name, option): (&'static str, libc::c_int),
+    value: &T,
+) -> Result<(), ConfigError> {
+    // SAFETY: the pointer and length describe a live, initialised T
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            (value as *const T).cast(),
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(socket_error(name)(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn new_socket(addr: SocketAddr) -> io::Result<TcpSocket> {
+    match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }
+}
+
+fn socket_error(option: &'static str) -> impl Fn(io::Error) -> ConfigError {
+    move |err| ConfigError::SocketOption {
+        option,
+        reason: err.to_string(),
+    }
+}
+
+/// TCP MD5 signatures (RFC 2385)
+#[cfg(feature = "md5sig")]
+mod md5sig {
+    use std::net::IpAddr;
+    #[cfg(target_os = "linux")]
+    use std::os::fd::AsRawFd;
+
+    use super::super::config::ConfigError;
+
+    #[cfg(target_os = "linux")]
+    pub(super) fn set(
+        socket: &impl AsRawFd,
+        peer: IpAddr,
+        password: &str,
+    ) -> Result<(), ConfigError> {
+        use std::{mem, ptr};
+
+        use super::super::config::MD5_MAX_KEY_LEN;
+
+        /// `struct tcp_md5sig` from linux/tcp.h, which libc doesn't expose
+        #[repr(C)]
+        struct TcpMd5Sig {
+            addr: libc::sockaddr_storage,
+            flags: u8,
+            prefix_len: u8,
+            key_len: u16,
+            ifindex: libc::c_int,
+            key: [u8; MD5_MAX_KEY_LEN],
+        }
+
+        // SAFETY: every field is plain data for which all zeroes is valid
+        let mut sig: TcpMd5Sig = unsafe { mem::zeroed() };
+        match peer {
+            IpAddr::V4(ip) => {
+                let addr = libc::sockaddr_in {
+                    sin_family: libc::AF_INET as libc::sa_family_t,
+                    sin_port: 0,
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from(ip).to_be(),
+                    },
+                    sin_zero: [0; 8],
+                };
+                // SAFETY: sockaddr_storage is large and aligned enough for any sockaddr
+                unsafe { ptr::write(ptr::addr_of_mut!(sig.addr).cast(), addr) };
+            }
+            IpAddr::V6(ip) => {
+                let addr = libc::sockaddr_in6 {
+                    sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                    sin6_port: 0,
+                    sin6_flowinfo: 0,
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: ip.octets(),
+                    },
+                    sin6_scope_id: 0,
+                };
+                // SAFETY: sockaddr_storage is large and aligned enough for any sockaddr
+                unsafe { ptr::write(ptr::addr_of_mut!(sig.addr).cast(), addr) };
+            }
+        }
+
+        let key = password.as_bytes();
+        if key.len() > MD5_MAX_KEY_LEN {
+            return Err(ConfigError::Md5PasswordTooLong(key.len()));
+        }
+        sig.key_len = key.len() as u16;
+        sig.key[..key.len()].copy_from_slice(key);
+
+        super::setsockopt(
+            socket,
+            libc::IPPROTO_TCP,
+            ("TCP_MD5SIG", libc::TCP_MD5SIG),
+            &sig,
+        )
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn set<S>(_socket: &S, _peer: IpAddr, _password: &str) -> Result<(), ConfigError> {
+        Err(ConfigError::Unsupported("TCP_MD5SIG"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use tokio::net::TcpStream;
+    use tokio::time;
+
+    use crate::session::connector::establish;
+    use crate::session::{BgpListener, Peer, SessionError};
+
+    fn localhost_config() -> PeerConfig {
+        PeerConfig::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 1),
+        )
+    }
+
+    #[cfg(feature = "md5sig")]
+    fn config(password: &str) -> PeerConfig {
+        let mut config = localhost_config();
+        config.md5_password = Some(password.to_string());
+        config
+    }
+
+    #[test]
+    fn test_ttl_security_validation() {
+        let builder = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        assert_eq!(
+            builder.clone().ttl_security(0).build(),
+            Err(ConfigError::InvalidTtlSecurity)
+        );
+        assert_eq!(
+            builder.ttl_security(254).build().unwrap().ttl_security,
+            Some(254)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ttl_security_session() {
+        let mut config = localhost_config();
+        config.ttl_security = Some(255);
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), [config.clone()])
+            .await
+            .unwrap();
+        config.remote_port = listener.local_addr().port();
+
+        let socket = peer_socket(&config).unwrap();
+        let stream = socket.connect(config.remote_socket_addr()).await.unwrap();
+        assert_eq!(stream.ttl().unwrap(), 255);
+        drop(stream);
+
+        let session = Peer::connect(config).await.unwrap();
+        assert!(listener.accept().await.is_some());
+        session.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ttl_security_drops_low_ttl() {
+        let mut config = localhost_config();
+        config.ttl_security = Some(255);
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), [config.clone()])
+            .await
+            .unwrap();
+        config.remote_port = listener.local_addr().port();
+        config.ttl_security = None;
+
+        // Our OPEN leaves with the default TTL and is discarded, so the exchange stalls
+        let stream = TcpStream::connect(config.remote_socket_addr())
+            .await
+            .unwrap();
+        stream.set_ttl(64).unwrap();
+        let dial = tokio::spawn(async move { establish(stream, &config).await });
+        assert!(
+            time::timeout(Duration::from_millis(500), listener.accept())
+                .await
+                .is_err()
+        );
+        dial.abort();
+    }
+
+    #[cfg(feature = "md5sig")]
+    /// Kernels built without CONFIG_TCP_MD5SIG reject the option; that must be a typed error
+    fn md5_supported(result: &Result<TcpSocket, ConfigError>) -> bool {
+        match result {
+            Ok(_) => true,
+            Err(ConfigError::SocketOption { option, .. }) => {
+                assert_eq!(*option, "TCP_MD5SIG");
+                false
+            }
+            Err(ConfigError::Unsupported(option)) => {
+                assert_eq!(*option, "TCP_MD5SIG");
+                false
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    #[cfg(feature = "md5sig")]
+    #[tokio::test]
+    async fn test_md5_session() {
+        let socket = peer_socket(&config("secret"));
+        if !md5_supported(&socket) {
+            return;
+        }
+
+        let passive = config("secret");
+        let listener = listen_socket((Ipv4Addr::LOCALHOST, 0).into(), [&passive]).unwrap();
+        let mut active = config("secret");
+        active.remote_port = listener.local_addr().unwrap().port();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            establish(stream, &passive).await
+        });
+        let stream = socket
+            .unwrap()
+            .connect(active.remote_socket_addr())
+            .await
+            .unwrap();
+        assert!(establish(stream, &active).await.is_ok());
+        assert!(accept.await.unwrap().is_ok());
+    }
+
+    #[cfg(feature = "md5sig")]
+    #[test]
+    fn test_md5_password_too_long() {
+        let builder = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        assert_eq!(
+            builder.md5_password("x".repeat(81)).build(),
+            Err(ConfigError::Md5PasswordTooLong(81))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_addr_sources_session() {
+        let source = Ipv4Addr::new(127, 0, 0, 2);
+        let mut expected = localhost_config();
+        expected.remote_addr = IpAddr::V4(source);
+        let mut listener = BgpListener::bind((Ipv4Addr::LOCALHOST, 0), [expected])
+            .await
+            .unwrap();
+
+        let mut config = localhost_config();
+        config.remote_port = listener.local_addr().port();
+        config.local_addr = Some(SocketAddr::new(IpAddr::V4(source), 0));
+        let session = Peer::connect(config).await.unwrap();
+
+        let accepted = listener.accept().await.unwrap();
+        assert_eq!(accepted.peer_addr().ip(), IpAddr::V4(source));
+        session.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_addr_not_on_host() {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 0);
+        let mut config = localhost_config();
+        config.local_addr = Some(addr);
+        match Peer::connect(config).await {
+            Err(SessionError::Config(ConfigError::LocalAddr { addr: failed, .. })) => {
+                assert_eq!(failed, addr)
+            }
+            other => panic!("Unexpected connect result {:?}", other.map(|_| ())),
+        }
+
+        let builder = PeerConfig::builder(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            65000,
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        let ipv6 = SocketAddr::new(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST), 0);
+        assert!(matches!(
+            builder.local_addr(ipv6).build(),
+            Err(ConfigError::LocalAddr { .. })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_device() {
+        let mut config = localhost_config();
+        config.bind_device = Some("nonexistent0".to_string());
+        assert!(matches!(
+            peer_socket(&config),
+            Err(ConfigError::SocketOption {
+                option: "SO_BINDTODEVICE",
+                ..
+            })
+        ));
+
+        let mut other = localhost_config();
+        other.remote_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        other.bind_device = Some("lo".to_string());
+        config.bind_device = Some("eth0".to_string());
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        assert!(matches!(
+            listen_socket(addr, [&config, &other]),
+            Err(ConfigError::ConflictingBindDevice { .. })
+        ));
+
+        // A listener without a device can't take a peer that needs one
+        let listener = listen_socket(addr, []).unwrap();
+        assert_eq!(
+            add_listener_peer(&listener, &other),
+            Err(ConfigError::ConflictingBindDevice {
+                listener: None,
+                peer: "lo".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::attribute::AttributeValue;
+use crate::bgp_message::BgpMessage;
+use crate::header::BgpMessageType;
+use crate::monitor::RateWindow;
+use crate::notification_message::NotificationMessage;
+use crate::update_message::UpdateMessage;
+
+/// How many NOTIFICATIONs [`SessionStats::notifications`] remembers
+pub const NOTIFICATION_HISTORY: usize = 16;
+
+/// Windows [`SessionStats::rates`] averages over
+pub const RATE_WINDOWS: [Duration; 3] = [
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+];
+
+/// Message types counted separately, in type code order
+const COUNTED_TYPES: usize = 5;
+
+/// Traffic counters of a single session.
+///
+/// Cloning is cheap and every clone reads the same live counters, so status reporting never
+/// has to coordinate with the session task.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    messages_in: [AtomicU64; COUNTED_TYPES],
+    messages_out: [AtomicU64; COUNTED_TYPES],
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    updates_with_nlri: AtomicU64,
+    updates_withdraw_only: AtomicU64,
+    parse_errors: AtomicU64,
+    /// Microseconds since the epoch, zero when no message of the type arrived yet
+    last_received: [AtomicU64; COUNTED_TYPES],
+    notifications: Mutex<VecDeque<NotificationRecord>>,
+    rates: Rates,
+}
+
+#[derive(Debug)]
+struct Rates {
+    updates: RateWindow,
+    withdrawals: RateWindow,
+    prefixes_changed: RateWindow,
+}
+
+/// UPDATE traffic per second, averaged over one of the [`RATE_WINDOWS`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateRates {
+    pub window: Duration,
+    pub updates: f64,
+    /// Prefixes withdrawn
+    pub withdrawals: f64,
+    /// Prefixes announced or withdrawn
+    pub prefixes_changed: f64,
+}
+
+/// A NOTIFICATION exchanged during the session
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationRecord {
+    pub at: SystemTime,
+    /// Whether we sent it rather than the peer
+    pub sent: bool,
+    pub notification: NotificationMessage,
+}
+
+impl Default for Rates {
+    fn default() -> Self {
+        // The runtime's clock, so that tests can control it
+        let start = tokio::time::Instant::now().into_std();
+        let seconds = RATE_WINDOWS[RATE_WINDOWS.len() - 1].as_secs() as usize;
+        Rates {
+            updates: RateWindow::new(start, seconds),
+            withdrawals: RateWindow::new(start, seconds),
+            prefixes_changed: RateWindow::new(start, seconds),
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats::default()
+    }
+
+    /// UPDATE traffic received over each of the [`RATE_WINDOWS`] up to now
+    pub fn rates(&self) -> Vec<UpdateRates> {
+        self.rates_at(tokio::time::Instant::now().into_std())
+    }
+
+    pub fn rates_at(&self, now: Instant) -> Vec<UpdateRates> {
+        let rates = &self.inner.rates;
+        RATE_WINDOWS
+            .iter()
+            .map(|&window| UpdateRates {
+                window,
+                updates: rates.updates.rate(now, window),
+                withdrawals: rates.withdrawals.rate(now, window),
+                prefixes_changed: rates.prefixes_changed.rate(now, window),
+            })
+            .collect()
+    }
+
+    pub fn messages_in(&self, message_type: BgpMessageType) -> u64 {
+        index(message_type).map_or(0, |i| self.inner.messages_in[i].load(Ordering::Relaxed))
+    }
+
+    pub fn messages_out(&self, message_type: BgpMessageType) -> u64 {
+        index(message_type).map_or(0, |i| self.inner.messages_out[i].load(Ordering::Relaxed))
+    }
+
+    /// Bytes of every received message including headers
+    pub fn bytes_in(&self) -> u64 {
+        self.inner.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.inner.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Received UPDATEs announcing at least one prefix
+    pub fn updates_with_nlri(&self) -> u64 {
+        self.inner.updates_with_nlri.load(Ordering::Relaxed)
+    }
+
+    /// Received UPDATEs that only withdraw prefixes
+    pub fn updates_withdraw_only(&self) -> u64 {
+        self.inner.updates_withdraw_only.load(Ordering::Relaxed)
+    }
+
+    /// Received messages that failed to decode
+    pub fn parse_errors(&self) -> u64 {
+        self.inner.parse_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn last_received(&self, message_type: BgpMessageType) -> Option<SystemTime> {
+        let micros = self.inner.last_received[index(message_type)?].load(Ordering::Relaxed);
+        (micros != 0).then(|| UNIX_EPOCH + Duration::from_micros(micros))
+    }
+
+    /// The most recent NOTIFICATIONs in either direction, oldest first
+    pub fn notifications(&self) -> Vec<NotificationRecord> {
+        self.inner
+            .notifications
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn record_received(&self, message: &BgpMessage, length: usize) {
+        self.inner
+            .bytes_in
+            .fetch_add(length as u64, Ordering::Relaxed);
+        let Some(i) = index(message.message_type()) else {
+            return;
+        };
+        self.inner.messages_in[i].fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now();
+        let micros = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.inner.last_received[i].store(micros.max(1), Ordering::Relaxed);
+
+        if let BgpMessage::Update(update) = message {
+            self.record_rates(update);
+        }
+        match message {
+            BgpMessage::Update(update) if !update.nlri.is_empty() => {
+                self.inner.updates_with_nlri.fetch_add(1, Ordering::Relaxed);
+            }
+            BgpMessage::Update(update) if !update.withdrawn_routes.is_empty() => {
+                self.inner
+                    .updates_withdraw_only
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            BgpMessage::Notification(notification) => {
+                self.remember(now, false, notification);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn record_sent(&self, message: &BgpMessage, length: usize) {
+        self.inner
+            .bytes_out
+            .fetch_add(length as u64, Ordering::Relaxed);
+        if let Some(i) = index(message.message_type()) {
+            self.inner.messages_out[i].fetch_add(1, Ordering::Relaxed);
+        }
+        if let BgpMessage::Notification(notification) = message {
+            self.remember(SystemTime::now(), true, notification);
+        }
+    }
+
+    pub(crate) fn record_parse_error(&self, length: usize) {
+        self.inner
+            .bytes_in
+            .fetch_add(length as u64, Ordering::Relaxed);
+        self.inner.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_rates(&self, update: &UpdateMessage) {
+        let (mut announced, mut withdrawn) = (update.nlri.len(), update.withdrawn_routes.len());
+        for attribute in &update.path_attributes {
+            match &attribute.value {
+                AttributeValue::MpReachNlri(mp_reach) => announced += mp_reach.nlri.len(),
+                AttributeValue::MpUnreachNlri(mp_unreach) => {
+                    withdrawn += mp_unreach.withdrawn_routes.len()
+                }
+                _ => {}
+            }
+        }
+        let now = tokio::time::Instant::now().into_std();
+        let rates = &self.inner.rates;
+        rates.updates.record(now, 1);
+        if withdrawn > 0 {
+            rates.withdrawals.record(now, withdrawn as u64);
+        }
+        if announced + withdrawn > 0 {
+            rates
+                .prefixes_changed
+                .record(now, (announced + withdrawn) as u64);
+        }
+    }
+
+    fn remember(&self, at: SystemTime, sent: bool, notification: &NotificationMessage) {
+        let mut notifications = self.inner.notifications.lock().unwrap();
+        if notifications.len() == NOTIFICATION_HISTORY {
+            notifications.pop_front();
+        }
+        notifications.push_back(NotificationRecord {
+            at,
+            sent,
+            notification: notification.clone(),
+        });
+    }
+}
+
+fn index(message_type: BgpMessageType) -> Option<usize> {
+    match message_type {
+        BgpMessageType::Unknown(_) => None,
+        known => Some(u8::from(&known) as usize - 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+    use tokio::io::AsyncWriteExt;
+
+    use crate::notification_message::NotificationErrorCode;
+    use crate::session::SessionError;
+    use crate::session::established::test::{establish_with_fake_peer, local_config};
+    use crate::update_message::UpdateMessage;
+
+    fn update(bytes: &'static [u8]) -> BgpMessage {
+        BgpMessage::Update(UpdateMessage::try_decode(&mut Bytes::from_static(bytes)).unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_counters_match_exchange() {
+        // A zero hold time keeps timer driven KEEPALIVEs out of the exchange
+        let (session, mut peer) = establish_with_fake_peer(local_config(0), 0).await;
+        let stats = session.stats();
+        let local_open = BgpMessage::Open(session.local_open().clone())
+            .to_bytes()
+            .len();
+        let remote_open = BgpMessage::Open(session.remote_open().clone())
+            .to_bytes()
+            .len();
+
+        let announce = update(&[0, 0, 0, 0, 24, 192, 0, 2]);
+        let withdraw = update(&[0, 4, 24, 192, 0, 2, 0, 0]);
+        peer.send(announce.clone()).await;
+        peer.send(withdraw.clone()).await;
+        peer.send(BgpMessage::Keepalive).await;
+        // A ROUTE-REFRESH with a truncated body
+        let malformed = [&[0xff; 16][..], &[0, 22, 5, 0, 1, 0]].concat();
+        peer.writer.write_all(&malformed).await.unwrap();
+
+        let Some(BgpMessage::Notification(sent)) = peer.recv().await else {
+            panic!("expected a NOTIFICATION");
+        };
+        assert!(matches!(
+            session.close().await,
+            Err(SessionError::Decode(_))
+        ));
+
+        assert_eq!(stats.messages_in(BgpMessageType::Open), 1);
+        assert_eq!(stats.messages_in(BgpMessageType::Keepalive), 2);
+        assert_eq!(stats.messages_in(BgpMessageType::Update), 2);
+        assert_eq!(stats.messages_in(BgpMessageType::Notification), 0);
+        assert_eq!(stats.messages_out(BgpMessageType::Open), 1);
+        assert_eq!(stats.messages_out(BgpMessageType::Keepalive), 1);
+        assert_eq!(stats.messages_out(BgpMessageType::Notification), 1);
+        assert_eq!(stats.updates_with_nlri(), 1);
+        assert_eq!(stats.updates_withdraw_only(), 1);
+        assert_eq!(stats.parse_errors(), 1);
+
+        let updates = announce.to_bytes().len() + withdraw.to_bytes().len();
+        assert_eq!(
+            stats.bytes_in(),
+            (remote_open + 2 * 19 + updates + malformed.len()) as u64
+        );
+        let notification = BgpMessage::Notification(sent.clone()).to_bytes().len();
+        assert_eq!(stats.bytes_out(), (local_open + 19 + notification) as u64);
+
+        assert!(stats.last_received(BgpMessageType::Update).is_some());
+        assert_eq!(stats.last_received(BgpMessageType::Notification), None);
+        let history = stats.notifications();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].sent);
+        assert_eq!(history[0].notification, sent);
+        assert!(matches!(
+            sent.error_codes,
+            NotificationErrorCode::Unknown(7, 1)
+        ));
+    }
+
+    #[test]
+    fn test_notification_history_is_bounded() {
+        let stats = SessionStats::new();
+        for _ in 0..NOTIFICATION_HISTORY + 4 {
+            let notification =
+                NotificationMessage::new(NotificationErrorCode::HoldTimeExpired, vec![]);
+            stats.record_sent(&BgpMessage::Notification(notification), 21);
+        }
+        assert_eq!(stats.notifications().len(), NOTIFICATION_HISTORY);
+        assert_eq!(stats.messages_out(BgpMessageType::Notification), 20);
+        assert_eq!(stats.bytes_out(), 20 * 21);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_rates() {
+        let stats = SessionStats::new();
+        let announce = update(&[0, 0, 0, 0, 24, 192, 0, 2]);
+        let withdraw = update(&[0, 4, 24, 192, 0, 2, 0, 0]);
+        for i in 0..20 {
+            if i > 0 {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+            stats.record_received(&announce, 23);
+            stats.record_received(&withdraw, 23);
+            stats.record_received(&BgpMessage::Keepalive, 19);
+        }
+
+        // Ten seconds of traffic, all of it within every window
+        let rates = stats.rates();
+        assert_eq!(
+            rates.iter().map(|rate| rate.window).collect::<Vec<_>>(),
+            RATE_WINDOWS
+        );
+        assert_eq!((rates[0].updates, rates[0].withdrawals), (4.0, 2.0));
+        assert_eq!(rates[0].prefixes_changed, 4.0);
+        assert_eq!(rates[1].updates, 40.0 / 60.0);
+        assert_eq!(rates[2].prefixes_changed, 40.0 / 300.0);
+
+        // After a quiet minute only the longest window remembers it
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let rates = stats.rates();
+        assert_eq!((rates[0].updates, rates[1].updates), (0.0, 0.0));
+        assert_eq!(rates[2].updates, 40.0 / 300.0);
+    }
+}
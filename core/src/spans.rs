@@ -0,0 +1,202 @@
+//! Optional recording of where decoders find each field, for [`crate::annotate`].
+//!
+//! Decoders report a field once they have read it, labelled by a closure that only runs while
+//! recording. Positions are taken from the pointers of the `Bytes` being decoded, which all
+//! slice the buffer handed to [`record`], so nothing is threaded through the decoders and
+//! outside [`record`] each report costs a thread local check.
+
+use std::cell::{Cell, RefCell};
+
+use bytes::Bytes;
+
+/// A field, or a group of fields, as offsets into the recorded buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// Groups the span is nested in
+    pub depth: usize,
+    pub label: String,
+    pub group: bool,
+}
+
+struct Recorder {
+    base: usize,
+    len: usize,
+    spans: Vec<Span>,
+    /// Indices of the groups entered but not yet left
+    open: Vec<usize>,
+}
+
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+/// Runs `decode` over slices of `data`, collecting the spans its decoders report in the order
+/// they were reported. Groups a failing decoder didn't leave end with their last field.
+pub(crate) fn record<T>(data: &Bytes, decode: impl FnOnce() -> T) -> (T, Vec<Span>) {
+    /// Stops recording even if `decode` panics
+    struct Stop;
+
+    impl Drop for Stop {
+        fn drop(&mut self) {
+            ACTIVE.set(false);
+        }
+    }
+
+    RECORDER.set(Some(Recorder {
+        base: data.as_ptr() as usize,
+        len: data.len(),
+        spans: vec![],
+        open: vec![],
+    }));
+    ACTIVE.set(true);
+    let stop = Stop;
+    let result = decode();
+    drop(stop);
+
+    let mut recorder = RECORDER.take().unwrap();
+    while let Some(index) = recorder.open.pop() {
+        let end = recorder.spans[index..]
+            .iter()
+            .map(|span| span.end)
+            .max()
+            .unwrap_or(recorder.spans[index].start);
+        recorder.spans[index].end = end;
+    }
+    (result, recorder.spans)
+}
+
+pub(crate) fn active() -> bool {
+    ACTIVE.get()
+}
+
+/// Where `data` starts, to pass to [`since`] or [`enter`] once a field is read
+pub(crate) fn mark(data: &Bytes) -> usize {
+    data.as_ptr() as usize
+}
+
+/// The field from `start` up to where `data` now starts
+pub(crate) fn since(start: usize, data: &Bytes, label: impl FnOnce() -> String) {
+    if active() {
+        push(start, data.as_ptr() as usize, false, label);
+    }
+}
+
+/// The `len` octets just read from `data`
+pub(crate) fn consumed(data: &Bytes, len: usize, label: impl FnOnce() -> String) {
+    if active() {
+        let end = data.as_ptr() as usize;
+        push(end.wrapping_sub(len), end, false, label);
+    }
+}
+
+/// The `len` octets from `start`
+pub(crate) fn sized(start: usize, len: usize, label: impl FnOnce() -> String) {
+    if active() {
+        push(start, start.wrapping_add(len), false, label);
+    }
+}
+
+/// Opens a group at `start`, nesting the spans reported until [`leave`]
+pub(crate) fn enter(start: usize, label: impl FnOnce() -> String) {
+    if active() {
+        push(start, start, true, label);
+    }
+}
+
+/// Closes the innermost group where `data` now starts
+pub(crate) fn leave(data: &Bytes) {
+    if !active() {
+        return;
+    }
+    RECORDER.with_borrow_mut(|recorder| {
+        let Some(recorder) = recorder else {
+            return;
+        };
+        if let Some(index) = recorder.open.pop() {
+            let end = recorder.offset(data.as_ptr() as usize);
+            let span = &mut recorder.spans[index];
+            span.end = end.unwrap_or(span.start).max(span.start);
+        }
+    });
+}
+
+fn push(start: usize, end: usize, group: bool, label: impl FnOnce() -> String) {
+    RECORDER.with_borrow_mut(|recorder| {
+        let Some(recorder) = recorder else {
+            return;
+        };
+        // Slices of other buffers, such as copies made by a decoder, can't be placed
+        let (Some(start), Some(end)) = (recorder.offset(start), recorder.offset(end)) else {
+            return;
+        };
+        if end < start {
+            return;
+        }
+        let depth = recorder.open.len();
+        if group {
+            recorder.open.push(recorder.spans.len());
+        }
+        recorder.spans.push(Span {
+            start,
+            end,
+            depth,
+            label: label(),
+            group,
+        });
+    });
+}
+
+impl Recorder {
+    fn offset(&self, address: usize) -> Option<usize> {
+        address
+            .checked_sub(self.base)
+            .filter(|offset| *offset <= self.len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Buf;
+
+    #[test]
+    fn records_nested_fields_only_while_recording() {
+        let data = Bytes::from_static(&[1, 0, 2, 3, 4, 5]);
+        let ((), spans) = record(&data, || {
+            let mut data = data.clone();
+            let value = data.get_u8();
+            consumed(&data, 1, || format!("version {value}"));
+            enter(mark(&data), || "body".into());
+            let at = mark(&data);
+            data.advance(3);
+            since(at, &data, || "three".into());
+            leave(&data);
+            enter(mark(&data), || "unfinished".into());
+            sized(mark(&data), 2, || "rest".into());
+            // Not part of the buffer
+            consumed(&Bytes::from(vec![0; 4]), 4, || "copy".into());
+        });
+        let spans: Vec<_> = spans
+            .iter()
+            .map(|span| (span.start, span.end, span.depth, span.label.as_str()))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (0, 1, 0, "version 1"),
+                (1, 4, 0, "body"),
+                (1, 4, 1, "three"),
+                (4, 6, 0, "unfinished"),
+                (4, 6, 1, "rest"),
+            ]
+        );
+
+        assert!(!active());
+        let mut data = data.clone();
+        data.advance(1);
+        consumed(&data, 1, || panic!("labelled outside a recording"));
+    }
+}
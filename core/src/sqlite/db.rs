@@ -0,0 +1,310 @@
+//! Just enough of the SQLite C API for the sink, linked against the system libsqlite3
+
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::marker::PhantomData;
+use std::ptr;
+
+use super::SqliteError;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_NULL: c_int = 5;
+
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+
+/// Tells SQLite to copy bound text before the call returns
+const SQLITE_TRANSIENT: isize = -1;
+
+#[repr(C)]
+struct RawConnection {
+    _opaque: [u8; 0],
+}
+
+#[repr(C)]
+struct RawStatement {
+    _opaque: [u8; 0],
+}
+
+#[link(name = "sqlite3")]
+unsafe extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut RawConnection,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close_v2(db: *mut RawConnection) -> c_int;
+    fn sqlite3_errmsg(db: *mut RawConnection) -> *const c_char;
+    fn sqlite3_exec(
+        db: *mut RawConnection,
+        sql: *const c_char,
+        callback: *const (),
+        argument: *mut (),
+        errmsg: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut RawConnection,
+        sql: *const c_char,
+        len: c_int,
+        statement: *mut *mut RawStatement,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_step(statement: *mut RawStatement) -> c_int;
+    fn sqlite3_reset(statement: *mut RawStatement) -> c_int;
+    fn sqlite3_finalize(statement: *mut RawStatement) -> c_int;
+    fn sqlite3_bind_null(statement: *mut RawStatement, index: c_int) -> c_int;
+    fn sqlite3_bind_int64(statement: *mut RawStatement, index: c_int, value: i64) -> c_int;
+    // The destructor is a function pointer or one of the sentinels SQLITE_STATIC and
+    // SQLITE_TRANSIENT, only the latter is passed
+    fn sqlite3_bind_text(
+        statement: *mut RawStatement,
+        index: c_int,
+        value: *const c_char,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_column_type(statement: *mut RawStatement, column: c_int) -> c_int;
+    fn sqlite3_column_int64(statement: *mut RawStatement, column: c_int) -> i64;
+    fn sqlite3_column_text(statement: *mut RawStatement, column: c_int) -> *const u8;
+    fn sqlite3_column_bytes(statement: *mut RawStatement, column: c_int) -> c_int;
+}
+
+/// A value bound to a statement parameter
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Value<'a> {
+    Null,
+    Integer(i64),
+    Text(&'a str),
+}
+
+pub(super) struct Connection {
+    db: *mut RawConnection,
+}
+
+// SQLite is built thread safe, and a connection is only ever used from one thread at a time
+unsafe impl Send for Connection {}
+
+/// A prepared statement, reset after each use
+pub(super) struct Statement<'c> {
+    connection: &'c Connection,
+    statement: *mut RawStatement,
+}
+
+/// The current row of a query
+pub(super) struct Row<'s> {
+    statement: *mut RawStatement,
+    _statement: PhantomData<&'s Statement<'s>>,
+}
+
+impl Connection {
+    /// Opens or creates the database at `filename`, `:memory:` for a private in-memory one
+    pub fn open(filename: &str) -> Result<Self, SqliteError> {
+        let filename = CString::new(filename).map_err(|_| SqliteError::Sqlite {
+            code: 0,
+            message: "database name contains a NUL".into(),
+        })?;
+        let mut db = ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE;
+        let code = unsafe { sqlite3_open_v2(filename.as_ptr(), &mut db, flags, ptr::null()) };
+        // Even a failed open allocates a handle, which carries the error and must be closed
+        let connection = Connection { db };
+        match code {
+            SQLITE_OK => Ok(connection),
+            _ if db.is_null() => Err(SqliteError::Sqlite {
+                code,
+                message: "out of memory".into(),
+            }),
+            _ => Err(connection.error(code)),
+        }
+    }
+
+    /// Runs one or more statements separated by semicolons
+    pub fn execute_batch(&self, sql: &str) -> Result<(), SqliteError> {
+        let sql = self.c_string(sql)?;
+        let code = unsafe {
+            sqlite3_exec(
+                self.db,
+                sql.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        self.check(code)
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>, SqliteError> {
+        let sql = self.c_string(sql)?;
+        let mut statement = ptr::null_mut();
+        let code = unsafe {
+            sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut statement, ptr::null_mut())
+        };
+        self.check(code)?;
+        Ok(Statement {
+            connection: self,
+            statement,
+        })
+    }
+
+    /// Runs a query returning a single integer, such as a pragma
+    pub fn query_integer(&self, sql: &str) -> Result<Option<i64>, SqliteError> {
+        let mut statement = self.prepare(sql)?;
+        let values = statement.query(&[], |row| row.integer(0))?;
+        Ok(values.into_iter().next().flatten())
+    }
+
+    fn c_string(&self, sql: &str) -> Result<CString, SqliteError> {
+        CString::new(sql).map_err(|_| SqliteError::Sqlite {
+            code: 0,
+            message: "statement contains a NUL".into(),
+        })
+    }
+
+    fn check(&self, code: c_int) -> Result<(), SqliteError> {
+        match code {
+            SQLITE_OK => Ok(()),
+            _ => Err(self.error(code)),
+        }
+    }
+
+    fn error(&self, code: c_int) -> SqliteError {
+        let message = unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) };
+        SqliteError::Sqlite {
+            code,
+            message: message.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { sqlite3_close_v2(self.db) };
+    }
+}
+
+impl Statement<'_> {
+    /// Runs the statement to completion with `params` bound to `?1`, `?2`, ...
+    pub fn execute(&mut self, params: &[Value]) -> Result<(), SqliteError> {
+        self.query(params, |_| ()).map(drop)
+    }
+
+    /// Runs the statement with `params` bound, mapping each row it returns
+    pub fn query<T>(
+        &mut self,
+        params: &[Value],
+        mut map: impl FnMut(&Row) -> T,
+    ) -> Result<Vec<T>, SqliteError> {
+        let result = self.bind(params).and_then(|()| {
+            let mut rows = vec![];
+            loop {
+                match unsafe { sqlite3_step(self.statement) } {
+                    SQLITE_ROW => rows.push(map(&Row {
+                        statement: self.statement,
+                        _statement: PhantomData,
+                    })),
+                    SQLITE_DONE => return Ok(rows),
+                    code => return Err(self.connection.error(code)),
+                }
+            }
+        });
+        unsafe { sqlite3_reset(self.statement) };
+        result
+    }
+
+    fn bind(&mut self, params: &[Value]) -> Result<(), SqliteError> {
+        for (i, param) in params.iter().enumerate() {
+            let index = i as c_int + 1;
+            let code = unsafe {
+                match param {
+                    Value::Null => sqlite3_bind_null(self.statement, index),
+                    Value::Integer(value) => sqlite3_bind_int64(self.statement, index, *value),
+                    Value::Text(value) => sqlite3_bind_text(
+                        self.statement,
+                        index,
+                        value.as_ptr().cast(),
+                        value.len() as c_int,
+                        SQLITE_TRANSIENT,
+                    ),
+                }
+            };
+            self.connection.check(code)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.statement) };
+    }
+}
+
+impl Row<'_> {
+    pub fn integer(&self, column: usize) -> Option<i64> {
+        let column = column as c_int;
+        unsafe {
+            match sqlite3_column_type(self.statement, column) {
+                SQLITE_NULL => None,
+                _ => Some(sqlite3_column_int64(self.statement, column)),
+            }
+        }
+    }
+
+    pub fn text(&self, column: usize) -> Option<String> {
+        let column = column as c_int;
+        unsafe {
+            if sqlite3_column_type(self.statement, column) == SQLITE_NULL {
+                return None;
+            }
+            // The pointer is valid until the next step, the length is only right after it
+            let text = sqlite3_column_text(self.statement, column);
+            let len = sqlite3_column_bytes(self.statement, column) as usize;
+            let octets = std::slice::from_raw_parts(text, len);
+            Some(String::from_utf8_lossy(octets).into_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_values() {
+        let connection = Connection::open(":memory:").unwrap();
+        connection
+            .execute_batch("CREATE TABLE t (i INTEGER, s TEXT); CREATE INDEX t_i ON t (i);")
+            .unwrap();
+        let mut insert = connection
+            .prepare("INSERT INTO t (i, s) VALUES (?1, ?2)")
+            .unwrap();
+        insert
+            .execute(&[Value::Integer(-1 << 40), Value::Text("bgp\u{e9}")])
+            .unwrap();
+        insert.execute(&[Value::Integer(7), Value::Null]).unwrap();
+
+        let mut select = connection.prepare("SELECT i, s FROM t ORDER BY i").unwrap();
+        let rows = select
+            .query(&[], |row| (row.integer(0), row.text(1)))
+            .unwrap();
+        assert_eq!(
+            rows,
+            [(Some(-1 << 40), Some("bgp\u{e9}".into())), (Some(7), None)]
+        );
+        assert_eq!(
+            connection.query_integer("SELECT count(*) FROM t"),
+            Ok(Some(2))
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let connection = Connection::open(":memory:").unwrap();
+        let Err(SqliteError::Sqlite { message, .. }) = connection.prepare("SELEC 1") else {
+            panic!("prepared invalid SQL");
+        };
+        assert!(message.contains("syntax error"), "{message}");
+    }
+}
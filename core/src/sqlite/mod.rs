@@ -0,0 +1,748 @@
+//! Durable history of route events and peer state in an SQLite database.
+//!
+//! [`SqliteSink`] is a [`SessionObserver`] that hands what it observes to a writer thread,
+//! which inserts it in batches, one transaction each. The schema is
+//!
+//! ```sql
+//! CREATE TABLE peers (
+//!     addr TEXT PRIMARY KEY,          -- the peer's address
+//!     asn INTEGER NOT NULL,
+//!     router_id TEXT NOT NULL,
+//!     state TEXT NOT NULL,            -- 'established' or 'idle'
+//!     since INTEGER NOT NULL          -- when it entered the state
+//! );
+//! CREATE TABLE events (
+//!     id INTEGER PRIMARY KEY,
+//!     received INTEGER NOT NULL,      -- when the UPDATE was read off the socket
+//!     peer TEXT NOT NULL,
+//!     prefix TEXT NOT NULL,           -- addr/length
+//!     safi INTEGER NOT NULL,
+//!     kind TEXT NOT NULL,             -- 'announce' or 'withdraw'
+//!     attributes TEXT                 -- for announcements, as exabgp's JSON "attribute"
+//! );
+//! CREATE INDEX events_by_prefix ON events (prefix, received);
+//! CREATE TABLE session_transitions (
+//!     id INTEGER PRIMARY KEY,
+//!     at INTEGER NOT NULL,
+//!     peer TEXT NOT NULL,
+//!     asn INTEGER NOT NULL,
+//!     state TEXT NOT NULL,
+//!     reason TEXT                     -- how the session ended, for people to read
+//! );
+//! ```
+//!
+//! with times in microseconds since the Unix epoch. The schema version is kept in the
+//! database's `user_version`, and opening a database of an older version migrates it.
+//!
+//! Bindings to the system libsqlite3 are part of the crate, so nothing beyond the library
+//! itself is needed to build.
+
+mod db;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::address_family::Safi;
+use crate::exabgp::attributes_json;
+use crate::json::Json;
+use crate::rib::{announced_keys, withdrawn_keys};
+use crate::session::{PeerInfo, PeerState, SessionEnd, SessionError, SessionObserver};
+use crate::timestamped::Timestamped;
+use crate::update_message::{IpAddrPrefix, UpdateMessage};
+
+use db::{Connection, Value};
+
+/// The schema version this build writes, the number of migrations
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Each migration takes the schema from its index to the next version
+const MIGRATIONS: [&str; 1] = ["
+    CREATE TABLE peers (
+        addr TEXT PRIMARY KEY,
+        asn INTEGER NOT NULL,
+        router_id TEXT NOT NULL,
+        state TEXT NOT NULL,
+        since INTEGER NOT NULL
+    );
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY,
+        received INTEGER NOT NULL,
+        peer TEXT NOT NULL,
+        prefix TEXT NOT NULL,
+        safi INTEGER NOT NULL,
+        kind TEXT NOT NULL CHECK (kind IN ('announce', 'withdraw')),
+        attributes TEXT
+    );
+    CREATE INDEX events_by_prefix ON events (prefix, received);
+    CREATE TABLE session_transitions (
+        id INTEGER PRIMARY KEY,
+        at INTEGER NOT NULL,
+        peer TEXT NOT NULL,
+        asn INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        reason TEXT
+    );
+"];
+
+/// Commands queued for the writer before observations are dropped
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Most observations inserted in one transaction
+const MAX_BATCH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SqliteError {
+    #[error("SQLite error {code}: {message}")]
+    Sqlite { code: i32, message: String },
+    #[error("database schema version {found} is newer than the supported {SCHEMA_VERSION}")]
+    SchemaVersion { found: i64 },
+    #[error("invalid {column} stored: {value}")]
+    Invalid { column: &'static str, value: String },
+    #[error("the database writer stopped")]
+    Closed,
+}
+
+/// An announcement or withdrawal, as stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEvent {
+    pub received: SystemTime,
+    pub peer: IpAddr,
+    pub prefix: IpAddrPrefix,
+    pub safi: Safi,
+    pub kind: StoredEventKind,
+    /// The attributes of an announcement, as exabgp's JSON `attribute` member
+    pub attributes: Option<Json>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredEventKind {
+    Announce,
+    Withdraw,
+}
+
+/// The last known state of a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredPeer {
+    pub addr: IpAddr,
+    pub asn: u32,
+    pub router_id: Ipv4Addr,
+    /// [`PeerState::Established`] or [`PeerState::Idle`]
+    pub state: PeerState,
+    pub since: SystemTime,
+}
+
+/// A session coming up or going down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTransition {
+    pub at: SystemTime,
+    pub peer: IpAddr,
+    pub asn: u32,
+    pub state: PeerState,
+    /// How the session ended, for people to read
+    pub reason: Option<String>,
+}
+
+/// Records route events and peer state in an SQLite database.
+///
+/// Never blocks the session: observations are queued for the writer thread, and when the
+/// queue is full they are dropped and counted in [`SqliteSink::dropped`]. Queries run on the
+/// writer thread too, after everything observed before them has been written.
+#[derive(Debug)]
+pub struct SqliteSink {
+    commands: mpsc::Sender<Command>,
+    dropped: AtomicU64,
+}
+
+/// Runs a query on the writer thread, sending the result back itself
+type Read = Box<dyn FnOnce(&Connection) + Send>;
+
+enum Command {
+    Write(Observation),
+    Read(Read),
+    /// Replies once everything before it is written, with the first error since the last
+    /// flush
+    Flush(oneshot::Sender<Result<(), SqliteError>>),
+}
+
+enum Observation {
+    Update(PeerInfo, Timestamped<UpdateMessage>),
+    Established(PeerInfo, SystemTime),
+    Closed(PeerInfo, SystemTime, String),
+}
+
+impl SqliteSink {
+    /// Opens or creates the database at `path` and migrates it to [`SCHEMA_VERSION`]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SqliteError> {
+        let path = path.as_ref().to_string_lossy();
+        Self::start(Connection::open(&path)?, DEFAULT_CAPACITY)
+    }
+
+    /// A database that lives as long as the sink
+    pub fn open_in_memory() -> Result<Self, SqliteError> {
+        Self::start(Connection::open(":memory:")?, DEFAULT_CAPACITY)
+    }
+
+    fn start(connection: Connection, capacity: usize) -> Result<Self, SqliteError> {
+        migrate(&connection)?;
+        let (commands, receiver) = mpsc::channel(capacity);
+        std::thread::Builder::new()
+            .name("bgp-sqlite".into())
+            .spawn(move || run(connection, receiver))
+            .map_err(|err| SqliteError::Sqlite {
+                code: 0,
+                message: format!("can't start the writer: {err}"),
+            })?;
+        Ok(SqliteSink {
+            commands,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Observations discarded because the writer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits until everything observed so far is written, returning the first error writing
+    /// it since the last flush
+    pub async fn flush(&self) -> Result<(), SqliteError> {
+        let (reply, flushed) = oneshot::channel();
+        self.send(Command::Flush(reply)).await?;
+        flushed.await.map_err(|_| SqliteError::Closed)?
+    }
+
+    /// The announcements and withdrawals of `prefix` received at or after `since`, oldest
+    /// first
+    pub async fn events_for_prefix(
+        &self,
+        prefix: &IpAddrPrefix,
+        since: SystemTime,
+    ) -> Result<Vec<StoredEvent>, SqliteError> {
+        let prefix = prefix.to_string();
+        self.read(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT received, peer, prefix, safi, kind, attributes FROM events
+                 WHERE prefix = ?1 AND received >= ?2 ORDER BY received, id",
+            )?;
+            let rows = statement.query(
+                &[Value::Text(&prefix), Value::Integer(micros(since))],
+                |row| {
+                    Ok(StoredEvent {
+                        received: time(row.integer(0)),
+                        peer: parse("peer", row.text(1))?,
+                        prefix: parse("prefix", row.text(2))?,
+                        safi: Safi::from(row.integer(3).unwrap_or_default() as u8),
+                        kind: match row.text(4).as_deref() {
+                            Some("announce") => StoredEventKind::Announce,
+                            _ => StoredEventKind::Withdraw,
+                        },
+                        attributes: row
+                            .text(5)
+                            .map(|json| parse("attributes", Some(json)))
+                            .transpose()?,
+                    })
+                },
+            )?;
+            rows.into_iter().collect()
+        })
+        .await
+    }
+
+    /// Every peer seen, by address
+    pub async fn peers(&self) -> Result<Vec<StoredPeer>, SqliteError> {
+        self.read(|connection| {
+            let mut statement = connection
+                .prepare("SELECT addr, asn, router_id, state, since FROM peers ORDER BY addr")?;
+            let rows = statement.query(&[], |row| {
+                Ok(StoredPeer {
+                    addr: parse("addr", row.text(0))?,
+                    asn: row.integer(1).unwrap_or_default() as u32,
+                    router_id: parse("router_id", row.text(2))?,
+                    state: state(row.text(3))?,
+                    since: time(row.integer(4)),
+                })
+            })?;
+            rows.into_iter().collect()
+        })
+        .await
+    }
+
+    /// The sessions of `peer` coming up and going down, oldest first
+    pub async fn transitions(&self, peer: IpAddr) -> Result<Vec<SessionTransition>, SqliteError> {
+        self.read(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT at, peer, asn, state, reason FROM session_transitions
+                 WHERE peer = ?1 ORDER BY at, id",
+            )?;
+            let peer = peer.to_string();
+            let rows = statement.query(&[Value::Text(&peer)], |row| {
+                Ok(SessionTransition {
+                    at: time(row.integer(0)),
+                    peer: parse("peer", row.text(1))?,
+                    asn: row.integer(2).unwrap_or_default() as u32,
+                    state: state(row.text(3))?,
+                    reason: row.text(4),
+                })
+            })?;
+            rows.into_iter().collect()
+        })
+        .await
+    }
+
+    async fn read<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&Connection) -> Result<T, SqliteError> + Send + 'static,
+    ) -> Result<T, SqliteError> {
+        let (reply, result) = oneshot::channel();
+        let read: Read = Box::new(move |connection| {
+            let _ = reply.send(query(connection));
+        });
+        self.send(Command::Read(read)).await?;
+        result.await.map_err(|_| SqliteError::Closed)?
+    }
+
+    async fn send(&self, command: Command) -> Result<(), SqliteError> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| SqliteError::Closed)
+    }
+
+    fn observe(&self, observation: Observation) {
+        if let Err(mpsc::error::TrySendError::Full(_)) =
+            self.commands.try_send(Command::Write(observation))
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SessionObserver for SqliteSink {
+    fn on_established(&self, peer: &PeerInfo) {
+        self.observe(Observation::Established(*peer, SystemTime::now()))
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        self.observe(Observation::Update(*peer, update.clone()))
+    }
+
+    fn on_close(&self, peer: &PeerInfo, end: Result<&SessionEnd, &SessionError>) {
+        let reason = match end {
+            Ok(end) => format!("{end:?}"),
+            Err(err) => err.to_string(),
+        };
+        self.observe(Observation::Closed(*peer, SystemTime::now(), reason))
+    }
+}
+
+/// Brings the schema up to [`SCHEMA_VERSION`], each migration in a transaction of its own
+fn migrate(connection: &Connection) -> Result<(), SqliteError> {
+    let version = connection
+        .query_integer("PRAGMA user_version")?
+        .unwrap_or_default();
+    if version > SCHEMA_VERSION {
+        return Err(SqliteError::SchemaVersion { found: version });
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = from + 1;
+        transaction(connection, || {
+            connection.execute_batch(migration)?;
+            connection.execute_batch(&format!("PRAGMA user_version = {to}"))
+        })?;
+    }
+    Ok(())
+}
+
+fn transaction(
+    connection: &Connection,
+    body: impl FnOnce() -> Result<(), SqliteError>,
+) -> Result<(), SqliteError> {
+    connection.execute_batch("BEGIN")?;
+    match body() {
+        Ok(()) => connection.execute_batch("COMMIT"),
+        Err(err) => {
+            let _ = connection.execute_batch("ROLLBACK");
+            Err(err)
+        }
+    }
+}
+
+/// The writer thread, until the sink is dropped
+fn run(connection: Connection, mut commands: mpsc::Receiver<Command>) {
+    let mut batch = vec![];
+    let mut failed = None;
+    while let Some(command) = commands.blocking_recv() {
+        let mut next = Some(command);
+        while let Some(command) = next.take() {
+            match command {
+                Command::Write(observation) => batch.push(observation),
+                Command::Read(read) => {
+                    commit(&connection, &mut batch, &mut failed);
+                    read(&connection);
+                }
+                Command::Flush(reply) => {
+                    commit(&connection, &mut batch, &mut failed);
+                    let _ = reply.send(failed.take().map_or(Ok(()), Err));
+                }
+            }
+            if batch.len() < MAX_BATCH {
+                next = commands.try_recv().ok();
+            }
+        }
+        commit(&connection, &mut batch, &mut failed);
+    }
+}
+
+fn commit(connection: &Connection, batch: &mut Vec<Observation>, failed: &mut Option<SqliteError>) {
+    if batch.is_empty() {
+        return;
+    }
+    if let Err(err) = transaction(connection, || insert(connection, batch)) {
+        event!(Warn, "SQLite batch failed", "reason" = err);
+        failed.get_or_insert(err);
+    }
+    batch.clear();
+}
+
+fn insert(connection: &Connection, batch: &[Observation]) -> Result<(), SqliteError> {
+    let mut event = connection.prepare(
+        "INSERT INTO events (received, peer, prefix, safi, kind, attributes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    let mut peer_state = connection.prepare(
+        "INSERT INTO peers (addr, asn, router_id, state, since) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (addr) DO UPDATE SET
+             asn = excluded.asn, router_id = excluded.router_id,
+             state = excluded.state, since = excluded.since",
+    )?;
+    let mut transition = connection.prepare(
+        "INSERT INTO session_transitions (at, peer, asn, state, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+
+    for observation in batch {
+        match observation {
+            Observation::Update(peer, update) => {
+                let addr = peer.peer_addr.ip().to_string();
+                let received = Value::Integer(micros(update.received));
+                for key in withdrawn_keys(&update.value) {
+                    event.execute(&[
+                        received,
+                        Value::Text(&addr),
+                        Value::Text(&key.prefix.to_string()),
+                        Value::Integer(u8::from(key.safi).into()),
+                        Value::Text("withdraw"),
+                        Value::Null,
+                    ])?;
+                }
+                let mut announced = announced_keys(&update.value).peekable();
+                if announced.peek().is_none() {
+                    continue;
+                }
+                let attributes = attributes_json(&update.value.path_attributes).to_string();
+                for key in announced {
+                    event.execute(&[
+                        received,
+                        Value::Text(&addr),
+                        Value::Text(&key.prefix.to_string()),
+                        Value::Integer(u8::from(key.safi).into()),
+                        Value::Text("announce"),
+                        Value::Text(&attributes),
+                    ])?;
+                }
+            }
+            Observation::Established(peer, at) => record_state(
+                &mut peer_state,
+                &mut transition,
+                peer,
+                *at,
+                "established",
+                None,
+            )?,
+            Observation::Closed(peer, at, reason) => record_state(
+                &mut peer_state,
+                &mut transition,
+                peer,
+                *at,
+                "idle",
+                Some(reason),
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn record_state(
+    peer_state: &mut db::Statement<'_>,
+    transition: &mut db::Statement<'_>,
+    peer: &PeerInfo,
+    at: SystemTime,
+    state: &str,
+    reason: Option<&str>,
+) -> Result<(), SqliteError> {
+    let addr = peer.peer_addr.ip().to_string();
+    let at = Value::Integer(micros(at));
+    let asn = Value::Integer(peer.asn.into());
+    peer_state.execute(&[
+        Value::Text(&addr),
+        asn,
+        Value::Text(&peer.router_id.to_string()),
+        Value::Text(state),
+        at,
+    ])?;
+    transition.execute(&[
+        at,
+        Value::Text(&addr),
+        asn,
+        Value::Text(state),
+        reason.map_or(Value::Null, Value::Text),
+    ])
+}
+
+fn micros(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(err) => -(err.duration().as_micros() as i64),
+    }
+}
+
+fn time(micros: Option<i64>) -> SystemTime {
+    let micros = micros.unwrap_or_default();
+    let offset = Duration::from_micros(micros.unsigned_abs());
+    match micros >= 0 {
+        true => UNIX_EPOCH + offset,
+        false => UNIX_EPOCH - offset,
+    }
+}
+
+fn state(value: Option<String>) -> Result<PeerState, SqliteError> {
+    match value.as_deref() {
+        Some("established") => Ok(PeerState::Established),
+        Some("idle") => Ok(PeerState::Idle),
+        _ => Err(SqliteError::Invalid {
+            column: "state",
+            value: value.unwrap_or_default(),
+        }),
+    }
+}
+
+fn parse<T: FromStr>(column: &'static str, value: Option<String>) -> Result<T, SqliteError> {
+    let value = value.unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| SqliteError::Invalid { column, value })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+
+    use crate::attribute::OriginType;
+    use crate::session::ShutdownReason;
+    use crate::update_message::UpdateMessageBuilder;
+
+    fn peer(n: u8, asn: u32) -> PeerInfo {
+        PeerInfo {
+            peer_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, n)), 179),
+            asn,
+            router_id: Ipv4Addr::new(10, 0, 0, n),
+            router: None,
+        }
+    }
+
+    fn prefix(s: &str) -> IpAddrPrefix {
+        s.parse().unwrap()
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    fn announce(prefixes: &[&str], med: u32, seconds: u64) -> Timestamped<UpdateMessage> {
+        let mut update = UpdateMessageBuilder::new()
+            .origin(OriginType::Igp)
+            .next_hop("192.0.2.254".parse().unwrap())
+            .med(med);
+        for p in prefixes {
+            update = update.announce(prefix(p));
+        }
+        Timestamped::at(at(seconds), update.build())
+    }
+
+    fn withdraw(prefixes: &[&str], seconds: u64) -> Timestamped<UpdateMessage> {
+        let mut update = UpdateMessageBuilder::new();
+        for p in prefixes {
+            update = update.withdraw(prefix(p));
+        }
+        Timestamped::at(at(seconds), update.build())
+    }
+
+    /// Two peers announcing, flapping and withdrawing 203.0.113.0/24
+    fn script(sink: &SqliteSink) {
+        let (a, b) = (peer(1, 64500), peer(2, 64501));
+        sink.on_established(&a);
+        sink.on_update(&a, &announce(&["203.0.113.0/24", "198.51.100.0/24"], 10, 0));
+        sink.on_established(&b);
+        sink.on_update(&b, &announce(&["203.0.113.0/24"], 20, 5));
+        sink.on_update(&a, &withdraw(&["203.0.113.0/24"], 10));
+        sink.on_update(&a, &announce(&["203.0.113.0/24"], 30, 15));
+        let end = SessionEnd::LocalShutdown(ShutdownReason::PeerDeconfigured);
+        sink.on_close(&b, Ok(&end));
+        sink.on_update(&a, &withdraw(&["198.51.100.0/24"], 20));
+    }
+
+    #[tokio::test]
+    async fn answers_queries_about_a_scripted_history() {
+        let sink = SqliteSink::open_in_memory().unwrap();
+        script(&sink);
+        sink.flush().await.unwrap();
+        assert_eq!(sink.dropped(), 0);
+
+        let events = sink
+            .events_for_prefix(&prefix("203.0.113.0/24"), at(0))
+            .await
+            .unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| {
+                let med = event.attributes.as_ref().map(|json| json["med"].clone());
+                (event.received, event.peer.to_string(), event.kind, med)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    at(0),
+                    "192.0.2.1".into(),
+                    StoredEventKind::Announce,
+                    Some(Json::from(10))
+                ),
+                (
+                    at(5),
+                    "192.0.2.2".into(),
+                    StoredEventKind::Announce,
+                    Some(Json::from(20))
+                ),
+                (at(10), "192.0.2.1".into(), StoredEventKind::Withdraw, None),
+                (
+                    at(15),
+                    "192.0.2.1".into(),
+                    StoredEventKind::Announce,
+                    Some(Json::from(30))
+                ),
+            ]
+        );
+        assert_eq!(events[0].prefix, prefix("203.0.113.0/24"));
+        assert_eq!(events[0].safi, Safi::Unicast);
+        assert_eq!(
+            events[0].attributes.as_ref().unwrap()["origin"],
+            Json::from("igp")
+        );
+
+        let since = sink
+            .events_for_prefix(&prefix("203.0.113.0/24"), at(10))
+            .await
+            .unwrap();
+        assert_eq!(since, events[2..]);
+        let other = sink
+            .events_for_prefix(&prefix("198.51.100.0/24"), at(0))
+            .await
+            .unwrap();
+        assert_eq!(other.len(), 2);
+        assert!(
+            sink.events_for_prefix(&prefix("203.0.113.0/25"), at(0))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let peers = sink.peers().await.unwrap();
+        let states: Vec<_> = peers
+            .iter()
+            .map(|peer| (peer.addr.to_string(), peer.asn, peer.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ("192.0.2.1".into(), 64500, PeerState::Established),
+                ("192.0.2.2".into(), 64501, PeerState::Idle),
+            ]
+        );
+        assert_eq!(peers[1].router_id, Ipv4Addr::new(10, 0, 0, 2));
+
+        let transitions = sink.transitions(peers[1].addr).await.unwrap();
+        let states: Vec<_> = transitions
+            .iter()
+            .map(|transition| (transition.state, transition.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (PeerState::Established, None),
+                (PeerState::Idle, Some("LocalShutdown(PeerDeconfigured)")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_observations_when_the_writer_falls_behind() {
+        let connection = Connection::open(":memory:").unwrap();
+        let sink = SqliteSink::start(connection, 1).unwrap();
+        // Holds up the writer until every observation is made
+        let (release, held) = std::sync::mpsc::channel::<()>();
+        let hold: Read = Box::new(move |_| {
+            let _ = held.recv();
+        });
+        sink.send(Command::Read(hold)).await.unwrap();
+        for _ in 0..1000 {
+            sink.on_update(&peer(1, 64500), &announce(&["203.0.113.0/24"], 10, 0));
+        }
+        drop(release);
+        sink.flush().await.unwrap();
+        assert!(sink.dropped() >= 999);
+        let stored = sink
+            .events_for_prefix(&prefix("203.0.113.0/24"), at(0))
+            .await
+            .unwrap();
+        assert_eq!(stored.len() as u64 + sink.dropped(), 1000);
+    }
+
+    #[tokio::test]
+    async fn migrates_once_and_refuses_newer_schemas() {
+        let path = std::env::temp_dir().join(format!("bgp_core_sqlite_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = SqliteSink::open(&path).unwrap();
+        script(&sink);
+        sink.flush().await.unwrap();
+        drop(sink);
+
+        // Reopening keeps the history rather than migrating again
+        let sink = SqliteSink::open(&path).unwrap();
+        assert_eq!(sink.peers().await.unwrap().len(), 2);
+        let version = sink
+            .read(|connection| connection.query_integer("PRAGMA user_version"))
+            .await;
+        assert_eq!(version, Ok(Some(SCHEMA_VERSION)));
+        drop(sink);
+
+        let connection = Connection::open(&path.to_string_lossy()).unwrap();
+        connection
+            .execute_batch("PRAGMA user_version = 99")
+            .unwrap();
+        drop(connection);
+        assert_eq!(
+            SqliteSink::open(&path).unwrap_err(),
+            SqliteError::SchemaVersion { found: 99 }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
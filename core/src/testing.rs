@@ -0,0 +1,355 @@
+//! A corpus of wire-format test vectors kept as text files, and a harness running them through
+//! the decoders.
+//!
+//! A fixture is a `.hex` file of bytes in hex, whitespace anywhere, with `#` starting a comment
+//! that runs to the end of the line. A comment line before the bytes states the outcome:
+//!
+//! ```text
+//! # Withdraws 10.0.0.0/8
+//! # expect: ok
+//! 00 02 08 0a
+//! 00 00
+//! ```
+//!
+//! or `# expect: error`, optionally followed by the kind of error, such as
+//! `# expect: error MalformedAsPath`. The directory a fixture is in picks its decoder, see
+//! [`Decoder`]. With the `serde` feature, a fixture that decodes is also compared against the
+//! serde representation in a `.expected.json` file of the same name, when there is one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+
+use crate::attribute::PathAttribute;
+use crate::header::BgpHeader;
+use crate::json::{Json, ParseJsonError};
+use crate::notification_message::NotificationMessage;
+use crate::open_message::OpenMessage;
+use crate::update_message::UpdateMessage;
+
+const EXTENSION: &str = "hex";
+const EXPECTED_EXTENSION: &str = "expected.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FixtureError {
+    #[error("fixture I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{path}:{line}: {reason}")]
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        reason: &'static str,
+    },
+    #[error("{path}: {error}")]
+    ExpectedJson {
+        path: PathBuf,
+        error: ParseJsonError,
+    },
+}
+
+/// What decoding a fixture should come to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// With the kind of error, when the fixture names one
+    Error(Option<String>),
+}
+
+/// A test vector loaded from its `.hex` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    pub bytes: Bytes,
+    /// From the `.expected.json` file next to it
+    pub expected: Option<Json>,
+}
+
+impl Fixture {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FixtureError> {
+        let path = path.as_ref();
+        let mut fixture = Self::parse(path, &fs::read_to_string(path)?)?;
+        let expected_path = path.with_extension(EXPECTED_EXTENSION);
+        if expected_path.exists() {
+            let text = fs::read_to_string(&expected_path)?;
+            let expected = text.parse().map_err(|error| FixtureError::ExpectedJson {
+                path: expected_path,
+                error,
+            })?;
+            fixture.expected = Some(expected);
+        }
+        Ok(fixture)
+    }
+
+    /// Parses the text of a fixture, `path` only naming it in errors
+    pub fn parse(path: impl AsRef<Path>, text: &str) -> Result<Self, FixtureError> {
+        let path = path.as_ref();
+        let error = |line: usize, reason| FixtureError::Syntax {
+            path: path.to_path_buf(),
+            line: line + 1,
+            reason,
+        };
+
+        let mut outcome = None;
+        let mut hex = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let (data, comment) = line.split_once('#').unwrap_or((line, ""));
+            if let Some(expect) = comment.trim().strip_prefix("expect:") {
+                if outcome.is_some() || !hex.is_empty() {
+                    return Err(error(
+                        number,
+                        "the outcome must come once, before the bytes",
+                    ));
+                }
+                let mut words = expect.split_whitespace();
+                outcome = Some(match (words.next(), words.next(), words.next()) {
+                    (Some("ok"), None, _) => Outcome::Ok,
+                    (Some("error"), kind, None) => Outcome::Error(kind.map(str::to_owned)),
+                    _ => return Err(error(number, "expected `ok` or `error [kind]`")),
+                });
+            }
+            let data: String = data.split_whitespace().collect();
+            if !data.is_empty() && outcome.is_none() {
+                return Err(error(number, "bytes before the outcome"));
+            }
+            if !data.len().is_multiple_of(2) {
+                return Err(error(number, "odd number of hex digits"));
+            }
+            hex.push_str(&data);
+        }
+        let Some(outcome) = outcome else {
+            return Err(error(0, "missing `# expect:` line"));
+        };
+
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error(0, "invalid hex digit"))?;
+        Ok(Fixture {
+            path: path.to_path_buf(),
+            outcome,
+            bytes: bytes.into(),
+            expected: None,
+        })
+    }
+
+    /// Runs the fixture through `decoder`, describing how the result differs from what was
+    /// expected
+    pub fn check(&self, decoder: Decoder) -> Result<(), String> {
+        let result = decoder.decode(self.bytes.clone());
+        match (&self.outcome, result) {
+            (Outcome::Ok, Ok(decoded)) => match (&self.expected, decoded) {
+                (Some(expected), Some(decoded)) if *expected != decoded => Err(format!(
+                    "decoded to\n{decoded:#}\nrather than the expected\n{expected:#}"
+                )),
+                _ => Ok(()),
+            },
+            (Outcome::Ok, Err(error)) => Err(format!("failed to decode: {error}")),
+            (Outcome::Error(_), Ok(_)) => Err("decoded but should have failed".to_owned()),
+            (Outcome::Error(Some(kind)), Err(error)) if error.kind.as_ref() != Some(kind) => {
+                Err(format!("failed with the wrong kind of error: {error}"))
+            }
+            (Outcome::Error(_), Err(_)) => Ok(()),
+        }
+    }
+}
+
+/// The decoder fixtures are run through, by the directory they're in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoder {
+    /// `headers/`: the 19 octet message header
+    Header,
+    /// `updates/`: an UPDATE message after its header
+    Update,
+    /// `opens/`: an OPEN message after its header
+    Open,
+    /// `notifications/`: a NOTIFICATION message after its header
+    Notification,
+    /// `attributes/`: a single path attribute, ASNs of 4 octets
+    Attribute,
+}
+
+/// How a fixture failed to decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeFailure {
+    /// The error variant, for decoders with typed errors
+    pub kind: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for DecodeFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            Some(kind) => write!(f, "{kind}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl Decoder {
+    pub const ALL: [Decoder; 5] = [
+        Decoder::Header,
+        Decoder::Update,
+        Decoder::Open,
+        Decoder::Notification,
+        Decoder::Attribute,
+    ];
+
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Decoder::Header => "headers",
+            Decoder::Update => "updates",
+            Decoder::Open => "opens",
+            Decoder::Notification => "notifications",
+            Decoder::Attribute => "attributes",
+        }
+    }
+
+    /// Decodes `bytes`, to its serde representation with the `serde` feature
+    pub fn decode(self, mut bytes: Bytes) -> Result<Option<Json>, DecodeFailure> {
+        match self {
+            Decoder::Header => BgpHeader::try_from_bytes(&mut bytes)
+                .map(|header| represent(&header))
+                .map_err(|error| typed(&error, error.to_string())),
+            Decoder::Update => UpdateMessage::try_decode(&mut bytes)
+                .map(|update| represent(&update))
+                .map_err(|error| typed(&error.kind, format!("{error:?}"))),
+            Decoder::Open => OpenMessage::try_from(&mut bytes)
+                .map(|open| represent(&open))
+                .map_err(|error| typed(&error, error.to_string())),
+            Decoder::Notification => NotificationMessage::try_decode(&mut bytes)
+                .map(|notification| represent(&notification))
+                .map_err(|error| typed(&error, error.to_string())),
+            Decoder::Attribute => PathAttribute::try_decode(&mut bytes)
+                .map(|attribute| represent(&attribute))
+                .map_err(|error| typed(&error.kind, format!("{error:?}"))),
+        }
+    }
+}
+
+/// The variant name of an error, the start of its `Debug` output
+fn typed(error: &impl std::fmt::Debug, message: String) -> DecodeFailure {
+    let debug = format!("{error:?}");
+    let kind = debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    DecodeFailure {
+        kind: Some(kind.to_owned()),
+        message,
+    }
+}
+
+#[cfg(feature = "serde")]
+fn represent<T: serde::Serialize>(value: &T) -> Option<Json> {
+    Some(crate::json::to_json(value).expect("decoded messages serialize"))
+}
+
+#[cfg(not(feature = "serde"))]
+fn represent<T>(_value: &T) -> Option<Json> {
+    None
+}
+
+/// The fixtures of one decoder's directory under `root`, in name order
+pub fn load_dir(root: impl AsRef<Path>, decoder: Decoder) -> Result<Vec<Fixture>, FixtureError> {
+    let dir = root.as_ref().join(decoder.dir_name());
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == EXTENSION)
+    });
+    paths.sort();
+    paths.into_iter().map(Fixture::load).collect()
+}
+
+/// What running a corpus came to
+#[derive(Debug, Default)]
+pub struct CorpusReport {
+    pub passed: usize,
+    /// Each failing fixture with what went wrong
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+/// Runs every fixture in the decoder directories under `root`
+pub fn run_corpus(root: impl AsRef<Path>) -> Result<CorpusReport, FixtureError> {
+    let mut report = CorpusReport::default();
+    for decoder in Decoder::ALL {
+        for fixture in load_dir(root.as_ref(), decoder)? {
+            match fixture.check(decoder) {
+                Ok(()) => report.passed += 1,
+                Err(failure) => report.failures.push((fixture.path, failure)),
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# A keepalive\n# expect: ok\nffffffff ffffffff # marker\nff ff ff ff\n";
+        let fixture = Fixture::parse("keepalive.hex", text).unwrap();
+        assert_eq!(fixture.outcome, Outcome::Ok);
+        assert_eq!(&fixture.bytes[..], &[0xff; 12]);
+
+        let fixture = Fixture::parse("x.hex", "# expect: error MalformedAsPath\n").unwrap();
+        assert_eq!(
+            fixture.outcome,
+            Outcome::Error(Some("MalformedAsPath".into()))
+        );
+
+        for (text, line) in [
+            ("00\n", 1),
+            ("# expect: maybe\n", 1),
+            ("# expect: ok\n0\n", 2),
+            ("# expect: ok\n00\n# expect: ok\n", 3),
+            ("# nothing expected\n", 1),
+        ] {
+            match Fixture::parse("x.hex", text) {
+                Err(FixtureError::Syntax { line: at, .. }) => assert_eq!(at, line, "{text:?}"),
+                other => panic!("{text:?} gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        // Attribute flags and type without a length
+        let fixture =
+            |expect: &str| Fixture::parse("x.hex", &format!("# expect: {expect}\n40 01")).unwrap();
+        assert_eq!(
+            fixture("error AttributeLengthErr").check(Decoder::Attribute),
+            Ok(())
+        );
+        let failure = fixture("error MalformedAsPath")
+            .check(Decoder::Attribute)
+            .unwrap_err();
+        assert!(failure.contains("AttributeLengthErr"), "{failure}");
+        assert!(fixture("ok").check(Decoder::Attribute).is_err());
+        assert_eq!(fixture("error").check(Decoder::Attribute), Ok(()));
+    }
+
+    #[test]
+    fn test_corpus() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let report = run_corpus(root).unwrap();
+        let failures: Vec<String> = report
+            .failures
+            .iter()
+            .map(|(path, failure)| format!("{}: {failure}", path.display()))
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+        assert!(report.passed >= 40, "only {} fixtures ran", report.passed);
+    }
+}
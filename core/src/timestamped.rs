@@ -0,0 +1,81 @@
+use std::ops::{Deref, DerefMut};
+use std::time::{Instant, SystemTime};
+
+/// A value with the time it was received.
+///
+/// For messages read from a socket this is when the final byte of the message arrived; for
+/// messages replayed from a capture it's the capture's own timestamp. `monotonic` only orders
+/// and measures intervals between values of the same process, so replays set it to when the
+/// value was read back. Derefs to the value, so fields and methods can be used directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamped<T> {
+    pub received: SystemTime,
+    pub monotonic: Instant,
+    pub value: T,
+}
+
+impl<T> Timestamped<T> {
+    /// Timestamps `value` with the current time
+    pub fn now(value: T) -> Self {
+        Timestamped {
+            received: SystemTime::now(),
+            monotonic: Instant::now(),
+            value,
+        }
+    }
+
+    /// Timestamps `value` with a wall clock time taken elsewhere, such as from a capture file
+    pub fn at(received: SystemTime, value: T) -> Self {
+        Timestamped {
+            received,
+            monotonic: Instant::now(),
+            value,
+        }
+    }
+
+    /// Replaces the value, keeping the timestamps
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Timestamped<U> {
+        Timestamped {
+            received: self.received,
+            monotonic: self.monotonic,
+            value: f(self.value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Timestamped<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Timestamped<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_deref_and_map() {
+        let received = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut stamped = Timestamped::at(received, vec![1, 2]);
+        stamped.push(3);
+        assert_eq!(stamped.len(), 3);
+
+        let monotonic = stamped.monotonic;
+        let mapped = stamped.map(|value| value.len());
+        assert_eq!(*mapped, 3);
+        assert_eq!((mapped.received, mapped.monotonic), (received, monotonic));
+    }
+}
@@ -0,0 +1,380 @@
+//! Structured diagnostics from the decoders, sessions and RIBs.
+//!
+//! Decoding a message opens a span carrying its type, and sessions open one carrying the peer
+//! around each message they decode, so every event raised while decoding carries the fields of
+//! the spans it was raised in. Nothing is formatted unless a [`Subscriber`] is installed and
+//! wants the event's level.
+//!
+//! Field names are stable so log pipelines can index them:
+//!
+//! | Field | Value |
+//! |-------|-------|
+//! | `peer.addr` | the peer's address |
+//! | `peer.asn` | the peer's ASN, once known |
+//! | `msg.type` | `open`, `update`, `notification`, `keepalive`, `route_refresh` or the type code |
+//! | `attr.type` | the path attribute type code |
+//! | `attr.len` | the length of the attribute value |
+//! | `prefix` | a prefix as `addr/length` |
+//! | `reason` | why something was discarded |
+//! | `state.from`, `state.to` | session states, as [`crate::session::PeerState`] names them |
+//!
+//! | Level | Events |
+//! |-------|--------|
+//! | debug | each path attribute decoded, routes filtered from an Adj-RIB-In, best path changes |
+//! | info | session state transitions |
+//! | warn | recoveries from malformed input: masked prefix host bits, discarded attributes |
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub level: Level,
+    /// The module that raised the event
+    pub target: &'static str,
+    pub message: &'static str,
+    /// The fields of the enclosing spans, outermost first, followed by the event's own
+    pub fields: Vec<(&'static str, String)>,
+}
+
+/// Receives the events of the threads it is installed for
+pub trait Subscriber: Send + Sync {
+    /// Whether events of `level` are wanted, checked before any field is formatted
+    fn enabled(&self, level: Level) -> bool;
+
+    fn event(&self, event: &Event);
+}
+
+/// Writes events at or above a level to stderr, one `key=value` line each
+#[derive(Debug, Clone, Copy)]
+pub struct StderrSubscriber {
+    level: Level,
+}
+
+/// Keeps every event, for tests and for inspecting what a decode raised
+#[derive(Debug, Default)]
+pub struct CaptureSubscriber {
+    events: Mutex<Vec<Event>>,
+}
+
+/// Entered by [`Span::enter`], exits the span when dropped
+#[must_use]
+pub struct Span {
+    entered: bool,
+}
+
+struct SpanData {
+    fields: Vec<(&'static str, String)>,
+}
+
+static GLOBAL: OnceLock<Arc<dyn Subscriber>> = OnceLock::new();
+
+thread_local! {
+    static SCOPED: RefCell<Option<Arc<dyn Subscriber>>> = const { RefCell::new(None) };
+    static STACK: RefCell<Vec<SpanData>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Installs the subscriber of every thread, returning it back if one already is
+pub fn set_global_subscriber(subscriber: Arc<dyn Subscriber>) -> Result<(), Arc<dyn Subscriber>> {
+    GLOBAL.set(subscriber)
+}
+
+/// Runs `f` with `subscriber` receiving the events of this thread instead of the global one
+pub fn with_subscriber<T>(subscriber: Arc<dyn Subscriber>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous subscriber even if `f` panics
+    struct Restore(Option<Arc<dyn Subscriber>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED.set(self.0.take());
+        }
+    }
+
+    let _restore = Restore(SCOPED.replace(Some(subscriber)));
+    f()
+}
+
+fn subscriber() -> Option<Arc<dyn Subscriber>> {
+    SCOPED
+        .with_borrow(|scoped| scoped.clone())
+        .or_else(|| GLOBAL.get().cloned())
+}
+
+/// Whether a subscriber is installed for this thread
+pub fn active() -> bool {
+    SCOPED.with_borrow(Option::is_some) || GLOBAL.get().is_some()
+}
+
+/// Whether an event of `level` would reach a subscriber
+pub fn enabled(level: Level) -> bool {
+    subscriber().is_some_and(|subscriber| subscriber.enabled(level))
+}
+
+/// Raises an event with the fields of the enclosing spans, use [`enabled`] first to skip
+/// formatting the fields
+pub fn event(
+    level: Level,
+    target: &'static str,
+    message: &'static str,
+    fields: &[(&'static str, &dyn fmt::Display)],
+) {
+    let Some(subscriber) = subscriber().filter(|subscriber| subscriber.enabled(level)) else {
+        return;
+    };
+    let mut all = STACK.with_borrow(|stack| {
+        stack
+            .iter()
+            .flat_map(|span| span.fields.iter().cloned())
+            .collect::<Vec<_>>()
+    });
+    all.extend(formatted(fields));
+    subscriber.event(&Event {
+        level,
+        target,
+        message,
+        fields: all,
+    });
+}
+
+/// Fields rendered empty, such as an ASN not yet known, are left out
+fn formatted(
+    fields: &[(&'static str, &dyn fmt::Display)],
+) -> impl Iterator<Item = (&'static str, String)> {
+    fields
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .filter(|(_, value)| !value.is_empty())
+}
+
+impl Span {
+    /// Adds `fields` to the events raised on this thread until the span is dropped. Does
+    /// nothing, not even format the fields, without a subscriber.
+    pub fn enter(fields: &[(&'static str, &dyn fmt::Display)]) -> Self {
+        if !active() {
+            return Span::none();
+        }
+        let fields = formatted(fields).collect();
+        STACK.with_borrow_mut(|stack| stack.push(SpanData { fields }));
+        Span { entered: true }
+    }
+
+    /// A span that adds nothing
+    pub fn none() -> Self {
+        Span { entered: false }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if self.entered {
+            STACK.with_borrow_mut(|stack| stack.pop());
+        }
+    }
+}
+
+impl Event {
+    /// The value of a field, the innermost if several spans set it
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        })
+    }
+}
+
+/// `level=warn target=... msg="..." key=value ...`, quoting values with spaces
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "level={} target={} msg={:?}",
+            self.level, self.target, self.message
+        )?;
+        for (name, value) in &self.fields {
+            match value.contains([' ', '"', '=']) {
+                true => write!(f, " {name}={value:?}")?,
+                false => write!(f, " {name}={value}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StderrSubscriber {
+    pub fn new(level: Level) -> Self {
+        StderrSubscriber { level }
+    }
+}
+
+impl Subscriber for StderrSubscriber {
+    fn enabled(&self, level: Level) -> bool {
+        level >= self.level
+    }
+
+    fn event(&self, event: &Event) {
+        let _ = writeln!(std::io::stderr().lock(), "{event}");
+    }
+}
+
+impl CaptureSubscriber {
+    pub fn new() -> Self {
+        CaptureSubscriber::default()
+    }
+
+    /// Takes the events captured so far
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.events.lock().unwrap())
+    }
+}
+
+impl Subscriber for CaptureSubscriber {
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+
+    fn event(&self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use bytes::{BufMut, Bytes, BytesMut};
+
+    use crate::bgp_message::BgpMessage;
+    use crate::header::BgpHeader;
+    use crate::update_message::{IpAddrPrefix, ParserConfig};
+
+    fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<Event>) {
+        let subscriber = Arc::new(CaptureSubscriber::new());
+        let result = with_subscriber(subscriber.clone(), f);
+        (result, subscriber.take())
+    }
+
+    /// An UPDATE from a 2 octet speaker whose AS4_PATH claims a segment longer than the
+    /// attribute
+    fn malformed_as4_path() -> Bytes {
+        let mut attributes = BytesMut::new();
+        attributes.put_slice(&[0x40, 1, 1, 0]); // ORIGIN IGP
+        attributes.put_slice(&[0x40, 2, 4, 2, 1, 0x5b, 0xa0]); // AS_PATH 23456
+        attributes.put_slice(&[0x40, 3, 4, 192, 0, 2, 1]); // NEXT_HOP
+        attributes.put_slice(&[0xc0, 17, 6, 2, 2, 0, 0, 0xfd, 0xe9]); // AS4_PATH of 2, holding 1
+
+        let mut body = BytesMut::new();
+        body.put_u16(0);
+        body.put_u16(attributes.len() as u16);
+        body.put_slice(&attributes);
+        body.put_slice(&[24, 198, 51, 100]);
+
+        let mut message = BytesMut::new();
+        message.put_slice(&BgpHeader::MARKER_VALUE);
+        message.put_u16(BgpHeader::MIN_LEN + body.len() as u16);
+        message.put_u8(2);
+        message.put_slice(&body);
+        message.freeze()
+    }
+
+    #[test]
+    fn warns_once_for_a_discarded_attribute() {
+        let message = malformed_as4_path();
+        let (decoded, events) = capture(|| {
+            let _peer = Span::enter(&[
+                ("peer.addr", &Ipv4Addr::new(192, 0, 2, 1)),
+                ("peer.asn", &64_512),
+            ]);
+            BgpMessage::decode_framed(&mut message.clone(), false, false)
+        });
+        let BgpMessage::Update(update) = decoded.unwrap() else {
+            panic!("not an UPDATE");
+        };
+        assert_eq!(update.path_attributes.len(), 3);
+
+        let warnings: Vec<_> = events
+            .iter()
+            .filter(|event| event.level == Level::Warn)
+            .collect();
+        assert_eq!(warnings.len(), 1, "{events:#?}");
+        let warning = warnings[0];
+        assert_eq!(warning.message, "discarded attribute");
+        assert_eq!(warning.field("peer.addr"), Some("192.0.2.1"));
+        assert_eq!(warning.field("peer.asn"), Some("64512"));
+        assert_eq!(warning.field("msg.type"), Some("update"));
+        assert_eq!(warning.field("attr.type"), Some("17"));
+        assert_eq!(warning.field("reason"), Some("malformed"));
+
+        // Every attribute, the discarded one included, was decoded
+        let decoded: Vec<_> = events
+            .iter()
+            .filter(|event| event.level == Level::Debug)
+            .filter_map(|event| event.field("attr.type"))
+            .collect();
+        assert_eq!(decoded, ["1", "2", "3", "17"]);
+    }
+
+    #[test]
+    fn warns_for_masked_host_bits() {
+        let mut nlri = Bytes::from_static(&[24, 198, 51, 100, 23, 198, 51, 101]);
+        let (_, events) = capture(|| {
+            let _message = Span::enter(&[("msg.type", &"update")]);
+            IpAddrPrefix::decode_stream(&mut nlri, 4, false, &ParserConfig::default())
+        });
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].level, Level::Warn);
+        assert_eq!(
+            events[0].to_string(),
+            "level=warn target=bgp_core::update_message msg=\"masked host bits\" \
+             msg.type=update prefix=198.51.100.0/23"
+        );
+    }
+
+    #[test]
+    fn stays_quiet_without_a_subscriber() {
+        let message = malformed_as4_path();
+        assert!(!enabled(Level::Error));
+        let _span = Span::enter(&[("peer.addr", &"unformatted")]);
+        STACK.with_borrow(|stack| assert!(stack.is_empty()));
+        assert!(BgpMessage::decode_framed(&mut message.clone(), false, false).is_ok());
+    }
+
+    #[test]
+    fn restores_the_previous_subscriber() {
+        let outer = Arc::new(CaptureSubscriber::new());
+        with_subscriber(outer.clone(), || {
+            let (_, inner) = capture(|| event(Level::Info, "test", "inner", &[]));
+            assert_eq!(inner.len(), 1);
+            event(Level::Info, "test", "outer", &[("empty", &"")]);
+        });
+        assert!(!enabled(Level::Error));
+        let events = outer.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message, "outer");
+        assert!(events[0].fields.is_empty());
+    }
+}
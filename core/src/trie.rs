@@ -0,0 +1,731 @@
+//! Values keyed by prefix, with longest-prefix match and covering and covered lookups
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::update_message::IpAddrPrefix;
+
+/// A map from prefixes of both families to values.
+///
+/// Each family has its own path-compressed binary trie: nodes only exist for inserted prefixes
+/// and for the branch points between them, so `n` prefixes take fewer than `2n` nodes, which
+/// live in one vector and link to each other by index. Path identifiers of keys are ignored.
+#[derive(Debug, Clone)]
+pub struct PrefixTrie<V> {
+    ipv4: Tree<u32, V>,
+    ipv6: Tree<u128, V>,
+}
+
+impl<V> PrefixTrie<V> {
+    pub fn new() -> Self {
+        PrefixTrie {
+            ipv4: Tree::new(),
+            ipv6: Tree::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ipv4.len + self.ipv6.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets the value of `prefix`, returning the one it replaces
+    pub fn insert(&mut self, prefix: &IpAddrPrefix, value: V) -> Option<V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => self
+                .ipv4
+                .insert(None, addr.to_bits(), prefix.length(), value),
+            IpAddr::V6(addr) => self
+                .ipv6
+                .insert(None, addr.to_bits(), prefix.length(), value),
+        }
+        .0
+    }
+
+    pub fn remove(&mut self, prefix: &IpAddrPrefix) -> Option<V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => self.ipv4.remove(addr.to_bits(), prefix.length()),
+            IpAddr::V6(addr) => self.ipv6.remove(addr.to_bits(), prefix.length()),
+        }
+    }
+
+    /// The value of exactly `prefix`
+    pub fn get(&self, prefix: &IpAddrPrefix) -> Option<&V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => {
+                let node = self.ipv4.find(addr.to_bits(), prefix.length())?;
+                self.ipv4.nodes[node].value.as_ref()
+            }
+            IpAddr::V6(addr) => {
+                let node = self.ipv6.find(addr.to_bits(), prefix.length())?;
+                self.ipv6.nodes[node].value.as_ref()
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, prefix: &IpAddrPrefix) -> Option<&mut V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => {
+                let node = self.ipv4.find(addr.to_bits(), prefix.length())?;
+                self.ipv4.nodes[node].value.as_mut()
+            }
+            IpAddr::V6(addr) => {
+                let node = self.ipv6.find(addr.to_bits(), prefix.length())?;
+                self.ipv6.nodes[node].value.as_mut()
+            }
+        }
+    }
+
+    /// The longest prefix holding `addr`
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(IpAddrPrefix, &V)> {
+        let width = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        self.covering(&IpAddrPrefix::new(addr, width).unwrap())
+            .last()
+    }
+
+    /// The prefixes covering `prefix`, itself included, shortest first
+    pub fn covering(&self, prefix: &IpAddrPrefix) -> Iter<'_, V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => Iter(Walks::V4(
+                self.ipv4.covering(addr.to_bits(), prefix.length()),
+            )),
+            IpAddr::V6(addr) => Iter(Walks::V6(
+                self.ipv6.covering(addr.to_bits(), prefix.length()),
+            )),
+        }
+    }
+
+    /// The prefixes within `prefix`, itself included, in prefix order
+    pub fn covered_by(&self, prefix: &IpAddrPrefix) -> Iter<'_, V> {
+        match prefix.addr() {
+            IpAddr::V4(addr) => Iter(Walks::V4(
+                self.ipv4.covered_by(addr.to_bits(), prefix.length()),
+            )),
+            IpAddr::V6(addr) => Iter(Walks::V6(
+                self.ipv6.covered_by(addr.to_bits(), prefix.length()),
+            )),
+        }
+    }
+
+    /// Every prefix in prefix order, IPv4 first
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddrPrefix, &V)> {
+        Iter(Walks::V4(self.ipv4.covered_by(0, 0)))
+            .chain(Iter(Walks::V6(self.ipv6.covered_by(0, 0))))
+    }
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        PrefixTrie::new()
+    }
+}
+
+/// Inserts starting from the deepest node of the previous insertion that covers the next
+/// prefix rather than from the root, which makes building from a sorted list cheap
+impl<V> Extend<(IpAddrPrefix, V)> for PrefixTrie<V> {
+    fn extend<I: IntoIterator<Item = (IpAddrPrefix, V)>>(&mut self, iter: I) {
+        let (mut ipv4, mut ipv6) = (vec![], vec![]);
+        for (prefix, value) in iter {
+            match prefix.addr() {
+                IpAddr::V4(addr) => {
+                    self.ipv4
+                        .insert_along(&mut ipv4, addr.to_bits(), prefix.length(), value)
+                }
+                IpAddr::V6(addr) => {
+                    self.ipv6
+                        .insert_along(&mut ipv6, addr.to_bits(), prefix.length(), value)
+                }
+            }
+        }
+    }
+}
+
+impl<V> FromIterator<(IpAddrPrefix, V)> for PrefixTrie<V> {
+    fn from_iter<I: IntoIterator<Item = (IpAddrPrefix, V)>>(iter: I) -> Self {
+        let mut trie = PrefixTrie::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+/// Entries of a [`PrefixTrie`] as prefix and value
+pub struct Iter<'a, V>(Walks<'a, V>);
+
+enum Walks<'a, V> {
+    V4(TreeIter<'a, u32, V>),
+    V6(TreeIter<'a, u128, V>),
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (IpAddrPrefix, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            Walks::V4(iter) => iter.next(),
+            Walks::V6(iter) => iter.next(),
+        }
+    }
+}
+
+/// Address bits, the first bit of a prefix being the most significant
+trait Key: Copy + Eq + std::fmt::Debug {
+    const WIDTH: u8;
+
+    /// Clears the bits past the first `length`
+    fn mask(self, length: u8) -> Self;
+    fn bit(self, i: u8) -> usize;
+    /// How many leading bits the keys share
+    fn common(self, other: Self) -> u8;
+    fn to_addr(self) -> IpAddr;
+}
+
+macro_rules! impl_key {
+    ($key:ty, $addr:ty) => {
+        impl Key for $key {
+            const WIDTH: u8 = <$key>::BITS as u8;
+
+            fn mask(self, length: u8) -> Self {
+                self & !<$key>::MAX.checked_shr(length as u32).unwrap_or(0)
+            }
+
+            fn bit(self, i: u8) -> usize {
+                (self >> (Self::WIDTH - 1 - i) & 1) as usize
+            }
+
+            fn common(self, other: Self) -> u8 {
+                (self ^ other).leading_zeros() as u8
+            }
+
+            fn to_addr(self) -> IpAddr {
+                IpAddr::from(<$addr>::from_bits(self))
+            }
+        }
+    };
+}
+
+impl_key!(u32, Ipv4Addr);
+impl_key!(u128, Ipv6Addr);
+
+/// Marks a missing child
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+struct Tree<K, V> {
+    nodes: Vec<Node<K, V>>,
+    /// Slots of removed nodes, reused before the vector grows
+    free: Vec<u32>,
+    root: u32,
+    /// Nodes holding a value
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<K, V> {
+    key: K,
+    length: u8,
+    children: [u32; 2],
+    /// `None` for branch points
+    value: Option<V>,
+}
+
+impl<K: Key, V> Tree<K, V> {
+    fn new() -> Self {
+        Tree {
+            nodes: vec![],
+            free: vec![],
+            root: NONE,
+            len: 0,
+        }
+    }
+
+    fn alloc(&mut self, node: Node<K, V>) -> u32 {
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i as usize] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() as u32 - 1
+            }
+        }
+    }
+
+    fn link(&mut self, parent: Option<(u32, usize)>, child: u32) {
+        match parent {
+            Some((parent, bit)) => self.nodes[parent as usize].children[bit] = child,
+            None => self.root = child,
+        }
+    }
+
+    fn covers(&self, node: u32, key: K, length: u8) -> bool {
+        let node = &self.nodes[node as usize];
+        node.length <= length && key.mask(node.length) == node.key
+    }
+
+    /// Inserts below `start`, a node covering the prefix, or from the root, returning the
+    /// replaced value and the nodes from below `start` down to the one of the prefix
+    fn insert(
+        &mut self,
+        start: Option<u32>,
+        key: K,
+        length: u8,
+        value: V,
+    ) -> (Option<V>, Vec<u32>) {
+        let key = key.mask(length);
+        let mut path = vec![];
+        let (mut parent, mut current) = match start {
+            Some(start) if self.nodes[start as usize].length == length => (None, start),
+            Some(start) => {
+                let bit = key.bit(self.nodes[start as usize].length);
+                (Some((start, bit)), self.nodes[start as usize].children[bit])
+            }
+            None => (None, self.root),
+        };
+        loop {
+            if current == NONE {
+                let leaf = self.alloc(Node::leaf(key, length, value));
+                self.link(parent, leaf);
+                self.len += 1;
+                path.push(leaf);
+                return (None, path);
+            }
+            let node = &self.nodes[current as usize];
+            let common = key.common(node.key).min(node.length).min(length);
+            if common == node.length {
+                path.push(current);
+                if node.length == length {
+                    let replaced = self.nodes[current as usize].value.replace(value);
+                    self.len += replaced.is_none() as usize;
+                    return (replaced, path);
+                }
+                let bit = key.bit(node.length);
+                parent = Some((current, bit));
+                current = node.children[bit];
+                continue;
+            }
+
+            let below = node.key.bit(common);
+            let mut new = Node::leaf(key, length, value);
+            self.len += 1;
+            if common == length {
+                // The prefix covers the node
+                new.children[below] = current;
+                let new = self.alloc(new);
+                self.link(parent, new);
+                path.push(new);
+            } else {
+                let leaf = self.alloc(new);
+                let mut branch = Node::branch(key.mask(common), common);
+                branch.children[below] = current;
+                branch.children[1 - below] = leaf;
+                let branch = self.alloc(branch);
+                self.link(parent, branch);
+                path.extend([branch, leaf]);
+            }
+            return (None, path);
+        }
+    }
+
+    /// Inserts from the deepest node of `path`, the nodes down to the previous insertion,
+    /// that covers the prefix, leaving the nodes down to this one in `path`
+    fn insert_along(&mut self, path: &mut Vec<u32>, key: K, length: u8, value: V) {
+        while let Some(&last) = path.last()
+            && !self.covers(last, key, length)
+        {
+            path.pop();
+        }
+        let start = path.last().copied();
+        if start.is_some_and(|start| self.nodes[start as usize].length == length) {
+            path.pop();
+        }
+        let (_, below) = self.insert(start, key, length, value);
+        path.extend(below);
+    }
+
+    fn find(&self, key: K, length: u8) -> Option<usize> {
+        let mut current = self.root;
+        while current != NONE && self.covers(current, key, length) {
+            let node = &self.nodes[current as usize];
+            if node.length == length {
+                return node.value.is_some().then_some(current as usize);
+            }
+            current = node.children[key.bit(node.length)];
+        }
+        None
+    }
+
+    fn remove(&mut self, key: K, length: u8) -> Option<V> {
+        let mut parents = vec![];
+        let mut current = self.root;
+        while current != NONE && self.covers(current, key, length) {
+            let node = &self.nodes[current as usize];
+            if node.length == length {
+                break;
+            }
+            let bit = key.bit(node.length);
+            parents.push((current, bit));
+            current = node.children[bit];
+        }
+        if current == NONE
+            || !self.covers(current, key, length)
+            || self.nodes[current as usize].length != length
+        {
+            return None;
+        }
+        let value = self.nodes[current as usize].value.take()?;
+        self.len -= 1;
+
+        // Drop the node unless it still branches, and its parent when that was only branching
+        // between it and another
+        self.prune(parents.last().copied(), current);
+        if let Some(&(parent, _)) = parents.last()
+            && self.nodes[parent as usize].value.is_none()
+        {
+            let grandparent = parents.len().checked_sub(2).map(|i| parents[i]);
+            self.prune(grandparent, parent);
+        }
+        Some(value)
+    }
+
+    /// Removes a node without value and with at most one child, attaching the child to the
+    /// parent
+    fn prune(&mut self, parent: Option<(u32, usize)>, node: u32) {
+        let children = self.nodes[node as usize].children;
+        let child = match children {
+            [NONE, child] | [child, NONE] => child,
+            _ => return,
+        };
+        self.link(parent, child);
+        self.nodes[node as usize].children = [NONE; 2];
+        self.free.push(node);
+    }
+
+    fn covering(&self, key: K, length: u8) -> TreeIter<'_, K, V> {
+        TreeIter {
+            tree: self,
+            walk: Walk::Covering {
+                key,
+                length,
+                next: self.root,
+            },
+        }
+    }
+
+    fn covered_by(&self, key: K, length: u8) -> TreeIter<'_, K, V> {
+        let key = key.mask(length);
+        let mut current = self.root;
+        // Down to the first node within the prefix
+        while current != NONE {
+            let node = &self.nodes[current as usize];
+            if node.length >= length {
+                if node.key.mask(length) != key {
+                    current = NONE;
+                }
+                break;
+            }
+            if key.mask(node.length) != node.key {
+                current = NONE;
+                break;
+            }
+            current = node.children[key.bit(node.length)];
+        }
+        let stack = match current {
+            NONE => vec![],
+            node => vec![node],
+        };
+        TreeIter {
+            tree: self,
+            walk: Walk::Subtree { stack },
+        }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn leaf(key: K, length: u8, value: V) -> Self {
+        Node {
+            key,
+            length,
+            children: [NONE; 2],
+            value: Some(value),
+        }
+    }
+
+    fn branch(key: K, length: u8) -> Self {
+        Node {
+            key,
+            length,
+            children: [NONE; 2],
+            value: None,
+        }
+    }
+}
+
+/// Entries of one family's tree
+struct TreeIter<'a, K, V> {
+    tree: &'a Tree<K, V>,
+    walk: Walk<K>,
+}
+
+enum Walk<K> {
+    /// Down the path to a prefix
+    Covering { key: K, length: u8, next: u32 },
+    /// Depth first, lower half first
+    Subtree { stack: Vec<u32> },
+}
+
+impl<'a, K: Key, V> Iterator for TreeIter<'a, K, V> {
+    type Item = (IpAddrPrefix, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let current = match &mut self.walk {
+                Walk::Covering { key, length, next } => {
+                    if *next == NONE || !self.tree.covers(*next, *key, *length) {
+                        return None;
+                    }
+                    let current = *next;
+                    let node = &self.tree.nodes[current as usize];
+                    *next = match node.length < *length {
+                        true => node.children[key.bit(node.length)],
+                        false => NONE,
+                    };
+                    current
+                }
+                Walk::Subtree { stack } => {
+                    let current = stack.pop()?;
+                    let children = self.tree.nodes[current as usize].children;
+                    stack.extend(children.into_iter().rev().filter(|&child| child != NONE));
+                    current
+                }
+            };
+            let node = &self.tree.nodes[current as usize];
+            if let Some(value) = &node.value {
+                let prefix = IpAddrPrefix::new(node.key.to_addr(), node.length).unwrap();
+                return Some((prefix, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn prefix(prefix: &str) -> IpAddrPrefix {
+        prefix.parse().unwrap()
+    }
+
+    fn prefixes<'a, V: 'a>(entries: impl Iterator<Item = (IpAddrPrefix, &'a V)>) -> Vec<String> {
+        entries.map(|(prefix, _)| prefix.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lookups() {
+        let mut trie = PrefixTrie::new();
+        for (i, p) in [
+            "10.0.0.0/8",
+            "10.1.0.0/16",
+            "10.1.2.0/24",
+            "10.2.0.0/16",
+            "2001:db8::/32",
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!(trie.insert(&prefix(p), i), None);
+        }
+        assert_eq!(trie.insert(&prefix("10.1.0.0/16"), 9), Some(1));
+        assert_eq!(trie.len(), 5);
+
+        assert_eq!(trie.get(&prefix("10.1.0.0/16")), Some(&9));
+        // Branch points between prefixes hold no value
+        assert_eq!(trie.get(&prefix("10.0.0.0/14")), None);
+        assert_eq!(trie.get(&prefix("10.1.2.0/23")), None);
+
+        let (longest, value) = trie.longest_match("10.1.2.3".parse().unwrap()).unwrap();
+        assert_eq!(
+            (longest.to_string(), *value),
+            ("10.1.2.0/24".to_string(), 2)
+        );
+        assert_eq!(trie.longest_match("11.0.0.1".parse().unwrap()), None);
+        assert_eq!(
+            prefixes(trie.covering(&prefix("10.1.2.128/25"))),
+            ["10.0.0.0/8", "10.1.0.0/16", "10.1.2.0/24"]
+        );
+        assert_eq!(
+            prefixes(trie.covered_by(&prefix("10.0.0.0/14"))),
+            ["10.1.0.0/16", "10.1.2.0/24", "10.2.0.0/16"]
+        );
+        assert_eq!(
+            prefixes(trie.iter()),
+            [
+                "10.0.0.0/8",
+                "10.1.0.0/16",
+                "10.1.2.0/24",
+                "10.2.0.0/16",
+                "2001:db8::/32"
+            ]
+        );
+
+        *trie.get_mut(&prefix("2001:db8::/32")).unwrap() += 1;
+        assert_eq!(trie.remove(&prefix("2001:db8::/32")), Some(5));
+        assert_eq!(trie.remove(&prefix("10.0.0.0/14")), None);
+        assert_eq!(trie.remove(&prefix("10.1.0.0/16")), Some(9));
+        assert_eq!(
+            prefixes(trie.iter()),
+            ["10.0.0.0/8", "10.1.2.0/24", "10.2.0.0/16"]
+        );
+    }
+
+    #[test]
+    fn test_default_routes() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(&prefix("0.0.0.0/0"), "default");
+        trie.insert(&prefix("::/0"), "default6");
+        trie.insert(&prefix("192.0.2.0/24"), "doc");
+
+        assert_eq!(prefixes(trie.covering(&prefix("0.0.0.0/0"))), ["0.0.0.0/0"]);
+        assert_eq!(
+            prefixes(trie.covered_by(&prefix("0.0.0.0/0"))),
+            ["0.0.0.0/0", "192.0.2.0/24"]
+        );
+        assert_eq!(prefixes(trie.covered_by(&prefix("::/0"))), ["::/0"]);
+        assert_eq!(
+            trie.longest_match("2001:db8::1".parse().unwrap()),
+            Some((prefix("::/0"), &"default6"))
+        );
+        assert_eq!(
+            trie.longest_match("198.51.100.1".parse().unwrap()),
+            Some((prefix("0.0.0.0/0"), &"default"))
+        );
+
+        trie.remove(&prefix("0.0.0.0/0"));
+        assert_eq!(
+            prefixes(trie.covered_by(&prefix("0.0.0.0/0"))),
+            ["192.0.2.0/24"]
+        );
+        assert_eq!(trie.longest_match("198.51.100.1".parse().unwrap()), None);
+    }
+
+    /// Prefixes mostly within 10.0.0.0/20 and 2001:db8::/44, so that they overlap a lot, and
+    /// now and then short ones up to the default route
+    fn generated(next: &mut impl FnMut() -> u64) -> IpAddrPrefix {
+        let bits = next();
+        let (addr, length) = match bits % 2 {
+            0 => (
+                IpAddr::from([10, 0, (bits >> 8) as u8 & 0xf, (bits >> 16) as u8]),
+                20 + (bits >> 32) % 13,
+            ),
+            _ => {
+                let group = (bits >> 8) as u16;
+                let addr = IpAddr::from([0x2001, 0xdb8, group & 0xf, group, 0, 0, 0, 0]);
+                (addr, 44 + (bits >> 32) % 21)
+            }
+        };
+        let length = match (bits >> 40) % 16 {
+            0 => (bits >> 44) % 21,
+            _ => length,
+        };
+        IpAddrPrefix::new(addr, length as u8).unwrap()
+    }
+
+    #[test]
+    fn test_against_vec_scan() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let covers = |outer: &IpAddrPrefix, inner: &IpAddrPrefix| {
+            outer.afi() == inner.afi()
+                && outer.length() <= inner.length()
+                && IpAddrPrefix::new(inner.addr(), outer.length()).as_ref() == Some(outer)
+        };
+
+        for round in 0..30 {
+            let mut trie = PrefixTrie::new();
+            let mut reference: Vec<(IpAddrPrefix, u64)> = vec![];
+            if round % 2 == 0 {
+                // Bulk construction from a sorted list
+                let mut entries: Vec<_> = (0..next() % 300)
+                    .map(|i| (generated(&mut next), i))
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries.dedup_by(|a, b| a.0 == b.0);
+                trie = entries.iter().cloned().collect();
+                reference = entries;
+            }
+
+            for op in 0..300 {
+                let prefix = generated(&mut next);
+                let existing = reference.iter().position(|(p, _)| *p == prefix);
+                match next() % 3 {
+                    0 => {
+                        let removed = existing.map(|i| reference.remove(i).1);
+                        assert_eq!(trie.remove(&prefix), removed, "{prefix}");
+                    }
+                    _ => {
+                        let replaced = match existing {
+                            Some(i) => Some(std::mem::replace(&mut reference[i].1, op)),
+                            None => {
+                                reference.push((prefix.clone(), op));
+                                None
+                            }
+                        };
+                        assert_eq!(trie.insert(&prefix, op), replaced, "{prefix}");
+                    }
+                }
+                assert_eq!(trie.len(), reference.len());
+
+                let query = generated(&mut next);
+                let mut covering: Vec<_> = reference
+                    .iter()
+                    .filter(|(p, _)| covers(p, &query))
+                    .collect();
+                covering.sort_by_key(|(p, _)| p.length());
+                let covering: Vec<_> = covering.into_iter().map(|(p, v)| (p.clone(), v)).collect();
+                assert_eq!(
+                    trie.covering(&query).collect::<Vec<_>>(),
+                    covering,
+                    "{query}"
+                );
+                assert_eq!(
+                    trie.get(&query),
+                    reference.iter().find(|(p, _)| *p == query).map(|(_, v)| v)
+                );
+
+                let mut covered: Vec<_> = reference
+                    .iter()
+                    .filter(|(p, _)| covers(&query, p))
+                    .map(|(p, v)| (p.clone(), v))
+                    .collect();
+                covered.sort();
+                assert_eq!(
+                    trie.covered_by(&query).collect::<Vec<_>>(),
+                    covered,
+                    "{query}"
+                );
+
+                let host = IpAddrPrefix::new(query.addr(), query.max_length()).unwrap();
+                let longest = reference
+                    .iter()
+                    .filter(|(p, _)| covers(p, &host))
+                    .max_by_key(|(p, _)| p.length())
+                    .map(|(p, v)| (p.clone(), v));
+                assert_eq!(trie.longest_match(query.addr()), longest);
+            }
+
+            let mut all: Vec<_> = reference.iter().map(|(p, v)| (p.clone(), v)).collect();
+            all.sort();
+            assert_eq!(trie.iter().collect::<Vec<_>>(), all);
+        }
+    }
+}
@@ -1,35 +1,203 @@
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use crate::attribute::PathAttribute;
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::address_family::{Afi, Safi};
+use crate::attribute::{
+    Aggregator, AsPath, AttributeType, AttributeValue, Attributes, AttributesMut, Communities,
+    Community, LocalPref, MpReachNlri, MpUnreachNlri, MultiExitDisc, NextHop, Origin, OriginType,
+    PathAttribute,
+};
 use crate::error::{Error as BgpError, ErrorKind};
+use crate::list::SmallList;
+use crate::open_message::OpenMessage;
+use crate::spans;
 
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct UpdateMessage {
     pub withdrawn_routes: Vec<IpAddrPrefix>,
     pub path_attributes: Vec<PathAttribute>,
     pub nlri: Vec<IpAddrPrefix>,
 }
 
-#[derive(Debug, PartialEq)]
+/// Assembles UPDATE messages with the standard attribute flags.
+///
+/// IPv4 prefixes travel in the classic fields, IPv6 prefixes in MP_REACH_NLRI and
+/// MP_UNREACH_NLRI. Path attributes are only included when something is announced.
+#[derive(Debug, Clone)]
+pub struct UpdateMessageBuilder {
+    withdrawn: Vec<IpAddrPrefix>,
+    announced: Vec<IpAddrPrefix>,
+    origin: Option<OriginType>,
+    as_path: Option<AsPath>,
+    next_hop: Option<IpAddr>,
+    mp_next_hop: Option<(IpAddr, Option<Ipv6Addr>)>,
+    med: Option<u32>,
+    local_pref: Option<u32>,
+    communities: SmallList<Community, 4>,
+    other: Vec<PathAttribute>,
+    four_octet_as: bool,
+}
+
+/// A prefix that isn't of the form `addr/length`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid prefix {0:?}")]
+pub struct ParsePrefixError(String);
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct IpAddrPrefix {
     length: u8,
-    prefix: Vec<u8>, // TODO: replace with ip addr
+    /// Octets of the address, 4 or 16
+    addr_len: u8,
+    /// The address in its first `addr_len` octets, the rest zero
+    prefix: [u8; 16],
+    /// ADD-PATH path identifier (RFC 7911) the prefix was sent with
+    path_id: Option<u32>,
+}
+
+/// What a session negotiated about the encoding of UPDATEs, and limits on what decoding takes
+/// the wire's word for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Elements any one list may hold, be it prefixes, path attributes, AS path segments or
+    /// their ASNs, or communities. Longer lists fail with [`ErrorKind::CollectionTooLong`].
+    pub max_collection_len: usize,
+    /// AS_PATHs carry 4 octet ASNs (RFC 6793), off for a speaker without the capability
+    pub four_octet_as: bool,
+    /// Families whose prefixes are preceded by a path identifier (RFC 7911)
+    pub add_path: AddPathFamilies,
+}
+
+/// No more elements than an extended message (RFC 8654) could carry, so no valid message is
+/// refused. ASNs take 4 octets and there are no path identifiers.
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            max_collection_len: u16::MAX as usize,
+            four_octet_as: true,
+            add_path: AddPathFamilies::NONE,
+        }
+    }
+}
+
+/// The decodable families, IPv4 and IPv6 unicast and multicast, that carry ADD-PATH path
+/// identifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AddPathFamilies(u8);
+
+impl AddPathFamilies {
+    pub const NONE: AddPathFamilies = AddPathFamilies(0);
+    pub const ALL: AddPathFamilies = AddPathFamilies(0b1111);
+
+    /// Families other than IPv4 and IPv6 unicast and multicast are ignored, their NLRI aren't
+    /// decoded anyway
+    pub fn insert(&mut self, afi: Afi, safi: Safi) {
+        if let Some(bit) = Self::bit(afi, safi) {
+            self.0 |= bit;
+        }
+    }
+
+    pub fn contains(&self, afi: Afi, safi: Safi) -> bool {
+        Self::bit(afi, safi).is_some_and(|bit| self.0 & bit != 0)
+    }
+
+    fn bit(afi: Afi, safi: Safi) -> Option<u8> {
+        let afi = match afi {
+            Afi::Ipv4 => 0,
+            Afi::Ipv6 => 2,
+            Afi::Unknown(_) => return None,
+        };
+        let safi = match safi {
+            Safi::Unicast => 0,
+            Safi::Multicast => 1,
+            Safi::Unknown(_) => return None,
+        };
+        Some(1 << (afi + safi))
+    }
+}
+
+impl FromIterator<(Afi, Safi)> for AddPathFamilies {
+    fn from_iter<I: IntoIterator<Item = (Afi, Safi)>>(iter: I) -> Self {
+        let mut families = AddPathFamilies::NONE;
+        for (afi, safi) in iter {
+            families.insert(afi, safi);
+        }
+        families
+    }
+}
+
+impl ParserConfig {
+    /// Fails unless a list of `len` elements may take one more
+    pub(crate) fn check_push(&self, len: usize) -> Result<(), ErrorKind> {
+        match len < self.max_collection_len {
+            true => Ok(()),
+            false => Err(ErrorKind::CollectionTooLong),
+        }
+    }
 }
 
 impl UpdateMessage {
     pub fn try_decode(data: &mut Bytes) -> Result<Self, BgpError> {
-        let c_data = data.clone().to_owned();
+        Self::decode(data, &ParserConfig::default())
+    }
+
+    /// Decodes an UPDATE with the ASN width and ADD-PATH families of `config`, within its limits
+    pub fn try_decode_with(data: &mut Bytes, config: &ParserConfig) -> Result<Self, BgpError> {
+        Self::decode(data, config)
+    }
+
+    /// Decodes an UPDATE whose prefixes are each preceded by a path identifier, as sent by a
+    /// speaker that negotiated sending ADD-PATH (RFC 7911) for their family
+    pub fn try_decode_add_path(data: &mut Bytes) -> Result<Self, BgpError> {
+        let config = ParserConfig {
+            add_path: AddPathFamilies::ALL,
+            ..ParserConfig::default()
+        };
+        Self::decode(data, &config)
+    }
+
+    /// Decodes an UPDATE from a speaker without the 4 octet AS capability, whose AS_PATH
+    /// carries 2 octet ASNs.
+    ///
+    /// ASNs beyond 16 bits are restored from AS4_PATH and AS4_AGGREGATOR as RFC 6793 describes,
+    /// and those attributes removed, so the message reads as if from a 4 octet speaker.
+    pub fn try_decode_two_octet_as(data: &mut Bytes) -> Result<Self, BgpError> {
+        let config = ParserConfig {
+            four_octet_as: false,
+            ..ParserConfig::default()
+        };
+        Self::decode(data, &config)
+    }
+
+    /// Decodes an UPDATE with the ASN width and ADD-PATH families of `config`, and no list
+    /// longer than it allows
+    pub(crate) fn decode(data: &mut Bytes, config: &ParserConfig) -> Result<Self, BgpError> {
+        // The classic fields hold IPv4 unicast prefixes
+        let add_path = config.add_path.contains(Afi::Ipv4, Safi::Unicast);
         if data.len() < 2 {
-            return Err(ErrorKind::BadMessageLength.with_bytes(c_data));
+            return Err(ErrorKind::BadMessageLength.as_err());
         }
+        let len = data.len();
 
         let withdrawn_len = data.get_u16() as usize;
+        spans::consumed(data, 2, || {
+            format!("withdrawn routes length: {withdrawn_len}")
+        });
 
         let withdrawn_routes = if withdrawn_len != 0 {
             if data.len() < withdrawn_len {
                 return Err(ErrorKind::MalformedAttributeList.as_err());
             }
             let mut withdrawn_data = data.copy_to_bytes(withdrawn_len);
-            IpAddrPrefix::decode_stream(&mut withdrawn_data, 4)?
+            spans::enter(spans::mark(&withdrawn_data), || "withdrawn routes".into());
+            let withdrawn_routes =
+                IpAddrPrefix::decode_stream(&mut withdrawn_data, 4, add_path, config)?;
+            spans::leave(&withdrawn_data);
+            withdrawn_routes
         } else {
             vec![]
         };
@@ -38,6 +206,9 @@ impl UpdateMessage {
             return Err(ErrorKind::MalformedAttributeList.as_err());
         }
         let attributes_len = data.get_u16() as usize;
+        spans::consumed(data, 2, || {
+            format!("path attributes length: {attributes_len}")
+        });
         if data.len() < attributes_len {
             return Err(ErrorKind::MalformedAttributeList.as_err());
         }
@@ -45,59 +216,622 @@ impl UpdateMessage {
         let mut attributes_data = data.copy_to_bytes(attributes_len);
         let mut path_attributes = Vec::new();
 
+        spans::enter(spans::mark(&attributes_data), || "path attributes".into());
         while !attributes_data.is_empty() {
-            let attr = PathAttribute::try_decode(&mut attributes_data)?;
+            config
+                .check_push(path_attributes.len())
+                .map_err(|kind| kind.as_err())?;
+            let at = len - data.len() - attributes_data.len();
+            let attr = PathAttribute::decode(&mut attributes_data, config)
+                .map_err(|err| err.offset_by(at))?;
             path_attributes.push(attr);
         }
+        spans::leave(&attributes_data);
 
-        let nlri = IpAddrPrefix::decode_stream(data, 4)?; // NOTE: assumes ipv4
+        spans::enter(spans::mark(data), || "NLRI".into());
+        let nlri = IpAddrPrefix::decode_stream(data, 4, add_path, config)?; // NOTE: assumes ipv4
+        spans::leave(data);
 
-        Ok(UpdateMessage {
+        let mut update = UpdateMessage {
             withdrawn_routes,
             path_attributes,
             nlri,
-        })
+        };
+        if !config.four_octet_as {
+            merge_as4_attributes(&mut update.path_attributes, config);
+        }
+        Ok(update)
+    }
+
+    /// The End-of-RIB marker for a family (RFC 4724)
+    pub fn end_of_rib(afi: Afi, safi: Safi) -> Self {
+        let mut update = UpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![],
+            nlri: vec![],
+        };
+        if (afi, safi) != (Afi::Ipv4, Safi::Unicast) {
+            update.path_attributes.push(attribute(
+                AttributeType::MpUnreachNlri,
+                AttributeValue::MpUnreachNlri(Box::new(MpUnreachNlri {
+                    afi,
+                    safi,
+                    withdrawn_routes: vec![],
+                })),
+            ));
+        }
+        update
+    }
+
+    /// Typed lookups into the path attributes, such as `update.attributes().next_hop()`
+    pub fn attributes(&self) -> Attributes<'_> {
+        Attributes::new(&self.path_attributes)
+    }
+
+    /// Edits to the path attributes, such as `update.attributes_mut().add_community(..)`
+    pub fn attributes_mut(&mut self) -> AttributesMut<'_> {
+        AttributesMut::new(&mut self.path_attributes)
+    }
+
+    /// The family this UPDATE marks End-of-RIB for, if it is such a marker
+    pub fn end_of_rib_family(&self) -> Option<(Afi, Safi)> {
+        if !self.withdrawn_routes.is_empty() || !self.nlri.is_empty() {
+            return None;
+        }
+        match self.path_attributes.as_slice() {
+            [] => Some((Afi::Ipv4, Safi::Unicast)),
+            [attribute] => match &attribute.value {
+                AttributeValue::MpUnreachNlri(mp_unreach)
+                    if mp_unreach.withdrawn_routes.is_empty() =>
+                {
+                    Some((mp_unreach.afi, mp_unreach.safi))
+                }
+                // Families we don't decode only carry AFI and SAFI when empty
+                AttributeValue::Unknown(value)
+                    if attribute.type_code == AttributeType::MpUnreachNlri && value.len() == 3 =>
+                {
+                    Some((
+                        Afi::from(u16::from_be_bytes([value[0], value[1]])),
+                        Safi::from(value[2]),
+                    ))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Octets [`UpdateMessage::to_bytes`] produces, the message without its header
+    pub fn encoded_len(&self) -> usize {
+        let withdrawn_len: usize = self
+            .withdrawn_routes
+            .iter()
+            .map(IpAddrPrefix::encoded_len)
+            .sum();
+        let attributes_len: usize = self
+            .path_attributes
+            .iter()
+            .map(PathAttribute::encoded_len)
+            .sum();
+        let nlri_len: usize = self.nlri.iter().map(IpAddrPrefix::encoded_len).sum();
+        2 + withdrawn_len + 2 + attributes_len + nlri_len
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::new();
+
+        let mut withdrawn = BytesMut::new();
+        for prefix in &self.withdrawn_routes {
+            prefix.encode(&mut withdrawn);
+        }
+        buffer.put_u16(withdrawn.len() as u16);
+        buffer.put_slice(&withdrawn);
+
+        let mut attributes = BytesMut::new();
+        for attribute in &self.path_attributes {
+            attribute.encode(&mut attributes);
+        }
+        buffer.put_u16(attributes.len() as u16);
+        buffer.put_slice(&attributes);
+
+        for prefix in &self.nlri {
+            prefix.encode(&mut buffer);
+        }
+
+        buffer.freeze()
+    }
+}
+
+impl UpdateMessageBuilder {
+    pub fn new() -> Self {
+        UpdateMessageBuilder {
+            withdrawn: vec![],
+            announced: vec![],
+            origin: None,
+            as_path: None,
+            next_hop: None,
+            mp_next_hop: None,
+            med: None,
+            local_pref: None,
+            communities: SmallList::new(),
+            other: vec![],
+            four_octet_as: true,
+        }
+    }
+
+    pub fn withdraw(mut self, prefix: IpAddrPrefix) -> Self {
+        self.withdrawn.push(prefix);
+        self
+    }
+
+    pub fn announce(mut self, prefix: IpAddrPrefix) -> Self {
+        self.announced.push(prefix);
+        self
+    }
+
+    pub fn origin(mut self, origin: OriginType) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    pub fn as_path(mut self, as_path: AsPath) -> Self {
+        self.as_path = Some(as_path);
+        self
+    }
+
+    /// NEXT_HOP for IPv4 prefixes, the MP_REACH_NLRI next hop for IPv6 prefixes
+    pub fn next_hop(mut self, next_hop: IpAddr) -> Self {
+        self.next_hop = Some(next_hop);
+        self
+    }
+
+    /// The MP_REACH_NLRI next hop when it differs from [`next_hop`](Self::next_hop), with the
+    /// link-local address that may follow an IPv6 global one
+    pub fn mp_next_hop(mut self, next_hop: IpAddr, link_local: Option<Ipv6Addr>) -> Self {
+        self.mp_next_hop = Some((next_hop, link_local));
+        self
+    }
+
+    pub fn med(mut self, med: u32) -> Self {
+        self.med = Some(med);
+        self
+    }
+
+    pub fn local_pref(mut self, local_pref: u32) -> Self {
+        self.local_pref = Some(local_pref);
+        self
+    }
+
+    pub fn community(mut self, community: Community) -> Self {
+        self.communities.push(community);
+        self
+    }
+
+    /// Adds an attribute the builder has no dedicated setter for
+    pub fn attribute(mut self, attribute: PathAttribute) -> Self {
+        self.other.push(attribute);
+        self
+    }
+
+    /// Encodes AS_PATH with 2 octet ASNs for peers without 4 octet AS support, substituting
+    /// AS_TRANS and carrying the real path in AS4_PATH (RFC 6793)
+    pub fn four_octet_as(mut self, four_octet_as: bool) -> Self {
+        self.four_octet_as = four_octet_as;
+        self
+    }
+
+    pub fn build(self) -> UpdateMessage {
+        let (withdrawn_routes, mp_withdrawn): (Vec<_>, Vec<_>) = self
+            .withdrawn
+            .into_iter()
+            .partition(|prefix| prefix.afi() == Afi::Ipv4);
+        let (nlri, mp_nlri): (Vec<_>, Vec<_>) = self
+            .announced
+            .into_iter()
+            .partition(|prefix| prefix.afi() == Afi::Ipv4);
+
+        let mut path_attributes = vec![];
+        if !nlri.is_empty() || !mp_nlri.is_empty() {
+            let origin = self.origin.unwrap_or(OriginType::Igp);
+            path_attributes.push(attribute(
+                AttributeType::Origin,
+                AttributeValue::Origin(Origin {
+                    origin_type: origin,
+                }),
+            ));
+
+            let as_path = self.as_path.unwrap_or_default();
+            if self.four_octet_as {
+                path_attributes.push(attribute(
+                    AttributeType::AsPath,
+                    AttributeValue::AsPath(Box::new(as_path)),
+                ));
+            } else {
+                path_attributes.extend(two_octet_as_path(as_path));
+            }
+
+            if let (false, Some(IpAddr::V4(ip))) = (nlri.is_empty(), self.next_hop) {
+                path_attributes.push(attribute(
+                    AttributeType::NextHop,
+                    AttributeValue::NextHop(NextHop { ip }),
+                ));
+            }
+            if let Some(med) = self.med {
+                path_attributes.push(attribute(
+                    AttributeType::MultiExitDisc,
+                    AttributeValue::MultiExitDisc(MultiExitDisc { med }),
+                ));
+            }
+            if let Some(pref) = self.local_pref {
+                path_attributes.push(attribute(
+                    AttributeType::LocalPref,
+                    AttributeValue::LocalPref(LocalPref { pref }),
+                ));
+            }
+            if !self.communities.is_empty() {
+                path_attributes.push(attribute(
+                    AttributeType::Communities,
+                    AttributeValue::Communities(Communities {
+                        communities: self.communities,
+                    }),
+                ));
+            }
+            if !mp_nlri.is_empty() {
+                let (next_hop, link_local) = self.mp_next_hop.unwrap_or((
+                    self.next_hop.unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                    None,
+                ));
+                path_attributes.push(attribute(
+                    AttributeType::MpReachNlri,
+                    AttributeValue::MpReachNlri(Box::new(MpReachNlri {
+                        afi: Afi::Ipv6,
+                        safi: Safi::Unicast,
+                        next_hop,
+                        link_local,
+                        nlri: mp_nlri,
+                    })),
+                ));
+            }
+            path_attributes.extend(self.other);
+        }
+        if !mp_withdrawn.is_empty() {
+            path_attributes.push(attribute(
+                AttributeType::MpUnreachNlri,
+                AttributeValue::MpUnreachNlri(Box::new(MpUnreachNlri {
+                    afi: Afi::Ipv6,
+                    safi: Safi::Unicast,
+                    withdrawn_routes: mp_withdrawn,
+                })),
+            ));
+        }
+        // Ascending type codes, as RFC 4271 recommends
+        path_attributes.sort_by_key(|attribute| u8::from(&attribute.type_code));
+
+        UpdateMessage {
+            withdrawn_routes,
+            path_attributes,
+            nlri,
+        }
+    }
+}
+
+impl Default for UpdateMessageBuilder {
+    fn default() -> Self {
+        UpdateMessageBuilder::new()
+    }
+}
+
+/// An attribute with the flags of its type
+fn attribute(type_code: AttributeType, value: AttributeValue) -> PathAttribute {
+    PathAttribute {
+        flags: type_code.default_flags(),
+        type_code,
+        value,
+    }
+}
+
+/// Attributes carrying 4 octet ASNs past speakers without the capability (RFC 6793)
+const AS4_PATH: u8 = 17;
+const AS4_AGGREGATOR: u8 = 18;
+
+/// AS_PATH with ASNs beyond 16 bits replaced by AS_TRANS, followed by AS4_PATH when any were
+fn two_octet_as_path(as_path: AsPath) -> Vec<PathAttribute> {
+    let mut value = BytesMut::new();
+    as_path.encode(&mut value, false);
+    let needs_as4_path = as_path
+        .segments
+        .iter()
+        .flat_map(|segment| &segment.asns)
+        .any(|asn| u16::try_from(*asn).is_err());
+
+    let mut attributes = vec![attribute(
+        AttributeType::AsPath,
+        AttributeValue::Unknown(value.freeze()),
+    )];
+    if needs_as4_path {
+        let mut as4_path = BytesMut::new();
+        as_path.encode(&mut as4_path, true);
+        attributes.push(attribute(
+            AttributeType::Unknown(AS4_PATH),
+            AttributeValue::Unknown(as4_path.freeze()),
+        ));
+    }
+    attributes
+}
+
+/// Replaces AS_PATH and AGGREGATOR by their 4 octet forms, removing AS4_PATH and
+/// AS4_AGGREGATOR.
+///
+/// Following RFC 6793 section 4.2.3, both are ignored when AGGREGATOR names an ASN other than
+/// AS_TRANS, and AS4_PATH is ignored when malformed or longer than AS_PATH. Otherwise it
+/// replaces as many trailing ASNs of AS_PATH as it holds.
+pub(crate) fn merge_as4_attributes(attributes: &mut Vec<PathAttribute>, config: &ParserConfig) {
+    let mut as4_path = None;
+    let mut as4_aggregator = None;
+    attributes.retain(|attribute| match (&attribute.type_code, &attribute.value) {
+        (AttributeType::Unknown(AS4_PATH), AttributeValue::Unknown(value)) => {
+            as4_path = AsPath::try_decode(&mut value.clone(), true, config).ok();
+            if as4_path.is_none() {
+                discarded(AS4_PATH, "malformed");
+            }
+            false
+        }
+        (AttributeType::Unknown(AS4_AGGREGATOR), AttributeValue::Unknown(value)) => {
+            match value.len() {
+                8 => {
+                    let mut value = value.clone();
+                    as4_aggregator = Some((value.get_u32(), Ipv4Addr::from_bits(value.get_u32())));
+                }
+                _ => discarded(AS4_AGGREGATOR, "malformed"),
+            }
+            false
+        }
+        _ => true,
+    });
+
+    let aggregator = attributes
+        .iter_mut()
+        .find_map(|attribute| match &mut attribute.value {
+            AttributeValue::Aggregator(aggregator) => Some(aggregator),
+            _ => None,
+        });
+    if let Some(aggregator) = aggregator {
+        // Aggregated by a 2 octet speaker, which can't have seen the 4 octet path either
+        if aggregator.asn != u32::from(OpenMessage::AS_TRANS) {
+            if as4_path.is_some() {
+                discarded(AS4_PATH, "AGGREGATOR is not AS_TRANS");
+            }
+            if as4_aggregator.is_some() {
+                discarded(AS4_AGGREGATOR, "AGGREGATOR is not AS_TRANS");
+            }
+            return;
+        }
+        if let Some((asn, ip)) = as4_aggregator {
+            *aggregator = Aggregator { asn, ip };
+        }
     }
+
+    let Some(as4_path) = as4_path else {
+        return;
+    };
+    let Some(as_path) = attributes
+        .iter_mut()
+        .find_map(|attribute| match &mut attribute.value {
+            AttributeValue::AsPath(as_path) => Some(as_path),
+            _ => None,
+        })
+    else {
+        return;
+    };
+    let Some(keep) = as_path.path_len().checked_sub(as4_path.path_len()) else {
+        discarded(AS4_PATH, "longer than AS_PATH");
+        return;
+    };
+    as_path.truncate_to(keep);
+    as_path.push_segments(as4_path.segments);
+}
+
+fn discarded(type_code: u8, reason: &'static str) {
+    event!(
+        Warn,
+        "discarded attribute",
+        "attr.type" = type_code,
+        "reason" = reason,
+    );
 }
 
 impl IpAddrPrefix {
-    /// Decodes a stream of prefixes (for NLRI or Withdrawn Routes).
-    fn decode_stream(data: &mut Bytes, addr_len: u8) -> Result<Vec<Self>, BgpError> {
-        let invalid_network_field_err =
-            ErrorKind::InvalidNetworkField.with_bytes(data.clone().to_owned());
+    /// A prefix of `length` bits of `addr`, `None` when `length` exceeds the address width.
+    ///
+    /// Host bits beyond `length` are cleared.
+    pub fn new(addr: IpAddr, length: u8) -> Option<Self> {
+        let mut prefix = [0; 16];
+        let addr_len = match addr {
+            IpAddr::V4(addr) => {
+                prefix[..4].copy_from_slice(&addr.octets());
+                4
+            }
+            IpAddr::V6(addr) => {
+                prefix = addr.octets();
+                16
+            }
+        };
+        if length > addr_len * 8 {
+            return None;
+        }
+
+        for (i, byte) in prefix.iter_mut().enumerate() {
+            let bits = (length as usize).saturating_sub(i * 8).min(8);
+            *byte &= !(0xff_u16 >> bits) as u8;
+        }
+        Some(IpAddrPrefix {
+            length,
+            addr_len,
+            prefix,
+            path_id: None,
+        })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        match <[u8; 4]>::try_from(self.octets()) {
+            Ok(octets) => IpAddr::from(octets),
+            Err(_) => IpAddr::from(self.prefix),
+        }
+    }
+
+    /// The octets of the address, host bits cleared
+    pub(crate) fn octets(&self) -> &[u8] {
+        &self.prefix[..self.addr_len as usize]
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// The ADD-PATH path identifier, `None` unless decoded from ADD-PATH NLRI
+    pub fn path_id(&self) -> Option<u32> {
+        self.path_id
+    }
+
+    /// The same prefix with another path identifier, which [`IpAddrPrefix::encode`] writes in
+    /// front of it
+    pub fn with_path_id(mut self, path_id: Option<u32>) -> Self {
+        self.path_id = path_id;
+        self
+    }
+
+    pub fn afi(&self) -> Afi {
+        match self.addr_len {
+            4 => Afi::Ipv4,
+            _ => Afi::Ipv6,
+        }
+    }
+
+    /// The width of the address, 32 or 128
+    pub fn max_length(&self) -> u8 {
+        self.addr_len * 8
+    }
+
+    /// Bit `i` of the address, counting from the most significant
+    pub(crate) fn bit(&self, i: u8) -> bool {
+        self.prefix[i as usize / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    /// Decodes a stream of prefixes (for NLRI or Withdrawn Routes), each preceded by a path
+    /// identifier when `add_path`, failing past `config`'s limit.
+    pub(crate) fn decode_stream(
+        data: &mut Bytes,
+        addr_len: u8,
+        add_path: bool,
+        config: &ParserConfig,
+    ) -> Result<Vec<Self>, BgpError> {
+        let path_id_len = if add_path { 4 } else { 0 };
         let mut prefixes = Vec::new();
         while !data.is_empty() {
-            if data.len() < 1 {
-                return Err(invalid_network_field_err);
+            // Invalid Network Field errors carry no data
+            let Some(&bit_len) = data.get(path_id_len) else {
+                return Err(ErrorKind::InvalidNetworkField.as_err());
+            };
+            let byte_len = (bit_len as usize).div_ceil(8);
+            if data.len() < path_id_len + 1 + byte_len || bit_len > addr_len * 8 {
+                return Err(ErrorKind::InvalidNetworkField.as_err());
             }
-            let bit_len = data.get_u8();
-            let byte_len = (bit_len as usize + 7) / 8;
+            config
+                .check_push(prefixes.len())
+                .map_err(|kind| kind.as_err())?;
 
-            if data.len() < byte_len {
-                return Err(invalid_network_field_err);
-            }
-            if bit_len > addr_len * 8 {
-                return Err(invalid_network_field_err);
-            }
+            let at = spans::mark(data);
+            let path_id = add_path.then(|| data.get_u32());
+            data.advance(1);
 
-            let mut prefix_bytes = data.copy_to_bytes(byte_len).to_vec();
-            prefix_bytes.resize(addr_len as usize, 0);
+            // Octets past those sent stay zero
+            let mut prefix_bytes = [0; 16];
+            prefix_bytes[..byte_len].copy_from_slice(&data[..byte_len]);
+            data.advance(byte_len);
 
             let rem = bit_len % 8;
+            let mut masked = false;
             if rem != 0 {
-                if let Some(last_byte) = prefix_bytes.get_mut(byte_len - 1) {
-                    let mask = 0xff_u8 << (8 - rem);
-                    *last_byte &= mask;
-                }
+                let last_byte = &mut prefix_bytes[byte_len - 1];
+                let mask = 0xff_u8 << (8 - rem);
+                masked = *last_byte & !mask != 0;
+                *last_byte &= mask;
             }
 
-            prefixes.push(IpAddrPrefix {
+            let prefix = IpAddrPrefix {
                 length: bit_len,
+                addr_len,
                 prefix: prefix_bytes,
-            });
+                path_id,
+            };
+            if masked {
+                event!(Warn, "masked host bits", "prefix" = prefix);
+            }
+            spans::since(at, data, || format!("prefix: {prefix}"));
+            prefixes.push(prefix);
         }
         Ok(prefixes)
     }
+
+    /// Octets [`IpAddrPrefix::encode`] writes, the path identifier included
+    pub fn encoded_len(&self) -> usize {
+        let path_id_len = if self.path_id.is_some() { 4 } else { 0 };
+        path_id_len + 1 + (self.length as usize).div_ceil(8)
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let byte_len = (self.length as usize).div_ceil(8);
+        if let Some(path_id) = self.path_id {
+            buf.put_u32(path_id);
+        }
+        buf.put_u8(self.length);
+        buf.put_slice(&self.prefix[..byte_len]);
+    }
+}
+
+/// Orders by address, then by length and path identifier, with IPv4 before IPv6
+impl Ord for IpAddrPrefix {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.addr_len, &self.prefix, self.length, self.path_id).cmp(&(
+            other.addr_len,
+            &other.prefix,
+            other.length,
+            other.path_id,
+        ))
+    }
+}
+
+impl PartialOrd for IpAddrPrefix {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for IpAddrPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr(), self.length)
+    }
+}
+
+/// Parses `addr/length`, clearing host bits
+impl FromStr for IpAddrPrefix {
+    type Err = ParsePrefixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParsePrefixError(s.to_string());
+        let (addr, length) = s.split_once('/').ok_or_else(error)?;
+        let addr = addr.parse().map_err(|_| error())?;
+        let length = length.parse().map_err(|_| error())?;
+        IpAddrPrefix::new(addr, length).ok_or_else(error)
+    }
+}
+
+/// Serialized in its textual form, e.g. `"192.0.2.0/24"`
+#[cfg(feature = "serde")]
+impl serde::Serialize for IpAddrPrefix {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
 #[cfg(test)]
@@ -140,9 +874,9 @@ mod test {
         // Verify Withdrawn Routes
         assert_eq!(msg.withdrawn_routes.len(), 2);
         assert_eq!(msg.withdrawn_routes[0].length, 8);
-        assert_eq!(msg.withdrawn_routes[0].prefix, vec![10, 0, 0, 0]);
+        assert_eq!(msg.withdrawn_routes[0].octets(), [10, 0, 0, 0]);
         assert_eq!(msg.withdrawn_routes[1].length, 16);
-        assert_eq!(msg.withdrawn_routes[1].prefix, vec![192, 168, 0, 0]);
+        assert_eq!(msg.withdrawn_routes[1].octets(), [192, 168, 0, 0]);
 
         // Verify Path Attributes
         assert_eq!(msg.path_attributes.len(), 4);
@@ -155,13 +889,32 @@ mod test {
             _ => panic!("Wrong attribute type"),
         }
 
+        let attributes = msg.attributes();
+        assert_eq!(attributes.origin().unwrap().origin_type, OriginType::Igp);
+        assert_eq!(attributes.as_path().unwrap().origin_as(), Some(65537));
+        assert_eq!(attributes.next_hop().unwrap().ip, Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(attributes.local_pref().unwrap().pref, 100);
+        assert_eq!(attributes.med(), None);
+        assert_eq!(attributes.aggregator(), None);
+        assert_eq!(attributes.communities(), None);
+        assert_eq!(
+            attributes.get(AttributeType::LocalPref),
+            Some(&msg.path_attributes[3])
+        );
+        assert!(attributes.has(AttributeType::AsPath));
+        assert!(!attributes.has(AttributeType::MpReachNlri));
+
         // Verify NLRI
         assert_eq!(msg.nlri.len(), 1);
         assert_eq!(msg.nlri[0].length, 16);
-        assert_eq!(msg.nlri[0].prefix, vec![172, 16, 0, 0]);
+        assert_eq!(msg.nlri[0].octets(), [172, 16, 0, 0]);
 
         // Ensure the buffer is fully consumed
         assert!(data.is_empty());
+
+        // Re-encoding yields the original bytes
+        let mut encoded = msg.to_bytes();
+        assert_eq!(UpdateMessage::try_decode(&mut encoded).unwrap(), msg);
     }
 
     #[test]
@@ -200,4 +953,346 @@ mod test {
         assert!(msg.path_attributes.is_empty());
         assert!(msg.nlri.is_empty());
     }
+
+    #[test]
+    fn test_prefix_new_masks_host_bits() {
+        let prefix = IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 77)), 26).unwrap();
+        assert_eq!(prefix.addr(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 64)));
+        assert_eq!(prefix.to_string(), "192.0.2.64/26");
+        assert_eq!(prefix.afi(), Afi::Ipv4);
+        assert!(IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 33).is_none());
+
+        let ipv6 = IpAddrPrefix::new("2001:db8:ffff::1".parse().unwrap(), 36).unwrap();
+        assert_eq!(ipv6.to_string(), "2001:db8:f000::/36");
+        assert_eq!(ipv6.afi(), Afi::Ipv6);
+    }
+
+    #[test]
+    fn test_decode_stream_pads_and_masks() {
+        let mut data = Bytes::from_static(&[
+            0, // 0.0.0.0/0
+            9, 10, 0xff, // 10.128.0.0/9, with host bits set
+            24, 192, 0, 2, // 192.0.2.0/24
+            32, 192, 0, 2, 1, // 192.0.2.1/32
+        ]);
+        let prefixes =
+            IpAddrPrefix::decode_stream(&mut data, 4, false, &ParserConfig::default()).unwrap();
+        let expected = ["0.0.0.0/0", "10.128.0.0/9", "192.0.2.0/24", "192.0.2.1/32"];
+        for (prefix, expected) in prefixes.iter().zip(expected) {
+            // The same as parsed, so equal and hashed alike as keys
+            assert_eq!(*prefix, expected.parse().unwrap());
+            assert_eq!(
+                prefix.encoded_len(),
+                1 + prefix.length().div_ceil(8) as usize
+            );
+        }
+        assert_eq!(prefixes[1].octets(), [10, 0x80, 0, 0]);
+        assert_eq!(prefixes.len(), 4);
+
+        let mut data = Bytes::from_static(&[36, 0x20, 0x01, 0x0d, 0xb8, 0xff]);
+        let ipv6 =
+            IpAddrPrefix::decode_stream(&mut data, 16, false, &ParserConfig::default()).unwrap();
+        assert_eq!(ipv6, ["2001:db8:f000::/36".parse().unwrap()]);
+        assert_eq!(ipv6[0].octets().len(), 16);
+        assert_eq!(ipv6[0].max_length(), 128);
+    }
+
+    #[test]
+    fn test_prefix_from_str() {
+        let prefix: IpAddrPrefix = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(prefix.to_string(), "10.0.0.0/8");
+        assert_eq!(prefix.max_length(), 32);
+        assert!(prefix.bit(4) && !prefix.bit(3));
+        assert_eq!("::/0".parse::<IpAddrPrefix>().unwrap().max_length(), 128);
+        for invalid in ["10.0.0.0", "10.0.0.0/33", "10.0.0/8", "10.0.0.0/-1"] {
+            assert!(invalid.parse::<IpAddrPrefix>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_builder_round_trip() {
+        let ipv4 = IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)), 24).unwrap();
+        let ipv6 = IpAddrPrefix::new("2001:db8::".parse().unwrap(), 32).unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(ipv4.clone())
+            .withdraw(ipv6.clone())
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 65002].into(),
+                }]
+                .into(),
+            })
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .med(10)
+            .build();
+
+        let types: Vec<_> = update
+            .path_attributes
+            .iter()
+            .map(|attribute| attribute.type_code.clone())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                AttributeType::Origin,
+                AttributeType::AsPath,
+                AttributeType::NextHop,
+                AttributeType::MultiExitDisc,
+                AttributeType::MpUnreachNlri,
+            ]
+        );
+        assert_eq!(update.nlri, vec![ipv4]);
+        assert!(update.path_attributes[3].flags.optional);
+
+        let mut encoded = update.to_bytes();
+        let decoded = UpdateMessage::try_decode(&mut encoded).unwrap();
+        assert_eq!(decoded, update);
+        assert_eq!(
+            decoded.path_attributes[4].value,
+            AttributeValue::MpUnreachNlri(Box::new(MpUnreachNlri {
+                afi: Afi::Ipv6,
+                safi: Safi::Unicast,
+                withdrawn_routes: vec![ipv6],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_add_path_round_trip() {
+        let ipv4 = IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)), 24).unwrap();
+        let ipv6 = IpAddrPrefix::new("2001:db8::".parse().unwrap(), 32).unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(ipv4.clone().with_path_id(Some(1)))
+            .announce(ipv4.clone().with_path_id(Some(2)))
+            .withdraw(ipv6.with_path_id(Some(7)))
+            .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .build();
+
+        let encoded = update.to_bytes();
+        assert_eq!(
+            UpdateMessage::try_decode_add_path(&mut encoded.clone()).unwrap(),
+            update
+        );
+        // Each path identifier reads as a /0 and the lengths of what follows are nonsense
+        assert!(UpdateMessage::try_decode(&mut encoded.clone()).is_err());
+        assert!(ipv4 < ipv4.clone().with_path_id(Some(1)));
+    }
+
+    #[test]
+    fn test_add_path_families() {
+        let families: AddPathFamilies = [(Afi::Ipv4, Safi::Unicast), (Afi::Ipv6, Safi::Multicast)]
+            .into_iter()
+            .collect();
+        assert!(families.contains(Afi::Ipv4, Safi::Unicast));
+        assert!(families.contains(Afi::Ipv6, Safi::Multicast));
+        assert!(!families.contains(Afi::Ipv4, Safi::Multicast));
+        assert!(!families.contains(Afi::Ipv6, Safi::Unicast));
+        assert!(!AddPathFamilies::ALL.contains(Afi::Unknown(25), Safi::Unicast));
+        assert!(AddPathFamilies::ALL.contains(Afi::Ipv6, Safi::Unicast));
+        assert!(!AddPathFamilies::NONE.contains(Afi::Ipv4, Safi::Unicast));
+    }
+
+    #[test]
+    fn test_builder_two_octet_as_path() {
+        let prefix = IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)), 24).unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix)
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [65001, 4200000000].into(),
+                }]
+                .into(),
+            })
+            .four_octet_as(false)
+            .build();
+
+        assert_eq!(
+            update.path_attributes[1].value,
+            AttributeValue::Unknown(Bytes::from_static(&[2, 2, 0xfd, 0xe9, 0x5b, 0xa0]))
+        );
+        assert_eq!(
+            update.path_attributes[2].type_code,
+            AttributeType::Unknown(17)
+        );
+    }
+
+    #[test]
+    fn test_end_of_rib() {
+        let ipv4 = UpdateMessage::end_of_rib(Afi::Ipv4, Safi::Unicast);
+        assert_eq!(ipv4.to_bytes().len(), 4);
+        assert_eq!(ipv4.end_of_rib_family(), Some((Afi::Ipv4, Safi::Unicast)));
+
+        let mut encoded = UpdateMessage::end_of_rib(Afi::Ipv6, Safi::Unicast).to_bytes();
+        let decoded = UpdateMessage::try_decode(&mut encoded).unwrap();
+        assert_eq!(
+            decoded.end_of_rib_family(),
+            Some((Afi::Ipv6, Safi::Unicast))
+        );
+
+        // A family whose NLRI isn't decoded is still recognized
+        let mut encoded = Bytes::from_static(&[0, 0, 0, 6, 0x80, 15, 3, 0, 1, 128]);
+        let decoded = UpdateMessage::try_decode(&mut encoded).unwrap();
+        assert_eq!(
+            decoded.end_of_rib_family(),
+            Some((Afi::Ipv4, Safi::Unknown(128)))
+        );
+        assert_eq!(
+            UpdateMessageBuilder::new()
+                .announce(IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 32).unwrap())
+                .build()
+                .end_of_rib_family(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_two_octet_as_decoding() {
+        let sequence = |asns: &[u32]| AsPath {
+            segments: [AsPathSegment {
+                segment_type: AsPathSegmentType::AsSequence,
+                asns: asns.to_vec().into(),
+            }]
+            .into(),
+        };
+        let as_path = |update: &UpdateMessage| update.attributes().as_path().unwrap().clone();
+        let prefix = IpAddrPrefix::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24).unwrap();
+        let update = UpdateMessageBuilder::new()
+            .announce(prefix.clone())
+            .as_path(sequence(&[64500, 4_200_000_000, 64501]))
+            .next_hop(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)))
+            .four_octet_as(false)
+            .build();
+        let decoded = UpdateMessage::try_decode_two_octet_as(&mut update.to_bytes()).unwrap();
+        assert_eq!(as_path(&decoded), sequence(&[64500, 4_200_000_000, 64501]));
+        assert_eq!(decoded.path_attributes.len(), 3);
+
+        // A 2 octet speaker prepended itself after the AS4_PATH was added
+        let mut update = UpdateMessageBuilder::new()
+            .announce(prefix)
+            .as_path(sequence(&[4_200_000_000, 64501]))
+            .four_octet_as(false)
+            .build();
+        update.path_attributes[1].value =
+            AttributeValue::Unknown(Bytes::from_static(&[2, 3, 0, 100, 0x5b, 0xa0, 0xfb, 0xf5]));
+        update.path_attributes.push(attribute(
+            AttributeType::Aggregator,
+            AttributeValue::Aggregator(Aggregator {
+                asn: OpenMessage::AS_TRANS.into(),
+                ip: Ipv4Addr::new(192, 0, 2, 1),
+            }),
+        ));
+        update.path_attributes.push(attribute(
+            AttributeType::Unknown(AS4_AGGREGATOR),
+            AttributeValue::Unknown(Bytes::from_static(&[0xfa, 0x56, 0xea, 0x00, 192, 0, 2, 1])),
+        ));
+        let mut encoded = update.to_bytes();
+        let decoded = UpdateMessage::try_decode_two_octet_as(&mut encoded).unwrap();
+        assert_eq!(as_path(&decoded), sequence(&[100, 4_200_000_000, 64501]));
+        assert!(
+            decoded
+                .path_attributes
+                .iter()
+                .any(|attribute| attribute.value
+                    == AttributeValue::Aggregator(Aggregator {
+                        asn: 4_200_000_000,
+                        ip: Ipv4Addr::new(192, 0, 2, 1),
+                    }))
+        );
+
+        // Read as 4 octets, the 2 octet AS_PATH is malformed
+        assert!(UpdateMessage::try_decode(&mut update.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        let prefix = |next: &mut dyn FnMut(u64) -> u64, afi: Afi| {
+            let (addr, width) = match afi {
+                Afi::Ipv4 => (IpAddr::from(Ipv4Addr::from_bits(next(1 << 32) as u32)), 33),
+                _ => (
+                    IpAddr::from(Ipv6Addr::from_bits(next(u64::MAX) as u128)),
+                    129,
+                ),
+            };
+            let path_id = (next(2) == 0).then(|| next(1 << 32) as u32);
+            IpAddrPrefix::new(addr, next(width) as u8)
+                .unwrap()
+                .with_path_id(path_id)
+        };
+
+        for _ in 0..300 {
+            // Long enough to cross the one octet attribute length and the segment limit
+            let segments = (0..next(4))
+                .map(|_| AsPathSegment {
+                    segment_type: [
+                        AsPathSegmentType::AsSet,
+                        AsPathSegmentType::AsSequence,
+                        AsPathSegmentType::AsConfedSequence,
+                        AsPathSegmentType::AsConfedSet,
+                    ][next(4) as usize],
+                    asns: (0..[0, 3, 300, 600][next(4) as usize])
+                        .map(|_| next(1 << 18) as u32)
+                        .collect(),
+                })
+                .collect();
+            let as_path = AsPath { segments };
+            for four_octet_as in [true, false] {
+                let mut buf = BytesMut::new();
+                as_path.encode(&mut buf, four_octet_as);
+                assert_eq!(as_path.encoded_len(four_octet_as), buf.len());
+            }
+
+            let mut builder = UpdateMessageBuilder::new()
+                .as_path(as_path)
+                .next_hop(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+                .four_octet_as(next(2) == 0);
+            if next(2) == 0 {
+                let link_local = (next(2) == 0).then(|| "fe80::1".parse().unwrap());
+                builder = builder.mp_next_hop("2001:db8::1".parse().unwrap(), link_local);
+            }
+            for _ in 0..next(70) {
+                builder = builder.community(Community::NO_EXPORT);
+            }
+            if next(2) == 0 {
+                builder = builder.med(next(100) as u32).local_pref(100);
+            }
+            let unknown_len = next(300) as usize;
+            builder = builder.attribute(PathAttribute {
+                flags: PathAttributeFlags {
+                    extended_length: next(2) == 0,
+                    ..PathAttributeFlags::optional_transitive()
+                },
+                type_code: AttributeType::Unknown(32),
+                value: AttributeValue::Unknown(Bytes::from(vec![0; unknown_len])),
+            });
+            for _ in 0..next(80) {
+                let afi = [Afi::Ipv4, Afi::Ipv6][next(2) as usize];
+                builder = match next(2) {
+                    0 => builder.announce(prefix(&mut next, afi)),
+                    _ => builder.withdraw(prefix(&mut next, afi)),
+                };
+            }
+            let update = builder.build();
+
+            for attribute in &update.path_attributes {
+                let mut buf = BytesMut::new();
+                attribute.encode(&mut buf);
+                assert_eq!(attribute.encoded_len(), buf.len(), "{attribute:?}");
+            }
+            for prefix in update.nlri.iter().chain(&update.withdrawn_routes) {
+                let mut buf = BytesMut::new();
+                prefix.encode(&mut buf);
+                assert_eq!(prefix.encoded_len(), buf.len());
+            }
+            assert_eq!(update.encoded_len(), update.to_bytes().len());
+        }
+    }
 }
@@ -0,0 +1,4 @@
+/// Semantic checks applied to a message after it has been decoded from the wire
+pub trait Validate<E> {
+    fn validate(&self) -> Result<(), E>;
+}
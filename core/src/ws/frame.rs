@@ -0,0 +1,138 @@
+//! WebSocket frames (RFC 6455 section 5)
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub(super) const TEXT: u8 = 0x1;
+pub(super) const CLOSE: u8 = 0x8;
+pub(super) const PING: u8 = 0x9;
+pub(super) const PONG: u8 = 0xa;
+
+/// Status codes of CLOSE frames (RFC 6455 section 7.4.1)
+pub(super) const NORMAL: u16 = 1000;
+pub(super) const GOING_AWAY: u16 = 1001;
+pub(super) const PROTOCOL_ERROR: u16 = 1002;
+pub(super) const TOO_BIG: u16 = 1009;
+
+/// A frame as received, unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub masked: bool,
+    pub payload: Vec<u8>,
+}
+
+/// A single final frame, masked with `mask` as clients must and servers must not
+pub(super) fn encode(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => frame.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(o, m)| o ^ m));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+/// A CLOSE frame from the server with a status code
+pub(super) fn close(code: u16) -> Vec<u8> {
+    encode(CLOSE, &code.to_be_bytes(), None)
+}
+
+/// Reads the next frame, `None` when the connection closes between frames.
+///
+/// Payloads longer than `max_payload` octets fail with [`io::ErrorKind::InvalidData`].
+pub(super) async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload: usize,
+) -> io::Result<Option<Frame>> {
+    let mut head = [0; 2];
+    match reader.read_exact(&mut head).await {
+        Ok(_) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let len = match head[1] & 0x7f {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    if len > max_payload as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let masked = head[1] & 0x80 != 0;
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (octet, mask) in payload.iter_mut().zip(mask.iter().cycle()) {
+        *octet ^= mask;
+    }
+    Ok(Some(Frame {
+        fin: head[0] & 0x80 != 0,
+        opcode: head[0] & 0x0f,
+        masked,
+        payload,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_frames() {
+        // RFC 6455 section 5.7
+        assert_eq!(
+            encode(TEXT, b"Hello", None),
+            b"\x81\x05\x48\x65\x6c\x6c\x6f"
+        );
+        let masked = encode(TEXT, b"Hello", Some([0x37, 0xfa, 0x21, 0x3d]));
+        assert_eq!(masked, b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58");
+
+        let frame = read_frame(&mut &masked[..], 125).await.unwrap().unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                fin: true,
+                opcode: TEXT,
+                masked: true,
+                payload: b"Hello".to_vec()
+            }
+        );
+
+        for len in [126, 0xffff, 0x10000] {
+            let payload = vec![b'x'; len];
+            let encoded = encode(TEXT, &payload, None);
+            let frame = read_frame(&mut &encoded[..], len).await.unwrap().unwrap();
+            assert_eq!(frame.payload, payload);
+            let error = read_frame(&mut &encoded[..], len - 1).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        assert!(read_frame(&mut &b""[..], 125).await.unwrap().is_none());
+        let error = read_frame(&mut &b"\x81\x05He"[..], 125).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
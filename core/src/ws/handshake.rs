@@ -0,0 +1,256 @@
+//! Request heads and the WebSocket opening handshake (RFC 6455 section 4)
+
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Appended to the client's key before hashing it into `Sec-WebSocket-Accept`
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest request head accepted
+const MAX_HEAD: usize = 8 * 1024;
+
+/// A request line and headers, with the target split and percent-decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// The first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the headers ask for a WebSocket of the version this server speaks
+    pub fn is_upgrade(&self) -> bool {
+        let has_token = |name, token: &str| {
+            self.header(name).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        has_token("Upgrade", "websocket")
+            && has_token("Connection", "upgrade")
+            && self.header("Sec-WebSocket-Version") == Some("13")
+    }
+}
+
+/// Reads a request head, `None` when the connection closes before one starts.
+///
+/// Only the head is consumed, what the client sends after it stays in `reader`.
+pub(super) async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Request>> {
+    let mut lines = vec![];
+    let mut read = 0;
+    loop {
+        let mut line = vec![];
+        let n = (&mut *reader)
+            .take((MAX_HEAD - read) as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        read += n;
+        if n == 0 && lines.is_empty() {
+            return Ok(None);
+        }
+        if !line.ends_with(b"\n") {
+            return Err(invalid("request head too large or truncated"));
+        }
+        let line = String::from_utf8(line).map_err(|_| invalid("request head is not UTF-8"))?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_owned());
+    }
+
+    let mut request_line = lines[0].split(' ');
+    let (Some(method), Some(target), Some(_version)) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(invalid("malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = percent_decode(path, false).ok_or_else(|| invalid("malformed path"))?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((percent_decode(name, true)?, percent_decode(value, true)?))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("malformed query"))?;
+    let headers = lines[1..]
+        .iter()
+        .map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_owned(), value.trim().to_owned()))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("malformed header"))?;
+    Ok(Some(Request {
+        method: method.to_owned(),
+        path,
+        query,
+        headers,
+    }))
+}
+
+/// The `Sec-WebSocket-Accept` answering a `Sec-WebSocket-Key`
+pub(super) fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes `%XX` escapes, and in query strings `+` as a space
+fn percent_decode(text: &str, query: bool) -> Option<String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut octets = text.bytes();
+    while let Some(octet) = octets.next() {
+        decoded.push(match octet {
+            b'%' => {
+                let hex = [octets.next()?, octets.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b'+' if query => b' ',
+            octet => octet,
+        });
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A827999),
+                20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (word, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *word = word.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (octets, word) in digest.chunks_exact_mut(4).zip(state) {
+        octets.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(octets: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(octets.len().div_ceil(3) * 4);
+    for chunk in octets.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, octet)| {
+            group | u32::from(*octet) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() {
+                ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char
+            } else {
+                '='
+            });
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answers_the_rfc_example_key() {
+        // RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(
+            sha1(b"abc"),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    #[tokio::test]
+    async fn reads_a_request_head() {
+        let mut input: &[u8] =
+            b"GET /rib/203.0.113.0%2F24?more_specifics=true&peer=a+b HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Upgrade: WebSocket\r\n\
+            Connection: keep-alive, Upgrade\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n\x81";
+        let request = read_request(&mut input).await.unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/rib/203.0.113.0/24");
+        assert_eq!(
+            request.query,
+            [
+                ("more_specifics".into(), "true".into()),
+                ("peer".into(), "a b".into())
+            ]
+        );
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert!(request.is_upgrade());
+        assert_eq!(input, b"\x81");
+
+        assert!(read_request(&mut &b""[..]).await.unwrap().is_none());
+        assert!(
+            read_request(&mut &b"GET /%zz HTTP/1.1\r\n\r\n"[..])
+                .await
+                .is_err()
+        );
+        assert!(
+            read_request(&mut &b"GET / HTTP/1.1\r\nHost"[..])
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,860 @@
+//! A live feed of [`RouteEvent`]s over WebSocket, for browsers and scripts.
+//!
+//! [`WsServer::serve`] answers
+//!
+//! - `GET /stream`, a WebSocket carrying each route event as a JSON text message. The
+//!   query parameters `peer=<address>` and `prefix=<prefix>` limit it to the events of a
+//!   peer and of a prefix, with `more_specifics=true` also those of prefixes the prefix
+//!   covers.
+//! - `GET /peers`, the established peers as a JSON array
+//! - `GET /rib/<prefix>`, the routes for a prefix, with the slash as is or as `%2F`
+//!
+//! An event reads
+//!
+//! ```text
+//! {"type": "announced", "peer": "192.0.2.1", "timestamp": "2024-01-31T12:00:00.250Z",
+//!  "prefix": "203.0.113.0/24", "safi": "unicast", "path_id": null,
+//!  "next_hop": "192.0.2.1", "attribute": {"origin": "igp", "as-path": [64500]},
+//!  "rpki": null}
+//! ```
+//!
+//! where `attribute` is as in exabgp's JSON, see [`crate::exabgp`]. Withdrawals, of type
+//! `withdrawn`, have no `next_hop` and `attribute`, and re-announcements, of type
+//! `reannounced`, add the replaced route's `previous_attribute`.
+//!
+//! With a [`WsServer::enricher`], events also carry `origin_as` and `origin_as_name`, for
+//! the route announced, and the `routed_prefix` covering the event's with its
+//! `routed_origins`, each `null` when unknown.
+//!
+//! The collector never waits for clients: each has a queue of
+//! [`WsServer::client_queue`] events, and one that falls further behind loses the oldest,
+//! told by a `{"type": "dropped", "count": <events>}` message. A client that doesn't take
+//! a message within [`WsServer::write_timeout`] is disconnected.
+//!
+//! This is a minimal HTTP/1.1 and WebSocket (RFC 6455) server answering one request per
+//! connection, without TLS or extensions; put a real web server in front of it for more.
+
+mod frame;
+mod handshake;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::address_family::Safi;
+use crate::attribute::{AttributeValue, PathAttribute};
+use crate::enrich::Enricher;
+use crate::exabgp::{attributes_json, safi_name};
+use crate::filter::{FilterAction, PrefixList, PrefixListEntry};
+use crate::json::Json;
+use crate::monitor::origin_as;
+use crate::rib::{AttributeSet, RouteEvent, RouteEventKind, rfc3339};
+use crate::rpki::RpkiStatus;
+use crate::session::{BusEvent, PeerInfo, Subscriber};
+use crate::update_message::IpAddrPrefix;
+
+use handshake::Request;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest frame accepted from clients, which only send control frames
+const MAX_CLIENT_FRAME: usize = 4096;
+
+/// Serves the feed, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct WsServer {
+    client_queue: usize,
+    write_timeout: Duration,
+    enricher: Option<Arc<dyn Enricher>>,
+}
+
+/// What [`WsServer::serve`] shares with its connections
+#[derive(Debug)]
+struct Shared {
+    config: WsServer,
+    snapshot: Mutex<Snapshot>,
+    events: broadcast::Sender<Arc<Streamed>>,
+}
+
+/// The peers and routes the events seen so far add up to
+#[derive(Debug, Default)]
+struct Snapshot {
+    peers: BTreeMap<IpAddr, (PeerInfo, SystemTime)>,
+    routes: HashMap<IpAddrPrefix, Vec<StoredRoute>>,
+}
+
+#[derive(Debug)]
+struct StoredRoute {
+    peer: IpAddr,
+    safi: Safi,
+    path_id: Option<u32>,
+    attributes: AttributeSet,
+    updated: SystemTime,
+}
+
+/// An event with its message, serialized once for all clients
+#[derive(Debug)]
+struct Streamed {
+    event: RouteEvent,
+    text: String,
+}
+
+/// What a client of `/stream` asked for
+#[derive(Debug, Default)]
+struct StreamFilter {
+    peer: Option<IpAddr>,
+    prefixes: Option<PrefixList>,
+}
+
+/// What the reader of a WebSocket asks its writer to send
+#[derive(Debug)]
+enum Control {
+    Pong(Vec<u8>),
+    /// Close the connection with this status code
+    Close(u16),
+}
+
+/// Aborts a task when dropped, so none outlives the connection or server it serves
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Default for WsServer {
+    fn default() -> Self {
+        WsServer {
+            client_queue: 1024,
+            write_timeout: Duration::from_secs(10),
+            enricher: None,
+        }
+    }
+}
+
+impl WsServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events a client may fall behind by before it loses the oldest, 1024 by default
+    ///
+    /// # Panics
+    ///
+    /// When `events` is zero
+    pub fn client_queue(mut self, events: usize) -> Self {
+        assert!(events > 0, "client queue must not be empty");
+        self.client_queue = events;
+        self
+    }
+
+    /// How long a client may take to accept a message before it's disconnected, 10 seconds
+    /// by default
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Adds AS names and routed prefixes to events, see the [module documentation](self)
+    pub fn enricher(mut self, enricher: Arc<dyn Enricher>) -> Self {
+        self.enricher = Some(enricher);
+        self
+    }
+
+    /// Serves the events received from `events`, typically an [`EventBus`] subscription, on
+    /// every connection accepted from `listener`, until accepting fails
+    ///
+    /// [`EventBus`]: crate::session::EventBus
+    pub async fn serve(self, listener: TcpListener, events: Subscriber) -> io::Result<()> {
+        let (sender, _) = broadcast::channel(self.client_queue);
+        let shared = Arc::new(Shared {
+            config: self,
+            snapshot: Mutex::default(),
+            events: sender,
+        });
+        let _pump = AbortOnDrop(tokio::spawn(pump(events, shared.clone())));
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                let _ = connection(stream, &shared).await;
+            });
+        }
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Keeps the snapshot up to date and hands route events to the clients
+async fn pump(mut events: Subscriber, shared: Arc<Shared>) {
+    while let Some(message) = events.recv().await {
+        match &message.value {
+            BusEvent::Route(event) => {
+                shared.snapshot.lock().unwrap().apply(event);
+                if shared.events.receiver_count() > 0 {
+                    let enricher = shared.config.enricher.as_deref();
+                    let text = event_json(event, enricher).to_string();
+                    let event = event.clone();
+                    let _ = shared.events.send(Arc::new(Streamed { event, text }));
+                }
+            }
+            BusEvent::PeerUp(up) => {
+                let mut snapshot = shared.snapshot.lock().unwrap();
+                let peer = (up.peer, message.received);
+                snapshot.peers.insert(up.peer.peer_addr.ip(), peer);
+            }
+            BusEvent::PeerDown(down) => {
+                let mut snapshot = shared.snapshot.lock().unwrap();
+                let addr = down.peer.peer_addr.ip();
+                snapshot.peers.remove(&addr);
+                if !down.retains_routes() {
+                    snapshot.routes.retain(|_, routes| {
+                        routes.retain(|route| route.peer != addr);
+                        !routes.is_empty()
+                    });
+                }
+            }
+            BusEvent::Session(_) => {}
+        }
+    }
+}
+
+async fn connection(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request =
+        match tokio::time::timeout(READ_TIMEOUT, handshake::read_request(&mut reader)).await {
+            Ok(Ok(Some(request))) => request,
+            Ok(Err(error)) if error.kind() == io::ErrorKind::InvalidData => {
+                return respond(&mut writer, "400 Bad Request", &error.to_string()).await;
+            }
+            Ok(result) => return result.map(drop),
+            Err(_) => return Ok(()),
+        };
+
+    if request.method != "GET" {
+        return respond(&mut writer, "405 Method Not Allowed", "Method not allowed").await;
+    }
+    if request.path == "/stream" {
+        return stream_events(reader, writer, &request, shared).await;
+    }
+    let body = if request.path == "/peers" {
+        shared.snapshot.lock().unwrap().peers_json()
+    } else if let Some(prefix) = request.path.strip_prefix("/rib/") {
+        let Ok(prefix) = prefix.parse() else {
+            let message = format!("Invalid prefix {prefix:?}");
+            return respond(&mut writer, "400 Bad Request", &message).await;
+        };
+        shared.snapshot.lock().unwrap().rib_json(&prefix)
+    } else {
+        return respond(&mut writer, "404 Not Found", "Not found").await;
+    };
+    write_response(&mut writer, "200 OK", "application/json", &body.to_string()).await
+}
+
+/// Upgrades the connection to a WebSocket and sends it the events the client asked for
+async fn stream_events(
+    reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    request: &Request,
+    shared: &Shared,
+) -> io::Result<()> {
+    let filter = match StreamFilter::from_query(&request.query) {
+        Ok(filter) => filter,
+        Err(message) => return respond(&mut writer, "400 Bad Request", &message).await,
+    };
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) if request.is_upgrade() => key,
+        _ => {
+            let response = "HTTP/1.1 426 Upgrade Required\r\n\
+                 Upgrade: websocket\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Content-Length: 0\r\n\
+                 Connection: close\r\n\r\n";
+            writer.write_all(response.as_bytes()).await?;
+            return writer.shutdown().await;
+        }
+    };
+
+    // Subscribed before the handshake completes, so the client sees every event after it
+    let mut events = shared.events.subscribe();
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        handshake::accept_key(key)
+    );
+    let timeout = shared.config.write_timeout;
+    send(&mut writer, response.as_bytes(), timeout).await?;
+
+    let (control_sender, mut control) = mpsc::channel(4);
+    let _reader = AbortOnDrop(tokio::spawn(read_control(reader, control_sender)));
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if filter.permits(&event.event) => {
+                    frame::encode(frame::TEXT, event.text.as_bytes(), None)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(count)) => {
+                    let dropped = Json::object([
+                        ("type", Json::from("dropped")),
+                        ("count", Json::Number(count as f64)),
+                    ]);
+                    frame::encode(frame::TEXT, dropped.to_string().as_bytes(), None)
+                }
+                Err(RecvError::Closed) => frame::close(frame::GOING_AWAY),
+            },
+            control = control.recv() => match control {
+                Some(Control::Pong(payload)) => frame::encode(frame::PONG, &payload, None),
+                Some(Control::Close(code)) => frame::close(code),
+                // The connection was lost
+                None => return Ok(()),
+            },
+        };
+        send(&mut writer, &message, timeout).await?;
+        if message[0] & 0x0f == frame::CLOSE {
+            return writer.shutdown().await;
+        }
+    }
+}
+
+/// Answers the client's control frames until it closes the WebSocket or breaks protocol
+async fn read_control(mut reader: BufReader<OwnedReadHalf>, control: mpsc::Sender<Control>) {
+    loop {
+        let reply = match frame::read_frame(&mut reader, MAX_CLIENT_FRAME).await {
+            Ok(Some(frame)) if !frame.masked || !frame.fin => Control::Close(frame::PROTOCOL_ERROR),
+            Ok(Some(frame)) => match frame.opcode {
+                frame::PING => Control::Pong(frame.payload),
+                frame::CLOSE => Control::Close(match frame.payload[..] {
+                    [high, low, ..] => u16::from_be_bytes([high, low]),
+                    _ => frame::NORMAL,
+                }),
+                _ => continue,
+            },
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                Control::Close(frame::TOO_BIG)
+            }
+            Ok(None) | Err(_) => return,
+        };
+        let closing = matches!(reply, Control::Close(_));
+        if control.send(reply).await.is_err() || closing {
+            return;
+        }
+    }
+}
+
+/// Writes `data` unless the client takes longer than `timeout` to accept it
+async fn send(writer: &mut OwnedWriteHalf, data: &[u8], timeout: Duration) -> io::Result<()> {
+    match tokio::time::timeout(timeout, writer.write_all(data)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "client too slow")),
+    }
+}
+
+/// Answers with a plain text `message`
+async fn respond(writer: &mut OwnedWriteHalf, status: &str, message: &str) -> io::Result<()> {
+    let body = format!("{message}\n");
+    write_response(writer, status, "text/plain; charset=utf-8", &body).await
+}
+
+async fn write_response(
+    writer: &mut OwnedWriteHalf,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    send(writer, response.as_bytes(), READ_TIMEOUT).await?;
+    writer.shutdown().await
+}
+
+impl Snapshot {
+    fn apply(&mut self, event: &RouteEvent) {
+        let routes = self.routes.entry(event.prefix.clone()).or_default();
+        routes.retain(|route| {
+            (route.peer, route.safi, route.path_id) != (event.peer, event.safi, event.path_id)
+        });
+        match &event.kind {
+            RouteEventKind::Announced { attrs }
+            | RouteEventKind::Reannounced {
+                new_attrs: attrs, ..
+            } => routes.push(StoredRoute {
+                peer: event.peer,
+                safi: event.safi,
+                path_id: event.path_id,
+                attributes: attrs.clone(),
+                updated: event.timestamp,
+            }),
+            RouteEventKind::Withdrawn if routes.is_empty() => {
+                self.routes.remove(&event.prefix);
+            }
+            RouteEventKind::Withdrawn => {}
+        }
+    }
+
+    fn peers_json(&self) -> Json {
+        Json::Array(
+            self.peers
+                .values()
+                .map(|(peer, since)| {
+                    Json::object([
+                        ("address", Json::from(peer.peer_addr.ip().to_string())),
+                        ("asn", Json::from(peer.asn)),
+                        ("router_id", Json::from(peer.router_id.to_string())),
+                        (
+                            "router",
+                            peer.router
+                                .map_or(Json::Null, |router| Json::from(router.to_string())),
+                        ),
+                        ("established", Json::from(rfc3339(*since))),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    fn rib_json(&self, prefix: &IpAddrPrefix) -> Json {
+        let routes = self.routes.get(prefix).map_or(&[][..], Vec::as_slice);
+        Json::object([
+            ("prefix", Json::from(prefix.to_string())),
+            (
+                "routes",
+                Json::Array(
+                    routes
+                        .iter()
+                        .map(|route| {
+                            Json::object([
+                                ("peer", Json::from(route.peer.to_string())),
+                                ("safi", Json::from(safi_name(route.safi))),
+                                ("path_id", route.path_id.map_or(Json::Null, Json::from)),
+                                ("updated", Json::from(rfc3339(route.updated))),
+                                ("next_hop", next_hop_json(&route.attributes)),
+                                ("attribute", attributes_json(&route.attributes)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+impl StreamFilter {
+    fn from_query(query: &[(String, String)]) -> Result<Self, String> {
+        let mut filter = StreamFilter::default();
+        let mut prefix = None;
+        let mut more_specifics = false;
+        for (name, value) in query {
+            let invalid = || format!("Invalid {name} {value:?}");
+            match name.as_str() {
+                "peer" => filter.peer = Some(value.parse().map_err(|_| invalid())?),
+                "prefix" => prefix = Some(value.parse::<IpAddrPrefix>().map_err(|_| invalid())?),
+                "more_specifics" => {
+                    more_specifics = match value.as_str() {
+                        "true" | "1" | "" => true,
+                        "false" | "0" => false,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(format!("Unknown parameter {name:?}")),
+            }
+        }
+        filter.prefixes = prefix.map(|prefix| {
+            let builder = PrefixList::builder();
+            let builder = if more_specifics {
+                let max = prefix.max_length();
+                let entry = PrefixListEntry::new(FilterAction::Permit, prefix, None, Some(max));
+                builder.entry(entry.expect("a prefix is no longer than its maximum length"))
+            } else {
+                builder.permit(prefix)
+            };
+            builder.build()
+        });
+        Ok(filter)
+    }
+
+    fn permits(&self, event: &RouteEvent) -> bool {
+        self.peer.is_none_or(|peer| peer == event.peer)
+            && self
+                .prefixes
+                .as_ref()
+                .is_none_or(|prefixes| prefixes.permits_prefix(&event.prefix))
+    }
+}
+
+/// A route event as streamed, see the [module documentation](self)
+fn event_json(event: &RouteEvent, enricher: Option<&dyn Enricher>) -> Json {
+    let (kind, attributes, previous) = match &event.kind {
+        RouteEventKind::Announced { attrs } => ("announced", Some(attrs), None),
+        RouteEventKind::Withdrawn => ("withdrawn", None, None),
+        RouteEventKind::Reannounced {
+            old_attrs,
+            new_attrs,
+        } => ("reannounced", Some(new_attrs), Some(old_attrs)),
+    };
+    let mut members = vec![
+        ("type", Json::from(kind)),
+        ("peer", Json::from(event.peer.to_string())),
+        ("timestamp", Json::from(rfc3339(event.timestamp))),
+        ("prefix", Json::from(event.prefix.to_string())),
+        ("safi", Json::from(safi_name(event.safi))),
+        ("path_id", event.path_id.map_or(Json::Null, Json::from)),
+    ];
+    if let Some(attributes) = attributes {
+        members.push(("next_hop", next_hop_json(attributes)));
+        members.push(("attribute", attributes_json(attributes)));
+    }
+    if let Some(previous) = previous {
+        members.push(("previous_attribute", attributes_json(previous)));
+    }
+    let rpki = event.rpki.map_or(Json::Null, |status| {
+        Json::from(match status {
+            RpkiStatus::Valid => "valid",
+            RpkiStatus::Invalid => "invalid",
+            RpkiStatus::NotFound => "not_found",
+        })
+    });
+    members.push(("rpki", rpki));
+    if let Some(enricher) = enricher {
+        let origin_as = attributes.and_then(|attributes| origin_as(attributes));
+        let routed = enricher.prefix_info(&event.prefix);
+        members.extend([
+            ("origin_as", origin_as.map_or(Json::Null, Json::from)),
+            (
+                "origin_as_name",
+                origin_as
+                    .and_then(|asn| enricher.asn_name(asn))
+                    .map_or(Json::Null, Json::from),
+            ),
+            (
+                "routed_prefix",
+                routed
+                    .as_ref()
+                    .map_or(Json::Null, |routed| Json::from(routed.prefix.to_string())),
+            ),
+            (
+                "routed_origins",
+                routed.map_or(Json::Null, |routed| {
+                    Json::Array(routed.origins.into_iter().map(Json::from).collect())
+                }),
+            ),
+        ]);
+    }
+    Json::object(members)
+}
+
+/// The NEXT_HOP or MP_REACH_NLRI next hop, `null` without either
+fn next_hop_json(attributes: &[PathAttribute]) -> Json {
+    attributes
+        .iter()
+        .find_map(|attribute| match &attribute.value {
+            AttributeValue::NextHop(hop) => Some(IpAddr::V4(hop.ip)),
+            AttributeValue::MpReachNlri(mp_reach) => Some(mp_reach.next_hop),
+            _ => None,
+        })
+        .map_or(Json::Null, |hop| Json::from(hop.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::UNIX_EPOCH;
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    use crate::attribute::{AsPath, AsPathSegment, AsPathSegmentType, OriginType};
+    use crate::capability::Capability;
+    use crate::enrich::FileEnricher;
+    use crate::open_message::OpenMessage;
+    use crate::session::{EventBus, LagPolicy, Negotiated, PeerUp};
+    use crate::timestamped::Timestamped;
+    use crate::update_message::UpdateMessageBuilder;
+
+    const PEER: &str = "192.0.2.1";
+    const OTHER_PEER: &str = "192.0.2.2";
+
+    async fn start(server: WsServer) -> (EventBus, SocketAddr) {
+        let bus = EventBus::new(LagPolicy::DropOldest, 64);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve(listener, bus.subscribe("ws")));
+        (bus, addr)
+    }
+
+    fn announced(peer: &str, prefix: &str) -> Timestamped<BusEvent> {
+        let update = UpdateMessageBuilder::new()
+            .origin(OriginType::Igp)
+            .as_path(AsPath {
+                segments: [AsPathSegment {
+                    segment_type: AsPathSegmentType::AsSequence,
+                    asns: [64500].into(),
+                }]
+                .into(),
+            })
+            .next_hop(peer.parse().unwrap())
+            .announce(prefix.parse().unwrap())
+            .build();
+        let attrs = update.path_attributes.into();
+        route(peer, prefix, RouteEventKind::Announced { attrs })
+    }
+
+    fn route(peer: &str, prefix: &str, kind: RouteEventKind) -> Timestamped<BusEvent> {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_706_702_400);
+        Timestamped::at(
+            timestamp,
+            BusEvent::Route(RouteEvent {
+                peer: peer.parse().unwrap(),
+                timestamp,
+                prefix: prefix.parse().unwrap(),
+                safi: Safi::Unicast,
+                path_id: None,
+                kind,
+                rpki: None,
+            }),
+        )
+    }
+
+    /// Opens a WebSocket on `/stream` with `query`
+    async fn connect(addr: SocketAddr, query: &str) -> BufReader<TcpStream> {
+        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let request = format!(
+            "GET /stream{query} HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        );
+        stream
+            .get_mut()
+            .write_all(request.as_bytes())
+            .await
+            .unwrap();
+        let mut head = vec![];
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_owned());
+        }
+        assert_eq!(head[0], "HTTP/1.1 101 Switching Protocols");
+        assert!(head.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_owned()));
+        stream
+    }
+
+    async fn next_message(stream: &mut BufReader<TcpStream>) -> Json {
+        let frame = frame::read_frame(stream, usize::MAX)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((frame.opcode, frame.masked), (frame::TEXT, false));
+        String::from_utf8(frame.payload).unwrap().parse().unwrap()
+    }
+
+    /// The status line and body of the response to a GET of `path`
+    async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_owned(), body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn streams_filtered_events() {
+        let (bus, addr) = start(WsServer::new()).await;
+        let mut more_specifics = connect(
+            addr,
+            "?peer=192.0.2.1&prefix=203.0.113.0/24&more_specifics=true",
+        )
+        .await;
+        let mut exact = connect(addr, "?prefix=203.0.113.0%2F24").await;
+
+        bus.publish(announced(PEER, "203.0.113.0/25")).await;
+        bus.publish(announced(OTHER_PEER, "203.0.113.0/24")).await;
+        bus.publish(announced(PEER, "198.51.100.0/24")).await;
+        bus.publish(route(PEER, "203.0.113.0/24", RouteEventKind::Withdrawn))
+            .await;
+
+        let event = next_message(&mut more_specifics).await;
+        assert_eq!(
+            event.to_string(),
+            "{\"type\":\"announced\",\"peer\":\"192.0.2.1\",\
+             \"timestamp\":\"2024-01-31T12:00:00.000Z\",\"prefix\":\"203.0.113.0/25\",\
+             \"safi\":\"unicast\",\"path_id\":null,\"next_hop\":\"192.0.2.1\",\
+             \"attribute\":{\"origin\":\"igp\",\"as-path\":[64500],\
+             \"confederation-path\":[]},\"rpki\":null}"
+        );
+        let event = next_message(&mut more_specifics).await;
+        assert_eq!(event["type"].as_str(), Some("withdrawn"));
+        assert_eq!(event["prefix"].as_str(), Some("203.0.113.0/24"));
+        assert_eq!(event.get("attribute"), None);
+
+        let event = next_message(&mut exact).await;
+        assert_eq!(event["peer"].as_str(), Some(OTHER_PEER));
+        assert_eq!(event["prefix"].as_str(), Some("203.0.113.0/24"));
+        let event = next_message(&mut exact).await;
+        assert_eq!(event["type"].as_str(), Some("withdrawn"));
+
+        // A masked CLOSE is answered in kind and ends the connection
+        let close = frame::encode(frame::CLOSE, &1000u16.to_be_bytes(), Some([1, 2, 3, 4]));
+        more_specifics.get_mut().write_all(&close).await.unwrap();
+        let frame = frame::read_frame(&mut more_specifics, 125)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (frame.opcode, frame.payload),
+            (frame::CLOSE, vec![0x03, 0xe8])
+        );
+        assert!(
+            frame::read_frame(&mut more_specifics, 125)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_enriched_events() {
+        let mut enricher = FileEnricher::new();
+        enricher.load_asn_names("64500 EXAMPLE-NET").unwrap();
+        enricher.load_pfx2as("203.0.113.0 24 64500_64501").unwrap();
+        let (bus, addr) = start(WsServer::new().enricher(Arc::new(enricher))).await;
+        let mut client = connect(addr, "").await;
+
+        bus.publish(announced(PEER, "203.0.113.128/25")).await;
+        bus.publish(route(PEER, "198.51.100.0/24", RouteEventKind::Withdrawn))
+            .await;
+
+        let event = next_message(&mut client).await;
+        assert_eq!(event["origin_as"].as_u64(), Some(64500));
+        assert_eq!(event["origin_as_name"].as_str(), Some("EXAMPLE-NET"));
+        assert_eq!(event["routed_prefix"].as_str(), Some("203.0.113.0/24"));
+        assert_eq!(event["routed_origins"].to_string(), "[64500,64501]");
+        let event = next_message(&mut client).await;
+        for member in [
+            "origin_as",
+            "origin_as_name",
+            "routed_prefix",
+            "routed_origins",
+        ] {
+            assert_eq!(event[member], Json::Null, "{member}");
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_events_for_lagging_clients() {
+        let (bus, addr) = start(WsServer::new().client_queue(2)).await;
+        let mut client = connect(addr, "").await;
+
+        // Published without yielding, so the server forwards all of them before the
+        // connection gets to send any
+        for i in 0..6 {
+            bus.publish(announced(PEER, &format!("203.0.113.{i}/32")))
+                .await;
+        }
+        let notice = next_message(&mut client).await;
+        assert_eq!(notice.to_string(), "{\"type\":\"dropped\",\"count\":4}");
+        for i in 4..6 {
+            let event = next_message(&mut client).await;
+            assert_eq!(
+                event["prefix"].as_str(),
+                Some(&*format!("203.0.113.{i}/32"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_snapshots() {
+        let (bus, addr) = start(WsServer::new()).await;
+        let mut client = connect(addr, "?prefix=198.51.100.0/24").await;
+
+        let peer = PeerInfo {
+            peer_addr: "192.0.2.1:179".parse().unwrap(),
+            asn: 64500,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            router: None,
+        };
+        let local_open = OpenMessage::new(
+            65000,
+            90,
+            Ipv4Addr::new(192, 0, 2, 254),
+            &[Capability::FourOctetAs { asn: 65000 }],
+        );
+        let remote_open = OpenMessage::new(
+            64500,
+            90,
+            peer.router_id,
+            &[Capability::FourOctetAs { asn: 64500 }],
+        );
+        let up = PeerUp {
+            peer,
+            negotiated: Negotiated::new(&local_open, &remote_open),
+            local_open,
+            remote_open,
+        };
+        let at = UNIX_EPOCH + Duration::from_secs(1_706_702_400);
+        bus.publish(Timestamped::at(at, BusEvent::PeerUp(up))).await;
+        bus.publish(announced(PEER, "203.0.113.0/24")).await;
+        bus.publish(announced(OTHER_PEER, "203.0.113.0/24")).await;
+        bus.publish(route(
+            OTHER_PEER,
+            "203.0.113.0/24",
+            RouteEventKind::Withdrawn,
+        ))
+        .await;
+        bus.publish(announced(PEER, "198.51.100.0/24")).await;
+        // The server has taken in everything before it once the last event arrives
+        next_message(&mut client).await;
+
+        let (status, body) = get(addr, "/peers").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            "[{\"address\":\"192.0.2.1\",\"asn\":64500,\"router_id\":\"192.0.2.1\",\
+             \"router\":null,\"established\":\"2024-01-31T12:00:00.000Z\"}]"
+        );
+
+        let (status, body) = get(addr, "/rib/203.0.113.0%2F24").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let rib: Json = body.parse().unwrap();
+        assert_eq!(rib["prefix"].as_str(), Some("203.0.113.0/24"));
+        let routes = rib["routes"].as_array().unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0]["peer"].as_str(), Some(PEER));
+        assert_eq!(routes[0]["next_hop"].as_str(), Some(PEER));
+        assert_eq!(routes[0]["attribute"]["origin"].as_str(), Some("igp"));
+
+        let (_, body) = get(addr, "/rib/192.0.2.0/24").await;
+        assert_eq!(body, "{\"prefix\":\"192.0.2.0/24\",\"routes\":[]}");
+        let (status, _) = get(addr, "/rib/nonsense").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = get(addr, "/stream?color=red").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = get(addr, "/stream").await;
+        assert_eq!(status, "HTTP/1.1 426 Upgrade Required");
+        let (status, _) = get(addr, "/").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+}
@@ -0,0 +1,125 @@
+//! Hostile lengths and counts over short buffers, decoded under an allocator that records the
+//! largest allocation made, so that a decoder trusting the wire shows up as a huge one.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use bgp_core::error::ErrorKind;
+use bgp_core::message::{ParserConfig, UpdateMessage};
+use bgp_core::mrt::{MrtError, MrtReader};
+
+struct Counting;
+
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Far below what any of the declared counts would take
+const MAX_ALLOCATION: usize = 64 * 1024;
+
+/// An UPDATE body with `attributes` and no withdrawn routes, followed by `nlri`
+fn update(attributes: &[u8], nlri: &[u8]) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u16(0);
+    body.put_u16(attributes.len() as u16);
+    body.put_slice(attributes);
+    body.put_slice(nlri);
+    body.freeze()
+}
+
+fn update_error(body: Bytes, config: &ParserConfig) -> ErrorKind {
+    UpdateMessage::try_decode_with(&mut body.clone(), config)
+        .unwrap_err()
+        .kind
+}
+
+/// An MRT record of `mrt_type` and `subtype` around `body`
+fn mrt_record(mrt_type: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+    let mut record = BytesMut::new();
+    record.put_u32(1_700_000_000);
+    record.put_u16(mrt_type);
+    record.put_u16(subtype);
+    record.put_u32(body.len() as u32);
+    record.put_slice(body);
+    record.to_vec()
+}
+
+fn mrt_error(record: Vec<u8>) -> MrtError {
+    MrtReader::new(Cursor::new(record))
+        .next()
+        .unwrap()
+        .unwrap_err()
+}
+
+// A single test, as the largest allocation is counted across threads
+#[test]
+fn huge_declared_counts_over_tiny_buffers() {
+    let config = ParserConfig::default();
+
+    // Withdrawn routes claiming 65535 octets
+    let body = Bytes::from_static(&[0xff, 0xff, 8, 10]);
+    assert_eq!(
+        update_error(body, &config),
+        ErrorKind::MalformedAttributeList
+    );
+
+    // An extended length attribute claiming 65535 octets
+    let body = update(&[0x50, 8, 0xff, 0xff, 0, 0, 0, 1], &[]);
+    assert_eq!(update_error(body, &config), ErrorKind::AttributeLengthErr);
+
+    // An AS_SEQUENCE claiming 255 ASNs, holding one
+    let body = update(&[0x40, 2, 6, 2, 255, 0, 0, 0xfd, 0xe8], &[]);
+    assert_eq!(update_error(body, &config), ErrorKind::MalformedAsPath);
+
+    // A prefix claiming more octets than remain
+    let body = update(&[], &[32, 10]);
+    assert_eq!(update_error(body, &config), ErrorKind::InvalidNetworkField);
+
+    // A peer index table claiming 65535 peers, holding none
+    let record = mrt_record(13, 1, &[192, 0, 2, 1, 0, 0, 0xff, 0xff]);
+    assert!(matches!(mrt_error(record), MrtError::Malformed { .. }));
+
+    // A RIB record claiming 65535 entries, holding none
+    let record = mrt_record(13, 2, &[0, 0, 0, 1, 8, 10, 0xff, 0xff]);
+    assert!(matches!(mrt_error(record), MrtError::Malformed { .. }));
+
+    // Past the cap, with everything present
+    let config = ParserConfig {
+        max_collection_len: 2,
+        ..ParserConfig::default()
+    };
+    let body = update(&[], &[8, 10, 8, 11, 8, 12]);
+    assert_eq!(update_error(body, &config), ErrorKind::CollectionTooLong);
+    let communities = [0xc0, 8, 12, 0, 1, 0, 1, 0, 1, 0, 2, 0, 1, 0, 3];
+    let body = update(&communities, &[]);
+    assert_eq!(update_error(body, &config), ErrorKind::CollectionTooLong);
+    let as_path = [0x40, 2, 14, 2, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3];
+    let body = update(&as_path, &[]);
+    assert_eq!(update_error(body, &config), ErrorKind::CollectionTooLong);
+
+    let largest = LARGEST.load(Ordering::Relaxed);
+    assert!(
+        largest < MAX_ALLOCATION,
+        "allocated {largest} octets at once"
+    );
+}
@@ -0,0 +1,52 @@
+//! Checks that the crate builds, tests and benches included, with no features and with each
+//! feature of the manifest on its own, so gates missed between modules show up. Spawns cargo
+//! once per combination, hence ignored: run with `cargo test --test features -- --ignored`.
+
+use std::path::Path;
+use std::process::Command;
+
+fn features() -> Vec<String> {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let manifest: toml::Table = std::fs::read_to_string(manifest).unwrap().parse().unwrap();
+    manifest["features"]
+        .as_table()
+        .unwrap()
+        .keys()
+        .filter(|feature| *feature != "default")
+        .cloned()
+        .collect()
+}
+
+#[test]
+#[ignore]
+fn test_feature_combinations() {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    let combinations = [vec!["--no-default-features".to_string()], vec![]]
+        .into_iter()
+        .chain(features().into_iter().map(|feature| {
+            vec![
+                "--no-default-features".to_string(),
+                "--features".to_string(),
+                feature,
+            ]
+        }))
+        .chain([vec!["--all-features".to_string()]]);
+
+    let failed: Vec<_> = combinations
+        .filter(|flags| {
+            let status = Command::new(env!("CARGO"))
+                .args(["check", "--quiet", "--all-targets", "--package", "bgp_core"])
+                .arg("--manifest-path")
+                .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+                .arg("--target-dir")
+                .arg(&target_dir)
+                .args(flags.iter())
+                .env("RUSTFLAGS", "-D warnings")
+                .status()
+                .unwrap();
+            !status.success()
+        })
+        .map(|flags| flags.join(" "))
+        .collect();
+    assert!(failed.is_empty(), "failed to build with {failed:?}");
+}
@@ -0,0 +1,258 @@
+//! `bgpmon dump`: the routes of an MRT file, one `bgpdump -m` line each
+
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use bgp_core::enrich::FileEnricher;
+use bgp_core::filter::{AsPathPattern, FilterAction, PrefixList, PrefixListEntry};
+use bgp_core::message::IpAddrPrefix;
+use bgp_core::mrt::{self, DumpLine, DumpLines, MrtReader};
+
+use crate::usage_error;
+
+#[derive(Debug, Default)]
+struct Options {
+    path: PathBuf,
+    json: bool,
+    /// Each with its more specifics
+    prefixes: Vec<IpAddrPrefix>,
+    as_paths: Vec<AsPathPattern>,
+    stats: bool,
+    lenient: bool,
+    asn_names: Option<PathBuf>,
+    pfx2as: Option<PathBuf>,
+}
+
+/// What `--stats` reports
+#[derive(Debug, Default)]
+struct Stats {
+    records: u64,
+    /// Records by type and subtype
+    types: BTreeMap<(u16, u16), u64>,
+    errors: u64,
+    lines: u64,
+    printed: u64,
+}
+
+pub fn main(args: impl Iterator<Item = String>) -> ExitCode {
+    let options = match parse(args) {
+        Ok(options) => options,
+        Err(message) => return usage_error(&message),
+    };
+    let enricher = match enricher(&options) {
+        Ok(enricher) => enricher,
+        Err(message) => {
+            eprintln!("bgpmon: {message}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match dump(&options, enricher.as_ref()) {
+        Ok(stats) if stats.errors > 0 && !options.lenient => ExitCode::FAILURE,
+        Ok(_) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("bgpmon: {}: {error}", options.path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--stats" => options.stats = true,
+            "--lenient" => options.lenient = true,
+            "--filter-prefix" => {
+                let prefix = value(&arg)?;
+                let prefix = prefix
+                    .parse()
+                    .map_err(|_| format!("invalid prefix {prefix}"))?;
+                options.prefixes.push(prefix);
+            }
+            "--asn-names" => options.asn_names = Some(PathBuf::from(value(&arg)?)),
+            "--pfx2as" => options.pfx2as = Some(PathBuf::from(value(&arg)?)),
+            "--filter-aspath" => {
+                let pattern = value(&arg)?.parse().map_err(|error| format!("{error}"))?;
+                options.as_paths.push(pattern);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err("expected a single file".to_owned()),
+        }
+    }
+    options.path = path.ok_or("expected a file")?;
+    Ok(options)
+}
+
+/// The names and prefixes of `--asn-names` and `--pfx2as`, if given
+fn enricher(options: &Options) -> Result<Option<FileEnricher>, String> {
+    if options.asn_names.is_none() && options.pfx2as.is_none() {
+        return Ok(None);
+    }
+    let mut enricher = FileEnricher::new();
+    let read = |path: &PathBuf| {
+        std::fs::read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))
+    };
+    if let Some(path) = &options.asn_names {
+        enricher
+            .load_asn_names(&read(path)?)
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+    }
+    if let Some(path) = &options.pfx2as {
+        enricher
+            .load_pfx2as(&read(path)?)
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+    }
+    Ok(Some(enricher))
+}
+
+/// Prints the lines of every record as it's read, and the statistics if asked for
+fn dump(options: &Options, enricher: Option<&FileEnricher>) -> io::Result<Stats> {
+    let prefixes = options
+        .prefixes
+        .iter()
+        .fold(PrefixList::builder(), |builder, prefix| {
+            let max_length = prefix.max_length();
+            let entry =
+                PrefixListEntry::new(FilterAction::Permit, prefix.clone(), None, Some(max_length));
+            builder.entry(entry.expect("a prefix is no longer than its maximum length"))
+        })
+        .build();
+    let permits = |line: &DumpLine| {
+        (options.prefixes.is_empty()
+            || line
+                .prefix()
+                .is_some_and(|prefix| prefixes.permits_prefix(prefix)))
+            && (options.as_paths.is_empty()
+                || line.attributes().is_some_and(|attributes| {
+                    options
+                        .as_paths
+                        .iter()
+                        .all(|pattern| pattern.matches_attributes(attributes))
+                }))
+    };
+
+    let mut stats = Stats::default();
+    let mut lines = DumpLines::new();
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for record in MrtReader::open(&options.path)? {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => {
+                stats.errors += 1;
+                eprintln!("bgpmon: {}: {error}", options.path.display());
+                continue;
+            }
+        };
+        stats.records += 1;
+        *stats
+            .types
+            .entry((record.header.mrt_type, record.header.subtype))
+            .or_default() += 1;
+        for line in lines.lines(&record) {
+            stats.lines += 1;
+            if !permits(&line) {
+                continue;
+            }
+            stats.printed += 1;
+            let written = match (options.json, enricher) {
+                (true, Some(enricher)) => writeln!(out, "{}", line.enriched(enricher).to_json()),
+                (true, None) => writeln!(out, "{}", line.to_json()),
+                (false, Some(enricher)) => writeln!(out, "{}", line.enriched(enricher)),
+                (false, None) => writeln!(out, "{line}"),
+            };
+            match written {
+                // Stop quietly when the reader goes away, as with `| head`
+                Err(error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(stats),
+                written => written?,
+            }
+        }
+    }
+    match out.flush() {
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(stats),
+        flushed => flushed?,
+    }
+
+    if lines.unknown_peers() > 0 {
+        stats.errors += lines.unknown_peers();
+        eprintln!(
+            "bgpmon: {}: {} RIB entries refer to peers missing from the PEER_INDEX_TABLE",
+            options.path.display(),
+            lines.unknown_peers()
+        );
+    }
+    if options.stats {
+        eprint!("{}", stats.report());
+    }
+    Ok(stats)
+}
+
+impl Stats {
+    fn report(&self) -> String {
+        let mut report = format!(
+            "records: {}\nerrors: {}\nlines: {}\nprinted: {}\n",
+            self.records, self.errors, self.lines, self.printed
+        );
+        for ((mrt_type, subtype), count) in &self.types {
+            report += &format!("{}: {count}\n", type_name(*mrt_type, *subtype));
+        }
+        report
+    }
+}
+
+/// `BGP4MP/MESSAGE_AS4` and the like, numbers for types without a name
+fn type_name(mrt_type: u16, subtype: u16) -> String {
+    let (type_name, subtypes): (&str, &[&str]) = match mrt_type {
+        mrt::TABLE_DUMP => ("TABLE_DUMP", &["", "AFI_IPv4", "AFI_IPv6"]),
+        mrt::TABLE_DUMP_V2 => (
+            "TABLE_DUMP_V2",
+            &[
+                "",
+                "PEER_INDEX_TABLE",
+                "RIB_IPV4_UNICAST",
+                "RIB_IPV4_MULTICAST",
+                "RIB_IPV6_UNICAST",
+                "RIB_IPV6_MULTICAST",
+                "RIB_GENERIC",
+                "GEO_PEER_TABLE",
+                "RIB_IPV4_UNICAST_ADDPATH",
+                "RIB_IPV4_MULTICAST_ADDPATH",
+                "RIB_IPV6_UNICAST_ADDPATH",
+                "RIB_IPV6_MULTICAST_ADDPATH",
+                "RIB_GENERIC_ADDPATH",
+            ],
+        ),
+        mrt::BGP4MP | mrt::BGP4MP_ET => (
+            if mrt_type == mrt::BGP4MP {
+                "BGP4MP"
+            } else {
+                "BGP4MP_ET"
+            },
+            &[
+                "STATE_CHANGE",
+                "MESSAGE",
+                "",
+                "",
+                "MESSAGE_AS4",
+                "STATE_CHANGE_AS4",
+                "MESSAGE_LOCAL",
+                "MESSAGE_AS4_LOCAL",
+                "MESSAGE_ADDPATH",
+                "MESSAGE_AS4_ADDPATH",
+                "MESSAGE_LOCAL_ADDPATH",
+                "MESSAGE_AS4_LOCAL_ADDPATH",
+            ],
+        ),
+        _ => return format!("{mrt_type}/{subtype}"),
+    };
+    match subtypes.get(usize::from(subtype)) {
+        Some(name) if !name.is_empty() => format!("{type_name}/{name}"),
+        _ => format!("{type_name}/{subtype}"),
+    }
+}
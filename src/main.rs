@@ -1,6 +1,32 @@
-#![feature(inherent_associated_types)]
-mod parse;
+mod dump;
+mod peer;
 
-fn main() {
-    println!("Hello, world!");
+use std::env;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: bgpmon dump [--json] [--filter-prefix PREFIX]... [--filter-aspath PATTERN]...
+                   [--asn-names FILE] [--pfx2as FILE] [--stats] [--lenient] FILE
+       bgpmon peer --local-asn ASN --router-id ID --neighbor ADDR [--neighbor-asn ASN]
+                   [--port PORT] [--passive] [--hold-time SECONDS] [--family FAMILY]...
+                   [--add-path receive|send|both] [--announce PREFIX]... [--next-hop ADDR]
+                   [--json] [--status-port PORT] [--record FILE] [--prefix-list FILE]";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("dump") => dump::main(args),
+        Some("peer") => peer::main(args),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        _ => usage_error("expected a command"),
+    }
+}
+
+/// Reports a mistake in the command line
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("bgpmon: {message}\n{USAGE}");
+    ExitCode::from(2)
 }
@@ -1 +1,601 @@
-pub struct BgpPeer {}
+//! `bgpmon peer`: a session with a single neighbor, printing the routes it sends.
+//!
+//! With `--prefix-list` only the routes the list permits are kept. SIGHUP re-reads the list
+//! and asks the neighbor for a route refresh, so the kept routes follow the new list.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use bgp_core::filter::{PrefixList, RouteFilter};
+use bgp_core::json::Json;
+use bgp_core::message::{
+    AddPathDirection, Afi, Asn, Capability, IpAddrPrefix, Route, RouteRefreshMessage, Safi,
+    Timestamped, UpdateMessage,
+};
+use bgp_core::mrt::{self, DumpEntry, DumpLine, FsmState, MrtWriter};
+use bgp_core::rib::{RibIn, RibMemoryStats};
+use bgp_core::session::{
+    BgpListener, EstablishedSession, Peer, PeerConfig, PeerInfo, Refresher, SessionEnd,
+    SessionError, SessionObserver, ShutdownReason,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::pipe;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+use crate::usage_error;
+
+/// Longest status request read, headers included
+const MAX_REQUEST: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Options {
+    local_asn: u32,
+    router_id: Ipv4Addr,
+    neighbor: IpAddr,
+    neighbor_asn: Option<u32>,
+    /// The neighbor's port or, when passive, the one listened on
+    port: u16,
+    passive: bool,
+    hold_time: u16,
+    families: Vec<(Afi, Safi)>,
+    add_path: Option<AddPathDirection>,
+    announce: Vec<IpAddrPrefix>,
+    next_hop: Option<IpAddr>,
+    json: bool,
+    status_port: Option<u16>,
+    record: Option<PathBuf>,
+    prefix_list: Option<PathBuf>,
+}
+
+pub fn main(args: impl Iterator<Item = String>) -> ExitCode {
+    let options = match parse(args) {
+        Ok(options) => options,
+        Err(message) => return usage_error(&message),
+    };
+    let config = match options.config() {
+        Ok(config) => config,
+        Err(error) => return usage_error(&error.to_string()),
+    };
+    let filter = match options
+        .prefix_list
+        .as_deref()
+        .map(load_prefix_list)
+        .transpose()
+    {
+        Ok(filter) => filter,
+        Err(error) => {
+            eprintln!("bgpmon: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("a runtime without threads builds");
+    match runtime.block_on(run(&options, config, filter)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("bgpmon: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut local_asn = None;
+    let mut router_id = None;
+    let mut neighbor = None;
+    let mut options = Options {
+        local_asn: 0,
+        router_id: Ipv4Addr::UNSPECIFIED,
+        neighbor: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        neighbor_asn: None,
+        port: PeerConfig::DEFAULT_PORT,
+        passive: false,
+        hold_time: PeerConfig::DEFAULT_HOLD_TIME,
+        families: vec![],
+        add_path: None,
+        announce: vec![],
+        next_hop: None,
+        json: false,
+        status_port: None,
+        record: None,
+        prefix_list: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--local-asn" => local_asn = Some(parse_value::<Asn>(&arg, value()?)?.0),
+            "--router-id" => router_id = Some(parse_value(&arg, value()?)?),
+            "--neighbor" => neighbor = Some(parse_value(&arg, value()?)?),
+            "--neighbor-asn" => options.neighbor_asn = Some(parse_value::<Asn>(&arg, value()?)?.0),
+            "--port" => options.port = parse_value(&arg, value()?)?,
+            "--passive" => options.passive = true,
+            "--hold-time" => options.hold_time = parse_value(&arg, value()?)?,
+            "--family" => options.families.push(family(&value()?)?),
+            "--add-path" => {
+                options.add_path = Some(match value()?.as_str() {
+                    "receive" => AddPathDirection::Receive,
+                    "send" => AddPathDirection::Send,
+                    "both" => AddPathDirection::Both,
+                    direction => return Err(format!("invalid ADD-PATH direction {direction}")),
+                })
+            }
+            "--announce" => options.announce.push(parse_value(&arg, value()?)?),
+            "--next-hop" => options.next_hop = Some(parse_value(&arg, value()?)?),
+            "--json" => options.json = true,
+            "--status-port" => options.status_port = Some(parse_value(&arg, value()?)?),
+            "--record" => options.record = Some(PathBuf::from(value()?)),
+            "--prefix-list" => options.prefix_list = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    options.local_asn = local_asn.ok_or("expected --local-asn")?;
+    options.router_id = router_id.ok_or("expected --router-id")?;
+    options.neighbor = neighbor.ok_or("expected --neighbor")?;
+    if options.families.is_empty() {
+        options.families.push((Afi::Ipv4, Safi::Unicast));
+    }
+    Ok(options)
+}
+
+fn parse_value<T: FromStr>(option: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {value} for {option}"))
+}
+
+/// `ipv4-unicast` and the like
+fn family(name: &str) -> Result<(Afi, Safi), String> {
+    match name {
+        "ipv4-unicast" => Ok((Afi::Ipv4, Safi::Unicast)),
+        "ipv4-multicast" => Ok((Afi::Ipv4, Safi::Multicast)),
+        "ipv6-unicast" => Ok((Afi::Ipv6, Safi::Unicast)),
+        "ipv6-multicast" => Ok((Afi::Ipv6, Safi::Multicast)),
+        _ => Err(format!("unknown address family {name}")),
+    }
+}
+
+impl Options {
+    fn config(&self) -> Result<PeerConfig, bgp_core::session::ConfigError> {
+        let mut builder = PeerConfig::builder(self.neighbor, self.local_asn, self.router_id)
+            .remote_port(self.port)
+            .passive(self.passive)
+            .hold_time(self.hold_time)
+            .next_hop(self.next_hop.unwrap_or(IpAddr::V4(self.router_id)));
+        if let Some(asn) = self.neighbor_asn {
+            builder = builder.remote_asn(asn);
+        }
+        // The neighbor re-advertises its routes when the prefix list changes
+        if self.prefix_list.is_some() {
+            builder = builder
+                .capability(Capability::RouteRefresh)
+                .capability(Capability::EnhancedRouteRefresh);
+        }
+        for &(afi, safi) in &self.families {
+            builder = builder.capability(Capability::MultiProtocol { afi, safi });
+            if let Some(direction) = self.add_path {
+                builder = builder.add_path(afi, safi, direction);
+            }
+        }
+        builder.build()
+    }
+}
+
+fn load_prefix_list(path: &Path) -> Result<Arc<dyn RouteFilter>, String> {
+    let list =
+        std::fs::read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    let list: PrefixList = list
+        .parse()
+        .map_err(|error| format!("{}: {error}", path.display()))?;
+    Ok(Arc::new(list))
+}
+
+/// Establishes the session, then prints updates until it ends or we're interrupted
+async fn run(
+    options: &Options,
+    config: PeerConfig,
+    filter: Option<Arc<dyn RouteFilter>>,
+) -> Result<(), SessionError> {
+    let interrupt = interrupts()?;
+    let reloads = options
+        .prefix_list
+        .as_ref()
+        .map(|_| reloads())
+        .transpose()?;
+    let record = match &options.record {
+        Some(path) => Some(Mutex::new(MrtWriter::create(path)?)),
+        None => None,
+    };
+    let mut status = Status::default();
+    if let Some(filter) = filter {
+        status.rib = status.rib.with_filter(filter);
+    }
+    let status = Arc::new(Mutex::new(status));
+    if let Some(port) = options.status_port {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        eprintln!("bgpmon: serving status on {}", listener.local_addr()?);
+        tokio::spawn(serve_status(listener, status.clone()));
+    }
+
+    let local_asn = config.local_asn;
+    let session = tokio::select! {
+        session = establish(config) => session?,
+        _ = interrupt.readable() => return Ok(()),
+    };
+    let peer = session.peer_info();
+    eprintln!(
+        "bgpmon: established with {} AS{}",
+        peer.peer_addr.ip(),
+        peer.asn
+    );
+
+    let announcer = session.announcer();
+    for prefix in &options.announce {
+        announcer.announce(Route::new(prefix.clone())).await?;
+    }
+    let reloader = options
+        .prefix_list
+        .clone()
+        .zip(reloads)
+        .map(|(path, reloads)| {
+            tokio::spawn(reload_prefix_list(
+                path,
+                reloads,
+                session.refresher(),
+                status.clone(),
+            ))
+        });
+    let printer = Arc::new(Printer {
+        json: options.json,
+        local_asn,
+        status,
+        record,
+    });
+    let end = session
+        .run_until(printer.clone(), async {
+            let _ = interrupt.readable().await;
+            ShutdownReason::AdministrativeShutdown(None)
+        })
+        .await;
+    printer.flush_record();
+    if let Some(reloader) = reloader {
+        reloader.abort();
+    }
+
+    match end? {
+        SessionEnd::RemoteCease { subcode, message } => {
+            let subcode =
+                subcode.map_or("unspecified".to_owned(), |subcode| format!("{subcode:?}"));
+            eprint!(
+                "bgpmon: {} closed the session: Cease/{subcode}",
+                peer.peer_addr.ip()
+            );
+            match message {
+                Some(message) => eprintln!(" {message:?}"),
+                None => eprintln!(),
+            }
+        }
+        SessionEnd::LocalShutdown(_) | SessionEnd::Closed => eprintln!("bgpmon: session closed"),
+    }
+    Ok(())
+}
+
+/// Re-reads the prefix list on every SIGHUP, then has the neighbor re-advertise each family
+/// so the RIB is rebuilt under the new list
+async fn reload_prefix_list(
+    path: PathBuf,
+    reloads: pipe::Receiver,
+    refresher: Refresher,
+    status: Arc<Mutex<Status>>,
+) {
+    let mut buf = [0; 16];
+    while reloads.readable().await.is_ok() {
+        while reloads.try_read(&mut buf).is_ok_and(|read| read > 0) {}
+        let filter = match load_prefix_list(&path) {
+            Ok(filter) => filter,
+            Err(error) => {
+                eprintln!("bgpmon: keeping the prefix list, {error}");
+                continue;
+            }
+        };
+        let withdrawn = status.lock().unwrap().rib.set_filter(filter).len();
+        eprintln!("bgpmon: reloaded the prefix list, withdrew {withdrawn} routes");
+        for &(afi, safi) in refresher.families() {
+            if let Err(error) = refresher.request_refresh(afi, safi).await {
+                eprintln!("bgpmon: route refresh failed: {error}");
+            }
+        }
+    }
+}
+
+/// Dials the neighbor or, when passive, waits for it to connect
+async fn establish(config: PeerConfig) -> Result<EstablishedSession, SessionError> {
+    if !config.passive {
+        return Peer::connect(config).await;
+    }
+    let any = match config.remote_addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let mut listener =
+        BgpListener::bind(SocketAddr::new(any, config.remote_port), [config]).await?;
+    eprintln!("bgpmon: listening on {}", listener.local_addr());
+    listener.accept().await.ok_or(SessionError::SessionClosed)
+}
+
+/// What the status port serves
+#[derive(Debug, Default)]
+struct Status {
+    peer: Option<PeerInfo>,
+    established_at: Option<Instant>,
+    updates: u64,
+    rib: RibIn,
+}
+
+impl Status {
+    /// The peer and its routes, each as `bgpmon dump --json` prints them
+    fn to_json(&self) -> Json {
+        let now = SystemTime::now();
+        let peer = self.peer.as_ref();
+        let routes = peer.map_or(vec![], |peer| {
+            self.rib
+                .iter()
+                .map(|(key, attributes)| {
+                    let line = DumpLine {
+                        source: "TABLE_DUMP2",
+                        timestamp: now,
+                        peer_addr: peer.peer_addr.ip().to_canonical(),
+                        peer_asn: peer.asn,
+                        entry: DumpEntry::Route {
+                            prefix: key.prefix.clone(),
+                            attributes,
+                        },
+                    };
+                    line.to_json()
+                })
+                .collect()
+        });
+        Json::object([
+            (
+                "state",
+                Json::from(match self.established_at {
+                    Some(_) => "established",
+                    None => "idle",
+                }),
+            ),
+            (
+                "peer",
+                peer.map_or(Json::Null, |peer| {
+                    Json::from(peer.peer_addr.ip().to_canonical().to_string())
+                }),
+            ),
+            (
+                "peer_asn",
+                peer.map_or(Json::Null, |peer| Json::from(peer.asn)),
+            ),
+            (
+                "router_id",
+                peer.map_or(Json::Null, |peer| Json::from(peer.router_id.to_string())),
+            ),
+            (
+                "uptime",
+                self.established_at
+                    .map_or(Json::Null, |at| Json::Number(at.elapsed().as_secs() as f64)),
+            ),
+            ("updates", Json::Number(self.updates as f64)),
+            ("prefixes", Json::Number(self.rib.len() as f64)),
+            ("memory", memory_json(&self.rib.memory_stats())),
+            ("routes", Json::Array(routes)),
+        ])
+    }
+}
+
+/// The estimated memory of the RIB, in octets
+fn memory_json(stats: &RibMemoryStats) -> Json {
+    Json::object([
+        ("attribute_sets", Json::Number(stats.attribute_sets as f64)),
+        ("keys", Json::Number(stats.key_bytes as f64)),
+        ("attributes", Json::Number(stats.attribute_bytes as f64)),
+        ("index", Json::Number(stats.index_bytes as f64)),
+        ("total", Json::Number(stats.total_bytes() as f64)),
+    ])
+}
+
+/// Answers every GET with the [`Status`], one request per connection
+async fn serve_status(listener: TcpListener, status: Arc<Mutex<Status>>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let status = status.clone();
+        tokio::spawn(async move {
+            let _ = time::timeout(REQUEST_TIMEOUT, answer_status(stream, &status)).await;
+        });
+    }
+}
+
+async fn answer_status(mut stream: TcpStream, status: &Mutex<Status>) -> io::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let (status_line, body) = match request.starts_with(b"GET ") {
+        true => ("200 OK", status.lock().unwrap().to_json().to_string()),
+        false => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status_line}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Prints, keeps and records the routes of the session
+struct Printer {
+    json: bool,
+    local_asn: u32,
+    status: Arc<Mutex<Status>>,
+    record: Option<Mutex<MrtWriter<BufWriter<File>>>>,
+}
+
+impl Printer {
+    fn record(&self, write: impl FnOnce(&mut MrtWriter<BufWriter<File>>) -> io::Result<()>) {
+        if let Some(record) = &self.record
+            && let Err(error) = write(&mut record.lock().unwrap())
+        {
+            eprintln!("bgpmon: recording failed: {error}");
+        }
+    }
+
+    fn flush_record(&self) {
+        self.record(MrtWriter::flush);
+    }
+
+    /// The peer as MRT records name it; sessions don't tell their local address
+    fn mrt_peer(&self, peer: &PeerInfo) -> mrt::PeerInfo {
+        let peer_addr = peer.peer_addr.ip().to_canonical();
+        mrt::PeerInfo {
+            peer_asn: peer.asn,
+            local_asn: self.local_asn,
+            interface_index: 0,
+            peer_addr,
+            local_addr: match peer_addr {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            },
+        }
+    }
+}
+
+impl SessionObserver for Printer {
+    fn on_established(&self, peer: &PeerInfo) {
+        let mut status = self.status.lock().unwrap();
+        status.peer = Some(*peer);
+        status.established_at = Some(Instant::now());
+        drop(status);
+        self.record(|record| {
+            let at = SystemTime::now();
+            record.write_state_change(
+                &self.mrt_peer(peer),
+                at,
+                FsmState::OpenConfirm,
+                FsmState::Established,
+            )
+        });
+    }
+
+    fn on_update(&self, peer: &PeerInfo, update: &Timestamped<UpdateMessage>) {
+        let peer_addr = peer.peer_addr.ip().to_canonical();
+        let mut out = io::stdout().lock();
+        for line in DumpLine::update(update.received, peer_addr, peer.asn, &update.value) {
+            // Nobody is left to tell when stdout is gone
+            let _ = match self.json {
+                true => writeln!(out, "{}", line.to_json()),
+                false => writeln!(out, "{line}"),
+            };
+        }
+        drop(out);
+
+        let mut status = self.status.lock().unwrap();
+        status.updates += 1;
+        status.rib.apply(&update.value);
+        drop(status);
+        self.record(|record| {
+            record.write_update(&self.mrt_peer(peer), update.received, &update.value)
+        });
+    }
+
+    /// Sweeps the routes the neighbor didn't re-advertise once its refresh ends
+    fn on_route_refresh(&self, _peer: &PeerInfo, route_refresh: &RouteRefreshMessage) {
+        let mut status = self.status.lock().unwrap();
+        status.rib.apply_route_refresh(route_refresh);
+    }
+
+    /// Keeps the recording at most a keepalive interval behind
+    fn on_keepalive(&self, _peer: &PeerInfo) {
+        self.flush_record();
+    }
+
+    fn on_close(&self, peer: &PeerInfo, _end: Result<&SessionEnd, &SessionError>) {
+        self.status.lock().unwrap().established_at = None;
+        self.record(|record| {
+            let at = SystemTime::now();
+            record.write_state_change(
+                &self.mrt_peer(peer),
+                at,
+                FsmState::Established,
+                FsmState::Idle,
+            )
+        });
+    }
+}
+
+/// Write ends of the pipes [`interrupts`] and [`reloads`] return the read ends of
+static INTERRUPTS: AtomicI32 = AtomicI32::new(-1);
+static RELOADS: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn interrupted(_signal: libc::c_int) {
+    notify(&INTERRUPTS);
+}
+
+extern "C" fn reload_requested(_signal: libc::c_int) {
+    notify(&RELOADS);
+}
+
+fn notify(pipe: &AtomicI32) {
+    let fd = pipe.load(Ordering::Relaxed);
+    // SAFETY: write is async-signal-safe and the buffer is a live static
+    unsafe { libc::write(fd, c"".as_ptr().cast(), 1) };
+}
+
+/// A pipe that becomes readable on SIGINT or SIGTERM, which no longer terminate the process
+fn interrupts() -> io::Result<pipe::Receiver> {
+    signal_pipe(&[libc::SIGINT, libc::SIGTERM], &INTERRUPTS, interrupted)
+}
+
+/// A pipe that becomes readable on SIGHUP, which no longer terminates the process
+fn reloads() -> io::Result<pipe::Receiver> {
+    signal_pipe(&[libc::SIGHUP], &RELOADS, reload_requested)
+}
+
+fn signal_pipe(
+    signals: &[libc::c_int],
+    write_end: &AtomicI32,
+    handler: extern "C" fn(libc::c_int),
+) -> io::Result<pipe::Receiver> {
+    let mut fds = [0; 2];
+    // SAFETY: the pointer describes a live, writable array of two descriptors
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 just opened the descriptor and nothing else owns it
+    let receiver = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+    // The write end stays open for as long as the handler may run
+    write_end.store(fds[1], Ordering::Relaxed);
+    let handler = handler as libc::sighandler_t;
+    for &signal in signals {
+        // SAFETY: the handler only makes async-signal-safe calls
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    pipe::Receiver::from_owned_fd(receiver)
+}
@@ -0,0 +1,199 @@
+use std::process::{Command, Output};
+
+use bgp_core::json::Json;
+
+fn bgpmon(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bgpmon"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/core/tests/data/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn stdout_lines(output: &Output) -> Vec<&str> {
+    std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .collect()
+}
+
+#[test]
+fn dumps_rib_like_bgpdump() {
+    let output = bgpmon(&["dump", &fixture("rib_dump.mrt")]);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout_lines(&output),
+        [
+            "TABLE_DUMP2|1700000000|A|192.0.2.1|64500|198.51.100.0/24|64500 {64511,64512}|INCOMPLETE|192.0.2.1|0|0||AG|64500 192.0.2.9|",
+            "TABLE_DUMP2|1700000000|A|192.0.2.1|64500|203.0.113.0/24|64500 64510|IGP|192.0.2.1|0|10|64500:1 64500:2|NAG||",
+            "TABLE_DUMP2|1700000000|A|2001:db8::2|4200000000|203.0.113.0/24|4200000000 64510|IGP|2001:db8::2|200|0||NAG||",
+            "TABLE_DUMP2|1700000000|A|2001:db8::2|4200000000|2001:db8:1000::/36|4200000000 64510|IGP|2001:db8::2|200|0||NAG||",
+        ]
+    );
+
+    let output = bgpmon(&[
+        "dump",
+        "--filter-prefix",
+        "203.0.113.0/24",
+        "--filter-aspath",
+        "^4200000000",
+        &fixture("rib_dump.mrt"),
+    ]);
+    assert_eq!(
+        stdout_lines(&output),
+        [
+            "TABLE_DUMP2|1700000000|A|2001:db8::2|4200000000|203.0.113.0/24|4200000000 64510|IGP|2001:db8::2|200|0||NAG||"
+        ]
+    );
+    let output = bgpmon(&[
+        "dump",
+        "--filter-prefix",
+        "2001:db8::/32",
+        &fixture("rib_dump.mrt"),
+    ]);
+    assert_eq!(stdout_lines(&output).len(), 1);
+
+    let output = bgpmon(&["dump", "--json", &fixture("rib_dump.mrt")]);
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 4);
+    let route: Json = lines[1].parse().unwrap();
+    assert_eq!(route["prefix"].as_str(), Some("203.0.113.0/24"));
+    assert_eq!(route["as_path"].as_str(), Some("64500 64510"));
+}
+
+#[test]
+fn enriches_lines_with_names_and_routed_prefixes() {
+    let output = bgpmon(&[
+        "dump",
+        "--lenient",
+        "--asn-names",
+        &fixture("asn.txt"),
+        "--pfx2as",
+        &fixture("pfx2as.txt"),
+        &fixture("bgp4mp_updates.mrt"),
+    ]);
+    assert!(output.status.success());
+    let lines = stdout_lines(&output);
+    assert_eq!(
+        lines[1],
+        "BGP4MP|1700000001|A|192.0.2.1|3356|203.0.113.0/24|3356 1299 4200000000|IGP|192.0.2.1|0|0|3356:2|NAG||LEVEL3 - Level 3 Parent, LLC, US|4200000000|PRIVATE|||"
+    );
+
+    let output = bgpmon(&[
+        "dump",
+        "--json",
+        "--lenient",
+        "--asn-names",
+        &fixture("asn.txt"),
+        &fixture("bgp4mp_updates.mrt"),
+    ]);
+    let route: Json = stdout_lines(&output)[1].parse().unwrap();
+    assert_eq!(route["origin_as_name"].as_str(), Some("PRIVATE"));
+    assert_eq!(route["routed_prefix"], Json::Null);
+
+    let output = bgpmon(&[
+        "dump",
+        "--pfx2as",
+        &fixture("asn.txt"),
+        &fixture("bgp4mp_updates.mrt"),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn dumps_legacy_table_dump() {
+    let output = bgpmon(&["dump", &fixture("table_dump_v1.mrt")]);
+    assert!(output.status.success());
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0],
+        "TABLE_DUMP|1100000000|A|192.0.2.1|7018|198.51.100.0/24|7018 3356 4200000000|IGP|192.0.2.1|0|0||NAG||"
+    );
+}
+
+#[test]
+fn dumps_add_path_records() {
+    let output = bgpmon(&["dump", "--stats", &fixture("addpath.mrt")]);
+    assert!(output.status.success());
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 6);
+    assert_eq!(
+        lines[5],
+        "BGP4MP|1700000060|A|192.0.2.1|64500|198.51.100.0/24|64500 64510|IGP|192.0.2.1|0|0||NAG||"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("TABLE_DUMP_V2/RIB_IPV4_UNICAST_ADDPATH: 1\n"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("BGP4MP_ET/MESSAGE_AS4_ADDPATH: 1\n"),
+        "{stderr}"
+    );
+
+    let output = bgpmon(&["dump", "--json", &fixture("addpath.mrt")]);
+    let path_ids: Vec<_> = stdout_lines(&output)
+        .iter()
+        .map(|line| line.parse::<Json>().unwrap()["path_id"].to_string())
+        .collect();
+    assert_eq!(path_ids, ["1", "2", "1", "2", "2", "1"]);
+}
+
+#[test]
+fn dumps_compressed_files() {
+    for (plain, compressed) in [
+        ("rib_dump.mrt", "rib_dump.mrt.gz"),
+        ("bgp4mp_updates.mrt", "bgp4mp_updates.mrt.bz2"),
+    ] {
+        let expected = bgpmon(&["dump", "--lenient", &fixture(plain)]);
+        let output = bgpmon(&["dump", "--lenient", &fixture(compressed)]);
+        assert!(output.status.success(), "{compressed}");
+        assert_eq!(output.stdout, expected.stdout);
+    }
+}
+
+#[test]
+fn fails_on_broken_records_unless_lenient() {
+    let output = bgpmon(&["dump", "--stats", &fixture("bgp4mp_updates.mrt")]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout_lines(&output).len(), 6);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("records: 9\nerrors: 1\n"), "{stderr}");
+    assert!(stderr.contains("BGP4MP/MESSAGE_AS4: 3\n"), "{stderr}");
+
+    let output = bgpmon(&["dump", "--lenient", &fixture("bgp4mp_updates.mrt")]);
+    assert!(output.status.success());
+    assert_eq!(stdout_lines(&output).len(), 6);
+
+    // State changes have no prefix to filter on
+    let output = bgpmon(&[
+        "dump",
+        "--lenient",
+        "--filter-prefix",
+        "0.0.0.0/0",
+        &fixture("bgp4mp_updates.mrt"),
+    ]);
+    assert_eq!(stdout_lines(&output).len(), 3);
+}
+
+#[test]
+fn rejects_bad_command_lines() {
+    for args in [
+        &["dump"][..],
+        &["dump", "--filter-prefix"],
+        &["dump", "--filter-aspath", "as64500", "x.mrt"],
+        &["dump", "a.mrt", "b.mrt"],
+        &["undump"],
+    ] {
+        assert_eq!(bgpmon(args).status.code(), Some(2), "{args:?}");
+    }
+    assert_eq!(
+        bgpmon(&["dump", &fixture("missing.mrt")]).status.code(),
+        Some(1)
+    );
+}
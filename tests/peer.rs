@@ -0,0 +1,294 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bgp_core::json::Json;
+use bgp_core::mrt::{DumpLines, MrtReader};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A running `bgpmon peer` with its output read line by line
+struct Instance {
+    child: Child,
+    stdout: Receiver<String>,
+    stderr: Receiver<String>,
+}
+
+impl Instance {
+    fn spawn(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bgpmon"))
+            .arg("peer")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Instance {
+            stdout: lines(child.stdout.take().unwrap()),
+            stderr: lines(child.stderr.take().unwrap()),
+            child,
+        }
+    }
+
+    /// The text after `prefix` in the next stderr line starting with it
+    fn stderr_after(&self, prefix: &str) -> String {
+        loop {
+            let line = self.stderr.recv_timeout(TIMEOUT).unwrap();
+            if let Some(rest) = line.strip_prefix(prefix) {
+                return rest.to_owned();
+            }
+        }
+    }
+
+    fn interrupt(&self) {
+        let pid = self.child.id() as libc::pid_t;
+        assert_eq!(unsafe { libc::kill(pid, libc::SIGINT) }, 0);
+    }
+
+    fn wait(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            if Instant::now() > deadline {
+                self.child.kill().unwrap();
+                panic!("bgpmon peer didn't exit");
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn lines(output: impl Read + Send + 'static) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn get_status(addr: &str) -> Json {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    body.parse().unwrap()
+}
+
+/// A passive collector and an active instance announcing a route to it
+#[test]
+fn test_two_instances() {
+    let dir = std::env::temp_dir().join(format!("bgpmon-peer-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let record = dir.join("updates.mrt");
+
+    let mut collector = Instance::spawn(&[
+        "--local-asn",
+        "65000",
+        "--router-id",
+        "10.0.0.1",
+        "--neighbor",
+        "127.0.0.1",
+        "--neighbor-asn",
+        "65010",
+        "--passive",
+        "--port",
+        "0",
+        "--status-port",
+        "0",
+        "--record",
+        record.to_str().unwrap(),
+    ]);
+    let status_addr = collector.stderr_after("bgpmon: serving status on ");
+    let port = collector.stderr_after("bgpmon: listening on 0.0.0.0:");
+    assert_eq!(get_status(&status_addr)["state"].as_str(), Some("idle"));
+
+    let mut speaker = Instance::spawn(&[
+        "--local-asn",
+        "65010",
+        "--router-id",
+        "10.0.0.2",
+        "--neighbor",
+        "127.0.0.1",
+        "--neighbor-asn",
+        "65000",
+        "--port",
+        &port,
+        "--announce",
+        "203.0.113.0/24",
+    ]);
+    let line = collector.stdout.recv_timeout(TIMEOUT).unwrap();
+    let (_, line) = line.split_once("|A|").unwrap();
+    assert_eq!(
+        line,
+        "127.0.0.1|65010|203.0.113.0/24|65010|IGP|10.0.0.2|0|0||NAG||"
+    );
+
+    let status = get_status(&status_addr);
+    assert_eq!(status["state"].as_str(), Some("established"));
+    assert_eq!(status["router_id"].as_str(), Some("10.0.0.2"));
+    let routes = status["routes"].as_array().unwrap();
+    assert_eq!(routes.len(), 1);
+    assert_eq!(routes[0]["prefix"].as_str(), Some("203.0.113.0/24"));
+    assert!(status["memory"]["total"].as_u64().unwrap() > 0);
+
+    collector.interrupt();
+    assert!(collector.wait().success());
+    assert_eq!(
+        speaker.stderr_after("bgpmon: 127.0.0.1 closed the session: "),
+        "Cease/AdministrativeShutdown"
+    );
+    assert!(speaker.wait().success());
+
+    let mut lines = DumpLines::new();
+    let recorded: Vec<String> = MrtReader::open(&record)
+        .unwrap()
+        .flat_map(|record| {
+            let record = record.unwrap();
+            let lines = lines.lines(&record);
+            // Without the source and the timestamp
+            lines
+                .iter()
+                .map(|line| line.to_string().splitn(3, '|').nth(2).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    assert_eq!(
+        recorded,
+        [
+            "STATE|127.0.0.1|65010|5|6",
+            "A|127.0.0.1|65010|203.0.113.0/24|65010|IGP|10.0.0.2|0|0||NAG||",
+            "STATE|127.0.0.1|65010|6|1",
+        ]
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// SIGHUP swaps the prefix list of the collector's RIB and asks for a route refresh
+#[test]
+fn test_prefix_list_reload() {
+    let dir = std::env::temp_dir().join(format!("bgpmon-prefix-list-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let list = dir.join("prefix-list");
+    std::fs::write(&list, "permit 203.0.113.0/24\n").unwrap();
+
+    let collector = Instance::spawn(&[
+        "--local-asn",
+        "65000",
+        "--router-id",
+        "10.0.0.1",
+        "--neighbor",
+        "127.0.0.1",
+        "--neighbor-asn",
+        "65010",
+        "--passive",
+        "--port",
+        "0",
+        "--status-port",
+        "0",
+        "--prefix-list",
+        list.to_str().unwrap(),
+    ]);
+    let status_addr = collector.stderr_after("bgpmon: serving status on ");
+    let port = collector.stderr_after("bgpmon: listening on 0.0.0.0:");
+    let _speaker = Instance::spawn(&[
+        "--local-asn",
+        "65010",
+        "--router-id",
+        "10.0.0.2",
+        "--neighbor",
+        "127.0.0.1",
+        "--neighbor-asn",
+        "65000",
+        "--port",
+        &port,
+        "--announce",
+        "203.0.113.0/24",
+        "--announce",
+        "198.51.100.0/24",
+        "--prefix-list",
+        list.to_str().unwrap(),
+    ]);
+    for _ in 0..2 {
+        collector.stdout.recv_timeout(TIMEOUT).unwrap();
+    }
+    let routes = |status: Json| -> Vec<String> {
+        let routes = status["routes"].as_array().unwrap();
+        routes
+            .iter()
+            .map(|route| route["prefix"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(routes(get_status(&status_addr)), ["203.0.113.0/24"]);
+
+    std::fs::write(&list, "permit 198.51.100.0/24\n").unwrap();
+    let pid = collector.child.id() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGHUP) }, 0);
+    assert_eq!(
+        collector.stderr_after("bgpmon: reloaded the prefix list, "),
+        "withdrew 1 routes"
+    );
+    assert!(routes(get_status(&status_addr)).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_rejects_bad_command_lines() {
+    for args in [
+        &["--router-id", "10.0.0.1", "--neighbor", "192.0.2.1"][..],
+        &["--local-asn", "65000", "--router-id", "10.0.0.1"],
+        &[
+            "--local-asn",
+            "as65000",
+            "--router-id",
+            "10.0.0.1",
+            "--neighbor",
+            "192.0.2.1",
+        ],
+        &[
+            "--local-asn",
+            "65000",
+            "--router-id",
+            "10.0.0.1",
+            "--neighbor",
+            "192.0.2.1",
+            "--family",
+            "ipv5",
+        ],
+        &[
+            "--local-asn",
+            "65000",
+            "--router-id",
+            "10.0.0.1",
+            "--neighbor",
+            "192.0.2.1",
+            "--hold-time",
+            "1",
+        ],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_bgpmon"))
+            .arg("peer")
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+    }
+}